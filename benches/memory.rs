@@ -0,0 +1,192 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use nirvana_notject::pattern;
+use nirvana_notject::{Memory, Pattern, RegionFilter, ScanFilter, ScanOptions, ScanSession};
+
+const HAYSTACK_SIZE: usize = 256 * 1024 * 1024;
+const PATTERN_LEN: usize = 16;
+
+// Deterministic filler so every run scans the same bytes.
+fn fill_pseudo_random(buffer: &mut [u8], mut seed: u32) {
+    for byte in buffer.iter_mut() {
+        seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        *byte = (seed >> 24) as u8;
+    }
+}
+
+fn make_pattern(seed: u32) -> Vec<u8> {
+    let mut pattern = vec![0u8; PATTERN_LEN];
+    fill_pseudo_random(&mut pattern, seed);
+    pattern
+}
+
+// Every `every`-th position becomes a wildcard; 0 disables wildcards.
+fn make_mask(every: usize) -> Vec<bool> {
+    (0..PATTERN_LEN)
+        .map(|i| every == 0 || i % every != 0 || i == 0)
+        .collect()
+}
+
+fn typed_reads(c: &mut Criterion) {
+    let memory = Memory::new().expect("failed to open current process");
+    let value_u32: u32 = 0xDEAD_BEEF;
+    let value_u64: u64 = 0xDEAD_BEEF_CAFE_BABE;
+    let value_f32: f32 = 100.0;
+
+    let mut group = c.benchmark_group("typed_read");
+    group.bench_function("u32", |b| {
+        let address = &value_u32 as *const u32 as usize;
+        b.iter(|| memory.read_memory::<u32>(black_box(address)).unwrap())
+    });
+    group.bench_function("u64", |b| {
+        let address = &value_u64 as *const u64 as usize;
+        b.iter(|| memory.read_memory::<u64>(black_box(address)).unwrap())
+    });
+    group.bench_function("f32", |b| {
+        let address = &value_f32 as *const f32 as usize;
+        b.iter(|| memory.read_memory::<f32>(black_box(address)).unwrap())
+    });
+    group.finish();
+}
+
+fn bulk_reads(c: &mut Criterion) {
+    let memory = Memory::new().expect("failed to open current process");
+    let mut source = vec![0u8; 1024 * 1024];
+    fill_pseudo_random(&mut source, 1);
+    let address = source.as_ptr() as usize;
+
+    let mut group = c.benchmark_group("bulk_read");
+    for (label, len) in [("4KiB", 4 * 1024), ("1MiB", 1024 * 1024)] {
+        group.throughput(Throughput::Bytes(len as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(label), &len, |b, &len| {
+            b.iter(|| memory.read_bytes(black_box(address), len).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn pattern_scan(c: &mut Criterion) {
+    let mut haystack = vec![0u8; HAYSTACK_SIZE];
+    fill_pseudo_random(&mut haystack, 2);
+
+    let early = make_pattern(3);
+    let late = make_pattern(4);
+    let absent = make_pattern(5);
+    haystack[..PATTERN_LEN].copy_from_slice(&early);
+    haystack[HAYSTACK_SIZE - PATTERN_LEN..].copy_from_slice(&late);
+
+    let mut memory = Memory::new().expect("failed to open current process");
+    memory.scan_memory().expect("failed to scan memory regions");
+    let start = haystack.as_ptr() as usize;
+    let filter = RegionFilter::new().range(start..start + HAYSTACK_SIZE);
+
+    let mut group = c.benchmark_group("pattern_scan");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(HAYSTACK_SIZE as u64));
+    for (position, pattern) in [("early", &early), ("late", &late), ("absent", &absent)] {
+        for (density, every) in [("exact", 0), ("wild_1_in_4", 4), ("wild_1_in_2", 2)] {
+            let pattern = Pattern::new(pattern, &make_mask(every)).unwrap();
+            group.bench_function(BenchmarkId::new(position, density), |b| {
+                b.iter(|| memory.find_pattern(black_box(&pattern), &filter).unwrap())
+            });
+        }
+    }
+    group.finish();
+
+    black_box(&haystack);
+}
+
+// The matcher alone, over a buffer already in hand: the difference to `pattern_scan` is the
+// cost of reading the target.
+fn buffer_search(c: &mut Criterion) {
+    let mut haystack = vec![0u8; HAYSTACK_SIZE];
+    fill_pseudo_random(&mut haystack, 2);
+    let absent = make_pattern(5);
+
+    let mut group = c.benchmark_group("buffer_search");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(HAYSTACK_SIZE as u64));
+    for (density, every) in [("exact", 0), ("wild_1_in_4", 4), ("wild_1_in_2", 2)] {
+        let pattern = Pattern::new(&absent, &make_mask(every)).unwrap();
+        group.bench_function(BenchmarkId::new("absent", density), |b| {
+            b.iter(|| pattern::search(black_box(&haystack), &pattern).len())
+        });
+    }
+    group.finish();
+}
+
+// One "next scan" round over the candidates of a first scan. Between passes a known share of
+// them changes, so each round has survivors to keep as well as candidates to drop.
+fn value_scan_narrowing(c: &mut Criterion) {
+    const SLOTS: usize = 1024 * 1024;
+    const TARGET: u32 = 1337;
+    // Every fourth candidate changes before the round.
+    const CHANGE_EVERY: usize = 4;
+
+    let memory = Memory::new().expect("failed to open current process");
+    let mut values: Vec<u32> = (0..SLOTS as u32).map(|i| i % 4096).collect();
+    let indices: Vec<usize> = (0..SLOTS).filter(|&i| values[i] == TARGET).collect();
+    let base = values.as_ptr() as usize;
+    let changed = indices.len().div_ceil(CHANGE_EVERY);
+
+    let mut group = c.benchmark_group("value_scan");
+    group.throughput(Throughput::Elements(indices.len() as u64));
+    group.bench_function("narrow_u32", |b| {
+        b.iter_batched(
+            || {
+                for &i in &indices {
+                    values[i] = TARGET;
+                }
+                let session = ScanSession::<u32>::from_addresses(&memory, indices.iter().map(|&i| base + i * 4));
+                for &i in indices.iter().step_by(CHANGE_EVERY) {
+                    values[i] = TARGET + 1;
+                }
+                black_box(&mut values);
+                session
+            },
+            |mut session| {
+                let survivors = session.next_scan(&memory, ScanFilter::Changed);
+                assert_eq!(survivors, changed);
+                survivors
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn value_scan_alignment(c: &mut Criterion) {
+    const SIZE: usize = 64 * 1024 * 1024;
+
+    // Zero-filled so every offset is a candidate for the first byte of the needle.
+    let haystack = vec![0u8; SIZE];
+    let mut memory = Memory::new().expect("failed to open current process");
+    memory.scan_memory().expect("failed to scan memory regions");
+    let start = haystack.as_ptr() as usize;
+    let filter = RegionFilter::new().range(start..start + SIZE);
+
+    let mut group = c.benchmark_group("value_scan_alignment");
+    group.sample_size(10);
+    group.throughput(Throughput::Bytes(SIZE as u64));
+    for alignment in [1, 4, 8] {
+        let options = ScanOptions::new().alignment(alignment);
+        group.bench_function(BenchmarkId::from_parameter(alignment), |b| {
+            b.iter(|| memory.scan_for_value_with(black_box(0u64), &filter, &options).unwrap().len())
+        });
+    }
+    group.finish();
+
+    black_box(&haystack);
+}
+
+criterion_group!(
+    benches,
+    typed_reads,
+    bulk_reads,
+    pattern_scan,
+    buffer_search,
+    value_scan_narrowing,
+    value_scan_alignment
+);
+criterion_main!(benches);
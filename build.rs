@@ -0,0 +1,38 @@
+fn main() {
+    #[cfg(feature = "capi")]
+    generate_header();
+}
+
+// Writes the C header for the `capi` feature into OUT_DIR, and also into the directory named
+// by NIRVANA_HEADER_DIR (relative to the crate) if that is set; the build never writes into
+// the source tree on its own. A failure only warns so it never breaks the build.
+#[cfg(feature = "capi")]
+fn generate_header() {
+    use std::path::PathBuf;
+
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=NIRVANA_HEADER_DIR");
+
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap_or_default();
+    let bindings = match cbindgen::Builder::new().with_crate(&crate_dir).with_config(config).generate() {
+        Ok(bindings) => bindings,
+        Err(e) => {
+            println!("cargo:warning=failed to generate the C header: {}", e);
+            return;
+        }
+    };
+
+    let mut dirs = vec![PathBuf::from(std::env::var("OUT_DIR").unwrap())];
+    if let Some(dir) = std::env::var_os("NIRVANA_HEADER_DIR") {
+        dirs.push(PathBuf::from(&crate_dir).join(dir));
+    }
+    for dir in dirs {
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            println!("cargo:warning=failed to create {}: {}", dir.display(), e);
+            continue;
+        }
+        bindings.write_to_file(dir.join("nirvana_notject.h"));
+    }
+}
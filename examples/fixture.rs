@@ -0,0 +1,72 @@
+// A target process with a known layout, for trying the crate out against something that
+// holds still. It starts a sandbox (see src/sandbox.rs) with a set of well-known objects,
+// prints their addresses as one line of JSON on stdout, and then waits until stdin is closed:
+//
+//     cargo run --example fixture --features sandbox
+//     {"pid":4242,"pattern":{"address":"0x1F0A2C40010","bytes":"4E 4E 46 58 ..."},...}
+//
+// `pid` is the sandbox's. Addresses are hex strings, because JSON numbers lose precision
+// past 2^53.
+
+use std::io::{self, BufRead, Write};
+use std::process;
+
+use nirvana_notject::sandbox::{self, CHAIN_F32, CHAIN_I32};
+use nirvana_notject::{Sandbox, SandboxConfig};
+use serde_json::json;
+
+// Tag that starts the pattern block, then bytes no scan is likely to find elsewhere.
+const PATTERN: [u8; 16] = [
+    b'N', b'N', b'F', b'X', 0xDE, 0xAD, 0xBE, 0xEF, 0x13, 0x37, 0xC0, 0xDE, 0x00, 0xFF, 0x5A, 0xA5,
+];
+const C_STRING: &str = "nirvana fixture string";
+const WIDE_STRING: &str = "nirvana fixture wide string";
+
+fn hex(address: usize) -> String {
+    format!("0x{:X}", address)
+}
+
+fn main() {
+    sandbox::run_if_child();
+    if let Err(e) = run() {
+        eprintln!("fixture: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run() -> io::Result<()> {
+    let mut c_string = C_STRING.as_bytes().to_vec();
+    c_string.push(0);
+    let wide_string: Vec<u8> = WIDE_STRING.encode_utf16().chain(Some(0)).flat_map(u16::to_le_bytes).collect();
+    let config =
+        SandboxConfig::new().pattern(&PATTERN).pattern(&c_string).pattern(&wide_string).pointer_chain().guard_page();
+    let sandbox = Sandbox::spawn(&config)?;
+
+    let layout = sandbox.layout();
+    let chain = layout.pointer_chain.clone().unwrap_or_default();
+    let fixture = json!({
+        "pid": layout.pid,
+        "pattern": {
+            "address": hex(layout.patterns[0]),
+            "bytes": PATTERN.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" "),
+        },
+        "c_string": { "address": hex(layout.patterns[1]), "text": C_STRING },
+        "wide_string": { "address": hex(layout.patterns[2]), "text": WIDE_STRING },
+        "i32": { "address": hex(chain.target), "value": CHAIN_I32 },
+        "f32": { "address": hex(chain.scale), "value": CHAIN_F32 },
+        "pointer_chain": { "root": hex(chain.root), "offsets": chain.offsets, "target": hex(chain.target) },
+        "guard_page": hex(layout.guard_page.unwrap_or_default()),
+    });
+
+    let mut stdout = io::stdout().lock();
+    writeln!(stdout, "{}", fixture)?;
+    stdout.flush()?;
+
+    // Keep the sandbox until the parent closes our stdin (or kills us).
+    for line in io::stdin().lock().lines() {
+        if line.is_err() {
+            break;
+        }
+    }
+    sandbox.stop()
+}
@@ -0,0 +1,64 @@
+// A scan sink of one's own: results handled as the scan finds them instead of collected and
+// sorted through afterwards. This one keeps the first match per page, as a dedup a caller
+// might want and the crate doesn't offer, and is teed with the JSON lines sink so every match
+// is printed as well:
+//
+//     cargo run --example scan_sink -- 4242 "4E 4E 46 58"
+//     {"match":"0x1F0A2C40010"}
+//     ...
+//     3 pages with matches, first at 0x1F0A2C40010
+//
+// Pointed at the fixture example's pid and pattern, it finds the fixture's pattern block.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+
+use nirvana_notject::{JsonLinesSink, Memory, Pattern, RegionFilter, ScanOptions, ScanReport, ScanSink, SkipReason, Tee};
+
+const PAGE_SIZE: usize = 0x1000;
+
+// The first match on each page, by page.
+#[derive(Default)]
+struct FirstPerPage {
+    pages: BTreeMap<usize, usize>,
+    regions_done: usize,
+}
+
+impl ScanSink for FirstPerPage {
+    fn on_match(&mut self, address: usize) -> ControlFlow<()> {
+        self.pages.entry(address / PAGE_SIZE).or_insert(address);
+        ControlFlow::Continue(())
+    }
+
+    fn on_region_done(&mut self, _: usize, _: Option<SkipReason>) {
+        self.regions_done += 1;
+    }
+
+    fn on_complete(&mut self, report: &ScanReport) {
+        eprintln!("{}", report);
+    }
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [pid, pattern] = args.as_slice() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "usage: scan_sink <pid> \"<pattern>\""));
+    };
+    let pid = pid.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid pid"))?;
+
+    let mut memory = Memory::open(pid)?;
+    memory.scan_memory()?;
+    let sink = Arc::new(Mutex::new(Tee(JsonLinesSink::new(io::stdout()), FirstPerPage::default())));
+    memory.find_pattern_into(&Pattern::parse(pattern)?, &RegionFilter::new(), &ScanOptions::new(), &sink)?;
+
+    let sink = sink.lock().unwrap();
+    let pages = &sink.1.pages;
+    match pages.values().next() {
+        Some(first) => println!("{} pages with matches, first at 0x{:X}", pages.len(), first),
+        None => println!("no matches in {} regions", sink.1.regions_done),
+    }
+    Ok(())
+}
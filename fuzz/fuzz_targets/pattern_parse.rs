@@ -0,0 +1,18 @@
+// Pattern::parse on arbitrary text, then searching with whatever parsed, forwards and back.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nirvana_notject::Pattern;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else { return };
+    let Ok(pattern) = Pattern::parse(text) else { return };
+
+    // The input doubles as the haystack.
+    let haystack = &data[..data.len().min(256)];
+    for start in 0..=haystack.len() + 1 {
+        let _ = pattern.find_from(haystack, start);
+        let _ = pattern.rfind_before(haystack, start);
+    }
+});
@@ -0,0 +1,24 @@
+// The PE parser on arbitrary bytes, read both as a file (headers) and as a laid-out image
+// (relocations, imports, exports), the way manual mapping and export resolution use it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nirvana_notject::pe::{self, ImportName, PeHeaders, DIRECTORY_BASERELOC, DIRECTORY_EXPORT, DIRECTORY_IMPORT};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(headers) = PeHeaders::parse(data) else { return };
+
+    if let Some(directory) = headers.directory(DIRECTORY_BASERELOC) {
+        let _ = pe::relocations(data, directory);
+    }
+    if let Some(directory) = headers.directory(DIRECTORY_IMPORT) {
+        let _ = pe::imports(data, directory, headers.is_64);
+    }
+    if let Some(directory) = headers.directory(DIRECTORY_EXPORT) {
+        if let Ok(exports) = pe::Exports::parse(data, 0, directory) {
+            let _ = exports.get(&ImportName::Ordinal(1));
+            let _ = exports.get(&ImportName::Name("DllMain".to_string()));
+        }
+    }
+});
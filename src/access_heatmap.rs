@@ -0,0 +1,139 @@
+// Which code writes a value, worked out without a debugger: sample where every thread is
+// executing while watching the value, and count which instruction pointers keep turning up
+// in the ticks where it changed.
+//
+//     let heatmap = memory.access_heatmap(health, 4, Duration::from_secs(10), Duration::from_millis(1))?;
+//     for entry in heatmap.entries.iter().take(5) {
+//         println!("{}", entry);
+//     }
+//
+// It is statistical: a writer that changes the value rarely, or spends most of its time
+// elsewhere, shows up weakly or not at all. Code that runs in every tick (a render loop, a
+// wait) scores on change ticks as well, which `seen` exposes. Shorter intervals give sharper
+// results but suspend the target's threads more often.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::memory::Memory;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessHeatmap {
+    pub address: usize,
+    pub len: usize,
+    pub ticks: usize,
+    // Ticks in which the value differed from the tick before.
+    pub changes: usize,
+    // Most hits first.
+    pub entries: Vec<HeatmapEntry>,
+}
+
+// One instruction pointer observed in a change tick.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeatmapEntry {
+    pub address: usize,
+    pub symbol: Option<String>,
+    // Change ticks in which some thread was at this address.
+    pub hits: usize,
+    // Ticks of any kind in which some thread was at this address.
+    pub seen: usize,
+}
+
+impl HeatmapEntry {
+    // Share of the ticks at this address in which the value changed; close to 1 for code that
+    // only runs when it writes the value.
+    pub fn correlation(&self) -> f64 {
+        self.hits as f64 / self.seen.max(1) as f64
+    }
+}
+
+// "game.exe!.text+0x1A2F3  12 hits, seen 14"
+impl fmt::Display for HeatmapEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.symbol {
+            Some(symbol) => write!(f, "{}", symbol)?,
+            None => write!(f, "0x{:X}", self.address)?,
+        }
+        write!(f, "  {} hit{}, seen {}", self.hits, if self.hits == 1 { "" } else { "s" }, self.seen)
+    }
+}
+
+impl Memory {
+    // Watches the `len` bytes at `address` for `duration`, sampling every thread's instruction
+    // pointer and then the value once per `interval`. Instruction pointers sampled in a tick
+    // where the value changed are credited with a hit. Fails if the value can't be read when
+    // watching starts; ticks where it can't be read later are skipped. Entries are symbolized
+    // against the cached modules, so call `scan_modules` first.
+    pub fn access_heatmap(
+        &self,
+        address: usize,
+        len: usize,
+        duration: Duration,
+        interval: Duration,
+    ) -> io::Result<AccessHeatmap> {
+        if len == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The watched value must not be empty"));
+        }
+        let mut previous = read_value(self, address, len)?;
+        let mut seen: HashMap<usize, (usize, usize)> = HashMap::new();
+        let mut ticks = 0;
+        let mut changes = 0;
+
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            let tick = Instant::now();
+            let contexts = self.thread_contexts()?;
+            if let Ok(current) = read_value(self, address, len) {
+                ticks += 1;
+                let changed = current != previous;
+                if changed {
+                    changes += 1;
+                    previous = current;
+                }
+
+                let mut addresses: Vec<usize> = contexts.iter().map(|context| context.instruction_pointer).collect();
+                // Two threads at the same address count once per tick.
+                addresses.sort_unstable();
+                addresses.dedup();
+                for ip in addresses {
+                    let (hits, count) = seen.entry(ip).or_default();
+                    *count += 1;
+                    if changed {
+                        *hits += 1;
+                    }
+                }
+            }
+
+            if let Some(rest) = interval.checked_sub(tick.elapsed()) {
+                thread::sleep(rest);
+            }
+        }
+
+        let mut entries: Vec<HeatmapEntry> = seen
+            .into_iter()
+            .filter(|(_, (hits, _))| *hits > 0)
+            .map(|(ip, (hits, seen))| HeatmapEntry { address: ip, symbol: None, hits, seen })
+            .collect();
+        // Ties go to the address seen less often outside change ticks.
+        entries.sort_by(|a, b| b.hits.cmp(&a.hits).then(a.seen.cmp(&b.seen)).then(a.address.cmp(&b.address)));
+        for entry in &mut entries {
+            entry.symbol = self.symbolize(entry.address);
+        }
+
+        Ok(AccessHeatmap { address, len, ticks, changes, entries })
+    }
+}
+
+fn read_value(memory: &Memory, address: usize, len: usize) -> io::Result<Vec<u8>> {
+    let bytes = memory.read_bytes(address, len)?;
+    if bytes.len() < len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Only 0x{:X} of 0x{:X} bytes at 0x{:X} are readable", bytes.len(), len, address),
+        ));
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,321 @@
+// Newtypes for addresses in the target and sizes of ranges in it, so one can't be passed
+// where the other is meant and no arithmetic on them can wrap silently. There is no `Add`:
+// moving an address goes through `checked_add`/`checked_offset`, which return None instead
+// of overflowing. Both convert to and from `usize`, and APIs that take an address accept
+// anything `Into<Address>`, so plain `usize`s keep working.
+//
+// Both print and parse as hex; see the `FromStr` impls for what they accept.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::num::ParseIntError;
+use std::ops::{Range, Sub};
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Address(usize);
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Size(usize);
+
+impl Address {
+    pub const NULL: Address = Address(0);
+
+    pub const fn new(address: usize) -> Self {
+        Address(address)
+    }
+
+    pub const fn get(self) -> usize {
+        self.0
+    }
+
+    pub fn is_null(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, size: impl Into<Size>) -> Option<Address> {
+        self.0.checked_add(size.into().0).map(Address)
+    }
+
+    pub fn checked_sub(self, size: impl Into<Size>) -> Option<Address> {
+        self.0.checked_sub(size.into().0).map(Address)
+    }
+
+    // Moves by a signed byte offset, as for struct fields with negative offsets.
+    pub fn checked_offset(self, offset: isize) -> Option<Address> {
+        self.0.checked_add_signed(offset).map(Address)
+    }
+
+    // Bytes from `base` up to this address; None if `base` lies above it.
+    pub fn checked_distance_from(self, base: Address) -> Option<Size> {
+        self.0.checked_sub(base.0).map(Size)
+    }
+
+    // `self..self + size`, or None if that would run past the end of the address space.
+    pub fn range(self, size: impl Into<Size>) -> Option<Range<usize>> {
+        let end = self.checked_add(size)?;
+        Some(self.0..end.0)
+    }
+
+    pub fn is_aligned(self, alignment: usize) -> bool {
+        self.0 % alignment.max(1) == 0
+    }
+}
+
+impl Size {
+    pub const ZERO: Size = Size(0);
+
+    pub const fn new(size: usize) -> Self {
+        Size(size)
+    }
+
+    pub const fn get(self) -> usize {
+        self.0
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: impl Into<Size>) -> Option<Size> {
+        self.0.checked_add(other.into().0).map(Size)
+    }
+
+    pub fn checked_sub(self, other: impl Into<Size>) -> Option<Size> {
+        self.0.checked_sub(other.into().0).map(Size)
+    }
+
+    pub fn checked_mul(self, factor: usize) -> Option<Size> {
+        self.0.checked_mul(factor).map(Size)
+    }
+}
+
+// The distance between two addresses. Panics if `rhs` lies above `self`, like integer
+// subtraction in debug builds but in release builds too; use `checked_distance_from` when
+// the order isn't known.
+impl Sub for Address {
+    type Output = Size;
+
+    fn sub(self, rhs: Address) -> Size {
+        self.checked_distance_from(rhs)
+            .unwrap_or_else(|| panic!("address {} lies above {}", rhs, self))
+    }
+}
+
+impl From<usize> for Address {
+    fn from(address: usize) -> Self {
+        Address(address)
+    }
+}
+
+impl From<Address> for usize {
+    fn from(address: Address) -> Self {
+        address.0
+    }
+}
+
+impl<T> From<*const T> for Address {
+    fn from(pointer: *const T) -> Self {
+        Address(pointer as usize)
+    }
+}
+
+impl<T> From<*mut T> for Address {
+    fn from(pointer: *mut T) -> Self {
+        Address(pointer as usize)
+    }
+}
+
+impl From<usize> for Size {
+    fn from(size: usize) -> Self {
+        Size(size)
+    }
+}
+
+impl From<Size> for usize {
+    fn from(size: Size) -> Self {
+        size.0
+    }
+}
+
+// Comparing against plain integers is harmless, unlike mixing them in arithmetic.
+impl PartialEq<usize> for Address {
+    fn eq(&self, other: &usize) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialOrd<usize> for Address {
+    fn partial_cmp(&self, other: &usize) -> Option<Ordering> {
+        self.0.partial_cmp(other)
+    }
+}
+
+impl PartialEq<usize> for Size {
+    fn eq(&self, other: &usize) -> bool {
+        self.0 == *other
+    }
+}
+
+impl PartialOrd<usize> for Size {
+    fn partial_cmp(&self, other: &usize) -> Option<Ordering> {
+        self.0.partial_cmp(other)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:X}", self.0)
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Address(0x{:X})", self.0)
+    }
+}
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:X}", self.0)
+    }
+}
+
+impl fmt::Debug for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Size(0x{:X})", self.0)
+    }
+}
+
+impl fmt::UpperHex for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::UpperHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::LowerHex for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::UpperHex for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::UpperHex::fmt(&self.0, f)
+    }
+}
+
+impl fmt::LowerHex for Size {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.0, f)
+    }
+}
+
+// Hex, with or without a "0x" prefix: "7FF612340000" and "0x7ff612340000" are the same.
+impl FromStr for Address {
+    type Err = ParseIntError;
+
+    fn from_str(text: &str) -> Result<Self, ParseIntError> {
+        let digits = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")).unwrap_or(text);
+        usize::from_str_radix(digits, 16).map(Address)
+    }
+}
+
+// Hex with a "0x" prefix, decimal without one, as sizes are written both ways.
+impl FromStr for Size {
+    type Err = ParseIntError;
+
+    fn from_str(text: &str) -> Result<Self, ParseIntError> {
+        match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+            Some(digits) => usize::from_str_radix(digits, 16).map(Size),
+            None => text.parse().map(Size),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arithmetic_stops_at_the_ends_of_the_address_space() {
+        let top = Address::new(usize::MAX - 1);
+        assert_eq!(top.checked_add(1usize), Some(Address::new(usize::MAX)));
+        assert_eq!(top.checked_add(2usize), None);
+        assert_eq!(Address::new(1).checked_sub(2usize), None);
+        assert_eq!(Address::new(0x1000).checked_offset(-0x10), Some(Address::new(0xFF0)));
+        assert_eq!(Address::new(0x10).checked_offset(-0x11), None);
+        assert_eq!(top.checked_offset(isize::MAX), None);
+
+        assert_eq!(Size::new(usize::MAX).checked_add(1usize), None);
+        assert_eq!(Size::new(2).checked_sub(3usize), None);
+        assert_eq!(Size::new(usize::MAX / 2 + 1).checked_mul(2), None);
+        assert_eq!(Size::new(0x10).checked_mul(0x10), Some(Size::new(0x100)));
+    }
+
+    #[test]
+    fn ranges_and_distances() {
+        assert_eq!(Address::new(0x1000).range(0x10usize), Some(0x1000..0x1010));
+        assert_eq!(Address::new(usize::MAX).range(1usize), None);
+        assert_eq!(Address::new(usize::MAX).range(0usize), Some(usize::MAX..usize::MAX));
+        assert_eq!(Address::new(0x1010).checked_distance_from(Address::new(0x1000)), Some(Size::new(0x10)));
+        assert_eq!(Address::new(0x1000).checked_distance_from(Address::new(0x1010)), None);
+        assert_eq!(Address::new(0x1010) - Address::new(0x1000), Size::new(0x10));
+    }
+
+    #[test]
+    #[should_panic(expected = "address 0x1010 lies above 0x1000")]
+    fn subtracting_a_higher_address_panics() {
+        let _ = Address::new(0x1000) - Address::new(0x1010);
+    }
+
+    #[test]
+    fn alignment_treats_zero_as_one() {
+        assert!(Address::new(0x1000).is_aligned(0x1000));
+        assert!(!Address::new(0x1004).is_aligned(8));
+        assert!(Address::new(0x1003).is_aligned(0));
+        assert!(Address::NULL.is_null() && Size::ZERO.is_zero());
+    }
+
+    #[test]
+    fn addresses_parse_as_hex_and_sizes_as_hex_or_decimal() {
+        assert_eq!("7FF61234".parse::<Address>().unwrap(), 0x7FF6_1234usize);
+        assert_eq!("0x7ff61234".parse::<Address>().unwrap(), 0x7FF6_1234usize);
+        assert_eq!("0X10".parse::<Address>().unwrap(), 0x10usize);
+        assert_eq!("10".parse::<Size>().unwrap(), 10usize);
+        assert_eq!("0x10".parse::<Size>().unwrap(), 0x10usize);
+        for text in ["", "0x", "0xG", "12 34"] {
+            assert!(text.parse::<Address>().is_err(), "{:?}", text);
+        }
+        assert!("A".parse::<Size>().is_err());
+    }
+
+    #[test]
+    fn both_print_as_hex() {
+        let address = Address::new(0x7FF6_1234);
+        assert_eq!(address.to_string(), "0x7FF61234");
+        assert_eq!(format!("{:?}", address), "Address(0x7FF61234)");
+        assert_eq!(format!("{:x} {:08X}", address, Address::new(0xAB)), "7ff61234 000000AB");
+        assert_eq!(Size::new(0x1000).to_string(), "0x1000");
+        assert_eq!(format!("{:?}", Size::new(0x1000)), "Size(0x1000)");
+        assert_eq!(address.to_string().parse::<Address>().unwrap(), address);
+    }
+
+    #[test]
+    fn both_serialize_as_plain_numbers() {
+        assert_eq!(serde_json::to_string(&Address::new(0x1000)).unwrap(), "4096");
+        assert_eq!(serde_json::from_str::<Size>("16").unwrap(), Size::new(16));
+    }
+
+    #[test]
+    fn both_compare_with_plain_integers() {
+        assert!(Address::new(0x1000) == 0x1000usize && Address::new(0x1000) < 0x1001usize);
+        assert!(Size::new(4) > 3usize);
+        assert_eq!(usize::from(Address::from(0x20usize)), 0x20);
+        assert_eq!(Address::from(0x20 as *const u8), Address::new(0x20));
+    }
+}
@@ -0,0 +1,109 @@
+// The whole user address space as VirtualQueryEx reports it: committed regions, reserved but
+// uncommitted ranges and free gaps. `enumerate_regions` keeps only the committed part.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+use crate::address::{Address, Size};
+use crate::memory::{Memory, MemoryRegion, MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_RESERVE};
+use crate::stats::format_size;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapEntry {
+    Committed(MemoryRegion),
+    Reserved { base: Address, size: Size, allocation_base: Address },
+    Free { base: Address, size: Size },
+}
+
+impl MapEntry {
+    fn from_mbi(mbi: &MEMORY_BASIC_INFORMATION) -> Self {
+        let base = Address::from(mbi.BaseAddress);
+        let size = Size::new(mbi.RegionSize);
+        match mbi.State {
+            MEM_COMMIT => MapEntry::Committed(MemoryRegion::from_mbi(mbi)),
+            MEM_RESERVE => MapEntry::Reserved { base, size, allocation_base: Address::from(mbi.AllocationBase) },
+            _ => MapEntry::Free { base, size },
+        }
+    }
+
+    pub fn base(&self) -> Address {
+        match self {
+            MapEntry::Committed(region) => region.start_address,
+            MapEntry::Reserved { base, .. } | MapEntry::Free { base, .. } => *base,
+        }
+    }
+
+    pub fn size(&self) -> Size {
+        match self {
+            MapEntry::Committed(region) => region.size,
+            MapEntry::Reserved { size, .. } | MapEntry::Free { size, .. } => *size,
+        }
+    }
+
+    // Saturates at the top of the address space, like `MemoryRegion::end`.
+    pub fn end(&self) -> Address {
+        self.base().checked_add(self.size().get()).unwrap_or(Address::new(usize::MAX))
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.base().get()..self.end().get()
+    }
+
+    pub fn is_free(&self) -> bool {
+        matches!(self, MapEntry::Free { .. })
+    }
+}
+
+impl fmt::Display for MapEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapEntry::Committed(region) => write!(f, "{}", region),
+            MapEntry::Reserved { allocation_base, .. } => write!(
+                f,
+                "{}-{} reserved  {} (allocation {})",
+                self.base(),
+                self.end(),
+                format_size(self.size().get()),
+                allocation_base
+            ),
+            MapEntry::Free { .. } => {
+                write!(f, "{}-{} free  {}", self.base(), self.end(), format_size(self.size().get()))
+            }
+        }
+    }
+}
+
+impl Memory {
+    // Every range from address 0 to the top of user space, in order. Each entry starts where
+    // the previous one ends.
+    pub fn full_map(&self) -> io::Result<Vec<MapEntry>> {
+        let entries = self.map_range(0..usize::MAX);
+        if entries.is_empty() {
+            return Err(io::Error::other("VirtualQueryEx failed at address 0"));
+        }
+        Ok(entries)
+    }
+
+    // The entries overlapping `range`; the first and last may extend past it.
+    pub(crate) fn map_range(&self, range: Range<usize>) -> Vec<MapEntry> {
+        let mut entries: Vec<MapEntry> = Vec::new();
+        let mut address = range.start;
+
+        while address < range.end {
+            let Ok(mbi) = self.query(address) else { break };
+            let entry = MapEntry::from_mbi(&mbi);
+            debug_assert!(entries.last().is_none_or(|last| last.end() == entry.base()), "map entries must tile");
+            let next = entry.base().checked_add(entry.size().get());
+            entries.push(entry);
+
+            // The last entry can end exactly at the top of the address space.
+            match next {
+                Some(next) if next > address => address = next.get(),
+                _ => break,
+            }
+        }
+
+        entries
+    }
+}
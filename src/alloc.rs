@@ -0,0 +1,204 @@
+use std::ffi::c_void;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+use log::debug;
+
+use crate::address_space::MapEntry;
+use crate::capabilities::Capabilities;
+use crate::memory::Memory;
+use crate::region_info::{RegionInfo, RegionState};
+use crate::sys::{VirtualAllocEx, VirtualFreeEx, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE};
+use crate::win_error::win_call;
+
+// VirtualAllocEx hands out addresses at this granularity.
+pub const ALLOCATION_GRANULARITY: usize = 0x10000;
+
+// Farthest a rel32 jump or rip-relative operand can reach.
+const REL32_RANGE: usize = 0x7FFF_0000;
+
+// An allocation made by `Memory::alloc_at`; `Memory::free(address)` releases it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemoteAllocation {
+    pub address: usize,
+    pub size: usize,
+    pub protection: u32,
+}
+
+impl RemoteAllocation {
+    pub fn range(&self) -> Range<usize> {
+        self.address..self.address + self.size
+    }
+}
+
+// Payload of the `io::Error` `alloc_at` returns when its range isn't free. See
+// `address_unavailable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressUnavailable {
+    pub address: usize,
+    pub size: usize,
+    // The first region in the range that isn't free. None if the range looked free but the
+    // allocation failed anyway and had no other reason.
+    pub conflict: Option<RegionInfo>,
+}
+
+impl fmt::Display for AddressUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:X}-0x{:X} is not free", self.address, self.address.saturating_add(self.size))?;
+        match &self.conflict {
+            Some(region) => write!(f, ": {}", region),
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::error::Error for AddressUnavailable {}
+
+pub fn address_unavailable(e: &io::Error) -> Option<AddressUnavailable> {
+    e.get_ref()?.downcast_ref::<AddressUnavailable>().copied()
+}
+
+impl Memory {
+    // Reserves and commits `size` bytes anywhere in the target.
+    pub fn allocate(&self, size: usize, protection: u32) -> io::Result<usize> {
+        self.require(Capabilities::VM_OPERATION)?;
+        self.allocate_at(0, size, protection)
+    }
+
+    // Allocates within rel32 reach of `address`, so code placed there can jump to and from it
+    // with 5-byte jumps. The nearest free spot on either side is found with the same search
+    // as `find_free_range` and tried first; a side whose spot was taken since the map was
+    // read looks again past it.
+    pub fn allocate_near(&self, address: usize, size: usize, protection: u32) -> io::Result<usize> {
+        self.require(Capabilities::VM_OPERATION)?;
+        let low = address.saturating_sub(REL32_RANGE);
+        let high = address.saturating_add(REL32_RANGE);
+
+        // Below `address` the top of a free range is the closest part, above it the bottom.
+        let mut below = address;
+        let mut above = address;
+        loop {
+            let under = self
+                .free_range(size, low..below, true)
+                .map(|range| (range.end - size) & !(ALLOCATION_GRANULARITY - 1));
+            let over = self.free_range(size, above..high, false).map(|range| range.start);
+            let candidate = match (under, over) {
+                (Some(under), Some(over)) if under.abs_diff(address) <= over.abs_diff(address) => under,
+                (_, Some(over)) => over,
+                (Some(under), None) => under,
+                (None, None) => break,
+            };
+            match self.allocate_at(candidate, size, protection) {
+                Ok(allocated) => return Ok(allocated),
+                Err(e) => debug!("allocating 0x{:X} bytes at 0x{:X} failed: {}", size, candidate, e),
+            }
+            if Some(candidate) == under {
+                below = candidate;
+            } else {
+                above = candidate + ALLOCATION_GRANULARITY;
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            format!("No free 0x{:X}-byte block within rel32 reach of 0x{:X}", size, address),
+        ))
+    }
+
+    // Reserves and commits `size` bytes at exactly `preferred`, which must be a multiple of
+    // `ALLOCATION_GRANULARITY`, rather than anywhere else. If part of the range is taken the
+    // error's kind is AddrInUse and it carries an `AddressUnavailable` naming what is in the
+    // way, from a live query; see `address_unavailable`.
+    pub fn alloc_at(&self, preferred: usize, size: usize, protection: u32) -> io::Result<RemoteAllocation> {
+        self.require(Capabilities::VM_OPERATION)?;
+        if preferred == 0 || preferred % ALLOCATION_GRANULARITY != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "0x{:X} is not a multiple of the allocation granularity 0x{:X}",
+                    preferred, ALLOCATION_GRANULARITY
+                ),
+            ));
+        }
+        let Some(end) = preferred.checked_add(size).filter(|_| size > 0) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid allocation size 0x{:X}", size)));
+        };
+
+        let unavailable = |conflict| {
+            let unavailable = AddressUnavailable { address: preferred, size, conflict };
+            io::Error::new(io::ErrorKind::AddrInUse, unavailable)
+        };
+        let conflict = |memory: &Memory| -> io::Result<Option<RegionInfo>> {
+            let regions = memory.query_range(preferred..end)?;
+            Ok(regions.into_iter().find(|region| region.state != RegionState::Free))
+        };
+        if let Some(region) = conflict(self)? {
+            return Err(unavailable(Some(region)));
+        }
+        match self.allocate_at(preferred, size, protection) {
+            Ok(address) => Ok(RemoteAllocation { address, size, protection }),
+            // Taken since the query, or refused for a reason the map doesn't show.
+            Err(e) => match conflict(self)? {
+                Some(region) => Err(unavailable(Some(region))),
+                None => Err(e),
+            },
+        }
+    }
+
+    // A free range of at least `min_size` bytes, starting on the allocation granularity, that
+    // lies above `above` and below `below`, clipped to those bounds. The search runs upwards
+    // from `above` (or the bottom of the address space) and returns the lowest such range;
+    // with only `below` given it runs downwards from there and returns the highest. Taken
+    // from the live map, so it may be stale by the time the caller allocates.
+    pub fn find_free_range(&self, min_size: usize, below: Option<usize>, above: Option<usize>) -> Option<Range<usize>> {
+        let highest = above.is_none() && below.is_some();
+        self.free_range(min_size, above.unwrap_or(0)..below.unwrap_or(usize::MAX), highest)
+    }
+
+    fn free_range(&self, min_size: usize, bounds: Range<usize>, highest: bool) -> Option<Range<usize>> {
+        if min_size == 0 || bounds.is_empty() {
+            return None;
+        }
+        let fits = |entry: &MapEntry| {
+            if !entry.is_free() {
+                return None;
+            }
+            // Address 0 would mean "anywhere" to VirtualAllocEx, so the first granule is out.
+            let start = entry.base().get().max(bounds.start).max(1).checked_next_multiple_of(ALLOCATION_GRANULARITY)?;
+            let end = entry.end().get().min(bounds.end);
+            (start.checked_add(min_size)? <= end).then_some(start..end)
+        };
+        let entries = self.map_range(bounds.clone());
+        match highest {
+            true => entries.iter().rev().find_map(fits),
+            false => entries.iter().find_map(fits),
+        }
+    }
+
+    // Releases an allocation made by `allocate`, `allocate_near` or `alloc_at`.
+    pub fn free(&self, address: usize) -> io::Result<()> {
+        self.require(Capabilities::VM_OPERATION)?;
+        win_call!(
+            unsafe { VirtualFreeEx(self.handle(), address as *mut c_void, 0, MEM_RELEASE) },
+            "Failed to free the allocation at 0x{:X}",
+            address
+        )?;
+        debug!("freed allocation at 0x{:X}", address);
+        self.allocation_list().lock().unwrap().retain(|allocation| allocation.start != address);
+        Ok(())
+    }
+
+    fn allocate_at(&self, address: usize, size: usize, protection: u32) -> io::Result<usize> {
+        let requested = address as *const c_void;
+        let allocated = win_call!(
+            unsafe { VirtualAllocEx(self.handle(), requested, size, MEM_COMMIT | MEM_RESERVE, protection) },
+            "Failed to allocate 0x{:X} bytes at 0x{:X}",
+            size,
+            address
+        )? as usize;
+        debug!("allocated 0x{:X} bytes at 0x{:X}", size, allocated);
+        self.allocation_list().lock().unwrap().push(allocated..allocated + size);
+        Ok(allocated)
+    }
+}
@@ -0,0 +1,89 @@
+// Regions grouped by the VirtualAlloc (or section view) they came from. VirtualQueryEx
+// reports a separate region for every run of pages with its own protection, so one
+// allocation whose middle was reprotected shows up as three regions.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::address::{Address, Size};
+use crate::memory::{Memory, MemoryRegion};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    pub base: Address,
+    // From the first region's start to the last region's end. Uncommitted pages inside the
+    // allocation are not listed as regions, so the span may have holes.
+    pub span: Range<usize>,
+    // The protection covering the most bytes; the lowest address breaks ties.
+    pub dominant_protection: u32,
+    // In ascending address order.
+    pub regions: Vec<MemoryRegion>,
+}
+
+impl Allocation {
+    // Bytes in the regions, not counting holes in the span.
+    pub fn committed_size(&self) -> Size {
+        Size::new(self.regions.iter().map(|region| region.size.get()).sum())
+    }
+
+    pub fn contains(&self, address: impl Into<Address>) -> bool {
+        let address = address.into();
+        self.regions.iter().any(|region| region.contains(address))
+    }
+
+    // More than one protection inside the allocation.
+    pub fn is_split(&self) -> bool {
+        self.regions.windows(2).any(|pair| pair[0].protection != pair[1].protection)
+    }
+}
+
+// Groups `regions` by allocation base, in ascending address order. Regions sharing a base
+// are always gathered into one `Allocation`, even when other regions lie between them in the
+// input.
+pub fn group_allocations(regions: &[MemoryRegion]) -> Vec<Allocation> {
+    let mut sorted: Vec<&MemoryRegion> = regions.iter().collect();
+    sorted.sort_by_key(|region| region.start_address);
+
+    let mut index_by_base = HashMap::new();
+    let mut groups: Vec<Vec<MemoryRegion>> = Vec::new();
+    for region in sorted {
+        let index = *index_by_base.entry(region.allocation_base).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[index].push(region.clone());
+    }
+
+    groups
+        .into_iter()
+        .map(|regions| {
+            let mut bytes_by_protection: Vec<(u32, usize)> = Vec::new();
+            for region in &regions {
+                match bytes_by_protection.iter_mut().find(|(protection, _)| *protection == region.protection) {
+                    Some((_, bytes)) => *bytes += region.size.get(),
+                    None => bytes_by_protection.push((region.protection, region.size.get())),
+                }
+            }
+            // max_by_key keeps the last maximum; reversing makes the first one win.
+            let dominant_protection = bytes_by_protection
+                .iter()
+                .rev()
+                .max_by_key(|(_, bytes)| *bytes)
+                .map_or(0, |(protection, _)| *protection);
+
+            Allocation {
+                base: regions[0].allocation_base,
+                span: regions[0].start_address.get()..regions[regions.len() - 1].end().get(),
+                dominant_protection,
+                regions,
+            }
+        })
+        .collect()
+}
+
+impl Memory {
+    // The cached regions grouped by allocation. See `group_allocations`.
+    pub fn allocations(&self) -> Vec<Allocation> {
+        group_allocations(self.cached_regions())
+    }
+}
@@ -0,0 +1,276 @@
+// Which code allocates what, by sampling. Every tick the region map is diffed against the
+// previous one, and for each private range that was committed since, every thread of the
+// target is stopped long enough to read where it is executing. Code that keeps showing up
+// next to allocations of one size is likely the code making them:
+//
+//     memory.scan_modules()?;
+//     let tracer = AllocationTracer::start(&memory, Duration::from_millis(20));
+//     thread::sleep(Duration::from_secs(30));
+//     let report = tracer.stop();
+//     print!("{}", report);
+//
+// The samples are taken up to a tick after the allocation, so the allocating thread may have
+// moved on: it's a correlation, not a call stack. Besides the instruction pointer, the top of
+// each stack is scanned for values pointing into executable images, which are likely return
+// addresses and survive longer than the instruction pointer. Sub-page heap allocations don't
+// commit pages and are never seen; only new and grown VirtualAlloc ranges are.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::debug;
+use serde::Serialize;
+
+use crate::memory::{Memory, MemoryRegion, RegionType};
+use crate::process_handle::{spawn_worker, upgrade};
+use crate::protection_monitor;
+use crate::region_snapshot;
+use crate::stats::format_size;
+
+// Bytes above each stack pointer scanned for return addresses.
+const STACK_SCAN_BYTES: usize = 0x200;
+
+// Return address candidates kept per thread, nearest the stack pointer first.
+const STACK_DEPTH: usize = 4;
+
+// Code locations listed per size class in the report.
+pub const SITES_PER_CLASS: usize = 10;
+
+// A code location seen while allocations of one size class appeared.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AllocationSite {
+    pub address: usize,
+    // "module!.section+0xOFFSET", if the modules were scanned before starting.
+    pub symbol: Option<String>,
+    // Allocations this location was seen with, at most once per thread each.
+    pub hits: u64,
+    // Of those, the times it was an instruction pointer rather than on a stack.
+    pub instruction_pointer_hits: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SizeClass {
+    // Allocations of up to this many bytes and more than half of it; a power of two.
+    pub size: usize,
+    pub allocations: u64,
+    pub bytes: u64,
+    // SITES_PER_CLASS at most, most hits first.
+    pub sites: Vec<AllocationSite>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AllocationReport {
+    pub ticks: u64,
+    pub allocations: u64,
+    // Ticks on which the threads couldn't be listed; their allocations count, unattributed.
+    pub missed_samples: u64,
+    // Smallest size first.
+    pub size_classes: Vec<SizeClass>,
+}
+
+impl AllocationReport {
+    // One "size_class,allocations,bytes,address,symbol,hits,instruction_pointer_hits" row per
+    // site, in report order.
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "size_class,allocations,bytes,address,symbol,hits,instruction_pointer_hits")?;
+        for class in &self.size_classes {
+            for site in &class.sites {
+                writeln!(
+                    writer,
+                    "{},{},{},0x{:X},{},{},{}",
+                    class.size,
+                    class.allocations,
+                    class.bytes,
+                    site.address,
+                    site.symbol.as_deref().unwrap_or_default(),
+                    site.hits,
+                    site.instruction_pointer_hits
+                )?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+// "<= 1.0 MiB: 37 allocations, 37.0 MiB" and the sites below it, one per line.
+impl fmt::Display for AllocationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} allocations in {} ticks", self.allocations, self.ticks)?;
+        for class in &self.size_classes {
+            let (size, bytes) = (format_size(class.size), format_size(class.bytes as usize));
+            writeln!(f, "<= {}: {} allocations, {}", size, class.allocations, bytes)?;
+            for site in &class.sites {
+                let symbol = site.symbol.clone().unwrap_or_else(|| format!("0x{:X}", site.address));
+                writeln!(f, "  {:>5} hits ({} as ip)  {}", site.hits, site.instruction_pointer_hits, symbol)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Samples the target from a background thread until stopped.
+pub struct AllocationTracer {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<AllocationReport>,
+}
+
+impl AllocationTracer {
+    // Allocations already committed when the tracer starts are not counted. The tracer stops
+    // by itself once `memory` is dropped or closed.
+    pub fn start(memory: &Arc<Memory>, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let stop = Arc::clone(&stop);
+            spawn_worker(memory, move |memory| run(memory, interval, &stop))
+        };
+        AllocationTracer { stop, thread }
+    }
+
+    pub fn stop(self) -> AllocationReport {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+#[derive(Default)]
+struct ClassTally {
+    allocations: u64,
+    bytes: u64,
+    // Address -> (hits, instruction pointer hits).
+    sites: HashMap<usize, (u64, u64)>,
+}
+
+fn size_class(bytes: usize) -> usize {
+    bytes.checked_next_power_of_two().unwrap_or(1 << (usize::BITS - 1))
+}
+
+fn run(memory: &Weak<Memory>, interval: Duration, stop: &AtomicBool) -> AllocationReport {
+    let mut classes: HashMap<usize, ClassTally> = HashMap::new();
+    let mut report = AllocationReport::default();
+    let mut previous: Option<Vec<MemoryRegion>> = None;
+
+    while !stop.load(Ordering::SeqCst) {
+        let Some(memory) = upgrade(memory) else {
+            break;
+        };
+        let _priority = memory.apply_thread_priority();
+        if let Some(current) = protection_monitor::committed(&memory) {
+            if let Some(previous) = &previous {
+                let sizes = new_private_sizes(previous, &current);
+                if !sizes.is_empty() {
+                    let sites = sample_sites(&memory, &current);
+                    if sites.is_none() {
+                        report.missed_samples += 1;
+                    }
+                    for size in sizes {
+                        let tally = classes.entry(size_class(size)).or_default();
+                        tally.allocations += 1;
+                        tally.bytes += size as u64;
+                        for &(address, is_ip) in sites.iter().flatten() {
+                            let (hits, ip_hits) = tally.sites.entry(address).or_default();
+                            *hits += 1;
+                            *ip_hits += is_ip as u64;
+                        }
+                        report.allocations += 1;
+                    }
+                }
+            }
+            previous = Some(current);
+        }
+
+        report.ticks += 1;
+        drop(memory);
+        thread::park_timeout(interval);
+    }
+
+    // Symbols come from the module list, which outlives a close.
+    let memory = memory.upgrade();
+
+    let mut sizes: Vec<usize> = classes.keys().copied().collect();
+    sizes.sort_unstable();
+    report.size_classes = sizes
+        .into_iter()
+        .map(|size| {
+            let tally = &classes[&size];
+            let mut sites: Vec<AllocationSite> = tally
+                .sites
+                .iter()
+                .map(|(&address, &(hits, instruction_pointer_hits))| AllocationSite {
+                    address,
+                    symbol: memory.as_ref().and_then(|memory| memory.symbolize(address)),
+                    hits,
+                    instruction_pointer_hits,
+                })
+                .collect();
+            sites.sort_by(|a, b| b.hits.cmp(&a.hits).then(a.address.cmp(&b.address)));
+            sites.truncate(SITES_PER_CLASS);
+            SizeClass { size, allocations: tally.allocations, bytes: tally.bytes, sites }
+        })
+        .collect();
+
+    debug!("allocation tracer stopped after {} ticks, {} allocations", report.ticks, report.allocations);
+    report
+}
+
+// Bytes of each private allocation that appeared or grew between the two maps.
+fn new_private_sizes(previous: &[MemoryRegion], current: &[MemoryRegion]) -> Vec<usize> {
+    let diff = region_snapshot::diff_regions(previous, current);
+    let is_private = |base: usize| {
+        current.iter().any(|region| region.allocation_base.get() == base && region.region_type == RegionType::Private)
+    };
+
+    let mut added: HashMap<usize, usize> = HashMap::new();
+    for region in diff.added.iter().filter(|region| region.region_type == RegionType::Private) {
+        *added.entry(region.allocation_base.get()).or_default() += region.size.get();
+    }
+    let grown = diff
+        .grown
+        .iter()
+        .filter(|growth| is_private(growth.allocation_base))
+        .map(|growth| growth.new_size - growth.old_size);
+    added.into_values().chain(grown).collect()
+}
+
+// (code address, is instruction pointer) for every thread: the instruction pointer and the
+// first STACK_DEPTH stack values pointing into executable images. Each address once per
+// thread. None if the threads couldn't be listed.
+fn sample_sites(memory: &Memory, regions: &[MemoryRegion]) -> Option<Vec<(usize, bool)>> {
+    let contexts = memory.thread_contexts().map_err(|e| debug!("no thread contexts this tick: {}", e)).ok()?;
+    let pointer_size = memory.pointer_size().ok()?;
+    let is_code = |address: usize| {
+        regions
+            .iter()
+            .find(|region| region.contains(address))
+            .is_some_and(|region| region.is_executable && region.region_type == RegionType::Image)
+    };
+
+    let mut sites = Vec::new();
+    let mut stack = vec![0; STACK_SCAN_BYTES];
+    for context in contexts {
+        let mut thread_sites = vec![(context.instruction_pointer, true)];
+        let read = memory.read_bulk(context.stack_pointer, &mut stack).unwrap_or(0);
+        let return_addresses = stack[..read]
+            .chunks_exact(pointer_size)
+            .map(|word| match pointer_size {
+                4 => u32::from_le_bytes(word.try_into().unwrap()) as usize,
+                _ => u64::from_le_bytes(word.try_into().unwrap()) as usize,
+            })
+            .filter(|&value| is_code(value));
+        for address in return_addresses {
+            if thread_sites.len() > STACK_DEPTH {
+                break;
+            }
+            if !thread_sites.iter().any(|&(seen, _)| seen == address) {
+                thread_sites.push((address, false));
+            }
+        }
+        sites.extend(thread_sites);
+    }
+    Some(sites)
+}
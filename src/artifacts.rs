@@ -0,0 +1,73 @@
+// Memory this crate itself put into the target: allocations (including hook trampolines) and
+// patched bytes. Scans skip these by default so our own code and data don't show up as
+// matches; `ScanOptions::exclude_self_artifacts(false)` turns that off. Ranges added with
+// `add_scan_exclusion` are skipped by every scan.
+
+use std::ops::Range;
+
+use crate::memory::Memory;
+
+impl Memory {
+    pub fn self_artifacts(&self) -> Vec<Range<usize>> {
+        let mut ranges = self.allocation_list().lock().unwrap().clone();
+        ranges.extend(self.patched_ranges());
+        ranges
+    }
+
+    #[cfg(feature = "patch")]
+    fn patched_ranges(&self) -> Vec<Range<usize>> {
+        self.patch_list().lock().unwrap().iter().map(|patch| patch.address..patch.address + patch.len()).collect()
+    }
+
+    #[cfg(not(feature = "patch"))]
+    fn patched_ranges(&self) -> Vec<Range<usize>> {
+        Vec::new()
+    }
+
+    pub fn add_scan_exclusion(&self, range: Range<usize>) {
+        self.scan_exclusion_list().lock().unwrap().push(range);
+    }
+
+    pub fn clear_scan_exclusions(&self) {
+        self.scan_exclusion_list().lock().unwrap().clear();
+    }
+
+    pub fn scan_exclusions(&self) -> Vec<Range<usize>> {
+        self.scan_exclusion_list().lock().unwrap().clone()
+    }
+
+    // Everything a scan should skip, sorted and with overlapping ranges merged.
+    pub(crate) fn excluded_ranges(&self, exclude_self_artifacts: bool) -> Vec<Range<usize>> {
+        let mut ranges = self.scan_exclusions();
+        if exclude_self_artifacts {
+            ranges.extend(self.self_artifacts());
+        }
+        ranges.retain(|range| !range.is_empty());
+        ranges.sort_by_key(|range| range.start);
+
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+    }
+}
+
+// The parts of `range` outside the sorted, disjoint `excluded` ranges, in ascending order.
+pub(crate) fn subtract(range: Range<usize>, excluded: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut pieces = Vec::new();
+    let mut start = range.start;
+    for exclusion in excluded.iter().filter(|exclusion| exclusion.start < range.end && range.start < exclusion.end) {
+        if exclusion.start > start {
+            pieces.push(start..exclusion.start);
+        }
+        start = start.max(exclusion.end);
+    }
+    if start < range.end {
+        pieces.push(start..range.end);
+    }
+    pieces
+}
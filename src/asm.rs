@@ -0,0 +1,62 @@
+use std::io;
+
+use keystone_engine::{Arch, Keystone, Mode};
+
+use crate::disasm;
+use crate::memory::Memory;
+use crate::patch::Patch;
+
+const NOP: u8 = 0x90;
+
+impl Memory {
+    // Assembles `asm` (Intel syntax, instructions separated by `;` or newlines) as if it were
+    // placed at `address`, so relative branches and rip-relative operands encode correctly.
+    pub fn assemble(&self, address: usize, asm: &str) -> io::Result<Vec<u8>> {
+        let mode = if self.pointer_size()? == 4 { Mode::MODE_32 } else { Mode::MODE_64 };
+        let engine = Keystone::new(Arch::X86, mode).map_err(|e| assembler_error(format!("{}", e)))?;
+        let result = engine
+            .asm(asm.to_string(), address as u64)
+            .map_err(|e| assembler_error(format!("Failed to assemble '{}': {}", asm, e)))?;
+        Ok(result.bytes)
+    }
+
+    // Assembles `asm` at `address` and applies it as a patch. The code may be at most `max_len`
+    // bytes; the rest of the last original instruction it overlaps is filled with NOPs so no
+    // half-overwritten instruction is left behind.
+    pub fn patch_asm(&self, address: usize, asm: &str, max_len: usize) -> io::Result<Patch> {
+        let mut code = self.assemble(address, asm)?;
+        if code.len() > max_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' encodes to 0x{:X} bytes, more than the 0x{:X} allowed", asm, code.len(), max_len),
+            ));
+        }
+
+        let boundary = self.instruction_boundary(address, code.len())?;
+        code.resize(boundary, NOP);
+        self.apply_patch(address, &code)
+    }
+
+    // Length of the whole instructions at `address` needed to cover at least `len` bytes.
+    fn instruction_boundary(&self, address: usize, len: usize) -> io::Result<usize> {
+        let bitness = self.pointer_size()? as u32 * 8;
+        let bytes = self.read_bytes(address, len + disasm::MAX_INSTRUCTION_LEN)?;
+
+        let mut covered = 0;
+        for insn in disasm::decode(&bytes, address, bitness, len) {
+            if covered >= len {
+                break;
+            }
+            covered += insn.bytes.len();
+        }
+
+        if covered < len {
+            return Err(io::Error::other(format!("Failed to decode the instructions at 0x{:X}", address)));
+        }
+        Ok(covered)
+    }
+}
+
+fn assembler_error(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
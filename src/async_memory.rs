@@ -0,0 +1,151 @@
+// Async front end for tokio applications. Every call runs its blocking reads on tokio's blocking
+// pool, so the caller's runtime keeps serving other tasks during a long scan. Dropping a scan
+// future or stream before it finishes cancels the scan behind it at the next chunk boundary.
+// All methods must be called from within a tokio runtime.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_core::Stream;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::task;
+
+use crate::filter::RegionFilter;
+use crate::hash::IntegrityBaseline;
+use crate::memory::Memory;
+use crate::pattern::Pattern;
+use crate::scan::{CancelToken, ScanOptions};
+
+// Matches a `ScanStream` buffers before the scan waits for the consumer.
+const STREAM_BUFFER: usize = 64;
+
+#[derive(Clone)]
+pub struct AsyncMemory {
+    memory: Arc<Memory>,
+}
+
+impl AsyncMemory {
+    pub fn new(memory: Arc<Memory>) -> Self {
+        AsyncMemory { memory }
+    }
+
+    pub fn memory(&self) -> &Arc<Memory> {
+        &self.memory
+    }
+
+    pub async fn find_pattern(
+        &self,
+        pattern: Pattern,
+        filter: RegionFilter,
+        options: ScanOptions,
+    ) -> io::Result<Option<usize>> {
+        self.scan(options, move |memory, options| memory.find_pattern_with(&pattern, &filter, options)).await
+    }
+
+    pub async fn find_pattern_all(
+        &self,
+        pattern: Pattern,
+        filter: RegionFilter,
+        options: ScanOptions,
+    ) -> io::Result<Vec<usize>> {
+        self.scan(options, move |memory, options| memory.find_pattern_all_with(&pattern, &filter, options)).await
+    }
+
+    pub async fn scan_for_value<T: Copy + Send + 'static>(
+        &self,
+        value: T,
+        filter: RegionFilter,
+        options: ScanOptions,
+    ) -> io::Result<Vec<usize>> {
+        self.scan(options, move |memory, options| memory.scan_for_value_with(value, &filter, options)).await
+    }
+
+    // Dumps `len` bytes at `address`.
+    pub async fn read_bytes(&self, address: usize, len: usize) -> io::Result<Vec<u8>> {
+        self.blocking(move |memory| memory.read_bytes(address, len)).await?
+    }
+
+    // Hashes the filtered regions, see `IntegrityBaseline::capture`.
+    pub async fn capture_baseline(&self, filter: RegionFilter) -> io::Result<IntegrityBaseline> {
+        self.blocking(move |memory| IntegrityBaseline::capture(memory, &filter)).await
+    }
+
+    // Streams matches as the lazy scan finds them. The scan runs at most `STREAM_BUFFER` matches
+    // ahead of the consumer and stops once the stream is dropped.
+    pub fn scan_stream(&self, pattern: Pattern, filter: RegionFilter, options: ScanOptions) -> ScanStream {
+        let (options, cancel) = with_cancel(options);
+        let (sender, matches) = mpsc::channel(STREAM_BUFFER);
+        let memory = Arc::clone(&self.memory);
+
+        task::spawn_blocking(move || {
+            let _priority = memory.apply_thread_priority();
+            for found in memory.pattern_matches_with(&pattern, &filter, &options) {
+                if sender.blocking_send(found).is_err() {
+                    break;
+                }
+            }
+        });
+
+        ScanStream { matches, _cancel: cancel }
+    }
+
+    async fn scan<T: Send + 'static>(
+        &self,
+        options: ScanOptions,
+        f: impl FnOnce(&Memory, &ScanOptions) -> io::Result<T> + Send + 'static,
+    ) -> io::Result<T> {
+        let (options, cancel) = with_cancel(options);
+        let result = self.blocking(move |memory| f(memory, &options)).await;
+        cancel.disarm();
+        result?
+    }
+
+    async fn blocking<T: Send + 'static>(&self, f: impl FnOnce(&Memory) -> T + Send + 'static) -> io::Result<T> {
+        let memory = Arc::clone(&self.memory);
+        task::spawn_blocking(move || {
+            let _priority = memory.apply_thread_priority();
+            f(&memory)
+        })
+        .await.map_err(io::Error::other)
+    }
+}
+
+// Stream returned by `AsyncMemory::scan_stream`. Read errors are yielded like those of
+// `Memory::pattern_matches` and the scan carries on with the next region.
+pub struct ScanStream {
+    matches: Receiver<io::Result<usize>>,
+    _cancel: CancelOnDrop,
+}
+
+impl Stream for ScanStream {
+    type Item = io::Result<usize>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.matches.poll_recv(cx)
+    }
+}
+
+// Cancels the token when dropped, unless disarmed first because the scan already finished.
+struct CancelOnDrop(Option<CancelToken>);
+
+impl CancelOnDrop {
+    fn disarm(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Some(token) = self.0.take() {
+            token.cancel();
+        }
+    }
+}
+
+// Uses the caller's token if there is one, so cancelling it also stops the async scan.
+fn with_cancel(mut options: ScanOptions) -> (ScanOptions, CancelOnDrop) {
+    let token = options.cancel.get_or_insert_with(CancelToken::new).clone();
+    (options, CancelOnDrop(Some(token)))
+}
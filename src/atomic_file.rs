@@ -0,0 +1,124 @@
+// Files written whole or not at all. The contents go to a `.partial` file next to the
+// destination, which is renamed over it once everything is written and flushed. If writing
+// fails, or the code writing panics, the partial file is removed and whatever was at the
+// destination stays as it was, so a snapshot cut short never passes for a finished one.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+pub(crate) fn write_atomically(
+    path: impl AsRef<Path>,
+    write: impl FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let partial = Partial(Some(partial_path(path)));
+    let partial_path = partial.0.as_deref().expect("set until renamed");
+
+    let mut writer = BufWriter::new(File::create(partial_path)?);
+    write(&mut writer)?;
+    writer.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+    fs::rename(partial_path, path)?;
+    partial.keep();
+    Ok(())
+}
+
+// "snapshot.json" becomes "snapshot.json.partial".
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+// Removes the partial file when dropped, unless it was renamed into place.
+struct Partial(Option<PathBuf>);
+
+impl Partial {
+    fn keep(mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for Partial {
+    fn drop(&mut self) {
+        let Some(path) = &self.0 else {
+            return;
+        };
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                warn!("failed to remove the incomplete file {}: {}", path.display(), e)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::*;
+
+    // A fresh directory holding only "out.json", with `existing` in it if given.
+    fn dir_with(test: &str, existing: Option<&str>) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nirvana-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        if let Some(existing) = existing {
+            fs::write(dir.join("out.json"), existing).unwrap();
+        }
+        dir
+    }
+
+    // What's left in `dir` afterwards, by name and contents.
+    fn contents(dir: &Path) -> Vec<(String, String)> {
+        let mut files: Vec<(String, String)> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .map(|path| (path.file_name().unwrap().to_string_lossy().into_owned(), fs::read_to_string(path).unwrap()))
+            .collect();
+        files.sort();
+        fs::remove_dir_all(dir).unwrap();
+        files
+    }
+
+    #[test]
+    fn finished_writes_replace_the_file() {
+        let dir = dir_with("atomic-finished", Some("old"));
+
+        write_atomically(dir.join("out.json"), |writer| writer.write_all(b"new")).unwrap();
+        assert_eq!(contents(&dir), [("out.json".to_string(), "new".to_string())]);
+    }
+
+    #[test]
+    fn failed_writes_leave_nothing_behind() {
+        let dir = dir_with("atomic-failed", None);
+
+        let e = write_atomically(dir.join("out.json"), |writer| {
+            writer.write_all(b"half")?;
+            Err(io::Error::other("cut short"))
+        })
+        .unwrap_err();
+        assert_eq!(e.to_string(), "cut short");
+        assert!(contents(&dir).is_empty());
+    }
+
+    #[test]
+    fn panicking_writers_keep_the_old_file() {
+        let dir = dir_with("atomic-panicked", Some("old"));
+
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            write_atomically(dir.join("out.json"), |writer| {
+                writer.write_all(b"half")?;
+                writer.flush()?;
+                panic!("cancelled mid-write")
+            })
+        }))
+        .unwrap_err();
+        assert_eq!(contents(&dir), [("out.json".to_string(), "old".to_string())]);
+    }
+}
@@ -0,0 +1,505 @@
+// What a `Memory` reaches its target through when that isn't a process handle. A `Memory`
+// normally makes its ReadProcessMemory, WriteProcessMemory, VirtualQueryEx and
+// VirtualProtectEx calls on the handle it opened; one made by `Memory::with_backend` hands
+// the same calls to a `Backend` instead, and everything above them — chunked scans, region
+// walks, stitching, holes, patches, the write guard — runs unchanged:
+//
+//     let memory = Memory::with_backend(Arc::new(backend), Capabilities::READ);
+//     memory.scan_memory()?;
+//     let found = memory.find_pattern_all(&pattern, &RegionFilter::default())?;
+//
+// Replaying a read log is one (read_log.rs); tests use `mock::MockBackend`. The capabilities
+// given with the backend are checked as for a handle, and what a backend doesn't do fails
+// with `Unsupported`. The metadata caches never refresh over a backend.
+
+use std::fmt;
+use std::io;
+
+use crate::memory::{MemoryRegion, MEMORY_BASIC_INFORMATION};
+use crate::module::ModuleInfo;
+use crate::read_log::ReplayBackend;
+
+pub(crate) trait Backend: fmt::Debug + Send + Sync {
+    // As ReadProcessMemory: how many bytes landed at the front of `buffer`.
+    fn read(&self, address: usize, buffer: &mut [u8]) -> io::Result<usize>;
+
+    // As WriteProcessMemory, with a `PartialWrite` for a write that stopped partway.
+    fn write(&self, address: usize, bytes: &[u8]) -> io::Result<()> {
+        let _ = bytes;
+        Err(unsupported("write", address))
+    }
+
+    fn query(&self, address: usize) -> io::Result<MEMORY_BASIC_INFORMATION> {
+        Err(unsupported("query", address))
+    }
+
+    // As VirtualProtectEx: the previous protection of the first page.
+    fn protect(&self, address: usize, size: usize, protection: u32) -> io::Result<u32> {
+        let _ = (size, protection);
+        Err(unsupported("reprotect", address))
+    }
+
+    fn pointer_size(&self) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "The backend doesn't know the target's pointer size"))
+    }
+
+    // Where the target's PEB is, to seed the probing walk of a 64-bit target.
+    fn peb_address(&self) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "The backend doesn't know the target's PEB"))
+    }
+
+    // The committed regions, for a backend that knows them without being queried.
+    fn regions(&self) -> Option<Vec<MemoryRegion>> {
+        None
+    }
+
+    fn modules(&self) -> io::Result<Vec<ModuleInfo>> {
+        Err(io::Error::new(io::ErrorKind::Unsupported, "The backend has no module list"))
+    }
+
+    fn as_replay(&self) -> Option<&ReplayBackend> {
+        None
+    }
+}
+
+fn unsupported(what: &str, address: usize) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("The backend can't {} memory at 0x{:X}", what, address))
+}
+
+#[cfg(test)]
+pub(crate) mod mock {
+    // A target made up by a test: regions of bytes at chosen addresses, pages within them that
+    // fail to read or write, and a log of every call made, so a test can check which reads a
+    // scan made as well as what it found. A hook set with `on_call` runs before each call,
+    // without the mock locked, to change the map between two calls or hold one up.
+    //
+    // Reads behave as ReadProcessMemory does across differently protected regions: one that
+    // runs past the end of the region it starts in comes back short at the boundary, and one
+    // touching a failing page fails whole. The first read of a guard region fails with a
+    // `GuardPageError` and clears the guard, as the real one-shot exception does. Writes stop
+    // likewise, at the end of the region they start in or at the first page that can't be
+    // written, and fail with a `PartialWrite` if that was partway.
+
+    use std::fmt;
+    use std::io;
+    use std::mem;
+    use std::ops::Range;
+    use std::sync::{Arc, Mutex};
+
+    use windows_sys::Win32::System::Memory::MEM_FREE;
+
+    use super::Backend;
+    use crate::capabilities::Capabilities;
+    use crate::memory::{GuardPageError, Memory, PartialWrite, MEMORY_BASIC_INFORMATION, MEM_COMMIT};
+    use crate::module::ModuleInfo;
+    use crate::sys::{
+        MEM_PRIVATE, PAGE_EXECUTE_READ, PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_NOACCESS,
+        PAGE_READONLY, PAGE_READWRITE, PAGE_WRITECOPY,
+    };
+
+    pub(crate) const PAGE: usize = 0x1000;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub(crate) enum Call {
+        Read(Range<usize>),
+        Write(Range<usize>),
+        Query(usize),
+        Protect(Range<usize>, u32),
+    }
+
+    type Hook = Arc<dyn Fn(&Call) + Send + Sync>;
+
+    #[derive(Debug, Clone)]
+    struct MockRegion {
+        base: usize,
+        protection: u32,
+        bytes: Vec<u8>,
+    }
+
+    impl MockRegion {
+        fn range(&self) -> Range<usize> {
+            self.base..self.base + self.bytes.len()
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct State {
+        // Sorted by base, without overlaps.
+        regions: Vec<MockRegion>,
+        failing_reads: Vec<Range<usize>>,
+        failing_writes: Vec<Range<usize>>,
+        calls: Vec<Call>,
+        // The target's, when it isn't this process's.
+        pointer_size: Option<usize>,
+        peb: Option<usize>,
+    }
+
+    impl State {
+        fn region_at(&mut self, address: usize) -> Option<&mut MockRegion> {
+            self.regions.iter_mut().find(|region| region.range().contains(&address))
+        }
+    }
+
+    #[derive(Default)]
+    pub(crate) struct MockBackend {
+        state: Mutex<State>,
+        hook: Mutex<Option<Hook>>,
+    }
+
+    impl fmt::Debug for MockBackend {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("MockBackend").field("state", &self.state).finish_non_exhaustive()
+        }
+    }
+
+    impl MockBackend {
+        pub(crate) fn new() -> Self {
+            MockBackend::default()
+        }
+
+        pub(crate) fn region(self, base: usize, bytes: Vec<u8>, protection: u32) -> Self {
+            self.map(base, bytes, protection);
+            self
+        }
+
+        // Readable and writable, the usual heap region.
+        pub(crate) fn data(self, base: usize, bytes: Vec<u8>) -> Self {
+            self.region(base, bytes, PAGE_READWRITE)
+        }
+
+        // The target's pointer size; at 4 the probing walk covers the whole address space.
+        pub(crate) fn pointer_size(self, size: usize) -> Self {
+            self.state.lock().unwrap().pointer_size = Some(size);
+            self
+        }
+
+        pub(crate) fn peb(self, address: usize) -> Self {
+            self.state.lock().unwrap().peb = Some(address);
+            self
+        }
+
+        // A `Memory` over the mock with every capability.
+        pub(crate) fn open(self) -> (Arc<MockBackend>, Memory) {
+            self.open_with(Capabilities::ALL)
+        }
+
+        pub(crate) fn open_with(self, capabilities: Capabilities) -> (Arc<MockBackend>, Memory) {
+            let mock = Arc::new(self);
+            let memory = Memory::with_backend(mock.clone(), capabilities);
+            (mock, memory)
+        }
+
+        // Adds a region, replacing any it overlaps.
+        pub(crate) fn map(&self, base: usize, bytes: Vec<u8>, protection: u32) {
+            let mut state = self.state.lock().unwrap();
+            let range = base..base + bytes.len();
+            state.regions.retain(|region| region.range().end <= range.start || region.base >= range.end);
+            state.regions.push(MockRegion { base, protection, bytes });
+            state.regions.sort_by_key(|region| region.base);
+        }
+
+        pub(crate) fn unmap(&self, base: usize) {
+            self.state.lock().unwrap().regions.retain(|region| region.base != base);
+        }
+
+        pub(crate) fn fail_reads(&self, range: Range<usize>) {
+            self.state.lock().unwrap().failing_reads.push(range);
+        }
+
+        pub(crate) fn fail_writes(&self, range: Range<usize>) {
+            self.state.lock().unwrap().failing_writes.push(range);
+        }
+
+        pub(crate) fn on_call(&self, hook: impl Fn(&Call) + Send + Sync + 'static) {
+            *self.hook.lock().unwrap() = Some(Arc::new(hook));
+        }
+
+        pub(crate) fn calls(&self) -> Vec<Call> {
+            self.state.lock().unwrap().calls.clone()
+        }
+
+        pub(crate) fn reads(&self) -> Vec<Range<usize>> {
+            let calls = self.calls();
+            calls.into_iter().filter_map(|call| if let Call::Read(range) = call { Some(range) } else { None }).collect()
+        }
+
+        pub(crate) fn writes(&self) -> Vec<Range<usize>> {
+            let calls = self.calls();
+            calls
+                .into_iter()
+                .filter_map(|call| if let Call::Write(range) = call { Some(range) } else { None })
+                .collect()
+        }
+
+        pub(crate) fn clear_calls(&self) {
+            self.state.lock().unwrap().calls.clear();
+        }
+
+        // The mapped bytes at `address`, as the target holds them now.
+        pub(crate) fn bytes(&self, address: usize, len: usize) -> Vec<u8> {
+            let mut state = self.state.lock().unwrap();
+            let region = state.region_at(address).expect("address is mapped");
+            let offset = address - region.base;
+            region.bytes[offset..offset + len].to_vec()
+        }
+
+        fn call(&self, call: Call) {
+            let hook = self.hook.lock().unwrap().clone();
+            if let Some(hook) = hook {
+                hook(&call);
+            }
+            self.state.lock().unwrap().calls.push(call);
+        }
+    }
+
+    fn readable(protection: u32) -> bool {
+        protection & (PAGE_NOACCESS | PAGE_GUARD) == 0
+            && protection
+                & (PAGE_READONLY
+                    | PAGE_READWRITE
+                    | PAGE_WRITECOPY
+                    | PAGE_EXECUTE_READ
+                    | PAGE_EXECUTE_READWRITE
+                    | PAGE_EXECUTE_WRITECOPY)
+                != 0
+    }
+
+    fn writable(protection: u32) -> bool {
+        protection & (PAGE_NOACCESS | PAGE_GUARD) == 0
+            && protection & (PAGE_READWRITE | PAGE_WRITECOPY | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY) != 0
+    }
+
+    fn overlaps(ranges: &[Range<usize>], range: &Range<usize>) -> bool {
+        ranges.iter().any(|failing| failing.start < range.end && range.start < failing.end)
+    }
+
+    impl Backend for MockBackend {
+        fn read(&self, address: usize, buffer: &mut [u8]) -> io::Result<usize> {
+            self.call(Call::Read(address..address.saturating_add(buffer.len())));
+            let len = buffer.len();
+            let unreadable =
+                || io::Error::other(format!("Failed to read 0x{:X} bytes at 0x{:X}: not readable", len, address));
+            let mut state = self.state.lock().unwrap();
+            let failing_reads = state.failing_reads.clone();
+            let Some(region) = state.region_at(address) else {
+                return Err(unreadable());
+            };
+            if region.protection & PAGE_GUARD != 0 {
+                region.protection &= !PAGE_GUARD;
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, GuardPageError { address }));
+            }
+            let end = address.saturating_add(len).min(region.range().end);
+            if !readable(region.protection) || overlaps(&failing_reads, &(address..end)) {
+                return Err(unreadable());
+            }
+            let offset = address - region.base;
+            buffer[..end - address].copy_from_slice(&region.bytes[offset..offset + end - address]);
+            Ok(end - address)
+        }
+
+        fn write(&self, address: usize, bytes: &[u8]) -> io::Result<()> {
+            self.call(Call::Write(address..address + bytes.len()));
+            let mut state = self.state.lock().unwrap();
+            let failing_writes = state.failing_writes.clone();
+            let mut written = 0;
+            if let Some(region) = state.region_at(address).filter(|region| writable(region.protection)) {
+                let end = (address + bytes.len()).min(region.range().end);
+                let end = failing_writes
+                    .iter()
+                    .filter(|failing| failing.start < end && failing.end > address)
+                    .fold(end, |end, failing| end.min(failing.start.max(address)));
+                let offset = address - region.base;
+                region.bytes[offset..offset + end - address].copy_from_slice(&bytes[..end - address]);
+                written = end - address;
+            }
+            match written {
+                _ if written == bytes.len() => Ok(()),
+                0 => Err(io::Error::other(format!(
+                    "Failed to write 0x{:X} bytes at 0x{:X}: not writable",
+                    bytes.len(),
+                    address
+                ))),
+                _ => Err(io::Error::other(PartialWrite { address, requested: bytes.len(), written })),
+            }
+        }
+
+        fn query(&self, address: usize) -> io::Result<MEMORY_BASIC_INFORMATION> {
+            self.call(Call::Query(address));
+            let state = self.state.lock().unwrap();
+            let mut mbi: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+            match state.regions.iter().find(|region| region.range().end > address) {
+                Some(region) if region.base <= address => {
+                    mbi.BaseAddress = region.base as *mut _;
+                    mbi.AllocationBase = region.base as *mut _;
+                    mbi.AllocationProtect = region.protection;
+                    mbi.RegionSize = region.bytes.len();
+                    mbi.State = MEM_COMMIT;
+                    mbi.Protect = region.protection;
+                    mbi.Type = MEM_PRIVATE;
+                }
+                // Free up to the next region.
+                Some(region) => {
+                    let base = address & !(PAGE - 1);
+                    mbi.BaseAddress = base as *mut _;
+                    mbi.RegionSize = region.base - base;
+                    mbi.State = MEM_FREE;
+                    mbi.Protect = PAGE_NOACCESS;
+                }
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("VirtualQueryEx failed at 0x{:X}: past the last region", address),
+                    ))
+                }
+            }
+            Ok(mbi)
+        }
+
+        fn protect(&self, address: usize, size: usize, protection: u32) -> io::Result<u32> {
+            self.call(Call::Protect(address..address + size, protection));
+            let mut state = self.state.lock().unwrap();
+            let end = address + size;
+            let mut old = None;
+            for region in state.regions.iter_mut().filter(|region| region.base < end && region.range().end > address) {
+                old.get_or_insert(region.protection);
+                region.protection = protection;
+            }
+            old.ok_or_else(|| io::Error::other(format!("Failed to change protection at 0x{:X}", address)))
+        }
+
+        fn pointer_size(&self) -> io::Result<usize> {
+            Ok(self.state.lock().unwrap().pointer_size.unwrap_or(mem::size_of::<usize>()))
+        }
+
+        fn peb_address(&self) -> io::Result<usize> {
+            let peb = self.state.lock().unwrap().peb;
+            peb.ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "The mock has no PEB"))
+        }
+
+        fn modules(&self) -> io::Result<Vec<ModuleInfo>> {
+            Ok(Vec::new())
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::mock::{Call, MockBackend, PAGE};
+    use crate::capabilities::missing_capability;
+    use crate::memory::{is_guard_page_error, partial_write, PartialWrite};
+    use crate::sys::{PAGE_GUARD, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE};
+
+    #[test]
+    fn reads_and_writes_go_to_the_backend() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        memory.write_memory(0x10010, 0x1122_3344u32).unwrap();
+
+        assert_eq!(memory.read_memory::<u32>(0x10010).unwrap(), 0x1122_3344);
+        assert_eq!(mock.bytes(0x10010, 4), [0x44, 0x33, 0x22, 0x11]);
+        assert_eq!(mock.writes(), [0x10010..0x10014]);
+        assert_eq!(memory.pointer_size().unwrap(), std::mem::size_of::<usize>());
+    }
+
+    #[test]
+    fn regions_are_walked_through_queries() {
+        let (mock, mut memory) =
+            MockBackend::new().data(0x10000, vec![0; PAGE]).region(0x30000, vec![0; 2 * PAGE], PAGE_READONLY).open();
+        memory.scan_memory().unwrap();
+
+        let regions: Vec<_> = memory.regions().map(|region| (region.range(), region.is_writable)).collect();
+        assert_eq!(regions, [(0x10000..0x11000, true), (0x30000..0x32000, false)]);
+        assert!(mock.calls().contains(&Call::Query(0x11000)));
+    }
+
+    #[test]
+    fn capabilities_are_checked_before_the_backend() {
+        let (mock, memory) =
+            MockBackend::new().data(0x10000, vec![0; PAGE]).open_with(crate::capabilities::Capabilities::READ);
+
+        let e = memory.write_memory(0x10000, 1u8).unwrap_err();
+        assert!(missing_capability(&e).is_some(), "{}", e);
+        assert!(mock.writes().is_empty());
+        assert_eq!(memory.read_memory::<u8>(0x10000).unwrap(), 0);
+    }
+
+    #[test]
+    fn short_reads_fail_typed_reads() {
+        let (_mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+
+        let e = memory.read_memory::<u64>(0x10FFC).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(memory.read_bytes(0x10FFC, 8).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn failing_pages_fail_whole_reads() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; 4 * PAGE]).open();
+        mock.fail_reads(0x12000..0x13000);
+
+        assert!(memory.read_bytes(0x11000, 2 * PAGE).is_err());
+        assert!(memory.read_bytes(0x11000, PAGE).is_ok());
+        assert_eq!(memory.unreadable_ranges(0x10000..0x14000), [0x12000..0x13000]);
+    }
+
+    #[test]
+    fn guard_pages_fire_once() {
+        let (_mock, memory) = MockBackend::new().region(0x10000, vec![0; PAGE], PAGE_READWRITE | PAGE_GUARD).open();
+
+        assert!(is_guard_page_error(&memory.read_memory::<u8>(0x10000).unwrap_err()));
+        assert_eq!(memory.read_memory::<u8>(0x10000).unwrap(), 0);
+    }
+
+    #[test]
+    fn writes_stop_at_failing_pages() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; 2 * PAGE]).open();
+        mock.fail_writes(0x11000..0x12000);
+
+        let e = memory.write_bytes(0x10FF0, &[0xAA; 0x20]).unwrap_err();
+        let partial = partial_write(&e).expect("a partial write");
+        assert_eq!(partial, PartialWrite { address: 0x10FF0, requested: 0x20, written: 0x10 });
+        assert_eq!(mock.bytes(0x10FF0, 0x20), [[0xAA; 0x10], [0; 0x10]].concat());
+    }
+
+    #[test]
+    fn noaccess_regions_and_unmapped_addresses_fail() {
+        let (mock, memory) = MockBackend::new().region(0x10000, vec![0; PAGE], PAGE_NOACCESS).open();
+
+        assert!(memory.read_memory::<u8>(0x10000).is_err());
+        assert!(memory.read_memory::<u8>(0x20000).is_err());
+        mock.map(0x20000, vec![7; PAGE], PAGE_READWRITE);
+        assert_eq!(memory.read_memory::<u8>(0x20000).unwrap(), 7);
+        mock.unmap(0x20000);
+        assert!(memory.read_memory::<u8>(0x20000).is_err());
+    }
+
+    #[test]
+    fn hooks_run_before_each_call() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        let reads = Arc::new(AtomicUsize::new(0));
+        let counted = reads.clone();
+        mock.on_call(move |call| {
+            if let Call::Read(_) = call {
+                counted.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+
+        memory.read_memory::<u32>(0x10000).unwrap();
+        memory.read_memory::<u32>(0x10004).unwrap();
+        assert_eq!(reads.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn protect_changes_the_map() {
+        let (mock, memory) = MockBackend::new().region(0x10000, vec![0; PAGE], PAGE_READONLY).open();
+
+        assert!(memory.write_memory(0x10000, 1u8).is_err());
+        memory.write_protected(0x10000, &[1]).unwrap();
+        assert_eq!(mock.bytes(0x10000, 1), [1]);
+        assert_eq!(memory.query_address(0x10000).unwrap().protection, PAGE_READONLY);
+    }
+}
@@ -0,0 +1,223 @@
+// Bit-level access for flag bytes and packed bitfields. Bits are numbered from the least
+// significant bit of the byte at `address` upwards, continuing into the following bytes as in a
+// little-endian integer, so `bit_offset` may point past the first byte.
+//
+// Writes are a read-modify-write of the bytes containing the field: one read and one write, so
+// the window is small, but it is not atomic. A concurrent write by the target to a neighbouring
+// bit in the same bytes between the two can be lost.
+
+use std::io;
+
+use crate::memory::Memory;
+
+// Widest field `read_bits` can return.
+const MAX_BITS: u32 = u64::BITS;
+
+impl Memory {
+    pub fn read_bits(&self, address: usize, bit_offset: u32, bit_len: u32) -> io::Result<u64> {
+        let field = BitField::new(address, bit_offset, bit_len)?;
+        let bytes = self.read_field(&field)?;
+        Ok(field.extract(bytes))
+    }
+
+    // Fails if `value` doesn't fit in `bit_len` bits; the bits around the field are preserved.
+    pub fn write_bits(&self, address: usize, bit_offset: u32, bit_len: u32, value: u64) -> io::Result<()> {
+        let field = BitField::new(address, bit_offset, bit_len)?;
+        if value & !field.mask() != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Value 0x{:X} does not fit in {} bits", value, bit_len),
+            ));
+        }
+        self.modify_bits(&field, |_| value)
+    }
+
+    pub fn set_flag(&self, address: usize, bit_index: u32) -> io::Result<()> {
+        self.modify_bits(&BitField::new(address, bit_index, 1)?, |_| 1)
+    }
+
+    pub fn clear_flag(&self, address: usize, bit_index: u32) -> io::Result<()> {
+        self.modify_bits(&BitField::new(address, bit_index, 1)?, |_| 0)
+    }
+
+    pub fn toggle_flag(&self, address: usize, bit_index: u32) -> io::Result<()> {
+        self.modify_bits(&BitField::new(address, bit_index, 1)?, |bit| bit ^ 1)
+    }
+
+    fn modify_bits(&self, field: &BitField, update: impl FnOnce(u64) -> u64) -> io::Result<()> {
+        let bytes = self.read_field(field)?;
+        let value = update(field.extract(bytes)) & field.mask();
+        let bytes = field.insert(bytes, value);
+        self.write_bytes(field.address, &bytes.to_le_bytes()[..field.len])
+    }
+
+    fn read_field(&self, field: &BitField) -> io::Result<u128> {
+        let mut bytes = [0u8; 16];
+        let read = self.read_into(field.address, &mut bytes[..field.len])?;
+        if read < field.len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Only 0x{:X} of 0x{:X} bytes at 0x{:X} are readable", read, field.len, field.address),
+            ));
+        }
+        Ok(u128::from_le_bytes(bytes))
+    }
+}
+
+// A bit range resolved to the bytes that contain it.
+struct BitField {
+    // First byte the field touches.
+    address: usize,
+    // Number of bytes the field touches, at most 9.
+    len: usize,
+    // Position of the field's lowest bit within the first byte.
+    shift: u32,
+    bits: u32,
+}
+
+impl BitField {
+    fn new(address: usize, bit_offset: u32, bit_len: u32) -> io::Result<Self> {
+        if bit_len == 0 || bit_len > MAX_BITS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Bit length {} is outside 1..={}", bit_len, MAX_BITS),
+            ));
+        }
+        let address = address
+            .checked_add((bit_offset / 8) as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Bit offset overflows the address space"))?;
+        let shift = bit_offset % 8;
+        Ok(BitField { address, len: (shift + bit_len).div_ceil(8) as usize, shift, bits: bit_len })
+    }
+
+    fn mask(&self) -> u64 {
+        u64::MAX >> (MAX_BITS - self.bits)
+    }
+
+    fn extract(&self, bytes: u128) -> u64 {
+        (bytes >> self.shift) as u64 & self.mask()
+    }
+
+    fn insert(&self, bytes: u128, value: u64) -> u128 {
+        let mask = (self.mask() as u128) << self.shift;
+        (bytes & !mask) | ((value as u128) << self.shift)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    const BASE: usize = 0x10000;
+    // Bytes the exhaustive tests work over; wide enough for a 64-bit field at any offset.
+    const SPAN: usize = 24;
+
+    // A page whose first bytes hold no pattern a shifted field could hide behind.
+    fn flags() -> (Arc<MockBackend>, Memory, Vec<u8>) {
+        let mut bytes = vec![0u8; 0x1000];
+        for (index, byte) in bytes[..SPAN].iter_mut().enumerate() {
+            *byte = (index as u8).wrapping_mul(0x9D) ^ 0x5A;
+        }
+        let model = bytes[..SPAN].to_vec();
+        let (mock, memory) = MockBackend::new().data(BASE, bytes).open();
+        (mock, memory, model)
+    }
+
+    // One bit at a time, as the field is defined, to check the shifting and masking against.
+    fn model_read(bytes: &[u8], offset: u32, len: u32) -> u64 {
+        (0..len).fold(0, |value, i| {
+            let bit = offset + i;
+            value | (u64::from(bytes[bit as usize / 8] >> (bit % 8) & 1) << i)
+        })
+    }
+
+    fn model_write(bytes: &mut [u8], offset: u32, len: u32, value: u64) {
+        for i in 0..len {
+            let bit = offset + i;
+            let byte = &mut bytes[bit as usize / 8];
+            *byte = (*byte & !(1 << (bit % 8))) | (((value >> i) & 1) as u8) << (bit % 8);
+        }
+    }
+
+    #[test]
+    fn every_field_reads_as_its_bits() {
+        let (_, memory, model) = flags();
+        for offset in 0..=8 * (SPAN as u32 - 8) {
+            for len in 1..=MAX_BITS {
+                let expected = model_read(&model, offset, len);
+                assert_eq!(memory.read_bits(BASE, offset, len).unwrap(), expected, "{} bits at bit {}", len, offset);
+            }
+        }
+        // Whole bytes past the first fold into the address.
+        assert_eq!(memory.read_bits(BASE + 2, 3, 9).unwrap(), model_read(&model, 19, 9));
+    }
+
+    #[test]
+    fn every_field_writes_only_its_bits() {
+        let (mock, memory, mut model) = flags();
+        for offset in 0..=8 * (SPAN as u32 - 8) {
+            for len in 1..=MAX_BITS {
+                let mask = u64::MAX >> (MAX_BITS - len);
+                for value in [0, mask, 0xA5A5_A5A5_A5A5_A5A5 & mask] {
+                    mock.clear_calls();
+                    memory.write_bits(BASE, offset, len, value).unwrap();
+                    model_write(&mut model, offset, len, value);
+                    assert_eq!(mock.bytes(BASE, SPAN), model, "0x{:X} as {} bits at bit {}", value, len, offset);
+                    // One read and one write of just the bytes the field touches.
+                    let touched = BASE + offset as usize / 8..BASE + (offset + len).div_ceil(8) as usize;
+                    assert_eq!(mock.reads(), slice::from_ref(&touched));
+                    assert_eq!(mock.writes(), [touched]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flags_change_one_bit() {
+        let (mock, memory, mut model) = flags();
+        for bit in 0..8 * SPAN as u32 {
+            let was = model_read(&model, bit, 1);
+            memory.toggle_flag(BASE, bit).unwrap();
+            model_write(&mut model, bit, 1, was ^ 1);
+            assert_eq!(mock.bytes(BASE, SPAN), model, "toggling bit {}", bit);
+
+            memory.set_flag(BASE, bit).unwrap();
+            model_write(&mut model, bit, 1, 1);
+            assert_eq!(mock.bytes(BASE, SPAN), model, "setting bit {}", bit);
+
+            memory.clear_flag(BASE, bit).unwrap();
+            model_write(&mut model, bit, 1, 0);
+            assert_eq!(mock.bytes(BASE, SPAN), model, "clearing bit {}", bit);
+        }
+    }
+
+    #[test]
+    fn fields_outside_1_to_64_bits_are_refused() {
+        let (mock, memory, model) = flags();
+        for len in [0, 65, u32::MAX] {
+            assert_eq!(memory.read_bits(BASE, 0, len).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(memory.write_bits(BASE, 0, len, 0).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+        let e = memory.write_bits(BASE, 3, 4, 0x10).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "Value 0x10 does not fit in 4 bits");
+        let e = memory.read_bits(usize::MAX, 8, 1).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        assert!(mock.writes().is_empty());
+        assert_eq!(mock.bytes(BASE, SPAN), model);
+    }
+
+    #[test]
+    fn fields_running_off_the_mapping_are_not_written() {
+        let (mock, memory, _) = flags();
+        // The field's second byte is past the end of the page.
+        let e = memory.read_bits(BASE + 0xFFF, 4, 8).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof, "{}", e);
+        assert!(memory.set_flag(BASE + 0xFFF, 8).is_err());
+        assert!(mock.writes().is_empty());
+    }
+}
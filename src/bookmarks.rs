@@ -0,0 +1,157 @@
+// Named addresses with notes, kept in one JSON file per target so they survive restarts of it.
+// Addresses are stored rebased (see `rebase`), so a bookmark inside a module still points at
+// the same code or data after ASLR has moved the module.
+//
+//     let mut bookmarks = Bookmarks::open(".", &memory)?;
+//     bookmarks.add(&memory, "health", "game.exe+0x1A2F30", Some("f32"), "player health")?;
+//     bookmarks.save_in(".")?;
+//
+// Expressions are an address ("0x7FF6A1B2C3D0") or a module with an optional offset
+// ("game.exe", "game.exe+0x1A2F30"). The type hint is a `StructTemplate` field type.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::Memory;
+use crate::parse;
+use crate::rebase::{RebasedAddress, Rebaser};
+use crate::session_file;
+use crate::struct_template::{self, DecodedValue, FieldType};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bookmark {
+    pub label: String,
+    // As the user wrote it.
+    pub expression: String,
+    pub address: RebasedAddress,
+    pub type_hint: Option<String>,
+    pub note: String,
+    // Seconds since the Unix epoch.
+    pub created: u64,
+}
+
+impl Bookmark {
+    // Where the bookmark points in the live process.
+    pub fn resolve(&self, rebaser: &Rebaser) -> io::Result<usize> {
+        rebaser.resolve(&self.address).map_err(|e| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Bookmark '{}' no longer resolves: {}", self.label, e))
+        })
+    }
+
+    // The value at `address` decoded as the type hint; None without one.
+    pub fn read_value(&self, memory: &Memory, address: usize) -> io::Result<Option<DecodedValue>> {
+        match &self.type_hint {
+            Some(type_hint) => Ok(Some(struct_template::read_field(memory, address, &type_hint.parse()?)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+// The bookmarks of one target, keyed by label. The target is identified by its main module's
+// name and size, as for saved sessions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bookmarks {
+    pub module: String,
+    pub module_size: usize,
+    pub bookmarks: BTreeMap<String, Bookmark>,
+}
+
+impl Bookmarks {
+    pub fn for_target(memory: &Memory) -> io::Result<Self> {
+        let (module, module_size) = session_file::identity(memory)?;
+        Ok(Bookmarks { module, module_size, bookmarks: BTreeMap::new() })
+    }
+
+    // "<dir>/game.exe-1F4000.bookmarks.json"
+    pub fn path_in(&self, dir: impl AsRef<Path>) -> PathBuf {
+        dir.as_ref().join(format!("{}-{:X}.bookmarks.json", self.module, self.module_size))
+    }
+
+    // The target's bookmarks file in `dir`, or an empty set if there isn't one yet.
+    pub fn open(dir: impl AsRef<Path>, memory: &Memory) -> io::Result<Self> {
+        let empty = Bookmarks::for_target(memory)?;
+        match Bookmarks::load(empty.path_in(&dir)) {
+            Ok(bookmarks) => Ok(bookmarks),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(empty),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    // Saves to the file `open` reads for this target.
+    pub fn save_in(&self, dir: impl AsRef<Path>) -> io::Result<()> {
+        self.save(self.path_in(dir))
+    }
+
+    // Evaluates `expression` against the target now and stores the result rebased. Fails if
+    // the label is taken, the expression doesn't evaluate or the type hint isn't a field type.
+    pub fn add(
+        &mut self,
+        memory: &Memory,
+        label: &str,
+        expression: &str,
+        type_hint: Option<&str>,
+        note: &str,
+    ) -> io::Result<&Bookmark> {
+        if label.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Bookmark labels must not be empty"));
+        }
+        if self.bookmarks.contains_key(label) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("A bookmark named '{}' already exists", label),
+            ));
+        }
+        if let Some(type_hint) = type_hint {
+            type_hint.parse::<FieldType>()?;
+        }
+
+        let address = parse::parse_address_expr(expression)?.resolve(memory)?;
+        let created = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+        let bookmark = Bookmark {
+            label: label.to_string(),
+            expression: expression.to_string(),
+            address: Rebaser::from_memory(memory)?.rebase(address),
+            type_hint: type_hint.map(str::to_string),
+            note: note.to_string(),
+            created,
+        };
+        Ok(self.bookmarks.entry(label.to_string()).or_insert(bookmark))
+    }
+
+    pub fn remove(&mut self, label: &str) -> Option<Bookmark> {
+        self.bookmarks.remove(label)
+    }
+
+    pub fn get(&self, label: &str) -> Option<&Bookmark> {
+        self.bookmarks.get(label)
+    }
+
+    // In label order.
+    pub fn iter(&self) -> impl Iterator<Item = &Bookmark> {
+        self.bookmarks.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bookmarks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bookmarks.is_empty()
+    }
+}
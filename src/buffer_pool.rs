@@ -0,0 +1,206 @@
+// Chunk buffers for bulk reads, reused instead of allocated per scan. Scans, readers, hashing
+// and snapshots each want megabyte-sized buffers for a moment and then drop them; over a long
+// session that churn fragments the heap. Each `Memory` keeps a pool of them instead:
+//
+//     let stats = memory.buffer_pool_stats();
+//     println!("{} hits, {} misses, {} held", stats.hits, stats.misses, format_size(stats.pooled_bytes));
+//     memory.set_buffer_pool_config(BufferPoolConfig { idle_timeout: Duration::from_secs(5), ..Default::default() });
+//
+// Buffers are kept per power-of-two size class, at most `max_bytes` of them in total; one
+// larger than a quarter of that is never kept, so a one-off huge scan leaves nothing behind.
+// Buffers left unused for `idle_timeout` are freed the next time the pool is used, or by
+// `trim_buffer_pool`. With `enabled: false` every rent allocates and every return frees, as
+// before there was a pool, which is the thing to try when a scan misbehaves.
+
+use std::collections::BTreeMap;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::memory::Memory;
+
+// Smallest size class; smaller requests are rounded up to it.
+const MIN_CLASS: usize = 0x1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferPoolConfig {
+    pub enabled: bool,
+    // Bytes the pool may hold while nobody is using them.
+    pub max_bytes: usize,
+    // Buffers unused for this long are freed.
+    pub idle_timeout: Duration,
+}
+
+impl Default for BufferPoolConfig {
+    fn default() -> Self {
+        BufferPoolConfig { enabled: true, max_bytes: 64 << 20, idle_timeout: Duration::from_secs(30) }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    // Rents served from the pool.
+    pub hits: u64,
+    // Rents that allocated.
+    pub misses: u64,
+    // Buffers freed instead of kept: too big, over `max_bytes`, or idle too long.
+    pub evicted: u64,
+    // Bytes held for reuse right now.
+    pub pooled_bytes: usize,
+    // The most bytes held for reuse at once.
+    pub high_water_bytes: usize,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct BufferPool {
+    state: Mutex<PoolState>,
+}
+
+#[derive(Debug, Default)]
+struct PoolState {
+    config: BufferPoolConfig,
+    // Size class -> free buffers with when they came back, most recent last.
+    free: BTreeMap<usize, Vec<(Vec<u8>, Instant)>>,
+    stats: BufferPoolStats,
+}
+
+impl BufferPool {
+    // An empty buffer with room for at least `capacity` bytes, returned to the pool when
+    // dropped.
+    pub(crate) fn rent(&self, capacity: usize) -> PooledBuffer<'_> {
+        PooledBuffer { pool: self, buffer: self.take(capacity) }
+    }
+
+    // `len` zero bytes, like `vec![0; len]`.
+    pub(crate) fn rent_zeroed(&self, len: usize) -> PooledBuffer<'_> {
+        let mut buffer = self.rent(len);
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    fn take(&self, capacity: usize) -> Vec<u8> {
+        let mut state = self.state.lock().unwrap();
+        state.evict_idle(Instant::now());
+        if !state.config.enabled {
+            state.stats.misses += 1;
+            return Vec::with_capacity(capacity);
+        }
+
+        let class = capacity.max(MIN_CLASS).checked_next_power_of_two().unwrap_or(capacity);
+        match state.free.get_mut(&class).and_then(Vec::pop) {
+            Some((buffer, _)) => {
+                state.stats.hits += 1;
+                state.stats.pooled_bytes -= buffer.capacity();
+                buffer
+            }
+            None => {
+                state.stats.misses += 1;
+                Vec::with_capacity(class)
+            }
+        }
+    }
+
+    fn give(&self, mut buffer: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        state.evict_idle(now);
+        let capacity = buffer.capacity();
+        let config = state.config;
+        if !config.enabled || capacity < MIN_CLASS {
+            return;
+        }
+        if capacity > config.max_bytes / 4 || state.stats.pooled_bytes + capacity > config.max_bytes {
+            state.stats.evicted += 1;
+            return;
+        }
+
+        // A buffer that grew past its class goes into the largest class it still covers.
+        let class = 1 << (usize::BITS - 1 - capacity.leading_zeros());
+        buffer.clear();
+        state.free.entry(class).or_default().push((buffer, now));
+        state.stats.pooled_bytes += capacity;
+        state.stats.high_water_bytes = state.stats.high_water_bytes.max(state.stats.pooled_bytes);
+    }
+
+    pub(crate) fn stats(&self) -> BufferPoolStats {
+        self.state.lock().unwrap().stats
+    }
+
+    pub(crate) fn set_config(&self, config: BufferPoolConfig) {
+        let mut state = self.state.lock().unwrap();
+        state.config = config;
+        if !config.enabled {
+            state.evict_all();
+        }
+        state.evict_idle(Instant::now());
+    }
+
+    pub(crate) fn trim(&self) {
+        self.state.lock().unwrap().evict_all();
+    }
+}
+
+impl PoolState {
+    fn evict_idle(&mut self, now: Instant) {
+        let timeout = self.config.idle_timeout;
+        let mut freed = (0, 0);
+        for buffers in self.free.values_mut() {
+            // Most recently returned last, so the idle ones are a prefix.
+            let idle = buffers.partition_point(|(_, returned)| now.duration_since(*returned) >= timeout);
+            for (buffer, _) in buffers.drain(..idle) {
+                freed = (freed.0 + 1, freed.1 + buffer.capacity());
+            }
+        }
+        self.free.retain(|_, buffers| !buffers.is_empty());
+        self.stats.evicted += freed.0;
+        self.stats.pooled_bytes -= freed.1;
+    }
+
+    fn evict_all(&mut self) {
+        let buffers = mem::take(&mut self.free).into_values().flatten();
+        self.stats.evicted += buffers.count() as u64;
+        self.stats.pooled_bytes = 0;
+    }
+}
+
+// A rented buffer; dereferences to the `Vec` and goes back to the pool when dropped.
+pub(crate) struct PooledBuffer<'a> {
+    pool: &'a BufferPool,
+    buffer: Vec<u8>,
+}
+
+impl Deref for PooledBuffer<'_> {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.buffer
+    }
+}
+
+impl DerefMut for PooledBuffer<'_> {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+}
+
+impl Drop for PooledBuffer<'_> {
+    fn drop(&mut self) {
+        self.pool.give(mem::take(&mut self.buffer));
+    }
+}
+
+impl Memory {
+    pub fn buffer_pool_stats(&self) -> BufferPoolStats {
+        self.buffer_pool().stats()
+    }
+
+    pub fn set_buffer_pool_config(&self, config: BufferPoolConfig) {
+        self.buffer_pool().set_config(config);
+    }
+
+    // Frees every buffer the pool holds now.
+    pub fn trim_buffer_pool(&self) {
+        self.buffer_pool().trim();
+    }
+}
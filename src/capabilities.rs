@@ -0,0 +1,407 @@
+// What the handle to a target is good for, found out once when it is opened. Some targets
+// let us read but not query regions, or query but not read, and without knowing that the
+// calls that can't work come back with empty results or a bare "access denied":
+//
+//     let memory = Memory::open(pid)?;
+//     if !memory.capabilities().can_query() {
+//         println!("regions are inferred by probing; scans run slower and see less");
+//     }
+//     match memory.write_memory(address, 100u32) {
+//         Err(e) if missing_capability(&e).is_some() => println!("{}", e),
+//         result => result?,
+//     }
+//
+// Reading, writing, region queries and memory operations follow from the rights the handle
+// was opened with and a test query; thread creation and suspension need rights beyond the
+// handle's, so they're tried with a separate open of the process and of one of its threads.
+// Each call that needs one of them checks first and fails with a `MissingCapability` error
+// (PermissionDenied) naming it. A `Memory` made by `from_replay` can only read.
+//
+// When regions can't be queried but memory can be read, `scan_memory` walks the address
+// space instead, one read per allocation granule (64 KiB), and caches each stretch of
+// readable granules as a region with `is_inferred` set: readable, of unknown protection and
+// type, and possibly with unreadable pages inside. A 32-bit target's address space is
+// walked entirely; a 64-bit one is too large, so the walk starts at the addresses its PEB
+// names — itself, the main image and the process heap — and goes on while reads succeed.
+
+use std::ffi::c_void;
+use std::fmt;
+use std::io;
+use std::ops::{BitOr, BitOrAssign};
+
+use log::debug;
+
+use crate::address::{Address, Size};
+use crate::memory::{Memory, MemoryRegion, RegionType};
+use crate::sys::{
+    CloseHandle, NtQueryInformationProcess, OpenProcess, ProcessBasicInformation, PROCESS_CREATE_THREAD,
+    PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE,
+};
+
+// The probing walk's step: VirtualAlloc reserves at this granularity, so no two allocations
+// share a granule.
+const GRANULE: usize = 0x10000;
+// Where a 32-bit target's walk ends.
+const WALK_END_32: usize = 0x1_0000_0000;
+// Unreadable granules a seeded walk steps over before it stops.
+const SEED_GAP: usize = 16;
+// PEB fields of a 64-bit process.
+const PEB64_IMAGE_BASE: usize = 0x10;
+const PEB64_PROCESS_HEAP: usize = 0x30;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities(u8);
+
+impl Capabilities {
+    pub const QUERY: Capabilities = Capabilities(1 << 0);
+    pub const READ: Capabilities = Capabilities(1 << 1);
+    pub const WRITE: Capabilities = Capabilities(1 << 2);
+    // Allocating, freeing and reprotecting memory.
+    pub const VM_OPERATION: Capabilities = Capabilities(1 << 3);
+    pub const CREATE_THREAD: Capabilities = Capabilities(1 << 4);
+    // Suspending the target's threads to read their registers.
+    pub const SUSPEND: Capabilities = Capabilities(1 << 5);
+    pub const ALL: Capabilities = Capabilities((1 << 6) - 1);
+
+    const NAMES: [(Capabilities, &'static str); 6] = [
+        (Capabilities::QUERY, "query"),
+        (Capabilities::READ, "read"),
+        (Capabilities::WRITE, "write"),
+        (Capabilities::VM_OPERATION, "vm operation"),
+        (Capabilities::CREATE_THREAD, "create thread"),
+        (Capabilities::SUSPEND, "suspend"),
+    ];
+
+    pub fn empty() -> Self {
+        Capabilities(0)
+    }
+
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // The ones in `self` that `other` lacks.
+    pub(crate) fn missing_from(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & !other.0)
+    }
+
+    pub fn can_query(self) -> bool {
+        self.contains(Capabilities::QUERY)
+    }
+
+    pub fn can_read(self) -> bool {
+        self.contains(Capabilities::READ)
+    }
+
+    pub fn can_write(self) -> bool {
+        self.contains(Capabilities::WRITE)
+    }
+
+    pub fn can_vm_operation(self) -> bool {
+        self.contains(Capabilities::VM_OPERATION)
+    }
+
+    pub fn can_create_thread(self) -> bool {
+        self.contains(Capabilities::CREATE_THREAD)
+    }
+
+    pub fn can_suspend(self) -> bool {
+        self.contains(Capabilities::SUSPEND)
+    }
+
+    // What `Memory::open_with_access` found for a fresh handle opened with `access`.
+    pub(crate) fn probe(memory: &Memory, access: u32) -> Self {
+        let mut capabilities = Capabilities::empty();
+        let from_access = [
+            (PROCESS_VM_READ, Capabilities::READ),
+            (PROCESS_VM_WRITE, Capabilities::WRITE),
+            (PROCESS_VM_OPERATION, Capabilities::VM_OPERATION),
+        ];
+        for (right, capability) in from_access {
+            if access & right != 0 {
+                capabilities |= capability;
+            }
+        }
+        if memory.query(0).is_ok() {
+            capabilities |= Capabilities::QUERY;
+        }
+
+        let thread_access = PROCESS_CREATE_THREAD | PROCESS_QUERY_INFORMATION | access;
+        let process = unsafe { OpenProcess(thread_access, 0, memory.process_id()) };
+        if !process.is_null() {
+            unsafe { CloseHandle(process) };
+            capabilities |= Capabilities::CREATE_THREAD;
+        }
+        if memory.can_suspend_threads() {
+            capabilities |= Capabilities::SUSPEND;
+        }
+        debug!("process {} capabilities: {}", memory.process_id(), capabilities);
+        capabilities
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Capabilities;
+
+    fn bitor(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for Capabilities {
+    fn bitor_assign(&mut self, other: Capabilities) {
+        self.0 |= other.0;
+    }
+}
+
+// "query, read, write", or "none".
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> =
+            Capabilities::NAMES.iter().filter(|(flag, _)| self.contains(*flag)).map(|(_, name)| *name).collect();
+        match names.is_empty() {
+            true => f.write_str("none"),
+            false => f.write_str(&names.join(", ")),
+        }
+    }
+}
+
+impl fmt::Debug for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Capabilities({})", self)
+    }
+}
+
+// Payload of the `io::Error` returned when a call needs something the handle can't do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingCapability(pub Capabilities);
+
+impl fmt::Display for MissingCapability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Missing capability: {}", self.0)
+    }
+}
+
+impl std::error::Error for MissingCapability {}
+
+pub fn missing_capability(e: &io::Error) -> Option<MissingCapability> {
+    e.get_ref()?.downcast_ref::<MissingCapability>().copied()
+}
+
+impl Memory {
+    // Fails with `MissingCapability` unless the handle can do all of `needed`, and with
+    // NotConnected once the `Memory` was closed.
+    pub(crate) fn require(&self, needed: Capabilities) -> io::Result<()> {
+        if self.is_closed() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("Process {} was closed", self.process_id()),
+            ));
+        }
+        let missing = needed.missing_from(self.capabilities());
+        if missing == Capabilities::empty() {
+            return Ok(());
+        }
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, MissingCapability(missing)))
+    }
+
+    // The regions `scan_memory` infers by reading when it can't query them. See above.
+    pub(crate) fn probe_regions(&self) -> Vec<MemoryRegion> {
+        let readable = |granule: usize| self.read_memory::<u8>(granule).is_ok();
+        let mut granules: Vec<usize> = Vec::new();
+        if self.pointer_size().unwrap_or(std::mem::size_of::<usize>()) == 4 {
+            granules.extend((GRANULE..WALK_END_32).step_by(GRANULE).filter(|&granule| readable(granule)));
+        } else {
+            for seed in self.peb_seeds() {
+                let mut granule = seed & !(GRANULE - 1);
+                let mut gap = 0;
+                while gap <= SEED_GAP {
+                    if readable(granule) {
+                        granules.push(granule);
+                        gap = 0;
+                    } else {
+                        gap += 1;
+                    }
+                    match granule.checked_add(GRANULE) {
+                        Some(next) => granule = next,
+                        None => break,
+                    }
+                }
+            }
+        }
+        granules.sort_unstable();
+        granules.dedup();
+
+        let mut regions: Vec<MemoryRegion> = Vec::new();
+        for granule in granules {
+            match regions.last_mut() {
+                Some(region) if region.end().get() == granule => region.size = Size::new(region.size.get() + GRANULE),
+                _ => regions.push(inferred_region(granule)),
+            }
+        }
+        debug!("inferred {} regions by probing process {}", regions.len(), self.process_id());
+        regions
+    }
+
+    // The PEB's own address, the main image and the process heap of a 64-bit target.
+    fn peb_seeds(&self) -> Vec<usize> {
+        let Some(peb) = self.peb_address() else {
+            return Vec::new();
+        };
+        let mut seeds = vec![peb];
+        for offset in [PEB64_IMAGE_BASE, PEB64_PROCESS_HEAP] {
+            if let Ok(address) = self.read_memory::<u64>(peb + offset) {
+                seeds.push(address as usize);
+            }
+        }
+        seeds
+    }
+
+    fn peb_address(&self) -> Option<usize> {
+        if let Some(backend) = self.backend() {
+            return backend.peb_address().map_err(|e| debug!("no PEB to seed the probing walk: {}", e)).ok();
+        }
+        // PROCESS_BASIC_INFORMATION: exit status, PEB address, affinity mask, priority, ids.
+        let mut info = [0usize; 6];
+        let status = unsafe {
+            NtQueryInformationProcess(
+                self.handle(),
+                ProcessBasicInformation,
+                info.as_mut_ptr() as *mut c_void,
+                std::mem::size_of_val(&info) as u32,
+                std::ptr::null_mut(),
+            )
+        };
+        if status < 0 || info[1] == 0 {
+            debug!("no PEB to seed the probing walk: NTSTATUS 0x{:08X}", status as u32);
+            return None;
+        }
+        Some(info[1])
+    }
+}
+
+fn inferred_region(start: usize) -> MemoryRegion {
+    MemoryRegion {
+        start_address: Address::from(start),
+        size: Size::new(GRANULE),
+        allocation_base: Address::from(start),
+        is_readable: true,
+        is_writable: false,
+        is_executable: false,
+        is_guard: false,
+        is_copy_on_write: false,
+        protection: 0,
+        region_type: RegionType::Unknown,
+        is_inferred: true,
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use std::ops::Range;
+
+    use super::*;
+    use crate::backend::mock::{Call, MockBackend, PAGE};
+    use crate::filter::RegionFilter;
+    use crate::pattern::Pattern;
+    use crate::sys::{PAGE_NOACCESS, PAGE_READWRITE};
+
+    fn inferred(memory: &Memory) -> Vec<Range<usize>> {
+        assert!(memory.regions().all(|region| region.is_inferred && region.region_type == RegionType::Unknown));
+        memory.regions().map(|region| region.range()).collect()
+    }
+
+    #[test]
+    fn capabilities_read_as_a_list() {
+        assert_eq!(Capabilities::empty().to_string(), "none");
+        assert_eq!((Capabilities::READ | Capabilities::QUERY).to_string(), "query, read");
+        assert_eq!(Capabilities::ALL.to_string(), "query, read, write, vm operation, create thread, suspend");
+        assert_eq!(
+            Capabilities::ALL.missing_from(Capabilities::READ | Capabilities::WRITE).to_string(),
+            "query, vm operation, create thread, suspend"
+        );
+    }
+
+    #[test]
+    fn each_call_needs_its_capability() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open_with(Capabilities::QUERY);
+        let missing = |result: io::Result<()>| missing_capability(&result.unwrap_err()).map(|missing| missing.0);
+
+        assert_eq!(missing(memory.read_memory::<u8>(0x10000).map(drop)), Some(Capabilities::READ));
+        assert_eq!(missing(memory.write_memory(0x10000, 1u8)), Some(Capabilities::WRITE));
+        assert_eq!(missing(memory.protect(0x10000, PAGE, PAGE_NOACCESS).map(drop)), Some(Capabilities::VM_OPERATION));
+        assert!(mock.calls().is_empty());
+        assert_eq!(memory.query(0x10000).unwrap().RegionSize, PAGE);
+    }
+
+    #[test]
+    fn without_query_a_32_bit_target_is_walked_whole() {
+        let mut heap = vec![0; PAGE];
+        heap[0x10..0x14].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        let (mock, mut memory) = MockBackend::new()
+            .pointer_size(4)
+            .data(0x10000, vec![0; GRANULE + PAGE])
+            .data(0x50000, heap)
+            .region(0x70000, vec![0; PAGE], PAGE_NOACCESS)
+            .data(0xFFFF_0000, vec![0; PAGE])
+            .open_with(Capabilities::READ);
+        memory.scan_memory().unwrap();
+
+        assert_eq!(inferred(&memory), [0x10000..0x30000, 0x50000..0x60000, 0xFFFF_0000..0x1_0000_0000]);
+        assert!(!mock.calls().iter().any(|call| matches!(call, Call::Query(_))));
+        // An inferred region runs past the readable stretch it stands for; the rest is a hole.
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        assert_eq!(memory.find_pattern_all(&pattern, &RegionFilter::default()).unwrap(), [0x50010]);
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn without_query_a_64_bit_walk_starts_where_the_peb_points() {
+        let mut peb = vec![0; PAGE];
+        peb[PEB64_IMAGE_BASE..PEB64_IMAGE_BASE + 8].copy_from_slice(&0x1_4000_0000u64.to_le_bytes());
+        peb[PEB64_PROCESS_HEAP..PEB64_PROCESS_HEAP + 8].copy_from_slice(&0x30_0000u64.to_le_bytes());
+        let (_mock, mut memory) = MockBackend::new()
+            .peb(0x7FF0_0000)
+            .data(0x7FF0_0000, peb)
+            .data(0x30_0000, vec![0; PAGE])
+            .data(0x1_4000_0000, vec![0; 3 * GRANULE])
+            // Within the gap a walk steps over, and past it.
+            .data(0x1_4000_0000 + 10 * GRANULE, vec![0; PAGE])
+            .data(0x1_4000_0000 + 40 * GRANULE, vec![0; PAGE])
+            // Near no seed.
+            .data(0x10000, vec![0; PAGE])
+            .open_with(Capabilities::READ);
+        memory.scan_memory().unwrap();
+
+        assert_eq!(
+            inferred(&memory),
+            [
+                0x30_0000..0x31_0000,
+                0x7FF0_0000..0x7FF1_0000,
+                0x1_4000_0000..0x1_4003_0000,
+                0x1_400A_0000..0x1_400B_0000
+            ]
+        );
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn without_a_peb_nothing_is_inferred() {
+        let (_mock, mut memory) =
+            MockBackend::new().data(0x10000, vec![0; PAGE]).open_with(Capabilities::READ | Capabilities::WRITE);
+        memory.scan_memory().unwrap();
+
+        assert!(inferred(&memory).is_empty());
+    }
+
+    #[test]
+    fn with_query_regions_are_queried_even_without_read() {
+        let (_mock, mut memory) =
+            MockBackend::new().region(0x10000, vec![0; PAGE], PAGE_READWRITE).open_with(Capabilities::QUERY);
+        memory.scan_memory().unwrap();
+
+        let regions: Vec<_> = memory.regions().map(|region| (region.range(), region.is_inferred)).collect();
+        assert_eq!(regions, [(0x10000..0x11000, false)]);
+    }
+}
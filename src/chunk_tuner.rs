@@ -0,0 +1,317 @@
+// Picks the size of a pattern scan's next chunk from how the previous ones went. Small
+// chunks spend their time in syscalls; big ones read a lot in vain when they fail and have
+// to be bisected for holes, and check for cancellation rarely. The tuner starts at the
+// options' chunk size and walks between the bounds by doubling and halving:
+//
+// - Throughput is measured over rounds of ROUND full chunks at one size: the bytes read over
+//   the time their reads took. Chunks clipped by a region's end or by paged-out memory, and
+//   short reads, don't count.
+// - After each round it steps again in the direction it went last while that gained more
+//   than TOLERANCE, goes back a step if the last one cost more than that, and otherwise
+//   stays. Having settled it stays for HOLD rounds, then tries a bigger size again.
+// - A chunk that fails to read halves the size at once, and so does one that took longer
+//   than SLOW_CHUNK; either way it then holds before growing again.
+//
+// Nothing here reads a clock: given the same measurements the sizes come out the same. The
+// chunks still overlap by the pattern's length less one whatever their sizes, so matches
+// across seams are found as with fixed chunks.
+
+use std::time::Duration;
+
+use crate::residency::PAGE_SIZE;
+
+// Default bounds.
+pub(crate) const MIN_CHUNK: usize = 64 * 1024;
+pub(crate) const MAX_CHUNK: usize = 8 * 1024 * 1024;
+
+// Full chunks per measurement.
+const ROUND: u32 = 4;
+// Throughput changes smaller than this share are noise.
+const TOLERANCE: f64 = 0.1;
+// Rounds to stay at a size after settling, before probing a bigger one.
+const HOLD: u32 = 8;
+// Longest a chunk may take before the next one is made smaller, to keep cancellation and
+// progress reporting responsive.
+const SLOW_CHUNK: Duration = Duration::from_millis(100);
+
+pub(crate) struct ChunkTuner {
+    min: usize,
+    max: usize,
+    size: usize,
+    // Whether the last step was up.
+    growing: bool,
+    // Throughput of the last round at the size before the current one, in bytes per second.
+    previous: Option<f64>,
+    // Rounds left to stay at this size.
+    hold: u32,
+    // The round in progress.
+    chunks: u32,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl ChunkTuner {
+    // Both bounds are rounded down to pages, and `start` is kept within them.
+    pub(crate) fn new(start: usize, min: usize, max: usize) -> Self {
+        let min = pages(min).max(PAGE_SIZE);
+        let max = pages(max).max(min);
+        ChunkTuner {
+            min,
+            max,
+            size: pages(start).clamp(min, max),
+            growing: true,
+            previous: None,
+            hold: 0,
+            chunks: 0,
+            bytes: 0,
+            elapsed: Duration::ZERO,
+        }
+    }
+
+    // A tuner that never changes `size`, which is used as it is.
+    pub(crate) fn fixed(size: usize) -> Self {
+        ChunkTuner { min: size, max: size, size, ..ChunkTuner::new(size, size, size) }
+    }
+
+    pub(crate) fn size(&self) -> usize {
+        self.size
+    }
+
+    // A chunk of `requested` bytes read `read` of them in `elapsed`, or failed.
+    pub(crate) fn observe(&mut self, requested: usize, read: Option<usize>, elapsed: Duration) {
+        let Some(read) = read else {
+            self.shrink();
+            return;
+        };
+        if elapsed > SLOW_CHUNK && requested >= self.size {
+            self.shrink();
+            return;
+        }
+        if requested != self.size || read < requested {
+            return;
+        }
+
+        self.chunks += 1;
+        self.bytes += read as u64;
+        self.elapsed += elapsed;
+        if self.chunks < ROUND {
+            return;
+        }
+        // A round too fast to time counts as a nanosecond.
+        let throughput = self.bytes as f64 / self.elapsed.as_secs_f64().max(1e-9);
+        self.start_round();
+
+        if self.hold > 0 {
+            self.hold -= 1;
+            if self.hold == 0 {
+                self.growing = true;
+                self.step(throughput);
+            }
+            return;
+        }
+        match self.previous {
+            Some(previous) if throughput < previous * (1.0 - TOLERANCE) => {
+                self.growing = !self.growing;
+                self.step(throughput);
+                self.hold = HOLD;
+            }
+            Some(previous) if throughput <= previous * (1.0 + TOLERANCE) => self.hold = HOLD,
+            _ => self.step(throughput),
+        }
+    }
+
+    // One step in the current direction, remembering what this size managed. At a bound
+    // there is nowhere to go, so the tuner settles there.
+    fn step(&mut self, throughput: f64) {
+        let next = match self.growing {
+            true => self.size.saturating_mul(2).min(self.max),
+            false => pages(self.size / 2).max(self.min),
+        };
+        if next == self.size {
+            self.hold = HOLD;
+            return;
+        }
+        self.previous = Some(throughput);
+        self.size = next;
+    }
+
+    fn shrink(&mut self) {
+        self.size = pages(self.size / 2).max(self.min);
+        self.growing = false;
+        self.previous = None;
+        self.hold = HOLD;
+        self.start_round();
+    }
+
+    fn start_round(&mut self) {
+        self.chunks = 0;
+        self.bytes = 0;
+        self.elapsed = Duration::ZERO;
+    }
+}
+
+fn pages(size: usize) -> usize {
+    size / PAGE_SIZE * PAGE_SIZE
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::filter::RegionFilter;
+    use crate::pattern::Pattern;
+    use crate::scan::ScanOptions;
+
+    const KIB: usize = 1024;
+    const MIB: usize = 1024 * KIB;
+
+    // The size after each of `rounds` rounds of full chunks, each taking `time(size)`.
+    fn rounds(tuner: &mut ChunkTuner, rounds: usize, time: impl Fn(usize) -> Duration) -> Vec<usize> {
+        (0..rounds)
+            .map(|_| {
+                for _ in 0..ROUND {
+                    let size = tuner.size();
+                    tuner.observe(size, Some(size), time(size));
+                }
+                tuner.size()
+            })
+            .collect()
+    }
+
+    // Reads that get faster up to 1 MiB chunks and slower past it, in MB per second.
+    fn peaking(size: usize) -> Duration {
+        let rate = match size / KIB {
+            ..=64 => 100,
+            128 => 200,
+            256 => 400,
+            512 => 800,
+            1024 => 1000,
+            _ => 500,
+        };
+        Duration::from_secs_f64(size as f64 / (rate as f64 * 1e6))
+    }
+
+    #[test]
+    fn bounds_are_whole_pages_and_hold_the_start() {
+        let tuner = ChunkTuner::new(12345, 1000, 3 * PAGE_SIZE + 5);
+        assert_eq!((tuner.min, tuner.max, tuner.size()), (PAGE_SIZE, 3 * PAGE_SIZE, 3 * PAGE_SIZE));
+        assert_eq!(ChunkTuner::new(0, 0, 0).size(), PAGE_SIZE);
+        assert_eq!(ChunkTuner::new(MAX_CHUNK * 2, MIN_CHUNK, MAX_CHUNK).size(), MAX_CHUNK);
+        assert_eq!(ChunkTuner::new(0, 2 * MIB, MIB).size(), 2 * MIB);
+    }
+
+    #[test]
+    fn it_climbs_to_the_fastest_size_and_probes_past_it() {
+        let mut tuner = ChunkTuner::new(256 * KIB, MIN_CHUNK, MAX_CHUNK);
+        let sizes = rounds(&mut tuner, 3 + 1 + HOLD as usize + 1, peaking);
+
+        // Up while it pays, one step too far and back, then held there until the next probe.
+        let mut expected = vec![512 * KIB, MIB, 2 * MIB, MIB];
+        expected.extend(vec![MIB; HOLD as usize - 1]);
+        expected.extend([2 * MIB, MIB]);
+        assert_eq!(sizes, expected);
+    }
+
+    #[test]
+    fn changes_within_the_tolerance_settle_it() {
+        let mut tuner = ChunkTuner::new(MIN_CHUNK, MIN_CHUNK, MAX_CHUNK);
+        // The same throughput at every size: one step up, then no gain worth another.
+        let sizes = rounds(&mut tuner, 2 + HOLD as usize, |size| Duration::from_micros(size as u64 / 1000));
+        assert_eq!(sizes[..2], [2 * MIN_CHUNK, 2 * MIN_CHUNK]);
+        assert_eq!(sizes[2 + HOLD as usize - 1], 4 * MIN_CHUNK);
+    }
+
+    #[test]
+    fn failures_and_slow_chunks_halve_at_once() {
+        let mut tuner = ChunkTuner::new(MIB, MIN_CHUNK, MAX_CHUNK);
+        tuner.observe(MIB, None, Duration::ZERO);
+        assert_eq!(tuner.size(), 512 * KIB);
+        tuner.observe(512 * KIB, Some(512 * KIB), SLOW_CHUNK * 2);
+        assert_eq!(tuner.size(), 256 * KIB);
+        // A slow chunk clipped short of the size says nothing about the size.
+        tuner.observe(128 * KIB, Some(128 * KIB), SLOW_CHUNK * 2);
+        assert_eq!(tuner.size(), 256 * KIB);
+
+        // Then it holds before growing, however fast the reads are.
+        let sizes = rounds(&mut tuner, HOLD as usize, |_| Duration::from_micros(1));
+        assert!(sizes[..HOLD as usize - 1].iter().all(|&size| size == 256 * KIB));
+        assert_eq!(sizes[HOLD as usize - 1], 512 * KIB);
+    }
+
+    #[test]
+    fn it_never_leaves_its_bounds() {
+        let mut tuner = ChunkTuner::new(MIN_CHUNK, MIN_CHUNK, 4 * MIN_CHUNK);
+        for _ in 0..4 {
+            tuner.observe(tuner.size(), None, Duration::ZERO);
+        }
+        assert_eq!(tuner.size(), MIN_CHUNK);
+        let sizes = rounds(&mut tuner, 40, |size| Duration::from_nanos((size / 64) as u64));
+        assert!(sizes.iter().all(|&size| (MIN_CHUNK..=4 * MIN_CHUNK).contains(&size)));
+        assert_eq!(*sizes.last().unwrap(), 4 * MIN_CHUNK);
+    }
+
+    #[test]
+    fn clipped_and_short_chunks_do_not_count() {
+        let mut tuner = ChunkTuner::new(MIN_CHUNK, MIN_CHUNK, MAX_CHUNK);
+        for _ in 0..ROUND - 1 {
+            tuner.observe(MIN_CHUNK, Some(MIN_CHUNK), Duration::from_micros(100));
+        }
+        for _ in 0..ROUND {
+            tuner.observe(MIN_CHUNK / 2, Some(MIN_CHUNK / 2), Duration::from_micros(100));
+            tuner.observe(MIN_CHUNK, Some(MIN_CHUNK - 1), Duration::from_micros(100));
+        }
+        assert_eq!(tuner.size(), MIN_CHUNK);
+        tuner.observe(MIN_CHUNK, Some(MIN_CHUNK), Duration::from_micros(100));
+        assert_eq!(tuner.size(), 2 * MIN_CHUNK);
+    }
+
+    #[test]
+    fn the_same_measurements_give_the_same_sizes() {
+        // Jittery timings from a fixed seed, with the odd failure.
+        let run = || {
+            let mut tuner = ChunkTuner::new(MIB, MIN_CHUNK, MAX_CHUNK);
+            let mut seed: u64 = 0x9E37_79B9;
+            (0..500)
+                .map(|_| {
+                    seed ^= seed << 13;
+                    seed ^= seed >> 7;
+                    seed ^= seed << 17;
+                    let size = tuner.size();
+                    let read = (seed % 50 != 0).then_some(size);
+                    tuner.observe(size, read, Duration::from_nanos(size as u64 / 2 + seed % 100_000));
+                    tuner.size()
+                })
+                .collect::<Vec<_>>()
+        };
+        let sizes = run();
+        assert_eq!(sizes, run());
+        assert!(sizes.iter().any(|&size| size != MIB));
+    }
+
+    #[test]
+    fn tuned_scans_find_what_fixed_ones_do() {
+        // Matches across every page seam, and a page that fails to read.
+        let pages = 64;
+        let mut bytes = vec![0u8; pages * PAGE_SIZE];
+        for seam in (1..pages).map(|page| page * PAGE_SIZE) {
+            bytes[seam - 2..seam + 2].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        }
+        let (mock, mut memory) = MockBackend::new().data(0x100000, bytes).open();
+        memory.scan_memory().unwrap();
+        mock.fail_reads(0x100000 + 20 * PAGE_SIZE..0x100000 + 21 * PAGE_SIZE);
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let filter = RegionFilter::new();
+        let fixed = ScanOptions::default().chunk_size(PAGE_SIZE);
+        let tuned = fixed.clone().adaptive_chunks(true).chunk_size_bounds(PAGE_SIZE, 8 * PAGE_SIZE);
+        let fixed = memory.find_pattern_report(&pattern, &filter, &fixed).unwrap();
+        let tuned = memory.find_pattern_report(&pattern, &filter, &tuned).unwrap();
+
+        assert_eq!(fixed.matches.len(), pages - 1 - 2);
+        assert_eq!(tuned.matches, fixed.matches);
+        assert_eq!(tuned.holes, [0x100000 + 20 * PAGE_SIZE..0x100000 + 21 * PAGE_SIZE]);
+        assert_eq!(fixed.chunk_sizes.keys().copied().collect::<Vec<_>>(), [PAGE_SIZE]);
+        assert!(tuned.chunk_sizes.keys().all(|&size| size <= 8 * PAGE_SIZE && size % PAGE_SIZE == 0));
+    }
+}
@@ -0,0 +1,105 @@
+// A piece of memory read a chunk at a time, the way the windowed, group, string and YARA
+// scans and session snapshots walk theirs. Each read reaches `overlap` bytes past its chunk,
+// so a window starting near the end of one is whole; windows starting in that overlap belong
+// to the next chunk:
+//
+//     let mut chunks = Chunks::new(piece, CHUNK_SIZE, width - 1, alignment);
+//     while let Some(chunk) = chunks.read_next(memory, &mut buffer) {
+//         // windows at chunk.address.. up to chunk.owned_end, within buffer[..chunk.read]
+//     }
+//
+// A read that fails or comes back short ends the piece, unless `skip_holes` is set: then the
+// unreadable pages where it stopped are found (see `unreadable_ranges`) and the walk goes on
+// after them.
+
+use std::ops::Range;
+
+use log::debug;
+
+use crate::memory::Memory;
+use crate::residency::PAGE_SIZE;
+
+pub(crate) struct Chunks {
+    next: usize,
+    end: usize,
+    chunk_size: usize,
+    overlap: usize,
+    alignment: usize,
+    skip_holes: bool,
+}
+
+// One read of `Chunks`, made at `address`.
+pub(crate) struct Chunk {
+    pub(crate) address: usize,
+    // Windows starting before this belong to the chunk.
+    pub(crate) owned_end: usize,
+    // Bytes read, the overlap included.
+    pub(crate) read: usize,
+    // Fewer bytes came back than the chunk asked for, none if the read failed.
+    pub(crate) short: bool,
+    // Bytes given up on after a short read: the rest of the piece, or the hole skipped.
+    pub(crate) unreadable: usize,
+}
+
+impl Chunks {
+    // Chunks start at multiples of `alignment`, the first at or after `piece.start`.
+    pub(crate) fn new(piece: Range<usize>, chunk_size: usize, overlap: usize, alignment: usize) -> Self {
+        let next = piece.start.next_multiple_of(alignment);
+        Chunks { next, end: piece.end, chunk_size, overlap, alignment, skip_holes: false }
+    }
+
+    pub(crate) fn skip_holes(mut self) -> Self {
+        self.skip_holes = true;
+        self
+    }
+
+    // None of the piece; for a walk that hasn't begun one.
+    pub(crate) fn empty() -> Self {
+        Chunks { next: 0, end: 0, chunk_size: 0, overlap: 0, alignment: 1, skip_holes: false }
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.next >= self.end
+    }
+
+    // Reads the next chunk into the front of `buffer`, which holds a chunk and its overlap
+    // or the rest of the piece; None once the piece is done.
+    pub(crate) fn read_next(&mut self, memory: &Memory, buffer: &mut [u8]) -> Option<Chunk> {
+        if self.is_done() {
+            return None;
+        }
+        let address = self.next;
+        let owned_end = address.saturating_add(self.chunk_size).min(self.end);
+        let len = owned_end.saturating_add(self.overlap).min(self.end) - address;
+        let read = match memory.read_bulk(address, &mut buffer[..len]) {
+            Ok(read) => read,
+            Err(e) => {
+                debug!("skipping unreadable chunk at 0x{:X}: {}", address, e);
+                0
+            }
+        };
+        let short = read < len;
+        self.next = match short {
+            false => owned_end.next_multiple_of(self.alignment),
+            true if self.skip_holes => {
+                self.after_hole(memory, address, address + read).next_multiple_of(self.alignment)
+            }
+            true => self.end,
+        };
+        let unreadable = if short { self.next.min(self.end).saturating_sub(address + read) } else { 0 };
+        Some(Chunk { address, owned_end, read, short, unreadable })
+    }
+
+    // Where reading goes on after a read at `address` stopped at `stop`: past the
+    // unreadable pages there, looked for up to a chunk ahead.
+    fn after_hole(&self, memory: &Memory, address: usize, stop: usize) -> usize {
+        let limit = stop.saturating_add(self.chunk_size.max(PAGE_SIZE)).min(self.end);
+        match memory.unreadable_ranges(stop..limit).first() {
+            Some(hole) if hole.start == stop => hole.end,
+            // Readable again by now: go back for the windows the read cut off, or past the
+            // page if it read nothing.
+            _ if stop > address => stop.saturating_sub(self.overlap).max(address + 1),
+            _ => (stop + 1).next_multiple_of(PAGE_SIZE).min(self.end),
+        }
+    }
+}
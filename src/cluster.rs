@@ -0,0 +1,199 @@
+// Scan results grouped by where they are, for a summary a person can read. Four hundred hits
+// make more sense as "3 in game.exe!.data, 390 in one 256 MiB private allocation, 7 on the
+// stack of thread 1234" than as a list:
+//
+//     let matches = memory.scan_for_value(100u32)?;
+//     print!("{}", ClusterSummary(&memory.cluster_results(&matches)));
+//
+// Each address goes to the first of these that holds it: a section of a module, the rest of
+// a module's image, the allocation around a thread's stack pointer, any other allocation,
+// and, for addresses in no cached region, one cluster of leftovers. Clusters come out with
+// the most hits first. `cluster_addresses` does the grouping from metadata alone;
+// `cluster_results` takes that metadata from `memory`, and finds the stacks in each thread's
+// TEB (see `thread_stacks`), without suspending anything.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+
+use crate::allocation::group_allocations;
+use crate::memory::{Memory, MemoryRegion, RegionType};
+use crate::module::ModuleInfo;
+use crate::sections::SectionRef;
+use crate::stats::format_size;
+
+// Addresses kept in `Cluster::sample`.
+pub const SAMPLE_SIZE: usize = 4;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ClusterKind {
+    Section { module: String, section: String },
+    // The part of a module's image no cached section covers, such as its headers.
+    Module(String),
+    // The allocation holding a thread's stack.
+    Stack { thread_id: u32 },
+    // Any other allocation: its base, its span and what kind of memory it is.
+    Allocation { base: usize, size: usize, region_type: RegionType },
+    // In none of the regions given.
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cluster {
+    pub kind: ClusterKind,
+    pub count: usize,
+    // From the lowest hit to just past the highest.
+    pub span: Range<usize>,
+    // Up to `SAMPLE_SIZE` hits spread evenly through the cluster, in ascending order.
+    pub sample: Vec<usize>,
+}
+
+// "390 hits in a private allocation at 0x1F0000 (256 MiB), 0x1F0040-0x10F0000, e.g. 0x1F0040, ..."
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hits = if self.count == 1 { "hit" } else { "hits" };
+        write!(f, "{} {} ", self.count, hits)?;
+        match &self.kind {
+            ClusterKind::Section { module, section } => write!(f, "in {}!{}", module, section)?,
+            ClusterKind::Module(module) => write!(f, "in {} outside its sections", module)?,
+            ClusterKind::Stack { thread_id } => write!(f, "on the stack of thread {}", thread_id)?,
+            ClusterKind::Allocation { base, size, region_type } => {
+                write!(f, "in a {} allocation at 0x{:X} ({})", region_type.name(), base, format_size(*size))?
+            }
+            ClusterKind::Unknown => f.write_str("outside any known region")?,
+        }
+        if self.count > 1 {
+            write!(f, ", 0x{:X}-0x{:X}", self.span.start, self.span.end)?;
+        }
+        let sample: Vec<String> = self.sample.iter().map(|address| format!("0x{:X}", address)).collect();
+        write!(f, ", e.g. {}", sample.join(", "))?;
+        if self.count > self.sample.len() {
+            f.write_str(", ...")?;
+        }
+        Ok(())
+    }
+}
+
+// A heading with the totals, then one line per cluster.
+pub struct ClusterSummary<'a>(pub &'a [Cluster]);
+
+impl fmt::Display for ClusterSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hits: usize = self.0.iter().map(|cluster| cluster.count).sum();
+        let noun = if hits == 1 { "hit" } else { "hits" };
+        let places = if self.0.len() == 1 { "place" } else { "places" };
+        writeln!(f, "{} {} in {} {}:", hits, noun, self.0.len(), places)?;
+        for cluster in self.0 {
+            writeln!(f, "  {}", cluster)?;
+        }
+        Ok(())
+    }
+}
+
+impl Memory {
+    // See above. Uses the cached regions, modules and sections.
+    pub fn cluster_results(&self, addresses: &[usize]) -> Vec<Cluster> {
+        let modules = self.modules_or_live().unwrap_or_default();
+        let stacks: Vec<(u32, usize)> =
+            self.thread_stacks().unwrap_or_default().iter().map(|stack| (stack.thread_id, stack.limit)).collect();
+        cluster_addresses(addresses, self.cached_regions(), &modules, &self.cached_sections(), &stacks)
+    }
+}
+
+// Groups `addresses` as described above. `stacks` holds (thread id, an address on the thread's
+// stack) pairs.
+pub fn cluster_addresses(
+    addresses: &[usize],
+    regions: &[MemoryRegion],
+    modules: &[ModuleInfo],
+    sections: &[SectionRef],
+    stacks: &[(u32, usize)],
+) -> Vec<Cluster> {
+    let allocations = group_allocations(regions);
+    // Every region with the allocation it belongs to, by start address.
+    let mut by_start: Vec<(Range<usize>, usize)> = allocations
+        .iter()
+        .enumerate()
+        .flat_map(|(index, allocation)| {
+            allocation.regions.iter().map(move |region| (region.start_address.get()..region.end().get(), index))
+        })
+        .collect();
+    by_start.sort_by_key(|(range, _)| range.start);
+    let allocation_at = |address: usize| {
+        let index = by_start.partition_point(|(range, _)| range.start <= address);
+        let containing = index.checked_sub(1).map(|index| &by_start[index]);
+        containing.filter(|(range, _)| range.contains(&address)).map(|(_, allocation)| *allocation)
+    };
+
+    // Sections and modules don't overlap one another, so sorted they can be searched too.
+    let mut sections: Vec<&SectionRef> = sections.iter().collect();
+    sections.sort_by_key(|section| section.range.start);
+    let mut modules: Vec<&ModuleInfo> = modules.iter().collect();
+    modules.sort_by_key(|module| module.base_address);
+    // The first thread found on each allocation.
+    let mut stack_of: HashMap<usize, u32> = HashMap::new();
+    for (thread_id, address) in stacks {
+        if let Some(allocation) = allocation_at(*address) {
+            stack_of.entry(allocation).or_insert(*thread_id);
+        }
+    }
+
+    let kind_of = |address: usize| {
+        let index = sections.partition_point(|section| section.range.start <= address);
+        let section = index.checked_sub(1).map(|index| sections[index]);
+        if let Some(section) = section.filter(|section| section.contains(address)) {
+            return ClusterKind::Section { module: section.module.clone(), section: section.name.clone() };
+        }
+        let index = modules.partition_point(|module| module.base_address <= address);
+        let module = index.checked_sub(1).map(|index| modules[index]);
+        if let Some(module) = module.filter(|module| module.contains(address)) {
+            return ClusterKind::Module(module.name.clone());
+        }
+        let Some(index) = allocation_at(address) else {
+            return ClusterKind::Unknown;
+        };
+        if let Some(thread_id) = stack_of.get(&index) {
+            return ClusterKind::Stack { thread_id: *thread_id };
+        }
+        let allocation = &allocations[index];
+        ClusterKind::Allocation {
+            base: allocation.base.get(),
+            size: allocation.span.len(),
+            region_type: allocation.regions[0].region_type,
+        }
+    };
+
+    let mut sorted = addresses.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+    let mut index_by_kind: HashMap<ClusterKind, usize> = HashMap::new();
+    let mut groups: Vec<(ClusterKind, Vec<usize>)> = Vec::new();
+    for address in sorted {
+        let kind = kind_of(address);
+        let index = *index_by_kind.entry(kind.clone()).or_insert_with(|| {
+            groups.push((kind, Vec::new()));
+            groups.len() - 1
+        });
+        groups[index].1.push(address);
+    }
+
+    let mut clusters: Vec<Cluster> = groups
+        .into_iter()
+        .map(|(kind, hits)| Cluster {
+            kind,
+            count: hits.len(),
+            span: hits[0]..hits[hits.len() - 1] + 1,
+            sample: sample(&hits),
+        })
+        .collect();
+    clusters.sort_by(|a, b| b.count.cmp(&a.count).then(a.span.start.cmp(&b.span.start)));
+    clusters
+}
+
+// `SAMPLE_SIZE` of the sorted `hits`, the first and last among them.
+fn sample(hits: &[usize]) -> Vec<usize> {
+    if hits.len() <= SAMPLE_SIZE {
+        return hits.to_vec();
+    }
+    (0..SAMPLE_SIZE).map(|i| hits[i * (hits.len() - 1) / (SAMPLE_SIZE - 1)]).collect()
+}
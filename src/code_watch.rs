@@ -0,0 +1,247 @@
+// Catches code that changes while the target runs, e.g. pages a protector decrypts just in
+// time. The executable sections of one module are hashed page by page once per tick, and a
+// page whose hash differs from the tick before is reported with the bytes that changed.
+//
+//     let watch = CodeWatch::start(&memory, "game.exe", Duration::from_millis(100))?;
+//     for change in watch.events().iter().take(10) {
+//         println!("{}", change);
+//     }
+//     watch.stop();
+//
+// Reads go through the bulk read path, so a `Throttle` on the `Memory` slows the watch down
+// rather than letting it hammer the target.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::hash::Fnv1a;
+use crate::memory::Memory;
+use crate::monitor::BLOCK_SIZE;
+use crate::process_handle::{spawn_worker, upgrade};
+use crate::sections;
+use crate::wstr;
+
+// Changed bytes listed per event; the rest are only counted.
+pub const MAX_DIFF_BYTES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteChange {
+    pub address: usize,
+    pub old: u8,
+    pub new: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeChange {
+    pub page: Range<usize>,
+    // Tick in which the change was seen. The first tick a page can be read in only records
+    // its baseline.
+    pub tick: u64,
+    // The first MAX_DIFF_BYTES changed bytes, in address order.
+    pub diff: Vec<ByteChange>,
+    // All changed bytes, including those left out of `diff`.
+    pub changed_bytes: usize,
+    // Where the page was dumped, when dumping is on and the write succeeded.
+    pub dump: Option<PathBuf>,
+}
+
+// "page 0x7FF6A0011000-0x7FF6A0012000 changed in tick 4: 12 bytes"
+impl fmt::Display for CodeChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "page 0x{:X}-0x{:X} changed in tick {}: {} byte{}",
+            self.page.start,
+            self.page.end,
+            self.tick,
+            self.changed_bytes,
+            if self.changed_bytes == 1 { "" } else { "s" }
+        )?;
+        if let Some(dump) = &self.dump {
+            write!(f, " (dumped to {})", dump.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CodeWatchReport {
+    pub ticks: u64,
+    // Ticks in which each page changed, keyed by page address.
+    pub pages: BTreeMap<usize, u64>,
+}
+
+// Watches the executable sections of a module from a background thread.
+pub struct CodeWatch {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<CodeWatchReport>,
+    events: Receiver<CodeChange>,
+}
+
+impl CodeWatch {
+    // The watch stops by itself once `memory` is dropped or closed.
+    pub fn start(memory: &Arc<Memory>, module: &str, interval: Duration) -> io::Result<Self> {
+        Self::start_with_dumps(memory, module, interval, None)
+    }
+
+    // Also writes every changed page to `dump_dir` as "<module>-<sequence>-<address>.bin",
+    // numbered from 0 in the order the changes are seen.
+    pub fn start_with_dumps(
+        memory: &Arc<Memory>,
+        module: &str,
+        interval: Duration,
+        dump_dir: Option<PathBuf>,
+    ) -> io::Result<Self> {
+        let (name, pages) = executable_pages(memory, module)?;
+        if let Some(dir) = &dump_dir {
+            fs::create_dir_all(dir)?;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, events) = mpsc::channel();
+
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let dumper = dump_dir.map(|dir| Dumper { dir, module: name, sequence: 0 });
+            spawn_worker(memory, move |memory| run(memory, &pages, dumper, interval, &stop, &sender))
+        };
+
+        Ok(CodeWatch { stop, thread, events })
+    }
+
+    // Changes as they are detected. The report from `stop` counts them even if nobody reads here.
+    pub fn events(&self) -> &Receiver<CodeChange> {
+        &self.events
+    }
+
+    pub fn stop(self) -> CodeWatchReport {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+// The module's name as loaded and the pages of its executable sections.
+fn executable_pages(memory: &Memory, module: &str) -> io::Result<(String, Vec<Range<usize>>)> {
+    let modules = memory.modules_or_live()?;
+    let module = modules
+        .iter()
+        .find(|loaded| wstr::eq_ignore_case(&loaded.name, module))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Module '{}' is not loaded", module)))?;
+
+    let pages: Vec<Range<usize>> = sections::read_sections(memory, module)?
+        .iter()
+        .filter(|section| section.is_executable())
+        .flat_map(|section| {
+            let range = section.range.clone();
+            range.clone().step_by(BLOCK_SIZE).map(move |page| page..(page + BLOCK_SIZE).min(range.end))
+        })
+        .collect();
+    if pages.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Module '{}' has no executable sections", module.name),
+        ));
+    }
+    Ok((module.name.clone(), pages))
+}
+
+struct Dumper {
+    dir: PathBuf,
+    module: String,
+    sequence: u64,
+}
+
+impl Dumper {
+    fn dump(&mut self, page: &Range<usize>, bytes: &[u8]) -> Option<PathBuf> {
+        let path = self.dir.join(format!("{}-{:04}-{:X}.bin", self.module, self.sequence, page.start));
+        self.sequence += 1;
+        match fs::write(&path, bytes) {
+            Ok(()) => Some(path),
+            Err(e) => {
+                warn!("failed to dump page 0x{:X} to {}: {}", page.start, path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+// The last readable contents of a page, kept for the diff, with their hash.
+struct Baseline {
+    hash: u64,
+    bytes: Vec<u8>,
+}
+
+fn run(
+    memory: &Weak<Memory>,
+    pages: &[Range<usize>],
+    mut dumper: Option<Dumper>,
+    interval: Duration,
+    stop: &AtomicBool,
+    sender: &Sender<CodeChange>,
+) -> CodeWatchReport {
+    let mut baselines: Vec<Option<Baseline>> = pages.iter().map(|_| None).collect();
+    let mut report = CodeWatchReport::default();
+    let mut buffer = vec![0u8; BLOCK_SIZE];
+
+    while !stop.load(Ordering::SeqCst) {
+        let Some(memory) = upgrade(memory) else {
+            break;
+        };
+        let _priority = memory.apply_thread_priority();
+        for (page, baseline) in pages.iter().zip(&mut baselines) {
+            let bytes = &mut buffer[..page.len()];
+            // A page that can't be read this tick keeps its baseline and is never reported.
+            if memory.read_bulk(page.start, bytes).ok() != Some(page.len()) {
+                continue;
+            }
+            let mut hasher = Fnv1a::new();
+            hasher.update(bytes);
+            let hash = hasher.finish();
+
+            match baseline {
+                Some(previous) if previous.hash != hash => {
+                    let (diff, changed_bytes) = diff_bytes(page.start, &previous.bytes, bytes, MAX_DIFF_BYTES);
+                    let dump = dumper.as_mut().and_then(|dumper| dumper.dump(page, bytes));
+                    *report.pages.entry(page.start).or_default() += 1;
+                    let _ = sender.send(CodeChange { page: page.clone(), tick: report.ticks, diff, changed_bytes, dump });
+                    previous.hash = hash;
+                    previous.bytes.copy_from_slice(bytes);
+                }
+                Some(_) => {}
+                None => *baseline = Some(Baseline { hash, bytes: bytes.to_vec() }),
+            }
+        }
+
+        report.ticks += 1;
+        drop(memory);
+        thread::park_timeout(interval);
+    }
+
+    debug!("code watch stopped after {} ticks, {} pages changed", report.ticks, report.pages.len());
+    report
+}
+
+// The first `limit` differing bytes and how many differ in all.
+pub(crate) fn diff_bytes(address: usize, old: &[u8], new: &[u8], limit: usize) -> (Vec<ByteChange>, usize) {
+    let mut changed = old
+        .iter()
+        .zip(new)
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(offset, (&old, &new))| ByteChange { address: address + offset, old, new });
+    let diff: Vec<ByteChange> = changed.by_ref().take(limit).collect();
+    let changed_bytes = diff.len() + changed.count();
+    (diff, changed_bytes)
+}
+
@@ -0,0 +1,536 @@
+// Several copies of the tool on one target. Instances that don't know about each other undo
+// each other's work: one reverts what the other patched, or both freeze a value to different
+// numbers. Coordination is opt-in. Each instance joins a registry for the target, a JSON
+// file in a directory they all use, and records there the patches, hooks and freezes it owns:
+//
+//     let coordinator = Coordinator::join(&memory, &registry_dir)?;
+//     if coordinator.peers()?.iter().any(|peer| peer.alive) {
+//         coordinator.set_read_only(true)?;
+//     }
+//     memory.set_coordinator(Some(coordinator));
+//     let adoption = memory.adopt_orphans()?;
+//     let freezer = Freezer::start(&memory, adoption.freezes, interval)?;
+//
+// With a coordinator set, `apply_patch`, `install_hook` and `Freezer::start` claim what they
+// are about to write first. A claim overlapping an artifact of this instance or of a live
+// peer fails with an `ArtifactConflict` payload (`artifact_conflict`); in read-only mode
+// every claim fails.
+// Reverting the patch, removing the hook or stopping the freezer gives the claim up. An
+// instance that goes away cleanly takes its entry with it, unless it still owns artifacts:
+// then the entry stays, marked departed, and they are orphans like a dead instance's.
+//
+// The registry is rewritten whole (see atomic_file.rs) under a named mutex, so an instance
+// that dies mid-update leaves the previous file and an abandoned mutex, which the next one to
+// wait on it gets. An instance that dies without leaving is found out by its pid no longer
+// running (in this process: by no `Coordinator` with its id being alive). Its artifacts, and
+// a departed instance's, are orphans, still in the target, with the bytes they replaced in
+// the registry.
+// `adopt_orphans` takes over the patches and hooks that are still in place, so they revert
+// as if applied here, drops those that aren't, and hands back the orphaned freezes, whose
+// thread died with their owner, for restarting. An adopted hook reverts like a patch: where
+// its trampoline was isn't recorded, so that stays allocated. A registry for another program
+// that was given the same pid is ignored.
+
+use std::collections::HashSet;
+use std::ffi::c_void;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::freeze::FrozenValue;
+use crate::memory::Memory;
+use crate::persist::{self, Format, TargetIdentity};
+use crate::sys::{
+    CloseHandle, CreateMutexW, GetCurrentProcessId, GetExitCodeProcess, OpenProcess, ReleaseMutex, WaitForSingleObject,
+    PROCESS_QUERY_LIMITED_INFORMATION, WAIT_ABANDONED, WAIT_OBJECT_0, WAIT_TIMEOUT,
+};
+use crate::win_error::win_call;
+use crate::wstr::to_wide_nul;
+
+// The exit code of a process still running, and the error OpenProcess gives for one we may
+// not open; windows-sys has them as an NTSTATUS and a u32.
+const STILL_ACTIVE: u32 = 259;
+const ERROR_ACCESS_DENIED: i32 = 5;
+
+// How long an update waits for another instance to finish its own.
+const LOCK_TIMEOUT_MS: u32 = 5000;
+
+const FORMAT: Format = Format { id: "coordination", version: 1, legacy: None };
+
+// Ids of the coordinators alive in this process, for telling which entries with our pid are
+// orphans.
+static LIVE: Mutex<Option<HashSet<InstanceId>>> = Mutex::new(None);
+static NEXT_NONCE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ArtifactKind {
+    Patch,
+    Hook,
+    Freeze,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Artifact {
+    pub kind: ArtifactKind,
+    pub address: usize,
+    // What the artifact puts at `address`.
+    pub bytes: Vec<u8>,
+    // What it replaced, for reverting it; empty for freezes.
+    pub original: Vec<u8>,
+    // The freeze's label; empty for the others.
+    pub label: String,
+}
+
+impl Artifact {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn overlaps(&self, other: &Artifact) -> bool {
+        self.address < other.address + other.len() && other.address < self.address + self.len()
+    }
+}
+
+impl fmt::Display for Artifact {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} of 0x{:X} bytes at 0x{:X}", self.kind, self.len(), self.address)?;
+        if !self.label.is_empty() {
+            write!(f, " ({})", self.label)?;
+        }
+        Ok(())
+    }
+}
+
+// The process an instance runs in, and which instance in it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct InstanceId {
+    pub pid: u32,
+    pub nonce: u64,
+}
+
+impl InstanceId {
+    fn new() -> Self {
+        let millis = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis() as u64);
+        let nonce = millis << 16 | NEXT_NONCE.fetch_add(1, Ordering::Relaxed) & 0xFFFF;
+        InstanceId { pid: unsafe { GetCurrentProcessId() }, nonce }
+    }
+
+    // Whether the instance may still be running. A pid that can't be opened for lack of rights
+    // belongs to a running process; one reused by an unrelated process looks alive until that
+    // exits, which at worst leaves orphans unadopted.
+    pub fn is_alive(&self) -> bool {
+        if self.pid == unsafe { GetCurrentProcessId() } {
+            return LIVE.lock().unwrap().as_ref().is_some_and(|live| live.contains(self));
+        }
+        let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, self.pid) };
+        if process.is_null() {
+            return io::Error::last_os_error().raw_os_error() == Some(ERROR_ACCESS_DENIED);
+        }
+        let mut code = 0;
+        let queried = unsafe { GetExitCodeProcess(process, &mut code) } != 0;
+        unsafe { CloseHandle(process) };
+        !queried || code == STILL_ACTIVE
+    }
+}
+
+impl fmt::Display for InstanceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{:X}", self.pid, self.nonce)
+    }
+}
+
+// Another instance in the registry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub id: InstanceId,
+    pub read_only: bool,
+    pub alive: bool,
+    pub artifacts: Vec<Artifact>,
+}
+
+// Payload of the `io::Error` for a claim that overlaps an artifact already owned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactConflict {
+    pub claimed: Artifact,
+    pub owner: InstanceId,
+    pub owned: Artifact,
+}
+
+impl fmt::Display for ArtifactConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} overlaps the {} owned by instance {}", self.claimed, self.owned, self.owner)
+    }
+}
+
+impl std::error::Error for ArtifactConflict {}
+
+pub fn artifact_conflict(e: &io::Error) -> Option<&ArtifactConflict> {
+    e.get_ref()?.downcast_ref::<ArtifactConflict>()
+}
+
+// What `adopt_orphans` found.
+#[derive(Debug, Clone, Default)]
+pub struct Adoption {
+    // Patches and hooks now owned here, and in the patch list.
+    pub adopted: Vec<Artifact>,
+    // Frozen values nobody holds any more, ready for `Freezer::start`.
+    pub freezes: Vec<FrozenValue>,
+    // Patches and hooks whose bytes are no longer there, and so were dropped.
+    pub gone: Vec<Artifact>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Registry {
+    instances: Vec<Entry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    id: InstanceId,
+    read_only: bool,
+    artifacts: Vec<Artifact>,
+    // The instance left while it still owned artifacts.
+    #[serde(default)]
+    departed: bool,
+}
+
+impl Entry {
+    fn is_alive(&self) -> bool {
+        !self.departed && self.id.is_alive()
+    }
+}
+
+pub struct Coordinator {
+    id: InstanceId,
+    target: TargetIdentity,
+    path: PathBuf,
+    mutex: *mut c_void,
+    read_only: AtomicBool,
+}
+
+// The mutex handle is only waited on, released and closed, all fine from any thread. Windows
+// tracks mutex ownership per thread, so each lock is released on the thread that took it.
+unsafe impl Send for Coordinator {}
+unsafe impl Sync for Coordinator {}
+
+impl Coordinator {
+    // Joins the registry in `dir` for `memory`'s target, creating it if it is the first, and
+    // logs who else is there.
+    pub fn join(memory: &Memory, dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let pid = memory.process_id();
+        let name = to_wide_nul(&format!("Local\\nirvana-notject-coordination-{}", pid));
+        let mutex = win_call!(
+            unsafe { CreateMutexW(ptr::null(), 0, name.as_ptr()) },
+            "Failed to create the lock for process {}'s registry",
+            pid
+        )?;
+        let coordinator = Coordinator {
+            id: InstanceId::new(),
+            target: TargetIdentity::of(memory)?,
+            path: dir.join(format!("coordination-{}.json", pid)),
+            mutex,
+            read_only: AtomicBool::new(false),
+        };
+        LIVE.lock().unwrap().get_or_insert_with(HashSet::new).insert(coordinator.id);
+
+        let peers = coordinator.update(|registry| {
+            registry.instances.push(Entry {
+                id: coordinator.id,
+                read_only: false,
+                artifacts: Vec::new(),
+                departed: false,
+            });
+            peers_of(registry, coordinator.id)
+        })?;
+        for peer in &peers {
+            let state = if peer.alive { "running" } else { "gone, leaving orphans" };
+            warn!("instance {} ({}) owns {} artifacts in process {}", peer.id, state, peer.artifacts.len(), pid);
+        }
+        info!("joined {} as instance {}", coordinator.path.display(), coordinator.id);
+        Ok(coordinator)
+    }
+
+    pub fn id(&self) -> InstanceId {
+        self.id
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only.load(Ordering::SeqCst)
+    }
+
+    // Read-only instances claim nothing, and say so in the registry for the others.
+    pub fn set_read_only(&self, read_only: bool) -> io::Result<()> {
+        self.read_only.store(read_only, Ordering::SeqCst);
+        self.update(|registry| {
+            if let Some(entry) = own_entry(registry, self.id) {
+                entry.read_only = read_only;
+            }
+        })
+    }
+
+    // Everyone else in the registry, orphans included.
+    pub fn peers(&self) -> io::Result<Vec<Peer>> {
+        self.read(|registry| peers_of(registry, self.id))
+    }
+
+    pub fn artifacts(&self) -> io::Result<Vec<Artifact>> {
+        self.read(|registry| {
+            let entry = registry.instances.iter().find(|entry| entry.id == self.id);
+            entry.map(|entry| entry.artifacts.clone()).unwrap_or_default()
+        })
+    }
+
+    // Records `artifact` as ours, unless it overlaps one we own already or one of a live peer.
+    // Orphans don't stand in the way; what they left is overwritten like any other bytes.
+    pub fn claim(&self, artifact: Artifact) -> io::Result<()> {
+        if self.is_read_only() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Instance {} is read-only and can't claim the {}", self.id, artifact),
+            ));
+        }
+        let conflict = self.update(|registry| {
+            let conflict = registry
+                .instances
+                .iter()
+                .filter(|entry| entry.id == self.id || entry.is_alive())
+                .find_map(|entry| {
+                    let owned = entry.artifacts.iter().find(|owned| owned.overlaps(&artifact))?;
+                    Some(ArtifactConflict { claimed: artifact.clone(), owner: entry.id, owned: owned.clone() })
+                });
+            if conflict.is_none() {
+                if let Some(entry) = own_entry(registry, self.id) {
+                    entry.artifacts.push(artifact.clone());
+                }
+            }
+            conflict
+        })?;
+        match conflict {
+            Some(conflict) => Err(io::Error::new(io::ErrorKind::AlreadyExists, conflict)),
+            None => Ok(()),
+        }
+    }
+
+    // Gives up our artifact at `address`; false if there was none. Claims don't overlap, so
+    // there is at most one.
+    pub fn release(&self, address: usize) -> io::Result<bool> {
+        self.update(|registry| {
+            let Some(entry) = own_entry(registry, self.id) else {
+                return false;
+            };
+            let before = entry.artifacts.len();
+            entry.artifacts.retain(|artifact| artifact.address != address);
+            entry.artifacts.len() != before
+        })
+    }
+
+    // Takes the registry's lock and lets `f` look at it.
+    fn read<R>(&self, f: impl FnOnce(&Registry) -> R) -> io::Result<R> {
+        let _lock = self.lock()?;
+        Ok(f(&self.load()))
+    }
+
+    // Takes the registry's lock, reads it, lets `f` change it and writes it back.
+    fn update<R>(&self, f: impl FnOnce(&mut Registry) -> R) -> io::Result<R> {
+        let _lock = self.lock()?;
+        let mut registry = self.load();
+        let result = f(&mut registry);
+        if registry.instances.is_empty() {
+            match fs::remove_file(&self.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        } else {
+            persist::write_json(&self.path, &FORMAT, Some(self.target.clone()), &registry)?;
+        }
+        Ok(result)
+    }
+
+    // A registry that can't be read, or belongs to another program, counts as empty.
+    fn load(&self) -> Registry {
+        if !self.path.exists() {
+            return Registry::default();
+        }
+        match persist::read_json::<Registry>(&self.path, &FORMAT) {
+            Ok((header, registry)) if header.target.as_ref() == Some(&self.target) => registry,
+            Ok((header, _)) => {
+                let other = header.target.map_or_else(|| "no target".to_string(), |target| target.to_string());
+                warn!("{} was written for {}, not {}; starting over", self.path.display(), other, self.target);
+                Registry::default()
+            }
+            Err(e) => {
+                warn!("{} can't be read ({}); starting over", self.path.display(), e);
+                Registry::default()
+            }
+        }
+    }
+
+    fn lock(&self) -> io::Result<RegistryLock> {
+        match unsafe { WaitForSingleObject(self.mutex, LOCK_TIMEOUT_MS) } {
+            WAIT_OBJECT_0 => {}
+            WAIT_ABANDONED => warn!("an instance died while updating {}; taking over its lock", self.path.display()),
+            WAIT_TIMEOUT => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Timed out waiting for another instance to finish updating {}", self.path.display()),
+                ))
+            }
+            _ => return Err(io::Error::last_os_error()),
+        }
+        Ok(RegistryLock(self.mutex))
+    }
+}
+
+impl Drop for Coordinator {
+    fn drop(&mut self) {
+        let left = self.update(|registry| {
+            registry.instances.retain_mut(|entry| {
+                if entry.id != self.id {
+                    return true;
+                }
+                entry.departed = true;
+                !entry.artifacts.is_empty()
+            })
+        });
+        if let Err(e) = left {
+            warn!("instance {} failed to leave {}: {}", self.id, self.path.display(), e);
+        }
+        if let Some(live) = LIVE.lock().unwrap().as_mut() {
+            live.remove(&self.id);
+        }
+        unsafe { CloseHandle(self.mutex) };
+    }
+}
+
+struct RegistryLock(*mut c_void);
+
+impl Drop for RegistryLock {
+    fn drop(&mut self) {
+        unsafe { ReleaseMutex(self.0) };
+    }
+}
+
+impl Memory {
+    // None (the default) turns coordination off again, leaving the registry.
+    pub fn set_coordinator(&mut self, coordinator: Option<Coordinator>) {
+        *self.coordinator_slot() = coordinator;
+    }
+
+    pub fn coordinator(&self) -> Option<&Coordinator> {
+        self.coordinator_setting().as_ref()
+    }
+
+    // Claims `artifact` with the coordinator, if one is set.
+    // `artifact` is only made when there is one to claim with, and its error fails the claim.
+    pub(crate) fn claim_artifact(&self, artifact: impl FnOnce() -> io::Result<Artifact>) -> io::Result<()> {
+        match self.coordinator() {
+            Some(coordinator) if !self.is_dry_run() => coordinator.claim(artifact()?),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn release_artifact(&self, address: usize) {
+        let Some(coordinator) = self.coordinator().filter(|_| !self.is_dry_run()) else {
+            return;
+        };
+        if let Err(e) = coordinator.release(address) {
+            warn!("failed to release the artifact at 0x{:X} in {}: {}", address, coordinator.path().display(), e);
+        }
+    }
+
+    // See above. Fails without a coordinator set, and claims nothing in read-only mode.
+    pub fn adopt_orphans(&self) -> io::Result<Adoption> {
+        let coordinator = self
+            .coordinator()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No coordinator is set"))?;
+        if coordinator.is_read_only() {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "A read-only instance can't adopt artifacts"));
+        }
+        coordinator.update(|registry| {
+            let mut adoption = Adoption::default();
+            let mut orphaned = Vec::new();
+            registry.instances.retain(|entry| {
+                let orphan = entry.id != coordinator.id && !entry.is_alive();
+                if orphan {
+                    info!("adopting what instance {} left: {} artifacts", entry.id, entry.artifacts.len());
+                    orphaned.extend(entry.artifacts.iter().cloned());
+                }
+                !orphan
+            });
+            for artifact in orphaned {
+                if artifact.kind == ArtifactKind::Freeze {
+                    adoption.freezes.push(FrozenValue::new(&artifact.label, artifact.address, artifact.bytes));
+                } else if self.read_bytes(artifact.address, artifact.len()).is_ok_and(|bytes| bytes == artifact.bytes)
+                    && self.adopt_patch(&artifact)
+                {
+                    adoption.adopted.push(artifact);
+                } else {
+                    adoption.gone.push(artifact);
+                }
+            }
+            if let Some(entry) = own_entry(registry, coordinator.id) {
+                entry.artifacts.extend(adoption.adopted.iter().cloned());
+            }
+            adoption
+        })
+    }
+
+    // Puts an orphaned patch or hook into the patch list; false if that can't be done.
+    #[cfg(feature = "patch")]
+    fn adopt_patch(&self, artifact: &Artifact) -> bool {
+        let mut patches = self.patch_list().lock().unwrap();
+        if patches.iter().any(|patch| patch.overlaps(artifact.address, artifact.len())) {
+            return false;
+        }
+        let region = self.query_address(artifact.address).ok().and_then(|info| info.to_region());
+        patches.push(crate::patch::Patch {
+            address: artifact.address,
+            original: artifact.original.clone(),
+            patched: artifact.bytes.clone(),
+            copy_on_write: region.as_ref().is_some_and(|region| region.is_copy_on_write),
+            protection: region.map_or(0, |region| region.protection),
+        });
+        true
+    }
+
+    #[cfg(not(feature = "patch"))]
+    fn adopt_patch(&self, _artifact: &Artifact) -> bool {
+        false
+    }
+}
+
+fn own_entry(registry: &mut Registry, id: InstanceId) -> Option<&mut Entry> {
+    registry.instances.iter_mut().find(|entry| entry.id == id)
+}
+
+fn peers_of(registry: &Registry, id: InstanceId) -> Vec<Peer> {
+    registry
+        .instances
+        .iter()
+        .filter(|entry| entry.id != id)
+        .map(|entry| Peer {
+            id: entry.id,
+            read_only: entry.read_only,
+            alive: entry.is_alive(),
+            artifacts: entry.artifacts.clone(),
+        })
+        .collect()
+}
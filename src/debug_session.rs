@@ -0,0 +1,289 @@
+// Attaching to the target as a debugger, for notifications the target pushes instead of
+// ones that have to be polled. A worker thread owns the attachment (Windows delivers debug
+// events only to the thread that attached) and forwards them on a channel:
+//
+//     let session = memory.attach_debugger()?;
+//     for event in session.events() {
+//         if let DebugEvent::Module(ModuleEvent::Loaded { path: Some(path), .. }) = event {
+//             println!("loaded {}", path);
+//         }
+//     }
+//
+// Right after attaching, Windows reports the exe and every module already loaded as loads.
+// Exceptions are passed on to the target unhandled, but a debugged process can tell
+// (IsDebuggerPresent and friends), so don't attach to targets that check. Dropping the
+// session detaches and leaves the process running.
+
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use log::{debug, warn};
+
+use crate::memory::Memory;
+use crate::sys::{
+    CloseHandle, ContinueDebugEvent, DebugActiveProcess, DebugActiveProcessStop, DebugSetProcessKillOnExit,
+    GetFinalPathNameByHandleW, ReadProcessMemory, WaitForDebugEvent, CREATE_PROCESS_DEBUG_EVENT, DBG_CONTINUE,
+    DBG_EXCEPTION_NOT_HANDLED, DEBUG_EVENT, EXCEPTION_BREAKPOINT, EXCEPTION_DEBUG_EVENT, EXIT_PROCESS_DEBUG_EVENT,
+    LOAD_DLL_DEBUG_EVENT, STATUS_WX86_BREAKPOINT, UNLOAD_DLL_DEBUG_EVENT,
+};
+use crate::win_error::win_call;
+use crate::wstr;
+
+// How long the worker blocks in WaitForDebugEvent before checking whether to detach.
+const WAIT_MILLISECONDS: u32 = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModuleEvent {
+    // `path` is None if neither the event nor the image's file handle gave one.
+    Loaded { base_address: usize, path: Option<String> },
+    Unloaded { base_address: usize },
+}
+
+impl ModuleEvent {
+    pub fn base_address(&self) -> usize {
+        match self {
+            ModuleEvent::Loaded { base_address, .. } | ModuleEvent::Unloaded { base_address } => *base_address,
+        }
+    }
+
+    // File name of a loaded module, for comparing with `ModuleInfo::name`.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            ModuleEvent::Loaded { path: Some(path), .. } => Path::new(path).file_name()?.to_str(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DebugEvent {
+    Module(ModuleEvent),
+    // The target exited; no events follow.
+    Exited { exit_code: u32 },
+}
+
+pub struct DebugSession {
+    process_id: u32,
+    events: Receiver<DebugEvent>,
+    stop: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl DebugSession {
+    pub fn attach(process_id: u32) -> io::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let (attached_sender, attached) = mpsc::sync_channel(1);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let worker_stop = stop.clone();
+        let worker = thread::Builder::new().name(format!("debug-{}", process_id)).spawn(move || {
+            let attached = win_call!(
+                unsafe { DebugActiveProcess(process_id) },
+                "Failed to attach a debugger to process {}",
+                process_id
+            );
+            if let Err(e) = attached {
+                let _ = attached_sender.send(Err(e));
+                return;
+            }
+            unsafe { DebugSetProcessKillOnExit(0) };
+            let mut attachment = Attachment { process_id, exited: false };
+            let _ = attached_sender.send(Ok(()));
+            debug_loop(&mut attachment, &sender, &worker_stop);
+        })?;
+
+        match attached.recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                let _ = worker.join();
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("Failed to attach to process {} as a debugger: {}", process_id, e),
+                ));
+            }
+            Err(_) => return Err(io::Error::other("The debug worker exited before attaching")),
+        }
+        debug!("attached to process {} as a debugger", process_id);
+
+        Ok(DebugSession { process_id, events, stop, worker: Some(worker) })
+    }
+
+    pub fn process_id(&self) -> u32 {
+        self.process_id
+    }
+
+    // Events in the order the target produced them. The channel closes after detaching or
+    // once the target has exited.
+    pub fn events(&self) -> &Receiver<DebugEvent> {
+        &self.events
+    }
+}
+
+impl Drop for DebugSession {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Memory {
+    // See `DebugSession`.
+    pub fn attach_debugger(&self) -> io::Result<DebugSession> {
+        DebugSession::attach(self.process_id())
+    }
+}
+
+// Detaches from the target when dropped, which the worker does however it ends, panics
+// included: a target left attached to a debugger that no longer continues its events hangs
+// at the next one. One that exited has nothing to detach from.
+struct Attachment {
+    process_id: u32,
+    exited: bool,
+}
+
+impl Drop for Attachment {
+    fn drop(&mut self) {
+        if self.exited {
+            return;
+        }
+        let process_id = self.process_id;
+        let detached =
+            win_call!(unsafe { DebugActiveProcessStop(process_id) }, "failed to detach from process {}", process_id);
+        match detached {
+            Ok(_) => debug!("detached from process {}", process_id),
+            Err(e) => warn!("{}", e),
+        }
+    }
+}
+
+fn debug_loop(attachment: &mut Attachment, sender: &Sender<DebugEvent>, stop: &AtomicBool) {
+    let process_id = attachment.process_id;
+    // From the create-process event; owned by the system, not closed here.
+    let mut process: *mut c_void = ptr::null_mut();
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut record: DEBUG_EVENT = unsafe { mem::zeroed() };
+        if unsafe { WaitForDebugEvent(&mut record, WAIT_MILLISECONDS) } == 0 {
+            continue;
+        }
+
+        // Each arm reads the member of the union that its event code says is filled in.
+        let mut status = DBG_CONTINUE;
+        let event = match record.dwDebugEventCode {
+            CREATE_PROCESS_DEBUG_EVENT => {
+                let info = unsafe { &record.u.CreateProcessInfo };
+                process = info.hProcess;
+                let path = image_path(process, info.lpImageName as usize, info.fUnicode, info.hFile);
+                close_file(info.hFile);
+                Some(DebugEvent::Module(ModuleEvent::Loaded { base_address: info.lpBaseOfImage as usize, path }))
+            }
+            LOAD_DLL_DEBUG_EVENT => {
+                let info = unsafe { &record.u.LoadDll };
+                let path = image_path(process, info.lpImageName as usize, info.fUnicode, info.hFile);
+                close_file(info.hFile);
+                Some(DebugEvent::Module(ModuleEvent::Loaded { base_address: info.lpBaseOfDll as usize, path }))
+            }
+            UNLOAD_DLL_DEBUG_EVENT => {
+                let base_address = unsafe { record.u.UnloadDll.lpBaseOfDll } as usize;
+                Some(DebugEvent::Module(ModuleEvent::Unloaded { base_address }))
+            }
+            EXIT_PROCESS_DEBUG_EVENT => {
+                Some(DebugEvent::Exited { exit_code: unsafe { record.u.ExitProcess.dwExitCode } })
+            }
+            EXCEPTION_DEBUG_EVENT => {
+                // The breakpoint raised when attaching is ours; anything else is the target's.
+                let code = unsafe { record.u.Exception.ExceptionRecord.ExceptionCode };
+                if code != EXCEPTION_BREAKPOINT && code != STATUS_WX86_BREAKPOINT {
+                    status = DBG_EXCEPTION_NOT_HANDLED;
+                }
+                None
+            }
+            _ => None,
+        };
+
+        let exited = matches!(event, Some(DebugEvent::Exited { .. }));
+        if let Some(event) = event {
+            // Nobody listening is fine; the session only detaches when dropped.
+            let _ = sender.send(event);
+        }
+        unsafe { ContinueDebugEvent(record.dwProcessId, record.dwThreadId, status) };
+        if exited {
+            debug!("process {} exited while being debugged", process_id);
+            attachment.exited = true;
+            return;
+        }
+    }
+}
+
+// The path from the event's `lpImageName` (a pointer, in the target, to the string), or
+// from the image's file handle when the event carries none.
+fn image_path(process: *mut c_void, image_name: usize, unicode: u16, file: *mut c_void) -> Option<String> {
+    read_image_name(process, image_name, unicode != 0).or_else(|| file_path(file))
+}
+
+fn read_image_name(process: *mut c_void, image_name: usize, unicode: bool) -> Option<String> {
+    if process.is_null() || image_name == 0 {
+        return None;
+    }
+    let name_address: usize = read_remote(process, image_name)?;
+    if name_address == 0 {
+        return None;
+    }
+
+    let mut buffer = [0u8; 520];
+    let mut read = 0;
+    unsafe {
+        ReadProcessMemory(process, name_address as *const c_void, buffer.as_mut_ptr() as *mut c_void, buffer.len(), &mut read)
+    };
+    let bytes = &buffer[..read];
+    let name = if unicode {
+        wstr::from_wide_nul(&wstr::units_from_bytes(bytes))
+    } else {
+        let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..len]).into_owned()
+    };
+    (!name.is_empty()).then_some(name)
+}
+
+fn read_remote(process: *mut c_void, address: usize) -> Option<usize> {
+    let mut value = 0usize;
+    let mut read = 0;
+    let ok = unsafe {
+        ReadProcessMemory(
+            process,
+            address as *const c_void,
+            &mut value as *mut usize as *mut c_void,
+            mem::size_of::<usize>(),
+            &mut read,
+        )
+    };
+    (ok != 0 && read == mem::size_of::<usize>()).then_some(value)
+}
+
+fn file_path(file: *mut c_void) -> Option<String> {
+    if file.is_null() {
+        return None;
+    }
+    let mut buffer = [0u16; 1024];
+    let len = unsafe { GetFinalPathNameByHandleW(file, buffer.as_mut_ptr(), buffer.len() as u32, 0) } as usize;
+    if len == 0 || len > buffer.len() {
+        return None;
+    }
+    let path = wstr::decode_lossy(&buffer[..len]);
+    Some(wstr::nt_to_dos_path(&path).unwrap_or(path))
+}
+
+fn close_file(file: *mut c_void) {
+    if !file.is_null() {
+        unsafe { CloseHandle(file) };
+    }
+}
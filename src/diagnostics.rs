@@ -0,0 +1,575 @@
+// Self-test of what the current system lets this tool do. Most bug reports about "nothing
+// works" come down to the environment: no SeDebugPrivilege, a security product blocking
+// cross-process access, or a 32/64-bit mismatch. `run` goes through the real code paths
+// against our own process and throwaway children and says which step fails and why:
+//
+//     for check in diagnostics::run() {
+//         println!("{}", check);
+//     }
+//
+// `required_access_for` and `preflight` let library users check one capability against a
+// given target before relying on it. When a target can't be opened at all, `why_cant_open`
+// says why, most likely cause first, and what access does still work:
+//
+//     let diagnosis = diagnostics::why_cant_open(pid);
+//     println!("{}", diagnosis); // "process 812 is a PPL (Antimalware) protected process: ..."
+
+use std::ffi::c_void;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::ptr;
+#[cfg(feature = "inject")]
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::memory::{Memory, PAGE_EXECUTE_READWRITE};
+use crate::process::MainThread;
+use crate::process_tree::ProcessTree;
+use crate::sys::{
+    AdjustTokenPrivileges, CloseHandle, GetCurrentProcess, GetCurrentProcessId, GetLastError, GetTokenInformation,
+    LookupPrivilegeValueW, NtQueryInformationProcess, OpenProcess, OpenProcessToken, ProcessProtectionInformation,
+    TokenElevation, TokenPrivileges, ERROR_NOT_ALL_ASSIGNED, LUID, LUID_AND_ATTRIBUTES, PROCESS_ALL_ACCESS,
+    PROCESS_CREATE_THREAD, PROCESS_DUP_HANDLE, PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION,
+    PROCESS_TERMINATE, PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE, SE_PRIVILEGE_ENABLED, SYNCHRONIZE,
+    TOKEN_ADJUST_PRIVILEGES, TOKEN_PRIVILEGES, TOKEN_QUERY,
+};
+use crate::win_error::win_call;
+
+// The access masks `why_cant_open` tries, most useful first. Protected processes grant the
+// last two to anyone.
+const ACCESS_LADDER: [(u32, &str); 5] = [
+    (PROCESS_VM_READ | PROCESS_VM_WRITE | PROCESS_VM_OPERATION | PROCESS_QUERY_INFORMATION, "read and write"),
+    (PROCESS_VM_READ | PROCESS_QUERY_INFORMATION, "read"),
+    (PROCESS_VM_READ | PROCESS_QUERY_LIMITED_INFORMATION, "read without full query rights"),
+    (PROCESS_QUERY_LIMITED_INFORMATION, "limited query"),
+    (SYNCHRONIZE, "wait for exit"),
+];
+
+// How long the test thread in a child may take.
+#[cfg(feature = "inject")]
+const EXECUTE_TIMEOUT: Duration = Duration::from_secs(5);
+// Exit code of the test thread: `mov eax, 0x2A; ret`.
+const STUB_EXIT_CODE: u32 = 0x2A;
+
+// What a caller wants to do with a process, for `required_access_for`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    // Reading memory and querying regions.
+    Read,
+    // Writing memory, including through read-only pages.
+    Write,
+    // Allocating, freeing and reprotecting memory.
+    Allocate,
+    // Running code on remote threads (remote calls, manual mapping).
+    Execute,
+    // Listing the process's handles.
+    Handles,
+    Terminate,
+}
+
+impl Capability {
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Read => "read",
+            Capability::Write => "write",
+            Capability::Allocate => "allocate",
+            Capability::Execute => "execute",
+            Capability::Handles => "handles",
+            Capability::Terminate => "terminate",
+        }
+    }
+}
+
+// The process access rights `capability` needs, as passed to OpenProcess.
+pub fn required_access_for(capability: Capability) -> u32 {
+    match capability {
+        Capability::Read => PROCESS_VM_READ | PROCESS_QUERY_INFORMATION,
+        Capability::Write => PROCESS_VM_WRITE | PROCESS_VM_OPERATION | PROCESS_QUERY_INFORMATION,
+        Capability::Allocate => PROCESS_VM_OPERATION | PROCESS_QUERY_INFORMATION,
+        Capability::Execute => {
+            PROCESS_CREATE_THREAD
+                | PROCESS_QUERY_INFORMATION
+                | PROCESS_VM_OPERATION
+                | PROCESS_VM_READ
+                | PROCESS_VM_WRITE
+        }
+        Capability::Handles => PROCESS_DUP_HANDLE | PROCESS_QUERY_INFORMATION,
+        Capability::Terminate => PROCESS_TERMINATE,
+    }
+}
+
+// Opens `process_id` with the rights `capability` needs and closes it again. Fails with
+// PermissionDenied if they are not granted.
+pub fn preflight(process_id: u32, capability: Capability) -> io::Result<()> {
+    let access = required_access_for(capability);
+    let handle = win_call!(
+        unsafe { OpenProcess(access, 0, process_id) },
+        "Cannot open process {} for {} (access 0x{:X})",
+        process_id,
+        capability.name(),
+        access
+    )
+    .map_err(|e| io::Error::new(io::ErrorKind::PermissionDenied, e.to_string()))?;
+    unsafe { CloseHandle(handle) };
+    Ok(())
+}
+
+// Enables SeDebugPrivilege in our token, which lets OpenProcess reach processes of other
+// users and services. Fails if the token doesn't hold it at all (as for non-elevated users).
+pub fn enable_debug_privilege() -> io::Result<()> {
+    let mut token = ptr::null_mut();
+    win_call!(
+        unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_PRIVILEGES | TOKEN_QUERY, &mut token) },
+        "Failed to open our process token"
+    )?;
+
+    let result = debug_privilege_luid().and_then(|luid| {
+        let privileges = TOKEN_PRIVILEGES {
+            PrivilegeCount: 1,
+            Privileges: [LUID_AND_ATTRIBUTES { Luid: luid, Attributes: SE_PRIVILEGE_ENABLED }],
+        };
+        win_call!(
+            unsafe { AdjustTokenPrivileges(token, 0, &privileges, 0, ptr::null_mut(), ptr::null_mut()) },
+            "Failed to enable SeDebugPrivilege"
+        )?;
+        // AdjustTokenPrivileges succeeds without assigning anything the token doesn't hold,
+        // and says so in the last error; nothing may run between the two.
+        if unsafe { GetLastError() } == ERROR_NOT_ALL_ASSIGNED {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "SeDebugPrivilege is not held by this user; run elevated",
+            ));
+        }
+        Ok(())
+    });
+    unsafe { CloseHandle(token) };
+    result
+}
+
+fn debug_privilege_luid() -> io::Result<LUID> {
+    let name: Vec<u16> = "SeDebugPrivilege".encode_utf16().chain(Some(0)).collect();
+    let mut luid = LUID { LowPart: 0, HighPart: 0 };
+    win_call!(
+        unsafe { LookupPrivilegeValueW(ptr::null(), name.as_ptr(), &mut luid) },
+        "Failed to look up SeDebugPrivilege"
+    )?;
+    Ok(luid)
+}
+
+// The protection level of a protected process, from its PS_PROTECTION byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtectionLevel {
+    // Protected Process Light, the level anti-malware services and LSA run at; a full
+    // protected process is out of reach even of PPLs.
+    pub light: bool,
+    // The signer the image had to be signed by, which also ranks protected processes.
+    pub signer: u8,
+}
+
+impl ProtectionLevel {
+    // None for an unprotected process.
+    fn from_raw(raw: u8) -> Option<Self> {
+        match raw & 0x7 {
+            0 => None,
+            kind => Some(ProtectionLevel { light: kind == 1, signer: raw >> 4 }),
+        }
+    }
+
+    pub fn signer_name(&self) -> &'static str {
+        match self.signer {
+            1 => "Authenticode",
+            2 => "CodeGen",
+            3 => "Antimalware",
+            4 => "Lsa",
+            5 => "Windows",
+            6 => "WinTcb",
+            7 => "WinSystem",
+            8 => "App",
+            _ => "unknown signer",
+        }
+    }
+}
+
+// "PPL (Antimalware)" or "PP (WinTcb)".
+impl fmt::Display for ProtectionLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", if self.light { "PPL" } else { "PP" }, self.signer_name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugPrivilege {
+    // Not in our token: the user isn't an administrator, or the tool isn't elevated.
+    NotHeld,
+    // In the token but off; `enable_debug_privilege` turns it on.
+    Disabled,
+    Enabled,
+}
+
+// Why OpenProcess fails for a process, from `why_cant_open`. Checks that couldn't be made
+// are None.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenDiagnosis {
+    pub process_id: u32,
+    pub exists: bool,
+    // None for unprotected processes, and when the level couldn't be read.
+    pub protection: Option<ProtectionLevel>,
+    // Whether our own token is elevated.
+    pub elevated: Option<bool>,
+    pub debug_privilege: Option<DebugPrivilege>,
+    // Whether PROCESS_QUERY_LIMITED_INFORMATION is granted. Refusing even that is what
+    // security products filtering handle access tend to do.
+    pub limited_open: bool,
+    // The most useful mask from the ones tried that opens the process, with what it allows.
+    pub best_access: Option<(u32, &'static str)>,
+}
+
+impl OpenDiagnosis {
+    // The likeliest reason, in the order the checks are made: the first that explains the
+    // failure wins.
+    pub fn explanation(&self) -> String {
+        let pid = self.process_id;
+        if !self.exists {
+            return format!("no process {} is running", pid);
+        }
+        if let Some(level) = self.protection {
+            return format!(
+                "process {} is a {} protected process; Windows refuses memory access to it even with SeDebugPrivilege",
+                pid, level
+            );
+        }
+        if self.elevated == Some(false) {
+            return format!(
+                "this tool isn't elevated, and process {} may belong to another user, a service or an elevated program",
+                pid
+            );
+        }
+        match self.debug_privilege {
+            Some(DebugPrivilege::NotHeld) => return "SeDebugPrivilege is not held by this token".to_string(),
+            Some(DebugPrivilege::Disabled) => {
+                return "SeDebugPrivilege is held but disabled; `enable_debug_privilege` turns it on".to_string()
+            }
+            _ => {}
+        }
+        if !self.limited_open {
+            format!("even limited query access to process {} is refused, which points at a security product", pid)
+        } else {
+            format!(
+                "process {} grants limited access only; an anti-cheat or security product may be stripping rights",
+                pid
+            )
+        }
+    }
+}
+
+// The explanation, then the access that does work, if any.
+impl fmt::Display for OpenDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.explanation())?;
+        match self.best_access {
+            Some((access, what)) => write!(f, " (access 0x{:X}, {}, still works)", access, what),
+            None if self.exists => f.write_str(" (no access at all is granted)"),
+            None => Ok(()),
+        }
+    }
+}
+
+// Looks into why `process_id` can't be opened: whether it exists, is a protected process,
+// whether our token is elevated and has SeDebugPrivilege, and which of a ladder of access
+// masks, down to PROCESS_QUERY_LIMITED_INFORMATION, OpenProcess still grants.
+pub fn why_cant_open(process_id: u32) -> OpenDiagnosis {
+    // If the snapshot fails, assume it exists; the remaining checks still say something.
+    let exists = ProcessTree::snapshot().map(|tree| tree.get(process_id).is_some()).unwrap_or(true);
+    let mut diagnosis = OpenDiagnosis {
+        process_id,
+        exists,
+        protection: None,
+        elevated: token_elevated().map_err(|e| debug!("elevation unknown: {}", e)).ok(),
+        debug_privilege: debug_privilege_state().map_err(|e| debug!("SeDebugPrivilege unknown: {}", e)).ok(),
+        limited_open: false,
+        best_access: None,
+    };
+    if !exists {
+        return diagnosis;
+    }
+
+    let limited = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, process_id) };
+    if !limited.is_null() {
+        diagnosis.limited_open = true;
+        diagnosis.protection = protection_level(limited);
+        unsafe { CloseHandle(limited) };
+    }
+    diagnosis.best_access = ACCESS_LADDER.into_iter().find(|&(access, _)| {
+        let handle = unsafe { OpenProcess(access, 0, process_id) };
+        !handle.is_null() && unsafe { CloseHandle(handle) } != 0
+    });
+    diagnosis
+}
+
+fn protection_level(process: *mut c_void) -> Option<ProtectionLevel> {
+    let mut raw = 0u8;
+    let buffer = &mut raw as *mut u8 as *mut c_void;
+    let status =
+        unsafe { NtQueryInformationProcess(process, ProcessProtectionInformation, buffer, 1, ptr::null_mut()) };
+    if status < 0 {
+        debug!("protection level unknown: NTSTATUS 0x{:08X}", status as u32);
+        return None;
+    }
+    ProtectionLevel::from_raw(raw)
+}
+
+// GetTokenInformation for our own process token, into a buffer of u32s.
+fn token_information(class: i32) -> io::Result<Vec<u32>> {
+    let mut token = ptr::null_mut();
+    win_call!(
+        unsafe { OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) },
+        "Failed to open our process token"
+    )?;
+    // Room for a few hundred privileges; tokens have around 35 at most.
+    let mut buffer = vec![0u32; 1024];
+    let (data, size, mut needed) = (buffer.as_mut_ptr() as *mut c_void, (buffer.len() * 4) as u32, 0);
+    let result = win_call!(
+        unsafe { GetTokenInformation(token, class, data, size, &mut needed) },
+        "Failed to query token information class {}",
+        class
+    );
+    unsafe { CloseHandle(token) };
+    result.map(|_| buffer)
+}
+
+fn token_elevated() -> io::Result<bool> {
+    // TOKEN_ELEVATION is a single DWORD.
+    Ok(token_information(TokenElevation)?[0] != 0)
+}
+
+fn debug_privilege_state() -> io::Result<DebugPrivilege> {
+    let luid = debug_privilege_luid()?;
+    // TOKEN_PRIVILEGES: a count, then LUID_AND_ATTRIBUTES entries of three DWORDs each.
+    let privileges = token_information(TokenPrivileges)?;
+    let count = privileges[0] as usize;
+    let state = privileges[1..]
+        .chunks_exact(3)
+        .take(count)
+        .find(|entry| entry[0] == luid.LowPart && entry[1] as i32 == luid.HighPart)
+        .map_or(DebugPrivilege::NotHeld, |entry| match entry[2] & SE_PRIVILEGE_ENABLED {
+            0 => DebugPrivilege::Disabled,
+            _ => DebugPrivilege::Enabled,
+        });
+    Ok(state)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckOutcome {
+    Pass,
+    Fail,
+    // Not applicable here, or an earlier check it depends on failed.
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: CheckOutcome,
+    // The underlying error for failures, the reason for skips, details for passes.
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        CheckResult { name, outcome: CheckOutcome::Pass, detail: detail.into() }
+    }
+
+    fn fail(name: &'static str, error: impl fmt::Display) -> Self {
+        CheckResult { name, outcome: CheckOutcome::Fail, detail: error.to_string() }
+    }
+
+    fn skipped(name: &'static str, reason: impl Into<String>) -> Self {
+        CheckResult { name, outcome: CheckOutcome::Skipped, detail: reason.into() }
+    }
+
+    fn from_result<T>(name: &'static str, result: io::Result<T>, detail: impl FnOnce(T) -> String) -> Self {
+        match result {
+            Ok(value) => Self::pass(name, detail(value)),
+            Err(e) => Self::fail(name, e),
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.outcome == CheckOutcome::Pass
+    }
+}
+
+// "[pass] open own process with full access", with the detail after a colon if there is one.
+impl fmt::Display for CheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tag = match self.outcome {
+            CheckOutcome::Pass => "pass",
+            CheckOutcome::Fail => "FAIL",
+            CheckOutcome::Skipped => "skip",
+        };
+        write!(f, "[{}] {}", tag, self.name)?;
+        if !self.detail.is_empty() {
+            write!(f, ": {}", self.detail)?;
+        }
+        Ok(())
+    }
+}
+
+// Runs every check in order. Children are started suspended from the system's cmd.exe and
+// killed before this returns; none of their own code runs.
+pub fn run() -> Vec<CheckResult> {
+    let mut results = vec![
+        CheckResult::from_result("open own process with full access", open_self(), |_| String::new()),
+        CheckResult::from_result("enable SeDebugPrivilege", enable_debug_privilege(), |_| String::new()),
+    ];
+
+    let native = system_directory("System32").join("cmd.exe");
+    match Memory::spawn_suspended(&format!("\"{}\"", native.display())) {
+        Ok((child, main_thread)) => {
+            results.push(CheckResult::pass("spawn and attach to a child", format!("process {}", child.process_id())));
+            results.extend(exercise_child(&child));
+            dispose(&child, main_thread);
+        }
+        Err(e) => {
+            results.push(CheckResult::fail("spawn and attach to a child", e));
+            for name in ["allocate in the child", "write and read back", "execute in the child"] {
+                results.push(CheckResult::skipped(name, "no child process"));
+            }
+        }
+    }
+
+    results.push(check_32_bit_modules());
+    results
+}
+
+fn open_self() -> io::Result<()> {
+    let pid = unsafe { GetCurrentProcessId() };
+    let handle = win_call!(unsafe { OpenProcess(PROCESS_ALL_ACCESS, 0, pid) }, "Failed to open our own process")?;
+    unsafe { CloseHandle(handle) };
+    Memory::new().map(|_| ())
+}
+
+fn exercise_child(child: &Memory) -> Vec<CheckResult> {
+    let mut results = Vec::new();
+    let address = match child.allocate(0x1000, PAGE_EXECUTE_READWRITE) {
+        Ok(address) => {
+            results.push(CheckResult::pass("allocate in the child", format!("0x{:X}", address)));
+            address
+        }
+        Err(e) => {
+            results.push(CheckResult::fail("allocate in the child", e));
+            results.push(CheckResult::skipped("write and read back", "nothing allocated"));
+            results.push(CheckResult::skipped("execute in the child", "nothing allocated"));
+            return results;
+        }
+    };
+
+    let stub = thread_stub(child.pointer_size().unwrap_or(8));
+    let written = child.write_bytes(address, &stub).and_then(|_| child.read_bytes(address, stub.len()));
+    let round_trip = match written {
+        Ok(bytes) if bytes == stub => Ok(()),
+        Ok(_) => Err(io::Error::new(io::ErrorKind::InvalidData, "Read back different bytes than were written")),
+        Err(e) => Err(e),
+    };
+    let executable = round_trip.is_ok();
+    results.push(CheckResult::from_result("write and read back", round_trip, |_| String::new()));
+
+    if !executable {
+        results.push(CheckResult::skipped("execute in the child", "the test code could not be written"));
+        let _ = child.free(address);
+        return results;
+    }
+
+    results.push(execute_in_child(child, address));
+    results
+}
+
+#[cfg(feature = "inject")]
+fn execute_in_child(child: &Memory, address: usize) -> CheckResult {
+    let exit_code = child.run_remote_thread(address, 0, EXECUTE_TIMEOUT);
+    // A thread that timed out may still be using the stub.
+    if !matches!(&exit_code, Err(e) if e.kind() == io::ErrorKind::TimedOut) {
+        let _ = child.free(address);
+    }
+    let checked = exit_code.and_then(|exit_code| match exit_code {
+        STUB_EXIT_CODE => Ok(()),
+        other => Err(io::Error::other(format!("The test thread exited with {} instead of {}", other, STUB_EXIT_CODE))),
+    });
+    CheckResult::from_result("execute in the child", checked, |_| String::new())
+}
+
+#[cfg(not(feature = "inject"))]
+fn execute_in_child(child: &Memory, address: usize) -> CheckResult {
+    let _ = child.free(address);
+    CheckResult::skipped("execute in the child", "built without the `inject` feature")
+}
+
+// Module enumeration across bitness, which fails if this build and the toolhelp snapshot
+// disagree about the target's layout.
+fn check_32_bit_modules() -> CheckResult {
+    const NAME: &str = "enumerate modules of a 32-bit process";
+    if cfg!(not(target_pointer_width = "64")) {
+        return CheckResult::skipped(NAME, "this is a 32-bit build");
+    }
+    let wow64 = system_directory("SysWOW64").join("cmd.exe");
+    if !wow64.exists() {
+        return CheckResult::skipped(NAME, format!("{} does not exist", wow64.display()));
+    }
+    if cfg!(not(feature = "inject")) {
+        return CheckResult::skipped(NAME, "built without the `inject` feature");
+    }
+
+    let (mut child, main_thread) = match Memory::spawn_suspended(&format!("\"{}\"", wow64.display())) {
+        Ok(spawned) => spawned,
+        Err(e) => return CheckResult::fail(NAME, e),
+    };
+    // The loader hasn't run in a suspended process; a no-op thread makes it initialize the
+    // module lists.
+    let result =
+        initialize_loader(&child).and_then(|_| child.scan_modules()).and_then(|_| match child.get_modules().len() {
+            0 => Err(io::Error::other("The module list is empty")),
+            count => Ok(count),
+        });
+    dispose(&child, main_thread);
+    CheckResult::from_result(NAME, result, |count| format!("{} modules", count))
+}
+
+#[cfg(feature = "inject")]
+fn initialize_loader(child: &Memory) -> io::Result<()> {
+    let address = child.allocate(0x1000, PAGE_EXECUTE_READWRITE)?;
+    child.write_bytes(address, &thread_stub(4))?;
+    child.run_remote_thread(address, 0, EXECUTE_TIMEOUT).map(|_| ())
+}
+
+#[cfg(not(feature = "inject"))]
+fn initialize_loader(_child: &Memory) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Built without the `inject` feature"))
+}
+
+// Kills a child started for a check. Its main thread never ran.
+fn dispose(child: &Memory, main_thread: MainThread) {
+    if let Err(e) = child.terminate(1) {
+        warn!("failed to end diagnostic child {}: {}", child.process_id(), e);
+    }
+    // Resuming the thread of the dead process only keeps `MainThread` from warning.
+    let _ = main_thread.resume();
+}
+
+// Thread routine returning STUB_EXIT_CODE, for a target with `pointer_size`-byte pointers.
+fn thread_stub(pointer_size: usize) -> Vec<u8> {
+    let mut code = vec![0xB8]; // mov eax, imm32
+    code.extend_from_slice(&STUB_EXIT_CODE.to_le_bytes());
+    if pointer_size == 4 {
+        code.extend_from_slice(&[0xC2, 0x04, 0x00]); // ret 4 (the thread parameter)
+    } else {
+        code.push(0xC3); // ret
+    }
+    code
+}
+
+fn system_directory(name: &str) -> PathBuf {
+    let root = std::env::var_os("SystemRoot").unwrap_or_else(|| "C:\\Windows".into());
+    PathBuf::from(root).join(name)
+}
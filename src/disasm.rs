@@ -0,0 +1,65 @@
+use std::io;
+
+use iced_x86::{Decoder, DecoderError, DecoderOptions, Formatter, Instruction, IntelFormatter};
+
+use crate::memory::Memory;
+
+pub use crate::lde::MAX_INSTRUCTION_LEN;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Insn {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    // Intel syntax, e.g. "mov rax,[rip+1234h]". Undecodable bytes show up as "(bad)".
+    pub text: String,
+}
+
+impl Memory {
+    // Decodes up to `count` instructions starting at `address`, as 32- or 64-bit code depending
+    // on the target. If the code runs into unreadable memory the listing stops at the last
+    // instruction that was read completely.
+    pub fn disassemble(&self, address: usize, count: usize) -> io::Result<Vec<Insn>> {
+        let bitness = self.pointer_size()? as u32 * 8;
+
+        let mut bytes = vec![0u8; count.saturating_mul(MAX_INSTRUCTION_LEN)];
+        let read = self.read_into(address, &mut bytes)?;
+        if read == 0 && count > 0 {
+            return Err(io::Error::other(format!("Failed to read code at 0x{:X}", address)));
+        }
+        bytes.truncate(read);
+
+        Ok(decode(&bytes, address, bitness, count))
+    }
+}
+
+// Decodes `bytes` as if they were loaded at `address`. An instruction cut off by the end of
+// `bytes` is dropped rather than reported as invalid.
+pub fn decode(bytes: &[u8], address: usize, bitness: u32, count: usize) -> Vec<Insn> {
+    let mut decoder = Decoder::with_ip(bitness, bytes, address as u64, DecoderOptions::NONE);
+    let mut formatter = IntelFormatter::new();
+    let mut instruction = Instruction::default();
+    let mut listing = Vec::with_capacity(count);
+
+    while listing.len() < count && decoder.can_decode() {
+        decoder.decode_out(&mut instruction);
+        if decoder.last_error() == DecoderError::NoMoreBytes {
+            break;
+        }
+
+        let start = instruction.ip() as usize - address;
+        let mut text = String::new();
+        if instruction.is_invalid() {
+            text.push_str("(bad)");
+        } else {
+            formatter.format(&instruction, &mut text);
+        }
+
+        listing.push(Insn {
+            address: instruction.ip() as usize,
+            bytes: bytes[start..start + instruction.len()].to_vec(),
+            text,
+        });
+    }
+
+    listing
+}
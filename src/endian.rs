@@ -0,0 +1,207 @@
+// Byte-order-explicit typed reads and writes, for targets that keep big-endian data such as
+// network buffers or emulated consoles. `read_memory::<T>` remains the native-layout path.
+
+use std::io;
+
+use crate::memory::Memory;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+impl Endianness {
+    #[cfg(target_endian = "little")]
+    pub const NATIVE: Endianness = Endianness::Little;
+    #[cfg(target_endian = "big")]
+    pub const NATIVE: Endianness = Endianness::Big;
+}
+
+// Types that can be built from their fixed-size byte representation.
+pub trait FromBytes: Sized {
+    type Bytes: AsMut<[u8]> + Default;
+
+    fn from_le_bytes(bytes: Self::Bytes) -> Self;
+    fn from_be_bytes(bytes: Self::Bytes) -> Self;
+
+    fn from_bytes(bytes: Self::Bytes, endianness: Endianness) -> Self {
+        match endianness {
+            Endianness::Little => Self::from_le_bytes(bytes),
+            Endianness::Big => Self::from_be_bytes(bytes),
+        }
+    }
+}
+
+pub trait ToBytes {
+    type Bytes: AsRef<[u8]>;
+
+    fn to_le_bytes(&self) -> Self::Bytes;
+    fn to_be_bytes(&self) -> Self::Bytes;
+
+    fn to_bytes(&self, endianness: Endianness) -> Self::Bytes {
+        match endianness {
+            Endianness::Little => self.to_le_bytes(),
+            Endianness::Big => self.to_be_bytes(),
+        }
+    }
+}
+
+// `usize` and `isize` are left out on purpose: their width depends on the target, not on us.
+macro_rules! impl_bytes {
+    ($($ty:ty),*) => {$(
+        impl FromBytes for $ty {
+            type Bytes = [u8; std::mem::size_of::<$ty>()];
+
+            fn from_le_bytes(bytes: Self::Bytes) -> Self {
+                <$ty>::from_le_bytes(bytes)
+            }
+
+            fn from_be_bytes(bytes: Self::Bytes) -> Self {
+                <$ty>::from_be_bytes(bytes)
+            }
+        }
+
+        impl ToBytes for $ty {
+            type Bytes = [u8; std::mem::size_of::<$ty>()];
+
+            fn to_le_bytes(&self) -> Self::Bytes {
+                <$ty>::to_le_bytes(*self)
+            }
+
+            fn to_be_bytes(&self) -> Self::Bytes {
+                <$ty>::to_be_bytes(*self)
+            }
+        }
+    )*};
+}
+
+impl_bytes!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64);
+
+macro_rules! typed_access {
+    ($($ty:ty => $read_le:ident, $read_be:ident, $write_le:ident, $write_be:ident;)*) => {
+        impl Memory {$(
+            pub fn $read_le(&self, address: usize) -> io::Result<$ty> {
+                self.read_value(address, Endianness::Little)
+            }
+
+            pub fn $read_be(&self, address: usize) -> io::Result<$ty> {
+                self.read_value(address, Endianness::Big)
+            }
+
+            pub fn $write_le(&self, address: usize, value: $ty) -> io::Result<()> {
+                self.write_value(address, value, Endianness::Little)
+            }
+
+            pub fn $write_be(&self, address: usize, value: $ty) -> io::Result<()> {
+                self.write_value(address, value, Endianness::Big)
+            }
+        )*}
+    };
+}
+
+typed_access! {
+    u16 => read_u16_le, read_u16_be, write_u16_le, write_u16_be;
+    u32 => read_u32_le, read_u32_be, write_u32_le, write_u32_be;
+    u64 => read_u64_le, read_u64_be, write_u64_le, write_u64_be;
+    i16 => read_i16_le, read_i16_be, write_i16_le, write_i16_be;
+    i32 => read_i32_le, read_i32_be, write_i32_le, write_i32_be;
+    i64 => read_i64_le, read_i64_be, write_i64_le, write_i64_be;
+    f32 => read_f32_le, read_f32_be, write_f32_le, write_f32_be;
+    f64 => read_f64_le, read_f64_be, write_f64_le, write_f64_be;
+}
+
+impl Memory {
+    // Unlike `read_memory`, fails unless every byte of the value could be read.
+    pub fn read_value<T: FromBytes>(&self, address: usize, endianness: Endianness) -> io::Result<T> {
+        let mut bytes = T::Bytes::default();
+        let buffer = bytes.as_mut();
+        let read = self.read_into(address, buffer)?;
+        if read < buffer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Only 0x{:X} of 0x{:X} bytes at 0x{:X} are readable", read, buffer.len(), address),
+            ));
+        }
+        Ok(T::from_bytes(bytes, endianness))
+    }
+
+    pub fn write_value<T: ToBytes>(&self, address: usize, value: T, endianness: Endianness) -> io::Result<()> {
+        self.write_bytes(address, value.to_bytes(endianness).as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::filter::RegionFilter;
+    use crate::scan::ScanOptions;
+
+    const BASE: usize = 0x10000;
+
+    fn page() -> (Arc<MockBackend>, Memory) {
+        MockBackend::new().data(BASE, vec![0u8; 0x1000]).open()
+    }
+
+    #[test]
+    fn writes_lay_bytes_out_in_the_order_asked_for() {
+        let (mock, memory) = page();
+        memory.write_u32_be(BASE, 0x1122_3344).unwrap();
+        memory.write_u32_le(BASE + 4, 0x1122_3344).unwrap();
+        memory.write_i16_be(BASE + 8, -2).unwrap();
+        memory.write_f32_be(BASE + 10, 1.0).unwrap();
+        assert_eq!(
+            mock.bytes(BASE, 14),
+            [0x11, 0x22, 0x33, 0x44, 0x44, 0x33, 0x22, 0x11, 0xFF, 0xFE, 0x3F, 0x80, 0, 0]
+        );
+    }
+
+    #[test]
+    fn values_round_trip_in_either_order() {
+        let (_, memory) = page();
+        memory.write_u16_be(BASE, 0xBEEF).unwrap();
+        memory.write_u64_le(BASE + 8, 0x0102_0304_0506_0708).unwrap();
+        memory.write_i32_be(BASE + 16, i32::MIN + 1).unwrap();
+        memory.write_i64_le(BASE + 24, -1234).unwrap();
+        memory.write_f64_be(BASE + 32, -0.5).unwrap();
+        memory.write_f32_le(BASE + 40, f32::MAX).unwrap();
+
+        assert_eq!(memory.read_u16_be(BASE).unwrap(), 0xBEEF);
+        assert_eq!(memory.read_u16_le(BASE).unwrap(), 0xEFBE);
+        assert_eq!(memory.read_u64_le(BASE + 8).unwrap(), 0x0102_0304_0506_0708);
+        assert_eq!(memory.read_u64_be(BASE + 8).unwrap(), 0x0807_0605_0403_0201);
+        assert_eq!(memory.read_i32_be(BASE + 16).unwrap(), i32::MIN + 1);
+        assert_eq!(memory.read_i64_le(BASE + 24).unwrap(), -1234);
+        assert_eq!(memory.read_f64_be(BASE + 32).unwrap(), -0.5);
+        assert_eq!(memory.read_f32_le(BASE + 40).unwrap(), f32::MAX);
+    }
+
+    #[test]
+    fn values_cut_off_by_the_mapping_fail() {
+        let (_, memory) = page();
+        let e = memory.read_u32_be(BASE + 0xFFE).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(e.to_string(), "Only 0x2 of 0x4 bytes at 0x10FFE are readable");
+        assert!(memory.read_u16_le(BASE + 0xFFE).is_ok());
+    }
+
+    #[test]
+    fn value_scans_search_in_the_order_asked_for() {
+        let (_, mut memory) = page();
+        memory.write_u32_be(BASE + 0x100, 0x1122_3344).unwrap();
+        memory.write_u32_le(BASE + 0x200, 0x1122_3344).unwrap();
+        memory.scan_memory().unwrap();
+
+        let filter = RegionFilter::new();
+        let scan = |endianness| {
+            let options = ScanOptions::default().endianness(endianness);
+            memory.scan_for_value_with(0x1122_3344u32, &filter, &options).unwrap()
+        };
+        assert_eq!(scan(Endianness::Big), [BASE + 0x100]);
+        assert_eq!(scan(Endianness::Little), [BASE + 0x200]);
+        assert_eq!(memory.scan_for_value(0x1122_3344u32, &filter).unwrap(), scan(Endianness::NATIVE));
+    }
+}
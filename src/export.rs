@@ -0,0 +1,347 @@
+use std::ffi::c_void;
+use std::io::{self, Write};
+
+use crate::memory::{Memory, MemoryRegion, RegionType};
+use crate::stats::format_size;
+use crate::sys::K32GetMappedFileNameW;
+use crate::wstr;
+
+const MAX_PATH: usize = 260;
+// The longest path the NT APIs handle, in characters.
+const MAX_MAPPED_PATH: usize = 32_767;
+
+// Target number of map characters; the scale is picked so the committed memory fills about this.
+const MAP_LINES: usize = 16;
+
+impl Memory {
+    // NT-style path (\Device\HarddiskVolume3\...) of the file mapped at `address`, if any.
+    pub fn mapped_file(&self, address: usize) -> Option<String> {
+        // Paths can be longer than MAX_PATH; a result that fills the buffer may be truncated.
+        let mut buffer = vec![0u16; MAX_PATH];
+        while buffer.len() <= MAX_MAPPED_PATH {
+            let len = unsafe {
+                K32GetMappedFileNameW(self.handle(), address as *const c_void, buffer.as_mut_ptr(), buffer.len() as u32)
+            } as usize;
+            if len == 0 {
+                return None;
+            }
+            if len < buffer.len() - 1 {
+                return Some(wstr::decode_lossy(&buffer[..len]));
+            }
+            buffer.resize(buffer.len() * 4, 0);
+        }
+        None
+    }
+
+    // `mapped_file` with the device translated to a drive letter (C:\Games\...), or the NT
+    // path if no drive maps it.
+    pub fn mapped_file_dos(&self, address: usize) -> Option<String> {
+        self.mapped_file(address).map(|path| wstr::nt_to_dos_path(&path).unwrap_or(path))
+    }
+
+    pub fn export_regions_csv(&self, writer: impl Write) -> io::Result<()> {
+        write_regions_csv(self.cached_regions(), |region| match region.region_type {
+            RegionType::Image | RegionType::Mapped => self.mapped_file_dos(region.start_address.get()),
+            _ => None,
+        }, writer)
+    }
+
+    // `fmt_table` with the file of each image and mapped view, as a DOS path where possible.
+    pub fn regions_table(&self) -> String {
+        format_table(self.cached_regions(), |region| match region.region_type {
+            RegionType::Image | RegionType::Mapped => self.mapped_file_dos(region.start_address.get()),
+            _ => None,
+        })
+    }
+
+    pub fn render_map(&self, width: usize) -> String {
+        render_map(self.cached_regions(), width)
+    }
+}
+
+pub fn write_regions_csv(
+    regions: &[MemoryRegion],
+    mapped_file: impl Fn(&MemoryRegion) -> Option<String>,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    writeln!(writer, "start,end,size,protection,type,mapped_file")?;
+    for region in regions {
+        writeln!(
+            writer,
+            "0x{:X},0x{:X},0x{:X},{},{},{}",
+            region.start_address,
+            region.end(),
+            region.size,
+            region.protection_string(),
+            region.region_type.name(),
+            csv_field(&mapped_file(region).unwrap_or_default())
+        )?;
+    }
+    writer.flush()
+}
+
+// One line per region, in aligned columns: start, end, protection, type tag and size.
+pub fn fmt_table(regions: &[MemoryRegion]) -> String {
+    format_table(regions, |_| None)
+}
+
+// `fmt_table` with a last column naming the file behind each region, where known. Only the
+// file name of the NT path is shown.
+pub fn format_table(regions: &[MemoryRegion], mapped_file: impl Fn(&MemoryRegion) -> Option<String>) -> String {
+    let rows: Vec<[String; 6]> = regions
+        .iter()
+        .map(|region| {
+            let file = mapped_file(region).unwrap_or_default();
+            let name = file.rsplit('\\').next().unwrap_or_default().to_string();
+            [
+                region.start_address.to_string(),
+                region.end().to_string(),
+                region.protection_string(),
+                region.region_type.tag().to_string(),
+                format_size(region.size.get()),
+                if region.is_guard { format!("{} [guard]", name).trim_start().to_string() } else { name },
+            ]
+        })
+        .collect();
+
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for [start, end, protection, tag, size, name] in &rows {
+        let line = format!(
+            "{:>w0$}-{:>w1$} {} {}  {:>w4$}  {}",
+            start,
+            end,
+            protection,
+            tag,
+            size,
+            name,
+            w0 = widths[0],
+            w1 = widths[1],
+            w4 = widths[4]
+        );
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+// Draws the regions as rows of `width` characters, each standing for the same number of bytes:
+// I = image, M = mapped, P = private, - = inaccessible or unknown. Contiguous runs of regions
+// are drawn to scale, but the gaps between them are collapsed into a single marker line,
+// otherwise a 64-bit address space would be nothing but empty rows.
+pub fn render_map(regions: &[MemoryRegion], width: usize) -> String {
+    let width = width.max(1);
+    let total: usize = regions.iter().map(|region| region.size.get()).sum();
+    let scale = total.div_ceil(width * MAP_LINES).max(1);
+
+    let mut sorted: Vec<&MemoryRegion> = regions.iter().collect();
+    sorted.sort_by_key(|region| region.start_address);
+
+    let mut map = String::new();
+    let mut index = 0;
+    while index < sorted.len() {
+        // Collect one run of back-to-back regions.
+        let run_start = index;
+        while index + 1 < sorted.len()
+            && sorted[index].end() == sorted[index + 1].start_address
+        {
+            index += 1;
+        }
+        let run = &sorted[run_start..=index];
+        index += 1;
+
+        let start = run[0].start_address.get();
+        let end = run[run.len() - 1].end().get();
+        let chars = (end - start).div_ceil(scale);
+
+        let mut line = 0;
+        while line * width < chars {
+            let first = line * width;
+            let last = (first + width).min(chars);
+            map.push_str(&format!("0x{:012X} |", start + first * scale));
+            for slot in first..last {
+                map.push(map_char(run, start + slot * scale));
+            }
+            map.push_str("|\n");
+            line += 1;
+        }
+
+        if let Some(next) = sorted.get(index) {
+            map.push_str(&format!("{:>15} ~ gap of {} ~\n", "", format_size(next.start_address.get() - end)));
+        }
+    }
+
+    map
+}
+
+// Sixteen bytes per line, addresses on the left and printable ASCII on the right:
+// "0x00007FF6A1B2C3D0  48 8B 05 ...  H..."
+pub fn hex_dump(bytes: &[u8], address: usize) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        dump.push_str(&format!("0x{:012X} ", address + line * 16));
+        for column in 0..16 {
+            match chunk.get(column) {
+                Some(byte) => dump.push_str(&format!(" {:02X}", byte)),
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str("  ");
+        dump.extend(chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+        dump.push('\n');
+    }
+    dump
+}
+
+fn map_char(run: &[&MemoryRegion], address: usize) -> char {
+    let region = run.iter().find(|region| region.contains(address)).unwrap_or(&run[run.len() - 1]);
+    if !region.is_readable {
+        return '-';
+    }
+    match region.region_type {
+        RegionType::Image => 'I',
+        RegionType::Mapped => 'M',
+        RegionType::Private => 'P',
+        RegionType::Unknown => '-',
+    }
+}
+
+pub(crate) fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::{Address, Size};
+
+    // A committed region with the access `protection` spells out, e.g. "rw-".
+    fn region(start: usize, size: usize, protection: &str, region_type: RegionType) -> MemoryRegion {
+        MemoryRegion {
+            start_address: Address::new(start),
+            size: Size::new(size),
+            allocation_base: Address::new(start),
+            is_readable: protection.contains('r'),
+            is_writable: protection.contains('w'),
+            is_executable: protection.contains('x'),
+            is_guard: false,
+            is_copy_on_write: false,
+            protection: 0,
+            region_type,
+            is_inferred: false,
+        }
+    }
+
+    fn layout() -> Vec<MemoryRegion> {
+        let mut guard = region(0x7F_0000, 0x1000, "---", RegionType::Private);
+        guard.is_guard = true;
+        vec![
+            region(0x40_0000, 0x1000, "r--", RegionType::Image),
+            region(0x40_1000, 0x3000, "r-x", RegionType::Image),
+            region(0x50_0000, 0x2000, "r--", RegionType::Mapped),
+            guard,
+        ]
+    }
+
+    fn mapped_file(region: &MemoryRegion) -> Option<String> {
+        match region.region_type {
+            RegionType::Image => Some("C:\\Games\\game.exe".to_string()),
+            RegionType::Mapped => Some("C:\\Data\\a \"b\",c.dat".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn csv_has_a_row_per_region_and_quotes_what_needs_it() {
+        let mut csv = Vec::new();
+        write_regions_csv(&layout(), mapped_file, &mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "start,end,size,protection,type,mapped_file\n\
+             0x400000,0x401000,0x1000,r--,image,C:\\Games\\game.exe\n\
+             0x401000,0x404000,0x3000,r-x,image,C:\\Games\\game.exe\n\
+             0x500000,0x502000,0x2000,r--,mapped,\"C:\\Data\\a \"\"b\"\",c.dat\"\n\
+             0x7F0000,0x7F1000,0x1000,---,private,\n"
+        );
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_they_must_be() {
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+
+    #[test]
+    fn tables_align_their_columns() {
+        let mut regions = layout();
+        regions.push(region(0x7FF6_0000, 0x10_0000, "rw-", RegionType::Private));
+        assert_eq!(
+            format_table(&regions, mapped_file),
+            "  0x400000-  0x401000 r-- IMG   4.0 KiB  game.exe\n\
+             \x20 0x401000-  0x404000 r-x IMG  12.0 KiB  game.exe\n\
+             \x20 0x500000-  0x502000 r-- MAP   8.0 KiB  a \"b\",c.dat\n\
+             \x20 0x7F0000-  0x7F1000 --- PRV   4.0 KiB  [guard]\n\
+             0x7FF60000-0x80060000 rw- PRV   1.0 MiB\n"
+        );
+        assert_eq!(fmt_table(&regions[..1]), "0x400000-0x401000 r-- IMG  4.0 KiB\n");
+        assert_eq!(fmt_table(&[]), "");
+    }
+
+    // `count` map rows of `text`, the first at `start`, each `step` bytes on from the last.
+    fn rows(start: usize, step: usize, count: usize, text: &str) -> String {
+        (0..count).map(|row| format!("0x{:012X} |{}|\n", start + row * step, text)).collect()
+    }
+
+    #[test]
+    fn maps_draw_runs_to_scale_and_collapse_gaps() {
+        let regions = [
+            region(0x50_0000, 0x2000, "r--", RegionType::Mapped),
+            region(0x40_0000, 0x1000, "r--", RegionType::Image),
+            region(0x40_1000, 0x1000, "---", RegionType::Private),
+        ];
+        // 0x4000 bytes over about 16 rows of 2 characters: 0x200 bytes a character.
+        let expected = [
+            rows(0x40_0000, 0x400, 4, "II"),
+            rows(0x40_1000, 0x400, 4, "--"),
+            format!("{:>15} ~ gap of 1016.0 KiB ~\n", ""),
+            rows(0x50_0000, 0x400, 8, "MM"),
+        ]
+        .concat();
+        assert_eq!(render_map(&regions, 2), expected);
+    }
+
+    #[test]
+    fn maps_end_on_a_partial_row() {
+        let regions = [region(0x1_0000, 0x1000, "rw-", RegionType::Private)];
+        // 52 bytes a character makes 79 of them: 15 full rows of 5 and one of 4.
+        let expected = rows(0x1_0000, 5 * 52, 15, "PPPPP") + &rows(0x1_0000 + 15 * 5 * 52, 0, 1, "PPPP");
+        assert_eq!(render_map(&regions, 5), expected);
+        // No width is taken as one character, and no regions draw nothing.
+        assert_eq!(render_map(&regions, 0), rows(0x1_0000, 0x100, 16, "P"));
+        assert_eq!(render_map(&[], 80), "");
+    }
+
+    #[test]
+    fn hex_dumps_pad_the_last_line() {
+        let bytes: Vec<u8> = b"Hello, dump!\x00\x01\x7F\xFFmore".to_vec();
+        assert_eq!(
+            hex_dump(&bytes, 0x1000),
+            "0x000000001000  48 65 6C 6C 6F 2C 20 64 75 6D 70 21 00 01 7F FF  Hello, dump!....\n\
+             0x000000001010  6D 6F 72 65                                      more\n"
+        );
+        assert_eq!(hex_dump(&[], 0x1000), "");
+    }
+}
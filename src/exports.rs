@@ -0,0 +1,131 @@
+// Looking up exports of modules loaded in the target, by reading their export tables
+// remotely. Nothing is called in the target, so this works without GetProcAddress there.
+
+use std::collections::HashMap;
+use std::io;
+
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::pe::{ExportTarget, Exports, ImportName, PeHeaders, DIRECTORY_EXPORT};
+use crate::wstr;
+
+// Export directories claiming to be larger are not read. The largest system DLLs need a
+// few hundred KiB.
+const MAX_EXPORT_DIRECTORY_SIZE: usize = 64 * 1024 * 1024;
+
+// Forwarders pointing at forwarders are followed this deep.
+const MAX_FORWARDS: usize = 8;
+
+// Hosts tried, in order, for API set names such as "api-ms-win-core-synch-l1-2-0.dll", which
+// are never loaded under that name. The loader resolves them through the API set schema.
+const API_SET_HOSTS: &[&str] = &["kernelbase.dll", "ucrtbase.dll", "kernel32.dll", "ntdll.dll", "advapi32.dll"];
+
+// Resolves exports against one view of the target's modules, caching each module's parsed
+// export table.
+pub struct ExportResolver<'a> {
+    memory: &'a Memory,
+    modules: Vec<ModuleInfo>,
+    exports: HashMap<String, Exports>,
+}
+
+impl<'a> ExportResolver<'a> {
+    pub fn new(memory: &'a Memory) -> io::Result<Self> {
+        Ok(ExportResolver { memory, modules: memory.modules_or_live()?.into_owned(), exports: HashMap::new() })
+    }
+
+    // Absolute address of `import` in `module`, following forwarders.
+    pub fn resolve(&mut self, module: &str, import: &ImportName) -> io::Result<usize> {
+        let mut module = module.to_string();
+        let mut import = import.clone();
+
+        for _ in 0..MAX_FORWARDS {
+            match self.lookup(&module, &import)? {
+                (base, ExportTarget::Rva(rva)) => return Ok(base + rva as usize),
+                (_, ExportTarget::Forwarded(forward)) => {
+                    let (target_module, target) = forward
+                        .split_once('.')
+                        .ok_or_else(|| invalid(format!("Malformed forwarder '{}' in {}", forward, module)))?;
+                    import = match target.strip_prefix('#') {
+                        Some(ordinal) => ImportName::Ordinal(
+                            ordinal.parse().map_err(|_| invalid(format!("Malformed forwarder '{}'", forward)))?,
+                        ),
+                        None => ImportName::Name(target.to_string()),
+                    };
+                    module = format!("{}.dll", target_module);
+                }
+            }
+        }
+
+        Err(invalid(format!("Too many forwarders resolving {:?} from {}", import, module)))
+    }
+
+    fn lookup(&mut self, module: &str, import: &ImportName) -> io::Result<(usize, ExportTarget)> {
+        let api_set = is_api_set(module);
+        let candidates: Vec<String> = if api_set {
+            API_SET_HOSTS.iter().map(|host| host.to_string()).collect()
+        } else {
+            vec![module.to_string()]
+        };
+
+        for candidate in &candidates {
+            let Some(loaded) = self.modules.iter().find(|loaded| wstr::eq_ignore_case(&loaded.name, candidate)).cloned()
+            else {
+                continue;
+            };
+            let key = wstr::fold_case(&loaded.name);
+            if !self.exports.contains_key(&key) {
+                let exports = read_exports(self.memory, &loaded)?;
+                self.exports.insert(key.clone(), exports);
+            }
+            if let Some(target) = self.exports[&key].get(import) {
+                return Ok((loaded.base_address, target.clone()));
+            }
+        }
+
+        if !api_set && !candidates.iter().any(|candidate| self.is_loaded(candidate)) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Module {} is not loaded in the target", module)));
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, format!("{} does not export {:?}", module, import)))
+    }
+
+    fn is_loaded(&self, module: &str) -> bool {
+        self.modules.iter().any(|loaded| wstr::eq_ignore_case(&loaded.name, module))
+    }
+}
+
+impl Memory {
+    // One-off export lookup. Use an `ExportResolver` for many, which caches export tables.
+    pub fn find_export(&self, module: &str, name: &str) -> io::Result<usize> {
+        ExportResolver::new(self)?.resolve(module, &ImportName::Name(name.to_string()))
+    }
+}
+
+pub(crate) fn read_exports(memory: &Memory, module: &ModuleInfo) -> io::Result<Exports> {
+    let headers = memory.read_bytes(module.base_address, 0x1000)?;
+    let headers = PeHeaders::parse(&headers)?;
+    let Some(directory) = headers.directory(DIRECTORY_EXPORT) else {
+        return Ok(Exports::default());
+    };
+    if directory.size as usize > MAX_EXPORT_DIRECTORY_SIZE {
+        return Err(invalid(format!("Export directory of {} is implausibly large", module.name)));
+    }
+    let view = memory.read_bytes(module.base_address + directory.rva as usize, directory.size as usize)?;
+    if view.len() < directory.size as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Export directory of {} is not fully readable", module.name),
+        ));
+    }
+    Exports::parse(&view, directory.rva, directory).map_err(|e| {
+        io::Error::new(e.kind(), format!("Bad export directory in {}: {}", module.name, e))
+    })
+}
+
+fn is_api_set(module: &str) -> bool {
+    let module = wstr::fold_case(module);
+    module.starts_with("api-ms-") || module.starts_with("ext-ms-")
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
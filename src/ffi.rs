@@ -0,0 +1,171 @@
+// C ABI for using the crate as a DLL. Every function null-checks its pointers, catches panics
+// before they cross the boundary and returns an `NnStatus` code (0 on success). Building with
+// the `capi` feature generates `nirvana_notject.h` into OUT_DIR, and into the directory
+// NIRVANA_HEADER_DIR names if it is set (e.g. `NIRVANA_HEADER_DIR=include`).
+
+use std::ffi::{c_char, CStr, CString};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::pattern::Pattern;
+
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NnStatus {
+    Ok = 0,
+    // A required pointer argument was null.
+    NullPointer = -1,
+    // An argument was malformed, e.g. a pattern that doesn't parse or a non-UTF-8 string.
+    InvalidArgument = -2,
+    // The scan completed without a match.
+    NotFound = -3,
+    // The underlying read, write or query failed.
+    IoError = -4,
+    // The library panicked; the handle should not be used any further.
+    Panic = -5,
+}
+
+// Opaque handle returned by `nn_open`.
+pub struct NnMemory(Memory);
+
+// Opens `pid` and caches its region list. Returns null on failure.
+#[no_mangle]
+pub extern "C" fn nn_open(pid: u32) -> *mut NnMemory {
+    let opened = panic::catch_unwind(|| {
+        let mut memory = Memory::open(pid)?;
+        memory.scan_memory()?;
+        Ok::<_, io::Error>(memory)
+    });
+    match opened {
+        Ok(Ok(memory)) => Box::into_raw(Box::new(NnMemory(memory))),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Closes a handle from `nn_open`. Null is ignored.
+///
+/// # Safety
+///
+/// `handle` must be null or a handle from `nn_open` that has not been closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn nn_close(handle: *mut NnMemory) {
+    if !handle.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(handle))));
+    }
+}
+
+/// Reads up to `len` bytes at `address` into `buffer`; the count read goes to `out_read`.
+///
+/// # Safety
+///
+/// `handle` must come from `nn_open`, `buffer` must be valid for `len` bytes of writes and
+/// `out_read` must point to a writable `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn nn_read(
+    handle: *const NnMemory,
+    address: usize,
+    buffer: *mut u8,
+    len: usize,
+    out_read: *mut usize,
+) -> i32 {
+    guard(|| {
+        let memory = memory(handle)?;
+        if buffer.is_null() || out_read.is_null() {
+            return Err(NnStatus::NullPointer);
+        }
+        let read = memory.read_into(address, slice::from_raw_parts_mut(buffer, len)).map_err(io_status)?;
+        *out_read = read;
+        Ok(())
+    })
+}
+
+/// Writes `len` bytes from `buffer` to `address`.
+///
+/// # Safety
+///
+/// `handle` must come from `nn_open` and `buffer` must be valid for `len` bytes of reads.
+#[no_mangle]
+pub unsafe extern "C" fn nn_write(handle: *const NnMemory, address: usize, buffer: *const u8, len: usize) -> i32 {
+    guard(|| {
+        let memory = memory(handle)?;
+        if buffer.is_null() {
+            return Err(NnStatus::NullPointer);
+        }
+        memory.write_bytes(address, slice::from_raw_parts(buffer, len)).map_err(io_status)
+    })
+}
+
+/// Finds the first match of `pattern` (`Pattern::parse` syntax, e.g. "48 8B ?? 90") in the
+/// cached regions and stores its address in `out_address`.
+///
+/// # Safety
+///
+/// `handle` must come from `nn_open`, `pattern` must be a NUL-terminated string and
+/// `out_address` must point to a writable `uintptr_t`.
+#[no_mangle]
+pub unsafe extern "C" fn nn_scan_pattern(handle: *const NnMemory, pattern: *const c_char, out_address: *mut usize) -> i32 {
+    guard(|| {
+        let memory = memory(handle)?;
+        if pattern.is_null() || out_address.is_null() {
+            return Err(NnStatus::NullPointer);
+        }
+        let text = CStr::from_ptr(pattern).to_str().map_err(|_| NnStatus::InvalidArgument)?;
+        let pattern = Pattern::parse(text).map_err(|_| NnStatus::InvalidArgument)?;
+        let found = memory.find_pattern(&pattern, &RegionFilter::new()).map_err(io_status)?;
+        *out_address = found.ok_or(NnStatus::NotFound)?;
+        Ok(())
+    })
+}
+
+/// The cached regions as a JSON array, or null on failure. Free it with `nn_free_string`.
+///
+/// # Safety
+///
+/// `handle` must come from `nn_open`.
+#[no_mangle]
+pub unsafe extern "C" fn nn_regions_json(handle: *const NnMemory) -> *mut c_char {
+    let json = panic::catch_unwind(AssertUnwindSafe(|| {
+        let memory = memory(handle).ok()?;
+        let json = serde_json::to_string(memory.cached_regions()).ok()?;
+        CString::new(json).ok()
+    }));
+    match json {
+        Ok(Some(json)) => json.into_raw(),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Frees a string returned by this library. Null is ignored.
+///
+/// # Safety
+///
+/// `string` must be null or come from `nn_regions_json` and not have been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn nn_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(CString::from_raw(string))));
+    }
+}
+
+unsafe fn memory<'a>(handle: *const NnMemory) -> Result<&'a Memory, NnStatus> {
+    handle.as_ref().map(|handle| &handle.0).ok_or(NnStatus::NullPointer)
+}
+
+fn guard(f: impl FnOnce() -> Result<(), NnStatus>) -> i32 {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => NnStatus::Ok as i32,
+        Ok(Err(status)) => status as i32,
+        Err(_) => NnStatus::Panic as i32,
+    }
+}
+
+fn io_status(e: io::Error) -> NnStatus {
+    match e.kind() {
+        io::ErrorKind::InvalidInput => NnStatus::InvalidArgument,
+        _ => NnStatus::IoError,
+    }
+}
@@ -0,0 +1,108 @@
+use std::io;
+
+use log::debug;
+
+use crate::memory::{partial_write, Memory};
+#[cfg(feature = "patch")]
+use crate::patch::Patch;
+use crate::scan::CHUNK_SIZE;
+
+impl Memory {
+    // Sets `len` bytes at `address` to `byte`, writing a chunk at a time from one reusable
+    // buffer. Pages that aren't writable are written through `write_protected`. Returns how
+    // many bytes were written: fewer than `len` if the fill stopped at a region or page that
+    // couldn't be written, and an error only if nothing was written at all.
+    pub fn fill(&self, address: usize, len: usize, byte: u8) -> io::Result<usize> {
+        let end = address
+            .checked_add(len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Fill range overflows the address space"))?;
+        let buffer = vec![byte; CHUNK_SIZE.min(len)];
+
+        let mut cursor = address;
+        while cursor < end {
+            let result = self
+                .query_address(cursor)
+                .and_then(|info| {
+                    info.to_region()
+                        .ok_or_else(|| io::Error::other(format!("Region at 0x{:X} is not committed", info.base)))
+                })
+                .and_then(|region| {
+                    // Never straddle a region boundary, so each write sees a single protection.
+                    let chunk = (end - cursor).min(CHUNK_SIZE).min(region.end().get() - cursor);
+                    let bytes = &buffer[..chunk];
+                    if region.is_writable {
+                        self.write_bytes(cursor, bytes)
+                    } else {
+                        self.write_protected(cursor, bytes)
+                    }
+                    .map(|_| chunk)
+                });
+
+            let e = match result {
+                Ok(chunk) => {
+                    cursor += chunk;
+                    continue;
+                }
+                Err(e) => e,
+            };
+            // A chunk that stopped partway still wrote its first bytes.
+            cursor += partial_write(&e).map_or(0, |partial| partial.written);
+            if cursor == address {
+                return Err(e);
+            }
+            debug!("fill at 0x{:X} stopped after 0x{:X} bytes: {}", address, cursor - address, e);
+            break;
+        }
+
+        Ok(cursor - address)
+    }
+
+    pub fn zero(&self, address: usize, len: usize) -> io::Result<usize> {
+        self.fill(address, len, 0)
+    }
+
+    // Like `fill`, but through `apply_patch` so the original bytes can be restored with
+    // `revert_patch`. The patch keeps full copies of both, and nothing is written unless the
+    // whole range can be backed up.
+    #[cfg(feature = "patch")]
+    pub fn fill_patched(&self, address: usize, len: usize, byte: u8) -> io::Result<Patch> {
+        self.apply_patch(address, &vec![byte; len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::backend::mock::{MockBackend, PAGE};
+    use crate::memory::partial_write;
+    use crate::sys::PAGE_READONLY;
+
+    #[test]
+    fn fills_stop_where_a_write_stops() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; 4 * PAGE]).open();
+        mock.fail_writes(0x12345..0x13000);
+
+        assert_eq!(memory.fill(0x10000, 4 * PAGE, 0xCC).unwrap(), 0x2345);
+        assert!(mock.bytes(0x10000, 0x2345).iter().all(|&byte| byte == 0xCC));
+        assert!(mock.bytes(0x12345, 0x1CBB).iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn fills_fail_only_when_nothing_was_written() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        mock.fail_writes(0x10000..0x11000);
+
+        let e = memory.fill(0x10000, PAGE, 0xCC).unwrap_err();
+        assert!(partial_write(&e).is_none());
+    }
+
+    #[test]
+    fn fills_go_through_read_only_regions() {
+        let (mock, memory) =
+            MockBackend::new().data(0x10000, vec![0; PAGE]).region(0x11000, vec![0; PAGE], PAGE_READONLY).open();
+
+        assert_eq!(memory.zero(0x10800, PAGE).unwrap(), PAGE);
+        assert_eq!(memory.fill(0x10800, PAGE, 1).unwrap(), PAGE);
+        assert!(mock.bytes(0x11000, 0x800).iter().all(|&byte| byte == 1));
+        assert_eq!(memory.query_address(0x11000).unwrap().protection, PAGE_READONLY);
+    }
+}
@@ -0,0 +1,86 @@
+use std::ops::Range;
+
+use crate::memory::MemoryRegion;
+use crate::scan::SkipReason;
+use crate::sections::SectionRef;
+
+// Selects which cached regions a scan visits. Only readable regions are ever scanned, which
+// rules out PAGE_NOACCESS and guard pages whatever the filter says.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RegionFilter {
+    pub writable: Option<bool>,
+    pub executable: Option<bool>,
+    pub range: Option<Range<usize>>,
+    pub max_region_size: Option<usize>,
+}
+
+impl RegionFilter {
+    pub fn new() -> Self {
+        RegionFilter::default()
+    }
+
+    pub fn writable(mut self, writable: bool) -> Self {
+        self.writable = Some(writable);
+        self
+    }
+
+    pub fn executable(mut self, executable: bool) -> Self {
+        self.executable = Some(executable);
+        self
+    }
+
+    pub fn range(mut self, range: Range<usize>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    pub fn max_region_size(mut self, size: usize) -> Self {
+        self.max_region_size = Some(size);
+        self
+    }
+
+    // Restricts the scan to one module section, e.g. the writable globals in
+    // `memory.section("game.exe", ".data")`. Replaces any `range` set before.
+    pub fn in_section(self, section: &SectionRef) -> Self {
+        self.range(section.range.clone())
+    }
+
+    pub fn matches(&self, region: &MemoryRegion) -> bool {
+        self.clip(region).is_some()
+    }
+
+    // The part of `region` a scan should cover, or None when the filter excludes it.
+    pub fn clip(&self, region: &MemoryRegion) -> Option<Range<usize>> {
+        if !region.is_readable
+            || region.is_guard
+            || self.writable.is_some_and(|writable| region.is_writable != writable)
+            || self.executable.is_some_and(|executable| region.is_executable != executable)
+            || self.max_region_size.is_some_and(|max| region.size > max)
+        {
+            return None;
+        }
+
+        let Range { mut start, mut end } = region.range();
+        if let Some(range) = &self.range {
+            start = start.max(range.start);
+            end = end.min(range.end);
+        }
+
+        if start < end {
+            Some(start..end)
+        } else {
+            None
+        }
+    }
+
+    // Why `clip` leaves `region` out, for the scan report. Only meaningful when it does.
+    pub(crate) fn skip_reason(&self, region: &MemoryRegion) -> SkipReason {
+        if region.is_guard {
+            SkipReason::Guard
+        } else if self.max_region_size.is_some_and(|max| region.size > max) {
+            SkipReason::TooLarge
+        } else {
+            SkipReason::Filtered
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::io;
+
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::scan::ScanOptions;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloatWidth {
+    F32,
+    F64,
+}
+
+impl FloatWidth {
+    pub fn size(self) -> usize {
+        match self {
+            FloatWidth::F32 => 4,
+            FloatWidth::F64 => 8,
+        }
+    }
+
+    // The slot at the start of `bytes` as an f64. f32 values widen exactly, denormals included.
+    pub(crate) fn decode(self, bytes: &[u8]) -> f64 {
+        match self {
+            FloatWidth::F32 => f32::from_ne_bytes(bytes[..4].try_into().unwrap()) as f64,
+            FloatWidth::F64 => f64::from_ne_bytes(bytes[..8].try_into().unwrap()),
+        }
+    }
+}
+
+impl Memory {
+    // Finds floats within `tolerance` of `value`, since exact bit patterns rarely survive the
+    // target's arithmetic. The comparison runs in f64 for both widths; NaN and infinite
+    // candidates never match. Slots are `width`-aligned unless `ScanOptions::alignment` says
+    // otherwise.
+    pub fn scan_for_float(
+        &self,
+        value: f64,
+        tolerance: f64,
+        width: FloatWidth,
+        filter: &RegionFilter,
+    ) -> io::Result<Vec<usize>> {
+        self.scan_for_float_with(value, tolerance, width, filter, self.default_scan_options())
+    }
+
+    pub fn scan_for_float_with(
+        &self,
+        value: f64,
+        tolerance: f64,
+        width: FloatWidth,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<Vec<usize>> {
+        if !value.is_finite() || tolerance.is_nan() || tolerance < 0.0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot scan for {} with tolerance {}", value, tolerance),
+            ));
+        }
+
+        let size = width.size();
+        Ok(self.scan_windows(size, filter, options, |window, _| {
+            let candidate = width.decode(window);
+            candidate.is_finite() && (candidate - value).abs() <= tolerance
+        })
+        .matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::mock::{MockBackend, PAGE};
+
+    const BASE: usize = 0x10000;
+
+    // A page of NaNs, whatever the width, with `slots` written over it.
+    fn floats(slots: &[(usize, &[u8])]) -> (Arc<MockBackend>, Memory) {
+        let mut bytes = vec![0xFF; PAGE];
+        for &(offset, slot) in slots {
+            bytes[offset..offset + slot.len()].copy_from_slice(slot);
+        }
+        let (mock, mut memory) = MockBackend::new().data(BASE, bytes).open();
+        memory.scan_memory().unwrap();
+        (mock, memory)
+    }
+
+    fn scan(memory: &Memory, value: f64, tolerance: f64, width: FloatWidth) -> Vec<usize> {
+        let found = memory.scan_for_float(value, tolerance, width, &RegionFilter::new()).unwrap();
+        found.into_iter().map(|address| address - BASE).collect()
+    }
+
+    fn below(value: f64) -> f64 {
+        f64::from_bits(value.to_bits() - 1)
+    }
+
+    #[test]
+    fn f32_values_compare_at_f64_precision() {
+        let (_mock, memory) = floats(&[(0x10, &0.1f32.to_ne_bytes()), (0x20, &100.0f32.to_ne_bytes())]);
+
+        // 0.1 has no exact f32; the nearest one is about 1.5e-9 off.
+        let off = (0.1f32 as f64 - 0.1).abs();
+        assert!(scan(&memory, 0.1, 0.0, FloatWidth::F32).is_empty());
+        assert_eq!(scan(&memory, 0.1, off, FloatWidth::F32), [0x10]);
+        assert!(scan(&memory, 0.1, below(off), FloatWidth::F32).is_empty());
+        assert_eq!(scan(&memory, 100.0, 0.0, FloatWidth::F32), [0x20]);
+    }
+
+    #[test]
+    fn the_tolerance_is_inclusive_on_both_sides() {
+        let (_mock, memory) = floats(&[(0x10, &1.5f64.to_ne_bytes()), (0x20, &0.5f64.to_ne_bytes())]);
+
+        assert_eq!(scan(&memory, 1.0, 0.5, FloatWidth::F64), [0x10, 0x20]);
+        assert!(scan(&memory, 1.0, below(0.5), FloatWidth::F64).is_empty());
+        assert_eq!(scan(&memory, 1.5, 0.0, FloatWidth::F64), [0x10]);
+    }
+
+    #[test]
+    fn nan_and_infinite_candidates_never_match() {
+        let (_mock, memory) = floats(&[
+            (0x10, &f32::INFINITY.to_ne_bytes()),
+            (0x20, &f32::NEG_INFINITY.to_ne_bytes()),
+            (0x30, &f32::NAN.to_ne_bytes()),
+            (0x40, &f32::MAX.to_ne_bytes()),
+        ]);
+
+        assert_eq!(scan(&memory, 0.0, f64::INFINITY, FloatWidth::F32), [0x40]);
+        assert_eq!(scan(&memory, f32::MAX as f64, 0.0, FloatWidth::F32), [0x40]);
+        // f32::MAX widened is nowhere near infinite.
+        assert!(scan(&memory, f64::MAX, f64::MAX / 2.0, FloatWidth::F32).is_empty());
+    }
+
+    #[test]
+    fn denormals_and_signed_zeros_widen_exactly() {
+        let smallest = f32::from_bits(1);
+        let (_mock, memory) = floats(&[
+            (0x10, &smallest.to_ne_bytes()),
+            (0x20, &(-0.0f32).to_ne_bytes()),
+            (0x30, &f32::from_bits(0x007F_FFFF).to_ne_bytes()),
+        ]);
+
+        assert_eq!(scan(&memory, smallest as f64, 0.0, FloatWidth::F32), [0x10]);
+        assert_eq!(scan(&memory, 1.4e-45, 1e-46, FloatWidth::F32), [0x10]);
+        assert_eq!(scan(&memory, 0.0, 0.0, FloatWidth::F32), [0x20]);
+        assert_eq!(scan(&memory, 0.0, smallest as f64, FloatWidth::F32), [0x10, 0x20]);
+        assert_eq!(scan(&memory, f32::MIN_POSITIVE as f64, smallest as f64, FloatWidth::F32), [0x30]);
+    }
+
+    #[test]
+    fn slots_are_width_aligned_unless_told_otherwise() {
+        let (_mock, memory) = floats(&[(0x12, &2.0f32.to_ne_bytes()), (0x24, &3.0f64.to_ne_bytes())]);
+
+        assert!(scan(&memory, 2.0, 0.0, FloatWidth::F32).is_empty());
+        assert!(scan(&memory, 3.0, 0.0, FloatWidth::F64).is_empty());
+
+        let options = ScanOptions::default().alignment(2);
+        let filter = RegionFilter::new();
+        assert_eq!(memory.scan_for_float_with(2.0, 0.0, FloatWidth::F32, &filter, &options).unwrap(), [BASE + 0x12]);
+        assert_eq!(memory.scan_for_float_with(3.0, 0.0, FloatWidth::F64, &filter, &options).unwrap(), [BASE + 0x24]);
+    }
+
+    #[test]
+    fn unusable_values_and_tolerances_are_refused() {
+        let (mock, memory) = floats(&[]);
+        mock.clear_calls();
+
+        for (value, tolerance) in [(f64::NAN, 0.0), (f64::INFINITY, 0.0), (1.0, -0.5), (1.0, f64::NAN)] {
+            let e = memory.scan_for_float(value, tolerance, FloatWidth::F32, &RegionFilter::new()).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(mock.reads().is_empty());
+    }
+}
@@ -0,0 +1,398 @@
+// Values held in place. A background thread writes each value back at a fixed interval, so
+// whatever the target does to it in between is undone at the next tick:
+//
+//     let health = FrozenValue::new("health", address, 100f32.to_le_bytes().to_vec());
+//     let freezer = Freezer::start(&memory, vec![health], Duration::from_millis(50))?;
+//     ...
+//     let report = freezer.stop();
+//
+// A failed write is counted and the freezer goes on with the other values; the value comes
+// back at the next tick if its page does. The freezer stops by itself once `memory` is
+// dropped or closed.
+//
+// Values close together are written with one call per tick: those that touch always share a
+// span, and with a coalescing window (`start_with`) so do those at most the window apart,
+// whose gaps are read and the span written back with the frozen bytes patched in. A span
+// that can't be read or written whole is written value by value instead, so one bad address
+// doesn't stop its neighbors from being held. The gap bytes are written back as they were
+// read, so a change the target makes to them between the read and the write is lost, which
+// is why `start` uses a window of 0, merging only values that touch and reading nothing.
+//
+// With a coordinator set (see coordination.rs), each value is claimed before the thread
+// starts, and `stop` gives the claims up.
+
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::coordination::{Artifact, ArtifactKind};
+use crate::memory::Memory;
+use crate::process_handle::{spawn_worker, upgrade};
+
+// Bytes between two values up to which `Freezer::start` writes them in one span: none, so
+// the gaps between values are never written.
+pub const DEFAULT_COALESCE_WINDOW: usize = 0;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrozenValue {
+    pub label: String,
+    pub address: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl FrozenValue {
+    pub fn new(label: &str, address: usize, bytes: Vec<u8>) -> Self {
+        FrozenValue { label: label.to_string(), address, bytes }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FreezeReport {
+    pub ticks: u64,
+    // Values written and values that failed to be, however many calls that took.
+    pub writes: u64,
+    pub failed_writes: u64,
+    // Write calls made, with the failed ones; without coalescing, `writes + failed_writes`.
+    pub write_calls: u64,
+    // Reads of the gaps in spans.
+    pub span_reads: u64,
+    // Spans written value by value because their read or write failed.
+    pub fallbacks: u64,
+}
+
+impl FreezeReport {
+    // The calls an uncoalesced freezer would have made each tick.
+    pub fn values_per_tick(&self) -> f64 {
+        per_tick(self.writes + self.failed_writes, self.ticks)
+    }
+
+    // The write calls this one made each tick.
+    pub fn write_calls_per_tick(&self) -> f64 {
+        per_tick(self.write_calls, self.ticks)
+    }
+}
+
+fn per_tick(count: u64, ticks: u64) -> f64 {
+    match ticks {
+        0 => 0.0,
+        ticks => count as f64 / ticks as f64,
+    }
+}
+
+// Values written with one call. `members` index the freezer's values, in their order, so
+// where two overlap the later one wins as it would written one at a time.
+#[derive(Debug, Clone)]
+struct Span {
+    range: Range<usize>,
+    members: Vec<usize>,
+    // Whether some bytes of `range` are in no value and have to be read first.
+    has_gaps: bool,
+}
+
+pub struct Freezer {
+    memory: Weak<Memory>,
+    values: Arc<Vec<FrozenValue>>,
+    spans: usize,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<FreezeReport>,
+}
+
+impl Freezer {
+    pub fn start(memory: &Arc<Memory>, values: Vec<FrozenValue>, interval: Duration) -> io::Result<Self> {
+        Freezer::start_with(memory, values, interval, DEFAULT_COALESCE_WINDOW)
+    }
+
+    // Values at most `coalesce_window` bytes apart are written in one span.
+    pub fn start_with(
+        memory: &Arc<Memory>,
+        values: Vec<FrozenValue>,
+        interval: Duration,
+        coalesce_window: usize,
+    ) -> io::Result<Self> {
+        if interval.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The freeze interval must not be zero"));
+        }
+        for (claimed, value) in values.iter().enumerate() {
+            let claim = memory.claim_artifact(|| {
+                Ok(Artifact {
+                    kind: ArtifactKind::Freeze,
+                    address: value.address,
+                    bytes: value.bytes.clone(),
+                    original: Vec::new(),
+                    label: value.label.clone(),
+                })
+            });
+            if let Err(e) = claim {
+                release(memory, &values[..claimed]);
+                return Err(e);
+            }
+        }
+        let spans = plan_spans(&values, coalesce_window);
+        let span_count = spans.len();
+        let values = Arc::new(values);
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let values = Arc::clone(&values);
+            let stop = Arc::clone(&stop);
+            spawn_worker(memory, move |memory| run(memory, &values, &spans, interval, &stop))
+        };
+        Ok(Freezer { memory: Arc::downgrade(memory), values, spans: span_count, stop, thread })
+    }
+
+    pub fn values(&self) -> &[FrozenValue] {
+        &self.values
+    }
+
+    // The write calls a tick makes while every span reads and writes whole.
+    pub fn spans(&self) -> usize {
+        self.spans
+    }
+
+    pub fn stop(self) -> FreezeReport {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+        let report = self.thread.join().unwrap_or_default();
+        if let Some(memory) = self.memory.upgrade() {
+            release(&memory, &self.values);
+        }
+        report
+    }
+}
+
+fn release(memory: &Memory, values: &[FrozenValue]) {
+    for value in values {
+        memory.release_artifact(value.address);
+    }
+}
+
+fn run(
+    memory: &Weak<Memory>,
+    values: &[FrozenValue],
+    spans: &[Span],
+    interval: Duration,
+    stop: &AtomicBool,
+) -> FreezeReport {
+    let mut report = FreezeReport::default();
+    let mut next = Instant::now();
+
+    while !stop.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if next > now {
+            thread::park_timeout(next - now);
+            continue;
+        }
+
+        let Some(memory) = upgrade(memory) else {
+            break;
+        };
+        let _priority = memory.apply_thread_priority();
+        for span in spans {
+            write_span(&memory, values, span, &mut report);
+        }
+        drop(memory);
+        report.ticks += 1;
+
+        // Ticks missed while the target was slow to write are skipped, not made up.
+        next += interval;
+        while next < Instant::now() {
+            next += interval;
+        }
+    }
+
+    debug!("freezer stopped after {} ticks, {} failed writes", report.ticks, report.failed_writes);
+    report
+}
+
+// Values sorted by address, merged while the next starts at most `window` past the end so far.
+fn plan_spans(values: &[FrozenValue], window: usize) -> Vec<Span> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by_key(|&index| values[index].address);
+
+    let mut spans: Vec<Span> = Vec::new();
+    for index in order {
+        let value = &values[index];
+        let end = value.address.saturating_add(value.bytes.len());
+        match spans.last_mut() {
+            Some(span) if value.address <= span.range.end.saturating_add(window) => {
+                span.has_gaps |= value.address > span.range.end;
+                span.range.end = span.range.end.max(end);
+                span.members.push(index);
+            }
+            _ => spans.push(Span { range: value.address..end, members: vec![index], has_gaps: false }),
+        }
+    }
+    for span in &mut spans {
+        span.members.sort_unstable();
+    }
+    spans
+}
+
+fn write_span(memory: &Memory, values: &[FrozenValue], span: &Span, report: &mut FreezeReport) {
+    if span.members.len() > 1 {
+        let buffer = match span.has_gaps {
+            true => {
+                report.span_reads += 1;
+                memory.read_bytes(span.range.start, span.range.len())
+            }
+            false => Ok(vec![0; span.range.len()]),
+        };
+        let written = buffer.and_then(|mut buffer| {
+            for &index in &span.members {
+                let value = &values[index];
+                let offset = value.address - span.range.start;
+                buffer[offset..offset + value.bytes.len()].copy_from_slice(&value.bytes);
+            }
+            report.write_calls += 1;
+            memory.write_bytes(span.range.start, &buffer)
+        });
+        match written {
+            Ok(()) => {
+                report.writes += span.members.len() as u64;
+                return;
+            }
+            Err(e) => {
+                let range = &span.range;
+                debug!("failed to write 0x{:X}-0x{:X} whole, writing its values: {}", range.start, range.end, e);
+                report.fallbacks += 1;
+            }
+        }
+    }
+
+    for &index in &span.members {
+        let value = &values[index];
+        report.write_calls += 1;
+        match memory.write_bytes(value.address, &value.bytes) {
+            Ok(()) => report.writes += 1,
+            Err(e) => {
+                debug!("failed to write frozen {} at 0x{:X}: {}", value.label, value.address, e);
+                report.failed_writes += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use std::ops::Range;
+
+    use super::*;
+    use crate::backend::mock::{MockBackend, PAGE};
+    use crate::sys::PAGE_READONLY;
+
+    fn value(address: usize, bytes: &[u8]) -> FrozenValue {
+        FrozenValue::new(&format!("0x{:X}", address), address, bytes.to_vec())
+    }
+
+    fn spans(values: &[FrozenValue], window: usize) -> Vec<(Range<usize>, Vec<usize>, bool)> {
+        plan_spans(values, window).into_iter().map(|span| (span.range, span.members, span.has_gaps)).collect()
+    }
+
+    // One tick's writes, as the freezer thread makes them.
+    fn tick(memory: &Memory, values: &[FrozenValue], window: usize) -> FreezeReport {
+        let mut report = FreezeReport::default();
+        for span in plan_spans(values, window) {
+            write_span(memory, values, &span, &mut report);
+        }
+        report
+    }
+
+    #[test]
+    fn spans_merge_touching_values_and_those_within_the_window() {
+        let values = [value(0x1010, &[0; 4]), value(0x1000, &[0; 4]), value(0x1004, &[0; 4]), value(0x1020, &[0; 2])];
+
+        assert_eq!(
+            spans(&values, 0),
+            [(0x1000..0x1008, vec![1, 2], false), (0x1010..0x1014, vec![0], false), (0x1020..0x1022, vec![3], false)]
+        );
+        assert_eq!(spans(&values, 8), [(0x1000..0x1014, vec![0, 1, 2], true), (0x1020..0x1022, vec![3], false)]);
+        assert_eq!(spans(&values, 12), [(0x1000..0x1022, vec![0, 1, 2, 3], true)]);
+    }
+
+    #[test]
+    fn touching_values_are_written_in_one_call() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0xAA; PAGE]).open();
+        let values = [value(0x10004, &[2; 4]), value(0x10000, &[1; 4]), value(0x10010, &[3; 4])];
+
+        let report = tick(&memory, &values, 0);
+        assert_eq!(mock.writes(), [0x10000..0x10008, 0x10010..0x10014]);
+        assert!(mock.reads().is_empty());
+        assert_eq!(mock.bytes(0x10000, 8), [1, 1, 1, 1, 2, 2, 2, 2]);
+        assert_eq!((report.writes, report.write_calls, report.span_reads, report.fallbacks), (3, 2, 0, 0));
+    }
+
+    #[test]
+    fn gaps_within_the_window_are_read_and_written_back() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0xAA; PAGE]).open();
+        let values = [value(0x10000, &[1; 4]), value(0x10010, &[3; 4])];
+
+        let report = tick(&memory, &values, 16);
+        assert_eq!(mock.reads(), [0x10000..0x10014]);
+        assert_eq!(mock.writes(), [0x10000..0x10014]);
+        assert_eq!(mock.bytes(0x10000, 0x14), [[1; 4].as_slice(), &[0xAA; 12], &[3; 4]].concat());
+        assert_eq!((report.writes, report.write_calls, report.span_reads, report.fallbacks), (2, 1, 1, 0));
+    }
+
+    #[test]
+    fn later_values_win_where_they_overlap() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        let values = [value(0x10000, &[1; 4]), value(0x10002, &[2; 4])];
+
+        tick(&memory, &values, 0);
+        assert_eq!(mock.bytes(0x10000, 6), [1, 1, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn a_span_that_fails_to_write_falls_back_to_its_values() {
+        let (mock, memory) =
+            MockBackend::new().data(0x10000, vec![0; PAGE]).region(0x11000, vec![0; PAGE], PAGE_READONLY).open();
+        let values = [value(0x10FFC, &[1; 4]), value(0x11000, &[2; 4]), value(0x10F00, &[3; 4])];
+
+        let report = tick(&memory, &values, 0);
+        // The span's write is stitched across the region boundary before it fails; then each
+        // of its values is written on its own.
+        let fallback = [0x10FFC..0x11000, 0x11000..0x11004];
+        assert_eq!(mock.writes(), [&[0x10F00..0x10F04, 0x10FFC..0x11004], &fallback[..], &fallback[..]].concat());
+        // Its neighbor's failure doesn't stop a value being held.
+        assert_eq!(mock.bytes(0x10FFC, 4), [1; 4]);
+        assert_eq!(mock.bytes(0x11000, 4), [0; 4]);
+        assert_eq!((report.writes, report.failed_writes, report.write_calls, report.fallbacks), (2, 1, 4, 1));
+    }
+
+    #[test]
+    fn a_span_whose_gap_fails_to_read_falls_back_to_its_values() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; 2 * PAGE]).open();
+        mock.fail_reads(0x10800..0x10900);
+        let values = [value(0x10000, &[1; 4]), value(0x10F00, &[2; 4])];
+
+        let report = tick(&memory, &values, PAGE);
+        assert_eq!(mock.writes(), [0x10000..0x10004, 0x10F00..0x10F04]);
+        assert_eq!(mock.bytes(0x10F00, 4), [2; 4]);
+        assert_eq!((report.writes, report.write_calls, report.span_reads, report.fallbacks), (2, 2, 1, 1));
+    }
+
+    #[test]
+    fn the_freezer_undoes_changes_each_tick() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        let memory = Arc::new(memory);
+        let values = vec![value(0x10000, &[1; 4]), value(0x10004, &[2; 4])];
+
+        let freezer = Freezer::start(&memory, values, Duration::from_millis(1)).unwrap();
+        assert_eq!(freezer.spans(), 1);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while mock.writes().len() < 3 && Instant::now() < deadline {
+            memory.write_bytes(0x10000, &[0; 8]).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        let report = freezer.stop();
+        assert_eq!(mock.bytes(0x10000, 8), [1, 1, 1, 1, 2, 2, 2, 2]);
+        assert!(report.ticks >= 2, "{:?}", report);
+        assert_eq!(report.write_calls_per_tick(), 1.0);
+        assert_eq!(report.values_per_tick(), 2.0);
+    }
+}
@@ -0,0 +1,281 @@
+// Scans for several values near each other, e.g. "an f32 near 100.0 followed within 0x30
+// bytes by an i32 equal to 7". That is how entity structs are usually found: a lone value is
+// ambiguous but its neighbours rarely are.
+//
+//     let bases = GroupScan::new()
+//         .float(100.0, 0.5, FloatWidth::F32)
+//         .exact_within(0x30, 7i32)
+//         .run(&memory, &RegionFilter::new().writable(true))?;
+
+use std::collections::HashSet;
+use std::io;
+
+use crate::artifacts;
+use crate::chunks::Chunks;
+use crate::endian::ToBytes;
+use crate::filter::RegionFilter;
+use crate::float::FloatWidth;
+use crate::memory::Memory;
+use crate::scan::CHUNK_SIZE;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Predicate {
+    // These bytes exactly; built from a little-endian value by `exact`.
+    Bytes(Vec<u8>),
+    // A finite float within `tolerance` of `value`, compared in f64.
+    Float { value: f64, tolerance: f64, width: FloatWidth },
+}
+
+impl Predicate {
+    pub fn size(&self) -> usize {
+        match self {
+            Predicate::Bytes(bytes) => bytes.len(),
+            Predicate::Float { width, .. } => width.size(),
+        }
+    }
+
+    // Elements sit at addresses that are a multiple of this: the element size for the usual
+    // 1, 2, 4 and 8-byte values, 1 for anything else.
+    pub fn alignment(&self) -> usize {
+        match self.size() {
+            size @ (1 | 2 | 4 | 8) => size,
+            _ => 1,
+        }
+    }
+
+    // Whether `bytes` starts with a matching element.
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        match self {
+            Predicate::Bytes(expected) => bytes.starts_with(expected),
+            Predicate::Float { value, tolerance, width } => {
+                if bytes.len() < width.size() {
+                    return false;
+                }
+                let candidate = width.decode(bytes);
+                candidate.is_finite() && (candidate - value).abs() <= *tolerance
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Element {
+    predicate: Predicate,
+    // Most bytes allowed between the end of the previous element and the start of this one.
+    max_gap: usize,
+}
+
+// An ordered list of predicates. `run` returns the address of the first element of every
+// window in which each following element starts, suitably aligned, at most its gap after the
+// end of the one before.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GroupScan {
+    elements: Vec<Element>,
+}
+
+impl GroupScan {
+    pub fn new() -> Self {
+        GroupScan::default()
+    }
+
+    // Appends an element with `max_gap` bytes of slack after the previous one. The gap of the
+    // first element is ignored.
+    pub fn push(mut self, predicate: Predicate, max_gap: usize) -> Self {
+        self.elements.push(Element { predicate, max_gap });
+        self
+    }
+
+    pub fn exact<T: ToBytes>(self, value: T) -> Self {
+        self.exact_within(0, value)
+    }
+
+    pub fn exact_within<T: ToBytes>(self, max_gap: usize, value: T) -> Self {
+        self.push(Predicate::Bytes(value.to_le_bytes().as_ref().to_vec()), max_gap)
+    }
+
+    pub fn float(self, value: f64, tolerance: f64, width: FloatWidth) -> Self {
+        self.float_within(0, value, tolerance, width)
+    }
+
+    pub fn float_within(self, max_gap: usize, value: f64, tolerance: f64, width: FloatWidth) -> Self {
+        self.push(Predicate::Float { value, tolerance, width }, max_gap)
+    }
+
+    // Longest stretch of bytes one match can cover, from its base to the end of its last element.
+    pub fn max_span(&self) -> usize {
+        self.elements
+            .iter()
+            .enumerate()
+            .map(|(index, element)| element.predicate.size() + if index == 0 { 0 } else { element.max_gap })
+            .sum()
+    }
+
+    // Whether the group matches with its first element at the start of `window`, which begins
+    // at `address` in the target (needed for alignment). Later elements are searched with
+    // backtracking, so a near miss for one doesn't hide a later fit. Where the rest of the
+    // group failed to fit after some element ending at some offset is remembered, so each
+    // (element, offset) is tried once however many ways lead to it.
+    pub fn matches_at(&self, window: &[u8], address: usize) -> bool {
+        match self.elements.first() {
+            Some(first) => {
+                address % first.predicate.alignment() == 0
+                    && first.predicate.matches(window)
+                    && self.match_rest(window, address, 1, first.predicate.size(), &mut HashSet::new())
+            }
+            None => false,
+        }
+    }
+
+    // `failed` holds the (element index, end of the element before it) already found not to fit.
+    fn match_rest(
+        &self,
+        window: &[u8],
+        address: usize,
+        index: usize,
+        end: usize,
+        failed: &mut HashSet<(usize, usize)>,
+    ) -> bool {
+        let Some(element) = self.elements.get(index) else { return true };
+        if failed.contains(&(index, end)) {
+            return false;
+        }
+        let alignment = element.predicate.alignment();
+        let first = (address + end).next_multiple_of(alignment) - address;
+        let last = end + element.max_gap;
+
+        let fits = (first..=last).step_by(alignment).any(|offset| {
+            offset < window.len()
+                && element.predicate.matches(&window[offset..])
+                && self.match_rest(window, address, index + 1, offset + element.predicate.size(), failed)
+        });
+        if !fits {
+            failed.insert((index, end));
+        }
+        fits
+    }
+
+    // Base addresses of all matches in the regions the filter selects, ascending and each
+    // reported once. The tool's own allocations and patches are skipped as in other scans.
+    pub fn run(&self, memory: &Memory, filter: &RegionFilter) -> io::Result<Vec<usize>> {
+        let Some(first) = self.elements.first() else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Group scan has no elements"));
+        };
+        let alignment = first.predicate.alignment();
+        let span = self.max_span();
+        let excluded = memory.excluded_ranges(true);
+        let mut buffer = memory.buffer_pool().rent_zeroed(CHUNK_SIZE + span);
+        let mut bases = Vec::new();
+
+        for region in memory.regions_filtered(filter) {
+            let Some(range) = filter.clip(region) else { continue };
+            for piece in artifacts::subtract(range, &excluded) {
+                // Read one span past each chunk so windows starting near its end are whole.
+                let mut chunks = Chunks::new(piece, CHUNK_SIZE, span, alignment);
+                while let Some(chunk) = chunks.read_next(memory, &mut buffer) {
+                    let mut address = chunk.address;
+                    while address < chunk.owned_end && address - chunk.address < chunk.read {
+                        if self.matches_at(&buffer[address - chunk.address..chunk.read], address) {
+                            bases.push(address);
+                        }
+                        address += alignment;
+                    }
+                }
+            }
+        }
+
+        bases.sort_unstable();
+        bases.dedup();
+        Ok(bases)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+
+    #[test]
+    fn predicates_align_to_their_usual_sizes() {
+        assert_eq!(Predicate::Bytes(vec![0; 2]).alignment(), 2);
+        assert_eq!(Predicate::Bytes(vec![0; 3]).alignment(), 1);
+        assert_eq!(Predicate::Bytes(vec![0; 16]).alignment(), 1);
+        assert_eq!(Predicate::Float { value: 0.0, tolerance: 0.0, width: FloatWidth::F64 }.alignment(), 8);
+
+        let group = GroupScan::new().exact(1u8).exact_within(3, 7i32).float_within(0x10, 1.0, 0.1, FloatWidth::F32);
+        assert_eq!(group.max_span(), 1 + (3 + 4) + (0x10 + 4));
+    }
+
+    #[test]
+    fn floats_match_within_the_tolerance_and_never_when_not_finite() {
+        let near = Predicate::Float { value: 100.0, tolerance: 0.5, width: FloatWidth::F32 };
+        for (candidate, matches) in
+            [(100.0f32, true), (100.5, true), (99.5, true), (100.51, false), (f32::NAN, false), (f32::INFINITY, false)]
+        {
+            assert_eq!(near.matches(&candidate.to_ne_bytes()), matches, "{}", candidate);
+        }
+        let any = Predicate::Float { value: 0.0, tolerance: f64::INFINITY, width: FloatWidth::F64 };
+        assert!(!any.matches(&f64::NEG_INFINITY.to_ne_bytes()));
+        assert!(!any.matches(&[0; 7]));
+    }
+
+    #[test]
+    fn later_elements_start_at_most_their_gap_after_the_one_before() {
+        let group = GroupScan::new().exact(1u8).exact_within(2, 2u8);
+        assert!(group.matches_at(&[1, 2], 0x1000));
+        assert!(group.matches_at(&[1, 0, 0, 2], 0x1000));
+        assert!(!group.matches_at(&[1, 0, 0, 0, 2], 0x1000));
+        // Cut off by the end of the window.
+        assert!(!group.matches_at(&[1, 0], 0x1000));
+        assert!(!GroupScan::new().matches_at(&[1, 2], 0x1000));
+    }
+
+    #[test]
+    fn elements_are_aligned_by_address() {
+        let group = GroupScan::new().exact(1u8).exact_within(3, 7i32);
+        let mut window = [0u8; 8];
+        window[0] = 1;
+        window[4..].copy_from_slice(&7i32.to_le_bytes());
+        assert!(group.matches_at(&window, 0x1000));
+        // From an odd base the i32 at window offset 4 is misaligned, and the offsets that are
+        // aligned don't hold it.
+        assert!(!group.matches_at(&window, 0x1001));
+
+        let aligned = GroupScan::new().exact(7i32);
+        assert!(aligned.matches_at(&7i32.to_le_bytes(), 0x1000));
+        assert!(!aligned.matches_at(&7i32.to_le_bytes(), 0x1002));
+    }
+
+    #[test]
+    fn a_near_miss_doesnt_hide_a_later_fit() {
+        // The first 2 leaves no room for the 3; the second one does.
+        let group = GroupScan::new().exact(1u8).exact_within(4, 2u8).exact(3u8);
+        assert!(group.matches_at(&[1, 2, 9, 2, 3], 0x1000));
+        assert!(!group.matches_at(&[1, 2, 9, 2, 9], 0x1000));
+    }
+
+    #[test]
+    fn runs_find_every_base_including_across_chunks() {
+        let mut bytes = vec![0u8; 2 * CHUNK_SIZE];
+        let mut put = |offset: usize, value: &[u8]| bytes[offset..offset + value.len()].copy_from_slice(value);
+        // An f32 near 100 followed within 0x30 bytes by an i32 7: inside the first chunk, then
+        // across the seam, then an f32 without its i32 right at the end.
+        put(0x100, &100.25f32.to_ne_bytes());
+        put(0x114, &7i32.to_le_bytes());
+        put(CHUNK_SIZE - 8, &99.75f32.to_ne_bytes());
+        put(CHUNK_SIZE + 0x1C, &7i32.to_le_bytes());
+        put(2 * CHUNK_SIZE - 4, &100.0f32.to_ne_bytes());
+        let (_, mut memory) = MockBackend::new().data(0x100_0000, bytes).open();
+        memory.scan_memory().unwrap();
+
+        let group = GroupScan::new().float(100.0, 0.5, FloatWidth::F32).exact_within(0x30, 7i32);
+        let bases = group.run(&memory, &RegionFilter::new()).unwrap();
+        assert_eq!(bases, [0x100_0100, 0x100_0000 + CHUNK_SIZE - 8]);
+    }
+
+    #[test]
+    fn an_empty_group_is_refused() {
+        let (_, memory) = MockBackend::new().open();
+        let e = GroupScan::new().run(&memory, &RegionFilter::new()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,207 @@
+// The target's handle table: which files, mutexes, sections and so on it holds open. The
+// system-wide table is filtered to the target, and each handle is duplicated into this
+// process to ask the object manager for its type and name. Handles that can't be duplicated
+// (protected objects, or a target we may only read) come back without a type or name.
+
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+
+use log::{debug, trace, warn};
+
+use crate::memory::Memory;
+use crate::sys::{
+    CloseHandle, DuplicateHandle, GetCurrentProcess, NtQueryObject, NtQuerySystemInformation, ObjectTypeInformation,
+    OpenProcess, DUPLICATE_SAME_ACCESS, PROCESS_DUP_HANDLE, STATUS_INFO_LENGTH_MISMATCH, UNICODE_STRING,
+};
+use crate::timeout::with_timeout;
+use crate::win_error::win_call;
+use crate::wstr;
+
+// SYSTEM_HANDLE_TABLE_ENTRY_INFO_EX, which windows-sys doesn't declare.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct HandleTableEntry {
+    object: usize,
+    process_id: usize,
+    handle: usize,
+    granted_access: u32,
+    creator_back_trace_index: u16,
+    object_type_index: u16,
+    attributes: u32,
+    reserved: u32,
+}
+
+// Information classes windows-sys doesn't declare.
+const SYSTEM_EXTENDED_HANDLE_INFORMATION: i32 = 64;
+const OBJECT_NAME_INFORMATION: i32 = 1;
+
+// NtQueryObject can block forever on the name of a file opened for synchronous I/O that is
+// busy (typically a pipe with a pending read), so file names are queried on a worker that
+// is given up on after this long.
+const NAME_QUERY_TIMEOUT: Duration = Duration::from_millis(200);
+
+const OBJECT_INFO_SIZE: usize = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandleInfo {
+    // The handle's value in the target.
+    pub handle: usize,
+    pub type_index: u16,
+    // e.g. "File", "Mutant", "Section", "Key". None if the handle couldn't be duplicated.
+    pub type_name: Option<String>,
+    pub granted_access: u32,
+    // The object's name in the object manager's namespace, e.g. "\Device\HarddiskVolume3\x.dat".
+    // None for unnamed objects, handles that couldn't be queried, and name queries that hung.
+    pub name: Option<String>,
+}
+
+impl Memory {
+    pub fn handles(&self) -> io::Result<Vec<HandleInfo>> {
+        let entries = system_handles(self.process_id())?;
+
+        let pid = self.process_id();
+        let source = win_call!(
+            unsafe { OpenProcess(PROCESS_DUP_HANDLE, 0, pid) },
+            "cannot duplicate handles of process {}",
+            pid
+        )
+        .unwrap_or_else(|e| {
+            warn!("{}; types and names are unavailable", e);
+            ptr::null_mut()
+        });
+
+        let mut type_names: HashMap<u16, Option<String>> = HashMap::new();
+        let mut handles = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let mut info = HandleInfo {
+                handle: entry.handle,
+                type_index: entry.object_type_index,
+                type_name: None,
+                granted_access: entry.granted_access,
+                name: None,
+            };
+
+            if let Some(duplicate) = duplicate(source, entry.handle) {
+                let type_name = type_names
+                    .entry(entry.object_type_index)
+                    .or_insert_with(|| query_unicode(duplicate, ObjectTypeInformation))
+                    .clone();
+                if type_name.as_deref() == Some("File") {
+                    // Closes the duplicate once the query is done, however long that takes.
+                    info.name = query_name_watched(duplicate);
+                } else {
+                    info.name = query_unicode(duplicate, OBJECT_NAME_INFORMATION);
+                    unsafe { CloseHandle(duplicate) };
+                }
+                info.type_name = type_name;
+            }
+            handles.push(info);
+        }
+
+        if !source.is_null() {
+            unsafe { CloseHandle(source) };
+        }
+        debug!("process {} holds {} handles", self.process_id(), handles.len());
+        Ok(handles)
+    }
+}
+
+fn system_handles(process_id: u32) -> io::Result<Vec<HandleTableEntry>> {
+    let mut buffer: Vec<usize> = vec![0; 0x10000];
+    loop {
+        let size = buffer.len() * mem::size_of::<usize>();
+        let mut needed = 0;
+        let status = unsafe {
+            NtQuerySystemInformation(
+                SYSTEM_EXTENDED_HANDLE_INFORMATION,
+                buffer.as_mut_ptr() as *mut c_void,
+                size as u32,
+                &mut needed,
+            )
+        };
+        if status == STATUS_INFO_LENGTH_MISMATCH {
+            // The table can grow between calls, so ask for some slack.
+            let wanted = (needed as usize).max(size) * 3 / 2;
+            buffer.resize(wanted / mem::size_of::<usize>() + 1, 0);
+            continue;
+        }
+        if status < 0 {
+            return Err(io::Error::other(format!("Failed to query the system handle table (status 0x{:08X})", status)));
+        }
+        break;
+    }
+
+    // Header: handle count and a reserved word, then the entries.
+    let count = buffer[0];
+    let available = (buffer.len() - 2) * mem::size_of::<usize>() / mem::size_of::<HandleTableEntry>();
+    let entries =
+        unsafe { std::slice::from_raw_parts(buffer.as_ptr().add(2) as *const HandleTableEntry, count.min(available)) };
+    Ok(entries.iter().filter(|entry| entry.process_id == process_id as usize).copied().collect())
+}
+
+fn duplicate(source: *mut c_void, handle: usize) -> Option<*mut c_void> {
+    if source.is_null() {
+        return None;
+    }
+    let mut duplicate = ptr::null_mut();
+    win_call!(
+        unsafe {
+            DuplicateHandle(
+                source,
+                handle as *mut c_void,
+                GetCurrentProcess(),
+                &mut duplicate,
+                0,
+                0,
+                DUPLICATE_SAME_ACCESS,
+            )
+        },
+        "cannot duplicate handle 0x{:X}",
+        handle
+    )
+    .map_err(|e| trace!("{}", e))
+    .ok()?;
+    Some(duplicate)
+}
+
+// Queries an information class that starts with a UNICODE_STRING (the type or the name).
+fn query_unicode(handle: *mut c_void, class: i32) -> Option<String> {
+    let mut buffer: Vec<usize> = vec![0; OBJECT_INFO_SIZE / mem::size_of::<usize>()];
+    let mut needed = 0;
+    let status = unsafe {
+        NtQueryObject(handle, class, buffer.as_mut_ptr() as *mut c_void, OBJECT_INFO_SIZE as u32, &mut needed)
+    };
+    if status < 0 {
+        return None;
+    }
+    let string = unsafe { &*(buffer.as_ptr() as *const UNICODE_STRING) };
+    if string.Buffer.is_null() || string.Length == 0 {
+        return None;
+    }
+    let text = unsafe { std::slice::from_raw_parts(string.Buffer, string.Length as usize / 2) };
+    Some(wstr::decode_lossy(text))
+}
+
+// `query_unicode(handle, OBJECT_NAME_INFORMATION)` with a deadline of NAME_QUERY_TIMEOUT.
+// Takes ownership of `handle`: the worker closes it when the query returns, so a query that
+// hung keeps its handle until then.
+fn query_name_watched(handle: *mut c_void) -> Option<String> {
+    let raw = handle as usize;
+    let name = with_timeout(NAME_QUERY_TIMEOUT, move || {
+        let handle = raw as *mut c_void;
+        let name = query_unicode(handle, OBJECT_NAME_INFORMATION);
+        unsafe { CloseHandle(handle) };
+        name
+    });
+    match name {
+        Ok(name) => name,
+        Err(e) => {
+            trace!("name query for handle 0x{:X}: {}", raw, e);
+            None
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::{self, BufRead};
+use std::ops::Range;
+
+use crate::artifacts;
+use crate::filter::RegionFilter;
+use crate::memory::{Memory, MemoryRegion};
+use crate::reader::{HolePolicy, MemoryReader};
+
+// 64-bit FNV-1a. Not cryptographic, but fast, allocation-free and stable across releases,
+// which is all change detection needs.
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+// Streaming FNV-1a; feeding the same bytes in any split gives the same hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fnv1a(u64);
+
+impl Fnv1a {
+    pub fn new() -> Self {
+        Self::with_seed(0)
+    }
+
+    // Seed 0 is plain FNV-1a; other seeds perturb the offset basis.
+    pub fn with_seed(seed: u64) -> Self {
+        Fnv1a(FNV_OFFSET_BASIS ^ seed)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.0 = bytes.iter().fold(self.0, |hash, &byte| (hash ^ byte as u64).wrapping_mul(FNV_PRIME));
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hasher = Fnv1a::new();
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionHash {
+    pub start_address: usize,
+    pub size: usize,
+    pub hash: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IntegrityViolation {
+    pub start_address: usize,
+    pub size: usize,
+    pub expected: u64,
+    // None when the range can no longer be read completely, e.g. the region shrank or was freed.
+    pub actual: Option<u64>,
+}
+
+// Per-region hashes taken at one point in time, to check later which regions were modified.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntegrityBaseline {
+    pub regions: Vec<RegionHash>,
+}
+
+impl Memory {
+    pub fn hash_range(&self, address: usize, len: usize) -> io::Result<u64> {
+        self.hash_range_seeded(address, len, 0)
+    }
+
+    // Reads the range a chunk at a time; fails unless every byte could be read.
+    pub fn hash_range_seeded(&self, address: usize, len: usize, seed: u64) -> io::Result<u64> {
+        let mut hasher = Fnv1a::with_seed(seed);
+        let mut reader = MemoryReader::new(self, address..address.saturating_add(len));
+
+        loop {
+            let chunk = match reader.fill_buf() {
+                Ok(chunk) => chunk,
+                Err(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("Only 0x{:X} of 0x{:X} bytes at 0x{:X} are readable", reader.address() - address, len, address),
+                    ))
+                }
+            };
+            if chunk.is_empty() {
+                break;
+            }
+            hasher.update(chunk);
+            let consumed = chunk.len();
+            reader.consume(consumed);
+        }
+
+        Ok(hasher.finish())
+    }
+
+    pub fn hash_region(&self, region: &MemoryRegion) -> io::Result<u64> {
+        self.hash_range(region.start_address.get(), region.size.get())
+    }
+
+    // Hashes the range with unreadable pages counted as zeros, and lists those pages.
+    pub fn hash_range_with_holes(&self, address: usize, len: usize) -> io::Result<(u64, Vec<Range<usize>>)> {
+        let mut hasher = Fnv1a::new();
+        let mut reader =
+            MemoryReader::new(self, address..address.saturating_add(len)).hole_policy(HolePolicy::ZeroFill);
+
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            hasher.update(chunk);
+            let len = chunk.len();
+            reader.consume(len);
+        }
+
+        Ok((hasher.finish(), reader.holes().to_vec()))
+    }
+}
+
+impl IntegrityBaseline {
+    // Hashes the parts of the cached regions the filter selects. Unreadable pages inside a
+    // region are left out, and the readable stretches between them hashed separately.
+    pub fn capture(memory: &Memory, filter: &RegionFilter) -> Self {
+        let regions = memory
+            .regions_filtered(filter)
+            .filter_map(|region| filter.clip(region))
+            .flat_map(|range| match memory.hash_range(range.start, range.len()) {
+                Ok(hash) => vec![RegionHash { start_address: range.start, size: range.len(), hash }],
+                Err(_) => {
+                    let holes = memory.unreadable_ranges(range.clone());
+                    artifacts::subtract(range, &holes)
+                        .into_iter()
+                        .filter_map(|piece| {
+                            let hash = memory.hash_range(piece.start, piece.len()).ok()?;
+                            Some(RegionHash { start_address: piece.start, size: piece.len(), hash })
+                        })
+                        .collect()
+                }
+            })
+            .collect();
+        IntegrityBaseline { regions }
+    }
+
+    // Rehashes every baseline range, whatever the current region map looks like.
+    pub fn verify(&self, memory: &Memory) -> Vec<IntegrityViolation> {
+        self.regions
+            .iter()
+            .filter_map(|region| {
+                let actual = memory.hash_range(region.start_address, region.size).ok();
+                (actual != Some(region.hash)).then_some(IntegrityViolation {
+                    start_address: region.start_address,
+                    size: region.size,
+                    expected: region.hash,
+                    actual,
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,495 @@
+// Editing a range of the target byte by byte. An `EditBuffer` holds what the range read as and
+// the edits made on top of it; nothing reaches the target until `commit`, which writes all of
+// them as one batch:
+//
+//     let mut buffer = EditBuffer::load(&memory, address, 0x100)?;
+//     buffer.overwrite_hex(0x10, "90 90")?;
+//     buffer.overwrite_ascii(0x20, "hp")?;
+//     let written = buffer.commit(&memory)?;
+//
+// The range is read page by page, so a page that can't be read becomes a hole rather than a
+// failure, and no edit can be made inside one. `refresh` reads the range again and says which
+// bytes the target changed meanwhile; edits outlive it, including those on a page that has
+// since become unreadable, which `commit` then refuses until they are reverted or the page
+// reads again. `commit` (with the `patch` feature) merges adjacent edits into runs, leaves
+// out those the target already holds, and writes the runs as one patch batch (into code or
+// read-only pages too, see `apply_patches`), then reads each back. If a run fails, the runs
+// before it are put back as the batch found them, so the target gets every edit or none; a
+// run over an applied patch fails until the patch is reverted. The runs don't stay in the
+// patch list. The buffer knows nothing of terminals; the `edit` command is one front end.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::ops::Range;
+
+use crate::memory::Memory;
+#[cfg(feature = "patch")]
+use crate::patch::Patch;
+use crate::residency::PAGE_SIZE;
+
+// Bytes per line of `render`.
+pub const LINE_WIDTH: usize = 16;
+
+// A byte of the buffer: what the target held when last read (`None` if its page couldn't be
+// read) and the edit pending over it, if any.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub current: Option<u8>,
+    pub pending: Option<u8>,
+}
+
+impl Cell {
+    // What the byte will be after a commit.
+    pub fn shown(&self) -> Option<u8> {
+        self.pending.or(self.current)
+    }
+}
+
+// What `refresh` found, as offsets into the buffer.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RefreshReport {
+    // Bytes that read differently than before, edited or not.
+    pub changed: Vec<usize>,
+    // Ranges that read before and no longer do.
+    pub unreadable: Vec<Range<usize>>,
+    // Edits now over an unreadable byte.
+    pub stranded: usize,
+}
+
+// One write of a commit: consecutive edits, as an address and the bytes to put there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditRun {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EditBuffer {
+    base: usize,
+    current: Vec<Option<u8>>,
+    pending: BTreeMap<usize, u8>,
+}
+
+impl EditBuffer {
+    // `current` is what the range at `base` holds, `None` where it can't be read.
+    pub fn new(base: usize, current: Vec<Option<u8>>) -> Self {
+        EditBuffer { base, current, pending: BTreeMap::new() }
+    }
+
+    pub fn load(memory: &Memory, address: usize, len: usize) -> io::Result<Self> {
+        if len == 0 || address.checked_add(len).is_none() {
+            return Err(invalid_input(format!("Can't edit 0x{:X} bytes at 0x{:X}", len, address)));
+        }
+        let current = read_paged(memory, address, len);
+        if current.iter().all(Option::is_none) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("None of 0x{:X} bytes at 0x{:X} can be read", len, address),
+            ));
+        }
+        Ok(EditBuffer::new(address, current))
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    pub fn len(&self) -> usize {
+        self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current.is_empty()
+    }
+
+    pub fn cell(&self, offset: usize) -> Option<Cell> {
+        let current = *self.current.get(offset)?;
+        Some(Cell { current, pending: self.pending.get(&offset).copied() })
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    // Pending edits by offset.
+    pub fn pending(&self) -> &BTreeMap<usize, u8> {
+        &self.pending
+    }
+
+    pub fn set(&mut self, offset: usize, byte: u8) -> io::Result<()> {
+        self.overwrite(offset, &[byte])
+    }
+
+    // Puts `bytes` at `offset`, all of them or, if any would land outside the buffer or on an
+    // unreadable byte, none.
+    pub fn overwrite(&mut self, offset: usize, bytes: &[u8]) -> io::Result<()> {
+        let end = offset.checked_add(bytes.len()).filter(|end| *end <= self.len()).ok_or_else(|| {
+            invalid_input(format!("0x{:X} bytes at +0x{:X} go past the end (+0x{:X})", bytes.len(), offset, self.len()))
+        })?;
+        if let Some(hole) = (offset..end).find(|offset| self.current[*offset].is_none()) {
+            return Err(invalid_input(format!("0x{:X} can't be read, so it can't be edited", self.base + hole)));
+        }
+        for (i, byte) in bytes.iter().enumerate() {
+            self.pending.insert(offset + i, *byte);
+        }
+        Ok(())
+    }
+
+    // "90 90", "9090" or "90,90". Returns how many bytes were put.
+    pub fn overwrite_hex(&mut self, offset: usize, text: &str) -> io::Result<usize> {
+        let digits: String = text.chars().filter(|c| !c.is_whitespace() && *c != ',').collect();
+        if digits.is_empty() || digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid_input(format!("Invalid hex bytes '{}'", text)));
+        }
+        let bytes: Vec<u8> =
+            (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap()).collect();
+        self.overwrite(offset, &bytes)?;
+        Ok(bytes.len())
+    }
+
+    // The text's UTF-8 bytes, as typed into the ASCII column.
+    pub fn overwrite_ascii(&mut self, offset: usize, text: &str) -> io::Result<usize> {
+        if text.is_empty() {
+            return Err(invalid_input("No text to write".to_string()));
+        }
+        self.overwrite(offset, text.as_bytes())?;
+        Ok(text.len())
+    }
+
+    // Drops the edits in `range`, returning how many there were.
+    pub fn revert(&mut self, range: Range<usize>) -> usize {
+        let reverted: Vec<usize> = self.pending.range(range).map(|(offset, _)| *offset).collect();
+        for offset in &reverted {
+            self.pending.remove(offset);
+        }
+        reverted.len()
+    }
+
+    pub fn discard(&mut self) {
+        self.pending.clear();
+    }
+
+    // The writes a commit would make: runs of consecutive edits, without those that match what
+    // the target held when last read. Edits over unreadable bytes stay in, for `commit` to
+    // refuse.
+    pub fn plan(&self) -> Vec<EditRun> {
+        let mut runs: Vec<EditRun> = Vec::new();
+        let mut next = None;
+        for (&offset, &byte) in &self.pending {
+            if self.current[offset] == Some(byte) {
+                continue;
+            }
+            match runs.last_mut() {
+                Some(run) if next == Some(offset) => run.bytes.push(byte),
+                _ => runs.push(EditRun { address: self.base + offset, bytes: vec![byte] }),
+            }
+            next = Some(offset + 1);
+        }
+        runs
+    }
+
+    // Reads the range again. Edits are kept, whatever the target did underneath them.
+    pub fn refresh(&mut self, memory: &Memory) -> RefreshReport {
+        let fresh = read_paged(memory, self.base, self.len());
+        let mut report = RefreshReport::default();
+        for (offset, (old, new)) in self.current.iter().zip(&fresh).enumerate() {
+            match (old, new) {
+                (Some(_), None) => match report.unreadable.last_mut() {
+                    Some(range) if range.end == offset => range.end += 1,
+                    _ => report.unreadable.push(offset..offset + 1),
+                },
+                (Some(old), Some(new)) if old != new => report.changed.push(offset),
+                (None, Some(_)) => report.changed.push(offset),
+                _ => {}
+            }
+        }
+        self.current = fresh;
+        report.stranded = self.pending.keys().filter(|offset| self.current[**offset].is_none()).count();
+        report
+    }
+
+    // Writes the plan and checks each run reads back as written. Returns how many bytes were
+    // written; on failure nothing stays written and the edits stay pending.
+    #[cfg(feature = "patch")]
+    pub fn commit(&mut self, memory: &Memory) -> io::Result<usize> {
+        let runs = self.plan();
+        if let Some((offset, _)) = self.pending.iter().find(|(offset, _)| self.current[**offset].is_none()) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("0x{:X} can no longer be read; refresh, or revert the edits there", self.base + offset),
+            ));
+        }
+
+        // Each run is backed up just before it is written, so a rollback puts back what the
+        // target held then rather than what the buffer last read.
+        let patches: Vec<(usize, Vec<u8>)> = runs.iter().map(|run| (run.address, run.bytes.clone())).collect();
+        let applied = memory.apply_patches(&patches)?;
+        let reads_back =
+            |patch: &Patch| memory.read_bytes(patch.address, patch.len()).is_ok_and(|read| read == patch.patched);
+        let unverified = applied.iter().find(|patch| !reads_back(patch));
+        if let Some(patch) = unverified {
+            let e = io::Error::other(format!(
+                "0x{:X} bytes at 0x{:X} didn't read back as written",
+                patch.len(),
+                patch.address
+            ));
+            for patch in applied.iter().rev() {
+                if let Err(e) = memory.revert_patch(patch.address) {
+                    log::warn!("putting back 0x{:X} bytes at 0x{:X} failed: {}", patch.len(), patch.address, e);
+                }
+            }
+            return Err(e);
+        }
+        for patch in &applied {
+            memory.forget_patch(patch.address);
+        }
+
+        for (offset, byte) in std::mem::take(&mut self.pending) {
+            self.current[offset] = Some(byte);
+        }
+        Ok(runs.iter().map(|run| run.bytes.len()).sum())
+    }
+
+    // `lines` lines of `LINE_WIDTH` bytes from `offset`, laid out like `hex_dump` but with each
+    // byte marked by what comes before it: '>' at the cursor, '*' if edited. Unreadable bytes
+    // show as "??".
+    pub fn render(&self, offset: usize, lines: usize, cursor: Option<usize>) -> String {
+        let mut text = String::new();
+        let end = offset.saturating_add(lines.saturating_mul(LINE_WIDTH)).min(self.len());
+        for start in (offset..end).step_by(LINE_WIDTH) {
+            let _ = write!(text, "0x{:012X} ", self.base + start);
+            let mut ascii = String::new();
+            for offset in start..start + LINE_WIDTH {
+                let Some(cell) = self.cell(offset).filter(|_| offset < end) else {
+                    text.push_str("   ");
+                    continue;
+                };
+                text.push(match (cursor == Some(offset), cell.pending.is_some()) {
+                    (true, _) => '>',
+                    (false, true) => '*',
+                    (false, false) => ' ',
+                });
+                match cell.shown() {
+                    Some(byte) => {
+                        let _ = write!(text, "{:02X}", byte);
+                        ascii.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' });
+                    }
+                    None => {
+                        text.push_str("??");
+                        ascii.push(' ');
+                    }
+                }
+            }
+            text.push_str("  ");
+            text.push_str(&ascii);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+// `len` bytes at `address`, one page at a time so that one unreadable page costs only itself.
+fn read_paged(memory: &Memory, address: usize, len: usize) -> Vec<Option<u8>> {
+    let mut bytes = Vec::with_capacity(len);
+    let end = address + len;
+    let mut at = address;
+    while at < end {
+        let page_end = (at / PAGE_SIZE + 1).saturating_mul(PAGE_SIZE).min(end);
+        let read = memory.read_bytes(at, page_end - at).unwrap_or_default();
+        bytes.extend(read.iter().map(|byte| Some(*byte)));
+        bytes.resize(page_end - address, None);
+        at = page_end;
+    }
+    bytes
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::mock::{MockBackend, PAGE};
+    use crate::sys::PAGE_READONLY;
+
+    // Two read-write pages of counting bytes at 0x10000 and a read-only one after them.
+    fn target() -> (Arc<MockBackend>, Memory) {
+        let counting = |page: usize| (0..PAGE).map(|i| (page * PAGE + i) as u8).collect::<Vec<u8>>();
+        MockBackend::new()
+            .data(0x10000, counting(0))
+            .data(0x11000, counting(1))
+            .region(0x12000, counting(2), PAGE_READONLY)
+            .open()
+    }
+
+    fn buffer(bytes: &[u8]) -> EditBuffer {
+        EditBuffer::new(0x10000, bytes.iter().map(|byte| Some(*byte)).collect())
+    }
+
+    fn refused<T: std::fmt::Debug>(result: io::Result<T>) -> io::ErrorKind {
+        result.unwrap_err().kind()
+    }
+
+    #[test]
+    fn unreadable_pages_load_as_holes() {
+        let (mock, memory) = target();
+        mock.fail_reads(0x11000..0x12000);
+
+        let buffer = EditBuffer::load(&memory, 0x10F00, 0x200).unwrap();
+        assert_eq!((buffer.base(), buffer.len()), (0x10F00, 0x200));
+        assert_eq!(buffer.cell(0xFF), Some(Cell { current: Some(0xFF), pending: None }));
+        assert_eq!(buffer.cell(0x100), Some(Cell { current: None, pending: None }));
+        assert_eq!(buffer.cell(0x200), None);
+
+        assert_eq!(refused(EditBuffer::load(&memory, 0x11800, 0x10)), io::ErrorKind::PermissionDenied);
+        assert_eq!(refused(EditBuffer::load(&memory, 0x10000, 0)), io::ErrorKind::InvalidInput);
+        assert_eq!(refused(EditBuffer::load(&memory, usize::MAX, 2)), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn overwrites_are_all_or_nothing() {
+        let mut buffer = EditBuffer::new(0x10000, vec![Some(0), Some(1), None, Some(3)]);
+
+        assert_eq!(refused(buffer.overwrite(3, &[9, 9])), io::ErrorKind::InvalidInput);
+        assert_eq!(refused(buffer.overwrite(usize::MAX, &[9, 9])), io::ErrorKind::InvalidInput);
+        let e = buffer.overwrite(0, &[9, 9, 9]).unwrap_err();
+        assert_eq!(e.to_string(), "0x10002 can't be read, so it can't be edited");
+        assert!(!buffer.is_dirty());
+
+        buffer.overwrite(0, &[9, 8]).unwrap();
+        buffer.set(3, 7).unwrap();
+        assert_eq!(
+            buffer.pending().iter().map(|(offset, byte)| (*offset, *byte)).collect::<Vec<_>>(),
+            [(0, 9), (1, 8), (3, 7)]
+        );
+        assert_eq!(buffer.cell(1).unwrap().shown(), Some(8));
+        assert_eq!(buffer.cell(2).unwrap().shown(), None);
+    }
+
+    #[test]
+    fn hex_and_ascii_input() {
+        let mut buffer = buffer(&[0; 8]);
+        assert_eq!(buffer.overwrite_hex(0, "90 90").unwrap(), 2);
+        assert_eq!(buffer.overwrite_hex(2, "cCdD").unwrap(), 2);
+        assert_eq!(buffer.overwrite_hex(4, "0a,0B").unwrap(), 2);
+        assert_eq!(buffer.overwrite_ascii(6, "hp").unwrap(), 2);
+        let shown: Vec<u8> = (0..8).map(|offset| buffer.cell(offset).unwrap().shown().unwrap()).collect();
+        assert_eq!(shown, [0x90, 0x90, 0xCC, 0xDD, 0x0A, 0x0B, b'h', b'p']);
+
+        for text in ["", "9", "90 9", "9G", "0x90"] {
+            assert_eq!(refused(buffer.overwrite_hex(0, text)), io::ErrorKind::InvalidInput, "{:?}", text);
+        }
+        assert_eq!(refused(buffer.overwrite_ascii(0, "")), io::ErrorKind::InvalidInput);
+        assert_eq!(refused(buffer.overwrite_ascii(7, "hp")), io::ErrorKind::InvalidInput);
+        assert_eq!(buffer.cell(7).unwrap().shown(), Some(b'p'));
+    }
+
+    #[test]
+    fn plans_merge_runs_and_skip_what_is_already_there() {
+        let mut buffer = buffer(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        buffer.overwrite(0, &[9, 1, 9, 9, 4]).unwrap();
+        buffer.set(7, 9).unwrap();
+
+        assert_eq!(
+            buffer.plan(),
+            [
+                EditRun { address: 0x10000, bytes: vec![9] },
+                EditRun { address: 0x10002, bytes: vec![9, 9] },
+                EditRun { address: 0x10007, bytes: vec![9] },
+            ]
+        );
+        // Unchanged edits stay pending, and still show.
+        assert_eq!(buffer.pending().len(), 6);
+        assert!(buffer.cell(1).unwrap().pending.is_some());
+
+        assert_eq!(buffer.revert(2..4), 2);
+        assert_eq!(buffer.revert(2..4), 0);
+        assert_eq!(buffer.plan().len(), 2);
+        buffer.discard();
+        assert!(!buffer.is_dirty() && buffer.plan().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "patch")]
+    fn refreshes_report_changes_and_keep_the_edits() {
+        let (mock, memory) = target();
+        let mut buffer = EditBuffer::load(&memory, 0x10FF0, 0x20).unwrap();
+        buffer.set(0x02, 0xAA).unwrap();
+        buffer.set(0x18, 0xBB).unwrap();
+
+        memory.write_bytes(0x10FF2, &[0x55]).unwrap();
+        memory.write_bytes(0x10FF4, &[0x55]).unwrap();
+        mock.fail_reads(0x11000..0x12000);
+        let report = buffer.refresh(&memory);
+
+        assert_eq!(report, RefreshReport { changed: vec![0x02, 0x04], unreadable: vec![0x10..0x20], stranded: 1 });
+        assert_eq!(buffer.cell(0x02), Some(Cell { current: Some(0x55), pending: Some(0xAA) }));
+        assert_eq!(buffer.cell(0x18), Some(Cell { current: None, pending: Some(0xBB) }));
+
+        // The stranded edit holds up the commit until it is reverted.
+        assert_eq!(refused(buffer.commit(&memory)), io::ErrorKind::PermissionDenied);
+        assert_eq!(mock.bytes(0x10FF2, 1), [0x55]);
+        assert_eq!(buffer.revert(0x10..0x20), 1);
+        assert_eq!(buffer.commit(&memory).unwrap(), 1);
+        assert_eq!(mock.bytes(0x10FF2, 1), [0xAA]);
+    }
+
+    #[test]
+    #[cfg(feature = "patch")]
+    fn commits_write_every_run_and_leave_no_patches() {
+        let (mock, memory) = target();
+        let mut buffer = EditBuffer::load(&memory, 0x11FFE, 4).unwrap();
+        buffer.overwrite_hex(0, "90 90 90 90").unwrap();
+        buffer.set(1, 0xFF).unwrap();
+
+        assert_eq!(buffer.commit(&memory).unwrap(), 3);
+        // Straight through the read-only page, which keeps its protection.
+        assert_eq!((mock.bytes(0x11FFE, 2), mock.bytes(0x12000, 2)), (vec![0x90, 0xFF], vec![0x90, 0x90]));
+        assert_eq!(memory.query_address(0x12000).unwrap().protection, PAGE_READONLY);
+        assert!(memory.patches().is_empty());
+        assert!(!buffer.is_dirty());
+        assert_eq!(buffer.cell(0), Some(Cell { current: Some(0x90), pending: None }));
+        assert_eq!(buffer.commit(&memory).unwrap(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "patch")]
+    fn a_commit_that_fails_puts_everything_back() {
+        let (mock, memory) = target();
+        let mut buffer = EditBuffer::load(&memory, 0x10000, 0x2000).unwrap();
+        buffer.overwrite_hex(0x10, "AA AA").unwrap();
+        buffer.overwrite_hex(0x1010, "BB BB").unwrap();
+        mock.fail_writes(0x11000..0x12000);
+
+        assert!(buffer.commit(&memory).is_err());
+        assert_eq!(mock.bytes(0x10010, 2), [0x10, 0x11]);
+        assert_eq!(mock.bytes(0x11010, 2), [0x10, 0x11]);
+        assert_eq!(buffer.pending().len(), 4);
+        assert!(memory.patches().is_empty());
+    }
+
+    #[test]
+    fn renders_mark_the_cursor_edits_and_holes() {
+        let mut current: Vec<Option<u8>> = b"Hello, world!\x00\x01\x7F AB".iter().map(|byte| Some(*byte)).collect();
+        current[3] = None;
+        let mut buffer = EditBuffer::new(0x7FF6_1000, current);
+        buffer.set(0, b'J').unwrap();
+
+        assert_eq!(
+            buffer.render(0, 4, Some(1)),
+            concat!(
+                "0x00007FF61000 *4A>65 6C ?? 6F 2C 20 77 6F 72 6C 64 21 00 01 7F  Jel o, world!...\n",
+                "0x00007FF61010  20 41 42                                          AB\n",
+            )
+        );
+        // Lines start wherever asked, padded out to the full width.
+        let padding = " ".repeat(3 * (LINE_WIDTH - 2));
+        assert_eq!(buffer.render(17, 1, Some(17)), format!("0x00007FF61011 >41 42{}  AB\n", padding));
+        assert_eq!(buffer.render(32, 1, None), "");
+    }
+}
@@ -0,0 +1,106 @@
+// Finding the unreadable pages inside a range that failed to read as a whole. A guard page
+// or an inaccessible enclave page in the middle of an otherwise readable region makes a
+// chunked read of it fail; bisecting the chunk down to pages pins the failure to the pages
+// responsible, so scans, dumps and hashes can work around exactly those.
+
+use std::ops::Range;
+
+use crate::memory::Memory;
+use crate::residency::PAGE_SIZE;
+
+impl Memory {
+    // The parts of `range` that can't be read right now, sorted and merged, at page
+    // granularity (clipped to `range`). Empty if the whole range reads.
+    pub fn unreadable_ranges(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let mut holes = Vec::new();
+        if range.start < range.end {
+            let mut buffer = self.buffer_pool().rent_zeroed(range.len());
+            self.bisect_holes(range.clone(), range.start, &mut buffer, &mut holes);
+        }
+        holes
+    }
+
+    // `buffer` covers the range `unreadable_ranges` started with, from `origin`.
+    fn bisect_holes(&self, range: Range<usize>, origin: usize, buffer: &mut [u8], holes: &mut Vec<Range<usize>>) {
+        let window = &mut buffer[range.start - origin..range.end - origin];
+        if self.read_into(range.start, window).is_ok_and(|read| read == range.len()) {
+            return;
+        }
+
+        // Split on a page boundary; a range within one page is a hole as a whole.
+        let middle = (range.start + range.len() / 2) & !(PAGE_SIZE - 1);
+        let middle = if middle <= range.start { (range.start + 1).next_multiple_of(PAGE_SIZE) } else { middle };
+        if middle >= range.end {
+            match holes.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => holes.push(range),
+            }
+            return;
+        }
+        self.bisect_holes(range.start..middle, origin, buffer, holes);
+        self.bisect_holes(middle..range.end, origin, buffer, holes);
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use crate::backend::mock::MockBackend;
+    use crate::residency::PAGE_SIZE;
+
+    // Sixteen readable pages at 0x10000.
+    fn sixteen_pages() -> MockBackend {
+        MockBackend::new().data(0x10000, vec![0x5A; 16 * PAGE_SIZE])
+    }
+
+    #[test]
+    fn a_range_that_reads_has_no_holes() {
+        let (mock, memory) = sixteen_pages().open();
+        assert_eq!(memory.unreadable_ranges(0x10000..0x20000), []);
+        assert_eq!(mock.reads(), [0x10000..0x20000]);
+
+        mock.clear_calls();
+        assert_eq!(memory.unreadable_ranges(0x10000..0x10000), []);
+        assert!(mock.reads().is_empty());
+    }
+
+    #[test]
+    fn bisection_pins_a_hole_to_its_page() {
+        let (mock, memory) = sixteen_pages().open();
+        mock.fail_reads(0x1B000..0x1C000);
+
+        assert_eq!(memory.unreadable_ranges(0x10000..0x20000), [0x1B000..0x1C000]);
+        // The whole range, then both halves at each of the four levels down to a page.
+        assert_eq!(mock.reads().len(), 9, "{:X?}", mock.reads());
+    }
+
+    #[test]
+    fn neighbouring_holes_are_merged() {
+        let (mock, memory) = sixteen_pages().open();
+        mock.fail_reads(0x11000..0x12000);
+        mock.fail_reads(0x17000..0x19000);
+        mock.fail_reads(0x19000..0x1A000);
+        mock.fail_reads(0x1F000..0x20000);
+
+        assert_eq!(memory.unreadable_ranges(0x10000..0x20000), [0x11000..0x12000, 0x17000..0x1A000, 0x1F000..0x20000]);
+    }
+
+    #[test]
+    fn holes_are_clipped_to_the_range() {
+        let (mock, memory) = sixteen_pages().open();
+        mock.fail_reads(0x10000..0x11000);
+        mock.fail_reads(0x13000..0x14000);
+
+        assert_eq!(memory.unreadable_ranges(0x10800..0x13800), [0x10800..0x11000, 0x13000..0x13800]);
+        assert_eq!(memory.unreadable_ranges(0x13100..0x13200), [0x13100..0x13200]);
+        assert_eq!(memory.unreadable_ranges(0x11800..0x12800), []);
+    }
+
+    #[test]
+    fn a_range_that_never_reads_is_one_hole() {
+        let (mock, memory) = sixteen_pages().open();
+        mock.fail_reads(0x10000..0x20000);
+        assert_eq!(memory.unreadable_ranges(0x10000..0x20000), [0x10000..0x20000]);
+        assert_eq!(memory.unreadable_ranges(0x20000..0x22000), [0x20000..0x22000]);
+    }
+}
@@ -0,0 +1,139 @@
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::debug;
+
+use crate::disasm::MAX_INSTRUCTION_LEN;
+use crate::hooks::{build_trampoline, steal_instructions, JMP_REL32_LEN, TRAMPOLINE_SIZE};
+use crate::memory::{Memory, PAGE_EXECUTE_READWRITE};
+use crate::sys::{FlushInstructionCache, GetCurrentProcess, VirtualProtect};
+use crate::win_error::win_call;
+
+const BITNESS: u32 = usize::BITS;
+
+// A detour of a function in our own process, for use from injected code. The prologue is
+// patched with plain memory writes instead of WriteProcessMemory.
+//
+// Other threads may be executing the prologue while it is patched. When the patched bytes
+// fit in one aligned 8-byte word they are swapped in with a single atomic store, so a thread
+// sees either the old or the new prologue; otherwise a thread can observe a half-written
+// jump. Enable and disable hooks while the affected threads are suspended or known idle.
+pub struct LocalHook {
+    target: usize,
+    allocation: usize,
+    trampoline: usize,
+    original: Vec<u8>,
+    prologue: Vec<u8>,
+    enabled: bool,
+}
+
+impl LocalHook {
+    /// Prepares a detour of `target` to `detour` without enabling it.
+    ///
+    /// # Safety
+    ///
+    /// `target` must point to the start of a function in this process, and the detour must
+    /// have the same signature and calling convention as that function.
+    pub unsafe fn new(target: *const c_void, detour: *const c_void) -> io::Result<Self> {
+        let target = target as usize;
+        let code = slice::from_raw_parts(target as *const u8, JMP_REL32_LEN + MAX_INSTRUCTION_LEN);
+        let (stolen, stolen_len) = steal_instructions(code, target, BITNESS, JMP_REL32_LEN)?;
+
+        // Only the near allocation goes through the process handle; code is accessed directly.
+        let allocation = Memory::new()?.allocate_near(target, TRAMPOLINE_SIZE, PAGE_EXECUTE_READWRITE)?;
+        let trampoline = match build_trampoline(target, detour as usize, allocation, BITNESS, &stolen, stolen_len) {
+            Ok(trampoline) => trampoline,
+            Err(e) => {
+                let _ = Memory::new().and_then(|memory| memory.free(allocation));
+                return Err(e);
+            }
+        };
+        ptr::copy_nonoverlapping(trampoline.code.as_ptr(), allocation as *mut u8, trampoline.code.len());
+        FlushInstructionCache(GetCurrentProcess(), allocation as *const c_void, trampoline.code.len());
+
+        Ok(LocalHook {
+            target,
+            allocation,
+            trampoline: trampoline.entry,
+            original: code[..stolen_len].to_vec(),
+            prologue: trampoline.prologue,
+            enabled: false,
+        })
+    }
+
+    pub fn enable(&mut self) -> io::Result<()> {
+        if !self.enabled {
+            unsafe { patch_code(self.target, &self.prologue)? };
+            self.enabled = true;
+            debug!("enabled local hook at 0x{:X}", self.target);
+        }
+        Ok(())
+    }
+
+    pub fn disable(&mut self) -> io::Result<()> {
+        if self.enabled {
+            unsafe { patch_code(self.target, &self.original)? };
+            self.enabled = false;
+            debug!("disabled local hook at 0x{:X}", self.target);
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The original function, callable while the hook is enabled, e.g.
+    /// `let original: extern "system" fn(u32) -> u32 = hook.trampoline();`.
+    ///
+    /// # Safety
+    ///
+    /// `F` must be a function pointer type matching the hooked function.
+    pub unsafe fn trampoline<F: Copy>(&self) -> F {
+        assert_eq!(mem::size_of::<F>(), mem::size_of::<usize>(), "F must be a function pointer");
+        mem::transmute_copy(&self.trampoline)
+    }
+}
+
+impl Drop for LocalHook {
+    // The trampoline is freed even if restoring the prologue fails, so a thread still inside
+    // the detour or trampoline at this point will crash.
+    fn drop(&mut self) {
+        if let Err(e) = self.disable() {
+            debug!("failed to disable local hook at 0x{:X}: {}", self.target, e);
+        }
+        if let Err(e) = Memory::new().and_then(|memory| memory.free(self.allocation)) {
+            debug!("failed to free trampoline at 0x{:X}: {}", self.allocation, e);
+        }
+    }
+}
+
+// Writes `bytes` over code at `address` in this process, atomically when they fit in one
+// aligned 8-byte word.
+unsafe fn patch_code(address: usize, bytes: &[u8]) -> io::Result<()> {
+    let word = address & !7;
+    let mut old_protection = 0;
+    let len = (address + bytes.len()).max(word + 8) - word;
+    win_call!(
+        VirtualProtect(word as *mut c_void, len, PAGE_EXECUTE_READWRITE, &mut old_protection),
+        "Failed to make 0x{:X} writable",
+        address
+    )?;
+
+    if address + bytes.len() <= word + 8 {
+        let atomic = AtomicU64::from_ptr(word as *mut u64);
+        let mut value = atomic.load(Ordering::SeqCst).to_le_bytes();
+        value[address - word..address - word + bytes.len()].copy_from_slice(bytes);
+        atomic.store(u64::from_le_bytes(value), Ordering::SeqCst);
+    } else {
+        ptr::copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, bytes.len());
+    }
+
+    VirtualProtect(word as *mut c_void, len, old_protection, &mut old_protection);
+    FlushInstructionCache(GetCurrentProcess(), address as *const c_void, bytes.len());
+    Ok(())
+}
@@ -0,0 +1,143 @@
+// Detours: the first instructions of a function are moved into a trampoline and replaced by a
+// jump to the hook. Calling the trampoline runs the moved instructions and jumps back into the
+// rest of the original function.
+
+pub mod local;
+pub mod remote;
+
+use std::io;
+
+use iced_x86::{BlockEncoder, BlockEncoderOptions, Decoder, DecoderError, DecoderOptions, Instruction, InstructionBlock};
+
+pub use local::LocalHook;
+pub use remote::Hook;
+
+// Size of `jmp rel32`, the shortest jump that works as a detour.
+pub const JMP_REL32_LEN: usize = 5;
+
+// Size of `jmp [rip+0]` followed by its 8-byte target.
+pub const JMP_ABS64_LEN: usize = 14;
+
+// Room for the relay jump, the relocated prologue (which can grow when short branches are
+// widened) and the jump back.
+pub(crate) const TRAMPOLINE_SIZE: usize = 0x100;
+
+const NOP: u8 = 0x90;
+
+pub(crate) struct Trampoline {
+    // Bytes to place at the allocation.
+    pub(crate) code: Vec<u8>,
+    // Address that behaves like the unhooked function.
+    pub(crate) entry: usize,
+    // Replacement for the stolen bytes at the target: a jump into the detour, NOP-padded.
+    pub(crate) prologue: Vec<u8>,
+}
+
+// Lays out a trampoline at `allocation` for moving `stolen` (`stolen_len` bytes at `target`).
+// 64-bit hooks can be anywhere, so the prologue jumps to an absolute relay jump placed in
+// front of the trampoline, which is always within rel32 reach of the target.
+pub(crate) fn build_trampoline(
+    target: usize,
+    hook: usize,
+    allocation: usize,
+    bitness: u32,
+    stolen: &[Instruction],
+    stolen_len: usize,
+) -> io::Result<Trampoline> {
+    let mut code = Vec::with_capacity(TRAMPOLINE_SIZE);
+    let jump_target = if bitness == 64 {
+        code.extend_from_slice(&jmp_abs64(hook));
+        allocation
+    } else {
+        hook
+    };
+
+    let entry = allocation + code.len();
+    code.extend(relocate(stolen, entry, bitness)?);
+    let back = jmp_rel32(allocation + code.len(), target + stolen_len, bitness)
+        .ok_or_else(|| unreachable_jump(allocation, target))?;
+    code.extend_from_slice(&back);
+    if code.len() > TRAMPOLINE_SIZE {
+        return Err(io::Error::other(format!(
+            "Relocated prologue of 0x{:X} needs 0x{:X} bytes, more than the trampoline holds",
+            target,
+            code.len()
+        )));
+    }
+
+    let mut prologue = jmp_rel32(target, jump_target, bitness)
+        .ok_or_else(|| unreachable_jump(target, jump_target))?
+        .to_vec();
+    prologue.resize(stolen_len, NOP);
+
+    Ok(Trampoline { code, entry, prologue })
+}
+
+// Decodes whole instructions from the start of `code` (loaded at `address`) until at least
+// `min_len` bytes are covered, returning them with their total length.
+pub(crate) fn steal_instructions(
+    code: &[u8],
+    address: usize,
+    bitness: u32,
+    min_len: usize,
+) -> io::Result<(Vec<Instruction>, usize)> {
+    let mut decoder = Decoder::with_ip(bitness, code, address as u64, DecoderOptions::NONE);
+    let mut stolen = Vec::new();
+    let mut len = 0;
+
+    while len < min_len {
+        let instruction = decoder.decode();
+        if decoder.last_error() == DecoderError::NoMoreBytes || instruction.is_invalid() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to decode the instruction at 0x{:X}", address + len),
+            ));
+        }
+        len += instruction.len();
+        stolen.push(instruction);
+    }
+
+    Ok((stolen, len))
+}
+
+// Re-encodes `instructions` to run at `address`. Relative branches and rip-relative operands
+// are fixed up for the new location; if one of them can't reach its target from there the
+// whole relocation fails instead of producing code that jumps somewhere else.
+pub(crate) fn relocate(instructions: &[Instruction], address: usize, bitness: u32) -> io::Result<Vec<u8>> {
+    let block = InstructionBlock::new(instructions, address as u64);
+    BlockEncoder::encode(bitness, block, BlockEncoderOptions::NONE)
+        .map(|result| result.code_buffer)
+        .map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Failed to relocate the stolen instructions to 0x{:X}: {}", address, e),
+            )
+        })
+}
+
+// `jmp rel32` at `from` to `to`, or None if `to` is out of rel32 reach. In 32-bit code the
+// displacement wraps around the address space, so every target is reachable.
+pub(crate) fn jmp_rel32(from: usize, to: usize, bitness: u32) -> Option<[u8; JMP_REL32_LEN]> {
+    let next = from.checked_add(JMP_REL32_LEN)?;
+    let displacement = if bitness == 32 {
+        (to as u32).wrapping_sub(next as u32) as i32
+    } else {
+        i32::try_from(to as i128 - next as i128).ok()?
+    };
+
+    let mut jump = [0xE9, 0, 0, 0, 0];
+    jump[1..].copy_from_slice(&displacement.to_le_bytes());
+    Some(jump)
+}
+
+// `jmp [rip+0]` followed by the absolute 64-bit target; reaches anywhere.
+pub(crate) fn jmp_abs64(to: usize) -> [u8; JMP_ABS64_LEN] {
+    let mut jump = [0u8; JMP_ABS64_LEN];
+    jump[..6].copy_from_slice(&[0xFF, 0x25, 0, 0, 0, 0]);
+    jump[6..].copy_from_slice(&(to as u64).to_le_bytes());
+    jump
+}
+
+fn unreachable_jump(from: usize, to: usize) -> io::Error {
+    io::Error::other(format!("0x{:X} is out of rel32 reach of 0x{:X}", to, from))
+}
@@ -0,0 +1,80 @@
+use std::io;
+
+use log::debug;
+
+use crate::coordination::ArtifactKind;
+use crate::disasm::MAX_INSTRUCTION_LEN;
+use crate::hooks::{build_trampoline, steal_instructions, JMP_REL32_LEN, TRAMPOLINE_SIZE};
+use crate::memory::{Memory, PAGE_EXECUTE_READWRITE};
+
+// A detour installed in the target by `Memory::install_hook`. It stays in place until
+// `remove` is called; dropping the value leaves the hook installed.
+pub struct Hook<'a> {
+    memory: &'a Memory,
+    target: usize,
+    hook: usize,
+    allocation: usize,
+    trampoline: usize,
+}
+
+impl Memory {
+    // Redirects `target` to `hook`. At least `min_len` bytes (never less than a 5-byte jump)
+    // of whole instructions are moved into a trampoline allocated near `target`; call
+    // `Hook::trampoline` from the hook to run the original function.
+    //
+    // Relative branches and rip-relative operands in the moved bytes are re-encoded for the
+    // trampoline, and installing fails if that isn't possible. Code that jumps back into the
+    // middle of the moved bytes is not detected. With `set_safety_net` on, the prologue and
+    // the trampoline's allocation are saved to a recovery file before either is written.
+    pub fn install_hook(&self, target: usize, hook: usize, min_len: usize) -> io::Result<Hook<'_>> {
+        if self.is_dry_run() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Hooks cannot be installed in dry-run mode"));
+        }
+
+        let bitness = self.pointer_size()? as u32 * 8;
+        let min_len = min_len.max(JMP_REL32_LEN);
+        let code = self.read_bytes(target, min_len + MAX_INSTRUCTION_LEN)?;
+        let (stolen, stolen_len) = steal_instructions(&code, target, bitness, min_len)?;
+
+        let allocation = self.allocate_near(target, TRAMPOLINE_SIZE, PAGE_EXECUTE_READWRITE)?;
+        let installed = build_trampoline(target, hook, allocation, bitness, &stolen, stolen_len).and_then(|trampoline| {
+            let planned = [target..target + trampoline.prologue.len(), allocation..allocation + trampoline.code.len()];
+            self.arm_safety_net("hook", &planned)?;
+            self.write_protected(allocation, &trampoline.code)?;
+            self.apply_patch_claimed(ArtifactKind::Hook, target, &trampoline.prologue)?;
+            Ok(trampoline.entry)
+        });
+        match installed {
+            Ok(trampoline) => {
+                debug!("hooked 0x{:X} -> 0x{:X} (trampoline 0x{:X})", target, hook, trampoline);
+                Ok(Hook { memory: self, target, hook, allocation, trampoline })
+            }
+            Err(e) => {
+                let _ = self.free(allocation);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Hook<'_> {
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    pub fn hook(&self) -> usize {
+        self.hook
+    }
+
+    // Entry point that behaves like the unhooked target.
+    pub fn trampoline(&self) -> usize {
+        self.trampoline
+    }
+
+    // Restores the original prologue and frees the trampoline. A thread that is executing the
+    // trampoline at this moment will crash, so quiesce the target first if that matters.
+    pub fn remove(self) -> io::Result<()> {
+        self.memory.revert_patch(self.target)?;
+        self.memory.free(self.allocation)
+    }
+}
@@ -0,0 +1,114 @@
+// How likely a value-scan hit is to be a real value rather than a number that happens to sit
+// in zero-initialized or repeat-filled memory. A hit is judged by its neighborhood, the
+// aligned block of NEIGHBORHOOD bytes (one cache line) around it, minus the hit itself:
+//
+//     let options = ScanOptions::new().skip_uniform_neighborhood(NEIGHBORHOOD).rank_results(true);
+//     let report = memory.scan_for_value_report(100u32, &RegionFilter::new().writable(true), &options)?;
+//     for ranked in report.ranked.iter().take(10) {
+//         println!("0x{:X} scores {}", ranked.address, ranked.score);
+//     }
+//
+// `skip_uniform_neighborhood` drops hits whose neighborhood is one byte over and over, such
+// as a 100 alone in a line of zeroes, before they are ever returned. `rank_results` scores
+// the rest by the entropy and the variety of their neighborhood's bytes, highest first; the
+// plain list in `ScanReport::matches` stays as it was. Everything here is arithmetic over
+// bytes already read; no extra reads are made.
+
+use std::ops::Range;
+
+use serde::{Deserialize, Serialize};
+
+// One cache line.
+pub const NEIGHBORHOOD: usize = 64;
+
+// The highest score, for a neighborhood of all-different bytes.
+pub const MAX_SCORE: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RankedMatch {
+    pub address: usize,
+    // 0 to MAX_SCORE.
+    pub score: u32,
+}
+
+// The aligned block of `size` bytes containing `hit`'s start, grown to cover all of `hit`,
+// as a range of absolute addresses. `size` is rounded up to a power of two.
+pub fn neighborhood(hit: &Range<usize>, size: usize) -> Range<usize> {
+    let size = size.max(1).next_power_of_two();
+    let start = hit.start & !(size - 1);
+    let end = hit.end.max(start + size).div_ceil(size) * size;
+    start..end
+}
+
+// The bytes of `window` outside `hit`, a range of offsets into it.
+fn context<'a>(window: &'a [u8], hit: &Range<usize>) -> impl Iterator<Item = u8> + 'a {
+    let hit = hit.start.min(window.len())..hit.end.min(window.len());
+    window[..hit.start].iter().chain(&window[hit.end..]).copied()
+}
+
+// Whether every byte of `window` outside `hit` is the same one. A window with nothing
+// outside the hit is not uniform.
+pub fn is_uniform_around(window: &[u8], hit: &Range<usize>) -> bool {
+    let mut bytes = context(window, hit);
+    match bytes.next() {
+        Some(first) => bytes.all(|byte| byte == first),
+        None => false,
+    }
+}
+
+// Shannon entropy of the bytes, in bits per byte: 0 for one repeated byte, up to 8.
+pub fn entropy(bytes: impl IntoIterator<Item = u8>) -> f64 {
+    let (counts, total) = histogram(bytes);
+    if total == 0 {
+        return 0.0;
+    }
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+// Distinct byte values as a share of the most there could be: 1.0 when no byte repeats.
+pub fn diversity(bytes: impl IntoIterator<Item = u8>) -> f64 {
+    let (counts, total) = histogram(bytes);
+    if total == 0 {
+        return 0.0;
+    }
+    let distinct = counts.iter().filter(|&&count| count > 0).count();
+    distinct as f64 / total.min(256) as f64
+}
+
+// 0 to MAX_SCORE: the entropy of `window` outside `hit`, as a share of what its length
+// allows, and its diversity, weighted equally.
+pub fn score(window: &[u8], hit: &Range<usize>) -> u32 {
+    let len = context(window, hit).count();
+    if len < 2 {
+        return 0;
+    }
+    let most_entropy = (len.min(256) as f64).log2();
+    let entropy = entropy(context(window, hit)) / most_entropy;
+    let diversity = diversity(context(window, hit));
+    ((entropy + diversity) / 2.0 * MAX_SCORE as f64).round() as u32
+}
+
+// Scores, best first and by address among equals, one per address.
+pub(crate) fn rank(mut scored: Vec<RankedMatch>) -> Vec<RankedMatch> {
+    scored.sort_unstable_by_key(|ranked| ranked.address);
+    scored.dedup_by_key(|ranked| ranked.address);
+    scored.sort_by(|a, b| b.score.cmp(&a.score).then(a.address.cmp(&b.address)));
+    scored
+}
+
+fn histogram(bytes: impl IntoIterator<Item = u8>) -> ([usize; 256], usize) {
+    let mut counts = [0usize; 256];
+    let mut total = 0;
+    for byte in bytes {
+        counts[byte as usize] += 1;
+        total += 1;
+    }
+    (counts, total)
+}
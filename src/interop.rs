@@ -0,0 +1,552 @@
+// Exchanging addresses with other tools: x64dbg's database JSON (labels, comments and
+// bookmarks, keyed by module and RVA) and the `address,label,type` CSV that ReClass and the
+// Cheat Engine plugins read. Importing turns either into bookmarks, rebased as `Bookmarks::add`
+// does:
+//
+//     let results = interop::labeled_results(&memory.scan_for_value(100i32, &filter)?, Some("i32"));
+//     let absolute = interop::export_x64dbg_labels(&results, &memory.modules_or_live()?, "game.dd64")?;
+//
+//     let labels = interop::import_x64dbg("game.dd64")?;
+//     let failures = bookmarks.import(&memory, &labels);
+//
+// x64dbg stores labels module-relative. An address outside every module is written absolute
+// with an empty module, which x64dbg only applies within the same run of the target.
+//
+// `export_reclass` hands a structure mapped with a `StructTemplate` over to ReClass.NET as a
+// project file.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::address::Address;
+use crate::bookmarks::Bookmarks;
+use crate::export::csv_field;
+use crate::hash::Fnv1a;
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::struct_template::{FieldType, StructTemplate};
+
+// Comments carrying a type hint start with this, so the hint survives a round trip.
+const TYPE_COMMENT_PREFIX: &str = "type: ";
+
+const CSV_HEADER: &str = "address,label,type";
+
+// The file format version ReClass.NET 1.2 writes and reads.
+const RECLASS_VERSION: u32 = 0x0001_0001;
+
+// An address to export, with the label other tools show for it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabeledAddress {
+    pub address: usize,
+    pub label: String,
+    // A `StructTemplate` field type, e.g. "f32".
+    pub type_hint: Option<String>,
+}
+
+// Scan results labeled "result_0", "result_1", ... in the order given.
+pub fn labeled_results(addresses: &[usize], type_hint: Option<&str>) -> Vec<LabeledAddress> {
+    addresses
+        .iter()
+        .enumerate()
+        .map(|(index, &address)| LabeledAddress {
+            address,
+            label: format!("result_{}", index),
+            type_hint: type_hint.map(str::to_string),
+        })
+        .collect()
+}
+
+// An entry read from another tool's file, ready for `Bookmarks::add`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedLabel {
+    // "game.exe+0x1A2F30" for module-relative entries, "0x7FF6A1B2C3D0" for absolute ones.
+    pub expression: String,
+    pub label: String,
+    pub type_hint: Option<String>,
+    pub note: String,
+}
+
+// The parts of an x64dbg database (.dd32/.dd64) this module reads and writes. x64dbg keeps
+// the other sections when it loads a file without them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct X64dbgDatabase {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    labels: Vec<X64dbgEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    comments: Vec<X64dbgEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    bookmarks: Vec<X64dbgEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct X64dbgEntry {
+    // Empty for an absolute address.
+    #[serde(default)]
+    module: String,
+    // Hex RVA, or the absolute address without a module, e.g. "0x1A2F30".
+    address: String,
+    // False for what x64dbg's analysis added, which isn't imported.
+    #[serde(default)]
+    manual: bool,
+    // Absent for bookmarks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+}
+
+// Writes `results` as an x64dbg database with a label per address, and a comment holding the
+// type hint where there is one. Returns the addresses that lie outside every module in
+// `modules` and were written absolute.
+pub fn export_x64dbg_labels(
+    results: &[LabeledAddress],
+    modules: &[ModuleInfo],
+    path: impl AsRef<Path>,
+) -> io::Result<Vec<usize>> {
+    let mut database = X64dbgDatabase::default();
+    let mut absolute = Vec::new();
+    for result in results {
+        let (module, address) = match modules.iter().find(|module| module.contains(result.address)) {
+            Some(module) => (module.name.clone(), result.address - module.base_address),
+            None => {
+                absolute.push(result.address);
+                (String::new(), result.address)
+            }
+        };
+        let entry = |text: String| X64dbgEntry {
+            module: module.clone(),
+            address: format!("0x{:X}", address),
+            manual: true,
+            text: Some(text),
+        };
+        database.labels.push(entry(result.label.clone()));
+        if let Some(type_hint) = &result.type_hint {
+            database.comments.push(entry(format!("{}{}", TYPE_COMMENT_PREFIX, type_hint)));
+        }
+    }
+    if !absolute.is_empty() {
+        warn!("{} of {} addresses are outside every module and were exported absolute", absolute.len(), results.len());
+    }
+
+    let path = path.as_ref();
+    let writer = BufWriter::new(create(path)?);
+    serde_json::to_writer_pretty(writer, &database)?;
+    Ok(absolute)
+}
+
+// The user-made labels of an x64dbg database, with the comment at the same address as the
+// note (or as the type hint, if `export_x64dbg_labels` wrote it). Bookmarked addresses
+// without a label come out labeled by their expression.
+pub fn import_x64dbg(path: impl AsRef<Path>) -> io::Result<Vec<ImportedLabel>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to read {}: {}", path.display(), e)))?;
+    let database: X64dbgDatabase = serde_json::from_str(&text).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("{} is not an x64dbg database: {}", path.display(), e))
+    })?;
+
+    let mut comments: BTreeMap<String, &str> = BTreeMap::new();
+    for comment in database.comments.iter().filter(|comment| comment.manual) {
+        if let Some(text) = &comment.text {
+            comments.insert(expression(comment)?, text);
+        }
+    }
+
+    let mut imported = Vec::new();
+    for label in database.labels.iter().filter(|label| label.manual) {
+        let expression = expression(label)?;
+        let (type_hint, note) = match comments.get(&expression) {
+            Some(comment) => match comment.strip_prefix(TYPE_COMMENT_PREFIX) {
+                Some(type_hint) => (Some(type_hint.to_string()), String::new()),
+                None => (None, comment.to_string()),
+            },
+            None => (None, String::new()),
+        };
+        imported.push(ImportedLabel { expression, label: label.text.clone().unwrap_or_default(), type_hint, note });
+    }
+    for bookmark in &database.bookmarks {
+        let expression = expression(bookmark)?;
+        if !imported.iter().any(|label| label.expression == expression) {
+            let note = comments.get(&expression).map(|comment| comment.to_string()).unwrap_or_default();
+            imported.push(ImportedLabel { label: expression.clone(), expression, type_hint: None, note });
+        }
+    }
+    Ok(imported)
+}
+
+// "game.exe+0x1A2F30", or "0x1A2F30" without a module.
+fn expression(entry: &X64dbgEntry) -> io::Result<String> {
+    let address: Address = entry.address.parse().map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidData, format!("Invalid x64dbg address '{}'", entry.address))
+    })?;
+    Ok(match entry.module.as_str() {
+        "" => format!("0x{:X}", address.get()),
+        module => format!("{}+0x{:X}", module, address.get()),
+    })
+}
+
+// Writes `results` as "address,label,type" lines under that header, addresses absolute in hex.
+pub fn export_csv(results: &[LabeledAddress], path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut writer = BufWriter::new(create(path)?);
+    writeln!(writer, "{}", CSV_HEADER)?;
+    for result in results {
+        writeln!(
+            writer,
+            "0x{:X},{},{}",
+            result.address,
+            csv_field(&result.label),
+            csv_field(result.type_hint.as_deref().unwrap_or_default())
+        )?;
+    }
+    writer.flush()
+}
+
+// Reads "address,label,type" lines; the header and the type column are optional, and the
+// address may be an expression such as "game.exe+0x1A2F30". Blank lines are skipped.
+pub fn import_csv(path: impl AsRef<Path>) -> io::Result<Vec<ImportedLabel>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to read {}: {}", path.display(), e)))?;
+
+    let mut imported = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || (number == 0 && line.eq_ignore_ascii_case(CSV_HEADER)) {
+            continue;
+        }
+        let fields = split_csv_line(line).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: unterminated quote", path.display(), number + 1))
+        })?;
+        let (expression, label, type_hint) = match fields.as_slice() {
+            [expression, label] => (expression, label, None),
+            [expression, label, type_hint] => (expression, label, Some(type_hint).filter(|hint| !hint.is_empty())),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}:{}: expected address,label[,type]", path.display(), number + 1),
+                ))
+            }
+        };
+        imported.push(ImportedLabel {
+            expression: expression.trim().to_string(),
+            label: label.clone(),
+            type_hint: type_hint.cloned(),
+            note: String::new(),
+        });
+    }
+    Ok(imported)
+}
+
+impl Bookmarks {
+    // Adds each imported label as a bookmark and returns those that failed, e.g. because the
+    // label is taken or the module isn't loaded. The rest are added even when some fail.
+    pub fn import(&mut self, memory: &Memory, labels: &[ImportedLabel]) -> Vec<(String, io::Error)> {
+        let mut failures = Vec::new();
+        for label in labels {
+            if let Err(e) = self.add(memory, &label.label, &label.expression, label.type_hint.as_deref(), &label.note) {
+                failures.push((label.label.clone(), e));
+            }
+        }
+        failures
+    }
+}
+
+fn create(path: &Path) -> io::Result<File> {
+    File::create(path).map_err(|e| io::Error::new(e.kind(), format!("Failed to create {}: {}", path.display(), e)))
+}
+
+// Fields of one CSV line, with quoted fields unquoted; None if a quote isn't closed.
+fn split_csv_line(line: &str) -> Option<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        return None;
+    }
+    fields.push(field);
+    Some(fields)
+}
+
+// Writes `template` at `base` as a ReClass.NET project (.rcnet): one class named after the
+// file, its fields as typed nodes and the gaps between them as hex nodes. The class address
+// is module-relative when `base` lies in a module. Each `ptr->T` field points at a class of
+// its own holding a single T, so the pointee shows up typed too. Fields overlapping the one
+// before (unions) are left out, as ReClass.NET can't show them.
+pub fn export_reclass(
+    memory: &Memory,
+    template: &StructTemplate,
+    base: usize,
+    path: impl AsRef<Path>,
+) -> io::Result<()> {
+    let path = path.as_ref();
+    let pointer_size = memory.pointer_size()?;
+    let name = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or("Class").to_string();
+    let modules = memory.modules_or_live()?;
+    let address = match modules.iter().find(|module| module.contains(base)) {
+        Some(module) => format!("<{}>+{:X}", module.name, base - module.base_address),
+        None => format!("{:X}", base),
+    };
+
+    let mut classes = Vec::new();
+    let mut nodes = Vec::new();
+    let mut end = 0;
+    for field in &template.fields {
+        if field.offset < end {
+            debug!("leaving out '{}' at 0x{:X}: it overlaps the field before", field.name, field.offset);
+            continue;
+        }
+        hex_nodes(&mut nodes, end..field.offset, "pad");
+        end = field.offset + field.ty.size(pointer_size);
+        match &field.ty {
+            FieldType::Deref(target) => nodes.push(match target.as_ref() {
+                FieldType::Utf8(None) => ReclassNode::new("Utf8TextPtrNode", &field.name),
+                FieldType::Utf16(None) => ReclassNode::new("Utf16TextPtrNode", &field.name),
+                target => {
+                    let mut pointee = ReclassClass {
+                        name: format!("{}_{}", name, field.name),
+                        address: "0".to_string(),
+                        nodes: Vec::new(),
+                    };
+                    field_nodes(&mut pointee.nodes, target, 0, "value");
+                    let reference = Some(pointee.uuid());
+                    classes.push(pointee);
+                    ReclassNode { reference, ..ReclassNode::new("PointerNode", &field.name) }
+                }
+            }),
+            ty => field_nodes(&mut nodes, ty, field.offset, &field.name),
+        }
+    }
+    classes.insert(0, ReclassClass { name, address, nodes });
+
+    let xml = reclass_xml(&classes, pointer_size);
+    fs::write(path, zip_stored("Data.xml", xml.as_bytes()))
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to write {}: {}", path.display(), e)))
+}
+
+struct ReclassClass {
+    name: String,
+    // A ReClass.NET address formula, e.g. "<game.exe>+1A2F30".
+    address: String,
+    nodes: Vec<ReclassNode>,
+}
+
+impl ReclassClass {
+    // ReClass.NET links classes by a GUID, written as base64. Derived from the name so an
+    // export of the same template comes out the same.
+    fn uuid(&self) -> String {
+        let mut bytes = [0u8; 16];
+        for (seed, half) in bytes.chunks_mut(8).enumerate() {
+            let mut hasher = Fnv1a::with_seed(seed as u64);
+            hasher.update(self.name.as_bytes());
+            half.copy_from_slice(&hasher.finish().to_le_bytes());
+        }
+        base64(&bytes)
+    }
+}
+
+struct ReclassNode {
+    // The node's class name in ReClass.NET, e.g. "Int32Node".
+    kind: &'static str,
+    name: String,
+    // Characters, for inline text nodes.
+    length: Option<usize>,
+    // UUID of the class a pointer node points at.
+    reference: Option<String>,
+}
+
+impl ReclassNode {
+    fn new(kind: &'static str, name: &str) -> Self {
+        ReclassNode { kind, name: name.to_string(), length: None, reference: None }
+    }
+}
+
+// The nodes for a field of type `ty` at `offset`, which isn't behind a pointer.
+fn field_nodes(nodes: &mut Vec<ReclassNode>, ty: &FieldType, offset: usize, name: &str) {
+    let kind = match ty {
+        FieldType::U8 => "UInt8Node",
+        FieldType::U16 => "UInt16Node",
+        FieldType::U32 => "UInt32Node",
+        FieldType::U64 => "UInt64Node",
+        FieldType::I8 => "Int8Node",
+        FieldType::I16 => "Int16Node",
+        FieldType::I32 => "Int32Node",
+        FieldType::I64 => "Int64Node",
+        FieldType::F32 => "FloatNode",
+        FieldType::F64 => "DoubleNode",
+        FieldType::Ptr | FieldType::Deref(_) => "PointerNode",
+        FieldType::Utf8(len) => {
+            nodes.push(ReclassNode { length: *len, ..ReclassNode::new("Utf8TextNode", name) });
+            return;
+        }
+        FieldType::Utf16(len) => {
+            nodes.push(ReclassNode { length: *len, ..ReclassNode::new("Utf16TextNode", name) });
+            return;
+        }
+        FieldType::Bytes(len) => return hex_nodes(nodes, offset..offset + len, name),
+    };
+    nodes.push(ReclassNode::new(kind, name));
+}
+
+// Hex nodes covering `range`, each as wide as its offset's alignment allows, named
+// "<name>_<offset>".
+fn hex_nodes(nodes: &mut Vec<ReclassNode>, range: Range<usize>, name: &str) {
+    let mut offset = range.start;
+    while offset < range.end {
+        let (kind, size) = hex_kind(range.end - offset, offset);
+        nodes.push(ReclassNode::new(kind, &format!("{}_{:04X}", name, offset)));
+        offset += size;
+    }
+}
+
+// The widest hex node that fits in `len` bytes at `offset`, with its size.
+fn hex_kind(len: usize, offset: usize) -> (&'static str, usize) {
+    [("Hex64Node", 8), ("Hex32Node", 4), ("Hex16Node", 2), ("Hex8Node", 1)]
+        .into_iter()
+        .find(|&(_, size)| size <= len && offset % size == 0)
+        .unwrap_or(("Hex8Node", 1))
+}
+
+fn reclass_xml(classes: &[ReclassClass], pointer_size: usize) -> String {
+    let platform = if pointer_size == 8 { "x64" } else { "x86" };
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml += &format!("<reclass version=\"{}\" platform=\"{}\" type=\"ReClass.NET\">\n", RECLASS_VERSION, platform);
+    xml += "  <custom_data />\n  <type_mapping />\n  <enums />\n  <classes>\n";
+    for class in classes {
+        xml += &format!(
+            "    <class uuid=\"{}\" name=\"{}\" comment=\"\" address=\"{}\">\n",
+            class.uuid(),
+            xml_escape(&class.name),
+            xml_escape(&class.address)
+        );
+        for node in &class.nodes {
+            xml += &format!("      <node {}", node_attributes(node.kind, &node.name));
+            if let Some(length) = node.length {
+                xml += &format!(" length=\"{}\"", length);
+            }
+            match &node.reference {
+                // Pointers hold a class instance node naming the class they point at.
+                Some(reference) => {
+                    xml += ">\n";
+                    xml += &format!(
+                        "        <node {} reference=\"{}\" />\n",
+                        node_attributes("ClassInstanceNode", ""),
+                        reference
+                    );
+                    xml += "      </node>\n";
+                }
+                None => xml += " />\n",
+            }
+        }
+        xml += "    </class>\n";
+    }
+    xml += "  </classes>\n</reclass>\n";
+    xml
+}
+
+fn node_attributes(kind: &str, name: &str) -> String {
+    format!("type=\"{}\" name=\"{}\" comment=\"\" hidden=\"false\"", kind, xml_escape(name))
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| group | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// A ZIP archive holding `data` uncompressed as `name`, which is what a .rcnet file is.
+fn zip_stored(name: &str, data: &[u8]) -> Vec<u8> {
+    let crc = crc32(data);
+    let (size, name_len) = (data.len() as u32, name.len() as u16);
+    // Version needed 2.0, no flags, stored, 00:00 on 1980-01-01.
+    let common = |out: &mut Vec<u8>| {
+        for value in [20u16, 0, 0, 0, 0x21] {
+            out.extend(value.to_le_bytes());
+        }
+        out.extend(crc.to_le_bytes());
+        out.extend(size.to_le_bytes());
+        out.extend(size.to_le_bytes());
+        out.extend(name_len.to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+    };
+
+    let mut zip = Vec::with_capacity(data.len() + 2 * name.len() + 100);
+    zip.extend(0x0403_4B50u32.to_le_bytes());
+    common(&mut zip);
+    zip.extend(name.as_bytes());
+    zip.extend(data);
+
+    let directory = zip.len() as u32;
+    zip.extend(0x0201_4B50u32.to_le_bytes());
+    zip.extend(20u16.to_le_bytes());
+    common(&mut zip);
+    // Comment length, disk, internal and external attributes, offset of the local header.
+    for value in [0u16, 0, 0] {
+        zip.extend(value.to_le_bytes());
+    }
+    zip.extend(0u32.to_le_bytes());
+    zip.extend(0u32.to_le_bytes());
+    zip.extend(name.as_bytes());
+    let directory_len = zip.len() as u32 - directory;
+
+    zip.extend(0x0605_4B50u32.to_le_bytes());
+    for value in [0u16, 0, 1, 1] {
+        zip.extend(value.to_le_bytes());
+    }
+    zip.extend(directory_len.to_le_bytes());
+    zip.extend(directory.to_le_bytes());
+    zip.extend(0u16.to_le_bytes());
+    zip
+}
+
+// CRC-32 as ZIP uses it (reflected, polynomial 0xEDB88320).
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
@@ -0,0 +1,56 @@
+// Ctrl-C as a cancellation rather than a kill. By default the console ends the process on the
+// spot, so no guard gets to run: a suspended thread stays suspended, a page made writable
+// stays writable, a half-written file stays behind. `ctrl_c_token` installs a handler that
+// instead cancels a token the first time Ctrl-C or Ctrl-Break is pressed:
+//
+//     let mut options = memory.default_scan_options().clone();
+//     options.cancel = Some(interrupt::ctrl_c_token()?);
+//     let matches = memory.find_pattern_all_with(&pattern, &filter, &options)?;
+//     if interrupt::interrupted() { ... the scan stopped early ... }
+//
+// Long-running loops check `interrupted` and wind down as if their time were up, dropping
+// their guards on the way out. A second press gets the default handling, for when something
+// doesn't check.
+
+use std::io;
+use std::sync::{Mutex, OnceLock};
+
+use crate::scan::CancelToken;
+use crate::sys::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT};
+use crate::win_error::win_call;
+
+static TOKEN: OnceLock<CancelToken> = OnceLock::new();
+static INSTALLED: Mutex<bool> = Mutex::new(false);
+
+// The token Ctrl-C cancels, installing the handler on the first call. Every call returns a
+// clone of the same token.
+pub fn ctrl_c_token() -> io::Result<CancelToken> {
+    // The token exists before the handler does, so the handler always finds it.
+    let token = TOKEN.get_or_init(CancelToken::new).clone();
+    let mut installed = INSTALLED.lock().unwrap();
+    if !*installed {
+        win_call!(unsafe { SetConsoleCtrlHandler(Some(handler), 1) }, "Failed to install the Ctrl-C handler")?;
+        *installed = true;
+    }
+    Ok(token)
+}
+
+// Whether Ctrl-C was pressed since `ctrl_c_token` installed the handler; false before that.
+pub fn interrupted() -> bool {
+    TOKEN.get().is_some_and(CancelToken::is_cancelled)
+}
+
+// Runs on a thread the console starts for the event. Returning 0 passes the event on to the
+// next handler, ending with the default one that exits the process.
+unsafe extern "system" fn handler(event: u32) -> i32 {
+    if event != CTRL_C_EVENT && event != CTRL_BREAK_EVENT {
+        return 0;
+    }
+    match TOKEN.get() {
+        Some(token) if !token.is_cancelled() => {
+            token.cancel();
+            1
+        }
+        _ => 0,
+    }
+}
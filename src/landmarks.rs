@@ -0,0 +1,267 @@
+// Addresses kept relative to each other: "health is player + 0x1C8". An anchor is an absolute
+// address, typically re-found by a scan after every restart; linked landmarks sit at a fixed
+// offset from an anchor or from another linked landmark, and move with it:
+//
+//     let mut landmarks = Landmarks::new();
+//     landmarks.set_anchor("player", player_address);
+//     landmarks.link("player", "health", 0x1C8)?;
+//     landmarks.set_type("health", FieldType::F32)?;
+//     // ... the target restarts, a value scan finds the player again ...
+//     for (name, address) in landmarks.update_anchor("player", new_player_address)? {
+//         println!("{} moved to 0x{:X}", name, address);
+//     }
+//     for check in landmarks.verify(&memory) {
+//         println!("{}", check);
+//     }
+//
+// `verify` probes each typed landmark and says whether what's there still looks like its
+// type, which catches an offset that was right for the last build only. Landmarks serialize
+// with serde, and `WatchedValue::from_landmarks` streams the typed ones.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::Memory;
+use crate::probe::{Guess, ProbeResult};
+use crate::struct_template::{self, FieldType};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Position {
+    Anchor(usize),
+    // `offset` bytes from the landmark named `anchor`, which may be linked itself.
+    Linked { anchor: String, offset: i64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Landmark {
+    pub position: Position,
+    // A `StructTemplate` field type, as for bookmarks.
+    pub type_hint: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Landmarks {
+    landmarks: BTreeMap<String, Landmark>,
+}
+
+impl Landmarks {
+    pub fn new() -> Self {
+        Landmarks::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Landmark> {
+        self.landmarks.get(name)
+    }
+
+    // Landmarks in name order with where they are now; None for those whose chain doesn't
+    // reach an anchor or overflows.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Landmark, Option<usize>)> {
+        self.landmarks.iter().map(|(name, landmark)| (name.as_str(), landmark, self.address(name)))
+    }
+
+    // Adds or moves an anchor. A linked landmark of this name becomes an anchor, keeping its
+    // type; its dependents follow it.
+    pub fn set_anchor(&mut self, name: &str, address: usize) {
+        let type_hint = self.landmarks.remove(name).and_then(|landmark| landmark.type_hint);
+        self.landmarks.insert(name.to_string(), Landmark { position: Position::Anchor(address), type_hint });
+    }
+
+    // Places `name` at `offset` from `anchor`, replacing any landmark of that name but keeping
+    // its type. Fails if `anchor` doesn't exist or the link would make a cycle.
+    pub fn link(&mut self, anchor: &str, name: &str, offset: i64) -> io::Result<()> {
+        if !self.landmarks.contains_key(anchor) {
+            return Err(not_found(anchor));
+        }
+        if self.chain(anchor).any(|step| step == name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Linking '{}' to '{}' would make a cycle", name, anchor),
+            ));
+        }
+        let type_hint = self.landmarks.remove(name).and_then(|landmark| landmark.type_hint);
+        let position = Position::Linked { anchor: anchor.to_string(), offset };
+        self.landmarks.insert(name.to_string(), Landmark { position, type_hint });
+        Ok(())
+    }
+
+    pub fn set_type(&mut self, name: &str, ty: FieldType) -> io::Result<()> {
+        let landmark = self.landmarks.get_mut(name).ok_or_else(|| not_found(name))?;
+        landmark.type_hint = Some(ty.to_string());
+        Ok(())
+    }
+
+    // Removes `name`. Landmarks linked to it are removed too, and returned with it.
+    pub fn remove(&mut self, name: &str) -> Vec<String> {
+        if !self.landmarks.contains_key(name) {
+            return Vec::new();
+        }
+        let dependents = self.dependents(name);
+        self.landmarks.remove(name);
+        let mut removed = vec![name.to_string()];
+        for dependent in dependents {
+            self.landmarks.remove(&dependent);
+            removed.push(dependent);
+        }
+        removed
+    }
+
+    // Where `name` is now: its anchor's address plus the offsets along the way.
+    pub fn address(&self, name: &str) -> Option<usize> {
+        let mut offset: i64 = 0;
+        let mut current = name;
+        // A chain is at most as long as there are landmarks; `link` keeps cycles out.
+        for _ in 0..=self.landmarks.len() {
+            match &self.landmarks.get(current)?.position {
+                Position::Anchor(address) => return address.checked_add_signed(offset.try_into().ok()?),
+                Position::Linked { anchor, offset: step } => {
+                    offset = offset.checked_add(*step)?;
+                    current = anchor;
+                }
+            }
+        }
+        None
+    }
+
+    // Moves the anchor `name` and returns every landmark that depends on it, directly or
+    // through other links, with its new address, in name order.
+    pub fn update_anchor(&mut self, name: &str, address: usize) -> io::Result<Vec<(String, usize)>> {
+        match self.landmarks.get_mut(name).map(|landmark| &mut landmark.position) {
+            Some(Position::Anchor(current)) => *current = address,
+            Some(Position::Linked { .. }) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}' is linked, not an anchor; `set_anchor` turns it into one", name),
+                ))
+            }
+            None => return Err(not_found(name)),
+        }
+        let mut moved: Vec<(String, usize)> = self
+            .dependents(name)
+            .into_iter()
+            .filter_map(|dependent| self.address(&dependent).map(|address| (dependent, address)))
+            .collect();
+        moved.sort();
+        Ok(moved)
+    }
+
+    // Probes every typed landmark, in name order.
+    pub fn verify(&self, memory: &Memory) -> Vec<LandmarkCheck> {
+        self.landmarks
+            .iter()
+            .filter_map(|(name, landmark)| Some((name, landmark.type_hint.as_deref()?)))
+            .map(|(name, type_hint)| {
+                let status = match (self.address(name), type_hint.parse::<FieldType>()) {
+                    (None, _) => CheckStatus::Unresolved,
+                    (_, Err(e)) => CheckStatus::Unreadable(e.to_string()),
+                    (Some(address), Ok(ty)) => check(memory, address, &ty),
+                };
+                LandmarkCheck { name: name.clone(), address: self.address(name), status }
+            })
+            .collect()
+    }
+
+    // `name`, then what it's linked to, up to the anchor.
+    fn chain<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        let mut next = Some(name);
+        std::iter::from_fn(move || {
+            let current = next?;
+            next = match &self.landmarks.get(current)?.position {
+                Position::Linked { anchor, .. } => Some(anchor.as_str()),
+                Position::Anchor(_) => None,
+            };
+            Some(current)
+        })
+        .take(self.landmarks.len())
+    }
+
+    // Every landmark whose chain passes through `name`, `name` itself excluded.
+    fn dependents(&self, name: &str) -> Vec<String> {
+        self.landmarks
+            .keys()
+            .filter(|other| other.as_str() != name && self.chain(other).any(|step| step == name))
+            .cloned()
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CheckStatus {
+    // The probe found the type among its guesses.
+    Matches,
+    // It didn't; `best` is what it guessed instead.
+    Mismatch { best: String },
+    // Read fine, but the probe has no guess for this type (integers, raw bytes).
+    Readable,
+    Unreadable(String),
+    // The chain doesn't reach an anchor.
+    Unresolved,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LandmarkCheck {
+    pub name: String,
+    pub address: Option<usize>,
+    pub status: CheckStatus,
+}
+
+impl LandmarkCheck {
+    // Whether nothing suggests the landmark went wrong.
+    pub fn passed(&self) -> bool {
+        matches!(self.status, CheckStatus::Matches | CheckStatus::Readable)
+    }
+}
+
+// "health 0x1F2A01C8: ok", "ammo 0x1F2A01D0: expected f32, looks like pointer 0x7FF6A0000000".
+impl fmt::Display for LandmarkCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "{} 0x{:X}: ", self.name, address)?,
+            None => write!(f, "{}: ", self.name)?,
+        }
+        match &self.status {
+            CheckStatus::Matches => f.write_str("ok"),
+            CheckStatus::Mismatch { best } => write!(f, "looks like {} instead", best),
+            CheckStatus::Readable => f.write_str("readable, type not checked"),
+            CheckStatus::Unreadable(e) => write!(f, "unreadable: {}", e),
+            CheckStatus::Unresolved => f.write_str("its anchor is gone"),
+        }
+    }
+}
+
+fn check(memory: &Memory, address: usize, ty: &FieldType) -> CheckStatus {
+    if let Err(e) = struct_template::read_field(memory, address, ty) {
+        return CheckStatus::Unreadable(e.to_string());
+    }
+    let probe = match memory.probe(address) {
+        Ok(probe) => probe,
+        Err(e) => return CheckStatus::Unreadable(e.to_string()),
+    };
+    match agrees(ty, &probe) {
+        Some(true) => CheckStatus::Matches,
+        Some(false) => CheckStatus::Mismatch { best: probe.best().value.to_string() },
+        None => CheckStatus::Readable,
+    }
+}
+
+// Whether one of the probe's guesses is of type `ty`; None for types it never guesses.
+fn agrees(ty: &FieldType, probe: &ProbeResult) -> Option<bool> {
+    let fits: fn(&Guess) -> bool = match ty {
+        FieldType::F32 => |guess| matches!(guess, Guess::F32(_) | Guess::F32x4(_)),
+        FieldType::F64 => |guess| matches!(guess, Guess::F64(_)),
+        // A pointer into nothing mapped doesn't count.
+        FieldType::Ptr | FieldType::Deref(_) => {
+            |guess| matches!(guess, Guess::Pointer { target, region } if *target == 0 || region.is_some())
+        }
+        FieldType::Utf8(_) => |guess| matches!(guess, Guess::Ascii(_)),
+        FieldType::Utf16(_) => |guess| matches!(guess, Guess::Utf16(_)),
+        _ => return None,
+    };
+    Some(probe.interpretations.iter().any(|interpretation| fits(&interpretation.value)))
+}
+
+fn not_found(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("No landmark '{}'", name))
+}
@@ -0,0 +1,469 @@
+// Instruction lengths without a disassembler, for copying code a whole instruction at a time
+// (hook prologues, signature bytes). Covers the one-byte, 0F, 0F38 and 0F3A opcode maps with
+// legacy, REX, VEX and EVEX prefixes; anything it doesn't recognize is reported as unknown
+// rather than guessed at. With the `disasm` feature, `copy_instructions` decodes with
+// iced-x86 instead.
+
+use std::io;
+
+use crate::memory::Memory;
+
+// Longest encodable x86 instruction.
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+// Length of the 64-bit instruction at the start of `code`; None if it is invalid, not covered
+// or cut off by the end of `code`.
+pub fn insn_len(code: &[u8]) -> Option<usize> {
+    decode_len(code, true)
+}
+
+// Same for 32-bit code. 16-bit addressing (an 0x67 prefix) is not covered.
+pub fn insn_len32(code: &[u8]) -> Option<usize> {
+    decode_len(code, false)
+}
+
+fn decode_len(code: &[u8], long_mode: bool) -> Option<usize> {
+    let mut at = 0;
+    let mut operand_16 = false;
+    let mut address_override = false;
+    let mut rex_w = false;
+
+    // Legacy prefixes, then an optional REX that has to come right before the opcode.
+    loop {
+        let byte = *code.get(at)?;
+        match byte {
+            0x66 => operand_16 = true,
+            0x67 => address_override = true,
+            0xF0 | 0xF2 | 0xF3 | 0x2E | 0x36 | 0x3E | 0x26 | 0x64 | 0x65 => {}
+            0x40..=0x4F if long_mode => {
+                rex_w = byte & 0x08 != 0;
+                at += 1;
+                // A legacy prefix after REX makes the REX ignored.
+                if is_legacy_prefix(*code.get(at)?) {
+                    rex_w = false;
+                    continue;
+                }
+                break;
+            }
+            _ => break,
+        }
+        at += 1;
+        if at >= MAX_INSTRUCTION_LEN {
+            return None;
+        }
+    }
+    if address_override && !long_mode {
+        return None;
+    }
+
+    let operand = Operands { operand_16, rex_w, long_mode, address_override };
+    let opcode = *code.get(at)?;
+    at += 1;
+    let form = match opcode {
+        0x0F => two_byte(code, &mut at, operand)?,
+        // In 32-bit code these are LES, LDS and BOUND unless ModRM would be a register form.
+        0xC4 | 0xC5 | 0x62 if long_mode || code.get(at).is_some_and(|&next| next >> 6 == 3) => {
+            vex(code, &mut at, opcode)?
+        }
+        _ => one_byte(opcode, code.get(at).copied(), operand)?,
+    };
+    finish(code, at, form)
+}
+
+fn is_legacy_prefix(byte: u8) -> bool {
+    matches!(byte, 0x66 | 0x67 | 0xF0 | 0xF2 | 0xF3 | 0x2E | 0x36 | 0x3E | 0x26 | 0x64 | 0x65)
+}
+
+#[derive(Clone, Copy)]
+struct Operands {
+    operand_16: bool,
+    rex_w: bool,
+    long_mode: bool,
+    address_override: bool,
+}
+
+impl Operands {
+    // Size of an Iz immediate (imm16 or imm32). REX.W takes precedence over 0x66, and a
+    // 64-bit operand still takes an imm32.
+    fn iz(self) -> usize {
+        if self.operand_16 && !self.rex_w {
+            2
+        } else {
+            4
+        }
+    }
+}
+
+// Whether the opcode is followed by a ModRM byte, and how many immediate bytes come after it.
+struct Form {
+    modrm: bool,
+    immediate: usize,
+}
+
+// Adds the ModRM, SIB, displacement and immediate bytes to the `at` bytes decoded so far.
+fn finish(code: &[u8], mut at: usize, form: Form) -> Option<usize> {
+    if form.modrm {
+        let modrm = *code.get(at)?;
+        at += 1;
+        let (mode, rm) = (modrm >> 6, modrm & 7);
+        if mode != 3 {
+            if rm == 4 {
+                let sib = *code.get(at)?;
+                at += 1;
+                if mode == 0 && sib & 7 == 5 {
+                    at += 4;
+                }
+            }
+            at += match (mode, rm) {
+                // disp32; RIP-relative in 64-bit code.
+                (0, 5) => 4,
+                (1, _) => 1,
+                (2, _) => 4,
+                _ => 0,
+            };
+        }
+    }
+    at += form.immediate;
+    (at <= code.len() && at <= MAX_INSTRUCTION_LEN).then_some(at)
+}
+
+// `next` is the byte after the opcode, the ModRM byte if there is one.
+fn one_byte(opcode: u8, next: Option<u8>, operand: Operands) -> Option<Form> {
+    let long_mode = operand.long_mode;
+    let iz = operand.iz();
+    let (modrm, immediate) = match opcode {
+        // The ALU block, eight times over: r/m forms, then AL/eAX with an immediate.
+        0x00..=0x3F => match opcode & 7 {
+            0..=3 => (true, 0),
+            4 => (false, 1),
+            5 => (false, iz),
+            // push/pop segment, daa/das/aaa/aas; 0x0F and the segment prefixes never get here.
+            _ if long_mode => return None,
+            _ => (false, 0),
+        },
+        // inc/dec; in 64-bit code only a second REX prefix gets here.
+        0x40..=0x4F if !long_mode => (false, 0),
+        0x50..=0x5F => (false, 0),
+        0x60 | 0x61 if !long_mode => (false, 0),
+        0x62 if !long_mode => (true, 0),
+        0x63 => (true, 0),
+        0x68 => (false, iz),
+        0x69 => (true, iz),
+        0x6A => (false, 1),
+        0x6B => (true, 1),
+        0x6C..=0x6F => (false, 0),
+        0x70..=0x7F => (false, 1),
+        0x80 | 0x83 => (true, 1),
+        0x82 if !long_mode => (true, 1),
+        0x81 => (true, iz),
+        0x84..=0x8F => (true, 0),
+        0x90..=0x99 | 0x9B..=0x9F => (false, 0),
+        // call ptr16:16/32
+        0x9A if !long_mode => (false, iz + 2),
+        0xA0..=0xA3 => (false, moffs_len(operand)),
+        0xA4..=0xA7 | 0xAA..=0xAF => (false, 0),
+        0xA8 => (false, 1),
+        0xA9 => (false, iz),
+        0xB0..=0xB7 => (false, 1),
+        0xB8..=0xBF => (false, if operand.rex_w { 8 } else { iz }),
+        0xC0 | 0xC1 => (true, 1),
+        0xC2 | 0xCA => (false, 2),
+        0xC3 | 0xC9 | 0xCB | 0xCC | 0xCF => (false, 0),
+        0xC4 | 0xC5 => (true, 0),
+        0xC6 => (true, 1),
+        0xC7 => (true, iz),
+        0xC8 => (false, 3),
+        0xCD => (false, 1),
+        0xCE if !long_mode => (false, 0),
+        0xD0..=0xD3 => (true, 0),
+        0xD4 | 0xD5 if !long_mode => (false, 1),
+        0xD7 => (false, 0),
+        0xD8..=0xDF => (true, 0),
+        0xE0..=0xE7 => (false, 1),
+        // call/jmp rel32; the operand size prefix doesn't shrink them in 64-bit code.
+        0xE8 | 0xE9 => (false, if long_mode { 4 } else { iz }),
+        0xEA if !long_mode => (false, iz + 2),
+        0xEB => (false, 1),
+        0xEC..=0xEF | 0xF1 | 0xF4 | 0xF5 | 0xF8..=0xFD => (false, 0),
+        // test r/m, imm (reg 0 and 1) has an immediate; not/neg/mul/div don't.
+        0xF6 => (true, if next.is_some_and(|modrm| (modrm >> 3) & 7 < 2) { 1 } else { 0 }),
+        0xF7 => (true, if next.is_some_and(|modrm| (modrm >> 3) & 7 < 2) { iz } else { 0 }),
+        0xFE | 0xFF => (true, 0),
+        _ => return None,
+    };
+    Some(Form { modrm, immediate })
+}
+
+// moffs operands of mov A0-A3 are address-sized.
+fn moffs_len(operand: Operands) -> usize {
+    match (operand.long_mode, operand.address_override) {
+        (true, false) => 8,
+        (true, true) | (false, false) => 4,
+        (false, true) => 2,
+    }
+}
+
+// The 0F map, including the 0F38 and 0F3A escapes. `at` points past the 0F byte and is
+// moved past the opcode.
+fn two_byte(code: &[u8], at: &mut usize, operand: Operands) -> Option<Form> {
+    let opcode = *code.get(*at)?;
+    *at += 1;
+    let (modrm, immediate) = match opcode {
+        0x38 => {
+            *at += 1;
+            (true, 0)
+        }
+        0x3A => {
+            *at += 1;
+            (true, 1)
+        }
+        0x04 | 0x0A | 0x0C | 0x24..=0x27 | 0x36 | 0x39 | 0x3B..=0x3F | 0x7A | 0x7B | 0xA6 | 0xA7 => return None,
+        0x05..=0x09 | 0x0B | 0x0E | 0x30..=0x37 | 0x77 | 0xA0..=0xA2 | 0xA8..=0xAA | 0xC8..=0xCF => (false, 0),
+        // 3DNow!: ModRM, then the real opcode as an immediate byte.
+        0x0F => (true, 1),
+        0x70..=0x73 | 0xA4 | 0xAC | 0xBA | 0xC2 | 0xC4..=0xC6 => (true, 1),
+        // jcc rel32
+        0x80..=0x8F => (false, if operand.long_mode { 4 } else { operand.iz() }),
+        _ => (true, 0),
+    };
+    Some(Form { modrm, immediate })
+}
+
+// VEX (C4, C5) and EVEX (62) encoded instructions. `at` points past the first prefix byte
+// and is moved past the opcode; the opcode map decides whether there is an immediate.
+fn vex(code: &[u8], at: &mut usize, prefix: u8) -> Option<Form> {
+    let map = match prefix {
+        0xC5 => {
+            *at += 1;
+            1
+        }
+        0xC4 => {
+            let map = *code.get(*at)? & 0x1F;
+            *at += 2;
+            map
+        }
+        _ => {
+            let map = *code.get(*at)? & 0x07;
+            *at += 3;
+            map
+        }
+    };
+    let opcode = *code.get(*at)?;
+    *at += 1;
+
+    let immediate = match map {
+        1 => usize::from(matches!(opcode, 0x70..=0x73 | 0xC2 | 0xC4..=0xC6)),
+        2 => 0,
+        3 => 1,
+        // The FP16 maps only exist for EVEX.
+        5 | 6 if prefix == 0x62 => 0,
+        _ => return None,
+    };
+    // vzeroupper / vzeroall have no ModRM.
+    let modrm = !(prefix != 0x62 && map == 1 && opcode == 0x77);
+    Some(Form { modrm, immediate })
+}
+
+impl Memory {
+    // Reads whole instructions from `address` until they add up to at least `min_bytes`,
+    // returning their bytes and total length, so the copy never ends inside an instruction.
+    // The bytes are copied as they are: relative branches and RIP-relative operands still
+    // point where they did from `address`. Decodes as 32- or 64-bit code depending on the
+    // target.
+    pub fn copy_instructions(&self, address: usize, min_bytes: usize) -> io::Result<(Vec<u8>, usize)> {
+        let long_mode = self.pointer_size()? == 8;
+        let window = min_bytes.saturating_add(MAX_INSTRUCTION_LEN);
+        let code = self.read_bytes(address, window)?;
+
+        let mut len = 0;
+        while len < min_bytes {
+            let Some(insn) = instruction_len(&code[len..], address + len, long_mode) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Failed to decode the instruction at 0x{:X}", address + len),
+                ));
+            };
+            len += insn;
+        }
+
+        Ok((code[..len].to_vec(), len))
+    }
+}
+
+#[cfg(feature = "disasm")]
+fn instruction_len(code: &[u8], address: usize, long_mode: bool) -> Option<usize> {
+    use iced_x86::{Decoder, DecoderOptions};
+
+    let bitness = if long_mode { 64 } else { 32 };
+    let mut decoder = Decoder::with_ip(bitness, code, address as u64, DecoderOptions::NONE);
+    let instruction = decoder.decode();
+    (!instruction.is_invalid()).then(|| instruction.len())
+}
+
+#[cfg(not(feature = "disasm"))]
+fn instruction_len(code: &[u8], _address: usize, long_mode: bool) -> Option<usize> {
+    decode_len(code, long_mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::MockBackend;
+    use crate::sys::PAGE_EXECUTE_READ;
+
+    // Each encoding with its length, as the Intel SDM gives it.
+    fn assert_lengths(len: fn(&[u8]) -> Option<usize>, cases: &[(&[u8], usize)]) {
+        for &(code, expected) in cases {
+            assert_eq!(len(code), Some(expected), "{:02X?}", code);
+            // Trailing bytes belong to the next instruction.
+            let mut longer = code.to_vec();
+            longer.extend([0x90; 4]);
+            assert_eq!(len(&longer), Some(expected), "{:02X?} followed by nops", code);
+            // One byte short is cut off, not shorter.
+            assert_eq!(len(&code[..code.len() - 1]), None, "{:02X?} cut short", code);
+        }
+    }
+
+    #[test]
+    fn common_64_bit_encodings() {
+        assert_lengths(
+            insn_len,
+            &[
+                (&[0x90], 1),                                                 // nop
+                (&[0xC3], 1),                                                 // ret
+                (&[0x55], 1),                                                 // push rbp
+                (&[0x48, 0x89, 0xE5], 3),                                     // mov rbp, rsp
+                (&[0x48, 0x83, 0xEC, 0x28], 4),                               // sub rsp, 0x28
+                (&[0x48, 0x81, 0xEC, 0x00, 0x01, 0x00, 0x00], 7),             // sub rsp, 0x100
+                (&[0x48, 0x8B, 0x05, 0x10, 0x20, 0x30, 0x40], 7),             // mov rax, [rip+disp32]
+                (&[0x48, 0x8B, 0x44, 0x24, 0x08], 5),                         // mov rax, [rsp+8]
+                (&[0x8B, 0x84, 0x24, 0x00, 0x01, 0x00, 0x00], 7),             // mov eax, [rsp+0x100]
+                (&[0x48, 0x8B, 0x04, 0x25, 0x28, 0x00, 0x00, 0x00], 8),       // mov rax, [0x28]
+                (&[0x65, 0x48, 0x8B, 0x04, 0x25, 0x60, 0x00, 0x00, 0x00], 9), // mov rax, gs:[0x60]
+                (&[0xC2, 0x08, 0x00], 3),                                     // ret 8
+                (&[0xC8, 0x10, 0x00, 0x00], 4),                               // enter 0x10, 0
+                (&[0xE8, 0x00, 0x00, 0x00, 0x00], 5),                         // call rel32
+                (&[0xEB, 0x05], 2),                                           // jmp rel8
+                (&[0x0F, 0x84, 0x00, 0x01, 0x00, 0x00], 6),                   // je rel32
+                (&[0xF3, 0x48, 0xAB], 3),                                     // rep stosq
+                (&[0xF0, 0x48, 0x0F, 0xB1, 0x0A], 5),                         // lock cmpxchg [rdx], rcx
+                (&[0x0F, 0x05], 2),                                           // syscall
+                (&[0x0F, 0x0B], 2),                                           // ud2
+                (&[0x0F, 0xB6, 0xC0], 3),                                     // movzx eax, al
+                (&[0x0F, 0xBA, 0xE0, 0x05], 4),                               // bt eax, 5
+                (&[0x0F, 0x1F, 0x44, 0x00, 0x00], 5),                         // nop [rax+rax]
+                (&[0x66, 0x0F, 0x1F, 0x84, 0x00, 0x00, 0x00, 0x00, 0x00], 9), // nop word [rax+rax+0]
+                (&[0x66, 0x0F, 0x38, 0x00, 0xC1], 5),                         // pshufb xmm0, xmm1
+                (&[0x66, 0x0F, 0x3A, 0x0F, 0xC1, 0x08], 6),                   // palignr xmm0, xmm1, 8
+            ],
+        );
+    }
+
+    #[test]
+    fn immediates_follow_the_operand_size() {
+        assert_lengths(
+            insn_len,
+            &[
+                (&[0xB8, 0x01, 0x00, 0x00, 0x00], 5),                   // mov eax, 1
+                (&[0x66, 0xB8, 0x34, 0x12], 4),                         // mov ax, 0x1234
+                (&[0x48, 0xB8, 1, 2, 3, 4, 5, 6, 7, 8], 10),            // mov rax, imm64
+                (&[0x66, 0x48, 0xB8, 1, 2, 3, 4, 5, 6, 7, 8], 11),      // REX.W wins over 0x66
+                (&[0x48, 0xC7, 0xC0, 0xFF, 0xFF, 0xFF, 0xFF], 7),       // mov rax, -1: imm32
+                (&[0x66, 0x48, 0xC7, 0xC0, 0xFF, 0xFF, 0xFF, 0xFF], 8), // still imm32
+                (&[0x66, 0xC7, 0x00, 0x34, 0x12], 5),                   // mov word [rax], imm16
+                (&[0x66, 0x81, 0xC1, 0x34, 0x12], 5),                   // add cx, imm16
+                (&[0x68, 0x00, 0x00, 0x00, 0x00], 5),                   // push imm32
+                (&[0x66, 0x68, 0x34, 0x12], 4),                         // push imm16
+                (&[0x66, 0xE8, 0x00, 0x00, 0x00, 0x00], 6),             // call rel32 all the same
+                (&[0xF6, 0xC1, 0x01], 3),                               // test cl, 1
+                (&[0xF6, 0xD8], 2),                                     // neg al
+                (&[0xF7, 0xC1, 0x01, 0x00, 0x00, 0x00], 6),             // test ecx, 1
+                (&[0x66, 0xF7, 0xC1, 0x01, 0x00], 5),                   // test cx, 1
+                (&[0xF7, 0xD8], 2),                                     // neg eax
+                (&[0xA1, 1, 2, 3, 4, 5, 6, 7, 8], 9),                   // mov eax, [moffs64]
+                (&[0x67, 0xA1, 1, 2, 3, 4], 6),                         // mov eax, [moffs32]
+                // A legacy prefix after REX voids it, so this is mov ax, imm16.
+                (&[0x48, 0x66, 0xB8, 0x34, 0x12], 5),
+            ],
+        );
+    }
+
+    #[test]
+    fn vex_and_evex_encodings() {
+        assert_lengths(
+            insn_len,
+            &[
+                (&[0xC5, 0xF8, 0x77], 3),                         // vzeroupper
+                (&[0xC5, 0xFD, 0x6F, 0x00], 4),                   // vmovdqa ymm0, [rax]
+                (&[0xC5, 0xF9, 0x70, 0xC1, 0x1B], 5),             // vpshufd xmm0, xmm1, 0x1B
+                (&[0xC4, 0xE2, 0x7D, 0x18, 0x00], 5),             // vbroadcastss ymm0, [rax]
+                (&[0xC4, 0xE3, 0x7D, 0x18, 0xC1, 0x01], 6),       // vinsertf128 ymm0, ymm0, xmm1, 1
+                (&[0x62, 0xF1, 0x7C, 0x48, 0x10, 0x00], 6),       // vmovups zmm0, [rax]
+                (&[0x62, 0xF1, 0x7C, 0x48, 0x10, 0x40, 0x01], 7), // vmovups zmm0, [rax+0x40]
+                (&[0x62, 0xF3, 0xFD, 0x48, 0x1A, 0xC1, 0x01], 7), // vinsertf64x4 zmm0, zmm0, ymm1, 1
+            ],
+        );
+    }
+
+    #[test]
+    fn common_32_bit_encodings() {
+        assert_lengths(
+            insn_len32,
+            &[
+                (&[0x40], 1),                               // inc eax
+                (&[0x06], 1),                               // push es
+                (&[0x60], 1),                               // pushad
+                (&[0x8B, 0x45, 0x08], 3),                   // mov eax, [ebp+8]
+                (&[0x8B, 0x04, 0x24], 3),                   // mov eax, [esp]
+                (&[0x8B, 0x05, 0x00, 0x10, 0x40, 0x00], 6), // mov eax, [disp32]
+                (&[0xA1, 0x00, 0x10, 0x40, 0x00], 5),       // mov eax, [moffs32]
+                (&[0xB8, 0x01, 0x00, 0x00, 0x00], 5),       // mov eax, 1
+                (&[0x66, 0xE8, 0x00, 0x00], 4),             // call rel16
+                (&[0x66, 0x0F, 0x84, 0x00, 0x00], 5),       // je rel16
+                (&[0x9A, 1, 2, 3, 4, 5, 6], 7),             // call ptr16:32
+                (&[0xEA, 1, 2, 3, 4, 5, 6], 7),             // jmp ptr16:32
+                (&[0xD4, 0x0A], 2),                         // aam
+                (&[0xC4, 0x00], 2),                         // les eax, [eax]
+                (&[0x62, 0x00], 2),                         // bound eax, [eax]
+                (&[0xC5, 0xF8, 0x77], 3),                   // vzeroupper: register form, so VEX
+            ],
+        );
+    }
+
+    #[test]
+    fn what_isnt_covered_is_unknown() {
+        // Invalid in 64-bit code.
+        for code in [&[0x06][..], &[0x60], &[0xD4, 0x0A], &[0x9A, 1, 2, 3, 4, 5, 6], &[0x0F, 0x0A]] {
+            assert_eq!(insn_len(code), None, "{:02X?}", code);
+        }
+        // 16-bit addressing, an undefined VEX map, and more prefixes than fit in 15 bytes.
+        assert_eq!(insn_len32(&[0x67, 0x8B, 0x00]), None);
+        assert_eq!(insn_len(&[0xC4, 0xE4, 0x7D, 0x18, 0x00]), None);
+        assert_eq!(insn_len(&[[0x66; 15].as_slice(), &[0x90]].concat()), None);
+        assert_eq!(insn_len(&[]), None);
+    }
+
+    #[test]
+    fn copies_end_on_an_instruction_boundary() {
+        // mov [rsp+8], rbx; push rdi; sub rsp, 0x20; then padding.
+        let mut code = vec![0x48, 0x89, 0x5C, 0x24, 0x08, 0x57, 0x48, 0x83, 0xEC, 0x20];
+        code.resize(0x1000, 0xCC);
+        let (_, memory) = MockBackend::new().region(0x10000, code, PAGE_EXECUTE_READ).pointer_size(8).open();
+
+        assert_eq!(memory.copy_instructions(0x10000, 5).unwrap().1, 5);
+        let (bytes, len) = memory.copy_instructions(0x10000, 6).unwrap();
+        assert_eq!((bytes.as_slice(), len), (&[0x48, 0x89, 0x5C, 0x24, 0x08, 0x57][..], 6));
+        assert_eq!(memory.copy_instructions(0x10000, 7).unwrap().1, 10);
+    }
+
+    #[test]
+    fn copies_fail_at_what_doesnt_decode() {
+        let mut code = vec![0x55, 0x06];
+        code.resize(0x1000, 0xCC);
+        let (_, memory) = MockBackend::new().region(0x10000, code, PAGE_EXECUTE_READ).pointer_size(8).open();
+
+        let e = memory.copy_instructions(0x10000, 2).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("0x10001"), "{}", e);
+    }
+}
@@ -0,0 +1,257 @@
+pub mod access_heatmap;
+pub mod address;
+pub mod address_space;
+pub mod alloc;
+pub mod allocation;
+pub mod allocation_tracer;
+pub mod artifacts;
+#[cfg(feature = "asm")]
+pub mod asm;
+mod atomic_file;
+#[cfg(feature = "async")]
+pub mod async_memory;
+mod backend;
+pub mod bits;
+#[cfg(feature = "files")]
+pub mod bookmarks;
+pub mod buffer_pool;
+pub mod capabilities;
+mod chunk_tuner;
+mod chunks;
+pub mod cluster;
+pub mod code_watch;
+pub mod coordination;
+#[cfg(feature = "debugger")]
+pub mod debug_session;
+pub mod diagnostics;
+#[cfg(feature = "disasm")]
+pub mod disasm;
+pub mod endian;
+pub mod export;
+pub mod exports;
+#[cfg(feature = "capi")]
+pub mod ffi;
+pub mod fill;
+pub mod filter;
+pub mod float;
+pub mod freeze;
+pub mod group;
+pub mod handles;
+pub mod hash;
+pub mod hex_edit;
+pub mod holes;
+pub mod interest;
+#[cfg(feature = "files")]
+pub mod interop;
+pub mod interrupt;
+#[cfg(feature = "hooks")]
+pub mod hooks;
+#[cfg(feature = "files")]
+pub mod landmarks;
+pub mod lde;
+pub mod live_diff;
+#[cfg(feature = "inject")]
+pub mod manual_map;
+pub mod memory;
+pub mod metadata;
+pub mod module;
+#[cfg(feature = "files")]
+pub mod module_diff;
+pub mod monitor;
+pub mod msvc;
+pub mod oplog;
+pub mod parse;
+#[cfg(feature = "patch")]
+pub mod patch;
+pub mod pattern;
+pub mod pe;
+pub mod persist;
+pub mod pod;
+pub mod pointer_map;
+pub mod pointers;
+pub mod predicate;
+pub mod preflight;
+pub mod probe;
+pub mod process;
+mod process_handle;
+pub mod process_tree;
+#[cfg(all(feature = "files", feature = "patch"))]
+pub mod profile;
+pub mod protection_guard;
+pub mod protection_monitor;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod range_recorder;
+pub mod read_log;
+pub mod reader;
+pub mod readonly;
+#[cfg(feature = "files")]
+pub mod recipe;
+pub mod rebase;
+pub mod recorder;
+mod region_cache;
+pub mod region_info;
+pub mod region_snapshot;
+#[cfg(feature = "inject")]
+pub mod remote_call;
+pub mod remote_ptr;
+pub mod remote_struct;
+#[cfg(feature = "inject")]
+pub mod remote_thread;
+pub mod rescan;
+pub mod residency;
+pub mod ring;
+pub mod rip;
+pub mod rtti;
+pub mod safety_net;
+#[cfg(feature = "sandbox")]
+pub mod sandbox;
+pub mod scan;
+pub mod sections;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod session;
+pub mod session_file;
+#[cfg(feature = "files")]
+pub mod session_state;
+#[cfg(feature = "inject")]
+pub mod shared;
+#[cfg(feature = "files")]
+pub mod signature;
+#[cfg(feature = "files")]
+pub mod signature_heal;
+pub mod sink;
+mod spans;
+pub mod stats;
+pub mod stitch;
+pub mod strings;
+#[cfg(feature = "files")]
+pub mod struct_template;
+mod sys;
+pub mod targets;
+pub mod threads;
+pub mod throttle;
+mod timeout;
+pub mod value_recorder;
+#[cfg(feature = "files")]
+pub mod value_stream;
+mod win_error;
+pub mod write_guard;
+pub mod writer;
+pub mod wstr;
+#[cfg(feature = "yara")]
+pub mod yara_scan;
+
+pub use access_heatmap::{AccessHeatmap, HeatmapEntry};
+pub use address::{Address, Size};
+pub use address_space::MapEntry;
+pub use alloc::{address_unavailable, AddressUnavailable, RemoteAllocation};
+pub use allocation::Allocation;
+pub use allocation_tracer::{AllocationReport, AllocationSite, AllocationTracer, SizeClass};
+#[cfg(feature = "files")]
+pub use bookmarks::{Bookmark, Bookmarks};
+pub use buffer_pool::{BufferPoolConfig, BufferPoolStats};
+pub use capabilities::{missing_capability, Capabilities, MissingCapability};
+pub use cluster::{Cluster, ClusterKind, ClusterSummary};
+pub use code_watch::{ByteChange, CodeChange, CodeWatch, CodeWatchReport};
+pub use coordination::{
+    artifact_conflict, Adoption, Artifact, ArtifactConflict, ArtifactKind, Coordinator, InstanceId, Peer,
+};
+#[cfg(feature = "async")]
+pub use async_memory::{AsyncMemory, ScanStream};
+#[cfg(feature = "debugger")]
+pub use debug_session::{DebugEvent, DebugSession, ModuleEvent};
+pub use diagnostics::{Capability, CheckOutcome, CheckResult, DebugPrivilege, OpenDiagnosis, ProtectionLevel};
+#[cfg(feature = "disasm")]
+pub use disasm::Insn;
+pub use endian::{Endianness, FromBytes, ToBytes};
+pub use exports::ExportResolver;
+pub use filter::RegionFilter;
+pub use float::FloatWidth;
+pub use freeze::{FreezeReport, Freezer, FrozenValue};
+pub use group::{GroupScan, Predicate};
+pub use handles::HandleInfo;
+pub use hex_edit::{Cell, EditBuffer, EditRun, RefreshReport};
+#[cfg(feature = "hooks")]
+pub use hooks::{Hook, LocalHook};
+pub use interest::RankedMatch;
+#[cfg(feature = "files")]
+pub use interop::{ImportedLabel, LabeledAddress};
+#[cfg(feature = "files")]
+pub use landmarks::{CheckStatus, Landmark, LandmarkCheck, Landmarks, Position};
+pub use live_diff::{BlockBytes, LiveDiff, LiveDiffReport};
+pub use memory::{
+    is_guard_page_error, partial_write, GuardPageError, Memory, MemoryRegion, PartialWrite, PlannedWrite, RegionType,
+    WritePolicy,
+};
+pub use metadata::ModuleChange;
+pub use hash::{IntegrityBaseline, IntegrityViolation, RegionHash};
+pub use module::ModuleInfo;
+#[cfg(feature = "files")]
+pub use module_diff::{ModuleBaseline, ModuleDiff};
+pub use monitor::{BlockChanges, ChangeEvent, ChangeMonitor, ChangeReport};
+pub use oplog::{OpEvent, OpResult};
+#[cfg(feature = "patch")]
+pub use patch::{Patch, PatchSet, SavedPatch};
+pub use pattern::Pattern;
+pub use pe::{ImportName, PeHeaders};
+pub use persist::{unsupported_version, Header, Persist, TargetIdentity, UnsupportedVersion};
+pub use pod::Pod;
+pub use pointer_map::{PointerMap, PointerMapOptions, PointerMapRefresh, PointerMapUsage};
+pub use pointers::PointerHit;
+pub use preflight::{Feature, PreflightReport, Readiness};
+pub use probe::{Guess, Interpretation, ProbeResult};
+pub use process::MainThread;
+pub use process_tree::{AccessLevel, ChildWatch, ProcessInfo, ProcessTree};
+#[cfg(all(feature = "files", feature = "patch"))]
+pub use profile::{Profile, ProfileReport};
+pub use protection_guard::ProtectionGuard;
+pub use protection_monitor::{ProtectionEvent, ProtectionEventKind, ProtectionMonitor};
+pub use range_recorder::{RangeRecorder, RecorderUsage, Timeline};
+pub use read_log::{ReadLog, RecordedError, RecordedRead, ReplayBackend};
+pub use reader::{HolePolicy, MemoryReader};
+pub use readonly::ReadOnlyMemory;
+#[cfg(feature = "files")]
+pub use recipe::{Recipe, RecipeError, RecipeFile, RecipeOutput};
+pub use rebase::{RebaseError, RebasedAddress, Rebaser, SavedPointerHit};
+pub use recorder::{Recorder, WriteRecord};
+pub use region_cache::RefreshProgress;
+pub use region_info::{RegionInfo, RegionState};
+pub use region_snapshot::{ProtectionChange, RegionDiff, RegionGrowth, RegionSnapshot};
+#[cfg(feature = "inject")]
+pub use remote_call::{CallConv, RemoteArg};
+pub use remote_ptr::RemotePtr;
+pub use rescan::{MatchLocation, RescanStrategy, Rescanned};
+pub use residency::PageInfo;
+pub use safety_net::{SafetyNet, SavedRange};
+#[cfg(feature = "sandbox")]
+pub use sandbox::{Sandbox, SandboxChain, SandboxConfig, SandboxLayout};
+pub use scan::{
+    CancelToken, MatchCallback, PatternMatches, ScanDirection, ScanOptions, ScanReport, SkipReason, StaleRegion,
+};
+pub use sections::SectionRef;
+pub use ring::{RingBuffer, TornRead};
+pub use session::{ScanFilter, ScanSession, ScanValue};
+#[cfg(feature = "files")]
+pub use session_state::{PatchOutcome, PatchRestore, Resolved, RestoreReport, SessionState};
+#[cfg(feature = "inject")]
+pub use shared::SharedChannel;
+#[cfg(feature = "files")]
+pub use signature::{CachedMatch, SigError, SignatureCache, SignatureEntry, SignatureFile};
+#[cfg(feature = "files")]
+pub use signature_heal::{heal_in_image, HealOutcome, HealedSignature, SignatureContext};
+pub use sink::{JsonLinesSink, ScanSink, SharedSink, SinkEvent, Tee};
+pub use stats::MemoryStats;
+pub use strings::{Encoding, FoundString, StringScan};
+#[cfg(feature = "files")]
+pub use struct_template::{DecodedStruct, StructTemplate};
+pub use targets::{RangeDiff, Session};
+pub use threads::{ThreadContext, ThreadStack};
+pub use throttle::{Throttle, ThrottleStats};
+pub use value_recorder::{ValueRecorder, ValueSummary};
+#[cfg(feature = "files")]
+pub use value_stream::{FrameValue, ValueFrame, ValueStream, ValueStreamReport, WatchedValue};
+pub use write_guard::{is_write_blocked, WriteBlocked, WriteGuard, WriteGuardBypass};
+pub use writer::MemoryWriter;
+#[cfg(feature = "yara")]
+pub use yara_scan::{YaraMatch, YaraStringMatch};
@@ -0,0 +1,208 @@
+// "What changed since I last looked", asked over and over without saving snapshots. A
+// baseline keeps one hash per small block of the regions it covers and nothing else, so it
+// costs a sixteenth of a byte per covered byte at the default block size; comparing re-reads
+// the same ranges, reports the blocks whose hash moved, and makes the new hashes the baseline:
+//
+//     memory.scan_memory()?;
+//     let mut diff = LiveDiff::baseline(&memory, &RegionFilter::new().writable(true));
+//     diff.materialize(&memory, player..player + 0x200)?;
+//     thread::sleep(Duration::from_secs(5));
+//     let report = diff.compare(&memory);
+//     for range in &report.changed {
+//         println!("0x{:X}-0x{:X}", range.start, range.end);
+//     }
+//     for block in &report.bytes {
+//         println!("0x{:X}: {:02X?} -> {:02X?}", block.range.start, block.old, block.new);
+//     }
+//
+// Old bytes can't be recovered from a hash, so only blocks named to `materialize` beforehand
+// keep theirs; their old and new contents come with the report. Blocks unreadable on either
+// side are counted, never reported as changed. This sits between `IntegrityBaseline`, which
+// hashes whole regions, and `ChangeMonitor`, which does this from a thread at page blocks;
+// the hashing is theirs.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::mem;
+use std::ops::Range;
+
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::monitor::for_each_block;
+use crate::scan::CHUNK_SIZE;
+
+pub const LIVE_BLOCK_SIZE: usize = 0x100;
+
+#[derive(Debug, Clone)]
+pub struct LiveDiff {
+    block_size: usize,
+    // The ranges covered, each with the index of its first block in `hashes`.
+    spans: Vec<(Range<usize>, usize)>,
+    hashes: Vec<Option<u64>>,
+    // Block index -> the block's bytes as of the last baseline, for materialized blocks.
+    kept: BTreeMap<usize, Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockBytes {
+    pub range: Range<usize>,
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LiveDiffReport {
+    // Changed blocks, adjacent ones merged, lowest address first.
+    pub changed: Vec<Range<usize>>,
+    // Old and new contents of the changed blocks that were materialized.
+    pub bytes: Vec<BlockBytes>,
+    pub changed_blocks: usize,
+    // Blocks that couldn't be read now or at the baseline.
+    pub unreadable_blocks: usize,
+}
+
+impl LiveDiff {
+    // Hashes the parts of the cached regions `filter` selects, in `LIVE_BLOCK_SIZE` blocks.
+    pub fn baseline(memory: &Memory, filter: &RegionFilter) -> Self {
+        Self::with_block_size(memory, filter, LIVE_BLOCK_SIZE).expect("LIVE_BLOCK_SIZE is a valid block size")
+    }
+
+    // `block_size` must be a power of two no larger than a scan chunk (1 MiB).
+    pub fn with_block_size(memory: &Memory, filter: &RegionFilter, block_size: usize) -> io::Result<Self> {
+        if !block_size.is_power_of_two() || block_size > CHUNK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Block size 0x{:X} is not a power of two up to 0x{:X}", block_size, CHUNK_SIZE),
+            ));
+        }
+
+        let mut spans = Vec::new();
+        let mut blocks = 0;
+        for range in memory.regions_filtered(filter).filter_map(|region| filter.clip(region)) {
+            let count = range.len().div_ceil(block_size);
+            spans.push((range, blocks));
+            blocks += count;
+        }
+        let mut diff = LiveDiff { block_size, spans, hashes: vec![None; blocks], kept: BTreeMap::new() };
+        diff.rehash(memory, |_, _, _| {});
+        Ok(diff)
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    // Blocks hashed per compare, the bound on what the baseline holds.
+    pub fn block_count(&self) -> usize {
+        self.hashes.len()
+    }
+
+    // Bytes covered, i.e. read by each compare.
+    pub fn covered_bytes(&self) -> usize {
+        self.spans.iter().map(|(range, _)| range.len()).sum()
+    }
+
+    // Heap bytes held: the hashes, the span list and materialized blocks.
+    pub fn memory_usage(&self) -> usize {
+        self.hashes.capacity() * mem::size_of::<Option<u64>>()
+            + self.spans.capacity() * mem::size_of::<(Range<usize>, usize)>()
+            + self.kept.values().map(|bytes| bytes.capacity() + mem::size_of::<(usize, Vec<u8>)>()).sum::<usize>()
+    }
+
+    // Keeps the current contents of every covered block overlapping `range`, so that later
+    // reports carry their old and new bytes. Returns how many blocks that added.
+    pub fn materialize(&mut self, memory: &Memory, range: Range<usize>) -> io::Result<usize> {
+        let mut added = 0;
+        for index in self.blocks_overlapping(&range) {
+            if self.kept.contains_key(&index) {
+                continue;
+            }
+            let block = self.block_range(index);
+            let bytes = memory.read_bytes(block.start, block.len())?;
+            if bytes.len() < block.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("Block 0x{:X}-0x{:X} is not readable", block.start, block.end),
+                ));
+            }
+            self.kept.insert(index, bytes);
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    // Stops keeping the bytes of the blocks overlapping `range`.
+    pub fn forget(&mut self, range: Range<usize>) {
+        for index in self.blocks_overlapping(&range) {
+            self.kept.remove(&index);
+        }
+    }
+
+    // What changed since the baseline or the last compare, which becomes the new baseline.
+    pub fn compare(&mut self, memory: &Memory) -> LiveDiffReport {
+        let mut report = LiveDiffReport::default();
+        let mut changed = Vec::new();
+        self.rehash(memory, |index, block, moved| match moved {
+            Some(true) => changed.push((index, block)),
+            Some(false) => {}
+            None => report.unreadable_blocks += 1,
+        });
+
+        report.changed_blocks = changed.len();
+        for (index, block) in changed {
+            if let Some(old) = self.kept.get_mut(&index) {
+                match memory.read_bytes(block.start, block.len()) {
+                    Ok(new) if new.len() == block.len() => {
+                        let old = mem::replace(old, new.clone());
+                        report.bytes.push(BlockBytes { range: block.clone(), old, new });
+                    }
+                    _ => {}
+                }
+            }
+            match report.changed.last_mut() {
+                Some(last) if last.end == block.start => last.end = block.end,
+                _ => report.changed.push(block),
+            }
+        }
+        report
+    }
+
+    // Hashes every block again, storing the new hashes, and tells `visit` each block's index,
+    // range and whether it changed (None if unreadable before or now).
+    fn rehash(&mut self, memory: &Memory, mut visit: impl FnMut(usize, Range<usize>, Option<bool>)) {
+        let mut buffer = memory.buffer_pool().rent_zeroed(CHUNK_SIZE);
+        let hashes = &mut self.hashes;
+        for (range, first) in &self.spans {
+            for_each_block(memory, range, self.block_size, &mut buffer[..], |offset, block, hash| {
+                let previous = mem::replace(&mut hashes[first + offset], hash);
+                let moved = match (previous, hash) {
+                    (Some(previous), Some(hash)) => Some(previous != hash),
+                    _ => None,
+                };
+                visit(first + offset, block, moved);
+            });
+        }
+    }
+
+    fn blocks_overlapping(&self, range: &Range<usize>) -> Vec<usize> {
+        let mut indices = Vec::new();
+        for (span, first) in &self.spans {
+            let start = span.start.max(range.start);
+            let end = span.end.min(range.end);
+            if start >= end {
+                continue;
+            }
+            let from = (start - span.start) / self.block_size;
+            let to = (end - span.start).div_ceil(self.block_size);
+            indices.extend(first + from..first + to);
+        }
+        indices
+    }
+
+    fn block_range(&self, index: usize) -> Range<usize> {
+        let span = self.spans.partition_point(|(_, first)| *first <= index) - 1;
+        let (range, first) = &self.spans[span];
+        let start = range.start + (index - first) * self.block_size;
+        start..(start + self.block_size).min(range.end)
+    }
+}
@@ -1,9 +1,1476 @@
-mod memory;
+use std::env;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use log::LevelFilter;
+use nirvana_notject::diagnostics;
+use nirvana_notject::export::hex_dump;
+use nirvana_notject::hex_edit::LINE_WIDTH;
+use nirvana_notject::interop;
+use nirvana_notject::interrupt::{self, interrupted};
+use nirvana_notject::live_diff::LIVE_BLOCK_SIZE;
+use nirvana_notject::parse::{parse_duration, parse_duration_in, parse_size};
+use nirvana_notject::predicate::count_by_pointee;
+use nirvana_notject::profile::apply_profile;
+use nirvana_notject::session_file::saved_value_type;
+use nirvana_notject::value_recorder;
+use nirvana_notject::{
+    AccessLevel, Address, AllocationTracer, Bookmarks, CancelToken, CheckOutcome, ChildWatch, ClusterSummary, CodeWatch,
+    EditBuffer, Encoding, JsonLinesSink, LiveDiff, Memory, Pattern, ProcessTree, Profile, ProtectionMonitor, ReadLog,
+    Rebaser, RecipeFile, RegionFilter, RegionSnapshot, ReplayBackend, SafetyNet, ScanFilter, ScanSession, ScanSink,
+    ScanValue, Session, SessionState, SignatureCache, SignatureFile, StructTemplate, Tee, ValueRecorder, ValueStream,
+    WatchedValue,
+};
 
-use std::io;
-use memory::Memory;
+fn init_logging(verbosity: usize) {
+    let level = match verbosity {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+}
+
+// --trace-out writes a Chrome trace (chrome://tracing, Perfetto) of the spans one command
+// went through.
+#[cfg(feature = "tracing")]
+fn init_tracing(path: Option<String>) -> io::Result<Option<tracing_chrome::FlushGuard>> {
+    use tracing_subscriber::prelude::*;
+
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    let (layer, guard) = tracing_chrome::ChromeLayerBuilder::new().file(path).build();
+    tracing_subscriber::registry().with(layer).init();
+    Ok(Some(guard))
+}
+
+#[cfg(not(feature = "tracing"))]
+fn init_tracing(path: Option<String>) -> io::Result<Option<()>> {
+    match path {
+        Some(_) => Err(invalid_input("--trace-out needs the `tracing` feature".to_string())),
+        None => Ok(None),
+    }
+}
+
+// How often loops that wait on a channel look at whether Ctrl-C was pressed.
+const CTRL_C_POLL: Duration = Duration::from_millis(100);
+
+// Makes the first Ctrl-C end the command's loop instead of the process, so that it winds
+// down as if its time were up and its guards run (see interrupt.rs).
+fn catch_ctrl_c() -> CancelToken {
+    interrupt::ctrl_c_token().unwrap_or_else(|e| {
+        log::warn!("{}; Ctrl-C ends the process at once", e);
+        CancelToken::new()
+    })
+}
+
+const USAGE: &str = "usage: nirvana-notject [-v|-vv] [--trace-out <file.json>] [command]
+
+commands:
+  regions <pid> [--all] [--residency] [--stats] [--csv] [--map] [--save <file>] [--diff <saved.json>]
+  write <pid> <address> <hex bytes> [--dry-run]
+  replace <pid> \"<find>\" \"<replace>\" [--any] [--dry-run] [--safety <dir>]   (--safety saves recovery files)
+  restore <pid> <recovery file>       (writes back what a safety net saved)
+  readlog record <pid> \"<pattern>\" <file> [--any] [--redact] [--json]   (scans and saves every read it made)
+  readlog replay <file> \"<pattern>\" [--any] [--json]                    (repeats the scan from the saved reads)
+  sigs <pid> <signatures.toml|.json> [--wait <seconds> | --cache <file>] [--heal <out file>]
+  recipe <pid> <recipes.toml|.txt>
+  launch <profile.toml|.json> [--wait <secs>] [--secs <n>] [--rate <fps>]
+      (waits for the profile's process, applies it, then holds its freezes and streams its
+       watches as JSON lines until --secs or Ctrl-C)
+  bm <pid> add <label> <address|module+offset> [--type <field type>] [--note <text>] [--dir <dir>]
+  bm <pid> list|rm <label>|goto <label> [--len <bytes>] [--dir <dir>]
+  bm <pid> import <labels.csv|x64dbg .dd64/.dd32> [--dir <dir>]
+  handles <pid>
+  doctor                              (checks what this system allows)
+  doctor <pid>                        (says why <pid> can't be opened, and what an open handle can do)
+  strings <pid> [--min <chars>] [--wide] [--unique]
+  scan save <pid> <u8..i64|f32|f64> <value|unknown> <file>
+  scan load <pid> <file> [--force] [--all]   (where the candidates are; --all lists each one)
+  scan next <pid> <file> <filter> [--force]
+      filters: changed, unchanged, increased, decreased, eq=N, increased-by=N,
+               decreased-by=N, increased-by-at-least=N, decreased-by-at-least=N
+  probe <pid> <address>               (guesses what the bytes there are)
+  edit <pid> <address> [len]          (hex editor; edits are written together on `w`)
+  dt <pid> <template file|fields> <address> [--json] [--reclass <file.rcnet>]
+  compare <pid a> <pid b> <module> <offset> <len>
+  record <pid> <u8..i64|f32|f64> <address>... [--secs <n>] [--interval-ms <n>] [--csv <file>]
+  stream <pid> [--rate <fps>] [--secs <n>] [--dir <dir>]   (typed bookmarks as JSON lines)
+  heatmap <pid> <address> [--len <bytes>] [--secs <n>] [--interval-ms <n>] [--top <n>]
+  codewatch <pid> <module> [--secs <n>] [--interval-ms <n>] [--dump <dir>]
+  protwatch <pid> [--secs <n>] [--interval-ms <n>] [--dump <dir>]
+  livediff <pid> [--secs <n>] [--interval-ms <n>] [--block <bytes>] [--all]   (changed ranges per interval)
+  alloctrace <pid> [--secs <n>] [--interval-ms <n>] [--csv <file>]   (code seen near new allocations)
+  pointers-into <pid> <module> [--top <n>]   (writable memory pointing into the module, by target)
+  session save <pid> <file> [--bookmarks <file>] [--patches <file>] [--sigs <file>]... [--scan <file>]
+  session restore <file>
+  tree <pid> [--watch <secs>]         (descendant processes; --watch reports new ones)
+  ps [--openable]                     (every process with its session, user and access level)
+  disasm <pid> <address> [count]      (needs the `disasm` feature)
+  serve <pid> <host:port>             (needs the `server` feature)
+
+sizes (--len, --block, compare's <len>) take a unit: 64K, 1MiB, or 0x1000 in hex; durations
+(--secs, --interval-ms, --wait, --watch) too: 250ms, 2s, 1m, with a bare --interval-ms in
+milliseconds and the others in seconds
+
+Ctrl-C ends the commands that run for --secs or --watch early, as if their time were up, and
+stops `scan save` without writing the file; pressing it again ends the process";
+
+// Lines of bytes `edit` shows at a time.
+const EDIT_PAGE_LINES: usize = 16;
+
+const EDIT_HELP: &str = "p print   n/b next/previous page   g <offset> move the cursor
+h <hex> overwrite from the cursor   a <text> the same with text   u [len] revert from the cursor
+d list pending edits   r reread the range   w write every edit, verified   q quit, dropping edits
+('>' is the cursor, '*' an edit, ?? a byte that can't be read)";
 
 fn main() -> io::Result<()> {
+    // A sandbox spawned by `Sandbox::spawn` runs here and never returns.
+    #[cfg(feature = "sandbox")]
+    nirvana_notject::sandbox::run_if_child();
+
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // -v enables debug logging, -vv (or -v -v) enables trace logging
+    let is_verbosity = |arg: &String| arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == 'v');
+    let verbosity = args.iter().filter(|arg| is_verbosity(arg)).map(|arg| arg.len() - 1).sum();
+    args.retain(|arg| !is_verbosity(arg));
+    init_logging(verbosity);
+    // Kept until the command returns, when it finishes writing the trace.
+    let _trace = init_tracing(take_value(&mut args, "--trace-out")?)?;
+
+    match args.first().map(String::as_str) {
+        None => demo(),
+        Some("regions") => regions_command(args[1..].to_vec()),
+        Some("write") => write_command(args[1..].to_vec()),
+        Some("replace") => replace_command(args[1..].to_vec()),
+        Some("restore") => restore_command(args[1..].to_vec()),
+        Some("readlog") => readlog_command(args[1..].to_vec()),
+        Some("sigs") => sigs_command(args[1..].to_vec()),
+        Some("recipe") => recipe_command(args[1..].to_vec()),
+        Some("launch") => launch_command(args[1..].to_vec()),
+        Some("bm") => bm_command(args[1..].to_vec()),
+        Some("handles") => handles_command(args[1..].to_vec()),
+        Some("doctor") => doctor_command(args[1..].to_vec()),
+        Some("strings") => strings_command(args[1..].to_vec()),
+        Some("scan") => scan_command(args[1..].to_vec()),
+        Some("record") => record_command(args[1..].to_vec()),
+        Some("stream") => stream_command(args[1..].to_vec()),
+        Some("heatmap") => heatmap_command(args[1..].to_vec()),
+        Some("codewatch") => codewatch_command(args[1..].to_vec()),
+        Some("protwatch") => protwatch_command(args[1..].to_vec()),
+        Some("livediff") => livediff_command(args[1..].to_vec()),
+        Some("alloctrace") => alloctrace_command(args[1..].to_vec()),
+        Some("pointers-into") => pointers_into_command(args[1..].to_vec()),
+        Some("tree") => tree_command(args[1..].to_vec()),
+        Some("ps") => ps_command(args[1..].to_vec()),
+        Some("session") => session_command(args[1..].to_vec()),
+        Some("probe") => probe_command(args[1..].to_vec()),
+        Some("edit") => edit_command(args[1..].to_vec()),
+        Some("dt") => dt_command(args[1..].to_vec()),
+        Some("compare") => compare_command(args[1..].to_vec()),
+        #[cfg(feature = "disasm")]
+        Some("disasm") => disasm_command(args[1..].to_vec()),
+        #[cfg(feature = "server")]
+        Some("serve") => serve_command(args[1..].to_vec()),
+        Some(command) => Err(invalid_input(format!("unknown command '{}'\n{}", command, USAGE))),
+    }
+}
+
+fn regions_command(mut args: Vec<String>) -> io::Result<()> {
+    let save = take_value(&mut args, "--save")?;
+    let diff = take_value(&mut args, "--diff")?;
+    let residency = take_flag(&mut args, "--residency");
+    let stats = take_flag(&mut args, "--stats");
+    let csv = take_flag(&mut args, "--csv");
+    let map = take_flag(&mut args, "--map");
+    let all = take_flag(&mut args, "--all");
+    if args.len() != 1 {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let mut memory = Memory::open(parse_pid(&args[0])?)?;
+    memory.scan_memory()?;
+    let snapshot = RegionSnapshot::capture(&memory);
+
+    match diff {
+        Some(path) => print_region_diff(&snapshot, &RegionSnapshot::load(path)?),
+        None if csv => memory.export_regions_csv(io::stdout().lock())?,
+        None if map => print!("{}", memory.render_map(80)),
+        None if all => {
+            for entry in memory.full_map()? {
+                println!("{}", entry);
+            }
+        }
+        None if stats => {
+            if let Err(e) = memory.scan_modules() {
+                log::debug!("module list unavailable: {}", e);
+            }
+            print!("{}", memory.memory_stats());
+        }
+        None => {
+            println!("Found {} memory regions:", snapshot.regions.len());
+            if residency {
+                for region in &snapshot.regions {
+                    match memory.resident_percentage(region) {
+                        Ok(percentage) => println!("{}  resident {:.0}%", region, percentage),
+                        Err(_) => println!("{}", region),
+                    }
+                }
+            } else {
+                print!("{}", memory.regions_table());
+            }
+        }
+    }
+
+    if let Some(path) = save {
+        snapshot.save(&path)?;
+        println!("Saved region snapshot to {}", path);
+    }
+
+    Ok(())
+}
+
+fn print_region_diff(current: &RegionSnapshot, saved: &RegionSnapshot) {
+    let diff = current.diff(saved);
+    if diff.is_empty() {
+        println!("No region changes");
+        return;
+    }
+
+    for region in &diff.added {
+        println!("+ 0x{:X} - 0x{:X} (Size: 0x{:X}, protection 0x{:X})",
+            region.start_address, region.end(), region.size, region.protection);
+    }
+    for region in &diff.removed {
+        println!("- 0x{:X} - 0x{:X} (Size: 0x{:X}, protection 0x{:X})",
+            region.start_address, region.end(), region.size, region.protection);
+    }
+    for growth in &diff.grown {
+        println!("> allocation 0x{:X} grew from 0x{:X} to 0x{:X} bytes",
+            growth.allocation_base, growth.old_size, growth.new_size);
+    }
+    for change in &diff.protection_changed {
+        println!("~ 0x{:X} (Size: 0x{:X}) protection 0x{:X} -> 0x{:X}",
+            change.start_address, change.size, change.old_protection, change.new_protection);
+    }
+}
+
+fn write_command(mut args: Vec<String>) -> io::Result<()> {
+    let dry_run = take_flag(&mut args, "--dry-run");
+    if args.len() < 3 {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let process_id = parse_pid(&args[0])?;
+    let address = parse_address(&args[1])?;
+    let bytes = parse_hex_bytes(&args[2..])?;
+
+    let mut memory = Memory::open(process_id)?;
+    memory.set_dry_run(dry_run);
+    memory.write_bytes(address, &bytes)?;
+
+    if dry_run {
+        for plan in memory.take_planned_writes() {
+            println!("Would write 0x{:X} bytes at 0x{:X} (region 0x{:X}, protection 0x{:X})",
+                plan.new_bytes.len(),
+                plan.address,
+                plan.region.start_address,
+                plan.region.protection
+            );
+            println!("  old: {}", format_hex(&plan.old_bytes));
+            println!("  new: {}", format_hex(&plan.new_bytes));
+        }
+    } else {
+        println!("Wrote 0x{:X} bytes at 0x{:X}", bytes.len(), address);
+    }
+
+    Ok(())
+}
+
+// Patches every match in executable memory, or in any readable memory with --any.
+fn replace_command(mut args: Vec<String>) -> io::Result<()> {
+    let any = take_flag(&mut args, "--any");
+    let dry_run = take_flag(&mut args, "--dry-run");
+    let safety = take_value(&mut args, "--safety")?.map(PathBuf::from);
+    if args.len() != 3 {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let process_id = parse_pid(&args[0])?;
+    let find = Pattern::parse(&args[1])?;
+    let replace = Pattern::parse(&args[2])?;
+    let filter = if any { RegionFilter::new() } else { RegionFilter::new().executable(true) };
+
+    let mut memory = Memory::open(process_id)?;
+    memory.set_dry_run(dry_run);
+    memory.set_safety_net(safety);
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+    let addresses = memory.replace_pattern(&find, &replace, &filter)?;
+    for address in &addresses {
+        match memory.symbolize(*address) {
+            Some(symbol) => println!("0x{:X} {}", address, symbol),
+            None => println!("0x{:X}", address),
+        }
+    }
+    let verb = if dry_run { "Would patch" } else { "Patched" };
+    println!("{} {} matches", verb, addresses.len());
+    Ok(())
+}
+
+fn restore_command(args: Vec<String>) -> io::Result<()> {
+    let [pid, path] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let memory = Memory::open(parse_pid(pid)?)?;
+    for range in SafetyNet::restore(path, &memory)? {
+        println!("restored 0x{:X} bytes at 0x{:X}", range.len(), range.start);
+    }
+    Ok(())
+}
+
+fn readlog_command(mut args: Vec<String>) -> io::Result<()> {
+    // --redact keeps only the bytes of the pattern's matches in the saved log
+    let redact = take_flag(&mut args, "--redact");
+    // --json prints the matches and the scan's numbers as JSON lines instead
+    let json = take_flag(&mut args, "--json");
+    let filter = if take_flag(&mut args, "--any") { RegionFilter::new() } else { RegionFilter::new().executable(true) };
+
+    let (memory, pattern) = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["record", pid, pattern, file] => {
+            let pattern = Pattern::parse(pattern)?;
+            let mut memory = Memory::open(parse_pid(pid)?)?;
+            memory.scan_memory()?;
+            memory.start_read_log();
+            let matches = print_matches(&memory, &pattern, &filter, json)?;
+            let log = memory.take_read_log().unwrap_or_default();
+            let log = if redact { log.redact(std::slice::from_ref(&pattern)) } else { log };
+            log.save(file)?;
+            if !json {
+                println!("{} matches, {} reads saved to {}", matches, log.reads.len(), file);
+            }
+            return Ok(());
+        }
+        ["replay", file, pattern] => {
+            let backend = ReplayBackend::new(ReadLog::load(file)?);
+            (Memory::from_replay(backend), Pattern::parse(pattern)?)
+        }
+        _ => return Err(invalid_input(USAGE.to_string())),
+    };
+
+    let matches = print_matches(&memory, &pattern, &filter, json)?;
+    if !json {
+        println!("{} matches", matches);
+    }
+    let misses = memory.replay_backend().map_or(0, ReplayBackend::misses);
+    if misses > 0 {
+        println!("{} reads were not in the log; the replay scanned differently from the recording", misses);
+    }
+    Ok(())
+}
+
+// Prints the matches as the scan finds them, through a sink (see sink.rs), and returns how
+// many there were.
+fn print_matches(memory: &Memory, pattern: &Pattern, filter: &RegionFilter, json: bool) -> io::Result<usize> {
+    let options = memory.default_scan_options();
+    if json {
+        let sink = Arc::new(Mutex::new(Tee(JsonLinesSink::new(io::stdout()), MatchCount(0))));
+        memory.find_pattern_into(pattern, filter, options, &sink)?;
+        let Tee(lines, count) = Arc::into_inner(sink).expect("the scan let go of its sink").into_inner().unwrap();
+        lines.finish()?;
+        return Ok(count.0);
+    }
+    let sink = Arc::new(Mutex::new(Tee(PrintMatches, MatchCount(0))));
+    memory.find_pattern_into(pattern, filter, options, &sink)?;
+    let count = sink.lock().unwrap().1 .0;
+    Ok(count)
+}
+
+// One address per line.
+struct PrintMatches;
+
+impl ScanSink for PrintMatches {
+    fn on_match(&mut self, address: usize) -> ControlFlow<()> {
+        println!("0x{:X}", address);
+        ControlFlow::Continue(())
+    }
+}
+
+struct MatchCount(usize);
+
+impl ScanSink for MatchCount {
+    fn on_match(&mut self, _: usize) -> ControlFlow<()> {
+        self.0 += 1;
+        ControlFlow::Continue(())
+    }
+}
+
+fn sigs_command(mut args: Vec<String>) -> io::Result<()> {
+    // --wait defers resolving until each signature's module has loaded
+    let wait = match take_value(&mut args, "--wait")? {
+        Some(seconds) => Some(parse_duration(&seconds)?),
+        None => None,
+    };
+    // --cache looks for each signature where it last matched first, and records where it did
+    let cache_path = take_value(&mut args, "--cache")?;
+    // --heal writes the file again with refreshed contexts and healed patterns, for review
+    let heal = take_value(&mut args, "--heal")?;
+    if args.len() != 2 {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+    if wait.is_some() && cache_path.is_some() {
+        return Err(invalid_input("--wait and --cache can't be combined".to_string()));
+    }
+    if heal.as_deref() == Some(args[1].as_str()) {
+        return Err(invalid_input("--heal must name a new file, not the signature file".to_string()));
+    }
+
+    let signatures = SignatureFile::load(&args[1])?;
+    let mut memory = Memory::open(parse_pid(&args[0])?)?;
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+
+    if let Some(cache_path) = &cache_path {
+        let mut cache = SignatureCache::load_or_default(cache_path)?;
+        let mut results: Vec<_> = signatures.resolve_all_cached(&memory, &mut cache).into_iter().collect();
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, result) in results {
+            match result {
+                Ok((address, strategy)) => println!("{} = 0x{:X} ({})", name, address, strategy),
+                Err(e) => println!("{} failed: {}", name, e),
+            }
+        }
+        cache.save(cache_path)?;
+    } else {
+        let mut results: Vec<_> = match wait {
+            Some(timeout) => signatures.resolve_all_deferred(&memory, Some(timeout)),
+            None => signatures.resolve_all(&memory),
+        }
+        .into_iter()
+        .collect();
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, result) in results {
+            match result {
+                Ok(address) => println!("{} = 0x{:X}", name, address),
+                Err(e) => println!("{} failed: {}", name, e),
+            }
+        }
+    }
+
+    if let Some(path) = heal {
+        let (healed, outcomes) = signatures.heal(&memory);
+        for (name, outcome) in outcomes {
+            println!("{}: {}", name, outcome);
+        }
+        healed.save(&path)?;
+        println!("proposed signatures written to {}", path);
+    }
+    Ok(())
+}
+
+fn recipe_command(args: Vec<String>) -> io::Result<()> {
+    let [pid, path] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let recipes = RecipeFile::load(path)?;
+    let mut memory = Memory::open(parse_pid(pid)?)?;
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+
+    for (name, result) in recipes.run_all(&memory) {
+        match result {
+            Ok(output) => println!("{} = {}", name, output),
+            Err(e) => println!("{}: {}", name, e),
+        }
+    }
+    Ok(())
+}
+
+// Bookmarks live in the current directory unless --dir says otherwise, one file per target.
+fn launch_command(mut args: Vec<String>) -> io::Result<()> {
+    // --wait gives up on the process and its modules after this long; unset waits forever
+    let wait = take_value(&mut args, "--wait")?.map(|wait| parse_duration(&wait)).transpose()?;
+    let duration = take_value(&mut args, "--secs")?.map(|secs| parse_duration(&secs)).transpose()?;
+    let rate: u32 = match take_value(&mut args, "--rate")? {
+        Some(rate) => parse_value(&rate)?,
+        None => 10,
+    };
+    let [path] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let profile = Profile::load(path)?;
+    let deadline = wait.map(|wait| Instant::now() + wait);
+    let remaining = || deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+    println!("waiting for {}", profile.target.process);
+    let mut memory = Memory::wait_for_process(&profile.target.process, remaining())?;
+    for module in profile.modules() {
+        memory.wait_for_module(module, remaining())?;
+    }
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+
+    let memory = Arc::new(memory);
+    let mut report = apply_profile(&memory, &profile)?;
+    println!("{}", report);
+    if report.freezer.is_none() && report.watches.is_empty() {
+        return Ok(());
+    }
+
+    let stream = match report.watches.is_empty() {
+        true => None,
+        false => Some(ValueStream::new(&memory, report.watches.clone(), rate)?),
+    };
+    let end = duration.map(|duration| Instant::now() + duration);
+    catch_ctrl_c();
+    let mut stdout = io::stdout().lock();
+    let mut result = Ok(());
+    while end.is_none_or(|end| Instant::now() < end) && !interrupted() {
+        let Some(stream) = &stream else {
+            thread::sleep(Duration::from_millis(100));
+            continue;
+        };
+        let Some(frame) = stream.recv_timeout(Duration::from_millis(100)) else { continue };
+        result = writeln!(stdout, "{}", frame.to_json()?).and_then(|()| stdout.flush());
+        if result.is_err() {
+            break;
+        }
+    }
+
+    // The patches stay applied.
+    let freezes = report.stop_freezes();
+    log::info!(
+        "{} freeze ticks, {} failed writes, {:.1} write calls per tick for {:.1} values",
+        freezes.ticks,
+        freezes.failed_writes,
+        freezes.write_calls_per_tick(),
+        freezes.values_per_tick()
+    );
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+fn bm_command(mut args: Vec<String>) -> io::Result<()> {
+    let dir = take_value(&mut args, "--dir")?.unwrap_or_else(|| ".".to_string());
+    let type_hint = take_value(&mut args, "--type")?;
+    let note = take_value(&mut args, "--note")?.unwrap_or_default();
+    let len = take_value(&mut args, "--len")?.map(|len| parse_size(&len)).transpose()?.unwrap_or(64);
+    if args.len() < 2 {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let mut memory = Memory::open(parse_pid(&args[0])?)?;
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+    let mut bookmarks = Bookmarks::open(&dir, &memory)?;
+
+    match (args[1].as_str(), &args[2..]) {
+        ("add", [label, expression]) => {
+            let bookmark = bookmarks.add(&memory, label, expression, type_hint.as_deref(), &note)?;
+            println!("{} = {:?}", bookmark.label, bookmark.address);
+            bookmarks.save_in(&dir)?;
+        }
+        ("list", []) => {
+            let rebaser = Rebaser::from_memory(&memory)?;
+            for bookmark in bookmarks.iter() {
+                let value = match bookmark.resolve(&rebaser) {
+                    Ok(address) => match bookmark.read_value(&memory, address) {
+                        Ok(Some(value)) => format!("0x{:X} = {}", address, value),
+                        Ok(None) => format!("0x{:X}", address),
+                        Err(e) => format!("0x{:X} ({})", address, e),
+                    },
+                    Err(e) => e.to_string(),
+                };
+                println!("{:<20} {:<28} {}  {}", bookmark.label, bookmark.expression, value, bookmark.note);
+            }
+        }
+        ("rm", [label]) => {
+            bookmarks
+                .remove(label)
+                .ok_or_else(|| invalid_input(format!("no bookmark named '{}'", label)))?;
+            bookmarks.save_in(&dir)?;
+        }
+        ("import", [path]) => {
+            let labels =
+                if path.ends_with(".csv") { interop::import_csv(path)? } else { interop::import_x64dbg(path)? };
+            let failures = bookmarks.import(&memory, &labels);
+            for (label, e) in &failures {
+                eprintln!("{}: {}", label, e);
+            }
+            println!("Imported {} of {} labels", labels.len() - failures.len(), labels.len());
+            bookmarks.save_in(&dir)?;
+        }
+        ("goto", [label]) => {
+            let bookmark = bookmarks.get(label).ok_or_else(|| invalid_input(format!("no bookmark named '{}'", label)))?;
+            let address = bookmark.resolve(&Rebaser::from_memory(&memory)?)?;
+            println!("{} ({}) at 0x{:X}  {}", bookmark.label, bookmark.expression, address, bookmark.note);
+            if let Some(value) = bookmark.read_value(&memory, address)? {
+                println!("{} = {}", bookmark.type_hint.as_deref().unwrap_or_default(), value);
+            }
+            match memory.probe(address) {
+                Ok(probe) => println!("looks like: {}", probe.summary()),
+                Err(e) => println!("looks like: ({})", e),
+            }
+            print!("{}", hex_dump(&memory.read_bytes(address, len)?, address));
+        }
+        _ => return Err(invalid_input(USAGE.to_string())),
+    }
+    Ok(())
+}
+
+fn probe_command(args: Vec<String>) -> io::Result<()> {
+    let [pid, address] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let mut memory = Memory::open(parse_pid(pid)?)?;
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+    let address = parse_address(address)?;
+    let probe = memory.probe(address)?;
+    print!("{}", probe);
+    print!("{}", hex_dump(&probe.bytes, address));
+    Ok(())
+}
+
+// A line at a time from stdin, so it works in any console and under a pipe. The buffer (see
+// hex_edit.rs) does the work; this reads commands and prints pages.
+fn edit_command(args: Vec<String>) -> io::Result<()> {
+    let (pid, address, len) = match args.as_slice() {
+        [pid, address] => (pid, address, 0x100),
+        [pid, address, len] => (pid, address, parse_size(len)?),
+        _ => return Err(invalid_input(USAGE.to_string())),
+    };
+
+    let memory = Memory::open(parse_pid(pid)?)?;
+    let mut buffer = EditBuffer::load(&memory, parse_address(address)?, len)?;
+    let page = EDIT_PAGE_LINES * LINE_WIDTH;
+    let mut cursor = 0;
+    println!("{}", EDIT_HELP);
+    print!("{}", buffer.render(cursor / page * page, EDIT_PAGE_LINES, Some(cursor)));
+
+    let mut line = String::new();
+    loop {
+        print!("edit +0x{:X}> ", cursor);
+        io::stdout().flush()?;
+        line.clear();
+        if io::stdin().read_line(&mut line)? == 0 {
+            break;
+        }
+        let input = line.trim_end_matches(['\r', '\n']);
+        let (command, rest) = input.trim_start().split_once(' ').unwrap_or((input.trim(), ""));
+        let result = match command {
+            "" => continue,
+            "p" => Ok(()),
+            "n" => {
+                cursor = (cursor / page + 1).saturating_mul(page).min(buffer.len() - 1);
+                Ok(())
+            }
+            "b" => {
+                cursor = (cursor / page).saturating_sub(1) * page;
+                Ok(())
+            }
+            "g" => usize::from_str_radix(rest.trim().trim_start_matches("0x"), 16)
+                .ok()
+                .filter(|offset| *offset < buffer.len())
+                .map(|offset| cursor = offset)
+                .ok_or_else(|| invalid_input(format!("offset '{}' is not in 0..0x{:X}", rest.trim(), buffer.len()))),
+            "h" => buffer.overwrite_hex(cursor, rest).map(|put| cursor = (cursor + put).min(buffer.len() - 1)),
+            "a" => buffer.overwrite_ascii(cursor, rest).map(|put| cursor = (cursor + put).min(buffer.len() - 1)),
+            "u" => {
+                let len = if rest.trim().is_empty() { Ok(1) } else { parse_size(rest.trim()) };
+                len.map(|len| println!("Reverted {} edits", buffer.revert(cursor..cursor.saturating_add(len))))
+            }
+            "d" => {
+                let runs = buffer.plan();
+                println!("{} pending edits, {} writes:", buffer.pending().len(), runs.len());
+                for run in runs {
+                    println!("  0x{:X}: {}", run.address, format_hex(&run.bytes));
+                }
+                continue;
+            }
+            "r" => {
+                let report = buffer.refresh(&memory);
+                println!("{} bytes changed since the last read", report.changed.len());
+                for range in &report.unreadable {
+                    let start = buffer.base() + range.start;
+                    println!("  0x{:X}-0x{:X} can no longer be read", start, start + range.len());
+                }
+                if report.stranded > 0 {
+                    println!("  {} edits there are kept, but `w` fails until they are reverted", report.stranded);
+                }
+                Ok(())
+            }
+            "w" => buffer.commit(&memory).map(|written| println!("Wrote and verified 0x{:X} bytes", written)),
+            "q" => break,
+            "?" => {
+                println!("{}", EDIT_HELP);
+                continue;
+            }
+            _ => Err(invalid_input(format!("unknown edit command '{}' (? lists them)", command))),
+        };
+        match result {
+            Ok(()) => print!("{}", buffer.render(cursor / page * page, EDIT_PAGE_LINES, Some(cursor))),
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    if buffer.is_dirty() {
+        println!("Discarded {} edits", buffer.pending().len());
+    }
+    Ok(())
+}
+
+fn handles_command(args: Vec<String>) -> io::Result<()> {
+    if args.len() != 1 {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let memory = Memory::open(parse_pid(&args[0])?)?;
+    let handles = memory.handles()?;
+    println!("{} handles:", handles.len());
+    for handle in handles {
+        let type_name = handle.type_name.unwrap_or_else(|| format!("type {}", handle.type_index));
+        println!(
+            "0x{:<6X} {:<20} access 0x{:08X}  {}",
+            handle.handle,
+            type_name,
+            handle.granted_access,
+            handle.name.as_deref().unwrap_or("")
+        );
+    }
+
+    Ok(())
+}
+
+fn doctor_command(args: Vec<String>) -> io::Result<()> {
+    match args.as_slice() {
+        [] => {}
+        [pid] => {
+            let pid = parse_pid(pid)?;
+            println!("{}", diagnostics::why_cant_open(pid));
+            if let Ok(memory) = Memory::open(pid) {
+                println!("capabilities: {}", memory.capabilities());
+            }
+            return Ok(());
+        }
+        _ => return Err(invalid_input(USAGE.to_string())),
+    }
+
+    let results = diagnostics::run();
+    for result in &results {
+        println!("{}", result);
+    }
+    let failed = results.iter().filter(|result| result.outcome == CheckOutcome::Fail).count();
+    if failed > 0 {
+        return Err(io::Error::other(format!("{} of {} checks failed", failed, results.len())));
+    }
+    Ok(())
+}
+
+fn strings_command(mut args: Vec<String>) -> io::Result<()> {
+    let min_len = match take_value(&mut args, "--min")? {
+        Some(min) => parse_value(&min)?,
+        None => 8,
+    };
+    // --wide adds UTF-16LE strings to the ASCII ones
+    let wide = take_flag(&mut args, "--wide");
+    let unique = take_flag(&mut args, "--unique");
+    if args.len() != 1 {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let mut memory = Memory::open(parse_pid(&args[0])?)?;
+    memory.scan_memory()?;
+
+    let encodings: &[Encoding] = if wide { &[Encoding::Ascii, Encoding::Utf16Le] } else { &[Encoding::Ascii] };
+    let filter = RegionFilter::new();
+    let mut scan = memory.extract_strings(min_len, encodings, &filter).dedup(unique);
+    for found in scan.by_ref() {
+        let marker = if found.encoding == Encoding::Utf16Le { 'W' } else { 'A' };
+        println!("0x{:X} {} {}", found.address, marker, found.text);
+    }
+    log::info!("{}", scan.report());
+
+    Ok(())
+}
+
+// Runs `$body` with `$ty` aliased to the scan value type called `$name`.
+macro_rules! with_value_type {
+    ($name:expr, $ty:ident => $body:expr) => {
+        match $name {
+            "u8" => { type $ty = u8; $body }
+            "u16" => { type $ty = u16; $body }
+            "u32" => { type $ty = u32; $body }
+            "u64" => { type $ty = u64; $body }
+            "i8" => { type $ty = i8; $body }
+            "i16" => { type $ty = i16; $body }
+            "i32" => { type $ty = i32; $body }
+            "i64" => { type $ty = i64; $body }
+            "f32" => { type $ty = f32; $body }
+            "f64" => { type $ty = f64; $body }
+            other => Err(invalid_input(format!("unknown value type '{}'", other))),
+        }
+    };
+}
+
+// A session lives in a file between invocations, so a value can be narrowed down over several
+// runs of the tool: `scan save` does the first scan, each `scan next` applies one filter.
+fn scan_command(mut args: Vec<String>) -> io::Result<()> {
+    let force = take_flag(&mut args, "--force");
+    let all = take_flag(&mut args, "--all");
+    let (Some(subcommand), Some(pid)) = (args.first(), args.get(1)) else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let mut memory = Memory::open(parse_pid(pid)?)?;
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+    if subcommand == "save" {
+        // Ctrl-C stops the first scan, and then nothing is saved
+        let mut options = memory.default_scan_options().clone();
+        options.cancel = Some(catch_ctrl_c());
+        memory.set_default_scan_options(options);
+    }
+
+    match (subcommand.as_str(), &args[2..]) {
+        ("save", [value_type, value, path]) => {
+            with_value_type!(value_type.as_str(), T => scan_save::<T>(&memory, value, path))
+        }
+        ("load", [path]) => {
+            with_value_type!(saved_value_type(path)?.as_str(), T => scan_load::<T>(&memory, path, force, all))
+        }
+        ("next", [path, filter]) => {
+            with_value_type!(saved_value_type(path)?.as_str(), T => scan_next::<T>(&memory, path, filter, force))
+        }
+        _ => Err(invalid_input(USAGE.to_string())),
+    }
+}
+
+fn scan_save<T: ScanValue + FromStr>(memory: &Memory, value: &str, path: &str) -> io::Result<()> {
+    let filter = RegionFilter::new().writable(true);
+    let (session, report) = if value == "unknown" {
+        ScanSession::<T>::start_unknown_report(memory, &filter)
+    } else {
+        let report = memory.scan_for_value_report(parse_value::<T>(value)?, &filter, memory.default_scan_options())?;
+        (ScanSession::<T>::from_addresses(memory, report.matches.clone()), report)
+    };
+    log::info!("{}", report);
+    if interrupted() {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, format!("Interrupted; {} was not written", path)));
+    }
+
+    session.save(path, memory)?;
+    println!("Saved {} {} candidates to {}", session.len(), T::NAME, path);
+    print!("{}", ClusterSummary(&memory.cluster_results(&session.addresses())));
+    Ok(())
+}
+
+// Where the candidates are by default; every one of them with its value with `all`.
+fn scan_load<T: ScanValue + Display>(memory: &Memory, path: &str, force: bool, all: bool) -> io::Result<()> {
+    let session = ScanSession::<T>::load(path, memory, force)?;
+    if !all {
+        print!("{}", ClusterSummary(&memory.cluster_results(&session.addresses())));
+        return Ok(());
+    }
+    println!("{} {} candidates:", session.len(), T::NAME);
+    for (address, saved) in session.candidates() {
+        match memory.read_unaligned::<T>(address) {
+            Ok(current) => println!("0x{:X}: {} (saved {})", address, current, saved),
+            Err(_) => println!("0x{:X}: unreadable (saved {})", address, saved),
+        }
+    }
+    Ok(())
+}
+
+fn scan_next<T: ScanValue + FromStr>(memory: &Memory, path: &str, filter: &str, force: bool) -> io::Result<()> {
+    let filter = parse_scan_filter::<T>(filter)?;
+    let mut session = ScanSession::<T>::load(path, memory, force)?;
+    let before = session.len();
+    let left = session.next_scan(memory, filter);
+    session.save(path, memory)?;
+    println!("{} of {} candidates left", left, before);
+    print!("{}", ClusterSummary(&memory.cluster_results(&session.addresses())));
+    Ok(())
+}
+
+fn parse_scan_filter<T: FromStr>(text: &str) -> io::Result<ScanFilter<T>> {
+    let (name, value) = match text.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (text, None),
+    };
+    match (name, value) {
+        ("changed", None) => Ok(ScanFilter::Changed),
+        ("unchanged", None) => Ok(ScanFilter::Unchanged),
+        ("increased", None) => Ok(ScanFilter::Increased),
+        ("decreased", None) => Ok(ScanFilter::Decreased),
+        ("eq", Some(value)) => Ok(ScanFilter::Equal(parse_value(value)?)),
+        ("increased-by", Some(value)) => Ok(ScanFilter::IncreasedBy(parse_value(value)?)),
+        ("decreased-by", Some(value)) => Ok(ScanFilter::DecreasedBy(parse_value(value)?)),
+        ("increased-by-at-least", Some(value)) => Ok(ScanFilter::IncreasedByAtLeast(parse_value(value)?)),
+        ("decreased-by-at-least", Some(value)) => Ok(ScanFilter::DecreasedByAtLeast(parse_value(value)?)),
+        _ => Err(invalid_input(format!("invalid scan filter '{}'", text))),
+    }
+}
+
+// Samples candidate addresses for a while and prints how each behaved, to pick the right one
+// out of the last few a scan left.
+fn record_command(mut args: Vec<String>) -> io::Result<()> {
+    let duration = match take_value(&mut args, "--secs")? {
+        Some(secs) => parse_duration(&secs)?,
+        None => Duration::from_secs(30),
+    };
+    let interval = match take_value(&mut args, "--interval-ms")? {
+        Some(interval) => parse_duration_in(&interval, Duration::from_millis(1))?,
+        None => Duration::from_millis(100),
+    };
+    let csv = take_value(&mut args, "--csv")?;
+    let [pid, value_type, addresses @ ..] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+    if addresses.is_empty() {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let memory = Memory::open(parse_pid(pid)?)?;
+    let addresses = addresses.iter().map(|address| parse_address(address)).collect::<io::Result<Vec<_>>>()?;
+    with_value_type!(value_type.as_str(), T => record::<T>(&memory, &addresses, interval, duration, csv.as_deref()))
+}
+
+// Streams the target's typed bookmarks to stdout, one JSON frame per line, e.g. for an overlay
+// reading the pipe. Runs until stdout closes or --secs have passed.
+fn stream_command(mut args: Vec<String>) -> io::Result<()> {
+    let dir = take_value(&mut args, "--dir")?.unwrap_or_else(|| ".".to_string());
+    let rate: u32 = match take_value(&mut args, "--rate")? {
+        Some(rate) => parse_value(&rate)?,
+        None => 30,
+    };
+    let duration = take_value(&mut args, "--secs")?.map(|secs| parse_duration(&secs)).transpose()?;
+    let [pid] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let mut memory = Memory::open(parse_pid(pid)?)?;
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+    let bookmarks = Bookmarks::open(&dir, &memory)?;
+    let watchlist = WatchedValue::from_bookmarks(&bookmarks, &Rebaser::from_memory(&memory)?);
+    if watchlist.is_empty() {
+        return Err(invalid_input("no bookmark with a type hint resolves in this process".to_string()));
+    }
+
+    let memory = Arc::new(memory);
+    let stream = ValueStream::new(&memory, watchlist, rate)?;
+    let deadline = duration.map(|duration| Instant::now() + duration);
+    catch_ctrl_c();
+    let mut stdout = io::stdout().lock();
+    let mut result = Ok(());
+    while deadline.is_none_or(|deadline| Instant::now() < deadline) && !interrupted() {
+        let Some(frame) = stream.recv_timeout(Duration::from_millis(100)) else { continue };
+        result = writeln!(stdout, "{}", frame.to_json()?).and_then(|()| stdout.flush());
+        if result.is_err() {
+            break;
+        }
+    }
+
+    let report = stream.stop();
+    log::info!("{} frames, {} dropped, {} failed reads", report.frames, report.dropped, report.errors);
+    // A reader that went away is the usual way to end an open-ended stream.
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+fn record<T: ScanValue + Display>(
+    memory: &Memory,
+    addresses: &[usize],
+    interval: Duration,
+    duration: Duration,
+    csv: Option<&str>,
+) -> io::Result<()> {
+    let start = Instant::now();
+    let history = ValueRecorder::record::<T>(memory, addresses, interval, duration)?;
+
+    let mut recorded: Vec<_> = history.iter().collect();
+    recorded.sort_by_key(|(address, _)| **address);
+    for (address, samples) in recorded {
+        match value_recorder::summarize(samples) {
+            Some(summary) => {
+                let trend = match (summary.non_decreasing, summary.non_increasing) {
+                    (true, true) => "constant",
+                    (true, false) => "increasing",
+                    (false, true) => "decreasing",
+                    (false, false) => "mixed",
+                };
+                println!(
+                    "0x{:X}: {} samples, min {}, max {}, {} changes, {}",
+                    address, summary.samples, summary.min, summary.max, summary.changes, trend
+                );
+            }
+            None => println!("0x{:X}: unreadable", address),
+        }
+    }
+
+    if let Some(path) = csv {
+        value_recorder::write_csv(&history, start, io::BufWriter::new(File::create(path)?))?;
+        println!("Saved samples to {}", path);
+    }
+    Ok(())
+}
+
+fn heatmap_command(mut args: Vec<String>) -> io::Result<()> {
+    let len = match take_value(&mut args, "--len")? {
+        Some(len) => parse_size(&len)?,
+        None => 4,
+    };
+    let duration = match take_value(&mut args, "--secs")? {
+        Some(secs) => parse_duration(&secs)?,
+        None => Duration::from_secs(10),
+    };
+    let interval = match take_value(&mut args, "--interval-ms")? {
+        Some(interval) => parse_duration_in(&interval, Duration::from_millis(1))?,
+        None => Duration::from_millis(1),
+    };
+    let top: usize = match take_value(&mut args, "--top")? {
+        Some(top) => parse_value(&top)?,
+        None => 10,
+    };
+    let [pid, address] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let mut memory = Memory::open(parse_pid(pid)?)?;
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+    let address = parse_address(address)?;
+    let heatmap = memory.access_heatmap(address, len, duration, interval)?;
+
+    println!("0x{:X}: {} changes in {} ticks", heatmap.address, heatmap.changes, heatmap.ticks);
+    for entry in heatmap.entries.iter().take(top) {
+        println!("  {}", entry);
+    }
+    Ok(())
+}
+
+fn codewatch_command(mut args: Vec<String>) -> io::Result<()> {
+    let duration = match take_value(&mut args, "--secs")? {
+        Some(secs) => parse_duration(&secs)?,
+        None => Duration::from_secs(30),
+    };
+    let interval = match take_value(&mut args, "--interval-ms")? {
+        Some(interval) => parse_duration_in(&interval, Duration::from_millis(1))?,
+        None => Duration::from_millis(100),
+    };
+    let dump = take_value(&mut args, "--dump")?.map(PathBuf::from);
+    let [pid, module] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let mut memory = Memory::open(parse_pid(pid)?)?;
+    memory.scan_modules()?;
+    let memory = Arc::new(memory);
+    let watch = CodeWatch::start_with_dumps(&memory, module, interval, dump)?;
+
+    let deadline = Instant::now() + duration;
+    catch_ctrl_c();
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|_| !interrupted()) {
+        let change = match watch.events().recv_timeout(left.min(CTRL_C_POLL)) {
+            Ok(change) => change,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        println!("{}", change);
+        for byte in &change.diff {
+            println!("  0x{:X}: {:02X} -> {:02X}", byte.address, byte.old, byte.new);
+        }
+        if change.changed_bytes > change.diff.len() {
+            println!("  ... {} more", change.changed_bytes - change.diff.len());
+        }
+    }
+
+    let report = watch.stop();
+    println!("{} pages changed in {} ticks", report.pages.len(), report.ticks);
+    Ok(())
+}
+
+fn protwatch_command(mut args: Vec<String>) -> io::Result<()> {
+    let duration = match take_value(&mut args, "--secs")? {
+        Some(secs) => parse_duration(&secs)?,
+        None => Duration::from_secs(30),
+    };
+    let interval = match take_value(&mut args, "--interval-ms")? {
+        Some(interval) => parse_duration_in(&interval, Duration::from_millis(1))?,
+        None => Duration::from_millis(50),
+    };
+    let dump = take_value(&mut args, "--dump")?.map(PathBuf::from);
+    let [pid] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let memory = Arc::new(Memory::open(parse_pid(pid)?)?);
+    let monitor = ProtectionMonitor::start_with_dumps(&memory, RegionFilter::new(), interval, dump);
+
+    let deadline = Instant::now() + duration;
+    catch_ctrl_c();
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|_| !interrupted()) {
+        match monitor.events().recv_timeout(left.min(CTRL_C_POLL)) {
+            Ok(event) => println!("{}{}", event, if event.became_executable() { "  [now executable]" } else { "" }),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    println!("{} ticks", monitor.stop());
+    Ok(())
+}
+
+fn livediff_command(mut args: Vec<String>) -> io::Result<()> {
+    let duration = match take_value(&mut args, "--secs")? {
+        Some(secs) => parse_duration(&secs)?,
+        None => Duration::from_secs(30),
+    };
+    let interval = match take_value(&mut args, "--interval-ms")? {
+        Some(interval) => parse_duration_in(&interval, Duration::from_millis(1))?,
+        None => Duration::from_millis(1000),
+    };
+    let block = match take_value(&mut args, "--block")? {
+        Some(block) => parse_size(&block)?,
+        None => LIVE_BLOCK_SIZE,
+    };
+    // --all covers every readable region, not only writable ones
+    let all = take_flag(&mut args, "--all");
+    let [pid] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let mut memory = Memory::open(parse_pid(pid)?)?;
+    memory.scan_memory()?;
+    let filter = if all { RegionFilter::new() } else { RegionFilter::new().writable(true) };
+    let mut diff = LiveDiff::with_block_size(&memory, &filter, block)?;
+    println!(
+        "baseline: {} blocks over 0x{:X} bytes, 0x{:X} bytes held",
+        diff.block_count(),
+        diff.covered_bytes(),
+        diff.memory_usage()
+    );
+
+    let deadline = Instant::now() + duration;
+    catch_ctrl_c();
+    while Instant::now() < deadline && !interrupted() {
+        thread::sleep(interval);
+        let report = diff.compare(&memory);
+        println!("{} blocks changed in {} ranges", report.changed_blocks, report.changed.len());
+        for range in &report.changed {
+            println!("  0x{:X}-0x{:X}", range.start, range.end);
+        }
+    }
+    Ok(())
+}
+
+fn alloctrace_command(mut args: Vec<String>) -> io::Result<()> {
+    let duration = match take_value(&mut args, "--secs")? {
+        Some(secs) => parse_duration(&secs)?,
+        None => Duration::from_secs(30),
+    };
+    let interval = match take_value(&mut args, "--interval-ms")? {
+        Some(interval) => parse_duration_in(&interval, Duration::from_millis(1))?,
+        None => Duration::from_millis(20),
+    };
+    let csv = take_value(&mut args, "--csv")?;
+    let [pid] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let mut memory = Memory::open(parse_pid(pid)?)?;
+    memory.scan_modules()?;
+    let memory = Arc::new(memory);
+    let tracer = AllocationTracer::start(&memory, interval);
+    let deadline = Instant::now() + duration;
+    catch_ctrl_c();
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|_| !interrupted()) {
+        thread::sleep(left.min(CTRL_C_POLL));
+    }
+    let report = tracer.stop();
+
+    print!("{}", report);
+    if let Some(csv) = csv {
+        report.write_csv(File::create(csv)?)?;
+    }
+    Ok(())
+}
+
+fn pointers_into_command(mut args: Vec<String>) -> io::Result<()> {
+    let top: usize = match take_value(&mut args, "--top")? {
+        Some(top) => parse_value(&top)?,
+        None => 20,
+    };
+    let [pid, module] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let mut memory = Memory::open(parse_pid(pid)?)?;
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+    let hits = memory.find_pointers_into_module(module, &RegionFilter::new().writable(true))?;
+
+    println!("{} slots point into {}", hits.len(), module);
+    for (pointee, slots) in count_by_pointee(&hits).into_iter().take(top) {
+        let name = memory.symbolize(pointee).unwrap_or_else(|| format!("0x{:X}", pointee));
+        println!("  {:>6}  {}", slots, name);
+    }
+    Ok(())
+}
+
+fn tree_command(mut args: Vec<String>) -> io::Result<()> {
+    let watch = take_value(&mut args, "--watch")?.map(|secs| parse_duration(&secs)).transpose()?;
+    let [pid] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+    let pid = parse_pid(pid)?;
+
+    let tree = ProcessTree::snapshot()?;
+    match tree.get(pid) {
+        Some(root) => println!("{}", root),
+        None => println!("process {} (exited)", pid),
+    }
+    for process in tree.descendants(pid) {
+        println!("  {}", process);
+    }
+
+    let Some(duration) = watch else { return Ok(()) };
+    let watch = ChildWatch::start(pid, Duration::from_millis(100));
+    let deadline = Instant::now() + duration;
+    catch_ctrl_c();
+    while let Some(left) = deadline.checked_duration_since(Instant::now()).filter(|_| !interrupted()) {
+        match watch.events().recv_timeout(left.min(CTRL_C_POLL)) {
+            Ok(process) => println!("started: {}", process),
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    watch.stop();
+    Ok(())
+}
+
+fn ps_command(mut args: Vec<String>) -> io::Result<()> {
+    // --openable leaves out the processes whose memory can't be read
+    let openable = take_flag(&mut args, "--openable");
+    if !args.is_empty() {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let tree = ProcessTree::snapshot_annotated()?;
+    println!("{:>7} {:>7}  {:<10}  {:<32}  image", "pid", "session", "access", "user");
+    for process in tree.iter() {
+        if openable && process.access < Some(AccessLevel::Read) {
+            continue;
+        }
+        let or_unknown = |value: Option<String>| value.unwrap_or_else(|| "?".to_string());
+        println!(
+            "{:>7} {:>7}  {:<10}  {:<32}  {}",
+            process.pid,
+            or_unknown(process.session_id.map(|session| session.to_string())),
+            process.access.map_or("?", AccessLevel::name),
+            or_unknown(process.user.clone()),
+            process.image_path.as_deref().unwrap_or(&process.name)
+        );
+    }
+    Ok(())
+}
+
+fn session_command(mut args: Vec<String>) -> io::Result<()> {
+    let bookmarks = take_value(&mut args, "--bookmarks")?.map(PathBuf::from);
+    let patches = take_value(&mut args, "--patches")?.map(PathBuf::from);
+    let scan = take_value(&mut args, "--scan")?.map(PathBuf::from);
+    let mut signatures = Vec::new();
+    while let Some(path) = take_value(&mut args, "--sigs")? {
+        signatures.push(PathBuf::from(path));
+    }
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["save", pid, path] => {
+            let mut memory = Memory::open(parse_pid(pid)?)?;
+            memory.scan_modules()?;
+            let mut state = SessionState::for_target(&memory)?;
+            state.bookmarks = bookmarks;
+            state.patches = patches;
+            state.signatures = signatures;
+            state.scan_session = scan;
+            state.save(path)?;
+            println!("Saved session for {} (pid {}) to {}", state.module, state.pid, path);
+            Ok(())
+        }
+        ["restore", path] => {
+            let (_memory, report) = SessionState::restore(path)?;
+            print!("{}", report);
+            if !report.is_complete() {
+                println!("restored partially");
+            }
+            Ok(())
+        }
+        _ => Err(invalid_input(USAGE.to_string())),
+    }
+}
+
+// Decodes the bytes at an address against a template file, or against fields given inline
+// as in "0x10 f32 health; 0x30 ptr->utf16 name".
+fn dt_command(mut args: Vec<String>) -> io::Result<()> {
+    let json = take_flag(&mut args, "--json");
+    let reclass = take_value(&mut args, "--reclass")?;
+    let [pid, template, address] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+
+    let template = if Path::new(template).is_file() {
+        StructTemplate::load(template)?
+    } else {
+        StructTemplate::parse(template)?
+    };
+    let memory = Memory::open(parse_pid(pid)?)?;
+    let address = parse_address(address)?;
+    if let Some(path) = reclass {
+        interop::export_reclass(&memory, &template, address, &path)?;
+        println!("Wrote {}", path);
+    }
+    let decoded = template.apply(&memory, address)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&decoded)?);
+    } else {
+        print!("{}", decoded);
+    }
+    Ok(())
+}
+
+// Diffs the same module-relative range in two processes, e.g. a modded and a vanilla
+// instance of a program.
+fn compare_command(args: Vec<String>) -> io::Result<()> {
+    let [pid_a, pid_b, module, offset, len] = args.as_slice() else {
+        return Err(invalid_input(USAGE.to_string()));
+    };
+    let offset = parse_address(offset)?;
+    let len = parse_size(len)?;
+
+    let mut session = Session::new();
+    session.attach("a", parse_pid(pid_a)?)?;
+    session.attach("b", parse_pid(pid_b)?)?;
+    let diff = session.compare_range("a", "b", module, offset..offset.saturating_add(len))?;
+
+    if diff.is_identical() {
+        println!("{}+0x{:X}..0x{:X} is identical", module, diff.range.start, diff.range.end);
+        return Ok(());
+    }
+    println!("{} bytes differ in {} runs:", diff.differing_bytes(), diff.differences.len());
+    for run in &diff.differences {
+        println!("  {}+0x{:X}..0x{:X}", module, run.start, run.end);
+    }
+    Ok(())
+}
+
+fn parse_value<T: FromStr>(text: &str) -> io::Result<T> {
+    text.parse().map_err(|_| invalid_input(format!("invalid value '{}'", text)))
+}
+
+#[cfg(feature = "disasm")]
+fn disasm_command(args: Vec<String>) -> io::Result<()> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let memory = Memory::open(parse_pid(&args[0])?)?;
+    let address = parse_address(&args[1])?;
+    let count = match args.get(2) {
+        Some(text) => text.parse().map_err(|_| invalid_input(format!("invalid count '{}'", text)))?,
+        None => 16,
+    };
+
+    for insn in memory.disassemble(address, count)? {
+        println!("0x{:X}  {:<30}  {}", insn.address, format_hex(&insn.bytes), insn.text);
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn serve_command(args: Vec<String>) -> io::Result<()> {
+    if args.len() != 2 {
+        return Err(invalid_input(USAGE.to_string()));
+    }
+
+    let mut memory = Memory::open(parse_pid(&args[0])?)?;
+    memory.scan_memory()?;
+    memory.scan_modules()?;
+
+    let listener = std::net::TcpListener::bind(&args[1])?;
+    println!("Serving process {} on {}", memory.process_id(), listener.local_addr()?);
+    nirvana_notject::server::serve(Arc::new(memory), listener)
+}
+
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let before = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != before
+}
+
+fn take_value(args: &mut Vec<String>, option: &str) -> io::Result<Option<String>> {
+    let Some(index) = args.iter().position(|arg| arg == option) else {
+        return Ok(None);
+    };
+    if index + 1 >= args.len() {
+        return Err(invalid_input(format!("{} needs a value", option)));
+    }
+    let value = args.remove(index + 1);
+    args.remove(index);
+    Ok(Some(value))
+}
+
+fn parse_pid(text: &str) -> io::Result<u32> {
+    text.parse::<u32>().map_err(|_| invalid_input(format!("invalid pid '{}'", text)))
+}
+
+// Addresses are always hexadecimal, with or without a 0x prefix.
+fn parse_address(text: &str) -> io::Result<usize> {
+    text.parse::<Address>().map(Address::get).map_err(|_| invalid_input(format!("invalid address '{}'", text)))
+}
+
+// Accepts "90 90 90" as separate arguments as well as a single "909090".
+fn parse_hex_bytes(args: &[String]) -> io::Result<Vec<u8>> {
+    let digits: String = args.iter().flat_map(|arg| arg.chars()).filter(|c| !c.is_whitespace()).collect();
+    if digits.is_empty() || digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid_input(format!("invalid byte string '{}'", digits)));
+    }
+
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn demo() -> io::Result<()> {
     // Create a new memory editor instance
     let mut memory = Memory::new()?;
     
@@ -11,28 +1478,20 @@ fn main() -> io::Result<()> {
     memory.scan_memory()?;
     
     // Print memory regions
-    println!("Found {} memory regions:", memory.get_regions().len());
-    for (i, region) in memory.get_regions().iter().enumerate() {
-        println!("Region {}: 0x{:X} - 0x{:X} (Size: 0x{:X})", 
-            i, 
-            region.start_address, 
-            region.start_address + region.size,
-            region.size
-        );
-    }
+    println!("Found {} memory regions:", memory.regions().len());
+    print!("{}", memory.regions_table());
     
     // Example of reading memory from the first region
-    if let Some(first_region) = memory.get_regions().first() {
-        match memory.read_memory::<u32>(first_region.start_address) {
+    if let Some(first_region) = memory.regions().next() {
+        match memory.read_memory::<u32>(first_region.start_address.get()) {
             Ok(value) => println!("Read value at 0x{:X}: {}", first_region.start_address, value),
             Err(e) => println!("Failed to read memory: {}", e),
         }
     }
 
     // Example of pattern scanning
-    let pattern = [0x90, 0x90, 0x90]; // NOP pattern
-    let mask = [true, true, true];
-    match memory.find_pattern(&pattern, &mask)? {
+    let pattern = Pattern::parse("90 90 90")?; // NOP pattern
+    match memory.find_pattern(&pattern, &RegionFilter::new())? {
         Some(address) => println!("Found pattern at address: 0x{:X}", address),
         None => println!("Pattern not found"),
     }
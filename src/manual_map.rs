@@ -0,0 +1,230 @@
+// Loading a DLL into the target without LoadLibrary: the image is laid out, relocated and
+// linked here, written into a fresh allocation, and its DllMain is called from a remote
+// thread. The loader never hears of it, so it doesn't show up in the module list either.
+//
+// Not handled in this version, and warned about when present: TLS callbacks (never run) and
+// x64 exception data (never registered, so exceptions inside the DLL kill the target).
+// Imported modules have to be loaded in the target already.
+
+use std::io::{self, Write};
+use std::slice;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::exports::ExportResolver;
+use crate::memory::{Memory, PAGE_EXECUTE_READWRITE};
+use crate::pe::{
+    self, PeHeaders, Section, DIRECTORY_BASERELOC, DIRECTORY_EXCEPTION, DIRECTORY_IMPORT, DIRECTORY_TLS,
+    SCN_MEM_EXECUTE, SCN_MEM_READ, SCN_MEM_WRITE,
+};
+use crate::writer::MemoryWriter;
+
+const PAGE_NOACCESS: u32 = 0x01;
+const PAGE_READONLY: u32 = 0x02;
+const PAGE_READWRITE: u32 = 0x04;
+const PAGE_EXECUTE: u32 = 0x10;
+const PAGE_EXECUTE_READ: u32 = 0x20;
+
+const DLL_PROCESS_ATTACH: u32 = 1;
+
+// Images claiming to be larger are refused rather than laid out in a buffer that big.
+const MAX_IMAGE_SIZE: usize = 512 * 1024 * 1024;
+
+// How long DllMain may run before `manual_map` gives up waiting.
+const DLL_MAIN_TIMEOUT: Duration = Duration::from_secs(10);
+
+impl Memory {
+    // Maps `dll` (the bytes of the file) into the target and runs its DllMain with
+    // DLL_PROCESS_ATTACH. Returns the image base. The image stays mapped even if DllMain
+    // times out; it is freed if anything before that fails or DllMain returns FALSE. With
+    // `set_safety_net` on, the fresh allocation is saved before the image is written.
+    pub fn manual_map(&self, dll: &[u8]) -> io::Result<usize> {
+        let headers = PeHeaders::parse(dll)?;
+        let pointer_size = self.pointer_size()?;
+        if headers.pointer_size() != pointer_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot map a {}-bit DLL into a {}-bit process", headers.pointer_size() * 8, pointer_size * 8),
+            ));
+        }
+        if headers.size_of_image as usize > MAX_IMAGE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Image size 0x{:X} is implausibly large", headers.size_of_image),
+            ));
+        }
+        if headers.directory(DIRECTORY_TLS).is_some() {
+            warn!("the DLL has a TLS directory; its TLS callbacks will not run");
+        }
+        if headers.is_64 && headers.directory(DIRECTORY_EXCEPTION).is_some() {
+            warn!("the DLL has exception data, which is not registered; exceptions in it will not unwind");
+        }
+
+        let mut image = layout_image(dll, &headers)?;
+        let base = self.allocate(headers.size_of_image as usize, PAGE_READWRITE)?;
+        debug!("mapping 0x{:X} byte image at 0x{:X}", image.len(), base);
+
+        let mapped = self.finish_mapping(&headers, &mut image, base);
+        if let Err(e) = mapped {
+            let _ = self.free(base);
+            return Err(e);
+        }
+        Ok(base)
+    }
+
+    fn finish_mapping(&self, headers: &PeHeaders, image: &mut [u8], base: usize) -> io::Result<()> {
+        relocate(image, headers, base)?;
+        link_imports(self, image, headers)?;
+        self.arm_safety_net("manual map", slice::from_ref(&(base..base + image.len())))?;
+
+        let mut writer = MemoryWriter::new(self, base);
+        writer.write_all(image)?;
+        writer.finish()?;
+
+        self.protect(base, headers.size_of_headers as usize, PAGE_READONLY)?;
+        for section in &headers.sections {
+            let size = section.virtual_size.max(section.raw_size) as usize;
+            if size > 0 {
+                self.protect(base + section.virtual_address as usize, size, section_protection(section))?;
+            }
+        }
+
+        if headers.entry_point == 0 {
+            return Ok(());
+        }
+        let entry = base + headers.entry_point as usize;
+        let stub = dll_main_stub(headers.is_64, base, entry);
+        let stub_address = self.allocate(stub.len(), PAGE_EXECUTE_READWRITE)?;
+        let result = self
+            .write_bytes(stub_address, &stub)
+            .and_then(|_| self.run_remote_thread(stub_address, 0, DLL_MAIN_TIMEOUT));
+        let exit_code = match result {
+            // A timed-out DllMain may still be using the stub, so only free it otherwise.
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => return Err(e),
+            result => {
+                self.free(stub_address)?;
+                result?
+            }
+        };
+        if exit_code == 0 {
+            return Err(io::Error::other(format!("DllMain of the image at 0x{:X} returned FALSE", base)));
+        }
+        Ok(())
+    }
+}
+
+// The file's headers and sections at their RVAs, as the loader would place them.
+fn layout_image(dll: &[u8], headers: &PeHeaders) -> io::Result<Vec<u8>> {
+    let mut image = vec![0u8; headers.size_of_image as usize];
+    let header_len = (headers.size_of_headers as usize).min(dll.len()).min(image.len());
+    image[..header_len].copy_from_slice(&dll[..header_len]);
+
+    for section in &headers.sections {
+        let len = if section.virtual_size == 0 { section.raw_size } else { section.raw_size.min(section.virtual_size) };
+        let raw = section.raw_offset as usize..section.raw_offset as usize + len as usize;
+        let virtual_range = section.virtual_address as usize..section.virtual_address as usize + len as usize;
+        let (Some(source), true) = (dll.get(raw), virtual_range.end <= image.len()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Section {} lies outside the file or the image", section.name),
+            ));
+        };
+        image[virtual_range].copy_from_slice(source);
+    }
+
+    Ok(image)
+}
+
+fn relocate(image: &mut [u8], headers: &PeHeaders, base: usize) -> io::Result<()> {
+    let delta = (base as u64).wrapping_sub(headers.image_base);
+    if delta == 0 {
+        return Ok(());
+    }
+    let Some(directory) = headers.directory(DIRECTORY_BASERELOC) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The DLL has no relocations and cannot be mapped away from 0x{:X}", headers.image_base),
+        ));
+    };
+
+    let relocations = pe::relocations(image, directory)?;
+    for relocation in &relocations {
+        let offset = relocation.rva as usize;
+        if relocation.is_64 {
+            let value = pe::u64_at(image, offset)?.wrapping_add(delta);
+            image[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+        } else {
+            let value = pe::u32_at(image, offset)?.wrapping_add(delta as u32);
+            image[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+    debug!("applied {} relocations (delta 0x{:X})", relocations.len(), delta);
+    Ok(())
+}
+
+// Fills the import address table with the addresses of the imports in the target.
+fn link_imports(memory: &Memory, image: &mut [u8], headers: &PeHeaders) -> io::Result<()> {
+    let Some(directory) = headers.directory(DIRECTORY_IMPORT) else { return Ok(()) };
+    let mut resolver = ExportResolver::new(memory)?;
+
+    for module in pe::imports(image, directory, headers.is_64)? {
+        for (slot, import) in &module.imports {
+            let address = resolver
+                .resolve(&module.name, import)
+                .map_err(|e| io::Error::new(e.kind(), format!("Cannot resolve {:?} from {}: {}", import, module.name, e)))?;
+            let slot = *slot as usize;
+            let size = headers.pointer_size();
+            let Some(target) = image.get_mut(slot..slot + size) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("IAT slot at rva 0x{:X} is outside the image", slot),
+                ));
+            };
+            target.copy_from_slice(&(address as u64).to_le_bytes()[..size]);
+        }
+        debug!("linked {} imports from {}", module.imports.len(), module.name);
+    }
+    Ok(())
+}
+
+fn section_protection(section: &Section) -> u32 {
+    let flags = section.characteristics;
+    match (flags & SCN_MEM_EXECUTE != 0, flags & SCN_MEM_READ != 0, flags & SCN_MEM_WRITE != 0) {
+        (true, _, true) => PAGE_EXECUTE_READWRITE,
+        (true, true, false) => PAGE_EXECUTE_READ,
+        (true, false, false) => PAGE_EXECUTE,
+        (false, _, true) => PAGE_READWRITE,
+        (false, true, false) => PAGE_READONLY,
+        (false, false, false) => PAGE_NOACCESS,
+    }
+}
+
+// Thread routine calling `DllMain(base, DLL_PROCESS_ATTACH, NULL)` and returning its result
+// as the thread's exit code.
+fn dll_main_stub(is_64: bool, base: usize, entry: usize) -> Vec<u8> {
+    let mut code = Vec::new();
+    if is_64 {
+        code.extend_from_slice(&[0x48, 0x83, 0xEC, 0x28]); // sub rsp, 0x28 (shadow space, alignment)
+        code.extend_from_slice(&[0x48, 0xB9]); // mov rcx, base
+        code.extend_from_slice(&(base as u64).to_le_bytes());
+        code.push(0xBA); // mov edx, DLL_PROCESS_ATTACH
+        code.extend_from_slice(&DLL_PROCESS_ATTACH.to_le_bytes());
+        code.extend_from_slice(&[0x45, 0x31, 0xC0]); // xor r8d, r8d
+        code.extend_from_slice(&[0x48, 0xB8]); // mov rax, entry
+        code.extend_from_slice(&(entry as u64).to_le_bytes());
+        code.extend_from_slice(&[0xFF, 0xD0]); // call rax
+        code.extend_from_slice(&[0x48, 0x83, 0xC4, 0x28]); // add rsp, 0x28
+        code.push(0xC3); // ret
+    } else {
+        code.extend_from_slice(&[0x6A, 0x00]); // push NULL
+        code.extend_from_slice(&[0x6A, DLL_PROCESS_ATTACH as u8]); // push DLL_PROCESS_ATTACH
+        code.push(0x68); // push base
+        code.extend_from_slice(&(base as u32).to_le_bytes());
+        code.push(0xB8); // mov eax, entry
+        code.extend_from_slice(&(entry as u32).to_le_bytes());
+        code.extend_from_slice(&[0xFF, 0xD0]); // call eax (DllMain is stdcall and pops its arguments)
+        code.extend_from_slice(&[0xC2, 0x04, 0x00]); // ret 4 (the thread parameter)
+    }
+    code
+}
@@ -1,208 +1,1658 @@
-use std::ptr;
+use std::borrow::Cow;
 use std::mem;
+use std::ops::{ControlFlow, Range};
 use std::io;
 use std::ffi::c_void;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-#[link(name = "kernel32")]
-extern "system" {
-    fn OpenProcess(dwDesiredAccess: u32, bInheritHandle: i32, dwProcessId: u32) -> *mut c_void;
-    fn CloseHandle(hObject: *mut c_void) -> i32;
-    fn ReadProcessMemory(
-        hProcess: *mut c_void,
-        lpBaseAddress: *const c_void,
-        lpBuffer: *mut c_void,
-        nSize: usize,
-        lpNumberOfBytesRead: *mut usize,
-    ) -> i32;
-    fn WriteProcessMemory(
-        hProcess: *mut c_void,
-        lpBaseAddress: *mut c_void,
-        lpBuffer: *const c_void,
-        nSize: usize,
-        lpNumberOfBytesWritten: *mut usize,
-    ) -> i32;
-    fn VirtualQueryEx(
-        hProcess: *mut c_void,
-        lpAddress: *const c_void,
-        lpBuffer: *mut MEMORY_BASIC_INFORMATION,
-        dwLength: usize,
-    ) -> usize;
-    fn GetCurrentProcessId() -> u32;
-}
-
-#[repr(C)]
-struct MEMORY_BASIC_INFORMATION {
-    base_address: *mut c_void,
-    allocation_base: *mut c_void,
-    allocation_protect: u32,
-    region_size: usize,
-    state: u32,
-    protect: u32,
-    r#type: u32,
-}
-
-const PROCESS_VM_READ: u32 = 0x0010;
-const PROCESS_VM_WRITE: u32 = 0x0020;
-const PROCESS_VM_OPERATION: u32 = 0x0008;
-const PROCESS_QUERY_INFORMATION: u32 = 0x0400;
+use log::{debug, trace, warn};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+use crate::address::{Address, Size};
+use crate::backend::Backend;
+use crate::buffer_pool::BufferPool;
+use crate::capabilities::Capabilities;
+use crate::coordination::Coordinator;
+use crate::diagnostics;
+use crate::endian::Endianness;
+use crate::filter::RegionFilter;
+use crate::metadata::{MetadataState, SectionsView};
+use crate::module::{self, ModuleInfo};
+use crate::oplog::{self, OpEvent};
+#[cfg(feature = "patch")]
+use crate::patch::Patch;
+use crate::pattern::Pattern;
+use crate::process_handle::ProcessHandle;
+use crate::protection_guard::ProtectionGuard;
+use crate::read_log::{ReadLog, ReplayBackend};
+use crate::recorder::{Recorder, WriteRecord};
+use crate::region_cache::RegionCache;
+use crate::scan::{PatternMatches, ScanDirection, ScanOptions, ScanReport};
+use crate::sections::{self, SectionRef};
+use crate::sink::ScanSink;
+use crate::spans::{record, span};
+use crate::stats::{format_size, MemoryStats};
+use crate::throttle::Throttle;
+use crate::timeout::with_timeout;
+use crate::win_error::win_call;
+use crate::write_guard::WriteGuardState;
+
+pub(crate) use crate::sys::{MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_RESERVE, PAGE_EXECUTE_READWRITE};
+use crate::sys::{
+    FlushInstructionCache, GetCurrentProcessId, IsWow64Process, OpenProcess, ReadProcessMemory,
+    VirtualProtectEx, VirtualQueryEx, WriteProcessMemory, MEM_IMAGE, MEM_MAPPED, MEM_PRIVATE, PAGE_EXECUTE,
+    PAGE_EXECUTE_READ, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE,
+    PAGE_WRITECOPY, PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_VM_OPERATION,
+    PROCESS_VM_READ, PROCESS_VM_WRITE,
+};
+
+const PAGE_READABLE: u32 = PAGE_READONLY | PAGE_READWRITE | PAGE_WRITECOPY
+    | PAGE_EXECUTE_READ | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY;
+const PAGE_WRITABLE: u32 = PAGE_READWRITE | PAGE_WRITECOPY | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY;
+pub(crate) const PAGE_EXECUTABLE: u32 =
+    PAGE_EXECUTE | PAGE_EXECUTE_READ | PAGE_EXECUTE_READWRITE | PAGE_EXECUTE_WRITECOPY;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum RegionType {
+    // Mapped from an executable image (exe or dll).
+    Image,
+    // A file or pagefile-backed section view.
+    Mapped,
+    Private,
+    Unknown,
+}
+
+impl RegionType {
+    pub(crate) fn from_raw(raw: u32) -> Self {
+        match raw {
+            MEM_IMAGE => RegionType::Image,
+            MEM_MAPPED => RegionType::Mapped,
+            MEM_PRIVATE => RegionType::Private,
+            _ => RegionType::Unknown,
+        }
+    }
+
+    pub(crate) fn to_raw(self) -> u32 {
+        match self {
+            RegionType::Image => MEM_IMAGE,
+            RegionType::Mapped => MEM_MAPPED,
+            RegionType::Private => MEM_PRIVATE,
+            RegionType::Unknown => 0,
+        }
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            RegionType::Image => "image",
+            RegionType::Mapped => "mapped",
+            RegionType::Private => "private",
+            RegionType::Unknown => "unknown",
+        }
+    }
+
+    // Three-letter tag for tables: IMG, MAP, PRV or "???".
+    pub fn tag(self) -> &'static str {
+        match self {
+            RegionType::Image => "IMG",
+            RegionType::Mapped => "MAP",
+            RegionType::Private => "PRV",
+            RegionType::Unknown => "???",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MemoryRegion {
-    pub start_address: usize,
-    pub size: usize,
+    pub start_address: Address,
+    pub size: Size,
+    // Base of the VirtualAlloc reservation this region belongs to.
+    pub allocation_base: Address,
     pub is_readable: bool,
     pub is_writable: bool,
     pub is_executable: bool,
+    // PAGE_GUARD is set. Such regions are never readable: the first touch raises a one-shot
+    // exception in the target, which is how its stacks grow.
+    #[serde(default)]
+    pub is_guard: bool,
+    // PAGE_WRITECOPY or PAGE_EXECUTE_WRITECOPY: the first write gives this process a private
+    // copy of the page instead of changing the shared one.
+    #[serde(default)]
+    pub is_copy_on_write: bool,
     pub protection: u32,
+    pub region_type: RegionType,
+    // Found by probing reads rather than queried; see capabilities.rs. Only readability is
+    // known, and only of the region's first page.
+    #[serde(default)]
+    pub is_inferred: bool,
+}
+
+impl MemoryRegion {
+    pub(crate) fn from_mbi(mbi: &MEMORY_BASIC_INFORMATION) -> Self {
+        // Guard and no-access pages fault on any access, whatever the base protection says.
+        let accessible = mbi.Protect & (PAGE_NOACCESS | PAGE_GUARD) == 0;
+        MemoryRegion {
+            start_address: Address::from(mbi.BaseAddress),
+            size: Size::new(mbi.RegionSize),
+            allocation_base: Address::from(mbi.AllocationBase),
+            is_readable: accessible && mbi.Protect & PAGE_READABLE != 0,
+            is_writable: accessible && mbi.Protect & PAGE_WRITABLE != 0,
+            is_executable: accessible && mbi.Protect & PAGE_EXECUTABLE != 0,
+            is_guard: mbi.Protect & PAGE_GUARD != 0,
+            is_copy_on_write: accessible && mbi.Protect & (PAGE_WRITECOPY | PAGE_EXECUTE_WRITECOPY) != 0,
+            protection: mbi.Protect,
+            region_type: RegionType::from_raw(mbi.Type),
+            is_inferred: false,
+        }
+    }
+
+    // "rwx"-style summary of the effective access.
+    pub fn protection_string(&self) -> String {
+        [
+            if self.is_readable { 'r' } else { '-' },
+            if self.is_writable { 'w' } else { '-' },
+            if self.is_executable { 'x' } else { '-' },
+        ]
+        .iter()
+        .collect()
+    }
+
+    // One past the last byte. Saturates rather than wrapping for a region reaching the top of
+    // the address space, which only a hand-edited snapshot can describe.
+    pub fn end(&self) -> Address {
+        self.start_address.checked_add(self.size).unwrap_or(Address::new(usize::MAX))
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.start_address.get()..self.end().get()
+    }
+
+    pub fn contains(&self, address: impl Into<Address>) -> bool {
+        let address = address.into();
+        address.checked_distance_from(self.start_address).is_some_and(|offset| offset < self.size)
+    }
+}
+
+// "0x7FF6A0000000-0x7FF6A0153000 r-x IMG  1.3 MiB", plus " guard" for guard pages. The
+// mapped file is not part of the region; `export::format_table` can add it.
+impl fmt::Display for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{} {} {}  {}",
+            self.start_address,
+            self.end(),
+            self.protection_string(),
+            self.region_type.tag(),
+            format_size(self.size.get())
+        )?;
+        if self.is_guard {
+            f.write_str(" guard")?;
+        }
+        if self.is_inferred {
+            f.write_str(" inferred")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MemoryRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "MemoryRegion({}-{} {} {} protection=0x{:X} allocation_base={}",
+            self.start_address,
+            self.end(),
+            self.protection_string(),
+            self.region_type.tag(),
+            self.protection,
+            self.allocation_base
+        )?;
+        if self.is_guard {
+            f.write_str(" guard")?;
+        }
+        if self.is_copy_on_write {
+            f.write_str(" cow")?;
+        }
+        if self.is_inferred {
+            f.write_str(" inferred")?;
+        }
+        f.write_str(")")
+    }
+}
+
+// Payload of the `io::Error` returned when a read fails because it hit a PAGE_GUARD page, as
+// opposed to memory that is simply not readable. See `is_guard_page_error`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuardPageError {
+    pub address: usize,
+}
+
+impl fmt::Display for GuardPageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to read memory: guard page at 0x{:X}", self.address)
+    }
+}
+
+impl std::error::Error for GuardPageError {}
+
+pub fn is_guard_page_error(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<GuardPageError>())
+}
+
+// Payload of the `io::Error` returned when a write stopped partway, typically at a page it
+// wasn't allowed to write. The first `written` bytes at `address` did change. See
+// `partial_write`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartialWrite {
+    pub address: usize,
+    pub requested: usize,
+    pub written: usize,
+}
+
+impl fmt::Display for PartialWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Only 0x{:X} of 0x{:X} bytes were written at 0x{:X}",
+            self.written, self.requested, self.address
+        )
+    }
+}
+
+impl std::error::Error for PartialWrite {}
+
+pub fn partial_write(e: &io::Error) -> Option<PartialWrite> {
+    e.get_ref()?.downcast_ref::<PartialWrite>().copied()
+}
+
+// What writes may do to file or pagefile-backed section views (`RegionType::Mapped`). Writes
+// into a shared view change the section itself, so other processes mapping it see them.
+// Image and private memory are not affected by the policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WritePolicy {
+    // Never write into a mapped view.
+    RefuseShared,
+    // Write into copy-on-write views only, which privatizes the pages written.
+    #[default]
+    AllowCow,
+    // Write into any view, shared or not.
+    Force,
+}
+
+// A write that dry-run mode validated but did not perform.
+#[derive(Debug, Clone)]
+pub struct PlannedWrite {
+    pub address: usize,
+    pub old_bytes: Vec<u8>,
+    pub new_bytes: Vec<u8>,
+    pub region: MemoryRegion,
 }
 
 pub struct Memory {
-    process_handle: *mut c_void,
-    regions: Vec<MemoryRegion>,
+    process_handle: Arc<ProcessHandle>,
+    process_id: u32,
+    regions: RegionCache,
+    modules: Vec<ModuleInfo>,
+    sections: Vec<SectionRef>,
+    operation_log: Option<Sender<OpEvent>>,
+    recorder: Mutex<Option<Recorder>>,
+    dry_run: bool,
+    write_policy: WritePolicy,
+    planned_writes: Mutex<Vec<PlannedWrite>>,
+    #[cfg(feature = "patch")]
+    patches: Mutex<Vec<Patch>>,
+    // Live allocations made through `allocate`/`allocate_near`, and ranges the user excluded
+    // from scans. See artifacts.rs.
+    allocations: Mutex<Vec<Range<usize>>>,
+    scan_exclusions: Mutex<Vec<Range<usize>>>,
+    // See throttle.rs.
+    throttle: Option<Throttle>,
+    background_priority: AtomicBool,
+    // What the scanning methods without an options parameter use.
+    default_scan_options: ScanOptions,
+    // See write_guard.rs.
+    write_guard: WriteGuardState,
+    // See `set_read_timeout`.
+    read_timeout: Option<Duration>,
+    buffer_pool: BufferPool,
+    // See read_log.rs: the log being recorded.
+    read_log: Mutex<Option<ReadLog>>,
+    // See backend.rs: what the calls go to instead of the handle, such as a replayed read log.
+    backend: Option<Arc<dyn Backend>>,
+    // See capabilities.rs.
+    capabilities: Capabilities,
+    // See safety_net.rs: where risky operations save what they touch, if they do.
+    safety_net: Option<PathBuf>,
+    // See coordination.rs.
+    coordinator: Option<Coordinator>,
+    // See metadata.rs.
+    metadata: MetadataState,
 }
 
 impl Memory {
     pub fn new() -> io::Result<Self> {
-        let process_id = unsafe { GetCurrentProcessId() };
-        let access = PROCESS_VM_READ | PROCESS_VM_WRITE | PROCESS_VM_OPERATION | PROCESS_QUERY_INFORMATION;
-        let handle = unsafe { OpenProcess(access, 0, process_id) };
-        
-        if handle.is_null() {
-            return Err(io::Error::new(io::ErrorKind::Other, "Failed to open process"));
+        Self::open(unsafe { GetCurrentProcessId() })
+    }
+
+    // Asks for full query rights, and settles for limited ones when those are refused, as they
+    // are for some processes of other users and sessions. Memory can then still be read and
+    // written, but region queries and module lists may fail; `process_tree::image_path` still
+    // identifies the target.
+    pub fn open(process_id: u32) -> io::Result<Self> {
+        Self::open_with_fallback(process_id, PROCESS_VM_READ | PROCESS_VM_WRITE | PROCESS_VM_OPERATION)
+    }
+
+    // For `ReadOnlyMemory`, whose handle can't write even by mistake.
+    pub(crate) fn open_read_only(process_id: u32) -> io::Result<Self> {
+        Self::open_with_fallback(process_id, PROCESS_VM_READ)
+    }
+
+    fn open_with_fallback(process_id: u32, vm_access: u32) -> io::Result<Self> {
+        match Self::open_with_access(process_id, vm_access | PROCESS_QUERY_INFORMATION) {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                let memory = Self::open_with_access(process_id, vm_access | PROCESS_QUERY_LIMITED_INFORMATION)
+                    .map_err(|_| e)?;
+                warn!(
+                    "process {} opened with limited query rights; region queries and module lists may fail",
+                    process_id
+                );
+                Ok(memory)
+            }
+            result => result,
         }
+    }
+
+    fn open_with_access(process_id: u32, access: u32) -> io::Result<Self> {
+        let handle = win_call!(unsafe { OpenProcess(access, 0, process_id) }, "Failed to open process {}", process_id)
+            .map_err(|e| {
+                // Only worth the extra calls once the open has failed.
+                let diagnosis = diagnostics::why_cant_open(process_id);
+                let kind = if diagnosis.exists { e.kind() } else { io::ErrorKind::NotFound };
+                io::Error::new(kind, format!("{}; {}", e, diagnosis))
+            })?;
+        let mut memory = Self::from_handle(handle, process_id);
+        memory.capabilities = Capabilities::probe(&memory, access);
+        Ok(memory)
+    }
 
-        Ok(Memory {
-            process_handle: handle,
-            regions: Vec::new(),
-        })
+    // For `Memory::from_replay`: no handle, only the log.
+    pub(crate) fn with_replay(backend: ReplayBackend) -> Self {
+        let process_id = backend.log().process_id;
+        let regions = RegionCache::new(backend.log().regions.clone());
+        let mut memory = Self::with_backend(Arc::new(backend), Capabilities::READ);
+        memory.process_id = process_id;
+        memory.regions = regions;
+        memory
     }
 
+    // No handle: every call goes to `backend`, allowed as far as `capabilities` say.
+    pub(crate) fn with_backend(backend: Arc<dyn Backend>, capabilities: Capabilities) -> Self {
+        let mut memory = Self::from_handle(ptr::null_mut(), 0);
+        memory.backend = Some(backend);
+        memory.capabilities = capabilities;
+        memory
+    }
+
+    fn from_handle(handle: *mut c_void, process_id: u32) -> Self {
+        Memory {
+            process_handle: Arc::new(ProcessHandle::new(handle, process_id)),
+            process_id,
+            regions: RegionCache::default(),
+            modules: Vec::new(),
+            sections: Vec::new(),
+            operation_log: None,
+            recorder: Mutex::new(None),
+            dry_run: false,
+            write_policy: WritePolicy::default(),
+            planned_writes: Mutex::new(Vec::new()),
+            #[cfg(feature = "patch")]
+            patches: Mutex::new(Vec::new()),
+            allocations: Mutex::new(Vec::new()),
+            scan_exclusions: Mutex::new(Vec::new()),
+            throttle: None,
+            background_priority: AtomicBool::new(false),
+            default_scan_options: ScanOptions::default(),
+            write_guard: WriteGuardState::default(),
+            read_timeout: None,
+            buffer_pool: BufferPool::default(),
+            read_log: Mutex::new(None),
+            backend: None,
+            capabilities: Capabilities::ALL,
+            safety_net: None,
+            coordinator: None,
+            metadata: MetadataState::default(),
+        }
+    }
+
+    pub fn process_id(&self) -> u32 {
+        self.process_id
+    }
+
+    // What the handle was found to be good for when it was opened.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    // Width of the target's pointers in bytes: 4 for WOW64 (32-bit) processes, else the host's.
+    pub fn pointer_size(&self) -> io::Result<usize> {
+        if let Some(backend) = self.backend() {
+            return backend.pointer_size();
+        }
+        let mut wow64 = 0;
+        win_call!(
+            unsafe { IsWow64Process(self.handle(), &mut wow64) },
+            "Failed to query the bitness of process {}",
+            self.process_id
+        )?;
+        Ok(if wow64 != 0 { 4 } else { mem::size_of::<usize>() })
+    }
+
+    // Reads a pointer of the target's width, zero-extended for 32-bit targets.
+    pub fn read_pointer(&self, address: usize) -> io::Result<usize> {
+        if self.pointer_size()? == 4 {
+            Ok(self.read_unaligned::<u32>(address)? as usize)
+        } else {
+            self.read_unaligned(address)
+        }
+    }
+
+    pub(crate) fn handle(&self) -> *mut c_void {
+        self.process_handle.raw()
+    }
+
+    pub(crate) fn process_handle(&self) -> &Arc<ProcessHandle> {
+        &self.process_handle
+    }
+
+    // Carries cached regions and modules over to another handle on the same process.
+    pub(crate) fn copy_caches_from(&mut self, other: &Memory) {
+        self.regions = other.regions.clone();
+        self.modules = other.modules.clone();
+        self.sections = other.sections.clone();
+        self.default_scan_options = other.default_scan_options.clone();
+    }
+
+    #[cfg(feature = "patch")]
+    pub(crate) fn patch_list(&self) -> &Mutex<Vec<Patch>> {
+        &self.patches
+    }
+
+    pub(crate) fn metadata_setting(&self) -> &MetadataState {
+        &self.metadata
+    }
+
+    pub(crate) fn metadata_slot(&mut self) -> &mut MetadataState {
+        &mut self.metadata
+    }
+
+    pub(crate) fn region_cache(&self) -> &RegionCache {
+        &self.regions
+    }
+
+    pub(crate) fn region_cache_mut(&mut self) -> &mut RegionCache {
+        &mut self.regions
+    }
+
+    pub(crate) fn take_region_cache(&mut self) -> RegionCache {
+        std::mem::take(&mut self.regions)
+    }
+
+    pub(crate) fn buffer_pool(&self) -> &BufferPool {
+        &self.buffer_pool
+    }
+
+    pub(crate) fn read_log_slot(&self) -> &Mutex<Option<ReadLog>> {
+        &self.read_log
+    }
+
+    // What a `Memory` made by `from_replay` reads from.
+    pub fn replay_backend(&self) -> Option<&ReplayBackend> {
+        self.backend.as_deref().and_then(|backend| backend.as_replay())
+    }
+
+    pub(crate) fn backend(&self) -> Option<&dyn Backend> {
+        self.backend.as_deref()
+    }
+
+    pub(crate) fn safety_net_setting(&self) -> &Option<PathBuf> {
+        &self.safety_net
+    }
+
+    pub(crate) fn safety_net_slot(&mut self) -> &mut Option<PathBuf> {
+        &mut self.safety_net
+    }
+
+    pub(crate) fn coordinator_setting(&self) -> &Option<Coordinator> {
+        &self.coordinator
+    }
+
+    pub(crate) fn coordinator_slot(&mut self) -> &mut Option<Coordinator> {
+        &mut self.coordinator
+    }
+
+    pub(crate) fn write_guard_state(&self) -> &WriteGuardState {
+        &self.write_guard
+    }
+
+    pub(crate) fn write_guard_state_mut(&mut self) -> &mut WriteGuardState {
+        &mut self.write_guard
+    }
+
+    pub(crate) fn allocation_list(&self) -> &Mutex<Vec<Range<usize>>> {
+        &self.allocations
+    }
+
+    pub(crate) fn scan_exclusion_list(&self) -> &Mutex<Vec<Range<usize>>> {
+        &self.scan_exclusions
+    }
+
+    pub fn set_operation_log(&mut self, log: Option<Sender<OpEvent>>) {
+        self.operation_log = log;
+    }
+
+    // Caches the committed regions as they are now. The cache is a snapshot: it isn't updated
+    // as the target allocates or reprotects memory. `query_address` gives the live view, and
+    // `refresh_regions_partial` brings the cache up to date a slice at a time.
     pub fn scan_memory(&mut self) -> io::Result<()> {
-        let mut address: usize = 0;
-        
-        while address < usize::MAX {
-            let mut mbi: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
-            let result = unsafe {
+        let regions = self.enumerate_regions();
+        self.regions.replace(regions);
+        self.refresh_write_guard();
+        self.metadata.regions_loaded();
+        Ok(())
+    }
+
+    // The write guard's allowed ranges follow the cached regions.
+    pub(crate) fn refresh_write_guard(&mut self) {
+        self.write_guard.refresh_regions(self.regions.as_slice());
+    }
+
+    // The committed regions as they are right now, without touching the cached list.
+    pub fn enumerate_regions(&self) -> Vec<MemoryRegion> {
+        if let Some(regions) = self.backend().and_then(|backend| backend.regions()) {
+            return regions;
+        }
+        if !self.capabilities.can_query() && self.capabilities.can_read() {
+            return self.probe_regions();
+        }
+        self.walk_regions(0, |_| true).0
+    }
+
+    // The committed regions from the one containing `from` upwards, querying on for as long
+    // as `more` says so about the next address. Also returns the range the walk covered,
+    // from the first region's base to where it stopped; it ends at usize::MAX when the walk
+    // reached the top of the address space.
+    pub(crate) fn walk_regions(
+        &self,
+        from: usize,
+        mut more: impl FnMut(usize) -> bool,
+    ) -> (Vec<MemoryRegion>, Range<usize>) {
+        let _span = span!("enumerate_regions", from = from; regions, end);
+        let mut regions = Vec::new();
+        let mut start = None;
+        let mut address = from;
+
+        loop {
+            let mbi = match self.query(address) {
+                Ok(mbi) => mbi,
+                Err(e) => {
+                    debug!("region enumeration stopped: {}", e);
+                    address = usize::MAX;
+                    break;
+                }
+            };
+            start.get_or_insert(mbi.BaseAddress as usize);
+
+            if mbi.State != MEM_COMMIT {
+                debug!(
+                    "skipping region 0x{:X} (size 0x{:X}): not committed (state 0x{:X})",
+                    mbi.BaseAddress as usize, mbi.RegionSize, mbi.State
+                );
+            } else {
+                regions.push(MemoryRegion::from_mbi(&mbi));
+            }
+
+            // The last region can end exactly at the top of the address space.
+            match Address::from(mbi.BaseAddress).checked_add(mbi.RegionSize) {
+                Some(next) if next > address && next.get() < usize::MAX => address = next.get(),
+                _ => {
+                    address = usize::MAX;
+                    break;
+                }
+            }
+            if !more(address) {
+                break;
+            }
+        }
+
+        record!(_span, regions = regions.len());
+        record!(_span, end = address);
+        (regions, start.unwrap_or(from).min(address)..address)
+    }
+
+    // The committed region starting at `address` right now, if there is one.
+    pub(crate) fn region_starting_at(&self, address: usize) -> Option<MemoryRegion> {
+        self.query_address(address).ok().filter(|info| info.base == address).and_then(|info| info.to_region())
+    }
+
+    pub(crate) fn query(&self, address: usize) -> io::Result<MEMORY_BASIC_INFORMATION> {
+        self.require(Capabilities::QUERY)?;
+        if let Some(backend) = self.backend() {
+            return backend.query(address);
+        }
+        let mut mbi: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+        win_call!(
+            unsafe {
                 VirtualQueryEx(
-                    self.process_handle,
+                    self.handle(),
                     address as *const c_void,
                     &mut mbi,
                     mem::size_of::<MEMORY_BASIC_INFORMATION>(),
                 )
-            };
+            },
+            "VirtualQueryEx failed at 0x{:X}",
+            address
+        )?;
+        Ok(mbi)
+    }
 
-            if result == 0 {
-                break;
-            }
+    pub fn read_memory<T>(&self, address: usize) -> io::Result<T> {
+        let mut buffer: T = unsafe { mem::zeroed() };
+        let size = mem::size_of::<T>();
 
-            if mbi.state == 0x1000 { // MEM_COMMIT
-                let region = MemoryRegion {
-                    start_address: mbi.base_address as usize,
-                    size: mbi.region_size,
-                    is_readable: (mbi.protect & 0x1) != 0, // PAGE_NOACCESS
-                    is_writable: (mbi.protect & 0x40) != 0, // PAGE_EXECUTE_READWRITE
-                    is_executable: (mbi.protect & 0x10) != 0, // PAGE_EXECUTE
-                    protection: mbi.protect,
-                };
-                self.regions.push(region);
-            }
+        let result = self.read_raw(address, &mut buffer as *mut T as *mut c_void, size);
+        self.log_operation(OpEvent::Read { address, size, result: to_op_result(&result) });
+        let read = result?;
+        if read < size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Only 0x{:X} of 0x{:X} bytes at 0x{:X} are readable", read, size, address),
+            ));
+        }
 
-            address = mbi.base_address as usize + mbi.region_size;
+        Ok(buffer)
+    }
+
+    // Reads a `T` from any address, such as a field of a packed struct. The bytes land in a plain
+    // byte buffer and are loaded with `ptr::read_unaligned`, so no reference to a misaligned `T`
+    // ever exists. Fails unless the whole value could be read.
+    pub fn read_unaligned<T: Copy>(&self, address: usize) -> io::Result<T> {
+        let size = mem::size_of::<T>();
+        let bytes = self.read_bytes(address, size)?;
+        if bytes.len() < size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Only 0x{:X} of 0x{:X} bytes at 0x{:X} are readable", bytes.len(), size, address),
+            ));
         }
+        Ok(unsafe { ptr::read_unaligned(bytes.as_ptr() as *const T) })
+    }
 
-        Ok(())
+    pub fn read_bytes(&self, address: usize, len: usize) -> io::Result<Vec<u8>> {
+        let mut buffer = vec![0u8; len];
+
+        let result = self.read_raw(address, buffer.as_mut_ptr() as *mut c_void, len);
+        self.log_operation(OpEvent::Read { address, size: len, result: to_op_result(&result) });
+
+        buffer.truncate(result?);
+        Ok(buffer)
     }
 
-    pub fn read_memory<T>(&self, address: usize) -> io::Result<T> {
-        let mut buffer: T = unsafe { mem::zeroed() };
+    // A read into a buffer the caller has, returning how much of it was filled.
+    pub(crate) fn read_into(&self, address: usize, buffer: &mut [u8]) -> io::Result<usize> {
+        self.read_raw(address, buffer.as_mut_ptr() as *mut c_void, buffer.len())
+    }
+
+    // `read_into` for the chunks of scans, readers and monitors, so it is the one the
+    // throttle applies to.
+    pub(crate) fn read_bulk(&self, address: usize, buffer: &mut [u8]) -> io::Result<usize> {
+        self.throttle_read(buffer.len());
+        self.read_into(address, buffer)
+    }
+
+    // Every read goes through here. One that fails or comes back short is retried a region at
+    // a time if it spans several (see stitch.rs).
+    fn read_raw(&self, address: usize, buffer: *mut c_void, len: usize) -> io::Result<usize> {
+        let _span = span!("read", address = address, size = len; outcome);
+        let buffer = unsafe { slice::from_raw_parts_mut(buffer as *mut u8, len) };
+        let result = match self.read_once(address, buffer) {
+            Ok(read) if read == len => Ok(read),
+            result => self.read_stitched(address, buffer, result),
+        };
+        record!(_span, outcome = crate::spans::read_outcome(&result));
+        result
+    }
+
+    // One call of the backend, so this is where the read log records and replays them.
+    pub(crate) fn read_once(&self, address: usize, buffer: &mut [u8]) -> io::Result<usize> {
+        self.require(Capabilities::READ)?;
+        let result = match self.backend() {
+            Some(backend) => backend.read(address, buffer),
+            None => self.read_process(address, buffer.as_mut_ptr() as *mut c_void, buffer.len()),
+        };
+        if let Some(log) = self.read_log.lock().unwrap().as_mut() {
+            let read = *result.as_ref().unwrap_or(&0);
+            log.record(address, buffer.len(), &buffer[..read], &result);
+        }
+        result
+    }
+
+    // Every ReadProcessMemory call goes through here so partial reads are traced in one place.
+    fn read_process(&self, address: usize, buffer: *mut c_void, len: usize) -> io::Result<usize> {
         let mut bytes_read: usize = 0;
 
-        let result = unsafe {
-            ReadProcessMemory(
-                self.process_handle,
-                address as *const c_void,
-                &mut buffer as *mut T as *mut c_void,
-                mem::size_of::<T>(),
-                &mut bytes_read,
-            )
+        // The last error is captured on the thread that made the call, before the guard page
+        // query below can overwrite it.
+        let result = match self.read_timeout {
+            None => win_call!(
+                unsafe {
+                    ReadProcessMemory(self.handle(), address as *const c_void, buffer, len, &mut bytes_read)
+                },
+                "Failed to read 0x{:X} bytes at 0x{:X}",
+                len,
+                address
+            ),
+            Some(timeout) => {
+                // The worker reads into a buffer of its own: after a timeout it may still be
+                // writing long after the caller's buffer is gone.
+                // The worker holds the handle too, so it stays open until the read comes back.
+                let handle = Arc::clone(&self.process_handle);
+                let (result, read, bytes) = with_timeout(timeout, move || {
+                    let mut bytes = vec![0u8; len];
+                    let mut read = 0;
+                    let result = win_call!(
+                        unsafe {
+                            ReadProcessMemory(
+                                handle.raw(),
+                                address as *const c_void,
+                                bytes.as_mut_ptr() as *mut c_void,
+                                len,
+                                &mut read,
+                            )
+                        },
+                        "Failed to read 0x{:X} bytes at 0x{:X}",
+                        len,
+                        address
+                    );
+                    (result, read, bytes)
+                })
+                .map_err(|e| io::Error::new(e.kind(), format!("Read of 0x{:X} bytes at 0x{:X}: {}", len, address, e)))?;
+                unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), buffer as *mut u8, read.min(len)) };
+                bytes_read = read;
+                result
+            }
         };
 
-        if result == 0 {
-            return Err(io::Error::new(io::ErrorKind::Other, "Failed to read memory"));
+        if let Err(e) = result {
+            trace!("{} ({} bytes copied)", e, bytes_read);
+            let failed_at = address + bytes_read;
+            if self.query_address(failed_at).is_ok_and(|info| info.protection & PAGE_GUARD != 0) {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, GuardPageError { address: failed_at }));
+            }
+            return Err(e);
         }
 
-        Ok(buffer)
+        if bytes_read < len {
+            trace!("partial read at 0x{:X}: 0x{:X} of 0x{:X} bytes", address, bytes_read, len);
+        } else {
+            trace!("read 0x{:X} bytes at 0x{:X}", len, address);
+        }
+
+        Ok(bytes_read)
+    }
+
+    fn log_operation(&self, event: OpEvent) {
+        oplog::emit(&self.operation_log, event);
     }
 
     pub fn write_memory<T>(&self, address: usize, value: T) -> io::Result<()> {
+        let bytes = unsafe { slice::from_raw_parts(&value as *const T as *const u8, mem::size_of::<T>()) };
+        self.write_bytes(address, bytes)
+    }
+
+    // Counterpart of `read_unaligned`: the value is copied out byte-wise, so `address` needs no
+    // particular alignment.
+    pub fn write_unaligned<T: Copy>(&self, address: usize, value: T) -> io::Result<()> {
+        let mut bytes = vec![0u8; mem::size_of::<T>()];
+        unsafe { ptr::write_unaligned(bytes.as_mut_ptr() as *mut T, value) };
+        self.write_bytes(address, &bytes)
+    }
+
+    // A write that stops partway fails with a `PartialWrite` error. With the recorder on, the
+    // part that was written is still recorded, so it can be undone.
+    pub fn write_bytes(&self, address: usize, bytes: &[u8]) -> io::Result<()> {
+        let mut recorder = self.recorder.lock().unwrap();
+        let Some(recorder) = recorder.as_mut().filter(|_| !self.dry_run) else {
+            return self.write_raw(address, bytes);
+        };
+
+        // Capture what is about to be overwritten so the write can be undone later.
+        let old_bytes = self.read_bytes(address, bytes.len())?;
+        if old_bytes.len() < bytes.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Only 0x{:X} of 0x{:X} bytes at 0x{:X} are readable", old_bytes.len(), bytes.len(), address),
+            ));
+        }
+        let result = self.write_raw(address, bytes);
+        let written = match &result {
+            Ok(()) => bytes.len(),
+            Err(e) => partial_write(e).map_or(0, |partial| partial.written),
+        };
+        if written > 0 {
+            let (old_bytes, new_bytes) = (old_bytes[..written].to_vec(), bytes[..written].to_vec());
+            recorder.push(WriteRecord::new(address, old_bytes, new_bytes, &self.modules));
+        }
+        result
+    }
+
+    // Adds a write made without `write_bytes` to the recording, if one is on, for the layers
+    // that record a whole operation once, such as a patch.
+    #[cfg(feature = "patch")]
+    pub(crate) fn record_write(&self, address: usize, old_bytes: &[u8], new_bytes: &[u8]) {
+        if let Some(recorder) = self.recorder.lock().unwrap().as_mut().filter(|_| !self.dry_run) {
+            recorder.push(WriteRecord::new(address, old_bytes.to_vec(), new_bytes.to_vec(), &self.modules));
+        }
+    }
+
+    fn write_raw(&self, address: usize, bytes: &[u8]) -> io::Result<()> {
+        if self.dry_run {
+            return self.plan_write(address, bytes, true);
+        }
+        self.require(Capabilities::WRITE)?;
+        self.check_write(address, bytes.len())?;
+
+        let size = bytes.len();
+        let result = match self.write_once(address, bytes) {
+            Ok(()) => Ok(()),
+            result => self.write_stitched(address, bytes, result),
+        };
+        match &result {
+            Ok(()) => self.log_operation(OpEvent::Write { address, size, result: Ok(size) }),
+            Err(e) => {
+                warn!("{}", e);
+                self.log_operation(OpEvent::Write { address, size, result: Err(e.to_string()) });
+            }
+        }
+        result
+    }
+
+    // One WriteProcessMemory call. Stopping partway fails with a `PartialWrite`.
+    pub(crate) fn write_once(&self, address: usize, bytes: &[u8]) -> io::Result<()> {
+        if let Some(backend) = self.backend() {
+            return backend.write(address, bytes);
+        }
         let mut bytes_written: usize = 0;
+        let size = bytes.len();
+
+        let result = win_call!(
+            unsafe {
+                WriteProcessMemory(
+                    self.handle(),
+                    address as *mut c_void,
+                    bytes.as_ptr() as *const c_void,
+                    size,
+                    &mut bytes_written,
+                )
+            },
+            "Failed to write 0x{:X} bytes at 0x{:X}",
+            size,
+            address
+        );
+
+        // ERROR_PARTIAL_COPY fails the call but still reports the bytes that made it, and a
+        // successful call can report fewer bytes than asked for.
+        if (result.is_err() && bytes_written > 0) || (result.is_ok() && bytes_written < size) {
+            let partial = PartialWrite { address, requested: size, written: bytes_written.min(size) };
+            return Err(io::Error::other(partial));
+        }
+        result.map(|_| ())
+    }
 
-        let result = unsafe {
-            WriteProcessMemory(
-                self.process_handle,
-                address as *mut c_void,
-                &value as *const T as *const c_void,
-                mem::size_of::<T>(),
-                &mut bytes_written,
+    // Changes the protection of the pages covering `address..address + size` and returns the
+    // previous protection of the first page.
+    pub fn protect(&self, address: usize, size: usize, protection: u32) -> io::Result<u32> {
+        self.require(Capabilities::VM_OPERATION)?;
+        let mut old_protection = 0;
+        let result = match self.backend() {
+            Some(backend) => backend.protect(address, size, protection).map(|old| old_protection = old),
+            None => win_call!(
+                unsafe {
+                    VirtualProtectEx(self.handle(), address as *mut c_void, size, protection, &mut old_protection)
+                },
+                "Failed to change protection at 0x{:X}",
+                address
             )
+            .map(|_| ()),
         };
 
-        if result == 0 {
-            return Err(io::Error::new(io::ErrorKind::Other, "Failed to write memory"));
+        if let Err(e) = result {
+            warn!("protecting 0x{:X} bytes at 0x{:X} as 0x{:X} failed: {}", size, address, protection, e);
+            self.log_operation(OpEvent::Protect { address, size, result: Err(e.to_string()) });
+            return Err(e);
         }
 
-        Ok(())
+        trace!("protected 0x{:X} bytes at 0x{:X}: 0x{:X} -> 0x{:X}", size, address, old_protection, protection);
+        self.log_operation(OpEvent::Protect { address, size, result: Ok(size) });
+        Ok(old_protection)
+    }
+
+    // Writes into read-only or code pages by making them writable for the duration of the write.
+    // Each region the range touches gets its own protection back afterwards, by a
+    // `ProtectionGuard` so that it does even if the write panics, and the instruction cache
+    // is flushed so patched code takes effect.
+    pub fn write_protected(&self, address: usize, bytes: &[u8]) -> io::Result<()> {
+        self.write_unprotecting(address, bytes, |address, bytes| self.write_bytes(address, bytes))
     }
 
-    pub fn find_pattern(&self, pattern: &[u8], mask: &[bool]) -> io::Result<Option<usize>> {
-        for region in &self.regions {
-            if !region.is_readable {
-                continue;
+    // `write_protected` without the recorder, for writes recorded by the caller or none at
+    // all: patches, and undoing recorded writes.
+    pub(crate) fn write_protected_unrecorded(&self, address: usize, bytes: &[u8]) -> io::Result<()> {
+        self.write_unprotecting(address, bytes, |address, bytes| self.write_raw(address, bytes))
+    }
+
+    fn write_unprotecting(
+        &self,
+        address: usize,
+        bytes: &[u8],
+        write: impl FnOnce(usize, &[u8]) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if self.dry_run {
+            return self.plan_write(address, bytes, false);
+        }
+        // Checked before any protection changes; a view made writable here is still shared.
+        self.check_write(address, bytes.len())?;
+
+        let end = address
+            .checked_add(bytes.len())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Write range overflows the address space"))?;
+
+        let mut unprotected = ProtectionGuard::new(self);
+        let mut cursor = address;
+        let mut result = Ok(());
+        while cursor < end {
+            let region_end = match self.query_address(cursor) {
+                Ok(info) => info.end().get().min(end),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            };
+            match self.protect(cursor, region_end - cursor, PAGE_EXECUTE_READWRITE) {
+                Ok(old_protection) => unprotected.record(cursor..region_end, old_protection),
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
             }
+            cursor = region_end;
+        }
 
-            let mut buffer = vec![0u8; region.size];
-            let mut bytes_read: usize = 0;
+        if result.is_ok() {
+            result = write(address, bytes);
+        }
 
-            let result = unsafe {
-                ReadProcessMemory(
-                    self.process_handle,
-                    region.start_address as *const c_void,
-                    buffer.as_mut_ptr() as *mut c_void,
-                    region.size,
-                    &mut bytes_read,
-                )
+        // Failures are logged, and don't fail a write that went through.
+        let _ = unprotected.restore();
+        if self.backend().is_none() {
+            unsafe { FlushInstructionCache(self.handle(), address as *const c_void, bytes.len()) };
+        }
+
+        result
+    }
+
+    // While enabled, writes are validated and collected as `PlannedWrite`s instead of being performed.
+    pub fn set_dry_run(&mut self, dry_run: bool) {
+        self.dry_run = dry_run;
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub fn set_write_policy(&mut self, policy: WritePolicy) {
+        self.write_policy = policy;
+    }
+
+    pub fn write_policy(&self) -> WritePolicy {
+        self.write_policy
+    }
+
+    // Rate limit for bulk reads; see throttle.rs.
+    pub fn set_throttle(&mut self, throttle: Option<Throttle>) {
+        self.throttle = throttle;
+    }
+
+    pub fn throttle(&self) -> Option<&Throttle> {
+        self.throttle.as_ref()
+    }
+
+    // Gives up on any read that takes longer than `timeout` with TimedOut, for targets that
+    // can hang a ReadProcessMemory call (one stuck in the kernel, say). Each read then runs on
+    // a worker thread and is copied over, which costs a thread handoff per call; a read that
+    // hung goes on in the background and its result is thrown away. None (the default) reads
+    // directly.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    // Options for the scanning methods that don't take any (`find_pattern`, `scan_for_value`,
+    // `extract_strings`, ...). The `_with` variants use what they are given instead.
+    pub fn set_default_scan_options(&mut self, options: ScanOptions) {
+        self.default_scan_options = options;
+    }
+
+    pub fn default_scan_options(&self) -> &ScanOptions {
+        &self.default_scan_options
+    }
+
+    // Whether worker threads working on this `Memory` (the change monitor, async scans) run
+    // below normal priority, so the target's own threads win when the CPU is contended.
+    // Threads that are already running pick the change up before their next batch of reads.
+    pub fn set_background_priority(&self, enabled: bool) {
+        self.background_priority.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn background_priority(&self) -> bool {
+        self.background_priority.load(Ordering::Relaxed)
+    }
+
+    // The write guard first, then the write policy.
+    fn check_write(&self, address: usize, len: usize) -> io::Result<()> {
+        self.check_write_guard(address, len)?;
+        self.check_write_policy(address, len)
+    }
+
+    // Fails with PermissionDenied if the policy forbids writing to any region the range touches.
+    fn check_write_policy(&self, address: usize, len: usize) -> io::Result<()> {
+        if self.write_policy == WritePolicy::Force {
+            return Ok(());
+        }
+
+        let end = address.saturating_add(len);
+        let mut cursor = address;
+        while cursor < end {
+            let Ok(info) = self.query_address(cursor) else { break };
+            cursor = info.end().get();
+            // Writes into uncommitted memory fail anyway.
+            let Some(region) = info.to_region() else { continue };
+            let allowed = match self.write_policy {
+                _ if region.region_type != RegionType::Mapped => true,
+                WritePolicy::RefuseShared => false,
+                WritePolicy::AllowCow => region.is_copy_on_write,
+                WritePolicy::Force => true,
             };
+            if !allowed {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "Write policy {:?} forbids writing to the mapped view at 0x{:X} (protection 0x{:X})",
+                        self.write_policy, region.start_address, region.protection
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
 
-            if result == 0 {
-                continue;
+    pub fn take_planned_writes(&self) -> Vec<PlannedWrite> {
+        mem::take(&mut *self.planned_writes.lock().unwrap())
+    }
+
+    fn plan_write(&self, address: usize, bytes: &[u8], require_writable: bool) -> io::Result<()> {
+        let end = address
+            .checked_add(bytes.len())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Write range overflows the address space"))?;
+        self.check_write(address, bytes.len())?;
+
+        // Every region the write touches must be committed and, unless the write will change
+        // protection itself, writable, just as for a real write.
+        let mut first_region = None;
+        let mut cursor = address;
+        while cursor < end || first_region.is_none() {
+            let info = self.query_address(cursor)?;
+            let Some(region) = info.to_region() else {
+                return Err(io::Error::other(format!("Region at 0x{:X} is not committed", info.base)));
+            };
+            if require_writable && !region.is_writable {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!("Region at 0x{:X} is not writable (protection 0x{:X})", region.start_address, region.protection),
+                ));
             }
+            cursor = region.end().get();
+            first_region.get_or_insert(region);
+        }
 
-            for i in 0..(bytes_read - pattern.len()) {
-                let mut found = true;
-                for j in 0..pattern.len() {
-                    if mask[j] && buffer[i + j] != pattern[j] {
-                        found = false;
-                        break;
-                    }
-                }
-                if found {
-                    return Ok(Some(region.start_address + i));
-                }
+        let old_bytes = self.read_bytes(address, bytes.len())?;
+        if old_bytes.len() < bytes.len() {
+            return Err(io::Error::other(format!("Failed to read back 0x{:X} bytes at 0x{:X}", bytes.len(), address)));
+        }
+
+        debug!("dry run: planned write of 0x{:X} bytes at 0x{:X}", bytes.len(), address);
+        self.planned_writes.lock().unwrap().push(PlannedWrite {
+            address,
+            old_bytes,
+            new_bytes: bytes.to_vec(),
+            region: first_region.unwrap(),
+        });
+        Ok(())
+    }
+
+    // Module-relative offsets are taken from the cached module list, which is loaded here if needed.
+    pub fn start_recording(&mut self) {
+        if self.modules.is_empty() {
+            if let Err(e) = self.scan_modules() {
+                debug!("recording without module information: {}", e);
             }
         }
+        *self.recorder.lock().unwrap() = Some(Recorder::new());
+    }
 
-        Ok(None)
+    pub fn stop_recording(&mut self) -> Option<Recorder> {
+        self.recorder.lock().unwrap().take()
     }
 
-    pub fn get_regions(&self) -> &Vec<MemoryRegion> {
-        &self.regions
+    pub fn recorded_writes(&self) -> Vec<WriteRecord> {
+        self.recorder
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or_else(Vec::new, |recorder| recorder.records().to_vec())
+    }
+
+    pub fn save_recording(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        match self.recorder.lock().unwrap().as_ref() {
+            Some(recorder) => recorder.save(path),
+            None => Err(io::Error::other("Recording is not enabled")),
+        }
+    }
+
+    // Restores the bytes overwritten by the most recent recorded write, lifting the protection
+    // for it as `write_protected` does, since the write may have been a patch into code.
+    pub fn undo_last(&self) -> io::Result<Option<WriteRecord>> {
+        let mut recorder = self.recorder.lock().unwrap();
+        let Some(recorder) = recorder.as_mut() else {
+            return Ok(None);
+        };
+        if self.dry_run {
+            // Plan the restore but keep the record: nothing was actually undone.
+            let Some(record) = recorder.records().last().cloned() else {
+                return Ok(None);
+            };
+            self.plan_write(record.address, &record.old_bytes, false)?;
+            return Ok(Some(record));
+        }
+        let Some(record) = recorder.pop() else {
+            return Ok(None);
+        };
+
+        if let Err(e) = self.write_protected_unrecorded(record.address, &record.old_bytes) {
+            recorder.push(record);
+            return Err(e);
+        }
+        Ok(Some(record))
+    }
+
+    // Undoes every recorded write, newest first, so overlapping writes unwind correctly.
+    pub fn undo_all(&self) -> io::Result<usize> {
+        if self.dry_run {
+            let records = self.recorded_writes();
+            for record in records.iter().rev() {
+                self.plan_write(record.address, &record.old_bytes, false)?;
+            }
+            return Ok(records.len());
+        }
+
+        let mut undone = 0;
+        while self.undo_last()?.is_some() {
+            undone += 1;
+        }
+        Ok(undone)
+    }
+
+    // Re-applies a saved recording, resolving module-relative addresses against this process.
+    pub fn replay(&self, path: impl AsRef<Path>) -> io::Result<usize> {
+        let recording = Recorder::load(path)?;
+        let modules = module::enumerate_modules(self.process_id)?;
+
+        for record in recording.records() {
+            let address = record.resolve(&modules)?;
+            self.write_bytes(address, &record.new_bytes)?;
+        }
+
+        Ok(recording.records().len())
+    }
+
+    pub fn pattern_matches<'a>(
+        &'a self,
+        pattern: &'a Pattern,
+        filter: &'a RegionFilter,
+    ) -> impl Iterator<Item = io::Result<usize>> + 'a {
+        self.pattern_matches_with(pattern, filter, &self.default_scan_options)
+    }
+
+    pub fn pattern_matches_with<'a>(
+        &'a self,
+        pattern: &'a Pattern,
+        filter: &'a RegionFilter,
+        options: &ScanOptions,
+    ) -> impl Iterator<Item = io::Result<usize>> + 'a {
+        PatternMatches::new(self, pattern, filter, options)
+    }
+
+    // Regions that fail to read are skipped; use `pattern_matches` to see those errors.
+    pub fn find_pattern(&self, pattern: &Pattern, filter: &RegionFilter) -> io::Result<Option<usize>> {
+        self.find_pattern_with(pattern, filter, &self.default_scan_options)
+    }
+
+    // With `ScanDirection::Reverse` this returns the highest match.
+    pub fn find_pattern_with(
+        &self,
+        pattern: &Pattern,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<Option<usize>> {
+        let mut found = None;
+        let mut scan = PatternMatches::new(self, pattern, filter, options);
+        scan.for_each_match(|address| {
+            found = Some(address);
+            ControlFlow::Break(())
+        });
+        self.track_results(found.as_slice());
+        scan.finish(found.into_iter().collect());
+        Ok(found)
+    }
+
+    pub fn find_pattern_all(&self, pattern: &Pattern, filter: &RegionFilter) -> io::Result<Vec<usize>> {
+        self.find_pattern_all_with(pattern, filter, &self.default_scan_options)
+    }
+
+    // Always returns the matches in ascending order, whatever the scan direction.
+    pub fn find_pattern_all_with(
+        &self,
+        pattern: &Pattern,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<Vec<usize>> {
+        self.find_pattern_limit_with(pattern, filter, options, usize::MAX)
+    }
+
+    // The first `limit` matches in scan order (the lowest ones scanning forward, the highest in
+    // reverse), sorted ascending. The scan stops as soon as it has them.
+    pub fn find_pattern_limit(&self, pattern: &Pattern, filter: &RegionFilter, limit: usize) -> io::Result<Vec<usize>> {
+        self.find_pattern_limit_with(pattern, filter, &self.default_scan_options, limit)
+    }
+
+    pub fn find_pattern_limit_with(
+        &self,
+        pattern: &Pattern,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+        limit: usize,
+    ) -> io::Result<Vec<usize>> {
+        let mut scan = PatternMatches::new(self, pattern, filter, options);
+        let mut matches = collect_matches(&mut scan, limit);
+        // The scan already yields each match once; this also covers regions that overlap in
+        // the cache, e.g. after scanning memory twice without clearing it.
+        matches.sort_unstable();
+        matches.dedup();
+        self.track_results(&matches);
+        Ok(scan.finish(matches).matches)
+    }
+
+    // Like `find_pattern_all_with`, with what the scan covered: the regions it read and those
+    // it skipped and why (stale ones only come up in `validate_regions` scans), the bytes
+    // read, the unreadable pages it went around and the time taken.
+    pub fn find_pattern_report(
+        &self,
+        pattern: &Pattern,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<ScanReport> {
+        let mut scan = PatternMatches::new(self, pattern, filter, options);
+        let mut matches = collect_matches(&mut scan, usize::MAX);
+        matches.sort_unstable();
+        matches.dedup();
+        self.track_results(&matches);
+        Ok(scan.finish(matches))
+    }
+
+    // Runs a pattern scan for `sink` alone (see sink.rs): the matches go to it as they are
+    // found and nowhere else, so the report returned, like the one the sink gets, has none.
+    // Nor are they tracked for the write guard.
+    pub fn find_pattern_into<S: ScanSink + 'static>(
+        &self,
+        pattern: &Pattern,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+        sink: &Arc<Mutex<S>>,
+    ) -> io::Result<ScanReport> {
+        let options = options.clone().sink(Arc::clone(sink));
+        let mut scan = PatternMatches::new(self, pattern, filter, &options);
+        scan.for_each_match(|_| ControlFlow::Continue(()));
+        Ok(scan.finish(Vec::new()))
+    }
+
+    // Finds every copy of `value`'s in-memory bytes, by default only at `align_of::<T>()` addresses.
+    // With `ScanOptions::endianness` set to the non-native order, the bytes are searched for
+    // reversed, which is only meaningful for scalar values.
+    pub fn scan_for_value<T: Copy>(&self, value: T, filter: &RegionFilter) -> io::Result<Vec<usize>> {
+        self.scan_for_value_with(value, filter, &self.default_scan_options)
+    }
+
+    pub fn scan_for_value_with<T: Copy>(
+        &self,
+        value: T,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<Vec<usize>> {
+        let (pattern, options) = value_pattern(value, options)?;
+        if options.rank_results {
+            let report = self.find_pattern_report(&pattern, filter, &options)?;
+            return Ok(report.ranked.iter().map(|ranked| ranked.address).collect());
+        }
+        self.find_pattern_all_with(&pattern, filter, &options)
+    }
+
+    // `scan_for_value_with` with the `ScanReport` of `find_pattern_report`.
+    pub fn scan_for_value_report<T: Copy>(
+        &self,
+        value: T,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<ScanReport> {
+        let (pattern, options) = value_pattern(value, options)?;
+        self.find_pattern_report(&pattern, filter, &options)
+    }
+
+    // `find_pattern_into` for `value`, as `scan_for_value_with` finds it.
+    pub fn scan_for_value_into<T: Copy, S: ScanSink + 'static>(
+        &self,
+        value: T,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+        sink: &Arc<Mutex<S>>,
+    ) -> io::Result<ScanReport> {
+        let (pattern, options) = value_pattern(value, options)?;
+        self.find_pattern_into(&pattern, filter, &options, sink)
+    }
+
+    // Closest match starting strictly below `address`.
+    pub fn find_pattern_before(&self, address: impl Into<Address>, pattern: &Pattern) -> io::Result<Option<usize>> {
+        // Bytes up to `address + len - 1` are needed for a match that starts just below it.
+        let end = address.into().get().saturating_add(pattern.len() - 1);
+        let filter = RegionFilter::new().range(0..end);
+        self.find_pattern_with(pattern, &filter, &self.default_scan_options.clone().direction(ScanDirection::Reverse))
+    }
+
+    // Closest match starting strictly above `address`.
+    pub fn find_pattern_after(&self, address: impl Into<Address>, pattern: &Pattern) -> io::Result<Option<usize>> {
+        let filter = RegionFilter::new().range(address.into().get().saturating_add(1)..usize::MAX);
+        self.find_pattern(pattern, &filter)
+    }
+
+    pub fn scan_modules(&mut self) -> io::Result<()> {
+        let modules = match self.backend() {
+            Some(backend) => backend.modules()?,
+            None => module::enumerate_modules(self.process_id)?,
+        };
+        let sections = sections::read_all_sections(self, &modules);
+        self.set_module_cache(modules, sections);
+        Ok(())
+    }
+
+    pub(crate) fn set_module_cache(&mut self, modules: Vec<ModuleInfo>, sections: Vec<SectionRef>) {
+        self.modules = modules;
+        self.sections = sections;
+        self.metadata.modules_loaded();
+    }
+
+    pub fn get_modules(&self) -> &Vec<ModuleInfo> {
+        &self.modules
+    }
+
+    // "module!.section+0xOFFSET" for addresses inside a section of a cached module,
+    // "module+0xOFFSET" elsewhere in it (headers, or no section table), None outside modules.
+    // With a metadata TTL, modules loaded since `scan_modules` count too (see metadata.rs).
+    pub fn symbolize(&self, address: usize) -> Option<String> {
+        let fresh = self.fresh_modules();
+        let (modules, sections) = match &fresh {
+            Some(view) => (&view.modules, &view.sections),
+            None => (&self.modules, &self.sections),
+        };
+        let module = modules.iter().find(|module| module.contains(address))?;
+        match sections.iter().find(|section| section.module == module.name && section.contains(address)) {
+            Some(section) => Some(format!("{}!{}+0x{:X}", module.name, section.name, address - section.range.start)),
+            None => Some(format!("{}+0x{:X}", module.name, address - module.base_address)),
+        }
+    }
+
+    // The cached sections, or those of the module list the metadata TTL refreshed.
+    pub(crate) fn cached_sections(&self) -> SectionsView<'_> {
+        match self.fresh_modules() {
+            Some(view) => SectionsView::Fresh(view),
+            None => SectionsView::Cached(&self.sections),
+        }
+    }
+
+    // The cached module list, or a freshly enumerated one when `scan_modules` hasn't run or the
+    // metadata TTL has replaced it.
+    pub(crate) fn modules_or_live(&self) -> io::Result<Cow<'_, [ModuleInfo]>> {
+        if let Some(view) = self.fresh_modules() {
+            Ok(Cow::Owned(view.modules.clone()))
+        } else if self.modules.is_empty() {
+            Ok(Cow::Owned(module::enumerate_modules(self.process_id)?))
+        } else {
+            Ok(Cow::Borrowed(&self.modules))
+        }
+    }
+
+    // Summary of the cached regions; per-module footprints need `scan_modules` first.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let (regions, modules) = (self.fresh_regions(), self.fresh_modules());
+        let regions = regions.as_deref().map_or(self.regions.as_slice(), Vec::as_slice);
+        MemoryStats::from_regions(regions, modules.as_deref().map_or(&self.modules, |view| &view.modules))
+    }
+}
+
+// The bytes a value scan looks for, and the options with the value's alignment filled in.
+fn value_pattern<T: Copy>(value: T, options: &ScanOptions) -> io::Result<(Pattern, ScanOptions)> {
+    let mut bytes = unsafe { slice::from_raw_parts(&value as *const T as *const u8, mem::size_of::<T>()) }.to_vec();
+    if options.endianness.is_some_and(|endianness| endianness != Endianness::NATIVE) {
+        bytes.reverse();
+    }
+    let mut options = options.clone();
+    options.alignment.get_or_insert(mem::align_of::<T>());
+    Ok((Pattern::exact(&bytes)?, options))
+}
+
+// Up to `limit` matches, in scan order.
+fn collect_matches(scan: &mut PatternMatches<'_>, limit: usize) -> Vec<usize> {
+    let mut matches = Vec::new();
+    if limit == 0 {
+        return matches;
+    }
+    scan.for_each_match(|address| {
+        matches.push(address);
+        if matches.len() == limit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+    matches
+}
+
+fn to_op_result(result: &io::Result<usize>) -> oplog::OpResult {
+    match result {
+        Ok(bytes) => Ok(*bytes),
+        Err(e) => Err(e.to_string()),
     }
 }
 
+// A process handle may be used from any thread, and all mutable state sits behind a Mutex.
+unsafe impl Send for Memory {}
+unsafe impl Sync for Memory {}
+
+// Stops the background workers; the handle closes once the last of them lets go of it. See
+// process_handle.rs.
 impl Drop for Memory {
     fn drop(&mut self) {
-        unsafe {
-            CloseHandle(self.process_handle);
+        self.process_handle.shut_down();
+    }
+} 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{Call, MockBackend, PAGE};
+
+    #[test]
+    fn partial_writes_report_the_prefix_written() {
+        for offset in [1, 0x7FF, 0xFFF] {
+            let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+            mock.fail_writes(0x10000 + offset..0x11000);
+
+            let e = memory.write_bytes(0x10000, &[0xAA; PAGE]).unwrap_err();
+            assert_eq!(partial_write(&e), Some(PartialWrite { address: 0x10000, requested: PAGE, written: offset }));
+            assert!(mock.bytes(0x10000, offset).iter().all(|&byte| byte == 0xAA));
+            assert!(mock.bytes(0x10000 + offset, PAGE - offset).iter().all(|&byte| byte == 0));
         }
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn partial_typed_writes_fail() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        mock.fail_writes(0x10804..0x10808);
+
+        let e = memory.write_memory(0x10800, u64::MAX).unwrap_err();
+        assert_eq!(partial_write(&e).map(|partial| partial.written), Some(4));
+        let e = memory.write_unaligned(0x10803, u32::MAX).unwrap_err();
+        assert_eq!(partial_write(&e).map(|partial| partial.written), Some(1));
+    }
+
+    #[test]
+    fn a_write_that_fails_at_once_is_not_partial() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        mock.fail_writes(0x10000..0x11000);
+
+        let e = memory.write_bytes(0x10000, &[1; 8]).unwrap_err();
+        assert!(partial_write(&e).is_none());
+    }
+
+    #[test]
+    fn partial_protected_writes_restore_the_protection() {
+        let (mock, memory) = MockBackend::new().region(0x10000, vec![0; PAGE], PAGE_READONLY).open();
+        mock.fail_writes(0x10010..0x11000);
+
+        let e = memory.write_protected(0x10000, &[0xAA; 0x20]).unwrap_err();
+        assert_eq!(partial_write(&e).map(|partial| partial.written), Some(0x10));
+        assert_eq!(memory.query_address(0x10000).unwrap().protection, PAGE_READONLY);
+    }
+
+    #[test]
+    fn recorded_partial_writes_keep_only_the_prefix() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, (0..=255).cycle().take(PAGE).collect()).open();
+        mock.fail_writes(0x10004..0x11000);
+        memory.start_recording();
+
+        assert!(memory.write_bytes(0x10000, &[0xAA; 8]).is_err());
+        let records = memory.recorded_writes();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].old_bytes, [0, 1, 2, 3]);
+        assert_eq!(records[0].new_bytes, [0xAA; 4]);
+    }
+
+    fn dry_run(mock: MockBackend) -> (Arc<MockBackend>, Memory) {
+        let (mock, mut memory) = mock.open();
+        memory.set_dry_run(true);
+        (mock, memory)
+    }
+
+    // Nothing that changes the target: no writes and no protection changes.
+    fn untouched(mock: &MockBackend) -> bool {
+        mock.calls().iter().all(|call| !matches!(call, Call::Write(_) | Call::Protect(..)))
+    }
+
+    #[test]
+    fn dry_runs_plan_writes_without_making_them() {
+        let original: Vec<u8> = (0..=255).cycle().take(PAGE).collect();
+        let (mock, memory) = dry_run(MockBackend::new().data(0x10000, original.clone()));
+
+        memory.write_bytes(0x10010, &[0xAA; 4]).unwrap();
+        memory.write_memory(0x10020, u16::MAX).unwrap();
+        assert!(untouched(&mock));
+        assert_eq!(mock.bytes(0x10000, PAGE), original);
+
+        let planned = memory.take_planned_writes();
+        assert_eq!(planned.len(), 2);
+        assert_eq!((planned[0].address, planned[0].old_bytes.as_slice()), (0x10010, &[0x10, 0x11, 0x12, 0x13][..]));
+        assert_eq!(planned[0].new_bytes, [0xAA; 4]);
+        assert_eq!(planned[0].region.start_address.get(), 0x10000);
+        assert_eq!((planned[1].address, planned[1].new_bytes.as_slice()), (0x10020, &[0xFF; 2][..]));
+        assert!(memory.take_planned_writes().is_empty());
+    }
+
+    #[test]
+    fn dry_runs_refuse_what_a_real_write_would() {
+        let (mock, memory) =
+            dry_run(MockBackend::new().data(0x10000, vec![0; PAGE]).region(0x11000, vec![0; PAGE], PAGE_READONLY));
+
+        let e = memory.write_bytes(0x11000, &[1; 4]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        // Straddling into the read-only region, or off the end of the mapping.
+        assert_eq!(memory.write_bytes(0x10FFE, &[1; 4]).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(memory.write_bytes(0x11FFE, &[1; 4]).is_err());
+        assert!(memory.write_bytes(usize::MAX - 1, &[1; 4]).is_err());
+        assert!(memory.take_planned_writes().is_empty());
+
+        // Protected writes change the protection themselves, so only the mapping matters.
+        memory.write_protected(0x10FFE, &[1; 4]).unwrap();
+        let planned = memory.take_planned_writes();
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].region.start_address.get(), 0x10000);
+        assert!(untouched(&mock));
+        assert_eq!(memory.query_address(0x11000).unwrap().protection, PAGE_READONLY);
+    }
+
+    #[test]
+    fn undoing_in_a_dry_run_plans_the_restore_and_keeps_the_records() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        memory.start_recording();
+        memory.write_bytes(0x10010, &[0xAA; 4]).unwrap();
+        memory.write_bytes(0x10012, &[0xBB; 4]).unwrap();
+
+        memory.set_dry_run(true);
+        memory.write_bytes(0x10100, &[0xCC; 4]).unwrap();
+        assert_eq!(memory.recorded_writes().len(), 2);
+        mock.clear_calls();
+
+        assert_eq!(memory.undo_last().unwrap().unwrap().address, 0x10012);
+        assert_eq!(memory.undo_all().unwrap(), 2);
+        assert!(untouched(&mock));
+        assert_eq!(memory.recorded_writes().len(), 2);
+        assert_eq!(mock.bytes(0x10010, 6), [0xAA, 0xAA, 0xBB, 0xBB, 0xBB, 0xBB]);
+
+        let restores: Vec<(usize, Vec<u8>)> =
+            memory.take_planned_writes().into_iter().map(|planned| (planned.address, planned.new_bytes)).collect();
+        assert_eq!(
+            restores,
+            [
+                (0x10100, vec![0xCC; 4]),
+                (0x10012, vec![0xAA, 0xAA, 0, 0]),
+                (0x10012, vec![0xAA, 0xAA, 0, 0]),
+                (0x10010, vec![0; 4])
+            ]
+        );
+    }
+}
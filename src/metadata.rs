@@ -0,0 +1,242 @@
+// Module and region metadata that refreshes itself. Over a long session the cached module
+// list and regions go stale as the target loads and unloads DLLs and grows its heaps, and
+// everything that consults them quietly gets worse. With a TTL set, lookups made through
+// `&self` enumerate again once what they would use is older than it:
+//
+//     memory.set_metadata_ttl(Some(Duration::from_secs(5)));
+//     memory.on_modules_changed(|change| println!("{}", change));
+//     // ... a DLL loads ...
+//     let name = memory.symbolize(address); // finds it, no scan_modules needed
+//
+// The first lookup past the TTL claims the refresh; the others of a burst, on this thread or
+// another, go on with what is there until it is done, so a burst costs one enumeration. One
+// that fails is logged and tried again a TTL later. `invalidate_metadata` makes the next
+// lookup refresh, TTL or not.
+//
+// The module list and sections refresh for `symbolize`, `memory_stats` and whatever takes
+// its modules from the cached list or a live one (rebasing, exports, profiles, clustering,
+// module diffs, RTTI), and the regions for the scans walking the cached list and for the
+// write guard's `allow_regions`, so what a scan finds in a new region can be written. Each
+// of those takes one snapshot per call, so a refresh lands between calls, never inside one.
+// Lookups that lend out references to the caches (`get_modules`, `regions`, `region_at`)
+// can't have them swapped under the caller and see a refresh once `refresh_metadata` folds
+// it in; `scan_modules` and `scan_memory` replace the caches as before. A `Memory` over a
+// backend (see backend.rs), a replayed one say, never refreshes.
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::memory::{Memory, MemoryRegion};
+use crate::module::{self, ModuleInfo};
+use crate::sections::{self, SectionRef};
+
+// What a refresh found loaded and unloaded since the list before it. A module reloaded at
+// another base is in both.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleChange {
+    pub loaded: Vec<ModuleInfo>,
+    pub unloaded: Vec<ModuleInfo>,
+}
+
+impl ModuleChange {
+    pub fn between(old: &[ModuleInfo], new: &[ModuleInfo]) -> Self {
+        let same = |a: &ModuleInfo, b: &ModuleInfo| a.base_address == b.base_address && a.name == b.name;
+        ModuleChange {
+            loaded: new.iter().filter(|module| !old.iter().any(|old| same(old, module))).cloned().collect(),
+            unloaded: old.iter().filter(|module| !new.iter().any(|new| same(new, module))).cloned().collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.loaded.is_empty() && self.unloaded.is_empty()
+    }
+}
+
+impl fmt::Display for ModuleChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.loaded.is_empty(), self.unloaded.is_empty()) {
+            (true, true) => write!(f, "no module changes"),
+            (false, true) => write!(f, "loaded {}", names(&self.loaded)),
+            (true, false) => write!(f, "unloaded {}", names(&self.unloaded)),
+            (false, false) => write!(f, "loaded {}; unloaded {}", names(&self.loaded), names(&self.unloaded)),
+        }
+    }
+}
+
+fn names(modules: &[ModuleInfo]) -> String {
+    modules.iter().map(|module| module.name.as_str()).collect::<Vec<_>>().join(", ")
+}
+
+// A module list with the sections read from it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ModuleView {
+    pub(crate) modules: Vec<ModuleInfo>,
+    pub(crate) sections: Vec<SectionRef>,
+}
+
+// The cached sections, or those of a refreshed module list, without copying either.
+pub(crate) enum SectionsView<'a> {
+    Cached(&'a [SectionRef]),
+    Fresh(Arc<ModuleView>),
+}
+
+impl Deref for SectionsView<'_> {
+    type Target = [SectionRef];
+
+    fn deref(&self) -> &[SectionRef] {
+        match self {
+            SectionsView::Cached(sections) => sections,
+            SectionsView::Fresh(view) => &view.sections,
+        }
+    }
+}
+
+type ModulesChanged = Box<dyn FnMut(&ModuleChange) + Send>;
+
+#[derive(Default)]
+pub(crate) struct MetadataState {
+    ttl: Option<Duration>,
+    modules: Mutex<Freshness<ModuleView>>,
+    regions: Mutex<Freshness<Vec<MemoryRegion>>>,
+    on_modules_changed: Option<Mutex<ModulesChanged>>,
+}
+
+struct Freshness<T> {
+    // When the list in use was loaded or claimed for a refresh; None if it never was.
+    loaded: Option<Instant>,
+    invalidated: bool,
+    // A list refreshed behind `&self`, newer than the cache it stands in for.
+    fresh: Option<Arc<T>>,
+}
+
+impl<T> Default for Freshness<T> {
+    fn default() -> Self {
+        Freshness { loaded: None, invalidated: false, fresh: None }
+    }
+}
+
+impl<T> Freshness<T> {
+    fn is_due(&self, ttl: Option<Duration>) -> bool {
+        self.invalidated || ttl.is_some_and(|ttl| self.loaded.is_none_or(|loaded| loaded.elapsed() >= ttl))
+    }
+
+    // The cache was just loaded whole, so it is what lookups use again.
+    fn reset(&mut self) {
+        *self = Freshness { loaded: Some(Instant::now()), invalidated: false, fresh: None };
+    }
+}
+
+impl MetadataState {
+    pub(crate) fn modules_loaded(&mut self) {
+        self.modules.get_mut().unwrap().reset();
+    }
+
+    pub(crate) fn regions_loaded(&mut self) {
+        self.regions.get_mut().unwrap().reset();
+    }
+
+    // The region cache changed in part, which makes it newer than a refresh for the range.
+    pub(crate) fn regions_spliced(&mut self) {
+        self.regions.get_mut().unwrap().fresh = None;
+    }
+}
+
+impl Memory {
+    // None, the default, refreshes only on `invalidate_metadata`.
+    pub fn set_metadata_ttl(&mut self, ttl: Option<Duration>) {
+        self.metadata_slot().ttl = ttl;
+    }
+
+    pub fn metadata_ttl(&self) -> Option<Duration> {
+        self.metadata_setting().ttl
+    }
+
+    // Makes the next module and region lookups refresh.
+    pub fn invalidate_metadata(&self) {
+        let state = self.metadata_setting();
+        state.modules.lock().unwrap().invalidated = true;
+        state.regions.lock().unwrap().invalidated = true;
+    }
+
+    // Called after a refresh whose module list differs from the one before it, on the thread
+    // whose lookup made the refresh and outside the metadata locks, so it can look things up
+    // itself. Replaces the callback set before.
+    pub fn on_modules_changed(&mut self, callback: impl FnMut(&ModuleChange) + Send + 'static) {
+        self.metadata_slot().on_modules_changed = Some(Mutex::new(Box::new(callback)));
+    }
+
+    // Refreshes what is due, then moves what lookups refreshed into the caches, for the
+    // lookups that lend them out.
+    pub fn refresh_metadata(&mut self) {
+        if let Some(view) = self.fresh_modules() {
+            let view = Arc::unwrap_or_clone(view);
+            self.set_module_cache(view.modules, view.sections);
+        }
+        if let Some(regions) = self.fresh_regions() {
+            let regions = Arc::unwrap_or_clone(regions);
+            self.region_cache_mut().replace(regions);
+            self.refresh_write_guard();
+            self.metadata_slot().regions_loaded();
+        }
+    }
+
+    // The module list lookups should use in place of the cached one, if a refresh has replaced
+    // it, after refreshing it if it is due.
+    pub(crate) fn fresh_modules(&self) -> Option<Arc<ModuleView>> {
+        let state = self.metadata_setting();
+        let (previous, first) = {
+            let mut modules = state.modules.lock().unwrap();
+            if self.backend().is_some() || !modules.is_due(state.ttl) {
+                return modules.fresh.clone();
+            }
+            let first = modules.loaded.replace(Instant::now()).is_none();
+            modules.invalidated = false;
+            (modules.fresh.clone(), first)
+        };
+
+        let loaded = match module::enumerate_modules(self.process_id()) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                debug!("refreshing the module list failed: {}", e);
+                return previous;
+            }
+        };
+        let previous = previous.as_ref().map_or(self.get_modules(), |view| &view.modules);
+        let change = ModuleChange::between(previous, &loaded);
+        let sections = sections::read_all_sections(self, &loaded);
+        let view = Arc::new(ModuleView { modules: loaded, sections });
+        state.modules.lock().unwrap().fresh = Some(Arc::clone(&view));
+
+        // Nothing changes on the first load, however many modules it finds.
+        if !first && !change.is_empty() {
+            debug!("module list refreshed: {}", change);
+            if let Some(callback) = &state.on_modules_changed {
+                let mut callback = callback.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                callback(&change);
+            }
+        }
+        Some(view)
+    }
+
+    // As `fresh_modules`, for the regions scans walk.
+    pub(crate) fn fresh_regions(&self) -> Option<Arc<Vec<MemoryRegion>>> {
+        let state = self.metadata_setting();
+        {
+            let mut regions = state.regions.lock().unwrap();
+            if self.backend().is_some() || !regions.is_due(state.ttl) {
+                return regions.fresh.clone();
+            }
+            regions.loaded = Some(Instant::now());
+            regions.invalidated = false;
+        }
+
+        let fresh = Arc::new(self.enumerate_regions());
+        debug!("region list refreshed: {} regions", fresh.len());
+        state.regions.lock().unwrap().fresh = Some(Arc::clone(&fresh));
+        Some(fresh)
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+use std::io;
+use std::mem;
+
+use serde::{Deserialize, Serialize};
+
+use crate::address::Address;
+use crate::stats::format_size;
+use crate::sys::{
+    CloseHandle, CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, MODULEENTRY32W, TH32CS_SNAPMODULE,
+    TH32CS_SNAPMODULE32,
+};
+use crate::win_error::win_call;
+use crate::wstr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub path: String,
+    pub base_address: usize,
+    pub size: usize,
+}
+
+impl ModuleInfo {
+    pub fn contains(&self, address: impl Into<Address>) -> bool {
+        let address = address.into().get();
+        address >= self.base_address && address - self.base_address < self.size
+    }
+}
+
+// "game.exe 0x7FF6A0000000-0x7FF6A0153000  1.3 MiB"
+impl fmt::Display for ModuleInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} 0x{:X}-0x{:X}  {}",
+            self.name,
+            self.base_address,
+            self.base_address.saturating_add(self.size),
+            format_size(self.size)
+        )
+    }
+}
+
+pub(crate) fn enumerate_modules(process_id: u32) -> io::Result<Vec<ModuleInfo>> {
+    let snapshot = win_call!(
+        unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPMODULE | TH32CS_SNAPMODULE32, process_id) },
+        "Failed to create module snapshot of process {}",
+        process_id
+    )?;
+
+    let mut modules = Vec::new();
+    let mut entry: MODULEENTRY32W = unsafe { mem::zeroed() };
+    entry.dwSize = mem::size_of::<MODULEENTRY32W>() as u32;
+
+    let mut more = unsafe { Module32FirstW(snapshot, &mut entry) } != 0;
+    while more {
+        modules.push(ModuleInfo {
+            name: wstr::from_wide_nul(&entry.szModule),
+            path: wstr::from_wide_nul(&entry.szExePath),
+            base_address: entry.modBaseAddr as usize,
+            size: entry.modBaseSize as usize,
+        });
+        more = unsafe { Module32NextW(snapshot, &mut entry) } != 0;
+    }
+
+    unsafe {
+        CloseHandle(snapshot);
+    }
+
+    Ok(modules)
+}
@@ -0,0 +1,209 @@
+// What changed in a module between two processes, or between a process and a baseline saved
+// earlier: size, link timestamp, exports added, removed or moved, and which signatures now
+// resolve to a different RVA. Meant for working out which resolved functions an update moved:
+//
+//     let baseline = ModuleBaseline::capture(&memory, "game.exe", Some(&signatures))?;
+//     baseline.save("game-1.0.json")?;
+//     // ... after the update ...
+//     let diff = ModuleBaseline::load("game-1.0.json")?.diff_live(&memory, Some(&signatures))?;
+//     print!("{}", diff);
+//
+// Exports are compared by name only; forwarded exports have no RVA and are left out.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::exports::read_exports;
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::pe::{ExportTarget, PeHeaders};
+use crate::persist::{self, Format};
+use crate::signature::SignatureFile;
+use crate::wstr;
+
+const FORMAT: Format = Format { id: "baseline", version: 2, legacy: None };
+
+// The parts of a loaded module `diff` compares, in a form that can be saved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleBaseline {
+    pub module: String,
+    pub size: usize,
+    pub time_date_stamp: u32,
+    // Named exports by RVA.
+    pub exports: BTreeMap<String, u32>,
+    // RVAs of the signatures for this module that resolved inside it.
+    #[serde(default)]
+    pub signatures: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Moved<T> {
+    pub name: String,
+    pub old: T,
+    pub new: T,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModuleDiff {
+    pub module: String,
+    // (old, new)
+    pub size: (usize, usize),
+    pub time_date_stamp: (u32, u32),
+    // Sorted by name.
+    pub added: Vec<(String, u32)>,
+    pub removed: Vec<(String, u32)>,
+    pub moved: Vec<Moved<u32>>,
+    // Signatures whose RVA changed; None where one side didn't resolve it inside the module.
+    pub signatures: Vec<Moved<Option<usize>>>,
+}
+
+impl ModuleDiff {
+    pub fn is_empty(&self) -> bool {
+        self.size.0 == self.size.1
+            && self.time_date_stamp.0 == self.time_date_stamp.1
+            && self.added.is_empty()
+            && self.removed.is_empty()
+            && self.moved.is_empty()
+            && self.signatures.is_empty()
+    }
+}
+
+impl ModuleBaseline {
+    // Reads the headers and export table of `module` in `memory`, and resolves the entries of
+    // `signatures` that name it. Signature resolution scans the cached regions, so call
+    // `scan_memory` first.
+    pub fn capture(memory: &Memory, module: &str, signatures: Option<&SignatureFile>) -> io::Result<Self> {
+        let modules = memory.modules_or_live()?;
+        let loaded = modules
+            .iter()
+            .find(|loaded| wstr::eq_ignore_case(&loaded.name, module))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Module {} is not loaded", module)))?;
+
+        let headers = PeHeaders::parse(&memory.read_bytes(loaded.base_address, 0x1000)?)?;
+        let exports = read_exports(memory, loaded)?
+            .named()
+            .filter_map(|(name, target)| match target {
+                ExportTarget::Rva(rva) => Some((name.to_string(), *rva)),
+                ExportTarget::Forwarded(_) => None,
+            })
+            .collect();
+
+        Ok(ModuleBaseline {
+            module: loaded.name.clone(),
+            size: loaded.size,
+            time_date_stamp: headers.time_date_stamp,
+            exports,
+            signatures: signatures.map(|file| resolve_rvas(memory, &modules, loaded, file)).unwrap_or_default(),
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(persist::read_json(path, &FORMAT)?.1)
+    }
+
+    // Written whole or not at all; see atomic_file.rs.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        persist::write_json(path, &FORMAT, None, self)
+    }
+
+    // Changes from `self` (old) to `newer`.
+    pub fn diff(&self, newer: &ModuleBaseline) -> ModuleDiff {
+        let mut diff = ModuleDiff {
+            module: newer.module.clone(),
+            size: (self.size, newer.size),
+            time_date_stamp: (self.time_date_stamp, newer.time_date_stamp),
+            ..ModuleDiff::default()
+        };
+
+        for (name, &old) in &self.exports {
+            match newer.exports.get(name) {
+                None => diff.removed.push((name.clone(), old)),
+                Some(&new) if new != old => diff.moved.push(Moved { name: name.clone(), old, new }),
+                Some(_) => {}
+            }
+        }
+        for (name, &new) in &newer.exports {
+            if !self.exports.contains_key(name) {
+                diff.added.push((name.clone(), new));
+            }
+        }
+
+        let mut names: Vec<&String> = self.signatures.keys().chain(newer.signatures.keys()).collect();
+        names.sort_unstable();
+        names.dedup();
+        for name in names {
+            let (old, new) = (self.signatures.get(name).copied(), newer.signatures.get(name).copied());
+            if old != new {
+                diff.signatures.push(Moved { name: name.clone(), old, new });
+            }
+        }
+
+        diff
+    }
+
+    // Changes from this baseline to the module as it is loaded in `memory` now.
+    pub fn diff_live(&self, memory: &Memory, signatures: Option<&SignatureFile>) -> io::Result<ModuleDiff> {
+        Ok(self.diff(&ModuleBaseline::capture(memory, &self.module, signatures)?))
+    }
+}
+
+// Changes from `module` as loaded in `a` to the same module in `b`.
+pub fn diff_modules(
+    a: &Memory,
+    b: &Memory,
+    module: &str,
+    signatures: Option<&SignatureFile>,
+) -> io::Result<ModuleDiff> {
+    Ok(ModuleBaseline::capture(a, module, signatures)?.diff(&ModuleBaseline::capture(b, module, signatures)?))
+}
+
+fn resolve_rvas(
+    memory: &Memory,
+    modules: &[ModuleInfo],
+    module: &ModuleInfo,
+    signatures: &SignatureFile,
+) -> BTreeMap<String, usize> {
+    signatures
+        .signatures
+        .iter()
+        .filter(|entry| wstr::eq_ignore_case(&entry.module, &module.name))
+        .filter_map(|entry| {
+            let address = entry.resolve(memory, modules).ok()?.address();
+            module.contains(address).then(|| (entry.name.clone(), address - module.base_address))
+        })
+        .collect()
+}
+
+// One line per change, for reading off what to update in a signature file.
+impl fmt::Display for ModuleDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "{}: no changes", self.module);
+        }
+        writeln!(f, "{}:", self.module)?;
+        if self.size.0 != self.size.1 {
+            writeln!(f, "  size 0x{:X} -> 0x{:X}", self.size.0, self.size.1)?;
+        }
+        if self.time_date_stamp.0 != self.time_date_stamp.1 {
+            writeln!(f, "  timestamp 0x{:08X} -> 0x{:08X}", self.time_date_stamp.0, self.time_date_stamp.1)?;
+        }
+        for (name, rva) in &self.added {
+            writeln!(f, "  + {} at 0x{:X}", name, rva)?;
+        }
+        for (name, rva) in &self.removed {
+            writeln!(f, "  - {} (was at 0x{:X})", name, rva)?;
+        }
+        for moved in &self.moved {
+            writeln!(f, "  ~ {} 0x{:X} -> 0x{:X}", moved.name, moved.old, moved.new)?;
+        }
+        let rva = |rva: Option<usize>| rva.map_or("unresolved".to_string(), |rva| format!("0x{:X}", rva));
+        for moved in &self.signatures {
+            writeln!(f, "  sig {} {} -> {}", moved.name, rva(moved.old), rva(moved.new))?;
+        }
+        Ok(())
+    }
+}
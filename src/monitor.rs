@@ -0,0 +1,168 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::debug;
+
+use crate::filter::RegionFilter;
+use crate::hash::fnv1a;
+use crate::memory::Memory;
+use crate::process_handle::{spawn_worker, upgrade};
+use crate::scan::CHUNK_SIZE;
+
+// Granularity at which changes are reported.
+pub const BLOCK_SIZE: usize = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    // Start address of the region the block belongs to.
+    pub region: usize,
+    pub block_range: Range<usize>,
+    // Tick in which the change was seen; tick 0 only records the baseline.
+    pub tick: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockChanges {
+    pub region: usize,
+    pub block_range: Range<usize>,
+    // Number of ticks in which the block changed.
+    pub changes: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChangeReport {
+    pub ticks: u64,
+    // Most frequently changed blocks first.
+    pub blocks: Vec<BlockChanges>,
+}
+
+impl ChangeReport {
+    // The changed blocks of each region, keyed by region start address.
+    pub fn by_region(&self) -> BTreeMap<usize, Vec<&BlockChanges>> {
+        let mut regions: BTreeMap<usize, Vec<&BlockChanges>> = BTreeMap::new();
+        for block in &self.blocks {
+            regions.entry(block.region).or_default().push(block);
+        }
+        regions
+    }
+}
+
+// Watches the regions cached in a `Memory` from a background thread, hashing them block by
+// block once per interval and reporting the blocks whose hash changed since the last tick.
+pub struct ChangeMonitor {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<ChangeReport>,
+    events: Receiver<ChangeEvent>,
+}
+
+impl ChangeMonitor {
+    // Only the regions cached when the monitor starts are watched; call `scan_memory` first.
+    // The monitor stops by itself once `memory` is dropped or closed.
+    pub fn start(memory: &Arc<Memory>, filter: RegionFilter, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, events) = mpsc::channel();
+
+        let thread = {
+            let stop = Arc::clone(&stop);
+            spawn_worker(memory, move |memory| run(memory, &filter, interval, &stop, &sender))
+        };
+
+        ChangeMonitor { stop, thread, events }
+    }
+
+    // Changes as they are detected. The report from `stop` covers them even if nobody reads here.
+    pub fn events(&self) -> &Receiver<ChangeEvent> {
+        &self.events
+    }
+
+    pub fn stop(self) -> ChangeReport {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+fn run(
+    memory: &Weak<Memory>,
+    filter: &RegionFilter,
+    interval: Duration,
+    stop: &AtomicBool,
+    sender: &Sender<ChangeEvent>,
+) -> ChangeReport {
+    let ranges: Vec<(usize, Range<usize>)> = match upgrade(memory) {
+        Some(memory) => memory
+            .regions_filtered(filter)
+            .filter_map(|region| filter.clip(region).map(|range| (region.start_address.get(), range)))
+            .collect(),
+        None => Vec::new(),
+    };
+
+    // Everything is allocated up front; ticks only overwrite these.
+    let mut hashes: Vec<Vec<Option<u64>>> =
+        ranges.iter().map(|(_, range)| vec![None; range.len().div_ceil(BLOCK_SIZE)]).collect();
+    let mut counts: HashMap<(usize, Range<usize>), u64> = HashMap::new();
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut tick = 0;
+
+    while !stop.load(Ordering::SeqCst) {
+        let Some(memory) = upgrade(memory) else {
+            break;
+        };
+        let _priority = memory.apply_thread_priority();
+        for ((region, range), hashes) in ranges.iter().zip(&mut hashes) {
+            for_each_block(&memory, range, BLOCK_SIZE, &mut buffer, |index, block_range, hash| {
+                let previous = std::mem::replace(&mut hashes[index], hash);
+                if let (Some(previous), Some(hash)) = (previous, hash) {
+                    if previous != hash {
+                        *counts.entry((*region, block_range.clone())).or_default() += 1;
+                        let _ = sender.send(ChangeEvent { region: *region, block_range, tick });
+                    }
+                }
+            });
+        }
+
+        tick += 1;
+        drop(memory);
+        thread::park_timeout(interval);
+    }
+
+    debug!("change monitor stopped after {} ticks", tick);
+    let mut blocks: Vec<BlockChanges> = counts
+        .into_iter()
+        .map(|((region, block_range), changes)| BlockChanges { region, block_range, changes })
+        .collect();
+    blocks.sort_by(|a, b| b.changes.cmp(&a.changes).then(a.block_range.start.cmp(&b.block_range.start)));
+
+    ChangeReport { ticks: tick, blocks }
+}
+
+// Hashes `range` in `block_size` blocks, reading a `buffer` at a time; the buffer's length
+// must be a multiple of `block_size`. Blocks that can't be read are passed as None so they
+// are never reported as changed.
+pub(crate) fn for_each_block(
+    memory: &Memory,
+    range: &Range<usize>,
+    block_size: usize,
+    buffer: &mut [u8],
+    mut visit: impl FnMut(usize, Range<usize>, Option<u64>),
+) {
+    let mut address = range.start;
+    let mut index = 0;
+    while address < range.end {
+        let len = buffer.len().min(range.end - address);
+        let read = memory.read_bulk(address, &mut buffer[..len]).unwrap_or(0);
+
+        for offset in (0..len).step_by(block_size) {
+            let end = (offset + block_size).min(len);
+            let hash = (end <= read).then(|| fnv1a(&buffer[offset..end]));
+            visit(index, address + offset..address + end, hash);
+            index += 1;
+        }
+        address += len;
+    }
+}
@@ -0,0 +1,98 @@
+// Readers for the MSVC standard library's container layouts, as compiled in release builds
+// (debug builds put an extra proxy pointer at the front). Pointer-sized fields follow the
+// target's bitness.
+//
+//   std::basic_string: 16-byte buffer or heap pointer, size, capacity. The buffer holds the
+//                      characters inline while the capacity fits in it (small string
+//                      optimisation).
+//   std::vector:       first, last and end-of-storage pointers.
+
+use std::io;
+use std::mem;
+
+use crate::memory::Memory;
+use crate::pod::Pod;
+use crate::remote_struct::{extract, read_span};
+use crate::wstr;
+
+// Size of the inline buffer in bytes, for every character type.
+const SSO_BUFFER_SIZE: usize = 16;
+
+// Element count above which a container is assumed to be garbage rather than read.
+pub const DEFAULT_MAX_LEN: usize = 1_000_000;
+
+impl Memory {
+    // Bytes are decoded as UTF-8, with invalid sequences replaced.
+    pub fn read_msvc_string(&self, address: usize) -> io::Result<String> {
+        let bytes = self.read_msvc_chars::<u8>(address, DEFAULT_MAX_LEN)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    // `std::wstring`, whose `wchar_t` is UTF-16 on Windows.
+    pub fn read_msvc_wstring(&self, address: usize) -> io::Result<String> {
+        let units = self.read_msvc_chars::<u16>(address, DEFAULT_MAX_LEN)?;
+        Ok(wstr::decode_lossy(&units))
+    }
+
+    pub fn read_msvc_vector<T: Pod>(&self, address: usize) -> io::Result<Vec<T>> {
+        self.read_msvc_vector_capped(address, DEFAULT_MAX_LEN)
+    }
+
+    // Fails rather than reading more than `max_len` elements, or if the pointer triple is
+    // inconsistent.
+    pub fn read_msvc_vector_capped<T: Pod>(&self, address: usize, max_len: usize) -> io::Result<Vec<T>> {
+        let pointer_size = self.pointer_size()?;
+        let header = self.read_header(address, 3 * pointer_size)?;
+        let [first, last, end] = [0, 1, 2].map(|index| word(&header, index * pointer_size, pointer_size));
+
+        let element_size = mem::size_of::<T>();
+        if first > last || last > end || (element_size != 0 && (last - first) % element_size != 0) {
+            return Err(corrupt(address, format!("pointers 0x{:X}, 0x{:X}, 0x{:X}", first, last, end)));
+        }
+        let len = (last - first).checked_div(element_size).unwrap_or(0);
+        self.read_elements(first, len, max_len, address)
+    }
+
+    fn read_msvc_chars<T: Pod>(&self, address: usize, max_len: usize) -> io::Result<Vec<T>> {
+        let pointer_size = self.pointer_size()?;
+        let header = self.read_header(address, SSO_BUFFER_SIZE + 2 * pointer_size)?;
+        let len = word(&header, SSO_BUFFER_SIZE, pointer_size);
+        let capacity = word(&header, SSO_BUFFER_SIZE + pointer_size, pointer_size);
+        if len > capacity {
+            return Err(corrupt(address, format!("size 0x{:X} exceeds capacity 0x{:X}", len, capacity)));
+        }
+
+        // The inline buffer also holds the terminator, hence `<` rather than `<=`.
+        let char_size = mem::size_of::<T>();
+        if capacity < SSO_BUFFER_SIZE / char_size {
+            return Ok((0..len).map(|index| extract(&header, index * char_size)).collect());
+        }
+        self.read_elements(word(&header, 0, pointer_size), len, max_len, address)
+    }
+
+    fn read_header(&self, address: usize, len: usize) -> io::Result<Vec<u8>> {
+        read_span(self, address, 0, len)
+    }
+
+    fn read_elements<T: Pod>(&self, data: usize, len: usize, max_len: usize, address: usize) -> io::Result<Vec<T>> {
+        if len > max_len {
+            return Err(corrupt(address, format!("0x{:X} elements exceed the limit of 0x{:X}", len, max_len)));
+        }
+        let element_size = mem::size_of::<T>();
+        let bytes = read_span(self, data, 0, len * element_size)?;
+        Ok((0..len).map(|index| extract(&bytes, index * element_size)).collect())
+    }
+}
+
+// A little-endian pointer-sized field of a header read from the target.
+fn word(bytes: &[u8], offset: usize, pointer_size: usize) -> usize {
+    if pointer_size == 4 {
+        extract::<u32>(bytes, offset) as usize
+    } else {
+        extract::<usize>(bytes, offset)
+    }
+}
+
+fn corrupt(address: usize, detail: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Container at 0x{:X} looks corrupt: {}", address, detail))
+}
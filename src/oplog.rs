@@ -0,0 +1,125 @@
+use std::sync::mpsc::Sender;
+
+// Bytes transferred on success, error description on failure.
+pub type OpResult = Result<usize, String>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpEvent {
+    Read { address: usize, size: usize, result: OpResult },
+    Write { address: usize, size: usize, result: OpResult },
+    Protect { address: usize, size: usize, result: OpResult },
+}
+
+pub(crate) fn emit(log: &Option<Sender<OpEvent>>, event: OpEvent) {
+    if let Some(sender) = log {
+        // A dropped receiver just means nobody is listening anymore.
+        let _ = sender.send(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver};
+
+    use super::*;
+    use crate::backend::mock::{MockBackend, PAGE};
+    use crate::memory::Memory;
+    use crate::sys::{PAGE_READONLY, PAGE_READWRITE};
+
+    fn logged(memory: &mut Memory) -> Receiver<OpEvent> {
+        let (sender, receiver) = mpsc::channel();
+        memory.set_operation_log(Some(sender));
+        receiver
+    }
+
+    #[test]
+    fn reads_and_writes_are_logged_in_order() {
+        let (_mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        let events = logged(&mut memory);
+
+        memory.write_bytes(0x10010, &[1, 2, 3]).unwrap();
+        assert_eq!(memory.read_bytes(0x10010, 3).unwrap(), [1, 2, 3]);
+        memory.read_memory::<u32>(0x10020).unwrap();
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                OpEvent::Write { address: 0x10010, size: 3, result: Ok(3) },
+                OpEvent::Read { address: 0x10010, size: 3, result: Ok(3) },
+                OpEvent::Read { address: 0x10020, size: 4, result: Ok(4) },
+            ]
+        );
+    }
+
+    #[test]
+    fn short_reads_log_what_was_read() {
+        let (_mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        let events = logged(&mut memory);
+
+        assert_eq!(memory.read_bytes(0x10FFC, 8).unwrap().len(), 4);
+        assert!(memory.read_memory::<u64>(0x10FFC).is_err());
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                OpEvent::Read { address: 0x10FFC, size: 8, result: Ok(4) },
+                OpEvent::Read { address: 0x10FFC, size: 8, result: Ok(4) },
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_carry_the_error() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        mock.fail_reads(0x10000..0x11000);
+        mock.fail_writes(0x10000..0x11000);
+        let events = logged(&mut memory);
+
+        let read = memory.read_bytes(0x10000, 4).unwrap_err();
+        let write = memory.write_bytes(0x10000, &[1; 4]).unwrap_err();
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                OpEvent::Read { address: 0x10000, size: 4, result: Err(read.to_string()) },
+                OpEvent::Write { address: 0x10000, size: 4, result: Err(write.to_string()) },
+            ]
+        );
+    }
+
+    #[test]
+    fn protected_writes_log_both_protection_changes() {
+        let (_mock, mut memory) = MockBackend::new().region(0x10000, vec![0; PAGE], PAGE_READONLY).open();
+        let events = logged(&mut memory);
+
+        memory.write_protected(0x10000, &[0xAA; 2]).unwrap();
+
+        let events: Vec<OpEvent> = events.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], OpEvent::Protect { address: 0x10000, result: Ok(_), .. }));
+        assert_eq!(events[1], OpEvent::Write { address: 0x10000, size: 2, result: Ok(2) });
+        assert!(matches!(events[2], OpEvent::Protect { address: 0x10000, result: Ok(_), .. }));
+    }
+
+    #[test]
+    fn failed_protection_changes_are_logged() {
+        let (_mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        let events = logged(&mut memory);
+
+        let e = memory.protect(0x20000, PAGE, PAGE_READWRITE).unwrap_err();
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [OpEvent::Protect { address: 0x20000, size: PAGE, result: Err(e.to_string()) }]
+        );
+    }
+
+    #[test]
+    fn nobody_listening_is_not_an_error() {
+        let (_mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        drop(logged(&mut memory));
+        memory.write_bytes(0x10000, &[1]).unwrap();
+
+        memory.set_operation_log(None);
+        assert_eq!(memory.read_bytes(0x10000, 1).unwrap(), [1]);
+    }
+}
@@ -0,0 +1,389 @@
+// Sizes, durations and address ranges as people type them, for the CLI and anything else that
+// reads them from text:
+//
+//     parse_size("64M")?;                                  // 0x400_0000
+//     parse_duration("250ms")?;                            // Duration::from_millis(250)
+//     parse_duration_in("50", Duration::from_millis(1))?;  // a bare number counts in `unit`
+//     parse_range("game.exe+0x1000..+0x2000")?.resolve(&memory)?;
+//
+// Sizes are a decimal number with an optional unit, or a 0x-prefixed hex number without one
+// (its B would be a digit). The units are B, K, M, G and T, each also spelled KB or KiB and
+// the like, and all of them powers of 1024, as memory is sized. Durations are a decimal
+// number, possibly with a fraction, and one of ns, us (µs), ms, s, m (min) and h; a bare
+// number is seconds for `parse_duration`. Units are matched regardless of case, and may be
+// separated from the number by spaces.
+//
+// Range endpoints are address expressions: hex addresses ("0x400000", "400000"), or a module
+// with an optional offset ("game.exe", "game.exe+0x1000"). An end written as just an offset
+// ("+0x2000") is relative to the start's base, and a missing endpoint is the start or end of
+// the start's (or end's) module, or of the address space when there is no module:
+// "game.exe+0x1000.." runs to the end of game.exe, "..0x500000" from 0. As with bookmarks, a
+// loaded module's name counts before hex, so a module called "cafe" isn't read as 0xCAFE;
+// that's why resolving needs the `Memory`.
+//
+// Every error is InvalidInput and quotes the text it's about, down to the part that's wrong.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::time::Duration;
+
+use crate::address::Address;
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::wstr;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+pub fn parse_size(text: &str) -> io::Result<usize> {
+    let trimmed = text.trim();
+    if let Some(digits) = trimmed.strip_prefix("0x").or_else(|| trimmed.strip_prefix("0X")) {
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid(format!("invalid size '{}': '{}' is not hex", text, digits)));
+        }
+        return usize::from_str_radix(digits, 16).map_err(|_| too_large("size", text));
+    }
+
+    let (digits, unit) = split_number(trimmed, false);
+    if digits.is_empty() {
+        return Err(invalid(format!("invalid size '{}': expected a number", text)));
+    }
+    let multiplier: u64 = match unit.to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err(invalid(format!("invalid size '{}': unknown unit '{}'", text, unit))),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .and_then(|bytes| usize::try_from(bytes).ok())
+        .ok_or_else(|| too_large("size", text))
+}
+
+// A bare number is seconds.
+pub fn parse_duration(text: &str) -> io::Result<Duration> {
+    parse_duration_in(text, Duration::from_secs(1))
+}
+
+// A bare number counts in `unit`, for options such as `--interval-ms` that always took one.
+pub fn parse_duration_in(text: &str, unit: Duration) -> io::Result<Duration> {
+    let (number, suffix) = split_number(text.trim(), true);
+    if number.is_empty() || number == "." {
+        return Err(invalid(format!("invalid duration '{}': expected a number", text)));
+    }
+    let unit_nanos: u128 = match suffix.to_lowercase().as_str() {
+        "" => unit.as_nanos(),
+        "ns" => 1,
+        "us" | "µs" => 1_000,
+        "ms" => 1_000_000,
+        "s" => NANOS_PER_SEC,
+        "m" | "min" => 60 * NANOS_PER_SEC,
+        "h" => 3600 * NANOS_PER_SEC,
+        _ => return Err(invalid(format!("invalid duration '{}': unknown unit '{}'", text, suffix))),
+    };
+
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if fraction.contains('.') {
+        return Err(invalid(format!("invalid duration '{}': '{}' has two decimal points", text, number)));
+    }
+    // Exact up to the nanosecond; digits past what can matter are dropped.
+    let fraction = &fraction[..fraction.len().min(18)];
+    let parse = |digits: &str| if digits.is_empty() { Some(0) } else { digits.parse::<u128>().ok() };
+    let nanos = parse(whole).and_then(|whole| whole.checked_mul(unit_nanos)).and_then(|nanos| {
+        let fraction_nanos = parse(fraction)?.checked_mul(unit_nanos)? / 10u128.checked_pow(fraction.len() as u32)?;
+        nanos.checked_add(fraction_nanos)
+    });
+    let nanos = nanos.ok_or_else(|| too_large("duration", text))?;
+    let secs = u64::try_from(nanos / NANOS_PER_SEC).map_err(|_| too_large("duration", text))?;
+    Ok(Duration::new(secs, (nanos % NANOS_PER_SEC) as u32))
+}
+
+// An address, or a module with an optional offset, as `AddressExpr::fmt` writes them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressExpr {
+    // A module name or a hex address; None for a bare offset ("+0x2000").
+    pub base: Option<String>,
+    pub offset: usize,
+}
+
+impl AddressExpr {
+    // A bare offset is taken as an absolute address.
+    pub fn resolve(&self, memory: &Memory) -> io::Result<usize> {
+        let modules = memory.modules_or_live()?;
+        Ok(self.resolve_in(&modules)?.0)
+    }
+
+    // The address, and the module its base named, if it named one.
+    fn resolve_in<'a>(&self, modules: &'a [ModuleInfo]) -> io::Result<(usize, Option<&'a ModuleInfo>)> {
+        let (base, module) = match &self.base {
+            None => (0, None),
+            Some(base) => match modules.iter().find(|module| wstr::eq_ignore_case(&module.name, base)) {
+                Some(module) => (module.base_address, Some(module)),
+                None => {
+                    let address = base.parse::<Address>().map_err(|_| {
+                        invalid(format!("invalid address '{}': '{}' is neither a loaded module nor hex", self, base))
+                    })?;
+                    (address.get(), None)
+                }
+            },
+        };
+        let address = base.checked_add(self.offset).ok_or_else(|| too_large("address", &self.to_string()))?;
+        Ok((address, module))
+    }
+}
+
+// "game.exe+0x1000", "0x400000" or "+0x2000".
+impl fmt::Display for AddressExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.base, self.offset) {
+            (Some(base), 0) => f.write_str(base),
+            (Some(base), offset) => write!(f, "{}+0x{:X}", base, offset),
+            (None, offset) => write!(f, "+0x{:X}", offset),
+        }
+    }
+}
+
+pub fn parse_address_expr(text: &str) -> io::Result<AddressExpr> {
+    let trimmed = text.trim();
+    let (base, offset) = match trimmed.split_once('+') {
+        Some((base, offset)) => (base.trim(), Some(offset.trim())),
+        None => (trimmed, None),
+    };
+    if base.is_empty() && offset.is_none() {
+        return Err(invalid(format!("invalid address '{}': it is empty", text)));
+    }
+    let offset = match offset {
+        Some(offset) => offset
+            .parse::<Address>()
+            .map_err(|_| invalid(format!("invalid address '{}': offset '{}' is not hex", text, offset)))?
+            .get(),
+        None => 0,
+    };
+    Ok(AddressExpr { base: Some(base.to_string()).filter(|base| !base.is_empty()), offset })
+}
+
+// `start..end`, either of them left out for an open end. See above.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeExpr {
+    pub start: Option<AddressExpr>,
+    pub end: Option<AddressExpr>,
+}
+
+impl RangeExpr {
+    pub fn resolve(&self, memory: &Memory) -> io::Result<Range<usize>> {
+        self.resolve_in(&memory.modules_or_live()?)
+    }
+
+    fn resolve_in(&self, modules: &[ModuleInfo]) -> io::Result<Range<usize>> {
+        let start = self.start.as_ref().map(|start| start.resolve_in(modules)).transpose()?;
+        let end = match &self.end {
+            // Relative to the start's base.
+            Some(AddressExpr { base: None, offset }) => {
+                let base = self.start.as_ref().map(|start| AddressExpr { offset: *offset, ..start.clone() });
+                let end = base.unwrap_or(AddressExpr { base: None, offset: *offset });
+                Some(end.resolve_in(modules)?)
+            }
+            Some(end) => Some(end.resolve_in(modules)?),
+            None => None,
+        };
+        let range = match (start, end) {
+            (Some((start, _)), Some((end, _))) => start..end,
+            (Some((start, module)), None) => {
+                start..module.map_or(usize::MAX, |module| module.base_address + module.size)
+            }
+            (None, Some((end, module))) => module.map_or(0, |module| module.base_address)..end,
+            (None, None) => 0..usize::MAX,
+        };
+        if range.end < range.start {
+            return Err(invalid(format!(
+                "invalid range '{}': it ends at 0x{:X}, before its start at 0x{:X}",
+                self, range.end, range.start
+            )));
+        }
+        Ok(range)
+    }
+}
+
+impl fmt::Display for RangeExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(start) = &self.start {
+            write!(f, "{}", start)?;
+        }
+        f.write_str("..")?;
+        match &self.end {
+            Some(end) => write!(f, "{}", end),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn parse_range(text: &str) -> io::Result<RangeExpr> {
+    let (start, end) = text
+        .split_once("..")
+        .ok_or_else(|| invalid(format!("invalid range '{}': expected <start>..<end>", text)))?;
+    let endpoint = |endpoint: &str| match endpoint.trim() {
+        "" => Ok(None),
+        endpoint => parse_address_expr(endpoint)
+            .map(Some)
+            .map_err(|e| invalid(format!("invalid range '{}': {}", text, e))),
+    };
+    Ok(RangeExpr { start: endpoint(start)?, end: endpoint(end)? })
+}
+
+// The leading digits (and decimal points, if `fraction`) and the trimmed rest.
+fn split_number(text: &str, fraction: bool) -> (&str, &str) {
+    let end = text.find(|c: char| !(c.is_ascii_digit() || (fraction && c == '.'))).unwrap_or(text.len());
+    (&text[..end], text[end..].trim())
+}
+
+fn too_large(what: &str, text: &str) -> io::Error {
+    invalid(format!("{} '{}' is too large", what, text))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn modules() -> Vec<ModuleInfo> {
+        let module = |name: &str, base_address, size| ModuleInfo {
+            name: name.to_string(),
+            path: format!("C:\\game\\{}", name),
+            base_address,
+            size,
+        };
+        vec![module("game.exe", 0x4000_0000, 0x5000), module("cafe", 0x7FF_0000, 0x1000)]
+    }
+
+    fn range(text: &str) -> io::Result<Range<usize>> {
+        parse_range(text)?.resolve_in(&modules())
+    }
+
+    // The error for `result`, which must be InvalidInput and quote `text`.
+    fn refused<T: fmt::Debug>(result: io::Result<T>, text: &str) -> String {
+        let e = result.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{}", e);
+        assert!(e.to_string().contains(&format!("'{}'", text)), "{} doesn't quote {:?}", e, text);
+        e.to_string()
+    }
+
+    #[test]
+    fn sizes_take_binary_units_or_hex() {
+        for (text, size) in [
+            ("12", 12),
+            ("12B", 12),
+            ("4K", 4 << 10),
+            ("4 KiB", 4 << 10),
+            ("1kb", 1 << 10),
+            ("64M", 64 << 20),
+            ("2gib", 2 << 30),
+            ("1T", 1 << 40),
+            ("0x400", 0x400),
+            (" 0X10B ", 0x10B),
+        ] {
+            assert_eq!(parse_size(text).unwrap(), size, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn malformed_sizes_are_refused() {
+        for text in ["", "K", "0x", "0xZZ", "0x10 K", "4Q", "1.5M", "-1"] {
+            refused(parse_size(text), text);
+        }
+        assert!(refused(parse_size("99999999T"), "99999999T").contains("too large"));
+        assert!(refused(parse_size("0x1FFFFFFFFFFFFFFFF"), "0x1FFFFFFFFFFFFFFFF").contains("too large"));
+    }
+
+    #[test]
+    fn durations_take_units_and_fractions() {
+        for (text, duration) in [
+            ("3", Duration::from_secs(3)),
+            ("250ms", Duration::from_millis(250)),
+            ("1.5s", Duration::from_millis(1500)),
+            (".5 S", Duration::from_millis(500)),
+            ("2m", Duration::from_secs(120)),
+            ("2min", Duration::from_secs(120)),
+            ("1h", Duration::from_secs(3600)),
+            ("10us", Duration::from_micros(10)),
+            ("10µs", Duration::from_micros(10)),
+            ("5ns", Duration::from_nanos(5)),
+            ("0.000000001s", Duration::from_nanos(1)),
+            // Past the nanosecond, digits are dropped rather than rounded.
+            ("0.0000000019s", Duration::from_nanos(1)),
+            ("1.00000000000000000000009s", Duration::from_secs(1)),
+        ] {
+            assert_eq!(parse_duration(text).unwrap(), duration, "{:?}", text);
+        }
+        assert_eq!(parse_duration_in("50", Duration::from_millis(1)).unwrap(), Duration::from_millis(50));
+        assert_eq!(parse_duration_in("50s", Duration::from_millis(1)).unwrap(), Duration::from_secs(50));
+    }
+
+    #[test]
+    fn malformed_durations_are_refused() {
+        for text in ["", ".", "s", "5 fortnights", "-1s"] {
+            refused(parse_duration(text), text);
+        }
+        assert!(refused(parse_duration("1.2.3s"), "1.2.3s").contains("two decimal points"));
+        assert!(refused(parse_duration("99999999999999999999h"), "99999999999999999999h").contains("too large"));
+    }
+
+    #[test]
+    fn address_expressions_split_into_base_and_offset() {
+        let expr = |base: Option<&str>, offset| AddressExpr { base: base.map(str::to_string), offset };
+        assert_eq!(parse_address_expr("game.exe+0x1000").unwrap(), expr(Some("game.exe"), 0x1000));
+        assert_eq!(parse_address_expr(" game.exe + 10 ").unwrap(), expr(Some("game.exe"), 0x10));
+        assert_eq!(parse_address_expr("0x400000").unwrap(), expr(Some("0x400000"), 0));
+        assert_eq!(parse_address_expr("+0x2000").unwrap(), expr(None, 0x2000));
+
+        for text in ["game.exe+0x1000", "0x400000", "+0x2000"] {
+            assert_eq!(parse_address_expr(text).unwrap().to_string(), text);
+        }
+        refused(parse_address_expr("  "), "  ");
+        assert!(refused(parse_address_expr("game.exe+zz"), "game.exe+zz").contains("'zz'"));
+    }
+
+    #[test]
+    fn loaded_modules_count_before_hex() {
+        let resolve = |text| parse_address_expr(text).unwrap().resolve_in(&modules()).map(|(address, _)| address);
+        assert_eq!(resolve("cafe").unwrap(), 0x7FF_0000);
+        assert_eq!(resolve("CAFE+4").unwrap(), 0x7FF_0004);
+        assert_eq!(resolve("Game.EXE+0x10").unwrap(), 0x4000_0010);
+        assert_eq!(resolve("beef").unwrap(), 0xBEEF);
+        assert_eq!(resolve("+0x20").unwrap(), 0x20);
+
+        assert!(refused(resolve("other.dll"), "other.dll").contains("neither a loaded module nor hex"));
+        let past_the_end = format!("0x{:X}+0x1", usize::MAX);
+        assert!(refused(resolve(&past_the_end), &past_the_end).contains("too large"));
+    }
+
+    #[test]
+    fn ranges_resolve_open_and_relative_ends() {
+        assert_eq!(range("game.exe+0x1000..+0x2000").unwrap(), 0x4000_1000..0x4000_2000);
+        assert_eq!(range("game.exe+0x1000..").unwrap(), 0x4000_1000..0x4000_5000);
+        assert_eq!(range("..game.exe+0x10").unwrap(), 0x4000_0000..0x4000_0010);
+        assert_eq!(range("..0x500000").unwrap(), 0..0x50_0000);
+        assert_eq!(range("0x1000..").unwrap(), 0x1000..usize::MAX);
+        assert_eq!(range(" .. ").unwrap(), 0..usize::MAX);
+        assert_eq!(range("+0x10..+0x20").unwrap(), 0x10..0x20);
+        assert_eq!(range("cafe..game.exe").unwrap(), 0x7FF_0000..0x4000_0000);
+
+        for text in ["game.exe+0x1000..+0x2000", "..0x500000", "cafe..", ".."] {
+            assert_eq!(parse_range(text).unwrap().to_string(), text);
+        }
+    }
+
+    #[test]
+    fn malformed_ranges_are_refused() {
+        refused(parse_range("0x1000"), "0x1000");
+        assert!(refused(parse_range("0x1000..game+zz"), "0x1000..game+zz").contains("'zz'"));
+        assert!(refused(range("0x2000..0x1000"), "0x2000..0x1000").contains("before its start"));
+        assert!(refused(range("game.exe+0x3000..+0x2000"), "game.exe+0x3000..+0x2000").contains("before its start"));
+    }
+}
@@ -0,0 +1,500 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use std::slice;
+use std::thread;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::coordination::{Artifact, ArtifactKind};
+use crate::filter::RegionFilter;
+use crate::memory::{partial_write, Memory};
+use crate::pattern::Pattern;
+use crate::rebase::{RebasedAddress, Rebaser};
+use crate::safety_net::SafetyNet;
+
+// Bytes written by `Memory::apply_patch`, together with what they replaced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Patch {
+    pub address: usize,
+    pub original: Vec<u8>,
+    pub patched: Vec<u8>,
+    // The first page was copy-on-write, so the patch gave the target a private copy of it.
+    #[serde(default)]
+    pub copy_on_write: bool,
+    // Protection of the first page before the patch was applied.
+    #[serde(default)]
+    pub protection: u32,
+}
+
+impl Patch {
+    pub fn len(&self) -> usize {
+        self.patched.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patched.is_empty()
+    }
+
+    pub fn overlaps(&self, address: usize, len: usize) -> bool {
+        address < self.address + self.len() && self.address < address + len
+    }
+}
+
+impl Memory {
+    // Writes `bytes` even into code or read-only pages and keeps the original bytes so the
+    // patch can be reverted. Patches may not overlap: revert the old one first. In dry-run
+    // mode the write is only planned and nothing is remembered. If the write stops partway,
+    // the bytes already written are restored and the `PartialWrite` error is returned.
+    //
+    // With `set_safety_net` on, the bytes about to be replaced are saved to a recovery file
+    // first; see safety_net.rs.
+    pub fn apply_patch(&self, address: usize, bytes: &[u8]) -> io::Result<Patch> {
+        let planned = address..address.saturating_add(bytes.len());
+        self.arm_safety_net("patch", slice::from_ref(&planned))?;
+        self.apply_patch_unarmed(address, bytes)
+    }
+
+    // Applies each (address, bytes) in order, all or nothing: if one fails, the ones already
+    // applied are reverted, newest first, and its error is returned. One safety net covers
+    // the whole batch.
+    //
+    // The rollback also runs if applying panics, except that then nothing more is written to
+    // the target. Whatever stays applied, because a revert failed or because of the panic, is
+    // saved to a recovery file (see safety_net.rs) whether or not `set_safety_net` is on, and
+    // its path logged, so a batch is never left half-applied without a way back.
+    pub fn apply_patches(&self, patches: &[(usize, Vec<u8>)]) -> io::Result<Vec<Patch>> {
+        self.apply_batch("patch batch", patches)
+    }
+
+    // `apply_patches` for the operations built on it, with `operation` naming the batch in
+    // the log.
+    pub(crate) fn apply_batch(&self, operation: &str, patches: &[(usize, Vec<u8>)]) -> io::Result<Vec<Patch>> {
+        let planned: Vec<Range<usize>> =
+            patches.iter().map(|(address, bytes)| *address..address.saturating_add(bytes.len())).collect();
+        self.arm_safety_net(operation, &planned)?;
+
+        let mut batch = Batch { memory: self, applied: Vec::with_capacity(patches.len()) };
+        for (address, bytes) in patches {
+            match self.apply_patch_unarmed(*address, bytes) {
+                Ok(patch) => batch.applied.push(patch),
+                Err(e) => {
+                    debug!("patch at 0x{:X} failed, rolling back {} before it", address, batch.applied.len());
+                    return Err(e);
+                }
+            }
+        }
+        Ok(batch.commit())
+    }
+
+    // `apply_patch` for callers that armed a safety net for more than the patch already.
+    pub(crate) fn apply_patch_unarmed(&self, address: usize, bytes: &[u8]) -> io::Result<Patch> {
+        self.apply_patch_claimed(ArtifactKind::Patch, address, bytes)
+    }
+
+    // With a coordinator set, the patch is claimed as a `kind` artifact first, and given up
+    // again if it fails (see coordination.rs). The claim is made before the patch list is
+    // locked, since `adopt_orphans` locks the list while it holds the registry.
+    pub(crate) fn apply_patch_claimed(&self, kind: ArtifactKind, address: usize, bytes: &[u8]) -> io::Result<Patch> {
+        self.claim_artifact(|| {
+            let original = self.back_up(address, bytes.len())?;
+            Ok(Artifact { kind, address, bytes: bytes.to_vec(), original, label: String::new() })
+        })?;
+        let applied = self.apply_patch_locked(address, bytes);
+        if applied.is_err() {
+            self.release_artifact(address);
+        }
+        applied
+    }
+
+    fn apply_patch_locked(&self, address: usize, bytes: &[u8]) -> io::Result<Patch> {
+        let mut patches = self.patch_list().lock().unwrap();
+        if let Some(existing) = patches.iter().find(|patch| patch.overlaps(address, bytes.len())) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Patch at 0x{:X} overlaps the patch at 0x{:X}", address, existing.address),
+            ));
+        }
+
+        let info = self.query_address(address)?;
+        let region = info
+            .to_region()
+            .ok_or_else(|| io::Error::other(format!("Region at 0x{:X} is not committed", info.base)))?;
+        let original = self.back_up(address, bytes.len())?;
+        // Recorded once here, as a whole, rather than by the writes that make it up.
+        if let Err(e) = self.write_protected_unrecorded(address, bytes) {
+            // A partly applied patch is undone from the backup rather than left half-written.
+            if let Some(partial) = partial_write(&e) {
+                match self.write_protected_unrecorded(address, &original[..partial.written]) {
+                    Ok(()) => debug!("undid 0x{:X} bytes of a partial patch at 0x{:X}", partial.written, address),
+                    Err(restore) => warn!("failed to undo a partial patch at 0x{:X}: {}", address, restore),
+                }
+            }
+            return Err(e);
+        }
+
+        let patch = Patch {
+            address,
+            original,
+            patched: bytes.to_vec(),
+            copy_on_write: region.is_copy_on_write,
+            protection: region.protection,
+        };
+        if !self.is_dry_run() {
+            debug!("applied patch of 0x{:X} bytes at 0x{:X}", bytes.len(), address);
+            self.record_write(address, &patch.original, &patch.patched);
+            patches.push(patch.clone());
+        }
+        Ok(patch)
+    }
+
+    // The `len` bytes at `address` a patch is about to replace, all of them or an error.
+    fn back_up(&self, address: usize, len: usize) -> io::Result<Vec<u8>> {
+        let original = self.read_bytes(address, len)?;
+        if original.len() < len {
+            return Err(io::Error::other(format!("Failed to back up 0x{:X} bytes at 0x{:X}", len, address)));
+        }
+        Ok(original)
+    }
+
+    // Restores the bytes under the patch starting at `address`; Ok(None) if there is none. A
+    // revert that only partly lands fails and keeps the patch, so it can be retried.
+    pub fn revert_patch(&self, address: usize) -> io::Result<Option<Patch>> {
+        self.revert_patch_with(address, false)
+    }
+
+    // Like `revert_patch`; with `restore_protection` the patched range also gets back the
+    // protection it had before the patch, e.g. PAGE_WRITECOPY on a page the patch privatized.
+    // A private copy stays private either way.
+    pub fn revert_patch_with(&self, address: usize, restore_protection: bool) -> io::Result<Option<Patch>> {
+        let mut patches = self.patch_list().lock().unwrap();
+        let Some(index) = patches.iter().position(|patch| patch.address == address) else {
+            return Ok(None);
+        };
+
+        let patch = &patches[index];
+        self.write_protected_unrecorded(address, &patch.original)?;
+        self.record_write(address, &patch.patched, &patch.original);
+        if restore_protection && !self.is_dry_run() {
+            self.protect(address, patch.len(), patch.protection)?;
+        }
+        if self.is_dry_run() {
+            return Ok(Some(patches[index].clone()));
+        }
+        let patch = patches.remove(index);
+        drop(patches);
+        self.release_artifact(address);
+        Ok(Some(patch))
+    }
+
+    // Drops the patch at `address` from the list, and its claim, leaving its bytes in place,
+    // for operations that patch only so that they can roll back.
+    pub(crate) fn forget_patch(&self, address: usize) {
+        self.patch_list().lock().unwrap().retain(|patch| patch.address != address);
+        self.release_artifact(address);
+    }
+
+    // Reverts every applied patch, newest first, and returns how many were reverted.
+    pub fn revert_all_patches(&self) -> io::Result<usize> {
+        let addresses: Vec<usize> = self.patches().iter().rev().map(|patch| patch.address).collect();
+        for address in &addresses {
+            self.revert_patch(*address)?;
+        }
+        Ok(addresses.len())
+    }
+
+    pub fn patches(&self) -> Vec<Patch> {
+        self.patch_list().lock().unwrap().clone()
+    }
+
+    // Patches every match of `find` in the regions `filter` lets through with `replace`, and
+    // returns the patched addresses. Wildcard nibbles in `replace` keep the byte that's there,
+    // so "?? 90 90" rewrites all but a match's first byte:
+    //
+    //     let find = Pattern::parse("E8 ?? ?? ?? ?? 84 C0 74")?;
+    //     let replace = Pattern::parse("?? ?? ?? ?? ?? 84 C0 EB")?;
+    //     memory.replace_pattern(&find, &replace, &RegionFilter::new().executable(true))?;
+    //
+    // Either every match is patched or none is: the replacements are applied as one batch (see
+    // `apply_patches`). Matches overlapping an earlier one are left alone, and one that
+    // stopped matching between the scan and the write fails the call before anything is
+    // written.
+    pub fn replace_pattern(&self, find: &Pattern, replace: &Pattern, filter: &RegionFilter) -> io::Result<Vec<usize>> {
+        if replace.len() != find.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("The replacement is {} bytes long, the pattern {}", replace.len(), find.len()),
+            ));
+        }
+
+        let mut addresses = self.find_pattern_all(find, filter)?;
+        let mut end = 0;
+        addresses.retain(|&address| {
+            let keep = address >= end;
+            if keep {
+                end = address + find.len();
+            }
+            keep
+        });
+
+        let patches = addresses
+            .iter()
+            .map(|&address| Ok((address, self.replacement_at(address, find, replace)?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        self.apply_batch("replace", &patches)?;
+        debug!("replaced {} matches of {}", addresses.len(), find);
+        Ok(addresses)
+    }
+
+    // The bytes `replace` makes of the match of `find` at `address`.
+    fn replacement_at(&self, address: usize, find: &Pattern, replace: &Pattern) -> io::Result<Vec<u8>> {
+        let current = self.read_bytes(address, find.len())?;
+        if current.len() < find.len() || !find.matches_at(&current, 0) {
+            return Err(io::Error::other(format!("0x{:X} no longer matches", address)));
+        }
+        Ok(current
+            .iter()
+            .zip(replace.bytes().iter().zip(replace.masks()))
+            .map(|(&old, (&new, &mask))| (old & !mask) | (new & mask))
+            .collect())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedPatch {
+    pub address: RebasedAddress,
+    pub original: Vec<u8>,
+    pub patched: Vec<u8>,
+}
+
+// The patches applied through a `Memory`, in a form that can be reapplied to a later run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PatchSet {
+    pub patches: Vec<SavedPatch>,
+}
+
+impl PatchSet {
+    pub fn capture(memory: &Memory, rebaser: &Rebaser) -> Self {
+        let patches = memory
+            .patches()
+            .into_iter()
+            .map(|patch: Patch| SavedPatch {
+                address: rebaser.rebase(patch.address),
+                original: patch.original,
+                patched: patch.patched,
+            })
+            .collect();
+        PatchSet { patches }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
+    // Applies every patch at its resolved address, stopping at the first one that can't be
+    // resolved or applied. Patches that landed stay applied.
+    pub fn apply(&self, memory: &Memory, rebaser: &Rebaser) -> io::Result<Vec<Patch>> {
+        self.patches
+            .iter()
+            .map(|saved| memory.apply_patch(rebaser.resolve(&saved.address)?, &saved.patched))
+            .collect()
+    }
+}
+
+// The patches of an `apply_patches` batch applied so far, rolled back when dropped unless
+// the batch was committed.
+struct Batch<'a> {
+    memory: &'a Memory,
+    applied: Vec<Patch>,
+}
+
+impl Batch<'_> {
+    fn commit(mut self) -> Vec<Patch> {
+        mem::take(&mut self.applied)
+    }
+}
+
+impl Drop for Batch<'_> {
+    fn drop(&mut self) {
+        let mut left = Vec::new();
+        if thread::panicking() {
+            // The patch list's lock may be poisoned, and a second panic here would abort. The
+            // recovery file is the way back.
+            left = mem::take(&mut self.applied);
+        } else {
+            for patch in self.applied.drain(..).rev() {
+                if let Err(e) = self.memory.revert_patch(patch.address) {
+                    warn!("failed to roll back the patch at 0x{:X}: {}", patch.address, e);
+                    left.push(patch);
+                }
+            }
+        }
+        if left.is_empty() {
+            return;
+        }
+
+        let count = left.len();
+        let saved = left.into_iter().map(|patch| (patch.address, patch.original)).collect();
+        match SafetyNet::record(self.memory, saved) {
+            Ok(net) => {
+                warn!("{} patches of a failed batch are still applied; {} restores them", count, net.path().display())
+            }
+            Err(e) => warn!("{} patches of a failed batch are still applied, and saving them failed: {}", count, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use crate::backend::mock::{Call, MockBackend, PAGE};
+    use crate::memory::partial_write;
+    use crate::safety_net::SafetyNet;
+    use crate::sys::{PAGE_EXECUTE_READ, PAGE_READWRITE};
+
+    // A page of code, counting up.
+    fn code() -> MockBackend {
+        MockBackend::new().region(0x10000, (0..=255).cycle().take(PAGE).collect(), PAGE_EXECUTE_READ)
+    }
+
+    #[test]
+    fn patches_restore_what_they_replaced() {
+        let (mock, memory) = code().open();
+
+        let patch = memory.apply_patch(0x10010, &[0x90; 4]).unwrap();
+        assert_eq!(patch.original, [0x10, 0x11, 0x12, 0x13]);
+        assert_eq!(mock.bytes(0x10010, 4), [0x90; 4]);
+        assert_eq!(memory.query_address(0x10000).unwrap().protection, PAGE_EXECUTE_READ);
+
+        memory.revert_patch(0x10010).unwrap().unwrap();
+        assert_eq!(mock.bytes(0x10010, 4), [0x10, 0x11, 0x12, 0x13]);
+        assert!(memory.patches().is_empty());
+    }
+
+    #[test]
+    fn partial_patches_are_undone() {
+        for offset in [1, 3, 7] {
+            let (mock, memory) = code().open();
+            let failing = 0x10010 + offset..0x10020;
+            mock.fail_writes(failing.clone());
+
+            let e = memory.apply_patch(0x10010, &[0x90; 8]).unwrap_err();
+            assert_eq!(partial_write(&e).map(|partial| partial.written), Some(offset));
+            assert_eq!(mock.bytes(0x10010, 8), [0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17]);
+            assert!(memory.patches().is_empty());
+        }
+    }
+
+    #[test]
+    fn overlapping_patches_are_refused() {
+        let (_mock, memory) = code().open();
+
+        memory.apply_patch(0x10010, &[0x90; 4]).unwrap();
+        let e = memory.apply_patch(0x10012, &[0xCC; 4]).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn failed_batches_roll_back() {
+        let (mock, memory) = code().region(0x11000, vec![0; PAGE], PAGE_READWRITE).open();
+        mock.fail_writes(0x11002..0x12000);
+
+        let patches = [(0x10010, vec![0x90; 2]), (0x10020, vec![0xCC; 2]), (0x11000, vec![1; 4])];
+        let e = memory.apply_patches(&patches).unwrap_err();
+        assert_eq!(partial_write(&e).map(|partial| partial.written), Some(2));
+        assert_eq!(mock.bytes(0x10010, 2), [0x10, 0x11]);
+        assert_eq!(mock.bytes(0x10020, 2), [0x20, 0x21]);
+        assert_eq!(mock.bytes(0x11000, 4), [0; 4]);
+        assert!(memory.patches().is_empty());
+    }
+
+    #[test]
+    fn whole_batches_apply() {
+        let (mock, memory) = code().open();
+
+        let patches = memory.apply_patches(&[(0x10010, vec![0x90; 2]), (0x10020, vec![0xCC; 2])]).unwrap();
+        assert_eq!(patches.len(), 2);
+        assert_eq!(memory.patches().len(), 2);
+        assert_eq!(mock.bytes(0x10020, 2), [0xCC; 2]);
+    }
+
+    // An empty directory of its own for a test's recovery files.
+    fn net_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nirvana-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // The ranges each recovery file in `dir` saved, by address, fewest first.
+    fn recovery_files(dir: &PathBuf) -> Vec<Vec<(usize, Vec<u8>)>> {
+        let mut nets: Vec<Vec<(usize, Vec<u8>)>> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| SafetyNet::load(entry.unwrap().path()).unwrap())
+            .map(|net| net.ranges.into_iter().map(|range| (range.address, range.bytes)).collect())
+            .collect();
+        nets.sort_by_key(Vec::len);
+        fs::remove_dir_all(dir).unwrap();
+        nets
+    }
+
+    #[test]
+    fn a_batch_that_cant_roll_back_leaves_a_recovery_file() {
+        let (mock, mut memory) = code().open();
+        let dir = net_dir("unrevertable-batch");
+        memory.set_safety_net(Some(dir.clone()));
+        // The third patch fails, and by then the first can't be written back either.
+        mock.fail_writes(0x10030..0x10032);
+        let hook_mock = Arc::downgrade(&mock);
+        mock.on_call(move |call| {
+            if *call == Call::Write(0x10030..0x10032) {
+                hook_mock.upgrade().unwrap().fail_writes(0x10010..0x10012);
+            }
+        });
+
+        let patches = [(0x10010, vec![0x90; 2]), (0x10020, vec![0xCC; 2]), (0x10030, vec![0xEB; 2])];
+        memory.apply_patches(&patches).unwrap_err();
+        assert_eq!(mock.bytes(0x10010, 2), [0x90; 2]);
+        assert_eq!(mock.bytes(0x10020, 2), [0x20, 0x21]);
+        // The net armed before the batch, and the record of what stayed applied.
+        let nets = recovery_files(&dir);
+        assert_eq!(nets.len(), 2);
+        assert_eq!(nets[0], [(0x10010, vec![0x10, 0x11])]);
+        assert_eq!(nets[1].len(), 3);
+    }
+
+    #[test]
+    fn a_batch_cut_short_by_a_panic_leaves_a_recovery_file() {
+        let (mock, mut memory) = code().open();
+        let dir = net_dir("panicked-batch");
+        memory.set_safety_net(Some(dir.clone()));
+        mock.on_call(|call| {
+            if *call == Call::Write(0x10030..0x10032) {
+                panic!("cancelled mid-batch");
+            }
+        });
+
+        let patches = [(0x10010, vec![0x90; 2]), (0x10020, vec![0xCC; 2]), (0x10030, vec![0xEB; 2])];
+        panic::catch_unwind(AssertUnwindSafe(|| memory.apply_patches(&patches))).unwrap_err();
+        // Nothing was written after the panic, the page got its protection back, and the
+        // recovery file undoes what the batch wrote.
+        assert_eq!(mock.writes(), [0x10010..0x10012, 0x10020..0x10022]);
+        assert_eq!(memory.query_address(0x10000).unwrap().protection, PAGE_EXECUTE_READ);
+        let nets = recovery_files(&dir);
+        assert_eq!(nets.len(), 2);
+        assert_eq!(nets[0], [(0x10010, vec![0x10, 0x11]), (0x10020, vec![0x20, 0x21])]);
+    }
+}
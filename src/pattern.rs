@@ -0,0 +1,580 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::iter::FusedIterator;
+use std::path::Path;
+
+// Bytes `search_file` reads at a time.
+const FILE_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// A byte signature with per-nibble wildcards.
+///
+/// `Pattern::parse` accepts whitespace-separated tokens:
+///
+/// - `8B` — two hex digits match that exact byte
+/// - `??` or `?` — matches any byte
+/// - `4?` — high nibble must be 4, low nibble is free (0x40..=0x4F)
+/// - `?5` — low nibble must be 5, high nibble is free
+///
+/// Anything else, such as `??x` or `G1`, is rejected.
+///
+/// ```
+/// use nirvana_notject::Pattern;
+///
+/// let pattern = Pattern::parse("48 8B ?? 4? ?5").unwrap();
+/// assert!(pattern.matches_at(&[0x48, 0x8B, 0x00, 0x4C, 0xF5], 0));
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct Pattern {
+    values: Vec<u8>,
+    // Bits that must match per position: 0xFF exact, 0xF0 / 0x0F nibble, 0x00 wildcard.
+    masks: Vec<u8>,
+    // First fully significant position, used to jump between candidates.
+    anchor: Option<usize>,
+}
+
+impl Pattern {
+    pub fn new(bytes: &[u8], mask: &[bool]) -> io::Result<Self> {
+        let masks: Vec<u8> = mask.iter().map(|&significant| if significant { 0xFF } else { 0x00 }).collect();
+        Self::with_masks(bytes, &masks)
+    }
+
+    pub fn exact(bytes: &[u8]) -> io::Result<Self> {
+        Self::with_masks(bytes, &vec![0xFF; bytes.len()])
+    }
+
+    // Bits set in `masks` are compared; clear bits are wildcards.
+    pub fn with_masks(values: &[u8], masks: &[u8]) -> io::Result<Self> {
+        if values.is_empty() {
+            return Err(invalid_pattern("Pattern is empty".to_string()));
+        }
+        if values.len() != masks.len() {
+            return Err(invalid_pattern(format!(
+                "Pattern has {} bytes but its mask has {} entries",
+                values.len(),
+                masks.len()
+            )));
+        }
+
+        Ok(Pattern {
+            values: values.iter().zip(masks).map(|(&value, &mask)| value & mask).collect(),
+            masks: masks.to_vec(),
+            anchor: masks.iter().position(|&mask| mask == 0xFF),
+        })
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut values = Vec::new();
+        let mut masks = Vec::new();
+
+        for token in text.split_whitespace() {
+            let (value, mask) = parse_token(token)?;
+            values.push(value);
+            masks.push(mask);
+        }
+
+        Self::with_masks(&values, &masks)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.values
+    }
+
+    pub fn masks(&self) -> &[u8] {
+        &self.masks
+    }
+
+    // Caller guarantees `haystack` holds at least `len()` bytes from `offset`.
+    pub fn matches_at(&self, haystack: &[u8], offset: usize) -> bool {
+        let window = &haystack[offset..offset + self.values.len()];
+        window
+            .iter()
+            .zip(&self.values)
+            .zip(&self.masks)
+            .all(|((&actual, &value), &mask)| actual & mask == value)
+    }
+
+    pub fn find_from(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        if haystack.len() < self.values.len() {
+            return None;
+        }
+        let last = haystack.len() - self.values.len();
+        if start > last {
+            return None;
+        }
+
+        let Some(anchor) = self.anchor else {
+            return (start..=last).find(|&offset| self.matches_at(haystack, offset));
+        };
+
+        // Only offsets where the anchor byte matches exactly can start a match.
+        let anchor_value = self.values[anchor];
+        let mut offset = start;
+        while offset <= last {
+            let candidates = &haystack[offset + anchor..=last + anchor];
+            let skip = candidates.iter().position(|&byte| byte == anchor_value)?;
+            offset += skip;
+            if self.matches_at(haystack, offset) {
+                return Some(offset);
+            }
+            offset += 1;
+        }
+
+        None
+    }
+
+    // Highest match starting before `end`, searching back to front.
+    pub fn rfind_before(&self, haystack: &[u8], end: usize) -> Option<usize> {
+        if haystack.len() < self.values.len() {
+            return None;
+        }
+        let mut bound = end.min(haystack.len() - self.values.len() + 1);
+
+        let Some(anchor) = self.anchor else {
+            return (0..bound).rev().find(|&offset| self.matches_at(haystack, offset));
+        };
+
+        let anchor_value = self.values[anchor];
+        while bound > 0 {
+            let candidates = &haystack[anchor..bound + anchor];
+            let offset = candidates.iter().rposition(|&byte| byte == anchor_value)?;
+            if self.matches_at(haystack, offset) {
+                return Some(offset);
+            }
+            bound = offset;
+        }
+
+        None
+    }
+}
+
+// Every match of `pattern` in `haystack`, as ascending offsets. Matches may overlap. This is
+// the matcher the live scans run on each chunk they read, so it applies to dumps and other
+// bytes already in hand without a process to attach to:
+//
+//     let dump = fs::read("game.dmp")?;
+//     let hits = pattern::search(&dump, &Pattern::parse("48 8B 05 ?? ?? ?? ??")?);
+pub fn search(haystack: &[u8], pattern: &Pattern) -> Vec<usize> {
+    search_iter(haystack, pattern).collect()
+}
+
+// The matches of `search`, found as they are asked for. Iterating from the back yields them
+// in descending order; `starting_at` and `ending_before` narrow the offsets searched.
+pub fn search_iter<'a>(haystack: &'a [u8], pattern: &'a Pattern) -> SearchIter<'a> {
+    SearchIter { haystack, pattern, front: 0, back: haystack.len() }
+}
+
+// Iterator returned by `search_iter`.
+#[derive(Debug, Clone)]
+pub struct SearchIter<'a> {
+    haystack: &'a [u8],
+    pattern: &'a Pattern,
+    // Lowest start offset not yet searched from the front.
+    front: usize,
+    // Exclusive upper bound of start offsets not yet searched from the back.
+    back: usize,
+}
+
+impl SearchIter<'_> {
+    // Only matches starting at `offset` or later.
+    pub fn starting_at(mut self, offset: usize) -> Self {
+        self.front = self.front.max(offset);
+        self
+    }
+
+    // Only matches starting below `offset`. The match itself may extend past it.
+    pub fn ending_before(mut self, offset: usize) -> Self {
+        self.back = self.back.min(offset);
+        self
+    }
+}
+
+impl Iterator for SearchIter<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.front >= self.back {
+            return None;
+        }
+        match self.pattern.find_from(self.haystack, self.front).filter(|&offset| offset < self.back) {
+            Some(offset) => {
+                self.front = offset + 1;
+                Some(offset)
+            }
+            None => {
+                self.front = self.back;
+                None
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for SearchIter<'_> {
+    fn next_back(&mut self) -> Option<usize> {
+        if self.front >= self.back {
+            return None;
+        }
+        match self.pattern.rfind_before(self.haystack, self.back).filter(|&offset| offset >= self.front) {
+            Some(offset) => {
+                self.back = offset;
+                Some(offset)
+            }
+            None => {
+                self.back = self.front;
+                None
+            }
+        }
+    }
+}
+
+impl FusedIterator for SearchIter<'_> {}
+
+// `search` over a file, read a chunk at a time so it needn't fit in memory. Each chunk
+// carries the last `len() - 1` bytes of the one before, so matches across chunk boundaries
+// are found once. Offsets are from the start of the file.
+pub fn search_file(path: impl AsRef<Path>, pattern: &Pattern) -> io::Result<Vec<u64>> {
+    let path = path.as_ref();
+    let mut file =
+        File::open(path).map_err(|e| io::Error::new(e.kind(), format!("Failed to open {}: {}", path.display(), e)))?;
+    search_reader(&mut file, pattern)
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to read {}: {}", path.display(), e)))
+}
+
+fn search_reader(reader: &mut impl Read, pattern: &Pattern) -> io::Result<Vec<u64>> {
+    let overlap = pattern.len() - 1;
+    let mut buffer = vec![0u8; FILE_CHUNK_SIZE + overlap];
+    let mut matches = Vec::new();
+    // File offset of `buffer[0]`, and how many bytes at the front were carried over.
+    let mut base = 0u64;
+    let mut carried = 0;
+
+    loop {
+        let filled = carried + read_full(reader, &mut buffer[carried..])?;
+        // A match starting in the carried bytes didn't fit in the previous chunk, so none of
+        // these was found before.
+        matches.extend(search_iter(&buffer[..filled], pattern).map(|offset| base + offset as u64));
+        if filled < buffer.len() {
+            return Ok(matches);
+        }
+        carried = overlap.min(filled);
+        buffer.copy_within(filled - carried..filled, 0);
+        base += (filled - carried) as u64;
+    }
+}
+
+// Reads until `buffer` is full or the reader is exhausted; returns the bytes read.
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+// The textual form `parse` accepts, e.g. "48 8B ?? 4? ?5". A nibble whose mask from
+// `with_masks` is neither 0x0 nor 0xF prints as a wildcard, so only such patterns lose
+// information on the way through `parse`.
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (&value, &mask)) in self.values.iter().zip(&self.masks).enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            for shift in [4, 0] {
+                if (mask >> shift) & 0xF == 0xF {
+                    write!(f, "{:X}", (value >> shift) & 0xF)?;
+                } else {
+                    f.write_str("?")?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pattern(\"{}\")", self)
+    }
+}
+
+fn parse_token(token: &str) -> io::Result<(u8, u8)> {
+    let invalid = || invalid_pattern(format!("Invalid pattern token '{}'", token));
+
+    if token == "?" {
+        return Ok((0, 0));
+    }
+
+    let digits: Vec<char> = token.chars().collect();
+    if digits.len() != 2 {
+        return Err(invalid());
+    }
+
+    let mut value = 0u8;
+    let mut mask = 0u8;
+    for digit in digits {
+        value <<= 4;
+        mask <<= 4;
+        if digit != '?' {
+            value |= digit.to_digit(16).ok_or_else(invalid)? as u8;
+            mask |= 0xF;
+        }
+    }
+
+    Ok((value, mask))
+}
+
+fn invalid_pattern(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A xorshift generator, so the property tests cover the same cases on every run.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, bound: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % bound as u64) as usize
+        }
+    }
+
+    // Bytes from a small alphabet, so random patterns match often enough to be worth testing.
+    fn haystack(rng: &mut Rng, len: usize) -> Vec<u8> {
+        (0..len).map(|_| [0x40, 0x45, 0x4F, 0x8B, 0xF5][rng.below(5)]).collect()
+    }
+
+    // Patterns of up to 6 bytes, each exact, either nibble or a wildcard.
+    fn pattern(rng: &mut Rng) -> Pattern {
+        let len = 1 + rng.below(6);
+        let values = haystack(rng, len);
+        let masks: Vec<u8> = (0..len).map(|_| [0xFF, 0xF0, 0x0F, 0x00][rng.below(4)]).collect();
+        Pattern::with_masks(&values, &masks).unwrap()
+    }
+
+    // Every offset in turn, every byte compared; what the anchored search must agree with.
+    fn naive(haystack: &[u8], pattern: &Pattern) -> Vec<usize> {
+        (0..(haystack.len() + 1).saturating_sub(pattern.len()))
+            .filter(|&offset| {
+                (0..pattern.len()).all(|i| haystack[offset + i] & pattern.masks()[i] == pattern.bytes()[i])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tokens_parse_into_values_and_masks() {
+        let pattern = Pattern::parse("8B ?? ? 4? ?5 ab").unwrap();
+        assert_eq!(pattern.bytes(), [0x8B, 0x00, 0x00, 0x40, 0x05, 0xAB]);
+        assert_eq!(pattern.masks(), [0xFF, 0x00, 0x00, 0xF0, 0x0F, 0xFF]);
+    }
+
+    #[test]
+    fn malformed_patterns_are_refused() {
+        for text in ["", "   ", "??x", "G1", "4", "123", "8B ?? 4G"] {
+            let e = Pattern::parse(text).unwrap_err();
+            assert_eq!(e.kind(), io::ErrorKind::InvalidInput, "{:?}", text);
+        }
+        assert!(Pattern::with_masks(&[1, 2], &[0xFF]).is_err());
+    }
+
+    #[test]
+    fn nibble_wildcards_match_only_their_nibble() {
+        let high = Pattern::parse("4?").unwrap();
+        let low = Pattern::parse("?5").unwrap();
+        for byte in 0..=255u8 {
+            assert_eq!(high.matches_at(&[byte], 0), byte >> 4 == 4, "{:02X}", byte);
+            assert_eq!(low.matches_at(&[byte], 0), byte & 0xF == 5, "{:02X}", byte);
+        }
+    }
+
+    #[test]
+    fn values_are_masked_when_the_pattern_is_made() {
+        let pattern = Pattern::with_masks(&[0x4C], &[0xF0]).unwrap();
+        assert_eq!(pattern.bytes(), [0x40]);
+        assert!(pattern.matches_at(&[0x47], 0));
+    }
+
+    #[test]
+    fn searches_agree_with_a_naive_matcher() {
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for _ in 0..2000 {
+            let len = rng.below(40);
+            let haystack = haystack(&mut rng, len);
+            let pattern = pattern(&mut rng);
+            let expected = naive(&haystack, &pattern);
+            assert_eq!(search(&haystack, &pattern), expected, "{:?} in {:02X?}", pattern, haystack);
+            for start in 0..=haystack.len() + 1 {
+                let first = expected.iter().copied().find(|&offset| offset >= start);
+                assert_eq!(pattern.find_from(&haystack, start), first, "{:?} from {}", pattern, start);
+            }
+        }
+    }
+
+    #[test]
+    fn patterns_without_an_exact_byte_try_every_offset() {
+        let pattern = Pattern::parse("4? ?? ?5").unwrap();
+        let haystack = [0x41, 0x42, 0x45, 0x35, 0xF5, 0x00];
+        assert_eq!(search(&haystack, &pattern), [0, 1, 2]);
+        assert_eq!(search(&haystack[..2], &pattern), [] as [usize; 0]);
+    }
+
+    #[test]
+    fn the_anchor_need_not_be_the_first_byte() {
+        // The 8B at offset 0 can't be the anchor of any match; the one at 3 is, for offset 2.
+        let pattern = Pattern::parse("?? 8B 4?").unwrap();
+        let haystack = [0x8B, 0x00, 0x11, 0x8B, 0x4F, 0x8B];
+        assert_eq!(search(&haystack, &pattern), [2]);
+        assert_eq!(pattern.find_from(&haystack, 3), None);
+    }
+
+    #[test]
+    fn reverse_searches_agree_with_a_naive_matcher() {
+        let mut rng = Rng(0xD1B5_4A32_D192_ED03);
+        for _ in 0..2000 {
+            let len = rng.below(40);
+            let haystack = haystack(&mut rng, len);
+            let pattern = pattern(&mut rng);
+            let expected = naive(&haystack, &pattern);
+            let backwards: Vec<usize> = search_iter(&haystack, &pattern).rev().collect();
+            assert!(backwards.iter().rev().eq(&expected), "{:?} in {:02X?}", pattern, haystack);
+            for end in 0..=haystack.len() + 1 {
+                let last = expected.iter().copied().rfind(|&offset| offset < end);
+                assert_eq!(pattern.rfind_before(&haystack, end), last, "{:?} before {}", pattern, end);
+            }
+        }
+    }
+
+    #[test]
+    fn reverse_searches_slice_from_the_anchor() {
+        // Only the 8B at 3 can anchor a match, at 2; the one at 5 has no byte after it.
+        let pattern = Pattern::parse("?? 8B 4?").unwrap();
+        let haystack = [0x8B, 0x00, 0x11, 0x8B, 0x4F, 0x8B];
+        assert_eq!(pattern.rfind_before(&haystack, usize::MAX), Some(2));
+        assert_eq!(pattern.rfind_before(&haystack, 3), Some(2));
+        assert_eq!(pattern.rfind_before(&haystack, 2), None);
+        assert_eq!(pattern.rfind_before(&haystack[..2], 2), None);
+    }
+
+    #[test]
+    fn iterators_narrowed_from_both_ends_meet_once() {
+        let pattern = Pattern::parse("8B").unwrap();
+        let haystack = [0x8B; 8];
+        let mut iter = search_iter(&haystack, &pattern).starting_at(1).ending_before(7);
+        let mut seen = Vec::new();
+        while let (Some(front), back) = (iter.next(), iter.next_back()) {
+            seen.push(front);
+            seen.extend(back);
+        }
+        seen.sort();
+        assert_eq!(seen, [1, 2, 3, 4, 5, 6]);
+        assert_eq!((iter.next(), iter.next_back()), (None, None));
+    }
+
+    #[test]
+    fn matches_may_run_past_ending_before() {
+        let pattern = Pattern::parse("8B 4?").unwrap();
+        let haystack = [0x00, 0x8B, 0x40, 0x8B, 0x41];
+        assert_eq!(search_iter(&haystack, &pattern).ending_before(2).collect::<Vec<_>>(), [1]);
+        assert_eq!(search_iter(&haystack, &pattern).ending_before(2).rev().collect::<Vec<_>>(), [1]);
+        assert_eq!(search_iter(&haystack, &pattern).starting_at(2).collect::<Vec<_>>(), [3]);
+    }
+
+    // Hands out at most `step` bytes per call, with an interruption before every other one.
+    struct Trickle<'a> {
+        bytes: &'a [u8],
+        step: usize,
+        interrupt: bool,
+    }
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            let read = self.step.min(buffer.len()).min(self.bytes.len());
+            buffer[..read].copy_from_slice(&self.bytes[..read]);
+            self.bytes = &self.bytes[read..];
+            Ok(read)
+        }
+    }
+
+    fn search_trickled(bytes: &[u8], pattern: &Pattern) -> Vec<u64> {
+        search_reader(&mut Trickle { bytes, step: 0x10001, interrupt: false }, pattern).unwrap()
+    }
+
+    #[test]
+    fn matches_across_chunk_seams_are_found_once() {
+        let pattern = Pattern::parse("01 02 ?? 04").unwrap();
+        // Each read ends 3 bytes past a multiple of the chunk size, and those 3 are carried
+        // into the next: starts just before them, in them, and right after, at both seams.
+        for seam in [FILE_CHUNK_SIZE, 2 * FILE_CHUNK_SIZE] {
+            for offset in seam - 2..seam + 5 {
+                let mut bytes = vec![0u8; 2 * FILE_CHUNK_SIZE + 16];
+                bytes[offset..offset + 4].copy_from_slice(&[1, 2, 3, 4]);
+                assert_eq!(search_trickled(&bytes, &pattern), [offset as u64], "at 0x{:X}", offset);
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_matches_are_kept_across_seams() {
+        let pattern = Pattern::parse("AA AA").unwrap();
+        let mut bytes = vec![0u8; FILE_CHUNK_SIZE + 2];
+        bytes[FILE_CHUNK_SIZE - 2..].fill(0xAA);
+        let seam = FILE_CHUNK_SIZE as u64;
+        assert_eq!(search_trickled(&bytes, &pattern), [seam - 2, seam - 1, seam]);
+    }
+
+    #[test]
+    fn inputs_that_fill_the_buffer_exactly_end_cleanly() {
+        let pattern = Pattern::parse("01 02 03").unwrap();
+        let mut bytes = vec![0u8; FILE_CHUNK_SIZE + 2];
+        bytes[FILE_CHUNK_SIZE - 1..].copy_from_slice(&[1, 2, 3]);
+        assert_eq!(search_trickled(&bytes, &pattern), [FILE_CHUNK_SIZE as u64 - 1]);
+        assert_eq!(search_trickled(&[1, 2], &pattern), [] as [u64; 0]);
+        assert_eq!(search_trickled(&[], &Pattern::parse("01").unwrap()), [] as [u64; 0]);
+    }
+
+    #[test]
+    fn file_searches_name_the_file_they_fail_on() {
+        let dir = std::env::temp_dir().join(format!("nirvana-search-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dump.bin");
+        std::fs::write(&path, [0x90, 0x48, 0x8B, 0x05, 0x48, 0x8B]).unwrap();
+
+        assert_eq!(search_file(&path, &Pattern::parse("48 8B").unwrap()).unwrap(), [1, 4]);
+        let e = search_file(dir.join("missing.bin"), &Pattern::parse("48").unwrap()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::NotFound);
+        assert!(e.to_string().contains("missing.bin"), "{}", e);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn patterns_print_as_they_parse() {
+        for text in ["8B", "48 8B ?? 4? ?5", "?? ?? 00 FF"] {
+            let pattern = Pattern::parse(text).unwrap();
+            assert_eq!(pattern.to_string(), text);
+            assert_eq!(Pattern::parse(&pattern.to_string()).unwrap(), pattern);
+        }
+        assert_eq!(Pattern::with_masks(&[0xFF], &[0x3C]).unwrap().to_string(), "??");
+    }
+}
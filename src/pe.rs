@@ -0,0 +1,384 @@
+// Just enough PE parsing for mapping images and resolving exports: headers, sections, data
+// directories, base relocations, imports and exports. Everything works on byte slices; a
+// file on disk uses `raw_offset`s, an image laid out in memory uses RVAs as offsets.
+
+use std::collections::HashMap;
+use std::io;
+
+pub const DIRECTORY_EXPORT: usize = 0;
+pub const DIRECTORY_IMPORT: usize = 1;
+pub const DIRECTORY_EXCEPTION: usize = 3;
+pub const DIRECTORY_BASERELOC: usize = 5;
+pub const DIRECTORY_TLS: usize = 9;
+
+pub const SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+pub const SCN_MEM_READ: u32 = 0x4000_0000;
+pub const SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+const DOS_MAGIC: u16 = 0x5A4D; // "MZ"
+const NT_SIGNATURE: u32 = 0x0000_4550; // "PE\0\0"
+const OPTIONAL_MAGIC_32: u16 = 0x10B;
+const OPTIONAL_MAGIC_64: u16 = 0x20B;
+
+const REL_BASED_ABSOLUTE: u16 = 0;
+const REL_BASED_HIGHLOW: u16 = 3;
+const REL_BASED_DIR64: u16 = 10;
+
+// Limits for input that would otherwise cost unbounded time or memory. The loader refuses
+// images with more sections, ordinals are 16 bits, and no sane import or export name comes
+// near the length limit.
+const MAX_SECTIONS: usize = 96;
+const MAX_IMPORTS: usize = 0x10000;
+const MAX_EXPORTS: usize = 0x10000;
+const MAX_NAME_LEN: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DataDirectory {
+    pub rva: u32,
+    pub size: u32,
+}
+
+impl DataDirectory {
+    pub fn is_present(&self) -> bool {
+        self.rva != 0 && self.size != 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub virtual_address: u32,
+    pub virtual_size: u32,
+    pub raw_offset: u32,
+    pub raw_size: u32,
+    pub characteristics: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeHeaders {
+    pub is_64: bool,
+    pub image_base: u64,
+    pub size_of_image: u32,
+    pub size_of_headers: u32,
+    // Link time from the file header, seconds since 1970. Reproducible builds put a hash here.
+    pub time_date_stamp: u32,
+    // RVA of the entry point (DllMain for a DLL), 0 if there is none.
+    pub entry_point: u32,
+    pub sections: Vec<Section>,
+    pub directories: Vec<DataDirectory>,
+}
+
+impl PeHeaders {
+    pub fn parse(bytes: &[u8]) -> io::Result<Self> {
+        if u16_at(bytes, 0)? != DOS_MAGIC {
+            return Err(invalid("Missing MZ signature"));
+        }
+        let nt = u32_at(bytes, 0x3C)? as usize;
+        if u32_at(bytes, nt)? != NT_SIGNATURE {
+            return Err(invalid(format!("Missing PE signature at 0x{:X}", nt)));
+        }
+
+        let file_header = nt + 4;
+        let section_count = u16_at(bytes, file_header + 2)? as usize;
+        let time_date_stamp = u32_at(bytes, file_header + 4)?;
+        if section_count > MAX_SECTIONS {
+            return Err(invalid(format!("Too many sections ({})", section_count)));
+        }
+        let optional_size = u16_at(bytes, file_header + 16)? as usize;
+        let optional = file_header + 20;
+
+        let is_64 = match u16_at(bytes, optional)? {
+            OPTIONAL_MAGIC_32 => false,
+            OPTIONAL_MAGIC_64 => true,
+            magic => return Err(invalid(format!("Unknown optional header magic 0x{:X}", magic))),
+        };
+        let entry_point = u32_at(bytes, optional + 16)?;
+        let image_base = if is_64 { u64_at(bytes, optional + 24)? } else { u32_at(bytes, optional + 28)? as u64 };
+        let size_of_image = u32_at(bytes, optional + 56)?;
+        let size_of_headers = u32_at(bytes, optional + 60)?;
+
+        let (count_offset, directories_offset) = if is_64 { (108, 112) } else { (92, 96) };
+        let directory_count = (u32_at(bytes, optional + count_offset)? as usize).min(16);
+        let directories = (0..directory_count)
+            .map(|index| {
+                let offset = optional + directories_offset + index * 8;
+                Ok(DataDirectory { rva: u32_at(bytes, offset)?, size: u32_at(bytes, offset + 4)? })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let sections = (0..section_count)
+            .map(|index| {
+                let offset = optional + optional_size + index * 40;
+                let name = slice_at(bytes, offset, 8)?;
+                let name = String::from_utf8_lossy(&name[..name.iter().position(|&b| b == 0).unwrap_or(8)]).into_owned();
+                Ok(Section {
+                    name,
+                    virtual_size: u32_at(bytes, offset + 8)?,
+                    virtual_address: u32_at(bytes, offset + 12)?,
+                    raw_size: u32_at(bytes, offset + 16)?,
+                    raw_offset: u32_at(bytes, offset + 20)?,
+                    characteristics: u32_at(bytes, offset + 36)?,
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        check_sections(&sections, size_of_image)?;
+
+        Ok(PeHeaders {
+            is_64,
+            image_base,
+            size_of_image,
+            size_of_headers,
+            time_date_stamp,
+            entry_point,
+            sections,
+            directories,
+        })
+    }
+
+    // The directory at `index` if the image has one.
+    pub fn directory(&self, index: usize) -> Option<DataDirectory> {
+        self.directories.get(index).copied().filter(DataDirectory::is_present)
+    }
+
+    pub fn pointer_size(&self) -> usize {
+        if self.is_64 {
+            8
+        } else {
+            4
+        }
+    }
+}
+
+// Sections have to be in ascending order without overlapping, and lie inside the image, as
+// the loader requires; anything else would have later sections overwrite earlier ones.
+fn check_sections(sections: &[Section], size_of_image: u32) -> io::Result<()> {
+    let mut end = 0u64;
+    for section in sections {
+        let size = if section.virtual_size == 0 { section.raw_size } else { section.virtual_size };
+        let start = section.virtual_address as u64;
+        if start < end {
+            return Err(invalid(format!("Section {} overlaps the one before it", section.name)));
+        }
+        end = start + size as u64;
+        if end > size_of_image as u64 {
+            return Err(invalid(format!(
+                "Section {} ends at rva 0x{:X}, past the image size 0x{:X}",
+                section.name, end, size_of_image
+            )));
+        }
+        if (section.raw_offset as u64) + (section.raw_size as u64) > u32::MAX as u64 {
+            return Err(invalid(format!("Raw data of section {} overflows", section.name)));
+        }
+    }
+    Ok(())
+}
+
+// A slot that base relocation has to adjust, at `rva` in the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Relocation {
+    pub rva: u32,
+    // True for a 64-bit slot (DIR64), false for a 32-bit one (HIGHLOW).
+    pub is_64: bool,
+}
+
+// Parses the base relocation blocks of an image laid out in memory. Fails on relocation
+// types other than HIGHLOW and DIR64, which x86 and x64 images don't use.
+pub fn relocations(image: &[u8], directory: DataDirectory) -> io::Result<Vec<Relocation>> {
+    let mut relocations = Vec::new();
+    let mut block = directory.rva as usize;
+    let end = block + directory.size as usize;
+
+    while block + 8 <= end {
+        let page = u32_at(image, block)?;
+        let block_size = u32_at(image, block + 4)? as usize;
+        if block_size < 8 {
+            break;
+        }
+        for entry in (block + 8..block + block_size).step_by(2) {
+            let entry = u16_at(image, entry)?;
+            let rva = page
+                .checked_add((entry & 0x0FFF) as u32)
+                .ok_or_else(|| invalid(format!("Relocation block for page 0x{:X} overflows", page)))?;
+            match entry >> 12 {
+                REL_BASED_ABSOLUTE => {}
+                REL_BASED_HIGHLOW => relocations.push(Relocation { rva, is_64: false }),
+                REL_BASED_DIR64 => relocations.push(Relocation { rva, is_64: true }),
+                kind => return Err(invalid(format!("Unsupported relocation type {} at rva 0x{:X}", kind, rva))),
+            }
+        }
+        block += block_size;
+    }
+
+    Ok(relocations)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ImportName {
+    Name(String),
+    Ordinal(u16),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedModule {
+    pub name: String,
+    // Each import with the RVA of its slot in the import address table.
+    pub imports: Vec<(u32, ImportName)>,
+}
+
+// Parses the import descriptors of an image laid out in memory.
+pub fn imports(image: &[u8], directory: DataDirectory, is_64: bool) -> io::Result<Vec<ImportedModule>> {
+    let thunk_size = if is_64 { 8 } else { 4 };
+    let ordinal_flag = if is_64 { 1u64 << 63 } else { 1u64 << 31 };
+    let mut modules = Vec::new();
+    let mut total = 0;
+
+    for descriptor in (directory.rva as usize..).step_by(20) {
+        let lookup = u32_at(image, descriptor)?;
+        let name = u32_at(image, descriptor + 12)?;
+        let address_table = u32_at(image, descriptor + 16)?;
+        if name == 0 && address_table == 0 {
+            break;
+        }
+
+        // Bound or stripped images may lack the lookup table; the IAT then holds the same thunks.
+        let thunks = if lookup != 0 { lookup } else { address_table };
+        let mut imports = Vec::new();
+        for index in 0.. {
+            let offset = thunks as usize + index * thunk_size;
+            let thunk = if is_64 { u64_at(image, offset)? } else { u32_at(image, offset)? as u64 };
+            if thunk == 0 {
+                break;
+            }
+            // Descriptors can share one huge thunk table, so count across all of them.
+            total += 1;
+            if total > MAX_IMPORTS {
+                return Err(invalid(format!("More than {} imports", MAX_IMPORTS)));
+            }
+            let import = if thunk & ordinal_flag != 0 {
+                ImportName::Ordinal(thunk as u16)
+            } else {
+                // Skip the two-byte hint in front of the name.
+                ImportName::Name(cstr_at(image, (thunk as usize).saturating_add(2))?)
+            };
+            let slot = u32::try_from(index * thunk_size)
+                .ok()
+                .and_then(|offset| address_table.checked_add(offset))
+                .ok_or_else(|| invalid(format!("Import address table at rva 0x{:X} overflows", address_table)))?;
+            imports.push((slot, import));
+        }
+
+        modules.push(ImportedModule { name: cstr_at(image, name as usize)?, imports });
+    }
+
+    Ok(modules)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportTarget {
+    Rva(u32),
+    // "OTHERDLL.Function" or "OTHERDLL.#ordinal", to be looked up in that module.
+    Forwarded(String),
+}
+
+// A module's export table.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Exports {
+    ordinal_base: u32,
+    functions: Vec<ExportTarget>,
+    // Name to index into `functions`.
+    names: HashMap<String, usize>,
+}
+
+impl Exports {
+    // Parses the export directory from `view`, the image bytes starting at `view_rva`. The
+    // view has to cover the directory and everything it points to, which for normal images
+    // lies inside the directory itself.
+    pub fn parse(view: &[u8], view_rva: u32, directory: DataDirectory) -> io::Result<Self> {
+        let at = |rva: u32| -> io::Result<usize> {
+            rva.checked_sub(view_rva)
+                .map(|offset| offset as usize)
+                .ok_or_else(|| invalid(format!("Export data at rva 0x{:X} lies outside the view", rva)))
+        };
+        let root = at(directory.rva)?;
+        let ordinal_base = u32_at(view, root + 16)?;
+        let function_count = u32_at(view, root + 20)? as usize;
+        let name_count = u32_at(view, root + 24)? as usize;
+        if function_count > MAX_EXPORTS || name_count > MAX_EXPORTS {
+            return Err(invalid(format!("Export table claims {} functions and {} names", function_count, name_count)));
+        }
+        let function_table = at(u32_at(view, root + 28)?)?;
+        let name_table = at(u32_at(view, root + 32)?)?;
+        let ordinal_table = at(u32_at(view, root + 36)?)?;
+
+        let export_range = directory.rva..directory.rva.saturating_add(directory.size);
+        let functions = (0..function_count)
+            .map(|index| {
+                let rva = u32_at(view, function_table + index * 4)?;
+                Ok(if export_range.contains(&rva) {
+                    ExportTarget::Forwarded(cstr_at(view, at(rva)?)?)
+                } else {
+                    ExportTarget::Rva(rva)
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let names = (0..name_count)
+            .map(|index| {
+                let name = cstr_at(view, at(u32_at(view, name_table + index * 4)?)?)?;
+                Ok((name, u16_at(view, ordinal_table + index * 2)? as usize))
+            })
+            .collect::<io::Result<HashMap<_, _>>>()?;
+
+        Ok(Exports { ordinal_base, functions, names })
+    }
+
+    pub fn get(&self, import: &ImportName) -> Option<&ExportTarget> {
+        let index = match import {
+            ImportName::Name(name) => *self.names.get(name)?,
+            ImportName::Ordinal(ordinal) => (*ordinal as u32).checked_sub(self.ordinal_base)? as usize,
+        };
+        // Unused ordinals have an RVA of 0.
+        self.functions.get(index).filter(|target| **target != ExportTarget::Rva(0))
+    }
+
+    // Exports that have a name, in no particular order.
+    pub fn named(&self) -> impl Iterator<Item = (&str, &ExportTarget)> {
+        self.names
+            .iter()
+            .filter_map(|(name, &index)| Some((name.as_str(), self.functions.get(index)?)))
+            .filter(|(_, target)| **target != ExportTarget::Rva(0))
+    }
+}
+
+fn slice_at(bytes: &[u8], offset: usize, len: usize) -> io::Result<&[u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| bytes.get(offset..end))
+        .ok_or_else(|| invalid(format!("PE data at 0x{:X} is truncated", offset)))
+}
+
+pub(crate) fn u16_at(bytes: &[u8], offset: usize) -> io::Result<u16> {
+    Ok(u16::from_le_bytes(slice_at(bytes, offset, 2)?.try_into().unwrap()))
+}
+
+pub(crate) fn u32_at(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    Ok(u32::from_le_bytes(slice_at(bytes, offset, 4)?.try_into().unwrap()))
+}
+
+pub(crate) fn u64_at(bytes: &[u8], offset: usize) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(slice_at(bytes, offset, 8)?.try_into().unwrap()))
+}
+
+// Strings longer than MAX_NAME_LEN count as unterminated.
+fn cstr_at(bytes: &[u8], offset: usize) -> io::Result<String> {
+    let tail = bytes.get(offset..).ok_or_else(|| invalid(format!("PE string at 0x{:X} is truncated", offset)))?;
+    let tail = &tail[..tail.len().min(MAX_NAME_LEN + 1)];
+    let len = tail
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid(format!("PE string at 0x{:X} is unterminated", offset)))?;
+    Ok(String::from_utf8_lossy(&tail[..len]).into_owned())
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
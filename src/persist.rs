@@ -0,0 +1,318 @@
+// Versioned files. What the crate saves for itself starts with the same header, so a file says
+// what it holds, which revision of its layout, when and by which build it was written, and
+// which target it belongs to:
+//
+//   magic "NNSAVE\r\n", str format id ("scan", "ptrmap", ...), u16 version,
+//   u64 created (seconds since the Unix epoch), str tool version,
+//   u8 has target, then str main module name and u64 its size             (binary formats)
+//
+//   {"format": "regions", "version": 2, "created": 1760000000, "tool_version": "0.1.0",
+//    "target": null, "data": {...}}                                      (JSON formats)
+//
+// Strings are a u16 byte length and UTF-8. A reader takes its own version and every older
+// one: an older body goes through the format's migration one version at a time before it is
+// decoded. A newer version fails with an `UnsupportedVersion` payload (`unsupported_version`)
+// instead of being misread. Files written before a format had this header, with only its own
+// magic or as bare JSON, read as version 1:
+//
+//     log.write_versioned("scan.nnlog")?;
+//     let (header, log) = ReadLog::read_versioned("scan.nnlog")?;
+//     println!("written by {:?} for {:?}", header.tool_version, header.target);
+//
+// Files people write by hand (profiles, recipes, signature files, templates) have no header;
+// their fields are optional instead.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::atomic_file::write_atomically;
+use crate::memory::Memory;
+use crate::session_file;
+
+const MAGIC: &[u8; 8] = b"NNSAVE\r\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    pub id: &'static str,
+    // The version written; every version from 1 up to it is read.
+    pub version: u16,
+    // How version 1 files of a binary format began before the header existed. JSON formats
+    // need none: a file without the envelope is their version 1.
+    pub legacy: Option<Legacy>,
+}
+
+// A magic followed by a little-endian version `version_len` bytes wide, together at least as
+// long as MAGIC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Legacy {
+    pub magic: &'static [u8],
+    pub version_len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub format: String,
+    pub version: u16,
+    // Seconds since the Unix epoch. Unset, like `tool_version`, in files from before the header.
+    pub created: Option<u64>,
+    pub tool_version: Option<String>,
+    pub target: Option<TargetIdentity>,
+}
+
+impl Header {
+    fn new(format: &Format, target: Option<TargetIdentity>) -> Self {
+        Header {
+            format: format.id.to_string(),
+            version: format.version,
+            created: Some(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())),
+            tool_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            target,
+        }
+    }
+
+    fn legacy(format: &Format) -> Self {
+        Header { format: format.id.to_string(), version: 1, created: None, tool_version: None, target: None }
+    }
+}
+
+// The main module's name and size, which survive restarts of the target but not a new build.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TargetIdentity {
+    pub module: String,
+    pub size: usize,
+}
+
+impl TargetIdentity {
+    pub fn of(memory: &Memory) -> io::Result<Self> {
+        let (module, size) = session_file::identity(memory)?;
+        Ok(TargetIdentity { module, size })
+    }
+}
+
+impl fmt::Display for TargetIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (0x{:X} bytes)", self.module, self.size)
+    }
+}
+
+// Payload of the `io::Error` for a file of a version this build doesn't read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnsupportedVersion {
+    pub format: String,
+    pub found: u16,
+    // The newest version read; every one from 1 up to it is.
+    pub supported: u16,
+}
+
+impl fmt::Display for UnsupportedVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unsupported {} version {} (this build reads 1 to {})", self.format, self.found, self.supported)
+    }
+}
+
+impl std::error::Error for UnsupportedVersion {}
+
+pub fn unsupported_version(e: &io::Error) -> Option<&UnsupportedVersion> {
+    e.get_ref()?.downcast_ref::<UnsupportedVersion>()
+}
+
+// Formats whose body is read into memory whole. `write_versioned` writes the header and the
+// body atomically (see atomic_file.rs); `read_versioned` migrates an older body, then decodes it.
+pub trait Persist: Sized {
+    const FORMAT: Format;
+
+    fn target(&self) -> Option<TargetIdentity> {
+        None
+    }
+
+    fn write_body(&self, out: &mut Vec<u8>) -> io::Result<()>;
+
+    // `body` is of `FORMAT.version`.
+    fn read_body(header: &Header, body: &[u8]) -> io::Result<Self>;
+
+    // Turns a body of `header.version` into one of the next version, moving into `header`
+    // whatever that version keeps there. The default is for a version that changed only the
+    // header.
+    fn migrate(header: &mut Header, body: Vec<u8>) -> io::Result<Vec<u8>> {
+        let _ = header;
+        Ok(body)
+    }
+
+    fn write_versioned(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut body = Vec::new();
+        self.write_body(&mut body)?;
+        let target = self.target();
+        write_atomically(path, |writer| {
+            write_header(writer, &Self::FORMAT, target.as_ref())?;
+            writer.write_all(&body)
+        })
+    }
+
+    fn read_versioned(path: impl AsRef<Path>) -> io::Result<(Header, Self)> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut header = read_header(&mut reader, &Self::FORMAT)?;
+        let mut body = Vec::new();
+        reader.read_to_end(&mut body)?;
+        while header.version < Self::FORMAT.version {
+            body = Self::migrate(&mut header, body)?;
+            header.version += 1;
+        }
+        let value = Self::read_body(&header, &body)?;
+        Ok((header, value))
+    }
+}
+
+// For formats streamed in and out, which write the body themselves after the header.
+pub fn write_header(out: &mut impl Write, format: &Format, target: Option<&TargetIdentity>) -> io::Result<()> {
+    let header = Header::new(format, target.cloned());
+    out.write_all(MAGIC)?;
+    put_str(out, &header.format)?;
+    out.write_all(&header.version.to_le_bytes())?;
+    out.write_all(&header.created.unwrap_or(0).to_le_bytes())?;
+    put_str(out, header.tool_version.as_deref().unwrap_or(""))?;
+    match &header.target {
+        Some(target) => {
+            out.write_all(&[1])?;
+            put_str(out, &target.module)?;
+            out.write_all(&(target.size as u64).to_le_bytes())
+        }
+        None => out.write_all(&[0]),
+    }
+}
+
+// Reads the header of a `format` file, or the magic and version of a legacy one, leaving
+// `input` at the body. The body still has the version in the header; see `Persist::migrate`.
+pub fn read_header(input: &mut impl Read, format: &Format) -> io::Result<Header> {
+    let mut head = [0; MAGIC.len()];
+    input.read_exact(&mut head).map_err(|_| invalid(format!("Not a {} file", format.id)))?;
+    let header = if &head == MAGIC {
+        let id = get_str(input)?;
+        if id != format.id {
+            return Err(invalid(format!("Not a {} file: it holds a {}", format.id, id)));
+        }
+        let version = u16::from_le_bytes(get(input)?);
+        let created = u64::from_le_bytes(get(input)?);
+        let tool_version = get_str(input)?;
+        let target = match get::<1>(input)? {
+            [0] => None,
+            _ => Some(TargetIdentity { module: get_str(input)?, size: u64::from_le_bytes(get(input)?) as usize }),
+        };
+        Header { format: id, version, created: Some(created), tool_version: Some(tool_version), target }
+    } else {
+        let legacy = format.legacy.filter(|legacy| head.starts_with(legacy.magic));
+        let Some(legacy) = legacy else {
+            return Err(invalid(format!("Not a {} file", format.id)));
+        };
+        let mut version = head[legacy.magic.len()..].to_vec();
+        let missing = (legacy.magic.len() + legacy.version_len).saturating_sub(MAGIC.len());
+        let mut rest = vec![0; missing];
+        input.read_exact(&mut rest)?;
+        version.extend_from_slice(&rest);
+        version.truncate(legacy.version_len);
+        let found = version.iter().rev().fold(0u64, |value, byte| value << 8 | *byte as u64);
+        let mut header = Header::legacy(format);
+        header.version = u16::try_from(found).unwrap_or(u16::MAX);
+        if header.version != 1 {
+            return Err(unsupported(format, header.version));
+        }
+        header
+    };
+    check_version(format, header.version)?;
+    Ok(header)
+}
+
+// JSON formats: written atomically, pretty-printed, inside the envelope above.
+pub fn write_json<T: Serialize>(
+    path: impl AsRef<Path>,
+    format: &Format,
+    target: Option<TargetIdentity>,
+    value: &T,
+) -> io::Result<()> {
+    write_atomically(path, |writer| write_json_to(writer, format, target, value))
+}
+
+pub fn write_json_to<T: Serialize>(
+    out: &mut impl Write,
+    format: &Format,
+    target: Option<TargetIdentity>,
+    value: &T,
+) -> io::Result<()> {
+    let mut envelope = serde_json::to_value(Header::new(format, target))?;
+    if let Some(object) = envelope.as_object_mut() {
+        object.insert("data".to_string(), serde_json::to_value(value)?);
+    }
+    Ok(serde_json::to_writer_pretty(out, &envelope)?)
+}
+
+pub fn read_json<T: DeserializeOwned>(path: impl AsRef<Path>, format: &Format) -> io::Result<(Header, T)> {
+    read_json_with(path, format, |_, data| Ok(data))
+}
+
+// `migrate` does for JSON what `Persist::migrate` does for binary bodies.
+pub fn read_json_with<T: DeserializeOwned>(
+    path: impl AsRef<Path>,
+    format: &Format,
+    migrate: impl Fn(&mut Header, Value) -> io::Result<Value>,
+) -> io::Result<(Header, T)> {
+    let mut value: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let enveloped = value.get("format").is_some_and(Value::is_string) && value.get("data").is_some();
+    let (mut header, mut data) = if enveloped {
+        let data = value.as_object_mut().and_then(|object| object.remove("data")).unwrap_or(Value::Null);
+        let header: Header = serde_json::from_value(value)?;
+        if header.format != format.id {
+            return Err(invalid(format!("Not a {} file: it holds a {}", format.id, header.format)));
+        }
+        (header, data)
+    } else {
+        (Header::legacy(format), value)
+    };
+    check_version(format, header.version)?;
+    while header.version < format.version {
+        data = migrate(&mut header, data)?;
+        header.version += 1;
+    }
+    Ok((header, serde_json::from_value(data)?))
+}
+
+fn check_version(format: &Format, version: u16) -> io::Result<()> {
+    match version {
+        0 => Err(unsupported(format, version)),
+        version if version > format.version => Err(unsupported(format, version)),
+        _ => Ok(()),
+    }
+}
+
+fn unsupported(format: &Format, found: u16) -> io::Error {
+    let payload = UnsupportedVersion { format: format.id.to_string(), found, supported: format.version };
+    io::Error::new(io::ErrorKind::InvalidData, payload)
+}
+
+fn put_str(out: &mut impl Write, text: &str) -> io::Result<()> {
+    let len = u16::try_from(text.len()).map_err(|_| invalid(format!("String too long to save: {}", text)))?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(text.as_bytes())
+}
+
+fn get_str(input: &mut impl Read) -> io::Result<String> {
+    let len = u16::from_le_bytes(get(input)?) as usize;
+    let mut bytes = vec![0; len];
+    input.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid("Invalid UTF-8 in a file header"))
+}
+
+fn get<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
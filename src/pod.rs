@@ -0,0 +1,18 @@
+/// Plain old data: `Copy` types for which every bit pattern of the right size is a valid value,
+/// so they can be built from bytes read out of another process.
+///
+/// # Safety
+///
+/// Implementors must have no padding, no invalid bit patterns (unlike `bool`, `char` or most
+/// enums) and no pointers or references that would be meaningful in this process.
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {$(
+        unsafe impl Pod for $ty {}
+    )*};
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
@@ -0,0 +1,755 @@
+// An index of every pointer in a process, built by one sweep and then asked as often as
+// needed. What points at an object, what an object points at and whole pointer chains all
+// come from sweeping every pointer-sized slot, the most expensive pass there is; with a map
+// they become binary searches:
+//
+//     memory.scan_memory()?;
+//     let mut map = PointerMap::build(&memory, &RegionFilter::new().writable(true))?;
+//     let owners = map.pointers_to(object..object + 0x40)?;      // (slot, pointee), by slot
+//     let fields = map.pointers_from(object..object + 0x40)?;
+//     let chains = memory.find_pointer_chains_in(&map, object..object + 0x40, 2)?;
+//     println!("{}", map.usage());
+//
+// A pointer is a pointer-aligned slot in the regions `filter` lets through whose value lies
+// in one of the cached regions. Values pointing at uncommitted memory are left out, which is
+// the one way the map's answers differ from a sweep by `find_pointers_to_range` with the
+// same (writable) filter. `pointers_to` a module's image is `find_pointers_into_module`.
+//
+// The map keeps each swept region apart: its pointers sorted by pointee, as a vector of
+// pointees and one of slots, and their order by slot, 20 bytes a pointer. `refresh` sweeps
+// again just the regions the region cache has seen since they were swept, which after a
+// `refresh_regions_partial` is the part it walked, sweeps the regions new to the cache
+// and forgets the ones gone from it. Writes into a region that wasn't seen again go
+// unnoticed until it is. A cancelled build or refresh leaves out the regions it didn't get
+// to, and the next `refresh` sweeps them.
+//
+// `usage` says what the map takes. When it would hold more than the options' `memory_cap`
+// in memory, its biggest regions go to files in a directory of its own under `spill_dir`,
+// and a query reads each spilled region back while it looks at it. A region that would take
+// the map past the cap on its own goes to disk in parts while it is swept. The directory goes
+// with the map. `save` writes a map to one file, and `load` reads it back for another handle to
+// the same process: the regions still cached with the same bounds and protection are kept,
+// and the next `refresh` sweeps the rest.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::{debug, warn};
+
+use crate::artifacts;
+use crate::atomic_file::write_atomically;
+use crate::filter::RegionFilter;
+use crate::memory::{Memory, MemoryRegion};
+use crate::persist::{self, Format, Legacy};
+use crate::scan::{self, CancelToken, ScanOptions};
+use crate::stats::format_size;
+
+pub const DEFAULT_MEMORY_CAP: usize = 256 * 1024 * 1024;
+
+// A pointee, a slot and a place in slot order.
+const BYTES_PER_POINTER: usize = 8 + 8 + 4;
+
+// The least a region being swept holds before a part of it is spilled, so a map at its cap
+// doesn't write a file every few pointers.
+const MIN_SPILL_PART: usize = 1024 * 1024;
+
+// Version 1 began with a magic "NNPTRMAP" and a u32 version instead of the header; the body
+// is the same.
+const FORMAT: Format =
+    Format { id: "ptrmap", version: 2, legacy: Some(Legacy { magic: b"NNPTRMAP", version_len: 4 }) };
+
+// Tells apart the spill directories of the maps in this process.
+static NEXT_SPILL_DIR: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Default)]
+pub struct PointerMapOptions {
+    // Region selection, `chunk_size` and `cancel`, as for the sweeps. Unset means the
+    // handle's default scan options.
+    pub scan: Option<ScanOptions>,
+    // Bytes of pointers held in memory before regions are spilled to disk. Unset means
+    // DEFAULT_MEMORY_CAP.
+    pub memory_cap: Option<usize>,
+    // Where the spill directory is made. Unset means the temp directory.
+    pub spill_dir: Option<PathBuf>,
+}
+
+impl PointerMapOptions {
+    pub fn new() -> Self {
+        PointerMapOptions::default()
+    }
+
+    pub fn scan_options(mut self, options: ScanOptions) -> Self {
+        self.scan = Some(options);
+        self
+    }
+
+    pub fn memory_cap(mut self, bytes: usize) -> Self {
+        self.memory_cap = Some(bytes);
+        self
+    }
+
+    pub fn spill_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.spill_dir = Some(dir.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PointerMapUsage {
+    pub pointers: usize,
+    pub regions: usize,
+    pub bytes_in_memory: usize,
+    pub spilled_regions: usize,
+    pub bytes_spilled: usize,
+    pub memory_cap: usize,
+}
+
+// "182034 pointers in 412 regions, 3.5 MiB in memory (cap 256.0 MiB)", plus
+// ", 2 regions (1.1 MiB) spilled" when some are.
+impl fmt::Display for PointerMapUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} pointers in {} regions, {} in memory (cap {})",
+            self.pointers,
+            self.regions,
+            format_size(self.bytes_in_memory),
+            format_size(self.memory_cap)
+        )?;
+        if self.spilled_regions > 0 {
+            write!(f, ", {} regions ({}) spilled", self.spilled_regions, format_size(self.bytes_spilled))?;
+        }
+        Ok(())
+    }
+}
+
+// What one `refresh` did, in regions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PointerMapRefresh {
+    pub kept: usize,
+    // Swept again because the cache saw them since, or their bounds or protection changed.
+    pub reswept: usize,
+    pub added: usize,
+    pub dropped: usize,
+    pub cancelled: bool,
+}
+
+#[derive(Debug)]
+pub struct PointerMap {
+    process_id: u32,
+    pointer_size: usize,
+    filter: RegionFilter,
+    options: ScanOptions,
+    memory_cap: usize,
+    spill: Spill,
+    // By start address.
+    regions: Vec<SweptRegion>,
+}
+
+impl PointerMap {
+    // Sweeps the regions `filter` lets through with the handle's default scan options and the
+    // default memory cap. Needs `scan_memory`.
+    pub fn build(memory: &Memory, filter: &RegionFilter) -> io::Result<Self> {
+        PointerMap::build_with(memory, filter, &PointerMapOptions::default())
+    }
+
+    pub fn build_with(memory: &Memory, filter: &RegionFilter, options: &PointerMapOptions) -> io::Result<Self> {
+        let mut map = PointerMap::empty(memory, filter.clone(), options)?;
+        let refresh = map.refresh(memory)?;
+        debug!("pointer map built: {} ({} regions swept)", map.usage(), refresh.added);
+        Ok(map)
+    }
+
+    fn empty(memory: &Memory, filter: RegionFilter, options: &PointerMapOptions) -> io::Result<Self> {
+        Ok(PointerMap {
+            process_id: memory.process_id(),
+            pointer_size: memory.pointer_size()?,
+            filter,
+            options: options.scan.clone().unwrap_or_else(|| memory.default_scan_options().clone()),
+            memory_cap: options.memory_cap.unwrap_or(DEFAULT_MEMORY_CAP),
+            spill: Spill::new(options.spill_dir.clone().unwrap_or_else(std::env::temp_dir)),
+            regions: Vec::new(),
+        })
+    }
+
+    pub fn process_id(&self) -> u32 {
+        self.process_id
+    }
+
+    pub fn filter(&self) -> &RegionFilter {
+        &self.filter
+    }
+
+    // (slot, pointee) for every pointer whose value lies in `range`, by slot.
+    pub fn pointers_to(&self, range: Range<usize>) -> io::Result<Vec<(usize, usize)>> {
+        let range = range.start as u64..range.end as u64;
+        let mut found = Vec::new();
+        for region in &self.regions {
+            region.for_each_part(&self.spill, |index| index.pointing_into(&range, &mut found))?;
+        }
+        found.sort_unstable();
+        Ok(found)
+    }
+
+    // (slot, pointee) for every pointer stored in `range`, by slot.
+    pub fn pointers_from(&self, range: Range<usize>) -> io::Result<Vec<(usize, usize)>> {
+        let first = self.regions.partition_point(|region| region.range.end <= range.start);
+        let overlapping = self.regions[first..].iter().take_while(|region| region.range.start < range.end);
+        let range = range.start as u64..range.end as u64;
+        let mut found = Vec::new();
+        for region in overlapping {
+            region.for_each_part(&self.spill, |index| index.stored_in(&range, &mut found))?;
+        }
+        Ok(found)
+    }
+
+    pub fn usage(&self) -> PointerMapUsage {
+        let mut usage = PointerMapUsage {
+            regions: self.regions.len(),
+            memory_cap: self.memory_cap,
+            ..PointerMapUsage::default()
+        };
+        for region in &self.regions {
+            usage.pointers += region.pointers;
+            match region.index {
+                Stored::Memory(_) => usage.bytes_in_memory += region.bytes(),
+                Stored::Spilled(_) => {
+                    usage.spilled_regions += 1;
+                    usage.bytes_spilled += region.bytes();
+                }
+            }
+        }
+        usage
+    }
+
+    // Spills regions at once if the map now holds more than `bytes`. Spilled regions stay on
+    // disk when the cap is raised again.
+    pub fn set_memory_cap(&mut self, bytes: usize) -> io::Result<()> {
+        self.memory_cap = bytes;
+        let in_memory = self.usage().bytes_in_memory;
+        enforce_cap(&mut self.regions, in_memory, self.memory_cap, &mut self.spill).map(drop)
+    }
+
+    // Sweeps the regions the cache has seen since they were swept, and the ones new to it,
+    // and forgets the ones gone from it. See above.
+    pub fn refresh(&mut self, memory: &Memory) -> io::Result<PointerMapRefresh> {
+        if memory.process_id() != self.process_id {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The pointer map is of process {}, not of process {}",
+                    self.process_id,
+                    memory.process_id()
+                ),
+            ));
+        }
+
+        let mut refresh = PointerMapRefresh::default();
+        // Whole slots per chunk, so chunk boundaries never split a pointer.
+        let chunk_size = self.options.chunk_len() / self.pointer_size * self.pointer_size;
+        let mut buffer = memory.buffer_pool().rent_zeroed(chunk_size);
+        let excluded = memory.excluded_ranges(!self.options.include_self_artifacts);
+        let mut old = std::mem::take(&mut self.regions).into_iter().peekable();
+        let mut regions = Vec::new();
+        let mut in_memory = 0;
+
+        for region in scan::scan_regions(memory, &self.options, &self.filter).iter() {
+            if self.filter.clip(region).is_none() {
+                continue;
+            }
+            if self.options.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+                refresh.cancelled = true;
+                break;
+            }
+            let start = region.start_address.get();
+            while let Some(gone) = old.next_if(|swept| swept.range.start < start) {
+                self.spill.discard(gone);
+                refresh.dropped += 1;
+            }
+
+            let generation = memory.region_generation_at(start).unwrap_or(0);
+            match old.next_if(|swept| swept.range.start == start) {
+                Some(swept) if swept.is_current(region, generation) => {
+                    if matches!(swept.index, Stored::Memory(_)) {
+                        in_memory += swept.bytes();
+                    }
+                    regions.push(swept);
+                    refresh.kept += 1;
+                    continue;
+                }
+                Some(swept) => {
+                    self.spill.discard(swept);
+                    refresh.reswept += 1;
+                }
+                None => refresh.added += 1,
+            }
+            if self.options.validate_regions && scan::validate_region(memory, region).is_err() {
+                continue;
+            }
+
+            let swept = self.sweep(memory, region, generation, &excluded, &mut buffer, in_memory)?;
+            if matches!(swept.index, Stored::Memory(_)) {
+                in_memory += swept.bytes();
+            }
+            regions.push(swept);
+            if in_memory > self.memory_cap {
+                in_memory = enforce_cap(&mut regions, in_memory, self.memory_cap, &mut self.spill)?;
+            }
+        }
+
+        // What a cancelled refresh didn't get to stays as it was.
+        match refresh.cancelled {
+            true => {
+                regions.extend(old);
+                regions.sort_by_key(|region| region.range.start);
+            }
+            false => {
+                for gone in old {
+                    self.spill.discard(gone);
+                    refresh.dropped += 1;
+                }
+            }
+        }
+        self.regions = regions;
+        debug!(
+            "pointer map refreshed: {} kept, {} swept again, {} added, {} dropped",
+            refresh.kept,
+            refresh.reswept,
+            refresh.added,
+            refresh.dropped
+        );
+        Ok(refresh)
+    }
+
+    // Sweeps one region with `in_memory` bytes of the map already in memory. Whenever the
+    // pointers found would take the map past its cap, they are spilled as a part of the region
+    // and the sweep goes on, so the region is never held whole.
+    fn sweep(
+        &mut self,
+        memory: &Memory,
+        region: &MemoryRegion,
+        generation: u64,
+        excluded: &[Range<usize>],
+        buffer: &mut [u8],
+        in_memory: usize,
+    ) -> io::Result<SweptRegion> {
+        let part_len = self.memory_cap.saturating_sub(in_memory).max(MIN_SPILL_PART) / BYTES_PER_POINTER;
+        let mut pairs = Vec::new();
+        let mut parts = Vec::new();
+        let mut pointers = 0;
+        let mut failed = None;
+        if let Some(range) = self.filter.clip(region) {
+            for piece in artifacts::subtract(range, excluded) {
+                memory.sweep_slots(piece, buffer, self.pointer_size, |slot, value| {
+                    if failed.is_some() || value == 0 || memory.region_at(value).is_none() {
+                        return;
+                    }
+                    pairs.push((value as u64, slot as u64));
+                    if pairs.len() >= part_len {
+                        pointers += pairs.len();
+                        match self.spill.write(&Index::new(std::mem::take(&mut pairs))) {
+                            Ok(path) => parts.push(path),
+                            Err(e) => failed = Some(e),
+                        }
+                    }
+                });
+            }
+        }
+
+        pointers += pairs.len();
+        let index = if parts.is_empty() {
+            Stored::Memory(Index::new(pairs))
+        } else {
+            if failed.is_none() && !pairs.is_empty() {
+                match self.spill.write(&Index::new(pairs)) {
+                    Ok(path) => parts.push(path),
+                    Err(e) => failed = Some(e),
+                }
+            }
+            Stored::Spilled(parts)
+        };
+        let swept = SweptRegion { range: region.range(), protection: region.protection, generation, pointers, index };
+        if let Some(e) = failed {
+            // The parts spilled so far go with the region.
+            self.spill.discard(swept);
+            return Err(e);
+        }
+        if let Stored::Spilled(parts) = &swept.index {
+            debug!(
+                "spilled pointer map region 0x{:X} ({} pointers) in {} parts while sweeping it",
+                swept.range.start,
+                pointers,
+                parts.len()
+            );
+        }
+        Ok(swept)
+    }
+
+    // Writes the whole map, spilled regions included, to one file, whole or not at all (see
+    // atomic_file.rs).
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        write_atomically(path, |writer| {
+            persist::write_header(writer, &FORMAT, None)?;
+            write_u32(writer, self.process_id)?;
+            write_u32(writer, self.pointer_size as u32)?;
+            write_filter(writer, &self.filter)?;
+            write_u64(writer, self.regions.len() as u64)?;
+            for region in &self.regions {
+                write_u64(writer, region.range.start as u64)?;
+                write_u64(writer, region.range.end as u64)?;
+                write_u32(writer, region.protection)?;
+                region.write_index(&self.spill, &mut *writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    // Reads a map `save` wrote from a handle to the same process, keeping the regions that
+    // are still cached as they were. `options` applies as for `build_with`.
+    pub fn load(path: impl AsRef<Path>, memory: &Memory, options: &PointerMapOptions) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        // An unsupported version keeps its payload; anything else gets the path.
+        persist::read_header(&mut reader, &FORMAT).map_err(|e| match persist::unsupported_version(&e) {
+            Some(_) => e,
+            None => io::Error::new(e.kind(), format!("{}: {}", path.display(), e)),
+        })?;
+        let process_id = read_u32(&mut reader)?;
+        let pointer_size = read_u32(&mut reader)? as usize;
+        if process_id != memory.process_id() || pointer_size != memory.pointer_size()? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} holds the pointers of process {}, not of process {}",
+                    path.display(),
+                    process_id,
+                    memory.process_id()
+                ),
+            ));
+        }
+
+        let mut map = PointerMap::empty(memory, read_filter(&mut reader)?, options)?;
+        let count = read_u64(&mut reader)?;
+        let mut in_memory = 0;
+        let mut dropped = 0;
+        for _ in 0..count {
+            let range = read_u64(&mut reader)? as usize..read_u64(&mut reader)? as usize;
+            let protection = read_u32(&mut reader)?;
+            let index = Index::read(&mut reader)?;
+            let current = memory.region_at(range.start).filter(|region| {
+                region.range() == range && region.protection == protection && map.filter.matches(region)
+            });
+            if current.is_none() {
+                dropped += 1;
+                continue;
+            }
+            let region = SweptRegion {
+                generation: memory.region_generation_at(range.start).unwrap_or(0),
+                range,
+                protection,
+                pointers: index.pointees.len(),
+                index: Stored::Memory(index),
+            };
+            in_memory += region.bytes();
+            map.regions.push(region);
+            if in_memory > map.memory_cap {
+                in_memory = enforce_cap(&mut map.regions, in_memory, map.memory_cap, &mut map.spill)?;
+            }
+        }
+        map.regions.sort_by_key(|region| region.range.start);
+        debug!("pointer map loaded from {}: {}, {} regions gone", path.display(), map.usage(), dropped);
+        Ok(map)
+    }
+}
+
+#[derive(Debug)]
+struct SweptRegion {
+    // The whole region, and its protection, when swept.
+    range: Range<usize>,
+    protection: u32,
+    // The cache generation that had last seen the region then.
+    generation: u64,
+    pointers: usize,
+    index: Stored,
+}
+
+impl SweptRegion {
+    fn is_current(&self, region: &MemoryRegion, generation: u64) -> bool {
+        self.range == region.range() && self.protection == region.protection && self.generation == generation
+    }
+
+    fn bytes(&self) -> usize {
+        self.pointers * BYTES_PER_POINTER
+    }
+
+    // Calls `f` with the index, or with each spilled part of it in slot order.
+    fn for_each_part(&self, spill: &Spill, mut f: impl FnMut(&Index)) -> io::Result<()> {
+        match &self.index {
+            Stored::Memory(index) => f(index),
+            Stored::Spilled(parts) => {
+                for path in parts {
+                    f(&spill.read(path)?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes the pointers as one index, the spilled parts put back together.
+    fn write_index(&self, spill: &Spill, writer: &mut impl Write) -> io::Result<()> {
+        match &self.index {
+            Stored::Memory(index) => index.write(writer),
+            Stored::Spilled(parts) if parts.len() == 1 => spill.read(&parts[0])?.write(writer),
+            Stored::Spilled(parts) => {
+                let mut pairs = Vec::with_capacity(self.pointers);
+                for path in parts {
+                    let part = spill.read(path)?;
+                    pairs.extend(part.pointees.into_iter().zip(part.slots));
+                }
+                Index::new(pairs).write(writer)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Stored {
+    Memory(Index),
+    // One file, or parts in slot order for a region spilled while it was swept.
+    Spilled(Vec<PathBuf>),
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    // Sorted by pointee, then slot.
+    pointees: Vec<u64>,
+    slots: Vec<u64>,
+    // Positions in the two above, in slot order.
+    by_slot: Vec<u32>,
+}
+
+impl Index {
+    // From (pointee, slot) pairs.
+    fn new(mut pairs: Vec<(u64, u64)>) -> Self {
+        pairs.sort_unstable();
+        let (pointees, slots): (Vec<u64>, Vec<u64>) = pairs.into_iter().unzip();
+        let mut by_slot: Vec<u32> = (0..slots.len() as u32).collect();
+        by_slot.sort_unstable_by_key(|&position| slots[position as usize]);
+        Index { pointees, slots, by_slot }
+    }
+
+    fn pointing_into(&self, range: &Range<u64>, found: &mut Vec<(usize, usize)>) {
+        let first = self.pointees.partition_point(|&pointee| pointee < range.start);
+        let last = self.pointees.partition_point(|&pointee| pointee < range.end).max(first);
+        found.extend((first..last).map(|position| self.pair(position)));
+    }
+
+    fn stored_in(&self, range: &Range<u64>, found: &mut Vec<(usize, usize)>) {
+        let slot = |position: &u32| self.slots[*position as usize];
+        let first = self.by_slot.partition_point(|position| slot(position) < range.start);
+        let last = self.by_slot.partition_point(|position| slot(position) < range.end).max(first);
+        found.extend(self.by_slot[first..last].iter().map(|&position| self.pair(position as usize)));
+    }
+
+    fn pair(&self, position: usize) -> (usize, usize) {
+        (self.slots[position] as usize, self.pointees[position] as usize)
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        write_u64(writer, self.pointees.len() as u64)?;
+        for &pointee in &self.pointees {
+            write_u64(writer, pointee)?;
+        }
+        for &slot in &self.slots {
+            write_u64(writer, slot)?;
+        }
+        for &position in &self.by_slot {
+            write_u32(writer, position)?;
+        }
+        Ok(())
+    }
+
+    fn read(reader: &mut impl Read) -> io::Result<Self> {
+        let len = read_u64(reader)? as usize;
+        // A corrupt length fails at the end of the file rather than allocating all of it.
+        let capacity = len.min(1 << 20);
+        let mut index = Index {
+            pointees: Vec::with_capacity(capacity),
+            slots: Vec::with_capacity(capacity),
+            by_slot: Vec::with_capacity(capacity),
+        };
+        for _ in 0..len {
+            index.pointees.push(read_u64(reader)?);
+        }
+        for _ in 0..len {
+            index.slots.push(read_u64(reader)?);
+        }
+        for _ in 0..len {
+            index.by_slot.push(read_u32(reader)?);
+        }
+        if !index.pointees.is_sorted() || index.by_slot.iter().any(|&position| position as usize >= len) {
+            return Err(invalid_data("pointer map index is corrupt".to_string()));
+        }
+        Ok(index)
+    }
+}
+
+// The map's own directory of spilled regions, made when the first one is spilled.
+#[derive(Debug)]
+struct Spill {
+    parent: PathBuf,
+    dir: Option<PathBuf>,
+    next_file: u64,
+}
+
+impl Spill {
+    fn new(parent: PathBuf) -> Self {
+        Spill { parent, dir: None, next_file: 0 }
+    }
+
+    fn write(&mut self, index: &Index) -> io::Result<PathBuf> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = self.parent.join(format!(
+                    "pointer-map-{}-{}",
+                    std::process::id(),
+                    NEXT_SPILL_DIR.fetch_add(1, Ordering::Relaxed)
+                ));
+                fs::create_dir_all(&dir)?;
+                self.dir.insert(dir).clone()
+            }
+        };
+        let path = dir.join(format!("{}.bin", self.next_file));
+        self.next_file += 1;
+        let mut writer = BufWriter::new(File::create(&path)?);
+        index.write(&mut writer)?;
+        writer.flush()?;
+        Ok(path)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Index> {
+        Index::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    fn discard(&self, region: SweptRegion) {
+        if let Stored::Spilled(parts) = region.index {
+            for path in parts {
+                if let Err(e) = fs::remove_file(&path) {
+                    warn!("failed to remove spilled pointer map region {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            if let Err(e) = fs::remove_dir_all(dir) {
+                warn!("failed to remove pointer map spill directory {}: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+// Spills the biggest regions still in memory until what's left of the `in_memory` bytes
+// fits into `cap`, and returns what's left.
+fn enforce_cap(regions: &mut [SweptRegion], mut in_memory: usize, cap: usize, spill: &mut Spill) -> io::Result<usize> {
+    while in_memory > cap {
+        let biggest = regions
+            .iter_mut()
+            .filter(|region| matches!(region.index, Stored::Memory(_)) && region.pointers > 0)
+            .max_by_key(|region| region.pointers);
+        let Some(region) = biggest else {
+            break;
+        };
+        let Stored::Memory(index) = &region.index else { unreachable!() };
+        let path = spill.write(index)?;
+        debug!(
+            "spilled pointer map region 0x{:X} ({} pointers) to {}",
+            region.range.start,
+            region.pointers,
+            path.display()
+        );
+        in_memory -= region.bytes();
+        region.index = Stored::Spilled(vec![path]);
+    }
+    Ok(in_memory)
+}
+
+// Each optional field as a presence byte, then its value.
+fn write_filter(writer: &mut impl Write, filter: &RegionFilter) -> io::Result<()> {
+    for flag in [filter.writable, filter.executable] {
+        writer.write_all(&[match flag {
+            None => 0,
+            Some(false) => 1,
+            Some(true) => 2,
+        }])?;
+    }
+    match &filter.range {
+        Some(range) => {
+            writer.write_all(&[1])?;
+            write_u64(writer, range.start as u64)?;
+            write_u64(writer, range.end as u64)?;
+        }
+        None => writer.write_all(&[0])?,
+    }
+    match filter.max_region_size {
+        Some(size) => {
+            writer.write_all(&[1])?;
+            write_u64(writer, size as u64)
+        }
+        None => writer.write_all(&[0]),
+    }
+}
+
+fn read_filter(reader: &mut impl Read) -> io::Result<RegionFilter> {
+    let mut flags = [0; 3];
+    reader.read_exact(&mut flags)?;
+    let flag = |byte| match byte {
+        0 => Ok(None),
+        1 => Ok(Some(false)),
+        2 => Ok(Some(true)),
+        _ => Err(invalid_data("pointer map filter is corrupt".to_string())),
+    };
+    let range = match flags[2] {
+        0 => None,
+        _ => Some(read_u64(reader)? as usize..read_u64(reader)? as usize),
+    };
+    let mut has_max = [0];
+    reader.read_exact(&mut has_max)?;
+    let max_region_size = match has_max[0] {
+        0 => None,
+        _ => Some(read_u64(reader)? as usize),
+    };
+    Ok(RegionFilter { writable: flag(flags[0])?, executable: flag(flags[1])?, range, max_region_size })
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
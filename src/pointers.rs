@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+use std::io;
+use std::ops::Range;
+
+use log::debug;
+
+use crate::artifacts;
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::pointer_map::PointerMap;
+use crate::remote_ptr::RemotePtr;
+use crate::scan::{self, CancelToken, ScanOptions};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PointerHit {
+    // Address of the slot holding the pointer.
+    pub slot: usize,
+    // The pointer stored there.
+    pub value: usize,
+    // `Memory::symbolize` of the slot, e.g. "game.exe!.data+0x1F0", when it lies in a cached module image.
+    pub location: Option<String>,
+    // 0 for slots pointing into the requested range, 1 for slots pointing into the region
+    // holding a level-0 slot, and so on.
+    pub level: usize,
+}
+
+impl PointerHit {
+    // The slot itself, whose `deref` rereads the pointer.
+    pub fn slot_ptr(&self) -> RemotePtr<RemotePtr<u8>> {
+        RemotePtr::new(self.slot)
+    }
+
+    pub fn target(&self) -> RemotePtr<u8> {
+        RemotePtr::new(self.value)
+    }
+}
+
+impl Memory {
+    // Pointer-aligned slots whose value falls inside `range`. Only writable regions accepted
+    // by `filter` are swept, since that is where object fields live. Needs `scan_memory`.
+    pub fn find_pointers_to_range(&self, range: Range<usize>, filter: &RegionFilter) -> io::Result<Vec<PointerHit>> {
+        self.find_pointer_chains(range, filter, 0)
+    }
+
+    pub fn find_pointers_to_range_with(
+        &self,
+        range: Range<usize>,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<Vec<PointerHit>> {
+        self.find_pointer_chains_with(range, filter, 0, options)
+    }
+
+    // Like `find_pointers_to_range`, then walks ownership upwards `levels` more times: each
+    // level looks for pointers into the regions containing the previous level's slots. The
+    // containing region is a whole heap segment rather than the individual heap block, so
+    // higher levels quickly get broad.
+    pub fn find_pointer_chains(
+        &self,
+        range: Range<usize>,
+        filter: &RegionFilter,
+        levels: usize,
+    ) -> io::Result<Vec<PointerHit>> {
+        self.find_pointer_chains_with(range, filter, levels, self.default_scan_options())
+    }
+
+    // Honors the region selection options, `chunk_size` and `cancel`; a cancelled sweep
+    // returns the hits found so far. Slots are always pointer-aligned.
+    pub fn find_pointer_chains_with(
+        &self,
+        range: Range<usize>,
+        filter: &RegionFilter,
+        levels: usize,
+        options: &ScanOptions,
+    ) -> io::Result<Vec<PointerHit>> {
+        let pointer_size = self.pointer_size()?;
+        let filter = filter.clone().writable(true);
+        self.walk_chains(range, levels, |targets, level| {
+            Ok(self.sweep_pointers(targets, &filter, options, pointer_size, level))
+        })
+    }
+
+    // `find_pointers_to_range` answered from `map`, which stands in for the sweep and its
+    // filter. Pointers into uncommitted parts of `range` aren't in the map.
+    pub fn find_pointers_to_range_in(&self, map: &PointerMap, range: Range<usize>) -> io::Result<Vec<PointerHit>> {
+        self.find_pointer_chains_in(map, range, 0)
+    }
+
+    // `find_pointer_chains` answered from `map`: each level is a lookup per target instead of
+    // a sweep.
+    pub fn find_pointer_chains_in(
+        &self,
+        map: &PointerMap,
+        range: Range<usize>,
+        levels: usize,
+    ) -> io::Result<Vec<PointerHit>> {
+        self.walk_chains(range, levels, |targets, level| {
+            let mut found = Vec::new();
+            for target in targets {
+                found.extend(map.pointers_to(target.clone())?);
+            }
+            found.sort_unstable();
+            found.dedup();
+            Ok(found
+                .into_iter()
+                .map(|(slot, value)| PointerHit { slot, value, location: self.symbolize(slot), level })
+                .collect())
+        })
+    }
+
+    // Level 0 is what points into `range`; each further level is what points into the
+    // regions holding the previous level's slots, each region visited once.
+    fn walk_chains(
+        &self,
+        range: Range<usize>,
+        levels: usize,
+        mut find: impl FnMut(&[Range<usize>], usize) -> io::Result<Vec<PointerHit>>,
+    ) -> io::Result<Vec<PointerHit>> {
+        let mut hits = Vec::new();
+        let mut targets = vec![range];
+        let mut visited = HashSet::new();
+        for level in 0..=levels {
+            let found = find(&targets, level)?;
+            debug!("pointer sweep level {}: {} hits", level, found.len());
+
+            targets = found
+                .iter()
+                .filter_map(|hit| self.region_at(hit.slot))
+                .filter(|region| visited.insert(region.start_address.get()))
+                .map(|region| region.range())
+                .collect();
+            hits.extend(found);
+            if targets.is_empty() {
+                break;
+            }
+        }
+
+        Ok(hits)
+    }
+
+    // One pass over the writable memory the filter allows; unreadable chunks are skipped.
+    fn sweep_pointers(
+        &self,
+        targets: &[Range<usize>],
+        filter: &RegionFilter,
+        options: &ScanOptions,
+        pointer_size: usize,
+        level: usize,
+    ) -> Vec<PointerHit> {
+        let mut hits = Vec::new();
+        // Whole slots per chunk, so chunk boundaries never split a pointer.
+        let chunk_size = options.chunk_len() / pointer_size * pointer_size;
+        let mut buffer = self.buffer_pool().rent_zeroed(chunk_size);
+        let excluded = self.excluded_ranges(!options.include_self_artifacts);
+
+        for region in scan::scan_regions(self, options, filter).iter() {
+            let Some(range) = filter.clip(region) else { continue };
+            if options.validate_regions && scan::validate_region(self, region).is_err() {
+                continue;
+            }
+            for piece in artifacts::subtract(range, &excluded) {
+                if options.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+                    return hits;
+                }
+                self.sweep_piece(piece, targets, &mut buffer, pointer_size, level, &mut hits);
+            }
+        }
+
+        hits
+    }
+
+    fn sweep_piece(
+        &self,
+        range: Range<usize>,
+        targets: &[Range<usize>],
+        buffer: &mut [u8],
+        pointer_size: usize,
+        level: usize,
+        hits: &mut Vec<PointerHit>,
+    ) {
+        self.sweep_slots(range, buffer, pointer_size, |slot, value| {
+            if targets.iter().any(|target| target.contains(&value)) {
+                hits.push(PointerHit { slot, value, location: self.symbolize(slot), level });
+            }
+        });
+    }
+
+    // Calls `visit` with (slot, value) for each pointer-aligned slot in `range`, a buffer at
+    // a time, up to the first chunk that can't be read in full. Shared with pointer_map.rs.
+    pub(crate) fn sweep_slots(
+        &self,
+        range: Range<usize>,
+        buffer: &mut [u8],
+        pointer_size: usize,
+        mut visit: impl FnMut(usize, usize),
+    ) {
+        let mut address = range.start.next_multiple_of(pointer_size);
+
+        while address < range.end {
+            let len = buffer.len().min(range.end - address);
+            let read = match self.read_bulk(address, &mut buffer[..len]) {
+                Ok(read) => read,
+                Err(e) => {
+                    debug!("skipping unreadable chunk at 0x{:X}: {}", address, e);
+                    break;
+                }
+            };
+
+            for (index, slot) in buffer[..read].chunks_exact(pointer_size).enumerate() {
+                let value = if pointer_size == 4 {
+                    u32::from_le_bytes(slot.try_into().unwrap()) as usize
+                } else {
+                    u64::from_le_bytes(slot.try_into().unwrap()) as usize
+                };
+                visit(address + index * pointer_size, value);
+            }
+
+            if read < len {
+                break;
+            }
+            address += len;
+        }
+    }
+}
@@ -0,0 +1,204 @@
+// Scans with a caller-supplied match test, for what the built-in scans don't cover:
+//
+//     // i32 values that are a multiple of 60
+//     let hits = memory.scan_with(
+//         |window, _| i32::from_ne_bytes(window.try_into().unwrap()) % 60 == 0,
+//         4,
+//         &RegionFilter::new().writable(true),
+//         &ScanOptions::new(),
+//     )?;
+//
+// The predicate sees each `width`-byte window at an aligned address (`width`-aligned unless
+// `ScanOptions::alignment` says otherwise) together with that address. Windows are borrowed
+// from the scan's read buffer, so nothing is allocated per window.
+
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::io;
+use std::mem;
+use std::ops::Range;
+use std::ptr;
+
+use crate::artifacts;
+use crate::chunks::Chunks;
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::pod::Pod;
+use crate::scan::{self, CancelToken, ScanOptions, ScanReport, ScanTally};
+use crate::wstr;
+
+impl Memory {
+    // Addresses of the windows `predicate` accepts, in ascending order. `Sync` so the same
+    // predicate can be shared by scans running on several threads.
+    pub fn scan_with<P>(
+        &self,
+        predicate: P,
+        width: usize,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<Vec<usize>>
+    where
+        P: Fn(&[u8], usize) -> bool + Sync,
+    {
+        Ok(self.scan_with_report(predicate, width, filter, options)?.matches)
+    }
+
+    // `scan_with` with what the scan covered, as `find_pattern_report` has it.
+    pub fn scan_with_report<P>(
+        &self,
+        predicate: P,
+        width: usize,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<ScanReport>
+    where
+        P: Fn(&[u8], usize) -> bool + Sync,
+    {
+        if width == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The window width must not be zero"));
+        }
+        Ok(self.scan_windows(width, filter, options, predicate))
+    }
+
+    // The scanning core shared with the float scan: reads each selected region a chunk at a
+    // time, with `width - 1` bytes of overlap so windows across chunk boundaries are whole.
+    // Unreadable pages inside a region are stepped over, and the scan goes on after them.
+    pub(crate) fn scan_windows(
+        &self,
+        width: usize,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+        mut predicate: impl FnMut(&[u8], usize) -> bool,
+    ) -> ScanReport {
+        let alignment = options.alignment.unwrap_or(width);
+        let excluded = self.excluded_ranges(!options.include_self_artifacts);
+        let chunk_size = options.chunk_len();
+        let mut buffer = self.buffer_pool().rent_zeroed(chunk_size + width - 1);
+        let mut matches = Vec::new();
+        let regions = scan::scan_regions(self, options, filter);
+        let mut tally = ScanTally::new(regions.len()).with_sink(options.sink.clone());
+
+        for region in regions.iter() {
+            let Some(range) = filter.clip(region) else {
+                tally.skip(region, filter.skip_reason(region));
+                continue;
+            };
+            if options.validate_regions {
+                if let Err(stale) = scan::validate_region(self, region) {
+                    tally.stale(stale);
+                    continue;
+                }
+            }
+            tally.enter(region);
+            for piece in artifacts::subtract(range, &excluded) {
+                let mut chunks = Chunks::new(piece, chunk_size, width - 1, alignment).skip_holes();
+                loop {
+                    if options.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+                        self.track_results(&matches);
+                        return tally.finish(matches);
+                    }
+                    let Some(chunk) = chunks.read_next(self, &mut buffer) else {
+                        break;
+                    };
+                    // The overlap is read again with the next chunk; count it there.
+                    tally.read(chunk.read.min(chunk.owned_end - chunk.address));
+                    tally.unreadable(chunk.unreadable);
+
+                    let mut address = chunk.address;
+                    while address < chunk.owned_end && address - chunk.address + width <= chunk.read {
+                        let offset = address - chunk.address;
+                        if predicate(&buffer[offset..offset + width], address) {
+                            matches.push(address);
+                            if options.report_match(address).is_break() {
+                                self.track_results(&matches);
+                                return tally.finish(matches);
+                            }
+                        }
+                        address += alignment;
+                    }
+                }
+            }
+        }
+
+        matches.sort_unstable();
+        matches.dedup();
+        self.track_results(&matches);
+        tally.finish(matches)
+    }
+
+    // (slot, pointee) for every pointer-aligned slot in the filtered regions that points into
+    // `module`'s image, e.g. the vtable pointers of heap objects whose class the module
+    // defines. Slots are as wide as the target's pointers. `count_by_pointee` ranks the targets.
+    pub fn find_pointers_into_module(&self, module: &str, filter: &RegionFilter) -> io::Result<Vec<(usize, usize)>> {
+        let modules = self.modules_or_live()?;
+        let module = modules
+            .iter()
+            .find(|loaded| wstr::eq_ignore_case(&loaded.name, module))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Module '{}' is not loaded", module)))?;
+        let image = module.base_address..module.base_address + module.size;
+        let pointer_size = self.pointer_size()?;
+
+        let is_pointer = pointer_into(image, pointer_size);
+        let mut hits = Vec::new();
+        let options = self.default_scan_options().clone().alignment(pointer_size);
+        self.scan_windows(pointer_size, filter, &options, |window, address| {
+            if !is_pointer(window, address) {
+                return false;
+            }
+            hits.push((address, read_pointer(window)));
+            true
+        });
+
+        hits.sort_unstable();
+        hits.dedup();
+        Ok(hits)
+    }
+}
+
+// A 4- or 8-byte window as a pointer.
+fn read_pointer(window: &[u8]) -> usize {
+    match window.len() {
+        4 => u32::from_ne_bytes(window.try_into().unwrap()) as usize,
+        _ => u64::from_ne_bytes(window[..8].try_into().unwrap()) as usize,
+    }
+}
+
+// (pointee, slots pointing at it) from `find_pointers_into_module` hits, most pointed-at
+// first: the popular targets of a module are usually vtables.
+pub fn count_by_pointee(hits: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    for &(_, pointee) in hits {
+        *counts.entry(pointee).or_default() += 1;
+    }
+    let mut counts: Vec<(usize, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    counts
+}
+
+// Pointer-sized windows (`pointer_size` 4 or 8) whose value lies in `range`, e.g. pointers
+// into one module: `pointer_into(module.base_address..module.base_address + module.size, 8)`.
+pub fn pointer_into(range: Range<usize>, pointer_size: usize) -> impl Fn(&[u8], usize) -> bool + Sync {
+    move |window, _| {
+        let value = match pointer_size {
+            4 => window.get(..4).map(|bytes| u32::from_ne_bytes(bytes.try_into().unwrap()) as usize),
+            _ => window.get(..8).map(|bytes| u64::from_ne_bytes(bytes.try_into().unwrap()) as usize),
+        };
+        value.is_some_and(|value| range.contains(&value))
+    }
+}
+
+// Windows that start with one of `values`; scan with a width of `size_of::<T>()`.
+pub fn value_in_set<T>(values: impl IntoIterator<Item = T>) -> impl Fn(&[u8], usize) -> bool + Sync
+where
+    T: Pod + Eq + Hash + Sync,
+{
+    let values: HashSet<T> = values.into_iter().collect();
+    move |window, _| {
+        if window.len() < mem::size_of::<T>() {
+            return false;
+        }
+        // Pod: any bytes of the right length are a valid T.
+        let value = unsafe { ptr::read_unaligned(window.as_ptr() as *const T) };
+        values.contains(&value)
+    }
+}
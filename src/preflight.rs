@@ -0,0 +1,270 @@
+// Which of the library's features will work against a target, asked all at once before
+// relying on any of them. An embedder can grey out what won't work instead of finding out
+// from the first failing call:
+//
+//     let report = preflight::preflight(pid, &[Feature::Read, Feature::PatternScan, Feature::Inject]);
+//     for (feature, readiness) in &report.features {
+//         println!("{}: {}", feature.name(), readiness);
+//     }
+//     if report.get(Feature::Write).is_some_and(Readiness::is_usable) { ... }
+//
+// Each feature comes back available, degraded (it works, but less well, and the reason says
+// how) or unavailable (with what blocks it, and whether running elevated would get past
+// that). The answers come from opening the process the way `Memory::open` does, falling
+// back to a read-only handle, and probing its `Capabilities`; what can't be done is
+// explained by `diagnostics::why_cant_open`. Every handle opened on the way is closed again
+// before `preflight` returns.
+
+use std::fmt;
+use std::io;
+
+use crate::capabilities::Capabilities;
+use crate::diagnostics::{self, DebugPrivilege, OpenDiagnosis};
+use crate::memory::Memory;
+#[cfg(feature = "debugger")]
+use crate::sys::{CloseHandle, OpenProcess};
+
+// What DebugActiveProcess wants of the debugger's access to the target.
+#[cfg(feature = "debugger")]
+const PROCESS_ALL_ACCESS: u32 = 0x001F_FFFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Read,
+    Write,
+    PatternScan,
+    // Remote threads, remote calls and manual mapping.
+    Inject,
+    // Attaching as a debugger.
+    Debug,
+    // Suspending threads to read their registers.
+    Suspend,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 6] =
+        [Feature::Read, Feature::Write, Feature::PatternScan, Feature::Inject, Feature::Debug, Feature::Suspend];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Read => "read",
+            Feature::Write => "write",
+            Feature::PatternScan => "pattern scan",
+            Feature::Inject => "inject",
+            Feature::Debug => "debug",
+            Feature::Suspend => "suspend",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Readiness {
+    Available,
+    Degraded { reason: String },
+    Unavailable { blocker: String, elevation_would_help: bool },
+}
+
+impl Readiness {
+    // Available or degraded.
+    pub fn is_usable(&self) -> bool {
+        !matches!(self, Readiness::Unavailable { .. })
+    }
+}
+
+// "available", "degraded: <reason>" or "unavailable: <blocker>".
+impl fmt::Display for Readiness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Readiness::Available => f.write_str("available"),
+            Readiness::Degraded { reason } => write!(f, "degraded: {}", reason),
+            Readiness::Unavailable { blocker, elevation_would_help } => {
+                write!(f, "unavailable: {}", blocker)?;
+                match elevation_would_help {
+                    true => f.write_str(" (running elevated may help)"),
+                    false => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreflightReport {
+    pub process_id: u32,
+    // What the best handle we got could do; empty if none could be opened.
+    pub capabilities: Capabilities,
+    // Whether that handle is read-only, because read and write access was refused.
+    pub read_only: bool,
+    // Why access was refused, when some was.
+    pub diagnosis: Option<OpenDiagnosis>,
+    // One per asked-for feature, in the order asked.
+    pub features: Vec<(Feature, Readiness)>,
+}
+
+impl PreflightReport {
+    pub fn get(&self, feature: Feature) -> Option<&Readiness> {
+        self.features.iter().find(|(asked, _)| *asked == feature).map(|(_, readiness)| readiness)
+    }
+
+    pub fn all_available(&self) -> bool {
+        self.features.iter().all(|(_, readiness)| *readiness == Readiness::Available)
+    }
+}
+
+// "process 812:", then a line per feature.
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "process {}:", self.process_id)?;
+        for (feature, readiness) in &self.features {
+            write!(f, "\n  {}: {}", feature.name(), readiness)?;
+        }
+        Ok(())
+    }
+}
+
+// Checks each of `features` against `process_id`. See above.
+pub fn preflight(process_id: u32, features: &[Feature]) -> PreflightReport {
+    let (memory, read_only) = match Memory::open(process_id) {
+        Ok(memory) => (Ok(memory), false),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => (Memory::open_read_only(process_id), true),
+        Err(e) => (Err(e), false),
+    };
+    let mut check = Check {
+        process_id,
+        capabilities: memory.as_ref().map(Memory::capabilities).unwrap_or_default(),
+        read_only: read_only && memory.is_ok(),
+        pointer_size: memory.as_ref().ok().and_then(|memory| memory.pointer_size().ok()),
+        diagnosis: None,
+    };
+    // Nothing further needs the handle.
+    drop(memory);
+
+    let features = features.iter().map(|&feature| (feature, check.feature(feature))).collect();
+    PreflightReport {
+        process_id,
+        capabilities: check.capabilities,
+        read_only: check.read_only,
+        diagnosis: check.diagnosis,
+        features,
+    }
+}
+
+struct Check {
+    process_id: u32,
+    capabilities: Capabilities,
+    read_only: bool,
+    pointer_size: Option<usize>,
+    // Made the first time something turns out unavailable.
+    diagnosis: Option<OpenDiagnosis>,
+}
+
+impl Check {
+    fn feature(&mut self, feature: Feature) -> Readiness {
+        match feature {
+            Feature::Read => match self.require(Capabilities::READ) {
+                Err(unavailable) => unavailable,
+                Ok(()) if !self.capabilities.can_query() => Readiness::Degraded {
+                    reason: "regions can't be queried, so region and module lists fail; reads at known addresses work"
+                        .to_string(),
+                },
+                Ok(()) => Readiness::Available,
+            },
+            Feature::Write => match self.require(Capabilities::WRITE) {
+                Err(unavailable) => unavailable,
+                Ok(()) if !self.capabilities.can_vm_operation() => Readiness::Degraded {
+                    reason: "page protection can't be changed, so writes to read-only pages fail".to_string(),
+                },
+                Ok(()) => Readiness::Available,
+            },
+            Feature::PatternScan => match self.require(Capabilities::READ) {
+                Err(unavailable) => unavailable,
+                Ok(()) if !self.capabilities.can_query() => Readiness::Degraded {
+                    reason: "regions are inferred by probing; scans run slower and see less".to_string(),
+                },
+                Ok(()) => Readiness::Available,
+            },
+            Feature::Inject => self.inject(),
+            Feature::Debug => self.debug(),
+            Feature::Suspend => match self.require(Capabilities::SUSPEND) {
+                Err(unavailable) => unavailable,
+                Ok(()) => Readiness::Available,
+            },
+        }
+    }
+
+    fn inject(&mut self) -> Readiness {
+        if !cfg!(feature = "inject") {
+            return not_built("inject");
+        }
+        let needed =
+            Capabilities::READ | Capabilities::WRITE | Capabilities::VM_OPERATION | Capabilities::CREATE_THREAD;
+        if let Err(unavailable) = self.require(needed) {
+            return unavailable;
+        }
+        match self.pointer_size {
+            Some(8) => Readiness::Available,
+            _ => Readiness::Degraded {
+                reason: "remote calls need a 64-bit target; remote threads and manual mapping still work".to_string(),
+            },
+        }
+    }
+
+    #[cfg(not(feature = "debugger"))]
+    fn debug(&mut self) -> Readiness {
+        not_built("debugger")
+    }
+
+    #[cfg(feature = "debugger")]
+    fn debug(&mut self) -> Readiness {
+        if self.process_id == std::process::id() {
+            return Readiness::Unavailable {
+                blocker: "a process can't debug itself".to_string(),
+                elevation_would_help: false,
+            };
+        }
+        let process = unsafe { OpenProcess(PROCESS_ALL_ACCESS, 0, self.process_id) };
+        if process.is_null() {
+            return self.unavailable("attaching a debugger needs full access to the process");
+        }
+        unsafe { CloseHandle(process) };
+        Readiness::Available
+    }
+
+    // Unavailable, saying why, unless the handle can do all of `needed`.
+    fn require(&mut self, needed: Capabilities) -> Result<(), Readiness> {
+        let missing = needed.missing_from(self.capabilities);
+        if missing == Capabilities::empty() {
+            return Ok(());
+        }
+        let what = match self.capabilities == Capabilities::empty() {
+            true => "the process can't be opened".to_string(),
+            false if self.read_only => format!("only a read-only handle was granted, without {}", missing),
+            false => format!("the handle lacks {}", missing),
+        };
+        Err(self.unavailable(&what))
+    }
+
+    fn unavailable(&mut self, what: &str) -> Readiness {
+        let process_id = self.process_id;
+        let diagnosis = self.diagnosis.get_or_insert_with(|| diagnostics::why_cant_open(process_id));
+        Readiness::Unavailable {
+            blocker: format!("{}: {}", what, diagnosis.explanation()),
+            elevation_would_help: elevation_would_help(diagnosis),
+        }
+    }
+}
+
+fn not_built(feature: &str) -> Readiness {
+    Readiness::Unavailable {
+        blocker: format!("built without the `{}` feature", feature),
+        elevation_would_help: false,
+    }
+}
+
+// Protected processes stay out of reach of elevated tools too, and a process that isn't
+// running can't be reached at all.
+fn elevation_would_help(diagnosis: &OpenDiagnosis) -> bool {
+    diagnosis.exists
+        && diagnosis.protection.is_none()
+        && (diagnosis.elevated == Some(false) || diagnosis.debug_privilege == Some(DebugPrivilege::NotHeld))
+}
@@ -0,0 +1,332 @@
+// A quick guess at what lives at an unknown address. `probe` reads a small window and lists
+// every interpretation the bytes allow, most likely first, each with the evidence behind it:
+//
+//     let probe = memory.probe(address)?;
+//     println!("{}", probe.summary());    // "pointer 0x1F2A0 (rw- PRV) 90%, ascii \"..\", ..."
+//     for guess in &probe.interpretations {
+//         println!("{:>3}% {}: {}", guess.confidence, guess.value, guess.evidence.join("; "));
+//     }
+//
+// The heuristics are plain functions over the bytes and a region map (`interpret`), so the
+// same guesses can be made for a saved dump. They are guesses: eight zero bytes are a null
+// pointer, 0.0 and an empty string alike, and only the confidence ranks them.
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::io;
+
+use crate::memory::{Memory, MemoryRegion};
+
+// Bytes read for a probe; strings are followed up to this length.
+pub const PROBE_WINDOW: usize = 64;
+
+// Printable runs shorter than this aren't offered as strings.
+const MIN_STRING_LEN: usize = 4;
+
+// Floats whose magnitude falls outside this range are unusual for game state and UI values.
+const PLAUSIBLE_FLOAT: (f64, f64) = (1e-4, 1e7);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Guess {
+    // Lands in `region`. `target` is 0 for a null pointer, with no region.
+    Pointer { target: usize, region: Option<MemoryRegion> },
+    F32(f32),
+    F64(f64),
+    Ascii(String),
+    Utf16(String),
+    // Four f32 lanes at a 16-byte boundary, as SSE code keeps positions and colors.
+    F32x4([f32; 4]),
+    // Nothing better fits.
+    Bytes(Vec<u8>),
+}
+
+// "pointer 0x1F2A0", "f32 12.5", "ascii \"player\"", "f32x4 [1, 0, 0, 1]", "bytes 00 01 .."
+impl fmt::Display for Guess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Guess::Pointer { target, .. } => write!(f, "pointer 0x{:X}", target),
+            Guess::F32(value) => write!(f, "f32 {}", value),
+            Guess::F64(value) => write!(f, "f64 {}", value),
+            Guess::Ascii(text) => write!(f, "ascii {:?}", text),
+            Guess::Utf16(text) => write!(f, "utf-16 {:?}", text),
+            Guess::F32x4([a, b, c, d]) => write!(f, "f32x4 [{}, {}, {}, {}]", a, b, c, d),
+            Guess::Bytes(bytes) => {
+                f.write_str("bytes")?;
+                for byte in bytes.iter().take(8) {
+                    write!(f, " {:02X}", byte)?;
+                }
+                if bytes.len() > 8 {
+                    f.write_str(" ..")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interpretation {
+    pub value: Guess,
+    // 0 to 100; only meaningful relative to the other interpretations of the same bytes.
+    pub confidence: u8,
+    // Why the bytes fit, e.g. "lands in r-x IMG region 0x7FF6A0000000-0x7FF6A0153000".
+    pub evidence: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProbeResult {
+    pub address: usize,
+    // What could be read, up to PROBE_WINDOW bytes; shorter at the end of a region.
+    pub bytes: Vec<u8>,
+    // Most likely first. Never empty: raw bytes always fit.
+    pub interpretations: Vec<Interpretation>,
+}
+
+impl ProbeResult {
+    pub fn best(&self) -> &Interpretation {
+        &self.interpretations[0]
+    }
+
+    // The likeliest three on one line: "pointer 0x1F2A0 (rw- PRV) 90%, f64 1.5e-300 10%".
+    pub fn summary(&self) -> String {
+        self.interpretations
+            .iter()
+            .take(3)
+            .map(|guess| match &guess.value {
+                Guess::Pointer { region: Some(region), .. } => format!(
+                    "{} ({} {}) {}%",
+                    guess.value,
+                    region.protection_string(),
+                    region.region_type.tag(),
+                    guess.confidence
+                ),
+                value => format!("{} {}%", value, guess.confidence),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+impl fmt::Display for ProbeResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "0x{:X}: {} bytes read", self.address, self.bytes.len())?;
+        for guess in &self.interpretations {
+            writeln!(f, "{:>3}% {}", guess.confidence, guess.value)?;
+            for evidence in &guess.evidence {
+                writeln!(f, "       {}", evidence)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Memory {
+    // Reads PROBE_WINDOW bytes at `address` and guesses what they are. Pointer targets are
+    // looked up in the cached regions when `scan_memory` has run, and queried live otherwise.
+    // Fails only if nothing at `address` is readable.
+    pub fn probe(&self, address: usize) -> io::Result<ProbeResult> {
+        let mut bytes = vec![0; PROBE_WINDOW];
+        let read = self.read_into(address, &mut bytes)?;
+        bytes.truncate(read);
+        if bytes.is_empty() {
+            return Err(io::Error::other(format!("Nothing readable at 0x{:X}", address)));
+        }
+        let pointer_size = self.pointer_size()?;
+
+        let live: Vec<MemoryRegion>;
+        let regions = if self.regions().len() == 0 {
+            let target = read_pointer_value(&bytes, pointer_size).unwrap_or(0);
+            live = self.query_address(target).ok().and_then(|info| info.to_region()).into_iter().collect();
+            &live
+        } else {
+            self.cached_regions()
+        };
+
+        let mut interpretations = interpret(&bytes, address, pointer_size, regions);
+        for guess in &mut interpretations {
+            if let Guess::Pointer { target, region: Some(_) } = guess.value {
+                if let Some(symbol) = self.symbolize(target) {
+                    guess.evidence.push(format!("points at {}", symbol));
+                }
+            }
+        }
+        Ok(ProbeResult { address, bytes, interpretations })
+    }
+}
+
+// Every interpretation of `bytes`, read at `address`, most likely first. `regions` is the map
+// pointer candidates are checked against.
+pub fn interpret(bytes: &[u8], address: usize, pointer_size: usize, regions: &[MemoryRegion]) -> Vec<Interpretation> {
+    let mut interpretations: Vec<Interpretation> = [
+        pointer(bytes, address, pointer_size, regions),
+        float32(bytes),
+        float64(bytes),
+        ascii(bytes),
+        utf16(bytes),
+        float_vector(bytes, address),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    interpretations.push(raw_bytes(bytes));
+    // Stable, so equally likely guesses keep the order above.
+    interpretations.sort_by_key(|guess| Reverse(guess.confidence));
+    interpretations
+}
+
+fn read_pointer_value(bytes: &[u8], pointer_size: usize) -> Option<usize> {
+    match pointer_size {
+        4 => Some(u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?) as usize),
+        _ => Some(u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?) as usize),
+    }
+}
+
+fn pointer(bytes: &[u8], address: usize, pointer_size: usize, regions: &[MemoryRegion]) -> Option<Interpretation> {
+    let target = read_pointer_value(bytes, pointer_size)?;
+    let mut evidence = Vec::new();
+    if target == 0 {
+        evidence.push(format!("{} zero bytes: a null pointer, or zero of any type", pointer_size));
+        return Some(Interpretation { value: Guess::Pointer { target, region: None }, confidence: 20, evidence });
+    }
+
+    let region = regions.iter().find(|region| region.contains(target))?;
+    evidence.push(format!(
+        "lands in {} {} region {}-{}",
+        region.protection_string(),
+        region.region_type.tag(),
+        region.start_address,
+        region.end()
+    ));
+    let mut confidence: i32 = if region.is_readable { 80 } else { 40 };
+    if !region.is_readable {
+        evidence.push("the target region isn't readable".to_string());
+    }
+    if target % pointer_size == 0 {
+        confidence += 10;
+        evidence.push(format!("target is {}-byte aligned", pointer_size));
+    }
+    if address % pointer_size != 0 {
+        confidence -= 30;
+        evidence.push(format!("stored at an address that isn't {}-byte aligned", pointer_size));
+    }
+    Some(Interpretation {
+        value: Guess::Pointer { target, region: Some(region.clone()) },
+        confidence: confidence.clamp(0, 100) as u8,
+        evidence,
+    })
+}
+
+fn in_plausible_range(magnitude: f64) -> bool {
+    (PLAUSIBLE_FLOAT.0..=PLAUSIBLE_FLOAT.1).contains(&magnitude)
+}
+
+// Evidence and a confidence for a float, or None if it's not a finite, normal value.
+fn plausible_float(value: f64, mantissa_bits: u32, trailing_zeros: u32) -> Option<(u8, Vec<String>)> {
+    if !value.is_normal() {
+        return None;
+    }
+    let magnitude = value.abs();
+    let mut evidence = vec!["finite and normal".to_string()];
+    let mut confidence = 30;
+    if in_plausible_range(magnitude) {
+        confidence += 30;
+        evidence.push(format!("magnitude between {:e} and {:e}", PLAUSIBLE_FLOAT.0, PLAUSIBLE_FLOAT.1));
+    }
+    // Constants and values set by code, like 100.0 or 0.5, use few mantissa bits.
+    if trailing_zeros >= mantissa_bits / 2 {
+        confidence += 20;
+        let zeros = trailing_zeros.min(mantissa_bits);
+        evidence.push(format!("{} of {} mantissa bits are trailing zeros", zeros, mantissa_bits));
+    }
+    Some((confidence, evidence))
+}
+
+fn float32(bytes: &[u8]) -> Option<Interpretation> {
+    let bits = u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?);
+    let value = f32::from_bits(bits);
+    let (confidence, evidence) = plausible_float(value as f64, 23, (bits & 0x7F_FFFF).trailing_zeros())?;
+    Some(Interpretation { value: Guess::F32(value), confidence, evidence })
+}
+
+fn float64(bytes: &[u8]) -> Option<Interpretation> {
+    let bits = u64::from_le_bytes(bytes.get(..8)?.try_into().ok()?);
+    let value = f64::from_bits(bits);
+    let (confidence, evidence) = plausible_float(value, 52, (bits & 0xF_FFFF_FFFF_FFFF).trailing_zeros())?;
+    Some(Interpretation { value: Guess::F64(value), confidence, evidence })
+}
+
+fn is_printable(byte: u8) -> bool {
+    matches!(byte, 0x20..=0x7E | b'\t' | b'\r' | b'\n')
+}
+
+// Confidence for a printable run of `len` characters, and whether a terminator follows it.
+fn string_confidence(len: usize, terminated: bool, evidence: &mut Vec<String>) -> u8 {
+    evidence.push(format!("{} printable characters", len));
+    let mut confidence = (30 + 5 * len).min(85);
+    if terminated {
+        confidence += 10;
+        evidence.push("followed by a terminator".to_string());
+    }
+    confidence as u8
+}
+
+fn ascii(bytes: &[u8]) -> Option<Interpretation> {
+    let len = bytes.iter().take_while(|&&byte| is_printable(byte)).count();
+    if len < MIN_STRING_LEN {
+        return None;
+    }
+    let mut evidence = Vec::new();
+    let confidence = string_confidence(len, bytes.get(len) == Some(&0), &mut evidence);
+    let text = String::from_utf8_lossy(&bytes[..len]).into_owned();
+    Some(Interpretation { value: Guess::Ascii(text), confidence, evidence })
+}
+
+// Like `strings -e l`: code units that are printable ASCII with a zero high byte.
+fn utf16(bytes: &[u8]) -> Option<Interpretation> {
+    let units: Vec<u16> = bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect();
+    let len = units.iter().take_while(|&&unit| unit < 0x80 && is_printable(unit as u8)).count();
+    if len < MIN_STRING_LEN {
+        return None;
+    }
+    let mut evidence = Vec::new();
+    let confidence = string_confidence(len, units.get(len) == Some(&0), &mut evidence);
+    let text = String::from_utf16_lossy(&units[..len]);
+    Some(Interpretation { value: Guess::Utf16(text), confidence, evidence })
+}
+
+fn float_vector(bytes: &[u8], address: usize) -> Option<Interpretation> {
+    if address % 16 != 0 {
+        return None;
+    }
+    let mut lanes = [0f32; 4];
+    for (lane, chunk) in lanes.iter_mut().zip(bytes.get(..16)?.chunks_exact(4)) {
+        *lane = f32::from_le_bytes(chunk.try_into().unwrap());
+    }
+    // Zero lanes are common (w = 0, unused channels), but at least two must carry a value.
+    let plausible = |lane: &f32| lane.is_normal() && in_plausible_range(lane.abs() as f64);
+    let values = lanes.iter().filter(|lane| plausible(lane)).count();
+    if values < 2 || !lanes.iter().all(|lane| *lane == 0.0 || plausible(lane)) {
+        return None;
+    }
+    let evidence =
+        vec!["16-byte aligned".to_string(), format!("{} of 4 lanes are plausible floats, the rest zero", values)];
+    Some(Interpretation { value: Guess::F32x4(lanes), confidence: 50, evidence })
+}
+
+fn raw_bytes(bytes: &[u8]) -> Interpretation {
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    let evidence = if zeros == bytes.len() {
+        vec![format!("all {} bytes are zero", bytes.len())]
+    } else {
+        vec![format!("{} bytes, {} distinct values", bytes.len(), distinct(bytes))]
+    };
+    Interpretation { value: Guess::Bytes(bytes.to_vec()), confidence: 10, evidence }
+}
+
+fn distinct(bytes: &[u8]) -> usize {
+    let mut seen = [false; 256];
+    for &byte in bytes {
+        seen[byte as usize] = true;
+    }
+    seen.iter().filter(|&&seen| seen).count()
+}
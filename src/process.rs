@@ -0,0 +1,172 @@
+// Launching and ending target processes. A trainer that starts the game itself can patch it
+// before any of its code runs:
+//
+//     let (memory, main_thread) = Memory::spawn_suspended("game.exe -windowed")?;
+//     memory.write_bytes(address, &[0x90, 0x90])?;
+//     main_thread.resume()?;
+//     let engine = memory.wait_for_module("engine.dll", Some(Duration::from_secs(30)))?;
+//
+// While the process is suspended the loader hasn't run: only the exe and ntdll are mapped,
+// and the module list is empty or fails to enumerate (CreateToolhelp32Snapshot reads the
+// loader's lists, which don't exist yet). Patch the exe by address and use `wait_for_module`
+// after resuming for everything else.
+
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace, warn};
+
+use crate::memory::Memory;
+use crate::module::{self, ModuleInfo};
+use crate::sys::{
+    CloseHandle, CreateProcessW, OpenProcess, ResumeThread, TerminateProcess, CREATE_SUSPENDED, PROCESS_INFORMATION,
+    PROCESS_TERMINATE, STARTUPINFOW,
+};
+use crate::win_error::win_call;
+use crate::wstr;
+
+// How often `wait_for_module` looks at the module list.
+const MODULE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+// The main thread of a process started by `Memory::spawn_suspended`. Nothing in the process
+// runs until it is resumed; dropping it resumes it too, with a warning, so that a caller
+// that errors out or panics between spawning and resuming doesn't leave the process hanging.
+pub struct MainThread {
+    handle: *mut c_void,
+    thread_id: u32,
+    resumed: bool,
+}
+
+// The handle is only used for ResumeThread and CloseHandle, both fine from any thread.
+unsafe impl Send for MainThread {}
+
+impl MainThread {
+    pub fn id(&self) -> u32 {
+        self.thread_id
+    }
+
+    pub fn resume(mut self) -> io::Result<()> {
+        win_call!(
+            unsafe { ResumeThread(self.handle) },
+            failed: |count| *count == u32::MAX,
+            "Failed to resume thread {}",
+            self.thread_id
+        )?;
+        debug!("resumed main thread {}", self.thread_id);
+        self.resumed = true;
+        Ok(())
+    }
+}
+
+impl Drop for MainThread {
+    fn drop(&mut self) {
+        if !self.resumed {
+            warn!("main thread {} dropped without being resumed; resuming it", self.thread_id);
+            unsafe { ResumeThread(self.handle) };
+        }
+        unsafe { CloseHandle(self.handle) };
+    }
+}
+
+impl Memory {
+    // Starts `command_line` (program and arguments, quoted as on a command prompt) with its
+    // main thread suspended and attaches to it. If attaching fails the process is killed.
+    pub fn spawn_suspended(command_line: &str) -> io::Result<(Memory, MainThread)> {
+        let mut command: Vec<u16> = command_line.encode_utf16().chain(Some(0)).collect();
+        let mut startup: STARTUPINFOW = unsafe { mem::zeroed() };
+        startup.cb = mem::size_of::<STARTUPINFOW>() as u32;
+        let mut info: PROCESS_INFORMATION = unsafe { mem::zeroed() };
+
+        win_call!(
+            unsafe {
+                CreateProcessW(
+                    ptr::null(),
+                    command.as_mut_ptr(),
+                    ptr::null(),
+                    ptr::null(),
+                    0,
+                    CREATE_SUSPENDED,
+                    ptr::null(),
+                    ptr::null(),
+                    &startup,
+                    &mut info,
+                )
+            },
+            "Failed to start '{}'",
+            command_line
+        )?;
+        debug!("started process {} suspended (main thread {})", info.dwProcessId, info.dwThreadId);
+
+        let memory = match Memory::open(info.dwProcessId) {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe {
+                    TerminateProcess(info.hProcess, 1);
+                    CloseHandle(info.hProcess);
+                    CloseHandle(info.hThread);
+                }
+                return Err(e);
+            }
+        };
+        unsafe { CloseHandle(info.hProcess) };
+        let main_thread = MainThread { handle: info.hThread, thread_id: info.dwThreadId, resumed: false };
+        Ok((memory, main_thread))
+    }
+
+    // Ends the process. Like `run_remote_thread`, this reopens it with the rights it needs.
+    pub fn terminate(&self, exit_code: u32) -> io::Result<()> {
+        if self.is_dry_run() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Processes cannot be terminated in dry-run mode"));
+        }
+
+        let pid = self.process_id();
+        let process = win_call!(
+            unsafe { OpenProcess(PROCESS_TERMINATE, 0, pid) },
+            "Failed to open process {} for termination",
+            pid
+        )?;
+        // Closing the handle first would overwrite the reason TerminateProcess failed.
+        let terminated =
+            win_call!(unsafe { TerminateProcess(process, exit_code) }, "Failed to terminate process {}", pid);
+        unsafe { CloseHandle(process) };
+        terminated?;
+        debug!("terminated process {} with exit code {}", self.process_id(), exit_code);
+        Ok(())
+    }
+
+    // Blocks until a module called `name` (case-insensitive) is loaded, polling the live
+    // module list, for at most `timeout` or forever with None. Enumeration errors count as
+    // "not loaded yet", since they are expected while the loader is still starting up. A
+    // `DebugSession` reports loads as they happen instead, if attaching as a debugger is an
+    // option.
+    pub fn wait_for_module(&self, name: &str, timeout: Option<Duration>) -> io::Result<ModuleInfo> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            match module::enumerate_modules(self.process_id()) {
+                Ok(modules) => {
+                    if let Some(found) = modules.into_iter().find(|module| wstr::eq_ignore_case(&module.name, name)) {
+                        return Ok(found);
+                    }
+                }
+                Err(e) => trace!("module list not available yet: {}", e),
+            }
+
+            let mut interval = MODULE_POLL_INTERVAL;
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("{} was not loaded within {:?}", name, timeout.unwrap_or_default()),
+                    ));
+                }
+                interval = interval.min(deadline - now);
+            }
+            thread::sleep(interval);
+        }
+    }
+}
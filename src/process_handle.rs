@@ -0,0 +1,169 @@
+// The process handle a `Memory` owns, and the background threads that may use it. Every
+// component that works from a thread of its own (ChangeMonitor, ProtectionMonitor,
+// CodeWatch, AllocationTracer, ValueStream, the server's clients) holds a `Weak<Memory>`,
+// upgraded for one tick at a time, so none of them keeps the target open once its owner lets
+// go:
+//
+//     let memory = Arc::new(Memory::open(pid)?);
+//     let monitor = ChangeMonitor::start(&memory, RegionFilter::new(), Duration::from_millis(100));
+//     drop(memory);
+//     // the monitor finishes its tick and stops; the handle closes after it
+//
+// Dropping the last `Arc<Memory>` wakes every worker, which then finds nothing to upgrade and
+// stops. `Memory::close` does the same explicitly and waits for them: afterwards every read,
+// write or other call on the handle fails, scans in flight end as if cancelled, and server
+// clients are disconnected at their next request. Their `stop` methods still return what
+// was gathered until then.
+//
+// The raw handle itself is closed when the last reference to it goes, which is also held by
+// timed reads still running on a timeout worker, so nothing ever calls through a closed one.
+
+use std::ffi::c_void;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle, Thread};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::memory::Memory;
+use crate::sys::CloseHandle;
+use crate::win_error::win_call;
+
+pub(crate) struct ProcessHandle {
+    raw: *mut c_void,
+    process_id: u32,
+    closing: AtomicBool,
+    workers: Mutex<Workers>,
+    finished: Condvar,
+}
+
+#[derive(Default)]
+struct Workers {
+    running: usize,
+    // Unparked on close, so that workers between ticks notice at once.
+    threads: Vec<Thread>,
+}
+
+// A process handle may be used from any thread.
+unsafe impl Send for ProcessHandle {}
+unsafe impl Sync for ProcessHandle {}
+
+impl ProcessHandle {
+    // Takes ownership of `raw`; null for a `Memory` without a handle.
+    pub(crate) fn new(raw: *mut c_void, process_id: u32) -> Self {
+        ProcessHandle {
+            raw,
+            process_id,
+            closing: AtomicBool::new(false),
+            workers: Mutex::new(Workers::default()),
+            finished: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn raw(&self) -> *mut c_void {
+        self.raw
+    }
+
+    pub(crate) fn is_closing(&self) -> bool {
+        self.closing.load(Ordering::SeqCst)
+    }
+
+    // Tells the workers to stop without waiting for them.
+    pub(crate) fn shut_down(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+        for thread in &self.workers.lock().unwrap().threads {
+            thread.unpark();
+        }
+    }
+}
+
+impl Drop for ProcessHandle {
+    fn drop(&mut self) {
+        if self.raw.is_null() {
+            return;
+        }
+        let process_id = self.process_id;
+        let closed = win_call!(unsafe { CloseHandle(self.raw) }, "Failed to close process {}", process_id);
+        if let Err(e) = closed {
+            warn!("{}", e);
+        }
+    }
+}
+
+// Counts a worker as running from before its thread starts until it returns.
+struct WorkerGuard(Arc<ProcessHandle>);
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        let mut workers = self.0.workers.lock().unwrap();
+        workers.running -= 1;
+        if workers.running == 0 {
+            workers.threads.clear();
+        }
+        self.0.finished.notify_all();
+    }
+}
+
+// Runs `work` on a thread of its own with a `Weak` to `memory`, counted among the workers
+// that `Memory::close` waits for.
+pub(crate) fn spawn_worker<T, F>(memory: &Arc<Memory>, work: F) -> JoinHandle<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&Weak<Memory>) -> T + Send + 'static,
+{
+    let handle = Arc::clone(memory.process_handle());
+    handle.workers.lock().unwrap().running += 1;
+    let guard = WorkerGuard(Arc::clone(&handle));
+    let weak = Arc::downgrade(memory);
+    let thread = thread::spawn(move || {
+        let _guard = guard;
+        work(&weak)
+    });
+
+    let mut workers = handle.workers.lock().unwrap();
+    if workers.running > 0 {
+        workers.threads.push(thread.thread().clone());
+    }
+    if handle.is_closing() {
+        thread.thread().unpark();
+    }
+    thread
+}
+
+// The `Memory` for one tick of a worker, None once it was dropped or closed.
+pub(crate) fn upgrade(memory: &Weak<Memory>) -> Option<Arc<Memory>> {
+    memory.upgrade().filter(|memory| !memory.is_closed())
+}
+
+impl Memory {
+    // Stops every background component using this `Memory` and waits up to `timeout` for
+    // them to finish; fails with TimedOut if some haven't by then. Whatever the outcome,
+    // everything that would use the handle fails from here on. The handle is closed when the
+    // `Memory` is dropped.
+    pub fn close(&self, timeout: Duration) -> io::Result<()> {
+        let handle = self.process_handle();
+        handle.shut_down();
+        let workers = handle.workers.lock().unwrap();
+        let (workers, wait) =
+            handle.finished.wait_timeout_while(workers, timeout, |workers| workers.running > 0).unwrap();
+        if wait.timed_out() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "{} background workers of process {} still running after {:?}",
+                    workers.running,
+                    self.process_id(),
+                    timeout
+                ),
+            ));
+        }
+        debug!("process {} closed", self.process_id());
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.process_handle().is_closing()
+    }
+}
@@ -0,0 +1,466 @@
+// Who started whom, from the parent process ids in a toolhelp snapshot. Launchers start the
+// real game as a child and exit, so attaching goes by descent from the launcher instead:
+//
+//     let launcher = Command::new("launcher.exe").spawn()?;
+//     let memory = Memory::open_descendant(
+//         launcher.id(),
+//         |name, _| wstr::eq_ignore_case(name, "game.exe"),
+//         Some(Duration::from_secs(60)),
+//     )?;
+//
+// Windows reuses process ids, and a parent id is never updated when the parent exits: a
+// process whose parent is long gone can list an id that now belongs to somebody else. The
+// tree trusts the ids as they are, so look for descendants soon after starting the root.
+//
+// Services and other users' processes live in other sessions, and which of them can be
+// opened depends on elevation, privileges and protection. `snapshot_annotated` says, per
+// process, who it runs as, where its executable is and how far OpenProcess gets with it:
+//
+//     for process in ProcessTree::snapshot_annotated()?.iter() {
+//         println!("{} {:?} {:?}", process, process.user, process.access);
+//     }
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+
+use crate::memory::Memory;
+use crate::sys::{
+    CloseHandle, CreateToolhelp32Snapshot, GetTokenInformation, LookupAccountSidW, NtQueryInformationProcess,
+    OpenProcess, OpenProcessToken, Process32FirstW, Process32NextW, ProcessCommandLineInformation,
+    ProcessIdToSessionId, QueryFullProcessImageNameW, TokenUser, PROCESSENTRY32W, PROCESS_QUERY_INFORMATION,
+    PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE,
+    STATUS_INFO_LENGTH_MISMATCH, TH32CS_SNAPPROCESS, TOKEN_QUERY, UNICODE_STRING,
+};
+use crate::win_error::win_call;
+use crate::wstr;
+
+// Longest path QueryFullProcessImageNameW returns, in UTF-16 units.
+const MAX_IMAGE_PATH: usize = 32 * 1024;
+
+// How often `open_descendant` and `wait_for_process` look at the process list.
+const PROCESS_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How far OpenProcess gets with a process, from nothing up to what `Memory::open` asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AccessLevel {
+    None,
+    // PROCESS_QUERY_LIMITED_INFORMATION: the image path and exit code, nothing in memory.
+    // Protected processes and most other sessions' processes get no further.
+    Limited,
+    // Reading memory and querying its regions.
+    Read,
+    // Everything `Memory::open` needs.
+    ReadWrite,
+}
+
+impl AccessLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            AccessLevel::None => "none",
+            AccessLevel::Limited => "limited",
+            AccessLevel::Read => "read",
+            AccessLevel::ReadWrite => "read-write",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    // The process that started this one; it may have exited since.
+    pub parent_pid: u32,
+    // File name of the executable, e.g. "game.exe".
+    pub name: String,
+    pub threads: u32,
+    // The terminal services session: 0 for services, one per logged-on user above that.
+    pub session_id: Option<u32>,
+    // The rest is only filled in by `snapshot_annotated`, and None where it couldn't be read.
+    // "DOMAIN\user", from the process token.
+    pub user: Option<String>,
+    pub image_path: Option<String>,
+    pub access: Option<AccessLevel>,
+}
+
+// "game.exe (pid 4312, parent 4120)"
+impl fmt::Display for ProcessInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (pid {}, parent {})", self.name, self.pid, self.parent_pid)
+    }
+}
+
+// Every process running at the moment of the snapshot.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessTree {
+    processes: Vec<ProcessInfo>,
+}
+
+impl ProcessTree {
+    pub fn snapshot() -> io::Result<Self> {
+        let snapshot =
+            win_call!(unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) }, "Failed to create process snapshot")?;
+
+        let mut processes = Vec::new();
+        let mut entry: PROCESSENTRY32W = unsafe { mem::zeroed() };
+        entry.dwSize = mem::size_of::<PROCESSENTRY32W>() as u32;
+        let mut more = unsafe { Process32FirstW(snapshot, &mut entry) } != 0;
+        while more {
+            processes.push(ProcessInfo {
+                pid: entry.th32ProcessID,
+                parent_pid: entry.th32ParentProcessID,
+                name: wstr::from_wide_nul(&entry.szExeFile),
+                threads: entry.cntThreads,
+                session_id: session_id(entry.th32ProcessID),
+                user: None,
+                image_path: None,
+                access: None,
+            });
+            more = unsafe { Process32NextW(snapshot, &mut entry) } != 0;
+        }
+        unsafe { CloseHandle(snapshot) };
+
+        Ok(ProcessTree { processes })
+    }
+
+    // A snapshot with each process's user, image path and access level. Opens every process
+    // a few times, so it's slower than `snapshot` by far; nothing is left open.
+    pub fn snapshot_annotated() -> io::Result<Self> {
+        let mut tree = Self::snapshot()?;
+        for process in &mut tree.processes {
+            process.access = Some(access_level(process.pid));
+            process.image_path = image_path(process.pid).map_err(|e| trace!("{}", e)).ok();
+            process.user = process_user(process.pid).map_err(|e| trace!("{}", e)).ok();
+        }
+        Ok(tree)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ProcessInfo> {
+        self.processes.iter()
+    }
+
+    pub fn get(&self, pid: u32) -> Option<&ProcessInfo> {
+        self.processes.iter().find(|process| process.pid == pid)
+    }
+
+    pub fn children(&self, pid: u32) -> Vec<ProcessInfo> {
+        // The idle process (pid 0) lists itself as its parent.
+        self.processes.iter().filter(|process| process.parent_pid == pid && process.pid != pid).cloned().collect()
+    }
+
+    // Children, their children and so on, parents before their children. `pid` itself need
+    // not be running any more: the children of an exited launcher are still found.
+    pub fn descendants(&self, pid: u32) -> Vec<ProcessInfo> {
+        let mut by_parent: HashMap<u32, Vec<&ProcessInfo>> = HashMap::new();
+        for process in &self.processes {
+            by_parent.entry(process.parent_pid).or_default().push(process);
+        }
+
+        // Reused ids can make the parent links cyclic.
+        let mut seen = HashSet::from([pid]);
+        let mut descendants = Vec::new();
+        let mut next = 0;
+        let mut parents = vec![pid];
+        while let Some(&parent) = parents.get(next) {
+            next += 1;
+            for &child in by_parent.get(&parent).into_iter().flatten() {
+                if seen.insert(child.pid) {
+                    parents.push(child.pid);
+                    descendants.push(child.clone());
+                }
+            }
+        }
+        descendants
+    }
+}
+
+// The command line `pid` was started with. Needs Windows 8.1 or later, and fails for
+// processes this one can't query, such as protected ones.
+pub fn command_line(pid: u32) -> io::Result<String> {
+    let process =
+        win_call!(unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) }, "Failed to open process {}", pid)?;
+
+    // A UNICODE_STRING followed by the text it points at. usize-aligned for the header.
+    let mut buffer: Vec<usize> = vec![0; 256];
+    let result = loop {
+        let size = (buffer.len() * mem::size_of::<usize>()) as u32;
+        let mut needed = 0u32;
+        let status = unsafe {
+            NtQueryInformationProcess(
+                process,
+                ProcessCommandLineInformation,
+                buffer.as_mut_ptr() as *mut c_void,
+                size,
+                &mut needed,
+            )
+        };
+        if status == STATUS_INFO_LENGTH_MISMATCH && needed > size {
+            buffer.resize((needed as usize).div_ceil(mem::size_of::<usize>()), 0);
+            continue;
+        }
+        if status < 0 {
+            break Err(io::Error::other(format!(
+                "Failed to query the command line of process {}: NTSTATUS 0x{:08X}",
+                pid, status as u32
+            )));
+        }
+        let header = unsafe { &*(buffer.as_ptr() as *const UNICODE_STRING) };
+        let units = if header.Buffer.is_null() {
+            &[][..]
+        } else {
+            unsafe { slice::from_raw_parts(header.Buffer, header.Length as usize / 2) }
+        };
+        break Ok(wstr::decode_lossy(units));
+    };
+    unsafe { CloseHandle(process) };
+    result
+}
+
+fn session_id(pid: u32) -> Option<u32> {
+    let mut session = 0;
+    (unsafe { ProcessIdToSessionId(pid, &mut session) } != 0).then_some(session)
+}
+
+// The most OpenProcess grants for `pid`, trying the levels from the top.
+pub fn access_level(pid: u32) -> AccessLevel {
+    let levels = [
+        (AccessLevel::ReadWrite, PROCESS_VM_READ | PROCESS_VM_WRITE | PROCESS_VM_OPERATION | PROCESS_QUERY_INFORMATION),
+        (AccessLevel::Read, PROCESS_VM_READ | PROCESS_QUERY_INFORMATION),
+        (AccessLevel::Limited, PROCESS_QUERY_LIMITED_INFORMATION),
+    ];
+    for (level, access) in levels {
+        let process = unsafe { OpenProcess(access, 0, pid) };
+        if !process.is_null() {
+            unsafe { CloseHandle(process) };
+            return level;
+        }
+    }
+    AccessLevel::None
+}
+
+// The full path of the executable `pid` runs. Needs only limited query rights, unlike the
+// module list, so it also works across sessions and for protected processes.
+pub fn image_path(pid: u32) -> io::Result<String> {
+    let process =
+        win_call!(unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) }, "Failed to open process {}", pid)?;
+    let mut path = vec![0u16; MAX_IMAGE_PATH];
+    let mut len = path.len() as u32;
+    let result = win_call!(
+        unsafe { QueryFullProcessImageNameW(process, 0, path.as_mut_ptr(), &mut len) },
+        "Failed to query the image path of process {}",
+        pid
+    );
+    unsafe { CloseHandle(process) };
+    result.map(|_| wstr::decode_lossy(&path[..len as usize]))
+}
+
+// "DOMAIN\user" the process runs as, from its token. Fails for processes of other users
+// unless this one is elevated, and for protected processes.
+pub fn process_user(pid: u32) -> io::Result<String> {
+    let process =
+        win_call!(unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) }, "Failed to open process {}", pid)?;
+    let mut token = std::ptr::null_mut();
+    let opened = win_call!(
+        unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token) },
+        "Failed to open the token of process {}",
+        pid
+    );
+    unsafe { CloseHandle(process) };
+    opened?;
+
+    // TOKEN_USER: a SID_AND_ATTRIBUTES whose SID lives further on in the same buffer.
+    let mut buffer: Vec<usize> = vec![0; 64];
+    let size = (buffer.len() * mem::size_of::<usize>()) as u32;
+    let mut needed = 0;
+    let queried = win_call!(
+        unsafe { GetTokenInformation(token, TokenUser, buffer.as_mut_ptr() as *mut c_void, size, &mut needed) },
+        "Failed to query the user of process {}",
+        pid
+    );
+    unsafe { CloseHandle(token) };
+    queried?;
+
+    let sid = buffer[0] as *mut c_void;
+    let (mut name, mut domain) = (vec![0u16; 256], vec![0u16; 256]);
+    let (mut name_len, mut domain_len, mut sid_use) = (name.len() as u32, domain.len() as u32, 0);
+    win_call!(
+        unsafe {
+            LookupAccountSidW(
+                std::ptr::null(),
+                sid,
+                name.as_mut_ptr(),
+                &mut name_len,
+                domain.as_mut_ptr(),
+                &mut domain_len,
+                &mut sid_use,
+            )
+        },
+        "Failed to look up the user of process {}",
+        pid
+    )?;
+    let name = wstr::decode_lossy(&name[..name_len as usize]);
+    match wstr::decode_lossy(&domain[..domain_len as usize]) {
+        domain if domain.is_empty() => Ok(name),
+        domain => Ok(format!("{}\\{}", domain, name)),
+    }
+}
+
+// How long to sleep before the next look at the process list, None once `deadline` passed.
+fn until_next_poll(deadline: Option<Instant>) -> Option<Duration> {
+    let Some(deadline) = deadline else {
+        return Some(PROCESS_POLL_INTERVAL);
+    };
+    let now = Instant::now();
+    (now < deadline).then(|| PROCESS_POLL_INTERVAL.min(deadline - now))
+}
+
+impl Memory {
+    // Waits for a descendant of `root_pid` that `predicate` accepts and attaches to it, for at
+    // most `timeout` or forever with None. The predicate gets the executable name and the
+    // command line, None if that couldn't be read; each process is offered to it once.
+    pub fn open_descendant<P>(root_pid: u32, mut predicate: P, timeout: Option<Duration>) -> io::Result<Memory>
+    where
+        P: FnMut(&str, Option<&str>) -> bool,
+    {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut offered: HashSet<(u32, String)> = HashSet::new();
+        loop {
+            // A failed snapshot is retried like an empty one.
+            let descendants = ProcessTree::snapshot().map(|tree| tree.descendants(root_pid)).unwrap_or_else(|e| {
+                trace!("process list not available: {}", e);
+                Vec::new()
+            });
+            for process in descendants {
+                if !offered.insert((process.pid, process.name.clone())) {
+                    continue;
+                }
+                let command_line = command_line(process.pid).map_err(|e| trace!("{}", e)).ok();
+                if predicate(&process.name, command_line.as_deref()) {
+                    debug!("attaching to descendant {} of {}", process, root_pid);
+                    return Memory::open(process.pid);
+                }
+            }
+
+            let Some(interval) = until_next_poll(deadline) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "No matching descendant of process {} appeared within {:?}",
+                        root_pid,
+                        timeout.unwrap_or_default()
+                    ),
+                ));
+            };
+            thread::sleep(interval);
+        }
+    }
+
+    // Attaches to a process whose executable is called `name` (case-insensitive), waiting for
+    // one to start for at most `timeout` or forever with None. Of several, the one listed first
+    // is taken. Its modules may still be loading; `wait_for_module` waits for those.
+    pub fn wait_for_process(name: &str, timeout: Option<Duration>) -> io::Result<Memory> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let found = match ProcessTree::snapshot() {
+                Ok(tree) => {
+                    tree.iter().find(|process| wstr::eq_ignore_case(&process.name, name)).map(|process| process.pid)
+                }
+                Err(e) => {
+                    trace!("process list not available: {}", e);
+                    None
+                }
+            };
+            if let Some(pid) = found {
+                debug!("attaching to {} ({})", name, pid);
+                return Memory::open(pid);
+            }
+
+            let Some(interval) = until_next_poll(deadline) else {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("No process {} started within {:?}", name, timeout.unwrap_or_default()),
+                ));
+            };
+            thread::sleep(interval);
+        }
+    }
+
+    // Reports the processes the target starts from now on, and the processes those start.
+    // Pairs with `spawn_suspended`: start watching before resuming the main thread so no
+    // child is missed.
+    pub fn watch_children(&self, interval: Duration) -> ChildWatch {
+        ChildWatch::start(self.process_id(), interval)
+    }
+}
+
+// Polls the process tree from a background thread and sends each new descendant of the root
+// once. Processes that start and exit between two polls are not seen.
+pub struct ChildWatch {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<u64>,
+    events: Receiver<ProcessInfo>,
+}
+
+impl ChildWatch {
+    // Descendants already running when the watch starts are not reported.
+    pub fn start(root_pid: u32, interval: Duration) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, events) = mpsc::channel();
+        let thread = {
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || run(root_pid, interval, &stop, &sender))
+        };
+        ChildWatch { stop, thread, events }
+    }
+
+    pub fn events(&self) -> &Receiver<ProcessInfo> {
+        &self.events
+    }
+
+    // Returns the number of polls run.
+    pub fn stop(self) -> u64 {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+fn run(root_pid: u32, interval: Duration, stop: &AtomicBool, sender: &Sender<ProcessInfo>) -> u64 {
+    // Keyed by name too, so a reused id counts as a new process.
+    let mut known: Option<HashSet<(u32, String)>> = None;
+    let mut tick = 0;
+
+    while !stop.load(Ordering::SeqCst) {
+        match ProcessTree::snapshot() {
+            Ok(tree) => {
+                let descendants = tree.descendants(root_pid);
+                let current: HashSet<(u32, String)> =
+                    descendants.iter().map(|process| (process.pid, process.name.clone())).collect();
+                if let Some(known) = &known {
+                    for process in descendants {
+                        if !known.contains(&(process.pid, process.name.clone())) {
+                            let _ = sender.send(process);
+                        }
+                    }
+                }
+                known = Some(current);
+            }
+            Err(e) => debug!("process list not available this tick: {}", e),
+        }
+
+        tick += 1;
+        thread::park_timeout(interval);
+    }
+
+    debug!("child watch of {} stopped after {} ticks", root_pid, tick);
+    tick
+}
@@ -0,0 +1,465 @@
+// The setup a trainer applies every time its target starts, in one file applied in one go:
+//
+//     [target]
+//     process = "game.exe"
+//     timestamp = 0x5F3A1B2C                   # optional: the module's link time
+//     signature = "48 8B 0D ?? ?? ?? ?? E8"    # optional: must match in the module
+//
+//     [[signatures]]
+//     name = "ammo"
+//     module = "game.exe"
+//     pattern = "FF 48 10 8B 40 10"
+//
+//     [[patches]]
+//     name = "infinite ammo"
+//     signature = "ammo"
+//     bytes = "90 90 90"
+//
+//     [[watches]]
+//     name = "health"
+//     signature = "player"
+//     offset = 0xC4
+//     type = "f32"
+//
+//     [[freezes]]
+//     name = "money"
+//     signature = "player"
+//     offset = 0x1F0
+//     type = "u32"
+//     value = "99999"
+//     optional = true
+//
+// and from code:
+//
+//     let memory = Arc::new(Memory::wait_for_process(&profile.target.process, None)?);
+//     let report = profile::apply_profile(&memory, &profile)?;
+//     let stream = ValueStream::new(&memory, report.watches.clone(), 30)?;
+//
+// `apply_profile` checks the target first: the process's name, then the version checks
+// against `target.module`, the executable unless given. It then resolves the signatures the
+// entries use, each entry's address being its signature's plus `offset`. If a required entry
+// doesn't resolve, nothing at all is applied; optional ones that don't are skipped and listed
+// in the report. The patches go in as one batch (`Memory::apply_patches`), so one that fails
+// rolls back the others, and then the freezes start. `ProfileReport::revert` undoes it all.
+//
+// Files ending in `.toml` are TOML, anything else JSON, as for signature files.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::filter::RegionFilter;
+use crate::freeze::{FreezeReport, Freezer, FrozenValue};
+use crate::memory::Memory;
+use crate::patch::Patch;
+use crate::pattern::Pattern;
+use crate::pe::PeHeaders;
+use crate::process_tree::ProcessTree;
+use crate::signature::{SigError, SignatureEntry};
+use crate::struct_template::{self, FieldType};
+use crate::value_stream::WatchedValue;
+use crate::wstr;
+
+// How often freezes are written when the profile doesn't say.
+pub const DEFAULT_FREEZE_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Profile {
+    #[serde(default)]
+    pub name: String,
+    pub target: TargetMatcher,
+    #[serde(default)]
+    pub signatures: Vec<SignatureEntry>,
+    #[serde(default)]
+    pub patches: Vec<ProfilePatch>,
+    #[serde(default)]
+    pub watches: Vec<ProfileValue>,
+    #[serde(default)]
+    pub freezes: Vec<ProfileFreeze>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub freeze_interval_ms: Option<u64>,
+}
+
+// Which process, and which build of it, the profile was made for.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TargetMatcher {
+    // Executable name, matched case-insensitively.
+    pub process: String,
+    // The module the version checks look at; unset means `process`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub module: Option<String>,
+    // The module's link time, `PeHeaders::time_date_stamp`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u32>,
+    // A pattern that must match somewhere in the module.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfilePatch {
+    pub name: String,
+    // Name of an entry in `signatures`.
+    pub signature: String,
+    #[serde(default)]
+    pub offset: isize,
+    // Hex bytes, e.g. "90 90 90".
+    pub bytes: String,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+// A value to watch.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileValue {
+    pub name: String,
+    pub signature: String,
+    #[serde(default)]
+    pub offset: isize,
+    // A `StructTemplate` field type.
+    #[serde(rename = "type")]
+    pub ty: String,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+// A value to hold, written as `struct_template::encode` reads `value` for the type.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileFreeze {
+    pub name: String,
+    pub signature: String,
+    #[serde(default)]
+    pub offset: isize,
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub value: String,
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl Profile {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+            Self::from_toml(&text)
+        } else {
+            Self::from_json(&text)
+        }
+    }
+
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        let profile: Profile = toml::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    pub fn from_json(text: &str) -> io::Result<Self> {
+        let profile: Profile = serde_json::from_str(text)?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    // Written as TOML or JSON by the same rule as `load`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let text = if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        fs::write(path, text)
+    }
+
+    // The name shown in reports: `name`, or the process when that's empty.
+    pub fn display_name(&self) -> &str {
+        match self.name.is_empty() {
+            true => &self.target.process,
+            false => &self.name,
+        }
+    }
+
+    // The modules the profile looks into, the version check's first, each once. Waiting for
+    // these with `wait_for_module` before applying covers DLLs that load late.
+    pub fn modules(&self) -> Vec<&str> {
+        let mut modules: Vec<&str> = vec![self.version_module()];
+        for entry in &self.signatures {
+            if !modules.iter().any(|module| wstr::eq_ignore_case(module, &entry.module)) {
+                modules.push(&entry.module);
+            }
+        }
+        modules
+    }
+
+    pub fn freeze_interval(&self) -> Duration {
+        self.freeze_interval_ms.map_or(DEFAULT_FREEZE_INTERVAL, Duration::from_millis)
+    }
+
+    fn version_module(&self) -> &str {
+        self.target.module.as_deref().unwrap_or(&self.target.process)
+    }
+
+    // Everything that can be checked without a target: patterns, types, bytes, and that every
+    // entry names a signature the profile has.
+    pub fn validate(&self) -> io::Result<()> {
+        if self.target.process.is_empty() {
+            return Err(invalid("the profile's target names no process".to_string()));
+        }
+        if let Some(pattern) = &self.target.signature {
+            Pattern::parse(pattern).map_err(|e| invalid(format!("target signature: {}", e)))?;
+        }
+        if self.freeze_interval_ms == Some(0) {
+            return Err(invalid("freeze_interval_ms must not be zero".to_string()));
+        }
+        for entry in &self.signatures {
+            entry.parse_pattern().map_err(|e| invalid(format!("signature '{}': {}", entry.name, e)))?;
+        }
+        let known = |kind: &str, name: &str, signature: &str| match self.signature(signature) {
+            Some(_) => Ok(()),
+            None => Err(invalid(format!(
+                "{} '{}' uses signature '{}', which the profile lacks",
+                kind, name, signature
+            ))),
+        };
+        for patch in &self.patches {
+            known("patch", &patch.name, &patch.signature)?;
+            parse_hex(&patch.bytes).map_err(|e| invalid(format!("patch '{}': {}", patch.name, e)))?;
+        }
+        for watch in &self.watches {
+            known("watch", &watch.name, &watch.signature)?;
+            watch.ty.parse::<FieldType>().map_err(|e| invalid(format!("watch '{}': {}", watch.name, e)))?;
+        }
+        for freeze in &self.freezes {
+            known("freeze", &freeze.name, &freeze.signature)?;
+            freeze.ty.parse::<FieldType>().map_err(|e| invalid(format!("freeze '{}': {}", freeze.name, e)))?;
+        }
+        Ok(())
+    }
+
+    fn signature(&self, name: &str) -> Option<&SignatureEntry> {
+        self.signatures.iter().find(|entry| entry.name == name)
+    }
+}
+
+// What `apply_profile` did. The freezer runs until the report is reverted, stopped or
+// dropped along with the `Memory`.
+pub struct ProfileReport {
+    pub profile: String,
+    // In profile order, with the names the profile gave them.
+    pub patches: Vec<(String, Patch)>,
+    // Ready for a `ValueStream`.
+    pub watches: Vec<WatchedValue>,
+    pub freezer: Option<Freezer>,
+    // Optional entries that didn't resolve: ("patch infinite ammo", why).
+    pub skipped: Vec<(String, String)>,
+}
+
+impl ProfileReport {
+    // Stops the freezes and reverts the patches, newest first, trying every one. Fails with
+    // the first error.
+    pub fn revert(mut self, memory: &Memory) -> io::Result<FreezeReport> {
+        let freezes = self.stop_freezes();
+        let mut first_error = None;
+        for (name, patch) in self.patches.iter().rev() {
+            if let Err(e) = memory.revert_patch(patch.address) {
+                log::warn!("failed to revert patch {} at 0x{:X}: {}", name, patch.address, e);
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(freezes), Err)
+    }
+
+    // Stops the freezes and leaves the patches in place.
+    pub fn stop_freezes(&mut self) -> FreezeReport {
+        self.freezer.take().map(Freezer::stop).unwrap_or_default()
+    }
+}
+
+// "profile 'my setup': 2 patches, 1 watch, 1 freeze", then a line per entry.
+impl fmt::Display for ProfileReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let freezes = self.freezer.as_ref().map_or(&[][..], Freezer::values);
+        write!(
+            f,
+            "profile '{}': {} patches, {} watches, {} freezes",
+            self.profile,
+            self.patches.len(),
+            self.watches.len(),
+            freezes.len()
+        )?;
+        for (name, patch) in &self.patches {
+            write!(f, "\n  patch {} at 0x{:X} (0x{:X} bytes)", name, patch.address, patch.len())?;
+        }
+        for watch in &self.watches {
+            write!(f, "\n  watch {} at 0x{:X} ({})", watch.label, watch.address, watch.ty)?;
+        }
+        for freeze in freezes {
+            write!(f, "\n  freeze {} at 0x{:X}", freeze.label, freeze.address)?;
+        }
+        for (entry, reason) in &self.skipped {
+            write!(f, "\n  skipped {}: {}", entry, reason)?;
+        }
+        Ok(())
+    }
+}
+
+// Checks `memory` against the profile's target, then applies its patches, returns its watch
+// list and starts its freezes. See above. Needs `scan_memory`.
+pub fn apply_profile(memory: &Arc<Memory>, profile: &Profile) -> io::Result<ProfileReport> {
+    profile.validate()?;
+    check_target(memory, profile)?;
+
+    let modules = memory.modules_or_live()?;
+    let pointer_size = memory.pointer_size()?;
+    let mut resolved: HashMap<&str, Result<usize, SigError>> = HashMap::new();
+    let mut resolve = |name: &str, offset: isize| -> Result<usize, String> {
+        let entry = profile.signature(name).expect("validated");
+        let found = resolved
+            .entry(&entry.name)
+            .or_insert_with(|| entry.resolve(memory, &modules).map(|ptr| ptr.address()));
+        match found {
+            Ok(address) => address.checked_add_signed(offset).ok_or_else(|| SigError::Overflow.to_string()),
+            Err(e) => Err(format!("signature '{}': {}", name, e)),
+        }
+    };
+
+    // Every entry resolved before anything is written.
+    let mut failed = Vec::new();
+    let mut skipped = Vec::new();
+    let mut outcome = |kind: &str, name: &str, optional: bool, result: Result<usize, String>| match result {
+        Ok(address) => Some(address),
+        Err(reason) => {
+            let entry = format!("{} {}", kind, name);
+            match optional {
+                true => skipped.push((entry, reason)),
+                false => failed.push(format!("{}: {}", entry, reason)),
+            }
+            None
+        }
+    };
+    let mut patches = Vec::new();
+    for patch in &profile.patches {
+        let address = resolve(&patch.signature, patch.offset);
+        if let Some(address) = outcome("patch", &patch.name, patch.optional, address) {
+            patches.push((patch.name.clone(), address, parse_hex(&patch.bytes)?));
+        }
+    }
+    let mut watches = Vec::new();
+    for watch in &profile.watches {
+        let address = resolve(&watch.signature, watch.offset);
+        if let Some(address) = outcome("watch", &watch.name, watch.optional, address) {
+            watches.push(WatchedValue::new(&watch.name, address, watch.ty.parse()?));
+        }
+    }
+    let mut freezes = Vec::new();
+    for freeze in &profile.freezes {
+        let address = resolve(&freeze.signature, freeze.offset);
+        if let Some(address) = outcome("freeze", &freeze.name, freeze.optional, address) {
+            let bytes = struct_template::encode(&freeze.ty.parse()?, &freeze.value, pointer_size)
+                .map_err(|e| invalid(format!("freeze '{}': {}", freeze.name, e)))?;
+            freezes.push(FrozenValue::new(&freeze.name, address, bytes));
+        }
+    }
+    if !failed.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Profile '{}' was not applied: {}", profile.display_name(), failed.join("; ")),
+        ));
+    }
+
+    let batch: Vec<(usize, Vec<u8>)> = patches.iter().map(|(_, address, bytes)| (*address, bytes.clone())).collect();
+    let applied = memory.apply_patches(&batch)?;
+    let patches: Vec<(String, Patch)> = patches.into_iter().map(|(name, _, _)| name).zip(applied).collect();
+    let freezer = match freezes.is_empty() {
+        true => None,
+        false => match Freezer::start(memory, freezes, profile.freeze_interval()) {
+            Ok(freezer) => Some(freezer),
+            Err(e) => {
+                for (_, patch) in patches.iter().rev() {
+                    let _ = memory.revert_patch(patch.address);
+                }
+                return Err(e);
+            }
+        },
+    };
+
+    debug!("applied profile '{}': {} entries skipped", profile.display_name(), skipped.len());
+    Ok(ProfileReport { profile: profile.display_name().to_string(), patches, watches, freezer, skipped })
+}
+
+// The process name, then the version checks.
+fn check_target(memory: &Memory, profile: &Profile) -> io::Result<()> {
+    let target = &profile.target;
+    let tree = ProcessTree::snapshot()?;
+    let process = tree.get(memory.process_id()).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("Process {} is not running", memory.process_id()))
+    })?;
+    if !wstr::eq_ignore_case(&process.name, &target.process) {
+        return Err(mismatch(format!(
+            "Profile '{}' is for {}, not for {}",
+            profile.display_name(),
+            target.process,
+            process.name
+        )));
+    }
+    if target.timestamp.is_none() && target.signature.is_none() {
+        return Ok(());
+    }
+
+    let module_name = profile.version_module();
+    let modules = memory.modules_or_live()?;
+    let module = modules
+        .iter()
+        .find(|module| wstr::eq_ignore_case(&module.name, module_name))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Module '{}' is not loaded", module_name)))?;
+    if let Some(expected) = target.timestamp {
+        let headers = PeHeaders::parse(&memory.read_bytes(module.base_address, 0x1000)?)?;
+        if headers.time_date_stamp != expected {
+            return Err(mismatch(format!(
+                "{} was linked at 0x{:08X}, not at 0x{:08X} as profile '{}' expects",
+                module_name,
+                headers.time_date_stamp,
+                expected,
+                profile.display_name()
+            )));
+        }
+    }
+    if let Some(signature) = &target.signature {
+        let filter = RegionFilter::new().range(module.base_address..module.base_address + module.size);
+        if memory.find_pattern(&Pattern::parse(signature)?, &filter)?.is_none() {
+            return Err(mismatch(format!(
+                "The version signature of profile '{}' doesn't match in {}",
+                profile.display_name(),
+                module_name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn parse_hex(text: &str) -> io::Result<Vec<u8>> {
+    let bytes = text
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).ok().filter(|_| byte.len() == 2))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(|| invalid(format!("'{}' is not hex bytes", text)))?;
+    match bytes.is_empty() {
+        true => Err(invalid("no bytes to write".to_string())),
+        false => Ok(bytes),
+    }
+}
+
+fn mismatch(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,190 @@
+// Several protection changes made together and undone together. Unpacking a module usually
+// means making all of its sections writable, poking around, and putting every page back as
+// it was:
+//
+//     let guard = memory.make_module_writable(&module)?;
+//     memory.write_bytes(unpacked, &decoded)?;
+//     guard.restore()?;
+//
+// `change_protection_bulk` applies each (range, protection) in the order given. Every region
+// a range covers is changed on its own, so the old protection of each piece is known and is
+// what the guard puts back, last change first, when `restore` is called or the guard is
+// dropped; `leak` keeps the new protections. A restore that fails is logged and the rest
+// still go ahead. Ranges may not overlap, since the second change to a page would record
+// the first one's protection as the original. If a change fails part way, the ones already
+// made are undone before the error is returned.
+
+use std::io;
+use std::ops::Range;
+
+use log::warn;
+
+use crate::memory::{Memory, PAGE_EXECUTE_READWRITE};
+use crate::module::ModuleInfo;
+use crate::sections;
+use crate::sys::PAGE_READWRITE;
+
+#[must_use = "dropping the guard restores the protections at once"]
+pub struct ProtectionGuard<'a> {
+    memory: &'a Memory,
+    // Each changed piece with the protection it had before, in the order changed.
+    changed: Vec<(Range<usize>, u32)>,
+}
+
+impl<'a> ProtectionGuard<'a> {
+    // A guard with nothing to restore yet, for changes `record`ed as they're made.
+    pub(crate) fn new(memory: &'a Memory) -> Self {
+        ProtectionGuard { memory, changed: Vec::new() }
+    }
+
+    pub(crate) fn record(&mut self, range: Range<usize>, old_protection: u32) {
+        self.changed.push((range, old_protection));
+    }
+
+    // The pieces changed and what they were before.
+    pub fn changes(&self) -> &[(Range<usize>, u32)] {
+        &self.changed
+    }
+
+    // Puts every piece back, last change first. Fails with the first error if any restore
+    // failed, after trying all of them.
+    pub fn restore(mut self) -> io::Result<()> {
+        self.restore_all()
+    }
+
+    // Keeps the new protections and returns what they replaced.
+    pub fn leak(mut self) -> Vec<(Range<usize>, u32)> {
+        std::mem::take(&mut self.changed)
+    }
+
+    fn restore_all(&mut self) -> io::Result<()> {
+        let mut first_error = None;
+        for (range, old_protection) in self.changed.drain(..).rev() {
+            if let Err(e) = self.memory.protect(range.start, range.len(), old_protection) {
+                warn!("failed to restore protection 0x{:X} at 0x{:X}: {}", old_protection, range.start, e);
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+impl Drop for ProtectionGuard<'_> {
+    fn drop(&mut self) {
+        // Failures were logged already.
+        let _ = self.restore_all();
+    }
+}
+
+impl Memory {
+    // Gives each range its PAGE_* protection, in order, and returns a guard that undoes all
+    // of it. See above.
+    pub fn change_protection_bulk(&self, ranges: &[(Range<usize>, u32)]) -> io::Result<ProtectionGuard<'_>> {
+        let mut sorted: Vec<&Range<usize>> =
+            ranges.iter().map(|(range, _)| range).filter(|range| !range.is_empty()).collect();
+        sorted.sort_by_key(|range| range.start);
+        if let Some(pair) = sorted.windows(2).find(|pair| pair[0].end > pair[1].start) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Ranges 0x{:X}-0x{:X} and 0x{:X}-0x{:X} overlap",
+                    pair[0].start,
+                    pair[0].end,
+                    pair[1].start,
+                    pair[1].end
+                ),
+            ));
+        }
+
+        let mut guard = ProtectionGuard::new(self);
+        for (range, protection) in ranges.iter().filter(|(range, _)| !range.is_empty()) {
+            // On failure the guard, dropped here, undoes the changes made so far.
+            for region in self.query_range(range.clone())? {
+                let start = region.base.get().max(range.start);
+                let end = region.end().get().min(range.end);
+                let old_protection = self.protect(start, end - start, *protection)?;
+                guard.record(start..end, old_protection);
+            }
+        }
+        Ok(guard)
+    }
+
+    // Makes every section of `module` writable: PAGE_EXECUTE_READWRITE for code, so that it
+    // still runs, and PAGE_READWRITE for the rest.
+    pub fn make_module_writable(&self, module: &ModuleInfo) -> io::Result<ProtectionGuard<'_>> {
+        let changes: Vec<(Range<usize>, u32)> = sections::read_sections(self, module)?
+            .into_iter()
+            .map(|section| {
+                let protection = if section.is_executable() { PAGE_EXECUTE_READWRITE } else { PAGE_READWRITE };
+                (section.range, protection)
+            })
+            .collect();
+        self.change_protection_bulk(&changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::backend::mock::{Call, MockBackend, PAGE};
+    use crate::memory::Memory;
+    use crate::sys::{PAGE_EXECUTE_READ, PAGE_READONLY, PAGE_READWRITE};
+
+    fn protections(memory: &Memory) -> Vec<u32> {
+        [0x10000, 0x11000, 0x20000].iter().map(|&address| memory.query_address(address).unwrap().protection).collect()
+    }
+
+    fn two_regions_and_a_third() -> MockBackend {
+        MockBackend::new()
+            .region(0x10000, vec![0; PAGE], PAGE_EXECUTE_READ)
+            .region(0x11000, vec![0; PAGE], PAGE_READONLY)
+            .region(0x20000, vec![0; PAGE], PAGE_READONLY)
+    }
+
+    #[test]
+    fn each_region_gets_its_own_protection_back() {
+        let (_mock, memory) = two_regions_and_a_third().open();
+
+        let guard = memory.change_protection_bulk(&[(0x10000..0x12000, PAGE_READWRITE)]).unwrap();
+        assert_eq!(guard.changes(), [(0x10000..0x11000, PAGE_EXECUTE_READ), (0x11000..0x12000, PAGE_READONLY)]);
+        assert_eq!(protections(&memory), [PAGE_READWRITE, PAGE_READWRITE, PAGE_READONLY]);
+        guard.restore().unwrap();
+        assert_eq!(protections(&memory), [PAGE_EXECUTE_READ, PAGE_READONLY, PAGE_READONLY]);
+    }
+
+    #[test]
+    fn a_change_failing_partway_undoes_the_rest() {
+        let (_mock, memory) = two_regions_and_a_third().open();
+
+        let changes = [(0x10000..0x12000, PAGE_READWRITE), (0x30000..0x31000, PAGE_READWRITE)];
+        assert!(memory.change_protection_bulk(&changes).is_err());
+        assert_eq!(protections(&memory), [PAGE_EXECUTE_READ, PAGE_READONLY, PAGE_READONLY]);
+    }
+
+    #[test]
+    fn a_panic_between_changes_restores_them() {
+        let (mock, memory) = two_regions_and_a_third().open();
+        // Once, so that the restores the unwinding runs get through.
+        let panicked = AtomicBool::new(false);
+        mock.on_call(move |call| {
+            if *call == Call::Protect(0x20000..0x21000, PAGE_READWRITE) && !panicked.swap(true, Ordering::SeqCst) {
+                panic!("cancelled between changes");
+            }
+        });
+
+        let changes = [(0x10000..0x12000, PAGE_READWRITE), (0x20000..0x21000, PAGE_READWRITE)];
+        panic::catch_unwind(AssertUnwindSafe(|| memory.change_protection_bulk(&changes).map(drop))).unwrap_err();
+        assert_eq!(protections(&memory), [PAGE_EXECUTE_READ, PAGE_READONLY, PAGE_READONLY]);
+    }
+
+    #[test]
+    fn leaked_changes_stay() {
+        let (_mock, memory) = two_regions_and_a_third().open();
+
+        let guard = memory.change_protection_bulk(&[(0x20000..0x21000, PAGE_READWRITE)]).unwrap();
+        assert_eq!(guard.leak(), [(0x20000..0x21000, PAGE_READONLY)]);
+        assert_eq!(protections(&memory), [PAGE_EXECUTE_READ, PAGE_READONLY, PAGE_READWRITE]);
+    }
+}
@@ -0,0 +1,243 @@
+// Watches for page protection transitions, above all writable memory turning executable, the
+// usual sign that code was just unpacked or injected. The region map is queried once per tick
+// and diffed against the previous one like `RegionSnapshot::diff` does.
+//
+//     let monitor = ProtectionMonitor::start(&memory, RegionFilter::new(), Duration::from_millis(50));
+//     for event in monitor.events().iter().take(10) {
+//         println!("{}", event);
+//     }
+//     monitor.stop();
+//
+// Changes that are undone within one tick are not seen.
+
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::address_space::MapEntry;
+use crate::filter::RegionFilter;
+use crate::memory::{Memory, MemoryRegion, PAGE_EXECUTABLE};
+use crate::process_handle::{spawn_worker, upgrade};
+use crate::region_snapshot::{self, ProtectionChange};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtectionEventKind {
+    // Pages of an existing allocation whose protection changed.
+    Changed(ProtectionChange),
+    // An allocation that wasn't committed in the previous tick.
+    Appeared(MemoryRegion),
+    Disappeared(MemoryRegion),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtectionEvent {
+    pub tick: u64,
+    pub kind: ProtectionEventKind,
+    // Set when the pages just became executable, dumping is on and the dump succeeded.
+    pub dump: Option<PathBuf>,
+}
+
+impl ProtectionEvent {
+    pub fn range(&self) -> Range<usize> {
+        match &self.kind {
+            ProtectionEventKind::Changed(change) => change.start_address..change.start_address + change.size,
+            ProtectionEventKind::Appeared(region) | ProtectionEventKind::Disappeared(region) => region.range(),
+        }
+    }
+
+    // Not executable before and executable now; always false for a region that disappeared.
+    pub fn became_executable(&self) -> bool {
+        match &self.kind {
+            ProtectionEventKind::Changed(change) => {
+                !is_executable(change.old_protection) && is_executable(change.new_protection)
+            }
+            ProtectionEventKind::Appeared(region) => region.is_executable,
+            ProtectionEventKind::Disappeared(_) => false,
+        }
+    }
+}
+
+// "tick 3: 0x1F0000-0x1F1000 protection 0x04 -> 0x20"
+impl fmt::Display for ProtectionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = self.range();
+        write!(f, "tick {}: 0x{:X}-0x{:X} ", self.tick, range.start, range.end)?;
+        match &self.kind {
+            ProtectionEventKind::Changed(change) => {
+                write!(f, "protection 0x{:02X} -> 0x{:02X}", change.old_protection, change.new_protection)?
+            }
+            ProtectionEventKind::Appeared(region) => write!(f, "appeared with protection 0x{:02X}", region.protection)?,
+            ProtectionEventKind::Disappeared(region) => {
+                write!(f, "disappeared, was protection 0x{:02X}", region.protection)?
+            }
+        }
+        if let Some(dump) = &self.dump {
+            write!(f, " (dumped to {})", dump.display())?;
+        }
+        Ok(())
+    }
+}
+
+fn is_executable(protection: u32) -> bool {
+    protection & PAGE_EXECUTABLE != 0
+}
+
+// Watches the target's region map from a background thread.
+pub struct ProtectionMonitor {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<u64>,
+    events: Receiver<ProtectionEvent>,
+}
+
+impl ProtectionMonitor {
+    // Only events for regions the filter selects are sent: a change is checked against the
+    // region before and after it and sent if either matches, so RW -> RX passes an
+    // executable-only filter. The monitor stops by itself once `memory` is dropped or closed.
+    pub fn start(memory: &Arc<Memory>, filter: RegionFilter, interval: Duration) -> Self {
+        Self::start_with_dumps(memory, filter, interval, None)
+    }
+
+    // Also dumps pages to `dump_dir` at the tick they turn executable, as
+    // "<sequence>-<address>.bin" numbered from 0. Failing to create the directory only turns
+    // dumping off.
+    pub fn start_with_dumps(
+        memory: &Arc<Memory>,
+        filter: RegionFilter,
+        interval: Duration,
+        dump_dir: Option<PathBuf>,
+    ) -> Self {
+        let dump_dir = dump_dir.filter(|dir| match fs::create_dir_all(dir) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("not dumping to {}: {}", dir.display(), e);
+                false
+            }
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, events) = mpsc::channel();
+
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let dumper = dump_dir.map(|dir| Dumper { dir, sequence: 0 });
+            spawn_worker(memory, move |memory| run(memory, &filter, dumper, interval, &stop, &sender))
+        };
+
+        ProtectionMonitor { stop, thread, events }
+    }
+
+    pub fn events(&self) -> &Receiver<ProtectionEvent> {
+        &self.events
+    }
+
+    // Returns the number of ticks run.
+    pub fn stop(self) -> u64 {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+        self.thread.join().unwrap_or_default()
+    }
+}
+
+struct Dumper {
+    dir: PathBuf,
+    sequence: u64,
+}
+
+impl Dumper {
+    fn dump(&mut self, memory: &Memory, range: Range<usize>) -> Option<PathBuf> {
+        let path = self.dir.join(format!("{:04}-{:X}.bin", self.sequence, range.start));
+        self.sequence += 1;
+        let written = memory.read_bytes(range.start, range.len()).and_then(|bytes| fs::write(&path, bytes));
+        match written {
+            Ok(()) => Some(path),
+            Err(e) => {
+                warn!("failed to dump 0x{:X}-0x{:X} to {}: {}", range.start, range.end, path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+// The committed regions right now, None if the map couldn't be read.
+pub(crate) fn committed(memory: &Memory) -> Option<Vec<MemoryRegion>> {
+    match memory.full_map() {
+        Ok(entries) => Some(
+            entries
+                .into_iter()
+                .filter_map(|entry| match entry {
+                    MapEntry::Committed(region) => Some(region),
+                    _ => None,
+                })
+                .collect(),
+        ),
+        Err(e) => {
+            debug!("region map not available this tick: {}", e);
+            None
+        }
+    }
+}
+
+fn run(
+    memory: &Weak<Memory>,
+    filter: &RegionFilter,
+    mut dumper: Option<Dumper>,
+    interval: Duration,
+    stop: &AtomicBool,
+    sender: &Sender<ProtectionEvent>,
+) -> u64 {
+    let mut previous: Option<Vec<MemoryRegion>> = None;
+    let mut tick = 0;
+
+    while !stop.load(Ordering::SeqCst) {
+        let Some(memory) = upgrade(memory) else {
+            break;
+        };
+        let _priority = memory.apply_thread_priority();
+        if let Some(current) = committed(&memory) {
+            if let Some(previous) = &previous {
+                let diff = region_snapshot::diff_regions(previous, &current);
+                let matches = |regions: &[MemoryRegion], address: usize| {
+                    regions.iter().find(|region| region.contains(address)).is_some_and(|region| filter.matches(region))
+                };
+
+                let changed = diff
+                    .protection_changed
+                    .into_iter()
+                    .filter(|change| {
+                        matches(previous, change.start_address) || matches(&current, change.start_address)
+                    })
+                    .map(ProtectionEventKind::Changed);
+                let appeared =
+                    diff.added.into_iter().filter(|region| filter.matches(region)).map(ProtectionEventKind::Appeared);
+                let disappeared = diff
+                    .removed
+                    .into_iter()
+                    .filter(|region| filter.matches(region))
+                    .map(ProtectionEventKind::Disappeared);
+
+                for kind in changed.chain(appeared).chain(disappeared) {
+                    let mut event = ProtectionEvent { tick, kind, dump: None };
+                    if event.became_executable() {
+                        event.dump = dumper.as_mut().and_then(|dumper| dumper.dump(&memory, event.range()));
+                    }
+                    let _ = sender.send(event);
+                }
+            }
+            previous = Some(current);
+        }
+
+        tick += 1;
+        drop(memory);
+        thread::park_timeout(interval);
+    }
+
+    debug!("protection monitor stopped after {} ticks", tick);
+    tick
+}
@@ -0,0 +1,138 @@
+// Python bindings, built as the `nirvana_notject` extension module with maturin (see
+// pyproject.toml). Failures raise `OSError` carrying the Windows error code when there is one.
+
+use std::io;
+
+use pyo3::exceptions::PyOSError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::pattern::Pattern;
+
+#[pyclass(name = "Memory", module = "nirvana_notject")]
+pub struct PyMemory {
+    memory: Memory,
+}
+
+#[pymethods]
+impl PyMemory {
+    // Opens the process and caches its regions and modules.
+    #[staticmethod]
+    fn open(pid: u32) -> PyResult<Self> {
+        let mut memory = Memory::open(pid).map_err(os_error)?;
+        memory.scan_memory().map_err(os_error)?;
+        memory.scan_modules().map_err(os_error)?;
+        Ok(PyMemory { memory })
+    }
+
+    #[getter]
+    fn pid(&self) -> u32 {
+        self.memory.process_id()
+    }
+
+    // Re-reads the region and module lists.
+    fn refresh(&mut self) -> PyResult<()> {
+        self.memory.scan_memory().map_err(os_error)?;
+        self.memory.scan_modules().map_err(os_error)
+    }
+
+    fn read<'py>(&self, py: Python<'py>, address: usize, size: usize) -> PyResult<Bound<'py, PyBytes>> {
+        let bytes = self.memory.read_bytes(address, size).map_err(os_error)?;
+        Ok(PyBytes::new_bound(py, &bytes))
+    }
+
+    fn read_u8(&self, address: usize) -> PyResult<u8> {
+        self.memory.read_memory(address).map_err(os_error)
+    }
+
+    fn read_u16(&self, address: usize) -> PyResult<u16> {
+        self.memory.read_memory(address).map_err(os_error)
+    }
+
+    fn read_u32(&self, address: usize) -> PyResult<u32> {
+        self.memory.read_memory(address).map_err(os_error)
+    }
+
+    fn read_u64(&self, address: usize) -> PyResult<u64> {
+        self.memory.read_memory(address).map_err(os_error)
+    }
+
+    fn read_i32(&self, address: usize) -> PyResult<i32> {
+        self.memory.read_memory(address).map_err(os_error)
+    }
+
+    fn read_i64(&self, address: usize) -> PyResult<i64> {
+        self.memory.read_memory(address).map_err(os_error)
+    }
+
+    fn read_f32(&self, address: usize) -> PyResult<f32> {
+        self.memory.read_memory(address).map_err(os_error)
+    }
+
+    fn read_f64(&self, address: usize) -> PyResult<f64> {
+        self.memory.read_memory(address).map_err(os_error)
+    }
+
+    fn write(&self, address: usize, data: &[u8]) -> PyResult<()> {
+        self.memory.write_bytes(address, data).map_err(os_error)
+    }
+
+    // One dict per cached region: start, end, size, protection ("rw-"), type, and the raw
+    // protection flags.
+    fn regions<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.memory
+            .regions()
+            .map(|region| {
+                let dict = PyDict::new_bound(py);
+                dict.set_item("start", region.start_address.get())?;
+                dict.set_item("end", region.end().get())?;
+                dict.set_item("size", region.size.get())?;
+                dict.set_item("protection", region.protection_string())?;
+                dict.set_item("raw_protection", region.protection)?;
+                dict.set_item("type", region.region_type.name())?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    fn modules<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.memory
+            .get_modules()
+            .iter()
+            .map(|module| {
+                let dict = PyDict::new_bound(py);
+                dict.set_item("name", &module.name)?;
+                dict.set_item("path", &module.path)?;
+                dict.set_item("base", module.base_address)?;
+                dict.set_item("size", module.size)?;
+                Ok(dict)
+            })
+            .collect()
+    }
+
+    // First match of a `Pattern::parse` pattern such as "48 8B ?? 90", or None. The GIL is
+    // released while scanning.
+    fn find_pattern(&self, py: Python<'_>, pattern: &str) -> PyResult<Option<usize>> {
+        let pattern = Pattern::parse(pattern).map_err(os_error)?;
+        py.allow_threads(|| self.memory.find_pattern(&pattern, &RegionFilter::new()))
+            .map_err(os_error)
+    }
+
+    fn find_pattern_all(&self, py: Python<'_>, pattern: &str) -> PyResult<Vec<usize>> {
+        let pattern = Pattern::parse(pattern).map_err(os_error)?;
+        py.allow_threads(|| self.memory.find_pattern_all(&pattern, &RegionFilter::new()))
+            .map_err(os_error)
+    }
+}
+
+#[pymodule]
+fn nirvana_notject(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyMemory>()
+}
+
+// OSError(errno, message); errno is 0 for failures that didn't come from the OS.
+fn os_error(e: io::Error) -> PyErr {
+    PyOSError::new_err((e.raw_os_error().unwrap_or(0), e.to_string()))
+}
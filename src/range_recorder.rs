@@ -0,0 +1,376 @@
+// The last stretch of a fast-changing structure's history, to scrub through after something
+// interesting happened. A background thread samples one byte range per interval into a ring
+// held to a fixed byte budget; `freeze` copies out what it holds:
+//
+//     let recorder = RangeRecorder::start(&memory, player..player + 0x200, Duration::from_millis(10), 1 << 20)?;
+//     // ... wait for the glitch ...
+//     let timeline = recorder.freeze();
+//     let before = timeline.at(glitch - Duration::from_millis(100));
+//     for change in timeline.diff(glitch - Duration::from_millis(100), glitch).unwrap_or_default() {
+//         println!("0x{:X}: {:02X} -> {:02X}", change.address, change.old, change.new);
+//     }
+//     timeline.export("glitch")?;
+//
+// The ring keeps the oldest sample whole and every later one as the runs of bytes that
+// changed since the one before it (`with_delta(false)` stores each whole instead), so a
+// mostly still structure costs little per sample. Everything it holds, the whole sample, the
+// runs and their bookkeeping, counts against `capacity`; the oldest samples are dropped to
+// stay within it, and `usage` reports where it stands. Samples that can't be read in full are
+// skipped and counted. The recorder stops by itself once `memory` is dropped or closed.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::code_watch::{diff_bytes, ByteChange};
+use crate::memory::Memory;
+use crate::process_handle::{spawn_worker, upgrade};
+
+// Bookkeeping counted per sample slot of the ring and per run of changed bytes.
+const SAMPLE_OVERHEAD: usize = mem::size_of::<Sample>();
+const RUN_OVERHEAD: usize = mem::size_of::<(u32, Vec<u8>)>();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RecorderUsage {
+    // Bytes held, never more than `capacity`.
+    pub bytes: usize,
+    pub capacity: usize,
+    pub samples: usize,
+    // Samples dropped to stay within the capacity.
+    pub evicted: u64,
+    // Samples skipped because the range couldn't be read in full.
+    pub missed: u64,
+}
+
+#[derive(Debug, Clone)]
+struct Sample {
+    at: Instant,
+    // Runs of bytes that differ from the sample before, as (offset, bytes). Empty for the
+    // oldest sample, which is `Ring::oldest`.
+    runs: Vec<(u32, Vec<u8>)>,
+}
+
+impl Sample {
+    // Heap bytes of the runs; the sample itself lives in a slot of the ring.
+    fn cost(&self) -> usize {
+        self.runs.iter().map(|(_, bytes)| RUN_OVERHEAD + bytes.len()).sum::<usize>()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Ring {
+    range: Range<usize>,
+    delta: bool,
+    // Contents at the oldest sample, and at the newest, which the next delta is taken against.
+    oldest: Vec<u8>,
+    newest: Vec<u8>,
+    samples: VecDeque<Sample>,
+    // Bytes of all the samples' runs.
+    run_bytes: usize,
+    // `bytes` is the runs plus every slot of `samples`, used or spare.
+    usage: RecorderUsage,
+}
+
+impl Ring {
+    fn push(&mut self, at: Instant, bytes: &[u8]) {
+        if self.samples.is_empty() {
+            self.oldest.copy_from_slice(bytes);
+            self.newest.copy_from_slice(bytes);
+            self.add(Sample { at, runs: Vec::new() });
+            return;
+        }
+
+        let runs = match self.delta {
+            true => changed_runs(&self.newest, bytes),
+            false => vec![(0, bytes.to_vec())],
+        };
+        self.newest.copy_from_slice(bytes);
+        self.add(Sample { at, runs });
+    }
+
+    fn add(&mut self, sample: Sample) {
+        // Rather than grow a full ring past the capacity, make room in it. Growing at least
+        // doubles it.
+        let slots = self.samples.capacity();
+        if self.samples.len() == slots && self.samples.len() > 1 {
+            let grown = self.run_bytes + sample.cost() + 2 * slots * SAMPLE_OVERHEAD;
+            if grown > self.usage.capacity {
+                self.evict();
+            }
+        }
+        self.run_bytes += sample.cost();
+        self.samples.push_back(sample);
+        while self.bytes() > self.usage.capacity && self.samples.len() > 1 {
+            self.evict();
+        }
+        if self.bytes() > self.usage.capacity {
+            self.samples.shrink_to_fit();
+        }
+        self.usage.bytes = self.bytes();
+        self.usage.samples = self.samples.len();
+    }
+
+    fn bytes(&self) -> usize {
+        self.run_bytes + self.samples.capacity() * SAMPLE_OVERHEAD
+    }
+
+    // Drops the oldest sample; the next one becomes the oldest and its runs go into `oldest`.
+    fn evict(&mut self) {
+        self.samples.pop_front();
+        let next = self.samples.front_mut().expect("evict is only called with two samples or more");
+        self.run_bytes -= next.cost();
+        apply(&mut self.oldest, &next.runs);
+        next.runs = Vec::new();
+        self.usage.evicted += 1;
+    }
+}
+
+// Samples a byte range from a background thread until stopped.
+pub struct RangeRecorder {
+    stop: Arc<AtomicBool>,
+    ring: Arc<Mutex<Ring>>,
+    thread: JoinHandle<()>,
+}
+
+impl RangeRecorder {
+    // Delta-compresses samples; `capacity` is the byte budget for everything held.
+    pub fn start(memory: &Arc<Memory>, range: Range<usize>, interval: Duration, capacity: usize) -> io::Result<Self> {
+        Self::with_delta(memory, range, interval, capacity, true)
+    }
+
+    // Fails if `capacity` can't hold the oldest sample and one more stored whole, the least
+    // the ring needs to keep recording.
+    pub fn with_delta(
+        memory: &Arc<Memory>,
+        range: Range<usize>,
+        interval: Duration,
+        capacity: usize,
+        delta: bool,
+    ) -> io::Result<Self> {
+        if range.is_empty() || range.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Cannot record 0x{:X}-0x{:X}", range.start, range.end),
+            ));
+        }
+        if interval.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The sampling interval must not be zero"));
+        }
+        // The two whole copies, two slots and one sample stored whole.
+        let least = 3 * range.len() + 2 * SAMPLE_OVERHEAD + RUN_OVERHEAD;
+        if capacity < least {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "A capacity of {} bytes can't record 0x{:X} bytes; it needs at least {}",
+                    capacity,
+                    range.len(),
+                    least
+                ),
+            ));
+        }
+
+        // The two whole copies are allocated up front and counted apart from the ring.
+        let samples = VecDeque::with_capacity(2);
+        let ring = Ring {
+            range: range.clone(),
+            delta,
+            oldest: vec![0; range.len()],
+            newest: vec![0; range.len()],
+            usage: RecorderUsage {
+                bytes: samples.capacity() * SAMPLE_OVERHEAD,
+                capacity: capacity - 2 * range.len(),
+                ..RecorderUsage::default()
+            },
+            samples,
+            run_bytes: 0,
+        };
+        let ring = Arc::new(Mutex::new(ring));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = {
+            let (stop, ring) = (Arc::clone(&stop), Arc::clone(&ring));
+            spawn_worker(memory, move |memory| run(memory, range, interval, &stop, &ring))
+        };
+        Ok(RangeRecorder { stop, ring, thread })
+    }
+
+    // What the ring holds now; recording goes on.
+    pub fn freeze(&self) -> Timeline {
+        Timeline::from_ring(&self.ring.lock().unwrap())
+    }
+
+    pub fn usage(&self) -> RecorderUsage {
+        let ring = self.ring.lock().unwrap();
+        with_copies(ring.usage, ring.range.len())
+    }
+
+    // Stops recording and returns what the ring holds.
+    pub fn stop(self) -> Timeline {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+        let _ = self.thread.join();
+        Timeline::from_ring(&self.ring.lock().unwrap())
+    }
+}
+
+// The ring's usage with the two whole copies counted in, as the caller sized it.
+fn with_copies(usage: RecorderUsage, len: usize) -> RecorderUsage {
+    RecorderUsage { bytes: usage.bytes + 2 * len, capacity: usage.capacity + 2 * len, ..usage }
+}
+
+fn run(memory: &Weak<Memory>, range: Range<usize>, interval: Duration, stop: &AtomicBool, ring: &Mutex<Ring>) {
+    let mut buffer = vec![0u8; range.len()];
+    let mut next = Instant::now();
+    while !stop.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if next > now {
+            thread::park_timeout(next - now);
+            continue;
+        }
+        let Some(memory) = upgrade(memory) else {
+            break;
+        };
+        let _priority = memory.apply_thread_priority();
+        let read = memory.read_bulk(range.start, &mut buffer);
+        drop(memory);
+
+        let mut ring = ring.lock().unwrap();
+        match read {
+            Ok(len) if len == buffer.len() => ring.push(now, &buffer),
+            _ => ring.usage.missed += 1,
+        }
+        drop(ring);
+
+        // Ticks missed while the target was slow to read are skipped, not made up.
+        next += interval;
+        while next < Instant::now() {
+            next += interval;
+        }
+    }
+    debug!("range recorder for 0x{:X}-0x{:X} stopped", range.start, range.end);
+}
+
+// A frozen copy of a recorder's ring.
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    range: Range<usize>,
+    oldest: Vec<u8>,
+    samples: Vec<Sample>,
+    usage: RecorderUsage,
+}
+
+impl Timeline {
+    fn from_ring(ring: &Ring) -> Self {
+        Timeline {
+            range: ring.range.clone(),
+            oldest: ring.oldest.clone(),
+            samples: ring.samples.iter().cloned().collect(),
+            usage: with_copies(ring.usage, ring.range.len()),
+        }
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // When each sample was taken, oldest first.
+    pub fn times(&self) -> Vec<Instant> {
+        self.samples.iter().map(|sample| sample.at).collect()
+    }
+
+    // The recorder's usage when this was frozen.
+    pub fn usage(&self) -> RecorderUsage {
+        self.usage
+    }
+
+    // The contents of sample `index`, 0 being the oldest.
+    pub fn sample(&self, index: usize) -> Option<Vec<u8>> {
+        if index >= self.samples.len() {
+            return None;
+        }
+        let mut bytes = self.oldest.clone();
+        for sample in &self.samples[1..=index] {
+            apply(&mut bytes, &sample.runs);
+        }
+        Some(bytes)
+    }
+
+    // The contents as of `instant`: the last sample taken at or before it. None before the
+    // oldest sample.
+    pub fn at(&self, instant: Instant) -> Option<Vec<u8>> {
+        self.index_at(instant).and_then(|index| self.sample(index))
+    }
+
+    // Every byte that differs between the contents as of `a` and as of `b`, by address.
+    pub fn diff(&self, a: Instant, b: Instant) -> Option<Vec<ByteChange>> {
+        let (old, new) = (self.at(a)?, self.at(b)?);
+        Some(diff_bytes(self.range.start, &old, &new, usize::MAX).0)
+    }
+
+    // Writes every sample to `dir` as "<index>-<milliseconds after the oldest>.bin" and
+    // returns the paths, oldest first.
+    pub fn export(&self, dir: impl AsRef<Path>) -> io::Result<Vec<PathBuf>> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let Some(first) = self.samples.first() else {
+            return Ok(Vec::new());
+        };
+        let mut bytes = self.oldest.clone();
+        let mut paths = Vec::new();
+        for (index, sample) in self.samples.iter().enumerate() {
+            apply(&mut bytes, &sample.runs);
+            let elapsed = sample.at.duration_since(first.at).as_millis();
+            let path = dir.join(format!("{:05}-{}.bin", index, elapsed));
+            fs::write(&path, &bytes)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    fn index_at(&self, instant: Instant) -> Option<usize> {
+        self.samples.partition_point(|sample| sample.at <= instant).checked_sub(1)
+    }
+}
+
+// The runs of `new` that differ from `old`, as (offset, bytes).
+fn changed_runs(old: &[u8], new: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut runs = Vec::new();
+    let mut offset = 0;
+    while offset < new.len() {
+        if old[offset] == new[offset] {
+            offset += 1;
+            continue;
+        }
+        let start = offset;
+        while offset < new.len() && old[offset] != new[offset] {
+            offset += 1;
+        }
+        runs.push((start as u32, new[start..offset].to_vec()));
+    }
+    runs
+}
+
+fn apply(bytes: &mut [u8], runs: &[(u32, Vec<u8>)]) {
+    for (offset, run) in runs {
+        let offset = *offset as usize;
+        bytes[offset..offset + run.len()].copy_from_slice(run);
+    }
+}
@@ -0,0 +1,507 @@
+// Every read a scan makes, recorded so the same scan can be replayed somewhere else. When a
+// scan misses something on one machine, its log holds exactly what that scan saw:
+//
+//     memory.scan_memory()?;
+//     memory.start_read_log();
+//     let found = memory.find_pattern_all(&pattern, &filter)?;
+//     let log = memory.take_read_log().unwrap();
+//     log.redact(&[pattern.clone()]).save("scan.nnlog")?;
+//
+//     // elsewhere
+//     let replayed = Memory::from_replay(ReplayBackend::new(ReadLog::load("scan.nnlog")?));
+//     assert_eq!(replayed.find_pattern_all(&pattern, &filter)?, found);
+//
+// Each entry is one call of the central read path: the address, the length asked for, the
+// bytes that came back and, for failures, the error. The log also keeps the cached region
+// list as it was when recording stopped, which is what a replayed `Memory` scans.
+//
+// A replayed `Memory` has no process behind it. Reads are served from the log only, and one
+// the recording never made fails with `NotFound`, counted in `ReplayBackend::misses`; with
+// the same pattern, filter and options a scan makes the same reads in the same order, so a
+// miss means the replay took another path. Everything else — region queries, modules,
+// writes — fails as it would on a closed handle.
+//
+// `redact` keeps the bytes of matches of the given patterns and overwrites everything else
+// with a salted hash of it, so a log can be shared without the rest of the target's memory.
+// The kept patterns find the same matches in the redacted log (barring a chance match in the
+// hashed bytes); other patterns don't.
+//
+// File layout, little-endian: the header (see persist.rs), u32 process id, u32 region count
+// and the regions, u64 read count and the reads. The bytes of each read are PackBits-
+// compressed: memory is mostly runs of zeros and padding. Version 1 had only a magic "NNRLOG"
+// and a u16 version before the same body.
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::debug;
+
+use crate::address::{Address, Size};
+use crate::backend::Backend;
+use crate::hash::Fnv1a;
+use crate::memory::{GuardPageError, Memory, MemoryRegion, RegionType};
+use crate::pattern::{self, Pattern};
+use crate::persist::{Format, Header, Legacy, Persist};
+
+// Error kinds a read can fail with, by their code in the file; anything else is saved as
+// `Other`, code 0.
+const ERROR_KINDS: [io::ErrorKind; 8] = [
+    io::ErrorKind::Other,
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::TimedOut,
+    io::ErrorKind::UnexpectedEof,
+    io::ErrorKind::InvalidInput,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::Interrupted,
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedError {
+    pub kind: io::ErrorKind,
+    pub message: String,
+    // Where the read hit a PAGE_GUARD page, when that was why it failed.
+    pub guard_page: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedRead {
+    pub address: usize,
+    pub len: usize,
+    // What came back: `len` bytes, fewer for a partial read, none for a failed one.
+    pub bytes: Vec<u8>,
+    pub error: Option<RecordedError>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReadLog {
+    pub process_id: u32,
+    pub regions: Vec<MemoryRegion>,
+    // In the order they were made.
+    pub reads: Vec<RecordedRead>,
+}
+
+impl ReadLog {
+    pub fn new(process_id: u32) -> Self {
+        ReadLog { process_id, ..Default::default() }
+    }
+
+    pub(crate) fn record(&mut self, address: usize, len: usize, bytes: &[u8], result: &io::Result<usize>) {
+        let error = result.as_ref().err().map(|e| RecordedError {
+            kind: e.kind(),
+            message: e.to_string(),
+            guard_page: e.get_ref().and_then(|inner| inner.downcast_ref::<GuardPageError>()).map(|guard| guard.address),
+        });
+        self.reads.push(RecordedRead { address, len, bytes: bytes.to_vec(), error });
+    }
+
+    // Bytes read successfully, counting each read.
+    pub fn bytes_read(&self) -> usize {
+        self.reads.iter().map(|read| read.bytes.len()).sum()
+    }
+
+    // A copy where only the bytes of matches of `keep` are left as they were. Matches are
+    // looked for across the bytes of all reads put together, so one split between two reads
+    // is kept too.
+    pub fn redact(&self, keep: &[Pattern]) -> ReadLog {
+        let mut kept: Vec<Range<usize>> = Vec::new();
+        for (start, bytes) in self.contiguous_runs() {
+            for pattern in keep {
+                kept.extend(pattern::search(&bytes, pattern).into_iter().map(|offset| {
+                    let address = start + offset;
+                    address..address + pattern.len()
+                }));
+            }
+        }
+        kept.sort_by_key(|range| range.start);
+
+        // Thrown away afterwards, so the hashes can't be brute-forced back into short runs.
+        let salt = RandomState::new().hash_one(self.process_id);
+        let reads = self
+            .reads
+            .iter()
+            .map(|read| RecordedRead { bytes: redact_bytes(read.address, &read.bytes, &kept, salt), ..read.clone() })
+            .collect();
+        ReadLog { process_id: self.process_id, regions: self.regions.clone(), reads }
+    }
+
+    // The bytes of the successful reads merged into (start address, bytes) runs, lowest first.
+    fn contiguous_runs(&self) -> Vec<(usize, Vec<u8>)> {
+        let mut reads: Vec<&RecordedRead> = self.reads.iter().filter(|read| !read.bytes.is_empty()).collect();
+        reads.sort_by_key(|read| read.address);
+
+        let mut runs: Vec<(usize, Vec<u8>)> = Vec::new();
+        for read in reads {
+            match runs.last_mut() {
+                Some((start, bytes)) if read.address <= *start + bytes.len() => {
+                    let overlap = *start + bytes.len() - read.address;
+                    if let Some(tail) = read.bytes.get(overlap..) {
+                        bytes.extend_from_slice(tail);
+                    }
+                }
+                _ => runs.push((read.address, read.bytes.clone())),
+            }
+        }
+        runs
+    }
+
+    // Written whole or not at all; see atomic_file.rs.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        self.write_versioned(path)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(ReadLog::read_versioned(path)?.1)
+    }
+}
+
+impl Persist for ReadLog {
+    const FORMAT: Format =
+        Format { id: "readlog", version: 2, legacy: Some(Legacy { magic: b"NNRLOG", version_len: 2 }) };
+
+    fn write_body(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        out.extend_from_slice(&self.process_id.to_le_bytes());
+
+        out.extend_from_slice(&(self.regions.len() as u32).to_le_bytes());
+        for region in &self.regions {
+            put_region(out, region);
+        }
+
+        out.extend_from_slice(&(self.reads.len() as u64).to_le_bytes());
+        for read in &self.reads {
+            out.extend_from_slice(&(read.address as u64).to_le_bytes());
+            out.extend_from_slice(&(read.len as u64).to_le_bytes());
+            match &read.error {
+                None => out.push(0),
+                Some(error) => {
+                    out.push(if error.guard_page.is_some() { 2 } else { 1 });
+                    out.push(ERROR_KINDS.iter().position(|kind| *kind == error.kind).unwrap_or(0) as u8);
+                    let message = &error.message.as_bytes()[..error.message.len().min(u16::MAX as usize)];
+                    out.extend_from_slice(&(message.len() as u16).to_le_bytes());
+                    out.extend_from_slice(message);
+                    if let Some(address) = error.guard_page {
+                        out.extend_from_slice(&(address as u64).to_le_bytes());
+                    }
+                }
+            }
+            let packed = pack(&read.bytes);
+            out.extend_from_slice(&(read.bytes.len() as u64).to_le_bytes());
+            out.extend_from_slice(&(packed.len() as u64).to_le_bytes());
+            out.extend_from_slice(&packed);
+        }
+
+        let (reads, bytes) = (self.reads.len(), self.bytes_read());
+        debug!("saving read log: {} reads, 0x{:X} bytes packed into 0x{:X}", reads, bytes, out.len());
+        Ok(())
+    }
+
+    // Versions 1 and 2 differ only in the header.
+    fn read_body(_: &Header, body: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { bytes: body, position: 0 };
+        let process_id = reader.u32()?;
+
+        let regions = (0..reader.u32()?).map(|_| read_region(&mut reader)).collect::<io::Result<Vec<_>>>()?;
+
+        let count = reader.u64()?;
+        let mut reads = Vec::new();
+        for _ in 0..count {
+            let address = reader.u64()? as usize;
+            let len = reader.u64()? as usize;
+            let error = match reader.u8()? {
+                0 => None,
+                status @ (1 | 2) => {
+                    let kind = *ERROR_KINDS.get(reader.u8()? as usize).unwrap_or(&io::ErrorKind::Other);
+                    let message_len = reader.u16()? as usize;
+                    let message = String::from_utf8_lossy(reader.take(message_len)?).into_owned();
+                    let guard_page = if status == 2 { Some(reader.u64()? as usize) } else { None };
+                    Some(RecordedError { kind, message, guard_page })
+                }
+                status => return Err(invalid(format!("Unknown read status {}", status))),
+            };
+            let unpacked_len = reader.u64()? as usize;
+            if unpacked_len > len {
+                return Err(invalid(format!(
+                    "Read at 0x{:X} holds 0x{:X} bytes, more than the 0x{:X} asked for",
+                    address, unpacked_len, len
+                )));
+            }
+            let packed_len = reader.u64()? as usize;
+            let bytes = unpack(reader.take(packed_len)?, unpacked_len)?;
+            reads.push(RecordedRead { address, len, bytes, error });
+        }
+
+        Ok(ReadLog { process_id, regions, reads })
+    }
+}
+
+// Serves reads from a `ReadLog` instead of a process. See `Memory::from_replay`.
+#[derive(Debug)]
+pub struct ReplayBackend {
+    log: ReadLog,
+    // (address, len) -> index of the first read made with them.
+    exact: HashMap<(usize, usize), usize>,
+    misses: AtomicU64,
+}
+
+impl ReplayBackend {
+    pub fn new(log: ReadLog) -> Self {
+        let mut exact = HashMap::new();
+        for (index, read) in log.reads.iter().enumerate() {
+            exact.entry((read.address, read.len)).or_insert(index);
+        }
+        ReplayBackend { log, exact, misses: AtomicU64::new(0) }
+    }
+
+    pub fn log(&self) -> &ReadLog {
+        &self.log
+    }
+
+    // Reads asked for that the log couldn't serve.
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+impl Backend for ReplayBackend {
+    // A read made while recording with the same address and length replays as it went then,
+    // failure included. Otherwise any successful read whose bytes cover the range serves it.
+    fn read(&self, address: usize, buffer: &mut [u8]) -> io::Result<usize> {
+        if let Some(&index) = self.exact.get(&(address, buffer.len())) {
+            let read = &self.log.reads[index];
+            let copied = read.bytes.len().min(buffer.len());
+            buffer[..copied].copy_from_slice(&read.bytes[..copied]);
+            return match &read.error {
+                None => Ok(copied),
+                Some(RecordedError { guard_page: Some(guard), .. }) => {
+                    Err(io::Error::new(io::ErrorKind::PermissionDenied, GuardPageError { address: *guard }))
+                }
+                Some(error) => Err(io::Error::new(error.kind, error.message.clone())),
+            };
+        }
+
+        let end = address.checked_add(buffer.len());
+        let covering = self.log.reads.iter().find(|read| {
+            let read_end = read.address.checked_add(read.bytes.len());
+            let covers = end.zip(read_end).is_some_and(|(end, read_end)| end <= read_end);
+            read.error.is_none() && read.address <= address && covers
+        });
+        match covering {
+            Some(read) => {
+                let offset = address - read.address;
+                buffer.copy_from_slice(&read.bytes[offset..offset + buffer.len()]);
+                Ok(buffer.len())
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Read of 0x{:X} bytes at 0x{:X} is not in the read log", buffer.len(), address),
+                ))
+            }
+        }
+    }
+
+    fn regions(&self) -> Option<Vec<MemoryRegion>> {
+        Some(self.log.regions.clone())
+    }
+
+    fn as_replay(&self) -> Option<&ReplayBackend> {
+        Some(self)
+    }
+}
+
+impl Memory {
+    // Records every read from now on, replacing a log already being recorded.
+    pub fn start_read_log(&self) {
+        *self.read_log_slot().lock().unwrap() = Some(ReadLog::new(self.process_id()));
+    }
+
+    // Stops recording and returns the log, with the cached regions as they are now.
+    pub fn take_read_log(&self) -> Option<ReadLog> {
+        let mut log = self.read_log_slot().lock().unwrap().take()?;
+        log.regions = self.cached_regions().to_vec();
+        Some(log)
+    }
+
+    // A `Memory` that reads from the log instead of a process, with its regions already
+    // cached; `scan_memory` keeps them.
+    pub fn from_replay(backend: ReplayBackend) -> Memory {
+        Memory::with_replay(backend)
+    }
+}
+
+// Hashed replacements for the bytes of a read at `address` outside `kept` (sorted by start).
+fn redact_bytes(address: usize, bytes: &[u8], kept: &[Range<usize>], salt: u64) -> Vec<u8> {
+    let end = address + bytes.len();
+    let mut out = bytes.to_vec();
+    let mut position = address;
+    for range in kept.iter().filter(|range| range.end > address && range.start < end) {
+        if range.start > position {
+            hash_over(&mut out[position - address..range.start - address], salt);
+        }
+        position = position.max(range.end);
+    }
+    if position < end {
+        hash_over(&mut out[position - address..], salt);
+    }
+    out
+}
+
+// Overwrites `run` with a byte stream derived from its contents and the salt.
+fn hash_over(run: &mut [u8], salt: u64) {
+    let mut hasher = Fnv1a::with_seed(salt);
+    hasher.update(run);
+    let seed = hasher.finish();
+    for (index, chunk) in run.chunks_mut(8).enumerate() {
+        let mut hasher = Fnv1a::with_seed(seed);
+        hasher.update(&(index as u64).to_le_bytes());
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes()[..chunk.len()]);
+    }
+}
+
+// PackBits: a control byte below 0x80 is followed by that many plus one literal bytes; one
+// from 0x80 up repeats the next byte (control - 0x80 + 3) times.
+fn pack(bytes: &[u8]) -> Vec<u8> {
+    const MAX_LITERAL: usize = 0x80;
+    const MAX_REPEAT: usize = 0x7F + 3;
+
+    let mut out = Vec::with_capacity(bytes.len() / 2);
+    let mut literal_start = 0;
+    let mut position = 0;
+    let flush = |out: &mut Vec<u8>, literal: &[u8]| {
+        for chunk in literal.chunks(MAX_LITERAL) {
+            out.push((chunk.len() - 1) as u8);
+            out.extend_from_slice(chunk);
+        }
+    };
+    while position < bytes.len() {
+        let byte = bytes[position];
+        let run = bytes[position..].iter().take(MAX_REPEAT).take_while(|&&other| other == byte).count();
+        if run >= 3 {
+            flush(&mut out, &bytes[literal_start..position]);
+            out.push((0x80 + run - 3) as u8);
+            out.push(byte);
+            position += run;
+            literal_start = position;
+        } else {
+            position += 1;
+        }
+    }
+    flush(&mut out, &bytes[literal_start..]);
+    out
+}
+
+fn unpack(packed: &[u8], len: usize) -> io::Result<Vec<u8>> {
+    let truncated = || invalid("Truncated read log data");
+    // A run packs at most 130 bytes into two, so a corrupt length can't allocate much more.
+    let mut out = Vec::with_capacity(len.min(packed.len().saturating_mul(65)));
+    let mut position = 0;
+    while position < packed.len() {
+        let control = packed[position] as usize;
+        position += 1;
+        if control < 0x80 {
+            let literal = packed.get(position..position + control + 1).ok_or_else(truncated)?;
+            out.extend_from_slice(literal);
+            position += control + 1;
+        } else {
+            let byte = *packed.get(position).ok_or_else(truncated)?;
+            out.resize(out.len() + control - 0x80 + 3, byte);
+            position += 1;
+        }
+    }
+    if out.len() != len {
+        return Err(invalid(format!("Read log data unpacks to 0x{:X} bytes, not 0x{:X}", out.len(), len)));
+    }
+    Ok(out)
+}
+
+fn put_region(out: &mut Vec<u8>, region: &MemoryRegion) {
+    out.extend_from_slice(&(region.start_address.get() as u64).to_le_bytes());
+    out.extend_from_slice(&(region.size.get() as u64).to_le_bytes());
+    out.extend_from_slice(&(region.allocation_base.get() as u64).to_le_bytes());
+    out.extend_from_slice(&region.protection.to_le_bytes());
+    out.push(match region.region_type {
+        RegionType::Image => 0,
+        RegionType::Mapped => 1,
+        RegionType::Private => 2,
+        RegionType::Unknown => 3,
+    });
+    let flags = [
+        region.is_readable,
+        region.is_writable,
+        region.is_executable,
+        region.is_guard,
+        region.is_copy_on_write,
+        region.is_inferred,
+    ];
+    out.push(flags.iter().enumerate().fold(0, |bits, (bit, &set)| bits | (set as u8) << bit));
+}
+
+fn read_region(reader: &mut Reader) -> io::Result<MemoryRegion> {
+    let start_address = Address::from(reader.u64()? as usize);
+    let size = Size::from(reader.u64()? as usize);
+    let allocation_base = Address::from(reader.u64()? as usize);
+    let protection = reader.u32()?;
+    let region_type = match reader.u8()? {
+        0 => RegionType::Image,
+        1 => RegionType::Mapped,
+        2 => RegionType::Private,
+        _ => RegionType::Unknown,
+    };
+    let flags = reader.u8()?;
+    let flag = |bit: u8| flags & (1 << bit) != 0;
+    Ok(MemoryRegion {
+        start_address,
+        size,
+        allocation_base,
+        is_readable: flag(0),
+        is_writable: flag(1),
+        is_executable: flag(2),
+        is_guard: flag(3),
+        is_copy_on_write: flag(4),
+        protection,
+        region_type,
+        is_inferred: flag(5),
+    })
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.saturating_add(len))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated read log"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
@@ -0,0 +1,198 @@
+// A range of the target as a `std::io` stream, read a chunk at a time, so code that takes a
+// `Read + Seek` (hashers, dumpers, third-party parsers such as goblin or object) can work on
+// target memory directly:
+//
+//     let mut reader = MemoryReader::new(&memory, module.base_address..module.base_address + module.size)
+//         .hole_policy(HolePolicy::ZeroFill);
+//     io::copy(&mut reader, &mut File::create("module.bin")?)?;
+//
+// Stream offset 0 is the start of the range. Bytes that can't be read are handled by the
+// `HolePolicy`; the holes met so far are available from `holes()` under every policy.
+
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::ops::Range;
+
+use crate::buffer_pool::PooledBuffer;
+use crate::memory::Memory;
+use crate::residency::PAGE_SIZE;
+use crate::scan::CHUNK_SIZE;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HolePolicy {
+    // Fail the read that reaches the hole. Bytes before it are still returned by earlier reads.
+    #[default]
+    Error,
+    // Serve zeros for the hole, keeping stream offsets equal to offsets in the range.
+    ZeroFill,
+    // Jump over the hole, so the stream position moves ahead by its size. Check `holes()` or
+    // `address()` to tell where the bytes came from.
+    Skip,
+}
+
+pub struct MemoryReader<'a> {
+    memory: &'a Memory,
+    range: Range<usize>,
+    // Absolute address of the next byte to serve.
+    position: usize,
+    buffer: PooledBuffer<'a>,
+    buffer_start: usize,
+    hole_policy: HolePolicy,
+    // Sorted, disjoint unreadable ranges seen so far.
+    holes: Vec<Range<usize>>,
+}
+
+impl<'a> MemoryReader<'a> {
+    pub fn new(memory: &'a Memory, range: Range<usize>) -> Self {
+        let start = range.start;
+        let buffer = memory.buffer_pool().rent(CHUNK_SIZE.min(range.len()));
+        MemoryReader {
+            memory,
+            range,
+            position: start,
+            buffer,
+            buffer_start: start,
+            hole_policy: HolePolicy::default(),
+            holes: Vec::new(),
+        }
+    }
+
+    pub fn hole_policy(mut self, policy: HolePolicy) -> Self {
+        self.hole_policy = policy;
+        self
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    // Address in the target of the next byte to be read.
+    pub fn address(&self) -> usize {
+        self.position
+    }
+
+    pub fn holes(&self) -> &[Range<usize>] {
+        &self.holes
+    }
+
+    fn buffered(&self) -> bool {
+        self.position >= self.buffer_start && self.position < self.buffer_start + self.buffer.len()
+    }
+
+    // Fills the buffer starting at `position`, dealing with holes on the way. Leaves the buffer
+    // empty only at the end of the range.
+    fn fill(&mut self) -> io::Result<()> {
+        self.buffer.clear();
+        while self.position < self.range.end {
+            let start = self.position;
+            let len = CHUNK_SIZE.min(self.range.end - start);
+            self.buffer.resize(len, 0);
+            self.buffer_start = start;
+
+            let error = match self.memory.read_bulk(start, &mut self.buffer) {
+                Ok(read) if read > 0 => {
+                    self.buffer.truncate(read);
+                    return Ok(());
+                }
+                Ok(_) => None,
+                Err(e) => Some(e),
+            };
+
+            // Pin the failure down to pages, and serve whatever precedes the first hole.
+            let hole = match self.memory.unreadable_ranges(start..start + len).first() {
+                Some(hole) if hole.start > start => {
+                    self.buffer.truncate(hole.start - start);
+                    match self.memory.read_bulk(start, &mut self.buffer) {
+                        Ok(read) if read > 0 => {
+                            self.buffer.truncate(read);
+                            return Ok(());
+                        }
+                        _ => start..self.hole_end(start),
+                    }
+                }
+                // A hole reaching the end of the window may go on past it; the next fill
+                // extends it.
+                Some(hole) => hole.clone(),
+                None => start..self.hole_end(start),
+            };
+            self.record_hole(hole.clone());
+            match self.hole_policy {
+                HolePolicy::Error => {
+                    self.buffer.clear();
+                    let kind = error.as_ref().map_or(io::ErrorKind::UnexpectedEof, io::Error::kind);
+                    return Err(match error {
+                        Some(e) => io::Error::new(kind, format!("Memory at 0x{:X} is not readable: {}", start, e)),
+                        None => io::Error::new(kind, format!("Memory at 0x{:X} is not readable", start)),
+                    });
+                }
+                HolePolicy::ZeroFill => {
+                    self.buffer.clear();
+                    self.buffer.resize(hole.len().min(CHUNK_SIZE), 0);
+                    return Ok(());
+                }
+                HolePolicy::Skip => self.position = hole.end,
+            }
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+
+    // End of an unreadable stretch at `address` that reads fine when retried page by page:
+    // the end of its page, clipped to the range.
+    fn hole_end(&self, address: usize) -> usize {
+        (address + 1).next_multiple_of(PAGE_SIZE).min(self.range.end)
+    }
+
+    fn record_hole(&mut self, hole: Range<usize>) {
+        let index = self.holes.partition_point(|known| known.end < hole.start);
+        match self.holes.get_mut(index) {
+            Some(known) if known.start <= hole.end => {
+                known.start = known.start.min(hole.start);
+                known.end = known.end.max(hole.end);
+            }
+            _ => self.holes.insert(index, hole),
+        }
+    }
+}
+
+impl Read for MemoryReader<'_> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let len = available.len().min(out.len());
+        out[..len].copy_from_slice(&available[..len]);
+        self.consume(len);
+        Ok(len)
+    }
+}
+
+impl BufRead for MemoryReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if !self.buffered() {
+            self.fill()?;
+        }
+        if self.buffer.is_empty() {
+            return Ok(&[]);
+        }
+        Ok(&self.buffer[self.position - self.buffer_start..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.range.end);
+    }
+}
+
+impl Seek for MemoryReader<'_> {
+    // Seeking past the end is allowed and reads nothing, as for files.
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => (self.range.start as i128, offset as i128),
+            SeekFrom::End(offset) => (self.range.end as i128, offset as i128),
+            SeekFrom::Current(offset) => (self.position as i128, offset as i128),
+        };
+        let target = base + offset;
+        if target < self.range.start as i128 || target > usize::MAX as i128 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek outside the address space of the range"));
+        }
+        self.position = target as usize;
+        Ok((self.position - self.range.start) as u64)
+    }
+}
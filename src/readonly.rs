@@ -0,0 +1,344 @@
+// A handle on the target that can only look. `ReadOnlyMemory` wraps a `Memory` opened without
+// write or VM-operation rights and forwards only the reading, scanning and snapshot methods,
+// so code holding one can't write, patch, allocate, protect or inject: those methods don't
+// exist on it, and the handle underneath couldn't do them anyway.
+//
+//     let mut memory = Memory::open_readonly(pid)?;
+//     memory.scan_memory()?;
+//     let snapshot = memory.snapshot();
+//     // memory.write_bytes(..) doesn't compile
+//
+// APIs that take `&Memory` aren't reachable from here, by design: a `&Memory` would hand out
+// the writing methods too. The ones that only read have counterparts below.
+
+#[cfg(feature = "files")]
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::address_space::MapEntry;
+use crate::filter::RegionFilter;
+use crate::handles::HandleInfo;
+use crate::hash::{IntegrityBaseline, IntegrityViolation};
+use crate::memory::{Memory, MemoryRegion};
+use crate::metadata::ModuleChange;
+use crate::module::ModuleInfo;
+use crate::pattern::Pattern;
+use crate::pod::Pod;
+use crate::probe::ProbeResult;
+use crate::reader::MemoryReader;
+use crate::region_snapshot::RegionSnapshot;
+use crate::remote_ptr::RemotePtr;
+use crate::residency::PageInfo;
+use crate::scan::{ScanOptions, ScanReport};
+use crate::sections::SectionRef;
+#[cfg(feature = "files")]
+use crate::signature::{SigError, SignatureFile};
+use crate::sink::ScanSink;
+use crate::stats::MemoryStats;
+
+/// The writing methods don't exist here. Each of these compiles with `Memory::open` in
+/// place of `open_readonly`, and fails here for want of the method:
+///
+/// ```compile_fail,E0599
+/// # fn main() -> std::io::Result<()> {
+/// let memory = nirvana_notject::Memory::open_readonly(4242)?;
+/// memory.write_bytes(0x1000, &[0x90])?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// ```compile_fail,E0599
+/// # fn main() -> std::io::Result<()> {
+/// let memory = nirvana_notject::Memory::open_readonly(4242)?;
+/// memory.apply_patch(0x1000, &[0x90])?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// ```compile_fail,E0599
+/// # fn main() -> std::io::Result<()> {
+/// let memory = nirvana_notject::Memory::open_readonly(4242)?;
+/// memory.allocate(0x1000, 0x04)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// ```compile_fail,E0599
+/// # fn main() -> std::io::Result<()> {
+/// let memory = nirvana_notject::Memory::open_readonly(4242)?;
+/// memory.protect(0x1000, 0x1000, 0x04)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReadOnlyMemory {
+    inner: Memory,
+}
+
+impl Memory {
+    pub fn open_readonly(process_id: u32) -> io::Result<ReadOnlyMemory> {
+        ReadOnlyMemory::open(process_id)
+    }
+}
+
+impl ReadOnlyMemory {
+    pub fn open(process_id: u32) -> io::Result<Self> {
+        Ok(ReadOnlyMemory { inner: Memory::open_read_only(process_id)? })
+    }
+
+    // Opens the process again with write access, keeping the cached regions and modules.
+    // Fails, leaving this handle as it was, if the caller isn't allowed to write.
+    pub fn try_upgrade(&self) -> io::Result<Memory> {
+        let mut memory = Memory::open(self.inner.process_id())?;
+        memory.copy_caches_from(&self.inner);
+        Ok(memory)
+    }
+
+    pub fn process_id(&self) -> u32 {
+        self.inner.process_id()
+    }
+
+    pub fn pointer_size(&self) -> io::Result<usize> {
+        self.inner.pointer_size()
+    }
+
+    pub fn read_pointer(&self, address: usize) -> io::Result<usize> {
+        self.inner.read_pointer(address)
+    }
+
+    pub fn read_memory<T>(&self, address: usize) -> io::Result<T> {
+        self.inner.read_memory(address)
+    }
+
+    pub fn read_unaligned<T: Copy>(&self, address: usize) -> io::Result<T> {
+        self.inner.read_unaligned(address)
+    }
+
+    pub fn read_bytes(&self, address: usize, len: usize) -> io::Result<Vec<u8>> {
+        self.inner.read_bytes(address, len)
+    }
+
+    // `RemotePtr::read` and `deref`, which take a `&Memory`.
+    pub fn read_ptr<T: Pod>(&self, ptr: RemotePtr<T>) -> io::Result<T> {
+        ptr.read(&self.inner)
+    }
+
+    pub fn deref_ptr<T>(&self, ptr: RemotePtr<RemotePtr<T>>) -> io::Result<RemotePtr<T>> {
+        ptr.deref(&self.inner)
+    }
+
+    pub fn reader(&self, range: Range<usize>) -> MemoryReader<'_> {
+        MemoryReader::new(&self.inner, range)
+    }
+
+    pub fn scan_memory(&mut self) -> io::Result<()> {
+        self.inner.scan_memory()
+    }
+
+    pub fn enumerate_regions(&self) -> Vec<MemoryRegion> {
+        self.inner.enumerate_regions()
+    }
+
+    pub fn full_map(&self) -> io::Result<Vec<MapEntry>> {
+        self.inner.full_map()
+    }
+
+    pub fn regions(&self) -> impl DoubleEndedIterator<Item = &MemoryRegion> + ExactSizeIterator + Clone + '_ {
+        self.inner.regions()
+    }
+
+    pub fn regions_filtered<'a>(
+        &'a self,
+        filter: &'a RegionFilter,
+    ) -> impl DoubleEndedIterator<Item = &'a MemoryRegion> + Clone + 'a {
+        self.inner.regions_filtered(filter)
+    }
+
+    pub fn region_at(&self, address: usize) -> Option<&MemoryRegion> {
+        self.inner.region_at(address)
+    }
+
+    pub fn into_regions(self) -> Vec<MemoryRegion> {
+        self.inner.into_regions()
+    }
+
+    #[deprecated(note = "use `regions`, `regions_filtered` or `region_at`")]
+    #[allow(deprecated)]
+    pub fn get_regions(&self) -> &Vec<MemoryRegion> {
+        self.inner.get_regions()
+    }
+
+    pub fn scan_modules(&mut self) -> io::Result<()> {
+        self.inner.scan_modules()
+    }
+
+    pub fn get_modules(&self) -> &Vec<ModuleInfo> {
+        self.inner.get_modules()
+    }
+
+    pub fn symbolize(&self, address: usize) -> Option<String> {
+        self.inner.symbolize(address)
+    }
+
+    pub fn set_metadata_ttl(&mut self, ttl: Option<Duration>) {
+        self.inner.set_metadata_ttl(ttl)
+    }
+
+    pub fn invalidate_metadata(&self) {
+        self.inner.invalidate_metadata()
+    }
+
+    pub fn on_modules_changed(&mut self, callback: impl FnMut(&ModuleChange) + Send + 'static) {
+        self.inner.on_modules_changed(callback)
+    }
+
+    pub fn refresh_metadata(&mut self) {
+        self.inner.refresh_metadata()
+    }
+
+    pub fn section_at(&self, address: usize) -> Option<SectionRef> {
+        self.inner.section_at(address)
+    }
+
+    pub fn probe(&self, address: usize) -> io::Result<ProbeResult> {
+        self.inner.probe(address)
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.inner.memory_stats()
+    }
+
+    pub fn handles(&self) -> io::Result<Vec<HandleInfo>> {
+        self.inner.handles()
+    }
+
+    pub fn residency(&self, region: &MemoryRegion) -> io::Result<Vec<PageInfo>> {
+        self.inner.residency(region)
+    }
+
+    pub fn hash_range(&self, address: usize, len: usize) -> io::Result<u64> {
+        self.inner.hash_range(address, len)
+    }
+
+    pub fn hash_range_with_holes(&self, address: usize, len: usize) -> io::Result<(u64, Vec<Range<usize>>)> {
+        self.inner.hash_range_with_holes(address, len)
+    }
+
+    pub fn unreadable_ranges(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        self.inner.unreadable_ranges(range)
+    }
+
+    pub fn read_bytes_with_holes(&self, address: usize, len: usize) -> io::Result<(Vec<u8>, Vec<Range<usize>>)> {
+        self.inner.read_bytes_with_holes(address, len)
+    }
+
+    pub fn pattern_matches_with<'a>(
+        &'a self,
+        pattern: &'a Pattern,
+        filter: &'a RegionFilter,
+        options: &ScanOptions,
+    ) -> impl Iterator<Item = io::Result<usize>> + 'a {
+        self.inner.pattern_matches_with(pattern, filter, options)
+    }
+
+    pub fn find_pattern(&self, pattern: &Pattern, filter: &RegionFilter) -> io::Result<Option<usize>> {
+        self.inner.find_pattern(pattern, filter)
+    }
+
+    pub fn find_pattern_with(
+        &self,
+        pattern: &Pattern,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<Option<usize>> {
+        self.inner.find_pattern_with(pattern, filter, options)
+    }
+
+    pub fn find_pattern_all(&self, pattern: &Pattern, filter: &RegionFilter) -> io::Result<Vec<usize>> {
+        self.inner.find_pattern_all(pattern, filter)
+    }
+
+    pub fn find_pattern_limit(&self, pattern: &Pattern, filter: &RegionFilter, limit: usize) -> io::Result<Vec<usize>> {
+        self.inner.find_pattern_limit(pattern, filter, limit)
+    }
+
+    pub fn find_pattern_report(
+        &self,
+        pattern: &Pattern,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<ScanReport> {
+        self.inner.find_pattern_report(pattern, filter, options)
+    }
+
+    pub fn find_pattern_into<S: ScanSink + 'static>(
+        &self,
+        pattern: &Pattern,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+        sink: &Arc<Mutex<S>>,
+    ) -> io::Result<ScanReport> {
+        self.inner.find_pattern_into(pattern, filter, options, sink)
+    }
+
+    pub fn scan_for_value<T: Copy>(&self, value: T, filter: &RegionFilter) -> io::Result<Vec<usize>> {
+        self.inner.scan_for_value(value, filter)
+    }
+
+    pub fn scan_for_value_report<T: Copy>(
+        &self,
+        value: T,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<ScanReport> {
+        self.inner.scan_for_value_report(value, filter, options)
+    }
+
+    pub fn scan_with<P>(
+        &self,
+        predicate: P,
+        width: usize,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<Vec<usize>>
+    where
+        P: Fn(&[u8], usize) -> bool + Sync,
+    {
+        self.inner.scan_with(predicate, width, filter, options)
+    }
+
+    pub fn scan_with_report<P>(
+        &self,
+        predicate: P,
+        width: usize,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<ScanReport>
+    where
+        P: Fn(&[u8], usize) -> bool + Sync,
+    {
+        self.inner.scan_with_report(predicate, width, filter, options)
+    }
+
+    // `RegionSnapshot::capture`.
+    pub fn snapshot(&self) -> RegionSnapshot {
+        RegionSnapshot::capture(&self.inner)
+    }
+
+    // `IntegrityBaseline::capture` and `verify`.
+    pub fn integrity_baseline(&self, filter: &RegionFilter) -> IntegrityBaseline {
+        IntegrityBaseline::capture(&self.inner, filter)
+    }
+
+    pub fn verify_integrity(&self, baseline: &IntegrityBaseline) -> Vec<IntegrityViolation> {
+        baseline.verify(&self.inner)
+    }
+
+    // `SignatureFile::resolve_all`.
+    #[cfg(feature = "files")]
+    pub fn resolve_signatures(&self, signatures: &SignatureFile) -> HashMap<String, Result<RemotePtr<u8>, SigError>> {
+        signatures.resolve_all(&self.inner)
+    }
+}
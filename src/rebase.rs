@@ -0,0 +1,129 @@
+// Saving addresses across runs of the target. ASLR moves modules on every start, so addresses
+// inside a module are stored as an RVA and resolved against wherever the module is loaded
+// next time. Heap addresses have nothing stable to anchor to; they are tagged with their
+// reservation so a later run can at least tell whether they are still valid.
+
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::{Memory, MemoryRegion, RegionType};
+use crate::module::ModuleInfo;
+#[cfg(feature = "patch")]
+pub use crate::patch::{PatchSet, SavedPatch};
+use crate::pointers::PointerHit;
+use crate::wstr;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum RebasedAddress {
+    ModuleRelative { module: String, rva: usize },
+    // Inside a private or mapped reservation. Only resolves while that reservation still exists
+    // at the same base, i.e. within the same run of the target.
+    HeapRelative { allocation_base: usize, offset: usize },
+    // Outside any known module or region; resolves unchanged.
+    Absolute { address: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RebaseError {
+    ModuleMissing(String),
+    // The module is loaded but is smaller than the saved RVA, so it is probably another build.
+    OutsideModule { module: String, rva: usize },
+    HeapMoved { allocation_base: usize },
+}
+
+impl fmt::Display for RebaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RebaseError::ModuleMissing(name) => write!(f, "module '{}' is not loaded", name),
+            RebaseError::OutsideModule { module, rva } => write!(f, "rva 0x{:X} lies outside module '{}'", rva, module),
+            RebaseError::HeapMoved { allocation_base } => {
+                write!(f, "the allocation at 0x{:X} no longer exists", allocation_base)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RebaseError {}
+
+impl From<RebaseError> for io::Error {
+    fn from(e: RebaseError) -> Self {
+        io::Error::new(io::ErrorKind::NotFound, e)
+    }
+}
+
+// Converts between absolute and rebased addresses using one view of the target's modules and
+// regions. Build a new one after the target restarts.
+#[derive(Debug, Clone, Default)]
+pub struct Rebaser {
+    modules: Vec<ModuleInfo>,
+    regions: Vec<MemoryRegion>,
+}
+
+impl Rebaser {
+    pub fn new(modules: Vec<ModuleInfo>, regions: Vec<MemoryRegion>) -> Self {
+        Rebaser { modules, regions }
+    }
+
+    // Uses the cached regions and the cached module list, or a live one if none is cached.
+    pub fn from_memory(memory: &Memory) -> io::Result<Self> {
+        Ok(Rebaser::new(memory.modules_or_live()?.into_owned(), memory.regions().cloned().collect()))
+    }
+
+    pub fn rebase(&self, address: usize) -> RebasedAddress {
+        if let Some(module) = self.modules.iter().find(|module| module.contains(address)) {
+            return RebasedAddress::ModuleRelative { module: module.name.clone(), rva: address - module.base_address };
+        }
+        match self.regions.iter().find(|region| region.contains(address)) {
+            Some(region) if region.region_type != RegionType::Image => RebasedAddress::HeapRelative {
+                allocation_base: region.allocation_base.get(),
+                offset: address - region.allocation_base.get(),
+            },
+            _ => RebasedAddress::Absolute { address },
+        }
+    }
+
+    pub fn resolve(&self, address: &RebasedAddress) -> Result<usize, RebaseError> {
+        match address {
+            RebasedAddress::ModuleRelative { module, rva } => {
+                let loaded = self
+                    .modules
+                    .iter()
+                    .find(|loaded| wstr::eq_ignore_case(&loaded.name, module))
+                    .ok_or_else(|| RebaseError::ModuleMissing(module.clone()))?;
+                if *rva >= loaded.size {
+                    return Err(RebaseError::OutsideModule { module: module.clone(), rva: *rva });
+                }
+                Ok(loaded.base_address + rva)
+            }
+            RebasedAddress::HeapRelative { allocation_base, offset } => self
+                .regions
+                .iter()
+                .any(|region| region.allocation_base == *allocation_base)
+                .then_some(allocation_base + offset)
+                .ok_or(RebaseError::HeapMoved { allocation_base: *allocation_base }),
+            RebasedAddress::Absolute { address } => Ok(*address),
+        }
+    }
+}
+
+// A `PointerHit` with both ends rebased, e.g. the first link of a pointer chain to rescan.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedPointerHit {
+    pub slot: RebasedAddress,
+    pub value: RebasedAddress,
+    pub level: usize,
+}
+
+impl SavedPointerHit {
+    pub fn new(hit: &PointerHit, rebaser: &Rebaser) -> Self {
+        SavedPointerHit { slot: rebaser.rebase(hit.slot), value: rebaser.rebase(hit.value), level: hit.level }
+    }
+
+    // The slot's address in the live process. Its value is best read again rather than resolved.
+    pub fn resolve_slot(&self, rebaser: &Rebaser) -> Result<usize, RebaseError> {
+        rebaser.resolve(&self.slot)
+    }
+}
@@ -0,0 +1,334 @@
+// Named chains of the steps that turn a signature into a value, so a set of addresses can be
+// found again after every update of the target. One recipe per line, steps separated by `;`:
+//
+//     health: module_base game.exe; signature 48 8B 05 ?? ?? ?? ??; rip_rel; add 0x18; deref; add 0xC4; read f32
+//
+// or the same as TOML, with the steps as strings:
+//
+//     [[recipes]]
+//     name = "health"
+//     steps = ["module_base game.exe", "signature 48 8B 05 ?? ?? ?? ??", "rip_rel", "add 0x18"]
+//
+// Steps, each working on the current address:
+//
+//     module_base <module>            the module's base; later signatures search this module
+//     signature <pattern>             first match in the current module (`Pattern::parse` syntax)
+//     rip_rel [<disp offset> <len>]   follow a rip-relative operand of the instruction here,
+//                                     laid out as `resolve_rip_relative_auto` infers by default
+//     add <n>                         n may be negative and hex (`-0x10`)
+//     deref                           read the pointer here (the target's width)
+//     read <type>                     decode a value here, as a `StructTemplate` field type;
+//                                     only as the last step
+//
+// Text after `#` is a comment.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::pattern::Pattern;
+use crate::struct_template::{self, DecodedValue, FieldType};
+use crate::wstr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    ModuleBase(String),
+    Signature(Pattern),
+    // (displacement offset, instruction length); None infers them from the instruction.
+    RipRel(Option<(usize, usize)>),
+    Add(isize),
+    Deref,
+    Read(FieldType),
+}
+
+impl FromStr for Step {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        let text = text.trim();
+        let (keyword, argument) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let argument = argument.trim();
+        let words: Vec<&str> = argument.split_whitespace().collect();
+        Ok(match (keyword, words.as_slice()) {
+            ("module_base", [module]) => Step::ModuleBase(module.to_string()),
+            ("signature", [_, ..]) => Step::Signature(Pattern::parse(argument)?),
+            ("rip_rel", []) => Step::RipRel(None),
+            ("rip_rel", [offset, len]) => Step::RipRel(Some((parse_offset(offset)?, parse_offset(len)?))),
+            ("add", [amount]) => Step::Add(parse_signed(amount)?),
+            ("deref", []) => Step::Deref,
+            ("read", [ty]) => Step::Read(ty.parse()?),
+            ("module_base" | "signature" | "rip_rel" | "add" | "deref" | "read", _) => {
+                return Err(invalid(format!("wrong arguments for '{}' in '{}'", keyword, text)));
+            }
+            _ => return Err(invalid(format!("unknown step '{}'", keyword))),
+        })
+    }
+}
+
+// The form `from_str` reads.
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Step::ModuleBase(module) => write!(f, "module_base {}", module),
+            Step::Signature(pattern) => write!(f, "signature {}", pattern),
+            Step::RipRel(None) => f.write_str("rip_rel"),
+            Step::RipRel(Some((offset, len))) => write!(f, "rip_rel {} {}", offset, len),
+            Step::Add(amount) if *amount < 0 => write!(f, "add -0x{:X}", amount.unsigned_abs()),
+            Step::Add(amount) => write!(f, "add 0x{:X}", amount),
+            Step::Deref => f.write_str("deref"),
+            Step::Read(ty) => write!(f, "read {}", ty),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipe {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+// What a recipe ended with: the final address, and the value there if it ends in `read`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecipeOutput {
+    pub address: usize,
+    pub value: Option<DecodedValue>,
+}
+
+impl fmt::Display for RecipeOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{} at 0x{:X}", value, self.address),
+            None => write!(f, "0x{:X}", self.address),
+        }
+    }
+}
+
+// A step that failed, numbered from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecipeError {
+    pub recipe: String,
+    pub step: usize,
+    pub step_text: String,
+    pub message: String,
+}
+
+// "step 3 'rip_rel' failed: ..."
+impl fmt::Display for RecipeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {} '{}' failed: {}", self.step, self.step_text, self.message)
+    }
+}
+
+impl std::error::Error for RecipeError {}
+
+impl Recipe {
+    // Checks that the steps can run in this order: the first one has to be `module_base`, a
+    // `signature` needs a module to search, and `read` can only come last.
+    pub fn new(name: &str, steps: Vec<Step>) -> io::Result<Self> {
+        let fail = |index: usize, message: &str| {
+            invalid(format!("recipe '{}' step {} '{}': {}", name, index + 1, steps[index], message))
+        };
+        match steps.first() {
+            None => return Err(invalid(format!("recipe '{}' has no steps", name))),
+            Some(Step::ModuleBase(_)) => {}
+            Some(_) => return Err(fail(0, "the first step must be module_base")),
+        }
+        if let Some(index) = steps.iter().position(|step| matches!(step, Step::Read(_))) {
+            if index + 1 != steps.len() {
+                return Err(fail(index, "read has to be the last step"));
+            }
+        }
+        Ok(Recipe { name: name.to_string(), steps })
+    }
+
+    // Runs the steps in order against the target and stops at the first that fails.
+    pub fn run(&self, memory: &Memory) -> Result<RecipeOutput, RecipeError> {
+        let modules = memory.modules_or_live().map_err(|e| self.error(0, e.to_string()))?;
+        let mut module: Option<&ModuleInfo> = None;
+        let mut address = 0usize;
+        let mut value = None;
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let fail = |message: String| self.error(index, message);
+            match step {
+                Step::ModuleBase(name) => {
+                    let found = modules
+                        .iter()
+                        .find(|loaded| wstr::eq_ignore_case(&loaded.name, name))
+                        .ok_or_else(|| fail(format!("module '{}' is not loaded", name)))?;
+                    module = Some(found);
+                    address = found.base_address;
+                }
+                Step::Signature(pattern) => {
+                    // `new` makes sure a module_base came first.
+                    let module = module.ok_or_else(|| fail("no module to search".to_string()))?;
+                    let filter = RegionFilter::new().range(module.base_address..module.base_address + module.size);
+                    address = memory
+                        .find_pattern(pattern, &filter)
+                        .map_err(|e| fail(e.to_string()))?
+                        .ok_or_else(|| fail(format!("pattern not found in {}", module.name)))?;
+                }
+                Step::RipRel(None) => {
+                    address = memory.resolve_rip_relative_auto(address).map_err(|e| fail(at(address, e)))?;
+                }
+                Step::RipRel(Some((offset, len))) => {
+                    address =
+                        memory.resolve_rip_relative(address, *offset, *len).map_err(|e| fail(at(address, e)))?;
+                }
+                Step::Add(amount) => {
+                    address = address
+                        .checked_add_signed(*amount)
+                        .ok_or_else(|| fail(format!("0x{:X} {:+} is outside the address space", address, amount)))?;
+                }
+                Step::Deref => {
+                    address = memory.read_pointer(address).map_err(|e| fail(at(address, e)))?;
+                }
+                Step::Read(ty) => {
+                    value = Some(struct_template::read_field(memory, address, ty).map_err(|e| fail(at(address, e)))?);
+                }
+            }
+        }
+
+        memory.track_results(&[address]);
+        Ok(RecipeOutput { address, value })
+    }
+
+    fn error(&self, index: usize, message: String) -> RecipeError {
+        RecipeError {
+            recipe: self.name.clone(),
+            step: index + 1,
+            step_text: self.steps.get(index).map(Step::to_string).unwrap_or_default(),
+            message,
+        }
+    }
+}
+
+// The text form `Recipe::parse` reads.
+impl fmt::Display for Recipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.name)?;
+        for (index, step) in self.steps.iter().enumerate() {
+            write!(f, "{} {}", if index == 0 { "" } else { ";" }, step)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecipeFile {
+    pub recipes: Vec<Recipe>,
+}
+
+// The TOML form, before the step strings are parsed.
+#[derive(Deserialize)]
+struct TomlRecipes {
+    recipes: Vec<TomlRecipe>,
+}
+
+#[derive(Deserialize)]
+struct TomlRecipe {
+    name: String,
+    steps: Vec<String>,
+}
+
+impl RecipeFile {
+    // Reads the TOML form from `.toml` files and the text form from anything else.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+            Self::from_toml(&text)
+        } else {
+            Self::parse(&text)
+        }
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut recipes = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, steps) = line
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("line {}: expected '<name>: <step>; <step>...'", number + 1)))?;
+            let steps = steps
+                .split(';')
+                .filter(|step| !step.trim().is_empty())
+                .map(Step::from_str)
+                .collect::<io::Result<Vec<_>>>()
+                .map_err(|e| invalid(format!("line {}: recipe '{}': {}", number + 1, name.trim(), e)))?;
+            recipes.push(Recipe::new(name.trim(), steps)?);
+        }
+        Self::new(recipes)
+    }
+
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        let file: TomlRecipes = toml::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let recipes = file
+            .recipes
+            .into_iter()
+            .map(|recipe| {
+                let steps = recipe
+                    .steps
+                    .iter()
+                    .map(|step| step.parse())
+                    .collect::<io::Result<Vec<Step>>>()
+                    .map_err(|e| invalid(format!("recipe '{}': {}", recipe.name, e)))?;
+                Recipe::new(&recipe.name, steps)
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        Self::new(recipes)
+    }
+
+    // Fails on duplicate names, which the report couldn't tell apart.
+    pub fn new(recipes: Vec<Recipe>) -> io::Result<Self> {
+        let mut names: Vec<&str> = recipes.iter().map(|recipe| recipe.name.as_str()).collect();
+        names.sort_unstable();
+        if let Some(pair) = names.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(invalid(format!("recipe '{}' is defined twice", pair[0])));
+        }
+        Ok(RecipeFile { recipes })
+    }
+
+    // Runs every recipe independently, so one that fails doesn't hide the others. Signatures
+    // search the cached regions, so call `scan_memory` first.
+    pub fn run_all(&self, memory: &Memory) -> BTreeMap<String, Result<RecipeOutput, RecipeError>> {
+        self.recipes.iter().map(|recipe| (recipe.name.clone(), recipe.run(memory))).collect()
+    }
+}
+
+fn at(address: usize, e: io::Error) -> String {
+    format!("at 0x{:X}: {}", address, e)
+}
+
+fn parse_offset(text: &str) -> io::Result<usize> {
+    let value = parse_signed(text)?;
+    usize::try_from(value).map_err(|_| invalid(format!("'{}' must not be negative", text)))
+}
+
+fn parse_signed(text: &str) -> io::Result<isize> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let magnitude = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => isize::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|_| invalid(format!("bad number '{}'", text)))?;
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
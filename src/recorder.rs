@@ -0,0 +1,194 @@
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::module::ModuleInfo;
+use crate::persist::{self, Format};
+use crate::wstr;
+
+const FORMAT: Format = Format { id: "writes", version: 2, legacy: None };
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WriteRecord {
+    pub address: usize,
+    // Set when the address fell inside a module, so a replay can re-resolve it after ASLR.
+    pub module: Option<String>,
+    pub module_offset: usize,
+    pub old_bytes: Vec<u8>,
+    pub new_bytes: Vec<u8>,
+    // Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl WriteRecord {
+    pub(crate) fn new(address: usize, old_bytes: Vec<u8>, new_bytes: Vec<u8>, modules: &[ModuleInfo]) -> Self {
+        let module = modules.iter().find(|module| module.contains(address));
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or(0);
+
+        WriteRecord {
+            address,
+            module: module.map(|module| module.name.clone()),
+            module_offset: module.map_or(0, |module| address - module.base_address),
+            old_bytes,
+            new_bytes,
+            timestamp,
+        }
+    }
+
+    pub fn resolve(&self, modules: &[ModuleInfo]) -> io::Result<usize> {
+        match &self.module {
+            None => Ok(self.address),
+            Some(name) => modules
+                .iter()
+                .find(|module| wstr::eq_ignore_case(&module.name, name))
+                .map(|module| module.base_address + self.module_offset)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Module {} is not loaded", name))),
+        }
+    }
+}
+
+// In-memory audit trail of the writes made through a `Memory` while recording is enabled.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recorder {
+    records: Vec<WriteRecord>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(persist::read_json(path, &FORMAT)?.1)
+    }
+
+    // Written whole or not at all; see atomic_file.rs.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        persist::write_json(path, &FORMAT, None, self)
+    }
+
+    pub fn records(&self) -> &[WriteRecord] {
+        &self.records
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    pub(crate) fn push(&mut self, record: WriteRecord) {
+        self.records.push(record);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<WriteRecord> {
+        self.records.pop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::backend::mock::{MockBackend, PAGE};
+
+    fn original() -> Vec<u8> {
+        (0..=255).cycle().take(PAGE).collect()
+    }
+
+    #[test]
+    fn overlapping_writes_undo_newest_first() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, original()).open();
+        memory.start_recording();
+
+        memory.write_bytes(0x10010, &[0xAA; 8]).unwrap();
+        memory.write_bytes(0x10014, &[0xBB; 8]).unwrap();
+        memory.write_bytes(0x1000C, &[0xCC; 8]).unwrap();
+        assert_eq!(memory.recorded_writes()[1].old_bytes, [0xAA, 0xAA, 0xAA, 0xAA, 0x18, 0x19, 0x1A, 0x1B]);
+
+        let last = memory.undo_last().unwrap().unwrap();
+        assert_eq!(last.address, 0x1000C);
+        assert_eq!(mock.bytes(0x1000C, 4), [0x0C, 0x0D, 0x0E, 0x0F]);
+        assert_eq!(mock.bytes(0x10010, 4), [0xAA; 4]);
+
+        assert_eq!(memory.undo_all().unwrap(), 2);
+        assert_eq!(mock.bytes(0x10000, PAGE), original());
+        assert!(memory.recorded_writes().is_empty());
+        assert_eq!(memory.undo_last().unwrap(), None);
+    }
+
+    #[test]
+    fn a_failed_undo_keeps_its_record() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, original()).open();
+        memory.start_recording();
+        memory.write_bytes(0x10010, &[0xAA; 4]).unwrap();
+        memory.write_bytes(0x10800, &[0xBB; 4]).unwrap();
+
+        mock.fail_writes(0x10800..0x10804);
+        assert!(memory.undo_all().is_err());
+        assert_eq!(memory.recorded_writes().len(), 2);
+        assert_eq!(mock.bytes(0x10010, 4), [0xAA; 4]);
+        assert!(memory.undo_last().is_err());
+        assert_eq!(memory.recorded_writes().len(), 2);
+    }
+
+    #[test]
+    fn nothing_is_recorded_or_undone_without_a_recorder() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, original()).open();
+        memory.write_bytes(0x10010, &[0xAA; 4]).unwrap();
+        assert_eq!(memory.undo_last().unwrap(), None);
+        assert_eq!(memory.undo_all().unwrap(), 0);
+        assert!(memory.save_recording("unused").is_err());
+
+        memory.start_recording();
+        memory.write_bytes(0x10020, &[0xBB; 4]).unwrap();
+        let recorder = memory.stop_recording().unwrap();
+        assert_eq!(recorder.records().len(), 1);
+        assert_eq!(memory.undo_last().unwrap(), None);
+        assert_eq!(mock.bytes(0x10020, 4), [0xBB; 4]);
+    }
+
+    #[test]
+    fn records_inside_modules_resolve_after_a_rebase() {
+        let module = |base_address| ModuleInfo {
+            name: "Game.exe".to_string(),
+            path: "C:\\game\\Game.exe".to_string(),
+            base_address,
+            size: 0x5000,
+        };
+        let record = WriteRecord::new(0x4000_1234, vec![0], vec![1], &[module(0x4000_0000)]);
+        assert_eq!((record.module.as_deref(), record.module_offset), (Some("Game.exe"), 0x1234));
+        assert_eq!(record.resolve(&[module(0x5000_0000)]).unwrap(), 0x5000_1234);
+
+        let mut elsewhere = module(0x5000_0000);
+        elsewhere.name = "game.exe".to_string();
+        assert_eq!(record.resolve(&[elsewhere]).unwrap(), 0x5000_1234);
+        assert_eq!(record.resolve(&[]).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        let outside = WriteRecord::new(0x6000_0000, vec![0], vec![1], &[module(0x4000_0000)]);
+        assert_eq!((outside.module.as_deref(), outside.module_offset), (None, 0));
+        assert_eq!(outside.resolve(&[]).unwrap(), 0x6000_0000);
+    }
+
+    #[test]
+    fn recordings_round_trip_through_a_file() {
+        let (_mock, mut memory) = MockBackend::new().data(0x10000, original()).open();
+        memory.start_recording();
+        memory.write_bytes(0x10010, &[0xAA; 4]).unwrap();
+        memory.write_bytes(0x10020, &[0xBB; 2]).unwrap();
+
+        let path = std::env::temp_dir().join(format!("nirvana-recording-{}.json", std::process::id()));
+        memory.save_recording(&path).unwrap();
+        let loaded = Recorder::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().records(), memory.recorded_writes());
+    }
+}
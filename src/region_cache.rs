@@ -0,0 +1,394 @@
+// The regions cached by `scan_memory`, and the ways to get at them:
+//
+//     memory.scan_memory()?;
+//     let filter = RegionFilter::new().writable(true).range(module.base..module.end());
+//     let writable = memory.regions_filtered(&filter).count();
+//     let heap = memory.regions().filter(|region| region.region_type == RegionType::Private);
+//     let owner = memory.region_at(address);
+//
+// The regions are kept sorted by start address, so a lookup by address is a binary search
+// and a filter with a range only looks at the regions overlapping it. How they're stored is
+// private to this module; callers get iterators and references, not the container.
+//
+// Enumerating a browser-sized process takes long enough that doing it before every
+// operation hurts. `refresh_regions_partial` instead walks on from where its last call
+// stopped for as long as its budget allows, and splices what it found into the cache:
+//
+//     loop {
+//         let progress = memory.refresh_regions_partial(Duration::from_millis(5));
+//         if progress.complete {
+//             break;
+//         }
+//     }
+//
+// Every full scan and every partial refresh is a new generation. Each cached region keeps
+// the generation that last saw it, so `region_generation() - region_generation_at(address)`
+// says how many refreshes ago the region there was confirmed. Scans with `refresh_range`
+// set enumerate their filter's range afresh when they start instead of trusting the cache.
+
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use log::trace;
+
+use crate::filter::RegionFilter;
+use crate::memory::{Memory, MemoryRegion};
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RegionCache {
+    // By start address; regions from one enumeration never overlap.
+    regions: Vec<MemoryRegion>,
+    // For each region, the generation that last saw it.
+    seen: Vec<u64>,
+    generation: u64,
+    // Where the next partial refresh resumes.
+    cursor: usize,
+}
+
+// What one `refresh_regions_partial` call did.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RefreshProgress {
+    // The address range walked, from the base of the first region queried.
+    pub start: usize,
+    pub end: usize,
+    // Committed regions found in it, and how they compare to what was cached there.
+    pub regions: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+    // The walk reached the top of the address space; the next call starts over at zero.
+    pub complete: bool,
+    pub generation: u64,
+}
+
+impl RegionCache {
+    pub(crate) fn new(regions: Vec<MemoryRegion>) -> Self {
+        let mut cache = RegionCache::default();
+        cache.replace(regions);
+        cache
+    }
+
+    // Replaces everything, as a full enumeration does.
+    pub(crate) fn replace(&mut self, mut regions: Vec<MemoryRegion>) {
+        regions.sort_by_key(|region| region.start_address);
+        self.generation += 1;
+        self.seen = vec![self.generation; regions.len()];
+        self.regions = regions;
+        self.cursor = 0;
+    }
+
+    // Puts `fresh`, a walk over `span`, in place of the cached regions overlapping it, as a
+    // new generation. Returns (added, removed, changed) by start address.
+    fn splice(&mut self, span: &Range<usize>, mut fresh: Vec<MemoryRegion>) -> (usize, usize, usize) {
+        fresh.sort_by_key(|region| region.start_address);
+        self.generation += 1;
+        let (first, last) = self.overlapping_indices(span);
+
+        let old = &self.regions[first..last];
+        let starts_in = |regions: &[MemoryRegion], start| {
+            regions.binary_search_by_key(&start, |region: &MemoryRegion| region.start_address).ok()
+        };
+        let mut changed = 0;
+        let mut added = 0;
+        for region in &fresh {
+            match starts_in(old, region.start_address) {
+                Some(index) if old[index] != *region => changed += 1,
+                Some(_) => {}
+                None => added += 1,
+            }
+        }
+        let removed = old.iter().filter(|region| starts_in(&fresh, region.start_address).is_none()).count();
+
+        let count = fresh.len();
+        self.regions.splice(first..last, fresh);
+        self.seen.splice(first..last, std::iter::repeat_n(self.generation, count));
+        (added, removed, changed)
+    }
+
+    // The cached regions with the part overlapping `span` replaced by `fresh`, cache untouched.
+    pub(crate) fn spliced(&self, span: &Range<usize>, mut fresh: Vec<MemoryRegion>) -> Vec<MemoryRegion> {
+        fresh.sort_by_key(|region| region.start_address);
+        let (first, last) = self.overlapping_indices(span);
+        let mut regions = self.regions[..first].to_vec();
+        regions.extend(fresh);
+        regions.extend_from_slice(&self.regions[last..]);
+        regions
+    }
+
+    pub(crate) fn as_slice(&self) -> &[MemoryRegion] {
+        &self.regions
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<MemoryRegion> {
+        self.regions
+    }
+
+    pub(crate) fn at(&self, address: usize) -> Option<&MemoryRegion> {
+        let index = self.regions.partition_point(|region| region.end().get() <= address);
+        self.regions.get(index).filter(|region| region.contains(address))
+    }
+
+    // The regions with any byte in `range`.
+    pub(crate) fn overlapping(&self, range: &Range<usize>) -> &[MemoryRegion] {
+        let (first, last) = self.overlapping_indices(range);
+        &self.regions[first..last]
+    }
+
+    fn overlapping_indices(&self, range: &Range<usize>) -> (usize, usize) {
+        let first = self.regions.partition_point(|region| region.end().get() <= range.start);
+        let last = self.regions.partition_point(|region| region.start_address.get() < range.end);
+        (first, last.max(first))
+    }
+
+    fn generation_at(&self, address: usize) -> Option<u64> {
+        let index = self.regions.partition_point(|region| region.end().get() <= address);
+        self.regions.get(index).filter(|region| region.contains(address)).map(|_| self.seen[index])
+    }
+}
+
+impl Memory {
+    // The snapshot taken by `scan_memory`, lowest address first.
+    pub fn regions(&self) -> impl DoubleEndedIterator<Item = &MemoryRegion> + ExactSizeIterator + Clone + '_ {
+        self.region_cache().as_slice().iter()
+    }
+
+    // The cached regions `filter` lets through, lowest address first. Unlike `clip`, this
+    // yields whole regions: one straddling the filter's range comes back untrimmed.
+    pub fn regions_filtered<'a>(
+        &'a self,
+        filter: &'a RegionFilter,
+    ) -> impl DoubleEndedIterator<Item = &'a MemoryRegion> + Clone + 'a {
+        let candidates = match &filter.range {
+            Some(range) => self.region_cache().overlapping(range),
+            None => self.region_cache().as_slice(),
+        };
+        candidates.iter().filter(move |region| filter.matches(region))
+    }
+
+    // The cached region containing `address`. The cache may be stale; `query_address` asks
+    // the target.
+    pub fn region_at(&self, address: usize) -> Option<&MemoryRegion> {
+        self.region_cache().at(address)
+    }
+
+    // Continues the region walk where the last call stopped, for about `budget` (at least one
+    // query), and updates the cache with what it found there. Regions that vanished from the
+    // walked range are dropped, new and changed ones spliced in.
+    pub fn refresh_regions_partial(&mut self, budget: Duration) -> RefreshProgress {
+        let deadline = Instant::now() + budget;
+        let cursor = self.region_cache().cursor;
+        let (fresh, span) = self.walk_regions(cursor, |_| Instant::now() < deadline);
+        let regions = fresh.len();
+
+        let cache = self.region_cache_mut();
+        let (added, removed, changed) = cache.splice(&span, fresh);
+        let complete = span.end == usize::MAX;
+        cache.cursor = if complete { 0 } else { span.end };
+        let generation = cache.generation;
+        self.metadata_slot().regions_spliced();
+        trace!(
+            "refreshed regions 0x{:X}-0x{:X}: {} found, {} added, {} removed, {} changed",
+            span.start,
+            span.end,
+            regions,
+            added,
+            removed,
+            changed
+        );
+        self.refresh_write_guard();
+
+        RefreshProgress { start: span.start, end: span.end, regions, added, removed, changed, complete, generation }
+    }
+
+    // Bumped by every `scan_memory` and `refresh_regions_partial`.
+    pub fn region_generation(&self) -> u64 {
+        self.region_cache().generation
+    }
+
+    // The generation that last saw the cached region containing `address`.
+    pub fn region_generation_at(&self, address: usize) -> Option<u64> {
+        self.region_cache().generation_at(address)
+    }
+
+    // The snapshot, for keeping after the handle is closed.
+    pub fn into_regions(mut self) -> Vec<MemoryRegion> {
+        self.take_region_cache().into_vec()
+    }
+
+    // The snapshot taken by `scan_memory`.
+    #[deprecated(note = "use `regions`, `regions_filtered` or `region_at`")]
+    pub fn get_regions(&self) -> &Vec<MemoryRegion> {
+        &self.region_cache().regions
+    }
+
+    // For the code that wants the regions as a slice: exports, stats, region diffs.
+    pub(crate) fn cached_regions(&self) -> &[MemoryRegion] {
+        self.region_cache().as_slice()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{MockBackend, PAGE};
+    use crate::pattern::Pattern;
+    use crate::scan::ScanOptions;
+    use crate::sys::{PAGE_READONLY, PAGE_READWRITE};
+
+    // Each call queries just once.
+    const ONE_QUERY: Duration = Duration::ZERO;
+
+    fn starts(memory: &Memory) -> Vec<usize> {
+        memory.regions().map(|region| region.start_address.get()).collect()
+    }
+
+    fn well_formed(memory: &Memory) -> bool {
+        let regions = memory.cached_regions();
+        regions.windows(2).all(|pair| pair[0].end() <= pair[1].start_address)
+    }
+
+    // (start, end, regions found, added, complete) of one refresh.
+    fn step(memory: &mut Memory) -> (usize, usize, usize, usize, bool) {
+        let progress = memory.refresh_regions_partial(ONE_QUERY);
+        (progress.start, progress.end, progress.regions, progress.added, progress.complete)
+    }
+
+    #[test]
+    fn partial_refreshes_resume_where_they_stopped() {
+        let (_mock, mut memory) = MockBackend::new()
+            .data(0x10000, vec![0; PAGE])
+            .data(0x20000, vec![0; 2 * PAGE])
+            .data(0x40000, vec![0; PAGE])
+            .open();
+
+        // Free gaps take a query of their own.
+        let steps: Vec<_> = (0..7).map(|_| step(&mut memory)).collect();
+        assert_eq!(
+            steps,
+            [
+                (0, 0x10000, 0, 0, false),
+                (0x10000, 0x11000, 1, 1, false),
+                (0x11000, 0x20000, 0, 0, false),
+                (0x20000, 0x22000, 1, 1, false),
+                (0x22000, 0x40000, 0, 0, false),
+                (0x40000, 0x41000, 1, 1, false),
+                (0x41000, usize::MAX, 0, 0, true),
+            ]
+        );
+        assert_eq!(starts(&memory), [0x10000, 0x20000, 0x40000]);
+        // And then start over.
+        assert_eq!(step(&mut memory), (0, 0x10000, 0, 0, false));
+    }
+
+    #[test]
+    fn refreshes_splice_in_what_changed() {
+        let (mock, mut memory) = MockBackend::new()
+            .data(0x10000, vec![0; PAGE])
+            .data(0x20000, vec![0; PAGE])
+            .data(0x40000, vec![0; PAGE])
+            .open();
+        memory.scan_memory().unwrap();
+
+        mock.unmap(0x20000);
+        mock.map(0x30000, vec![0; 2 * PAGE], PAGE_READWRITE);
+        mock.map(0x40000, vec![0; PAGE], PAGE_READONLY);
+        let progress = memory.refresh_regions_partial(Duration::from_secs(10));
+
+        assert!(progress.complete);
+        assert_eq!((progress.regions, progress.added, progress.removed, progress.changed), (3, 1, 1, 1));
+        assert_eq!(starts(&memory), [0x10000, 0x30000, 0x40000]);
+        assert_eq!(memory.region_at(0x40000).unwrap().protection, PAGE_READONLY);
+        assert_eq!(memory.region_at(0x31000).unwrap().start_address.get(), 0x30000);
+        assert!(memory.region_at(0x20000).is_none());
+    }
+
+    #[test]
+    fn a_region_that_grows_over_its_neighbours_replaces_them() {
+        let (mock, mut memory) = MockBackend::new()
+            .data(0x10000, vec![0; PAGE])
+            .data(0x11000, vec![0; PAGE])
+            .data(0x12000, vec![0; PAGE])
+            .open();
+        memory.scan_memory().unwrap();
+        step(&mut memory);
+
+        mock.map(0x10000, vec![0; 3 * PAGE], PAGE_READWRITE);
+        let progress = memory.refresh_regions_partial(ONE_QUERY);
+        assert_eq!((progress.start, progress.end), (0x10000, 0x13000));
+        assert_eq!((progress.added, progress.removed, progress.changed), (0, 2, 1));
+        assert_eq!(starts(&memory), [0x10000]);
+        assert_eq!(memory.region_at(0x12FFF).unwrap().size.get(), 3 * PAGE);
+    }
+
+    #[test]
+    fn generations_say_how_long_ago_a_region_was_seen() {
+        let (_mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).data(0x20000, vec![0; PAGE]).open();
+        memory.scan_memory().unwrap();
+        let scanned = memory.region_generation();
+        assert_eq!(memory.region_generation_at(0x10000), Some(scanned));
+
+        step(&mut memory);
+        step(&mut memory);
+        assert_eq!(memory.region_generation(), scanned + 2);
+        assert_eq!(memory.region_generation_at(0x10FFF), Some(scanned + 2));
+        assert_eq!(memory.region_generation_at(0x20000), Some(scanned));
+        assert_eq!(memory.region_generation_at(0x11000), None);
+
+        memory.scan_memory().unwrap();
+        assert_eq!(memory.region_generation_at(0x20000), Some(scanned + 3));
+    }
+
+    #[test]
+    fn random_mutations_converge_after_a_full_sweep() {
+        // xorshift, for a reproducible sequence.
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = |bound: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % bound) as usize
+        };
+
+        let (mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        memory.scan_memory().unwrap();
+        for round in 0..300 {
+            // Slots 0x10000 apart, each one to three pages or nothing.
+            let base = 0x10000 * (1 + next(16));
+            match next(4) {
+                0 => mock.unmap(base),
+                1 => mock.map(base, vec![0; PAGE * (1 + next(3))], PAGE_READONLY),
+                _ => mock.map(base, vec![0; PAGE * (1 + next(3))], PAGE_READWRITE),
+            }
+            for _ in 0..next(6) {
+                memory.refresh_regions_partial(ONE_QUERY);
+            }
+            assert!(well_formed(&memory), "round {}", round);
+
+            if round % 50 == 49 {
+                // Finish the walk in progress, then one whole walk with nothing changing.
+                while !memory.refresh_regions_partial(ONE_QUERY).complete {}
+                while !memory.refresh_regions_partial(ONE_QUERY).complete {}
+                assert_eq!(memory.cached_regions(), memory.enumerate_regions(), "round {}", round);
+            }
+        }
+    }
+
+    #[test]
+    fn range_refreshes_see_new_regions_without_touching_the_cache() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).data(0x30000, vec![0; PAGE]).open();
+        memory.scan_memory().unwrap();
+        let mut bytes = vec![0; PAGE];
+        bytes[0x10..0x14].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        mock.map(0x20000, bytes, PAGE_READWRITE);
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let filter = RegionFilter::new().range(0x18000..0x28000);
+        let stale = memory.find_pattern_report(&pattern, &filter, &ScanOptions::default()).unwrap();
+        assert!(stale.matches.is_empty());
+
+        let refreshed = ScanOptions::default().refresh_range(true);
+        let report = memory.find_pattern_report(&pattern, &filter, &refreshed).unwrap();
+        assert_eq!(report.matches, [0x20010]);
+        assert_eq!(starts(&memory), [0x10000, 0x30000]);
+    }
+}
@@ -0,0 +1,136 @@
+// The live view of the address space, one VirtualQueryEx call per region. The regions cached
+// by `scan_memory` are a snapshot that goes stale as the target allocates, frees and
+// reprotects; these queries reflect the target as it is at the moment of the call.
+//
+//     let info = memory.query_address(address)?;
+//     if info.state == RegionState::Committed && info.protection & 0x04 != 0 {
+//         // PAGE_READWRITE right now
+//     }
+
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ops::Range;
+
+use crate::address::{Address, Size};
+use crate::memory::{Memory, MemoryRegion, RegionType, MEMORY_BASIC_INFORMATION, MEM_COMMIT, MEM_RESERVE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegionState {
+    Committed,
+    // Reserved by VirtualAlloc but not backed by memory yet.
+    Reserved,
+    Free,
+}
+
+// Everything VirtualQueryEx reports about one region: a run of pages sharing state,
+// protection and type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RegionInfo {
+    pub base: Address,
+    pub size: Size,
+    // Zero for free regions.
+    pub allocation_base: Address,
+    // Protection the allocation was created with; zero for free regions.
+    pub allocation_protect: u32,
+    // Current protection; zero for reserved and free regions.
+    pub protection: u32,
+    pub state: RegionState,
+    // Unknown for free regions.
+    pub region_type: RegionType,
+}
+
+impl RegionInfo {
+    pub(crate) fn from_mbi(mbi: &MEMORY_BASIC_INFORMATION) -> Self {
+        RegionInfo {
+            base: Address::from(mbi.BaseAddress),
+            size: Size::new(mbi.RegionSize),
+            allocation_base: Address::from(mbi.AllocationBase),
+            allocation_protect: mbi.AllocationProtect,
+            protection: mbi.Protect,
+            state: match mbi.State {
+                MEM_COMMIT => RegionState::Committed,
+                MEM_RESERVE => RegionState::Reserved,
+                _ => RegionState::Free,
+            },
+            region_type: RegionType::from_raw(mbi.Type),
+        }
+    }
+
+    // Saturates at the top of the address space, like `MemoryRegion::end`.
+    pub fn end(&self) -> Address {
+        self.base.checked_add(self.size).unwrap_or(Address::new(usize::MAX))
+    }
+
+    pub fn range(&self) -> Range<usize> {
+        self.base.get()..self.end().get()
+    }
+
+    pub fn contains(&self, address: impl Into<Address>) -> bool {
+        self.range().contains(&address.into().get())
+    }
+
+    pub fn is_committed(&self) -> bool {
+        self.state == RegionState::Committed
+    }
+
+    // The region as `scan_memory` would have cached it; None unless it is committed.
+    pub fn to_region(&self) -> Option<MemoryRegion> {
+        self.is_committed().then(|| {
+            let mut mbi: MEMORY_BASIC_INFORMATION = unsafe { mem::zeroed() };
+            mbi.BaseAddress = self.base.get() as *mut _;
+            mbi.AllocationBase = self.allocation_base.get() as *mut _;
+            mbi.AllocationProtect = self.allocation_protect;
+            mbi.RegionSize = self.size.get();
+            mbi.State = MEM_COMMIT;
+            mbi.Protect = self.protection;
+            mbi.Type = self.region_type.to_raw();
+            MemoryRegion::from_mbi(&mbi)
+        })
+    }
+}
+
+// "0x1F0000-0x1F1000 committed private protection 0x04 (allocation 0x1F0000, 0x04)"
+impl fmt::Display for RegionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{} ", self.base, self.end())?;
+        match self.state {
+            RegionState::Free => f.write_str("free"),
+            RegionState::Reserved => write!(f, "reserved {} (allocation {})", self.region_type.name(), self.allocation_base),
+            RegionState::Committed => write!(
+                f,
+                "committed {} protection 0x{:02X} (allocation {}, 0x{:02X})",
+                self.region_type.name(),
+                self.protection,
+                self.allocation_base,
+                self.allocation_protect
+            ),
+        }
+    }
+}
+
+impl Memory {
+    // The region containing `address` as it is right now, whatever its state. Fails only if
+    // VirtualQueryEx does, e.g. for an address above the user address space.
+    pub fn query_address(&self, address: usize) -> io::Result<RegionInfo> {
+        self.query(address).map(|mbi| RegionInfo::from_mbi(&mbi))
+    }
+
+    // The regions overlapping `range`, in order and without gaps; the first and last may
+    // extend past it. Only that span is queried.
+    pub fn query_range(&self, range: Range<usize>) -> io::Result<Vec<RegionInfo>> {
+        let mut regions: Vec<RegionInfo> = Vec::new();
+        let mut address = range.start;
+        while address < range.end {
+            let region = self.query_address(address)?;
+            let next = region.base.get().checked_add(region.size.get());
+            regions.push(region);
+            // The last region can end exactly at the top of the address space.
+            match next {
+                Some(next) if next > address => address = next,
+                _ => break,
+            }
+        }
+        Ok(regions)
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::{Memory, MemoryRegion};
+use crate::persist::{self, Format};
+
+const FORMAT: Format = Format { id: "regions", version: 2, legacy: None };
+
+// The region map of a process at one point in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionSnapshot {
+    pub process_id: u32,
+    pub regions: Vec<MemoryRegion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionGrowth {
+    pub allocation_base: usize,
+    // Committed bytes of the allocation before and after.
+    pub old_size: usize,
+    pub new_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtectionChange {
+    pub start_address: usize,
+    pub size: usize,
+    pub old_protection: u32,
+    pub new_protection: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionDiff {
+    pub added: Vec<MemoryRegion>,
+    pub removed: Vec<MemoryRegion>,
+    pub grown: Vec<RegionGrowth>,
+    pub protection_changed: Vec<ProtectionChange>,
+}
+
+impl RegionDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.grown.is_empty() && self.protection_changed.is_empty()
+    }
+}
+
+impl RegionSnapshot {
+    // Uses the regions cached by the last `scan_memory` call.
+    pub fn capture(memory: &Memory) -> Self {
+        RegionSnapshot {
+            process_id: memory.process_id(),
+            regions: memory.regions().cloned().collect(),
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(persist::read_json(path, &FORMAT)?.1)
+    }
+
+    // Written whole or not at all; see atomic_file.rs.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        persist::write_json(path, &FORMAT, None, self)
+    }
+
+    // Regions are matched by allocation base rather than start address: heaps commit more pages
+    // inside their existing reservation, which shows up as growth, not as a new region.
+    pub fn diff(&self, older: &RegionSnapshot) -> RegionDiff {
+        diff_regions(&older.regions, &self.regions)
+    }
+}
+
+// See `RegionSnapshot::diff`. Protection is compared over every overlap of an old and a new
+// region of the same allocation, so a region that split or merged with its neighbours between
+// the two maps reports just the pages whose protection actually changed.
+pub(crate) fn diff_regions(older: &[MemoryRegion], newer: &[MemoryRegion]) -> RegionDiff {
+    let old_allocations = group_by_allocation(older);
+    let new_allocations = group_by_allocation(newer);
+    let mut diff = RegionDiff::default();
+
+    for (base, new_regions) in &new_allocations {
+        let Some(old_regions) = old_allocations.get(base) else {
+            diff.added.extend(new_regions.iter().map(|&region| region.clone()));
+            continue;
+        };
+
+        let old_size: usize = old_regions.iter().map(|region| region.size.get()).sum();
+        let new_size: usize = new_regions.iter().map(|region| region.size.get()).sum();
+        if new_size > old_size {
+            diff.grown.push(RegionGrowth { allocation_base: *base, old_size, new_size });
+        }
+
+        for region in new_regions {
+            for previous in old_regions.iter().filter(|old| old.protection != region.protection) {
+                let start = previous.start_address.max(region.start_address).get();
+                let end = previous.end().min(region.end()).get();
+                if start < end {
+                    diff.protection_changed.push(ProtectionChange {
+                        start_address: start,
+                        size: end - start,
+                        old_protection: previous.protection,
+                        new_protection: region.protection,
+                    });
+                }
+            }
+        }
+    }
+
+    for (base, old_regions) in &old_allocations {
+        if !new_allocations.contains_key(base) {
+            diff.removed.extend(old_regions.iter().map(|&region| region.clone()));
+        }
+    }
+
+    diff
+}
+
+fn group_by_allocation(regions: &[MemoryRegion]) -> BTreeMap<usize, Vec<&MemoryRegion>> {
+    let mut allocations: BTreeMap<usize, Vec<&MemoryRegion>> = BTreeMap::new();
+    for region in regions {
+        allocations.entry(region.allocation_base.get()).or_default().push(region);
+    }
+    allocations
+}
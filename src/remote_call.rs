@@ -0,0 +1,231 @@
+// Calling functions in the target with arguments. A small stub loads the arguments the way
+// the calling convention wants, calls the function and stores its return value in a slot,
+// then exits; it runs on a remote thread and the slot is read back afterwards.
+//
+//     let function = memory.find_export("kernel32.dll", "GetCurrentProcessId")?;
+//     let pid = memory.call_remote(function, &[], CallConv::Win64)?;
+//
+//     let args = [RemoteArg::Int(7), RemoteArg::F32(0.5), RemoteArg::from(&b"name\0"[..])];
+//     memory.call_remote(function, &args, CallConv::Win64)?;
+
+use std::io;
+use std::time::Duration;
+
+use log::warn;
+
+use crate::memory::{Memory, PAGE_EXECUTE_READWRITE};
+
+// How long `call_remote` waits for the function to return.
+pub const REMOTE_CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RemoteArg {
+    // Any integer or pointer, passed in a general-purpose register or stack slot.
+    Int(u64),
+    F32(f32),
+    F64(f64),
+    // Copied into the target for the duration of the call and passed as a pointer to the copy.
+    Bytes(Vec<u8>),
+}
+
+impl From<u64> for RemoteArg {
+    fn from(value: u64) -> Self {
+        RemoteArg::Int(value)
+    }
+}
+
+impl From<usize> for RemoteArg {
+    fn from(value: usize) -> Self {
+        RemoteArg::Int(value as u64)
+    }
+}
+
+impl From<f32> for RemoteArg {
+    fn from(value: f32) -> Self {
+        RemoteArg::F32(value)
+    }
+}
+
+impl From<f64> for RemoteArg {
+    fn from(value: f64) -> Self {
+        RemoteArg::F64(value)
+    }
+}
+
+impl From<&[u8]> for RemoteArg {
+    fn from(bytes: &[u8]) -> Self {
+        RemoteArg::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<Vec<u8>> for RemoteArg {
+    fn from(bytes: Vec<u8>) -> Self {
+        RemoteArg::Bytes(bytes)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CallConv {
+    // The Microsoft x64 convention: the first four arguments in rcx/rdx/r8/r9 or xmm0-3,
+    // the rest on the stack above 32 bytes of shadow space.
+    #[default]
+    Win64,
+}
+
+// Frees a temporary allocation when dropped, unless it is disarmed because the target may
+// still be using it.
+struct Temporary<'a> {
+    memory: &'a Memory,
+    address: Option<usize>,
+}
+
+impl Temporary<'_> {
+    fn disarm(&mut self) {
+        self.address = None;
+    }
+}
+
+impl Drop for Temporary<'_> {
+    fn drop(&mut self) {
+        if let Some(address) = self.address {
+            if let Err(e) = self.memory.free(address) {
+                warn!("failed to free the remote call allocation at 0x{:X}: {}", address, e);
+            }
+        }
+    }
+}
+
+impl Memory {
+    // Calls `address` in the target and returns what it left in rax. Float return values
+    // come back in xmm0 and are not captured.
+    pub fn call_remote(&self, address: usize, args: &[RemoteArg], convention: CallConv) -> io::Result<u64> {
+        self.call_remote_with_timeout(address, args, convention, REMOTE_CALL_TIMEOUT)
+    }
+
+    // The stub, result slot and byte buffers share one allocation, freed on every path except
+    // a timeout: the function may then still be running, and freeing its stub would crash the
+    // target, so the allocation is left behind.
+    pub fn call_remote_with_timeout(
+        &self,
+        address: usize,
+        args: &[RemoteArg],
+        convention: CallConv,
+        timeout: Duration,
+    ) -> io::Result<u64> {
+        if self.pointer_size()? != 8 {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Remote calls need a 64-bit target"));
+        }
+
+        // Layout: result slot, buffers (each 16-byte aligned), stub.
+        let mut offset = 16;
+        let buffer_offsets: Vec<Option<usize>> = args
+            .iter()
+            .map(|arg| match arg {
+                RemoteArg::Bytes(bytes) => {
+                    let at = offset;
+                    offset = (offset + bytes.len()).next_multiple_of(16);
+                    Some(at)
+                }
+                _ => None,
+            })
+            .collect();
+        let stub_offset = offset;
+
+        let size = stub_offset + MAX_STUB_LEN + args.len() * STUB_BYTES_PER_ARG;
+        let base = self.allocate(size, PAGE_EXECUTE_READWRITE)?;
+        let mut temporary = Temporary { memory: self, address: Some(base) };
+
+        let values: Vec<StubArg> = args
+            .iter()
+            .zip(&buffer_offsets)
+            .map(|(arg, buffer)| match arg {
+                RemoteArg::Int(value) => StubArg::Int(*value),
+                RemoteArg::F32(value) => StubArg::Float(value.to_bits() as u64),
+                RemoteArg::F64(value) => StubArg::Float(value.to_bits()),
+                RemoteArg::Bytes(_) => StubArg::Int((base + buffer.unwrap()) as u64),
+            })
+            .collect();
+        let stub = match convention {
+            CallConv::Win64 => win64_stub(address, &values, base),
+        };
+
+        let mut image = vec![0u8; stub_offset + stub.len()];
+        for (arg, buffer) in args.iter().zip(&buffer_offsets) {
+            if let (RemoteArg::Bytes(bytes), Some(at)) = (arg, buffer) {
+                image[*at..*at + bytes.len()].copy_from_slice(bytes);
+            }
+        }
+        image[stub_offset..].copy_from_slice(&stub);
+        self.write_bytes(base, &image)?;
+
+        match self.run_remote_thread(base + stub_offset, 0, timeout) {
+            Ok(_) => self.read_unaligned::<u64>(base),
+            Err(e) => {
+                if e.kind() == io::ErrorKind::TimedOut {
+                    warn!("remote call to 0x{:X} timed out; leaving its allocation at 0x{:X}", address, base);
+                    temporary.disarm();
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+// Longest stub without arguments, and the most bytes each argument adds to it.
+const MAX_STUB_LEN: usize = 64;
+const STUB_BYTES_PER_ARG: usize = 24;
+
+enum StubArg {
+    Int(u64),
+    // Raw bits, zero-extended.
+    Float(u64),
+}
+
+// Thread routine that calls `function` with `args`, stores rax at `result` and exits with 0.
+fn win64_stub(function: usize, args: &[StubArg], result: usize) -> Vec<u8> {
+    // On entry rsp is 8 past a 16-byte boundary; keep the call site aligned.
+    let stack_args = args.len().saturating_sub(4);
+    let mut frame = 32 + stack_args * 8;
+    if frame % 16 == 0 {
+        frame += 8;
+    }
+
+    let mut code = Vec::new();
+    code.extend_from_slice(&[0x48, 0x81, 0xEC]); // sub rsp, frame
+    code.extend_from_slice(&(frame as u32).to_le_bytes());
+
+    for (index, arg) in args.iter().enumerate() {
+        let bits = match arg {
+            StubArg::Int(bits) | StubArg::Float(bits) => *bits,
+        };
+        match (index, arg) {
+            (0..=3, StubArg::Int(_)) => {
+                // mov rcx / rdx / r8 / r9, imm64
+                code.extend_from_slice(&[[0x48, 0xB9], [0x48, 0xBA], [0x49, 0xB8], [0x49, 0xB9]][index]);
+                code.extend_from_slice(&bits.to_le_bytes());
+            }
+            (0..=3, StubArg::Float(_)) => {
+                code.extend_from_slice(&[0x48, 0xB8]); // mov rax, imm64
+                code.extend_from_slice(&bits.to_le_bytes());
+                code.extend_from_slice(&[0x66, 0x48, 0x0F, 0x6E, 0xC0 + index as u8 * 8]); // movq xmmN, rax
+            }
+            _ => {
+                code.extend_from_slice(&[0x48, 0xB8]); // mov rax, imm64
+                code.extend_from_slice(&bits.to_le_bytes());
+                code.extend_from_slice(&[0x48, 0x89, 0x84, 0x24]); // mov [rsp + disp32], rax
+                code.extend_from_slice(&(32 + (index as u32 - 4) * 8).to_le_bytes());
+            }
+        }
+    }
+
+    code.extend_from_slice(&[0x48, 0xB8]); // mov rax, function
+    code.extend_from_slice(&(function as u64).to_le_bytes());
+    code.extend_from_slice(&[0xFF, 0xD0]); // call rax
+    code.extend_from_slice(&[0x48, 0xA3]); // mov [result], rax
+    code.extend_from_slice(&(result as u64).to_le_bytes());
+    code.extend_from_slice(&[0x48, 0x81, 0xC4]); // add rsp, frame
+    code.extend_from_slice(&(frame as u32).to_le_bytes());
+    code.extend_from_slice(&[0x31, 0xC0]); // xor eax, eax
+    code.push(0xC3); // ret
+    code
+}
@@ -0,0 +1,149 @@
+// Typed addresses in the target. A `RemotePtr<T>` is just an address that remembers what lives
+// there, so reads, writes and offsets can't mix up units. It never dereferences anything in
+// this process.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::memory::Memory;
+use crate::pod::Pod;
+
+pub struct RemotePtr<T> {
+    address: usize,
+    // `fn() -> T` keeps the pointer `Send`, `Sync` and covariant whatever `T` is.
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> RemotePtr<T> {
+    pub const fn new(address: usize) -> Self {
+        RemotePtr { address, marker: PhantomData }
+    }
+
+    pub const fn null() -> Self {
+        Self::new(0)
+    }
+
+    pub const fn address(&self) -> usize {
+        self.address
+    }
+
+    pub const fn is_null(&self) -> bool {
+        self.address == 0
+    }
+
+    // Moves by `count` elements of `T`, like `pointer::wrapping_offset`.
+    pub fn offset(&self, count: isize) -> RemotePtr<T> {
+        let bytes = count.wrapping_mul(mem::size_of::<T>() as isize);
+        RemotePtr::new(self.address.wrapping_add_signed(bytes))
+    }
+
+    pub fn cast<U>(&self) -> RemotePtr<U> {
+        RemotePtr::new(self.address)
+    }
+
+    // The field of type `U` at `byte_offset` into the `T` this points to.
+    pub fn field<U>(&self, byte_offset: usize) -> RemotePtr<U> {
+        RemotePtr::new(self.address.wrapping_add(byte_offset))
+    }
+}
+
+impl<T: Pod> RemotePtr<T> {
+    // Fails unless the whole value could be read. The address needs no particular alignment.
+    pub fn read(&self, memory: &Memory) -> io::Result<T> {
+        memory.read_unaligned(self.address)
+    }
+
+    pub fn write(&self, memory: &Memory, value: T) -> io::Result<()> {
+        memory.write_unaligned(self.address, value)
+    }
+}
+
+impl<T> RemotePtr<RemotePtr<T>> {
+    // Reads the pointer stored here, 4 bytes wide in 32-bit targets and 8 in 64-bit ones.
+    pub fn deref(&self, memory: &Memory) -> io::Result<RemotePtr<T>> {
+        memory.read_pointer(self.address).map(RemotePtr::new)
+    }
+}
+
+// The trait impls are written out because derives would needlessly require `T` to implement
+// them as well.
+impl<T> Clone for RemotePtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for RemotePtr<T> {}
+
+impl<T> PartialEq for RemotePtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.address == other.address
+    }
+}
+
+impl<T> Eq for RemotePtr<T> {}
+
+impl<T> PartialOrd for RemotePtr<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for RemotePtr<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.address.cmp(&other.address)
+    }
+}
+
+impl<T> Hash for RemotePtr<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.address.hash(state);
+    }
+}
+
+impl<T> Default for RemotePtr<T> {
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T> From<usize> for RemotePtr<T> {
+    fn from(address: usize) -> Self {
+        RemotePtr::new(address)
+    }
+}
+
+impl<T> From<RemotePtr<T>> for usize {
+    fn from(ptr: RemotePtr<T>) -> Self {
+        ptr.address
+    }
+}
+
+impl<T> fmt::Debug for RemotePtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "RemotePtr<{}>(0x{:X})", std::any::type_name::<T>(), self.address)
+    }
+}
+
+// "0x7FF6A0001000".
+impl<T> fmt::Display for RemotePtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:X}", self.address)
+    }
+}
+
+impl<T> fmt::UpperHex for RemotePtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::UpperHex::fmt(&self.address, f)
+    }
+}
+
+impl<T> fmt::LowerHex for RemotePtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(&self.address, f)
+    }
+}
@@ -0,0 +1,123 @@
+// `remote_struct!` declares a struct whose fields sit at fixed byte offsets in the target:
+//
+//     remote_struct! {
+//         #[derive(Debug, Clone, Copy)]
+//         pub struct Player {
+//             #[offset(0x10)]
+//             pub health: f32,
+//             #[offset(0x18)]
+//             pub position: [f32; 3],
+//         }
+//     }
+//
+//     let player = Player::read(&memory, base)?;
+//     Player::health(base).write(&memory, 100.0)?;
+//
+// `read` fetches the span from the first field to the end of the last in one read and picks
+// the fields out of it; gaps between fields are read but ignored. Every field also gets an
+// associated function of the same name returning a `RemotePtr` to it, for reading or writing
+// that field alone. Fields must be `Pod` and listed in ascending, non-overlapping offset order,
+// both checked at compile time.
+
+use std::io;
+
+use crate::memory::Memory;
+
+#[macro_export]
+macro_rules! remote_struct {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                #[offset($offset:expr)]
+                $(#[$field_meta:meta])*
+                $field_vis:vis $field:ident : $ty:ty
+            ),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )+
+        }
+
+        impl $name {
+            // (offset, size) of every field, in declaration order.
+            #[doc(hidden)]
+            pub const LAYOUT: &'static [(usize, usize)] = &[$(($offset, ::std::mem::size_of::<$ty>())),+];
+
+            // End of the last field, counted from the base address.
+            pub const SPAN: usize = $crate::remote_struct::check_layout(Self::LAYOUT);
+
+            pub fn read(memory: &$crate::memory::Memory, base: usize) -> ::std::io::Result<Self> {
+                let start = Self::LAYOUT[0].0;
+                let bytes = $crate::remote_struct::read_span(memory, base, start, Self::SPAN)?;
+                Ok($name {
+                    $(
+                        $field: $crate::remote_struct::extract::<$ty>(&bytes, $offset - start),
+                    )+
+                })
+            }
+
+            // Writes every field separately, leaving the gaps between them untouched.
+            pub fn write(&self, memory: &$crate::memory::Memory, base: usize) -> ::std::io::Result<()> {
+                $(
+                    Self::$field(base).write(memory, self.$field)?;
+                )+
+                Ok(())
+            }
+
+            $(
+                $field_vis fn $field(base: usize) -> $crate::remote_ptr::RemotePtr<$ty> {
+                    $crate::remote_ptr::RemotePtr::<u8>::new(base).field($offset)
+                }
+            )+
+        }
+
+        const _: () = {
+            const fn assert_pod<T: $crate::pod::Pod>() {}
+            $(assert_pod::<$ty>();)+
+            let _ = $name::SPAN;
+        };
+    };
+}
+
+// Fails the build unless the fields are in ascending order and don't overlap, and returns the
+// end of the last one.
+#[doc(hidden)]
+pub const fn check_layout(layout: &[(usize, usize)]) -> usize {
+    let mut end = 0;
+    let mut index = 0;
+    while index < layout.len() {
+        let (offset, size) = layout[index];
+        if offset < end {
+            panic!("remote_struct! fields overlap or are not in ascending offset order");
+        }
+        end = offset + size;
+        index += 1;
+    }
+    end
+}
+
+#[doc(hidden)]
+pub fn read_span(memory: &Memory, base: usize, start: usize, end: usize) -> io::Result<Vec<u8>> {
+    let address = base.checked_add(start).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("0x{:X} + 0x{:X} overflows the address space", base, start))
+    })?;
+    let bytes = memory.read_bytes(address, end - start)?;
+    if bytes.len() < end - start {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Only 0x{:X} of 0x{:X} bytes at 0x{:X} are readable", bytes.len(), end - start, address),
+        ));
+    }
+    Ok(bytes)
+}
+
+#[doc(hidden)]
+pub fn extract<T: crate::pod::Pod>(bytes: &[u8], offset: usize) -> T {
+    let bytes = &bytes[offset..offset + std::mem::size_of::<T>()];
+    unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
@@ -0,0 +1,86 @@
+// Running code in the target on a thread of its own.
+
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+use std::ptr;
+use std::time::Duration;
+
+use log::debug;
+
+use crate::capabilities::Capabilities;
+use crate::memory::Memory;
+use crate::sys::{
+    CloseHandle, CreateRemoteThread, GetExitCodeThread, OpenProcess, WaitForSingleObject, LPTHREAD_START_ROUTINE,
+    PROCESS_CREATE_THREAD, PROCESS_QUERY_INFORMATION, PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE,
+    WAIT_FAILED, WAIT_OBJECT_0, WAIT_TIMEOUT,
+};
+use crate::win_error::win_call;
+
+impl Memory {
+    // Starts a thread at `start` with `parameter` as its argument (a `LPTHREAD_START_ROUTINE`)
+    // and waits up to `timeout` for it to finish, returning its exit code. On timeout the
+    // thread keeps running and TimedOut is returned.
+    //
+    // The process is opened again for this with thread-creation rights, so a handle that
+    // was only good for reading and writing is enough for everything else.
+    pub fn run_remote_thread(&self, start: usize, parameter: usize, timeout: Duration) -> io::Result<u32> {
+        if self.is_dry_run() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Remote threads cannot run in dry-run mode"));
+        }
+        self.require(Capabilities::CREATE_THREAD)?;
+
+        let access = PROCESS_CREATE_THREAD
+            | PROCESS_QUERY_INFORMATION
+            | PROCESS_VM_OPERATION
+            | PROCESS_VM_READ
+            | PROCESS_VM_WRITE;
+        let pid = self.process_id();
+        let process =
+            win_call!(unsafe { OpenProcess(access, 0, pid) }, "Failed to open process {} for thread creation", pid)?;
+
+        // An address in the target, typed as the routine CreateRemoteThread takes; this process
+        // never calls it.
+        let routine = unsafe { mem::transmute::<usize, LPTHREAD_START_ROUTINE>(start) };
+        let mut thread_id = 0;
+        let thread = win_call!(
+            unsafe {
+                CreateRemoteThread(process, ptr::null(), 0, routine, parameter as *const c_void, 0, &mut thread_id)
+            },
+            "Failed to start a remote thread at 0x{:X}",
+            start
+        );
+        unsafe { CloseHandle(process) };
+        let thread = thread?;
+        debug!("started remote thread {} at 0x{:X}", thread_id, start);
+
+        let milliseconds = timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+        let waited = win_call!(
+            unsafe { WaitForSingleObject(thread, milliseconds) },
+            failed: |result| *result == WAIT_FAILED,
+            "Failed to wait for remote thread {}",
+            thread_id
+        );
+        let result = match waited {
+            Ok(WAIT_OBJECT_0) => {
+                let mut exit_code = 0;
+                win_call!(
+                    unsafe { GetExitCodeThread(thread, &mut exit_code) },
+                    "Failed to read the exit code of remote thread {}",
+                    thread_id
+                )
+                .map(|_| exit_code)
+            }
+            Ok(WAIT_TIMEOUT) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Remote thread {} did not finish within {:?}", thread_id, timeout),
+            )),
+            Ok(status) => {
+                Err(io::Error::other(format!("Unexpected wait status 0x{:X} for remote thread {}", status, thread_id)))
+            }
+            Err(e) => Err(e),
+        };
+        unsafe { CloseHandle(thread) };
+        result
+    }
+}
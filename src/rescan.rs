@@ -0,0 +1,149 @@
+// Finding a pattern again where it was found before. Once the target restarts, most matches
+// are at the same RVA as last time, so the few bytes around each previous location are read
+// first and only the misses cost a scan of their whole module:
+//
+//     let previous = [MatchLocation::new("game.exe", 0x1234)];
+//     let found = memory.rescan_near(&previous, 0x100, &pattern);  // [Some(0x7FF6_0000_1234)]
+//
+// Locations are module-relative, so they carry over even when the module is loaded elsewhere.
+// A neighborhood is `window` bytes to either side of the previous start; of the matches in
+// it, the one closest to that start wins. `rescan_near_report` also says how each entry was
+// found, or why not.
+
+use std::fmt;
+use std::io;
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::pattern::{search_iter, Pattern};
+use crate::scan::ScanOptions;
+use crate::wstr;
+
+// How far either side of a previous location `SignatureFile::resolve_all_cached` looks.
+pub const DEFAULT_RESCAN_WINDOW: usize = 0x100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MatchLocation {
+    // Matched case-insensitively, as module names are everywhere else.
+    pub module: String,
+    pub rva: usize,
+}
+
+impl MatchLocation {
+    pub fn new(module: &str, rva: usize) -> Self {
+        MatchLocation { module: module.to_string(), rva }
+    }
+
+    // Where `address` is relative to the module it falls in, if any.
+    pub fn of(address: usize, modules: &[ModuleInfo]) -> Option<Self> {
+        let module = modules.iter().find(|module| module.contains(address))?;
+        Some(MatchLocation::new(&module.name, address - module.base_address))
+    }
+}
+
+impl fmt::Display for MatchLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+0x{:X}", self.module, self.rva)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RescanStrategy {
+    // Within the window around the previous location.
+    Near,
+    // Not near it, but elsewhere in the module.
+    FullScan,
+    // Neither, or the module isn't loaded.
+    NotFound,
+}
+
+impl fmt::Display for RescanStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RescanStrategy::Near => "near the previous match",
+            RescanStrategy::FullScan => "by a full module scan",
+            RescanStrategy::NotFound => "not found",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rescanned {
+    pub address: Option<usize>,
+    pub strategy: RescanStrategy,
+}
+
+impl Memory {
+    // One result per entry of `previous`, in the same order.
+    pub fn rescan_near(&self, previous: &[MatchLocation], window: usize, pattern: &Pattern) -> Vec<Option<usize>> {
+        self.rescan_near_report(previous, window, pattern).into_iter().map(|rescanned| rescanned.address).collect()
+    }
+
+    pub fn rescan_near_report(&self, previous: &[MatchLocation], window: usize, pattern: &Pattern) -> Vec<Rescanned> {
+        let modules = self.modules_or_live().unwrap_or_default();
+        let options = self.default_scan_options();
+        previous
+            .iter()
+            .map(|location| {
+                let found = modules
+                    .iter()
+                    .find(|module| wstr::eq_ignore_case(&module.name, &location.module))
+                    .map(|module| rescan_module(self, module, location.rva, window, pattern, options));
+                match found {
+                    Some(Ok(Some((address, strategy)))) => Rescanned { address: Some(address), strategy },
+                    Some(Err(e)) => {
+                        debug!("rescanning {} failed: {}", location, e);
+                        Rescanned { address: None, strategy: RescanStrategy::NotFound }
+                    }
+                    _ => Rescanned { address: None, strategy: RescanStrategy::NotFound },
+                }
+            })
+            .collect()
+    }
+}
+
+// The neighborhood of `rva` in `module` first, then all of `module`. Only the full scan's
+// errors are returned; a neighborhood that can't be read is just a miss.
+pub(crate) fn rescan_module(
+    memory: &Memory,
+    module: &ModuleInfo,
+    rva: usize,
+    window: usize,
+    pattern: &Pattern,
+    options: &ScanOptions,
+) -> io::Result<Option<(usize, RescanStrategy)>> {
+    if let Some(address) = find_near(memory, module, rva, window, pattern) {
+        return Ok(Some((address, RescanStrategy::Near)));
+    }
+    let filter = RegionFilter::new().range(module.base_address..module.base_address + module.size);
+    let found = memory.find_pattern_with(pattern, &filter, options)?;
+    Ok(found.map(|address| (address, RescanStrategy::FullScan)))
+}
+
+// The match starting within `window` bytes of `rva` closest to it, without leaving `module`.
+fn find_near(memory: &Memory, module: &ModuleInfo, rva: usize, window: usize, pattern: &Pattern) -> Option<usize> {
+    if pattern.is_empty() || rva >= module.size {
+        return None;
+    }
+    let start = rva.saturating_sub(window);
+    let end = rva.saturating_add(window).saturating_add(pattern.len()).min(module.size);
+    if end - start < pattern.len() {
+        return None;
+    }
+
+    let buffer = match memory.read_bytes(module.base_address + start, end - start) {
+        Ok(buffer) => buffer,
+        Err(e) => {
+            debug!("reading around {}+0x{:X} failed: {}", module.name, rva, e);
+            return None;
+        }
+    };
+    search_iter(&buffer, pattern)
+        .map(|offset| start + offset)
+        .min_by_key(|found| found.abs_diff(rva))
+        .map(|found| module.base_address + found)
+}
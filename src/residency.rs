@@ -0,0 +1,86 @@
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+
+use crate::memory::{Memory, MemoryRegion};
+use crate::sys::{K32QueryWorkingSetEx, PSAPI_WORKING_SET_EX_BLOCK, PSAPI_WORKING_SET_EX_INFORMATION};
+use crate::win_error::win_call;
+
+pub const PAGE_SIZE: usize = 0x1000;
+
+// Pages queried per QueryWorkingSetEx call.
+const BATCH_PAGES: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageInfo {
+    pub address: usize,
+    // The page is in the target's working set, i.e. touching it will not fault.
+    pub valid: bool,
+    pub shared: bool,
+    pub locked: bool,
+}
+
+impl PageInfo {
+    fn from_attributes(address: usize, attributes: usize) -> Self {
+        let valid = attributes & 1 != 0;
+        PageInfo {
+            address,
+            valid,
+            shared: attributes & (1 << 15) != 0,
+            locked: valid && attributes & (1 << 22) != 0,
+        }
+    }
+}
+
+impl Memory {
+    pub fn residency(&self, region: &MemoryRegion) -> io::Result<Vec<PageInfo>> {
+        self.residency_range(region.start_address.get(), region.size.get())
+    }
+
+    // One entry per page overlapping `address..address + len`.
+    pub fn residency_range(&self, address: usize, len: usize) -> io::Result<Vec<PageInfo>> {
+        let first_page = address & !(PAGE_SIZE - 1);
+        let end = address.saturating_add(len);
+        let page_count = (end - first_page).div_ceil(PAGE_SIZE);
+
+        let mut pages = Vec::with_capacity(page_count);
+        let mut entries = Vec::with_capacity(page_count.min(BATCH_PAGES));
+
+        for batch_start in (0..page_count).step_by(BATCH_PAGES) {
+            let batch_len = BATCH_PAGES.min(page_count - batch_start);
+            entries.clear();
+            entries.extend((0..batch_len).map(|i| PSAPI_WORKING_SET_EX_INFORMATION {
+                VirtualAddress: (first_page + (batch_start + i) * PAGE_SIZE) as *mut c_void,
+                VirtualAttributes: PSAPI_WORKING_SET_EX_BLOCK { Flags: 0 },
+            }));
+
+            win_call!(
+                unsafe {
+                    K32QueryWorkingSetEx(
+                        self.handle(),
+                        entries.as_mut_ptr() as *mut c_void,
+                        (batch_len * mem::size_of::<PSAPI_WORKING_SET_EX_INFORMATION>()) as u32,
+                    )
+                },
+                "Failed to query the working set of process {}",
+                self.process_id()
+            )?;
+
+            pages.extend(entries.iter().map(|entry| {
+                PageInfo::from_attributes(entry.VirtualAddress as usize, unsafe { entry.VirtualAttributes.Flags })
+            }));
+        }
+
+        Ok(pages)
+    }
+
+    // Percentage of the region's pages that are currently resident.
+    pub fn resident_percentage(&self, region: &MemoryRegion) -> io::Result<f64> {
+        let pages = self.residency(region)?;
+        if pages.is_empty() {
+            return Ok(0.0);
+        }
+        let resident = pages.iter().filter(|page| page.valid).count();
+        Ok(resident as f64 * 100.0 / pages.len() as f64)
+    }
+}
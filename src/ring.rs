@@ -0,0 +1,172 @@
+// A single-producer, single-consumer ring of fixed-size message slots laid out in a plain
+// byte region, so both ends can live in different processes (see shared.rs). Each side of a
+// channel only ever pushes or only ever pops.
+//
+// Layout, all little-endian and 8-byte aligned:
+//
+//   0x00 u32 magic "NNRB"     0x04 u32 slot size (payload bytes per slot)
+//   0x08 u32 slot count       0x0C u32 reserved
+//   0x10 u64 head: messages pushed so far (written by the producer)
+//   0x18 u64 tail: messages popped so far (written by the consumer)
+//   0x40 slots, each: u64 sequence, u32 length, u32 reserved, slot-size payload bytes
+//
+// Message n goes to slot n % count. The producer sets the slot's sequence to 2n + 1 before
+// touching the payload and to 2n + 2 once it is complete, then bumps head. A consumer that
+// sees anything but 2n + 2 on either side of its copy has read a torn message.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::ptr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"NNRB");
+const HEADER_SIZE: usize = 0x40;
+const SLOT_HEADER_SIZE: usize = 16;
+
+// A message changed while it was being read. Popping again retries it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TornRead {
+    pub sequence: u64,
+}
+
+impl fmt::Display for TornRead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "message {} was overwritten while being read", self.sequence)
+    }
+}
+
+impl std::error::Error for TornRead {}
+
+pub struct RingBuffer<'a> {
+    base: *mut u8,
+    slot_size: usize,
+    slot_count: usize,
+    region: PhantomData<&'a mut [u8]>,
+}
+
+// Only the atomics and this side's copies touch the region; the other end is another process.
+unsafe impl Send for RingBuffer<'_> {}
+
+impl<'a> RingBuffer<'a> {
+    // Bytes a ring with these dimensions takes up.
+    pub fn required_size(slot_size: usize, slot_count: usize) -> usize {
+        HEADER_SIZE + slot_count * slot_stride(slot_size)
+    }
+
+    // Lays out an empty ring in `region` with as many slots as fit. None if the region is
+    // misaligned or too small for one slot.
+    pub fn create(region: &'a mut [u8], slot_size: usize) -> Option<Self> {
+        if (region.as_ptr() as usize) % 8 != 0 || region.len() < Self::required_size(slot_size, 1) {
+            return None;
+        }
+        let slot_count = (region.len() - HEADER_SIZE) / slot_stride(slot_size);
+        region.fill(0);
+
+        let ring = RingBuffer { base: region.as_mut_ptr(), slot_size, slot_count, region: PhantomData };
+        ring.u32_at(0x04).store(slot_size as u32, Ordering::Relaxed);
+        ring.u32_at(0x08).store(slot_count as u32, Ordering::Relaxed);
+        ring.u32_at(0x00).store(MAGIC, Ordering::Release);
+        Some(ring)
+    }
+
+    // Attaches to a ring the other side created. None unless `region` holds a valid header.
+    pub fn attach(region: &'a mut [u8]) -> Option<Self> {
+        if (region.as_ptr() as usize) % 8 != 0 || region.len() < HEADER_SIZE {
+            return None;
+        }
+        let ring = RingBuffer { base: region.as_mut_ptr(), slot_size: 0, slot_count: 0, region: PhantomData };
+        if ring.u32_at(0x00).load(Ordering::Acquire) != MAGIC {
+            return None;
+        }
+        let slot_size = ring.u32_at(0x04).load(Ordering::Relaxed) as usize;
+        let slot_count = ring.u32_at(0x08).load(Ordering::Relaxed) as usize;
+        if slot_count == 0 || region.len() < Self::required_size(slot_size, slot_count) {
+            return None;
+        }
+        Some(RingBuffer { slot_size, slot_count, ..ring })
+    }
+
+    pub fn slot_size(&self) -> usize {
+        self.slot_size
+    }
+
+    pub fn slot_count(&self) -> usize {
+        self.slot_count
+    }
+
+    // Messages pushed but not popped yet.
+    pub fn len(&self) -> usize {
+        self.head().load(Ordering::Acquire).saturating_sub(self.tail().load(Ordering::Acquire)) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Producer side. False if the ring is full; messages longer than a slot are refused too.
+    pub fn push(&mut self, message: &[u8]) -> bool {
+        let head = self.head().load(Ordering::Relaxed);
+        let tail = self.tail().load(Ordering::Acquire);
+        if message.len() > self.slot_size || head.wrapping_sub(tail) >= self.slot_count as u64 {
+            return false;
+        }
+
+        let slot = self.slot(head);
+        let sequence = unsafe { &*(slot as *const AtomicU64) };
+        sequence.store(2 * head + 1, Ordering::Relaxed);
+        std::sync::atomic::fence(Ordering::Release);
+        unsafe {
+            (slot.add(8) as *mut u32).write_volatile(message.len() as u32);
+            ptr::copy_nonoverlapping(message.as_ptr(), slot.add(SLOT_HEADER_SIZE), message.len());
+        }
+        sequence.store(2 * head + 2, Ordering::Release);
+        self.head().store(head + 1, Ordering::Release);
+        true
+    }
+
+    // Consumer side. Ok(None) if the ring is empty.
+    pub fn pop(&mut self) -> Result<Option<Vec<u8>>, TornRead> {
+        let tail = self.tail().load(Ordering::Relaxed);
+        if self.head().load(Ordering::Acquire) == tail {
+            return Ok(None);
+        }
+
+        let slot = self.slot(tail);
+        let sequence = unsafe { &*(slot as *const AtomicU64) };
+        let expected = 2 * tail + 2;
+        if sequence.load(Ordering::Acquire) != expected {
+            return Err(TornRead { sequence: tail });
+        }
+        let len = (unsafe { (slot.add(8) as *const u32).read_volatile() } as usize).min(self.slot_size);
+        let mut message = vec![0u8; len];
+        unsafe { ptr::copy_nonoverlapping(slot.add(SLOT_HEADER_SIZE), message.as_mut_ptr(), len) };
+        std::sync::atomic::fence(Ordering::Acquire);
+        if sequence.load(Ordering::Relaxed) != expected {
+            return Err(TornRead { sequence: tail });
+        }
+
+        self.tail().store(tail + 1, Ordering::Release);
+        Ok(Some(message))
+    }
+
+    fn slot(&self, sequence: u64) -> *mut u8 {
+        let index = (sequence % self.slot_count as u64) as usize;
+        unsafe { self.base.add(HEADER_SIZE + index * slot_stride(self.slot_size)) }
+    }
+
+    fn u32_at(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*(self.base.add(offset) as *const AtomicU32) }
+    }
+
+    fn head(&self) -> &AtomicU64 {
+        unsafe { &*(self.base.add(0x10) as *const AtomicU64) }
+    }
+
+    fn tail(&self) -> &AtomicU64 {
+        unsafe { &*(self.base.add(0x18) as *const AtomicU64) }
+    }
+}
+
+fn slot_stride(slot_size: usize) -> usize {
+    (SLOT_HEADER_SIZE + slot_size).next_multiple_of(8)
+}
@@ -0,0 +1,60 @@
+use std::io;
+
+use crate::memory::Memory;
+
+impl Memory {
+    // Absolute target of a `[rip + disp32]` operand: the displacement is relative to the end of
+    // the instruction, not to its start or to the displacement itself.
+    pub fn resolve_rip_relative(
+        &self,
+        instruction_address: usize,
+        disp_offset: usize,
+        instruction_len: usize,
+    ) -> io::Result<usize> {
+        let displacement_address = instruction_address
+            .checked_add(disp_offset)
+            .ok_or_else(|| overflow(instruction_address))?;
+        let displacement: i32 = self.read_memory(displacement_address)?;
+
+        instruction_address
+            .checked_add(instruction_len)
+            .and_then(|next| next.checked_add_signed(displacement as isize))
+            .ok_or_else(|| overflow(instruction_address))
+    }
+
+    // Like `resolve_rip_relative`, with the offsets inferred from the instruction bytes.
+    // Understands `mov`/`lea` with a REX.W prefix (`48 8B 05`, `48 8D 05`, any register) and
+    // indirect `call`/`jmp` through the IAT (`FF 15`, `FF 25`).
+    pub fn resolve_rip_relative_auto(&self, instruction_address: usize) -> io::Result<usize> {
+        let bytes: [u8; 3] = self.read_unaligned(instruction_address)?;
+        let (disp_offset, instruction_len) = rip_operand_layout(&bytes).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "No rip-relative operand recognized at 0x{:X} ({:02X} {:02X} {:02X})",
+                    instruction_address, bytes[0], bytes[1], bytes[2]
+                ),
+            )
+        })?;
+        self.resolve_rip_relative(instruction_address, disp_offset, instruction_len)
+    }
+}
+
+// (displacement offset, instruction length) for the encodings `resolve_rip_relative_auto` knows.
+fn rip_operand_layout(bytes: &[u8]) -> Option<(usize, usize)> {
+    // ModRM with mod = 00 and r/m = 101 selects [rip + disp32] in 64-bit mode.
+    let is_rip_modrm = |modrm: u8| modrm & 0xC7 == 0x05;
+
+    match bytes {
+        [0x48 | 0x4C, 0x8B | 0x8D, modrm, ..] if is_rip_modrm(*modrm) => Some((3, 7)),
+        [0xFF, 0x15 | 0x25, ..] => Some((2, 6)),
+        _ => None,
+    }
+}
+
+fn overflow(address: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Rip-relative target of 0x{:X} is outside the address space", address),
+    )
+}
@@ -0,0 +1,119 @@
+use std::io;
+
+use log::debug;
+
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::pattern::Pattern;
+
+// MSVC RTTI, as emitted for classes with virtual functions:
+//
+//   vtable[-1]             -> RTTICompleteObjectLocator
+//   CompleteObjectLocator  { signature, offset, cd_offset, type_descriptor, class_descriptor, self }
+//   TypeDescriptor         { vftable pointer, spare pointer, ".?AVPlayer@@" }
+//
+// In 64-bit images the locator's references are 32-bit RVAs and its signature is 1; in 32-bit
+// images they are absolute pointers and the signature is 0.
+impl Memory {
+    // Vtables of `class_name`, which is either a decorated type name (`.?AVPlayer@@`) or a
+    // plain C++ name (`game::Player`, tried as both class and struct). Classes with multiple
+    // inheritance have one vtable per base, so there can be several. Needs `scan_memory`.
+    pub fn find_rtti_class(&self, class_name: &str) -> io::Result<Vec<usize>> {
+        let pointer_size = self.pointer_size()?;
+        let names = if class_name.starts_with(".?A") {
+            vec![class_name.to_string()]
+        } else {
+            vec![decorate(class_name, 'V'), decorate(class_name, 'U')]
+        };
+
+        let mut vtables = Vec::new();
+        for module in self.modules_or_live()?.iter() {
+            let module_filter = RegionFilter::new().range(module.base_address..module.base_address + module.size);
+            for name in &names {
+                let mut terminated = name.as_bytes().to_vec();
+                terminated.push(0);
+                for name_address in self.find_pattern_all(&Pattern::exact(&terminated)?, &module_filter)? {
+                    let descriptor = name_address - 2 * pointer_size;
+                    debug!("type descriptor {} at 0x{:X} in {}", name, descriptor, module.name);
+                    for locator in self.find_locators(module, descriptor, pointer_size, &module_filter)? {
+                        vtables.extend(self.find_vtables(locator, pointer_size, &module_filter)?);
+                    }
+                }
+            }
+        }
+
+        vtables.sort_unstable();
+        vtables.dedup();
+        Ok(vtables)
+    }
+
+    // Pointer-aligned slots in writable memory that hold `vtable`: the first field of every
+    // live object of that class. Needs `scan_memory`.
+    pub fn find_instances_of_vtable(&self, vtable: usize) -> io::Result<Vec<usize>> {
+        self.find_pointer_values(vtable, &RegionFilter::new().writable(true))
+    }
+
+    // Complete object locators in `module` that reference the type descriptor.
+    fn find_locators(
+        &self,
+        module: &ModuleInfo,
+        descriptor: usize,
+        pointer_size: usize,
+        filter: &RegionFilter,
+    ) -> io::Result<Vec<usize>> {
+        // signature, then the offset fields as wildcards, then the type descriptor reference.
+        let (signature, reference) = if pointer_size == 8 {
+            (1u32, (descriptor - module.base_address) as u32)
+        } else {
+            (0u32, descriptor as u32)
+        };
+        let mut values = signature.to_le_bytes().to_vec();
+        values.extend_from_slice(&[0; 8]);
+        values.extend_from_slice(&reference.to_le_bytes());
+        let mut masks = vec![0xFF; 4];
+        masks.extend_from_slice(&[0; 8]);
+        masks.extend_from_slice(&[0xFF; 4]);
+
+        let candidates = self.find_pattern_all(&Pattern::with_masks(&values, &masks)?, filter)?;
+        Ok(candidates
+            .into_iter()
+            .filter(|&locator| {
+                // 64-bit locators end with an RVA of themselves, which weeds out lookalikes.
+                pointer_size == 4
+                    || self
+                        .read_memory::<u32>(locator + 20)
+                        .is_ok_and(|rva| rva as usize == locator - module.base_address)
+            })
+            .collect())
+    }
+
+    // Each slot pointing at the locator sits right in front of a vtable.
+    fn find_vtables(&self, locator: usize, pointer_size: usize, filter: &RegionFilter) -> io::Result<Vec<usize>> {
+        Ok(self
+            .find_pointer_values(locator, filter)?
+            .into_iter()
+            .map(|slot| slot + pointer_size)
+            .collect())
+    }
+
+    // Pointer-aligned addresses holding `value`, using the target's pointer size.
+    pub(crate) fn find_pointer_values(&self, value: usize, filter: &RegionFilter) -> io::Result<Vec<usize>> {
+        if self.pointer_size()? == 4 {
+            self.scan_for_value(value as u32, filter)
+        } else {
+            self.scan_for_value(value as u64, filter)
+        }
+    }
+}
+
+// `game::Player` -> `.?AVPlayer@game@@` (kind 'V' for class, 'U' for struct).
+fn decorate(name: &str, kind: char) -> String {
+    let mut decorated = format!(".?A{}", kind);
+    for part in name.split("::").collect::<Vec<_>>().iter().rev() {
+        decorated.push_str(part);
+        decorated.push('@');
+    }
+    decorated.push('@');
+    decorated
+}
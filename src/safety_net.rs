@@ -0,0 +1,194 @@
+// A copy of exactly what a risky operation is about to touch, written to disk before it
+// proceeds. Patches, hooks and remote code can leave the target alive but broken; the
+// recovery file brings the bytes back:
+//
+//     let net = SafetyNet::arm(&memory, &[target..target + 16])?;
+//     memory.write_protected(target, &detour)?;
+//     // ... the target misbehaves ...
+//     SafetyNet::restore(net.path(), &memory)?;
+//
+// With `set_safety_net(Some(dir))` the patch manager (`apply_patch`, and `replace_pattern`
+// once for all its matches), the hook installer and the manual mapper arm one themselves,
+// and log the file's path. Recovery files are named
+// `safety-<pid>-<unix millis>.json`, with a counter added for a name already taken, and hold
+// each range's bytes and the committed regions it overlapped. Nothing deletes them, since an
+// operation can go wrong long after it returned.
+// `restore` writes the bytes back through `write_protected`, then gives every page of a saved
+// range the protection its region had when the net was armed. A manually mapped image is new
+// memory, so restoring its net only blanks the image again; it can't undo what DllMain did.
+
+use std::fs::{self, File};
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::atomic_file;
+use crate::memory::{Memory, MemoryRegion};
+use crate::persist::{self, Format};
+
+const FORMAT: Format = Format { id: "safetynet", version: 2, legacy: None };
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedRange {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    // The committed regions the range overlapped when it was saved.
+    pub regions: Vec<MemoryRegion>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SafetyNet {
+    pub process_id: u32,
+    // Milliseconds since the Unix epoch.
+    pub created: u64,
+    pub ranges: Vec<SavedRange>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl SafetyNet {
+    // Saves `planned` to a new recovery file in the directory given to `set_safety_net`, or
+    // the temporary directory. Fails, so that the operation doesn't go ahead, if any planned
+    // byte can't be read or the file can't be written.
+    pub fn arm(memory: &Memory, planned: &[Range<usize>]) -> io::Result<SafetyNet> {
+        let ranges = planned
+            .iter()
+            .filter(|range| !range.is_empty())
+            .map(|range| {
+                let bytes = memory.read_bytes(range.start, range.len())?;
+                if bytes.len() < range.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "Only 0x{:X} of 0x{:X} bytes at 0x{:X} are readable",
+                            bytes.len(),
+                            range.len(),
+                            range.start
+                        ),
+                    ));
+                }
+                let regions = memory.walk_regions(range.start, |next| next < range.end).0;
+                Ok(SavedRange { address: range.start, bytes, regions })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        SafetyNet::write_new(memory, ranges)
+    }
+
+    // Saves bytes the caller already has, such as what patches replaced, as they are rather
+    // than as the target holds them now. For undoing an operation that failed part way.
+    #[cfg(feature = "patch")]
+    pub(crate) fn record(memory: &Memory, saved: Vec<(usize, Vec<u8>)>) -> io::Result<SafetyNet> {
+        let ranges = saved
+            .into_iter()
+            .map(|(address, bytes)| {
+                let end = address.saturating_add(bytes.len());
+                let regions = memory.walk_regions(address, |next| next < end).0;
+                SavedRange { address, bytes, regions }
+            })
+            .collect();
+        SafetyNet::write_new(memory, ranges)
+    }
+
+    fn write_new(memory: &Memory, ranges: Vec<SavedRange>) -> io::Result<SafetyNet> {
+        let created =
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis() as u64).unwrap_or(0);
+        let dir = memory.safety_net_dir().map(Path::to_path_buf).unwrap_or_else(std::env::temp_dir);
+        fs::create_dir_all(&dir)?;
+        // Nets armed within the same millisecond get a counter. The name is taken with an empty
+        // file, which the finished one is renamed over (see atomic_file.rs), so a net cut short
+        // by a crash never passes for a whole one.
+        let (reserved, path) = (0..)
+            .map(|attempt| {
+                let name = match attempt {
+                    0 => format!("safety-{}-{}.json", memory.process_id(), created),
+                    _ => format!("safety-{}-{}-{}.json", memory.process_id(), created, attempt),
+                };
+                let path = dir.join(name);
+                (File::create_new(&path), path)
+            })
+            .find(|(file, _)| !matches!(file, Err(e) if e.kind() == io::ErrorKind::AlreadyExists))
+            .expect("the attempts never run out");
+        reserved?;
+        let net = SafetyNet { process_id: memory.process_id(), created, ranges, path };
+        let written =
+            atomic_file::write_atomically(&net.path, |writer| persist::write_json_to(writer, &FORMAT, None, &net));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&net.path);
+            return Err(e);
+        }
+        Ok(net)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut net: SafetyNet = persist::read_json(path.as_ref(), &FORMAT)?.1;
+        net.path = path.as_ref().to_path_buf();
+        Ok(net)
+    }
+
+    // Writes every saved range of the recovery file at `path` back into the target and
+    // returns the ranges restored. Refuses a file saved from another process.
+    pub fn restore(path: impl AsRef<Path>, memory: &Memory) -> io::Result<Vec<Range<usize>>> {
+        let net = SafetyNet::load(path)?;
+        if net.process_id != memory.process_id() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} was saved from process {}, not {}",
+                    net.path.display(),
+                    net.process_id,
+                    memory.process_id()
+                ),
+            ));
+        }
+
+        let mut restored = Vec::new();
+        for saved in &net.ranges {
+            let range = saved.address..saved.address + saved.bytes.len();
+            memory.write_protected(saved.address, &saved.bytes)?;
+            for region in &saved.regions {
+                let start = region.start_address.get().max(range.start);
+                let end = region.end().get().min(range.end);
+                if start < end && region.protection != 0 {
+                    if let Err(e) = memory.protect(start, end - start, region.protection) {
+                        warn!("failed to restore protection 0x{:X} at 0x{:X}: {}", region.protection, start, e);
+                    }
+                }
+            }
+            restored.push(range);
+        }
+        Ok(restored)
+    }
+
+    // The recovery file this net was saved to or loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Memory {
+    // Whether, and where, risky operations save a `SafetyNet` first; None (the default) turns
+    // that off.
+    pub fn set_safety_net(&mut self, dir: Option<PathBuf>) {
+        *self.safety_net_slot() = dir;
+    }
+
+    pub fn safety_net_dir(&self) -> Option<&Path> {
+        self.safety_net_setting().as_deref()
+    }
+
+    // Arms a net for `planned` when `set_safety_net` turned them on. `operation` names the
+    // caller in the log.
+    #[cfg(any(feature = "patch", feature = "inject"))]
+    pub(crate) fn arm_safety_net(&self, operation: &str, planned: &[Range<usize>]) -> io::Result<()> {
+        if self.safety_net_dir().is_none() || self.is_dry_run() {
+            return Ok(());
+        }
+        let net = SafetyNet::arm(self, planned)?;
+        log::info!("{} armed a safety net: {}", operation, net.path().display());
+        Ok(())
+    }
+}
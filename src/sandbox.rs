@@ -0,0 +1,477 @@
+// A target the crate can be aimed at without risk, for tests and for trying features out. The
+// sandbox is a child process laid out from a `SandboxConfig`: allocations with chosen
+// protections, planted patterns, a counter, a pointer chain to an i32/f32 pair, a guard page
+// and a huge reserved region. Once it runs, the parent changes it on demand through a control
+// channel, so a test decides exactly when the target changes instead of racing it:
+//
+//     let config = SandboxConfig::new().allocation(0x1000, 0x02).pattern(b"NNSBX\x13\x37");  // read-only
+//     let mut sandbox = Sandbox::spawn(&config)?;
+//     let memory = sandbox.open()?;
+//     let counter = sandbox.layout().counter;
+//     sandbox.step_counter(1)?;                       // the counter is 1 when this returns
+//     sandbox.protect(sandbox.layout().allocations[0], 0x1000, 0x01)?;   // no access
+//     let base = sandbox.load_dll("version.dll")?;    // a module that loads late
+//
+// The child is the same program started again with NIRVANA_SANDBOX set, so a program that
+// spawns sandboxes calls `run_if_child` first thing in `main` (the CLI does);
+// `Sandbox::spawn_with` starts another program instead. The channel is the child's stdin
+// and stdout: the config and then one `Control` per line, each answered by one `Reply` line,
+// as the server's protocol is. The child zeroes its copies of the planted patterns, so each
+// pattern is found once, at the address in the layout. It exits when its stdin closes, and
+// dropping a `Sandbox` kills it. The fixture example is a sandbox with a fixed config.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::c_void;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{self, Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::ptr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::Memory;
+use crate::sys::{
+    FreeLibrary, LoadLibraryW, VirtualAlloc, VirtualFree, VirtualProtect, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE,
+    PAGE_GUARD, PAGE_NOACCESS, PAGE_READWRITE,
+};
+use crate::win_error::win_call;
+use crate::wstr;
+
+// Set in the child's environment; `run_if_child` looks for it.
+pub const SANDBOX_ENV: &str = "NIRVANA_SANDBOX";
+
+// The pointer chain's offsets, and the values at its end.
+pub const CHAIN_OFFSETS: [usize; 2] = [0x10, 0x08];
+pub const CHAIN_I32: i32 = 0x1337;
+pub const CHAIN_F32: f32 = 13.37;
+
+// Static root of the pointer chain, so it lives in the exe image like a game's globals.
+static CHAIN_ROOT: AtomicUsize = AtomicUsize::new(0);
+
+// Two heap nodes: root -> first (+0x10) -> second (+0x08) -> the i32.
+#[repr(C)]
+struct FirstNode {
+    padding: [u64; 2],
+    next: *const SecondNode,
+}
+
+#[repr(C)]
+struct SecondNode {
+    padding: u64,
+    value: i32,
+    scale: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxAllocation {
+    pub size: usize,
+    pub protection: u32,
+    // The byte the allocation is filled with before it gets `protection`.
+    pub fill: u8,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    pub allocations: Vec<SandboxAllocation>,
+    // Each in a read-write allocation of its own.
+    pub patterns: Vec<Vec<u8>>,
+    // Milliseconds between the counter's own increments. Unset means it only changes through
+    // `Sandbox::step_counter`, which is what deterministic tests want.
+    pub counter_interval_ms: Option<u64>,
+    // CHAIN_I32 and CHAIN_F32 behind a two-level chain from a static root.
+    pub pointer_chain: bool,
+    pub guard_page: bool,
+    // Bytes reserved and never committed; 0 for none.
+    pub huge_reserve: usize,
+}
+
+impl SandboxConfig {
+    pub fn new() -> Self {
+        SandboxConfig::default()
+    }
+
+    pub fn allocation(self, size: usize, protection: u32) -> Self {
+        self.filled_allocation(size, protection, 0)
+    }
+
+    pub fn filled_allocation(mut self, size: usize, protection: u32, fill: u8) -> Self {
+        self.allocations.push(SandboxAllocation { size, protection, fill });
+        self
+    }
+
+    pub fn pattern(mut self, bytes: &[u8]) -> Self {
+        self.patterns.push(bytes.to_vec());
+        self
+    }
+
+    pub fn counter_interval(mut self, interval: Duration) -> Self {
+        self.counter_interval_ms = Some(interval.as_millis() as u64);
+        self
+    }
+
+    pub fn pointer_chain(mut self) -> Self {
+        self.pointer_chain = true;
+        self
+    }
+
+    pub fn guard_page(mut self) -> Self {
+        self.guard_page = true;
+        self
+    }
+
+    pub fn huge_reserve(mut self, bytes: usize) -> Self {
+        self.huge_reserve = bytes;
+        self
+    }
+}
+
+// Where the child put what its config asked for. `allocations` and `patterns` are in config
+// order; `counter` is the address of a u64.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxLayout {
+    pub pid: u32,
+    pub allocations: Vec<usize>,
+    pub patterns: Vec<usize>,
+    pub counter: usize,
+    pub pointer_chain: Option<SandboxChain>,
+    pub guard_page: Option<usize>,
+    pub huge_reserve: Option<usize>,
+}
+
+// `root` holds the first node's address; following `offsets` from it ends at `target`, the
+// i32, with the f32 at `scale` right after it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxChain {
+    pub root: usize,
+    pub offsets: Vec<usize>,
+    pub target: usize,
+    pub scale: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Control {
+    Alloc { size: usize, protection: u32 },
+    Free { address: usize },
+    // Answered with the old protection of the first page.
+    Protect { address: usize, size: usize, protection: u32 },
+    // Made writable for the write, whatever its protection, and put back after.
+    Write { address: usize, bytes: Vec<u8> },
+    // Adds `by` to the counter and answers with the new value.
+    Step { by: u64 },
+    LoadDll { name: String },
+    FreeDll { name: String },
+    Quit,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Reply {
+    Allocated { address: usize },
+    Protected { old: u32 },
+    Counter { value: u64 },
+    Loaded { base: usize },
+    Done,
+    Error { message: String },
+}
+
+pub struct Sandbox {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    layout: SandboxLayout,
+}
+
+impl Sandbox {
+    // Starts this program again as the sandbox.
+    pub fn spawn(config: &SandboxConfig) -> io::Result<Self> {
+        Sandbox::spawn_with(Command::new(env::current_exe()?), config)
+    }
+
+    // `command` must run a program that calls `run_if_child` before touching stdin or stdout.
+    pub fn spawn_with(mut command: Command, config: &SandboxConfig) -> io::Result<Self> {
+        let mut child = command.env(SANDBOX_ENV, "1").stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+        let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            let _ = child.kill();
+            return Err(io::Error::other("The sandbox's stdin and stdout were not piped"));
+        };
+        let mut sandbox = Sandbox { child, stdin, stdout: BufReader::new(stdout), layout: SandboxLayout::default() };
+        sandbox.send(config)?;
+        sandbox.layout = sandbox.receive()?;
+        Ok(sandbox)
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn layout(&self) -> &SandboxLayout {
+        &self.layout
+    }
+
+    // A handle to the sandbox with its regions and modules scanned.
+    pub fn open(&self) -> io::Result<Memory> {
+        let mut memory = Memory::open(self.pid())?;
+        memory.scan_memory()?;
+        memory.scan_modules()?;
+        Ok(memory)
+    }
+
+    pub fn alloc(&mut self, size: usize, protection: u32) -> io::Result<usize> {
+        match self.call(&Control::Alloc { size, protection })? {
+            Reply::Allocated { address } => Ok(address),
+            reply => Err(unexpected(&reply)),
+        }
+    }
+
+    pub fn free(&mut self, address: usize) -> io::Result<()> {
+        self.call_done(&Control::Free { address })
+    }
+
+    pub fn protect(&mut self, address: usize, size: usize, protection: u32) -> io::Result<u32> {
+        match self.call(&Control::Protect { address, size, protection })? {
+            Reply::Protected { old } => Ok(old),
+            reply => Err(unexpected(&reply)),
+        }
+    }
+
+    pub fn write(&mut self, address: usize, bytes: &[u8]) -> io::Result<()> {
+        self.call_done(&Control::Write { address, bytes: bytes.to_vec() })
+    }
+
+    pub fn step_counter(&mut self, by: u64) -> io::Result<u64> {
+        match self.call(&Control::Step { by })? {
+            Reply::Counter { value } => Ok(value),
+            reply => Err(unexpected(&reply)),
+        }
+    }
+
+    // The module's base once it is loaded in the sandbox.
+    pub fn load_dll(&mut self, name: &str) -> io::Result<usize> {
+        match self.call(&Control::LoadDll { name: name.to_string() })? {
+            Reply::Loaded { base } => Ok(base),
+            reply => Err(unexpected(&reply)),
+        }
+    }
+
+    pub fn free_dll(&mut self, name: &str) -> io::Result<()> {
+        self.call_done(&Control::FreeDll { name: name.to_string() })
+    }
+
+    // Asks the sandbox to exit and waits until it has.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.call_done(&Control::Quit)?;
+        self.child.wait()?;
+        Ok(())
+    }
+
+    fn call(&mut self, control: &Control) -> io::Result<Reply> {
+        self.send(control)?;
+        match self.receive()? {
+            Reply::Error { message } => Err(io::Error::other(format!("The sandbox failed: {}", message))),
+            reply => Ok(reply),
+        }
+    }
+
+    fn call_done(&mut self, control: &Control) -> io::Result<()> {
+        match self.call(control)? {
+            Reply::Done => Ok(()),
+            reply => Err(unexpected(&reply)),
+        }
+    }
+
+    fn send(&mut self, value: &impl Serialize) -> io::Result<()> {
+        let mut line = serde_json::to_string(value).map_err(io::Error::other)?;
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes())?;
+        self.stdin.flush()
+    }
+
+    fn receive<T: for<'de> Deserialize<'de>>(&mut self) -> io::Result<T> {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The sandbox exited"));
+        }
+        serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn unexpected(reply: &Reply) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected sandbox reply {:?}", reply))
+}
+
+// Runs the sandbox and exits if this process was started as one; returns otherwise.
+pub fn run_if_child() {
+    if env::var_os(SANDBOX_ENV).is_none() {
+        return;
+    }
+    let code = match run_child() {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("sandbox: {}", e);
+            1
+        }
+    };
+    process::exit(code);
+}
+
+fn run_child() -> io::Result<()> {
+    let mut lines = io::stdin().lock().lines();
+    let mut stdout = io::stdout().lock();
+    let Some(line) = lines.next() else {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No sandbox config on stdin"));
+    };
+    let mut config: SandboxConfig =
+        serde_json::from_str(&line?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut state = SandboxState::build(&mut config)?;
+    send_line(&mut stdout, &state.layout)?;
+
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match serde_json::from_str::<Control>(&line) {
+            Ok(Control::Quit) => {
+                send_line(&mut stdout, &Reply::Done)?;
+                break;
+            }
+            Ok(control) => state.handle(control),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        send_line(&mut stdout, &reply.unwrap_or_else(|e| Reply::Error { message: e.to_string() }))?;
+    }
+    Ok(())
+}
+
+fn send_line(out: &mut impl Write, value: &impl Serialize) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, value).map_err(io::Error::other)?;
+    out.write_all(b"\n")?;
+    out.flush()
+}
+
+// The child's side: what it allocated and loaded.
+struct SandboxState {
+    layout: SandboxLayout,
+    counter: &'static AtomicU64,
+    dlls: HashMap<String, usize>,
+}
+
+impl SandboxState {
+    fn build(config: &mut SandboxConfig) -> io::Result<Self> {
+        let mut layout = SandboxLayout { pid: process::id(), ..SandboxLayout::default() };
+        for allocation in &config.allocations {
+            let address = alloc_local(allocation.size, PAGE_READWRITE)?;
+            unsafe { ptr::write_bytes(address as *mut u8, allocation.fill, allocation.size) };
+            protect_local(address, allocation.size, allocation.protection)?;
+            layout.allocations.push(address);
+        }
+        for pattern in &mut config.patterns {
+            let address = alloc_local(pattern.len().max(1), PAGE_READWRITE)?;
+            unsafe { ptr::copy_nonoverlapping(pattern.as_ptr(), address as *mut u8, pattern.len()) };
+            pattern.fill(0);
+            layout.patterns.push(address);
+        }
+
+        // A page of its own, so the counter is the only thing in its region.
+        layout.counter = alloc_local(size_of::<AtomicU64>(), PAGE_READWRITE)?;
+        let counter: &'static AtomicU64 = unsafe { &*(layout.counter as *const AtomicU64) };
+        if let Some(interval) = config.counter_interval_ms {
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_millis(interval));
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+
+        if config.pointer_chain {
+            let second = Box::leak(Box::new(SecondNode { padding: 0, value: CHAIN_I32, scale: CHAIN_F32 }));
+            let first = Box::leak(Box::new(FirstNode { padding: [0; 2], next: second }));
+            CHAIN_ROOT.store(first as *const FirstNode as usize, Ordering::SeqCst);
+            layout.pointer_chain = Some(SandboxChain {
+                root: &CHAIN_ROOT as *const AtomicUsize as usize,
+                offsets: CHAIN_OFFSETS.to_vec(),
+                target: &second.value as *const i32 as usize,
+                scale: &second.scale as *const f32 as usize,
+            });
+        }
+        if config.guard_page {
+            layout.guard_page = Some(alloc_local(0x1000, PAGE_READWRITE | PAGE_GUARD)?);
+        }
+        if config.huge_reserve > 0 {
+            let size = config.huge_reserve;
+            let address = win_call!(
+                unsafe { VirtualAlloc(ptr::null_mut(), size, MEM_RESERVE, PAGE_NOACCESS) },
+                "Failed to reserve 0x{:X} bytes",
+                size
+            )?;
+            layout.huge_reserve = Some(address as usize);
+        }
+        Ok(SandboxState { layout, counter, dlls: HashMap::new() })
+    }
+
+    fn handle(&mut self, control: Control) -> io::Result<Reply> {
+        match control {
+            Control::Alloc { size, protection } => Ok(Reply::Allocated { address: alloc_local(size, protection)? }),
+            Control::Free { address } => {
+                let freed = unsafe { VirtualFree(address as *mut c_void, 0, MEM_RELEASE) };
+                win_call!(freed, "Failed to free 0x{:X}", address)?;
+                Ok(Reply::Done)
+            }
+            Control::Protect { address, size, protection } => {
+                Ok(Reply::Protected { old: protect_local(address, size, protection)? })
+            }
+            Control::Write { address, bytes } => {
+                let old = protect_local(address, bytes.len(), PAGE_READWRITE)?;
+                unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), address as *mut u8, bytes.len()) };
+                protect_local(address, bytes.len(), old)?;
+                Ok(Reply::Done)
+            }
+            Control::Step { by } => Ok(Reply::Counter { value: self.counter.fetch_add(by, Ordering::SeqCst) + by }),
+            Control::LoadDll { name } => {
+                let wide = wstr::to_wide_nul(&name);
+                let base = win_call!(unsafe { LoadLibraryW(wide.as_ptr()) }, "Failed to load {}", name)? as usize;
+                self.dlls.insert(name, base);
+                Ok(Reply::Loaded { base })
+            }
+            Control::FreeDll { name } => {
+                let Some(base) = self.dlls.remove(&name) else {
+                    return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} was not loaded", name)));
+                };
+                win_call!(unsafe { FreeLibrary(base as *mut c_void) }, "Failed to free {}", name)?;
+                Ok(Reply::Done)
+            }
+            Control::Quit => Ok(Reply::Done),
+        }
+    }
+}
+
+fn alloc_local(size: usize, protection: u32) -> io::Result<usize> {
+    let address = win_call!(
+        unsafe { VirtualAlloc(ptr::null_mut(), size, MEM_COMMIT | MEM_RESERVE, protection) },
+        "Failed to allocate 0x{:X} bytes",
+        size
+    )?;
+    Ok(address as usize)
+}
+
+// Returns the old protection of the first page.
+fn protect_local(address: usize, size: usize, protection: u32) -> io::Result<u32> {
+    let mut old = 0;
+    win_call!(
+        unsafe { VirtualProtect(address as *mut c_void, size, protection, &mut old) },
+        "Failed to protect 0x{:X} bytes at 0x{:X}",
+        size,
+        address
+    )?;
+    Ok(old)
+}
@@ -0,0 +1,1487 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ops::{ControlFlow, Range};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+use serde::{Deserialize, Serialize};
+
+use crate::address::Address;
+use crate::artifacts;
+use crate::buffer_pool::PooledBuffer;
+use crate::chunk_tuner::{ChunkTuner, MAX_CHUNK, MIN_CHUNK};
+use crate::endian::Endianness;
+use crate::filter::RegionFilter;
+use crate::interest::{self, RankedMatch, NEIGHBORHOOD};
+use crate::memory::{Memory, MemoryRegion};
+use crate::pattern::{self, Pattern};
+use crate::residency::PAGE_SIZE;
+use crate::sink::{ScanSink, SharedSink};
+use crate::spans::{record, span};
+use crate::stats::format_size;
+
+pub(crate) const CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ScanDirection {
+    #[default]
+    Forward,
+    // Walks regions from the highest address down and searches each chunk back to front.
+    Reverse,
+}
+
+// Shared flag that stops a running scan at the next chunk boundary. Clones share the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Tokens are equal when they share a flag.
+impl PartialEq for CancelToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CancelToken {}
+
+// Called with each match as the scan finds it; returning `Break` ends the scan after that
+// match. Clones share the closure, and calls are serialized through a mutex, so one callback
+// can be handed to scans on several threads. A callback that starts a scan with the same
+// callback deadlocks.
+//
+// A forward pattern scan reports its matches in ascending order: regions are walked in
+// cached (address) order and each is scanned front to back. A reverse scan reports them in
+// descending order. Float scans go forward only.
+#[derive(Clone)]
+pub struct MatchCallback(Arc<Mutex<dyn FnMut(usize) -> ControlFlow<()> + Send>>);
+
+impl MatchCallback {
+    pub fn new(callback: impl FnMut(usize) -> ControlFlow<()> + Send + 'static) -> Self {
+        MatchCallback(Arc::new(Mutex::new(callback)))
+    }
+
+    pub fn call(&self, address: usize) -> ControlFlow<()> {
+        // A callback that panicked once is still called; its state is the caller's business.
+        let mut callback = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        (*callback)(address)
+    }
+}
+
+impl fmt::Debug for MatchCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("MatchCallback")
+    }
+}
+
+// Callbacks are equal when they share a closure.
+impl PartialEq for MatchCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for MatchCallback {}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanOptions {
+    pub direction: ScanDirection,
+    // Only match at absolute addresses that are a multiple of this. Unset means 1 for pattern
+    // scans and `align_of::<T>()` for value scans.
+    pub alignment: Option<usize>,
+    // Skip pages outside the target's working set instead of faulting them in. Matches in
+    // paged-out memory are missed, so this trades completeness for speed.
+    pub resident_only: bool,
+    // Once cancelled, the scan ends as if there were no more matches.
+    pub cancel: Option<CancelToken>,
+    // Byte order of the values `scan_for_value` looks for. Unset means the native order.
+    pub endianness: Option<Endianness>,
+    // Also scan our own allocations and patches, which are skipped by default.
+    pub include_self_artifacts: bool,
+    // Query each cached region again right before reading it and skip it if it is gone or its
+    // allocation base, size or protection changed. Otherwise a region freed since
+    // `scan_memory` can be read as whatever unrelated memory was allocated there since.
+    pub validate_regions: bool,
+    // Enumerate the regions afresh when the scan starts instead of using the cached list.
+    pub rescan_regions: bool,
+    // Enumerate just the filter's range afresh when the scan starts, and use the cached list
+    // for the rest. Cheaper than `rescan_regions` for a scan of one module or heap; without
+    // a range in the filter it does nothing.
+    pub refresh_range: bool,
+    // Treat back-to-back regions of one allocation as contiguous, so a pattern scan finds
+    // matches that straddle a protection change inside it. Other scans ignore this.
+    pub merge_allocations: bool,
+    // Bytes read per call. Unset means 1 MiB; smaller chunks make cancellation
+    // and throttling more responsive at the cost of more calls. Pattern and value scans tune
+    // it as they go unless `fixed_chunk_size` is set, and start from here.
+    pub chunk_size: Option<usize>,
+    // Turns the tuning off; see chunk_tuner.rs.
+    pub fixed_chunk_size: bool,
+    // (smallest, largest) chunk the tuning may pick. Unset means 64 KiB to 8 MiB.
+    pub chunk_size_bounds: Option<(usize, usize)>,
+    // Sees every match as it is found, before the scan's own result includes it, and can end
+    // the scan early. See `MatchCallback` for the order matches arrive in.
+    pub on_match: Option<MatchCallback>,
+    // Sees the matches after `on_match`, and the regions and the report too; see sink.rs.
+    pub sink: Option<SharedSink>,
+    // Drop matches whose neighborhood, the aligned block of this many bytes around them (a
+    // cache line is 64), holds one byte value apart from the match itself. Meant for value
+    // scans, where it weeds out numbers sitting in zero- or pattern-filled memory; see
+    // interest.rs.
+    pub skip_uniform_neighborhood: Option<usize>,
+    // Score every match by its neighborhood into `ScanReport::ranked`, and have
+    // `scan_for_value_with` return matches best first instead of by address.
+    pub rank_results: bool,
+}
+
+// Options travel into worker threads (async scans, the server) and are cloned per call.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync + Clone>() {}
+    assert_send_sync::<ScanOptions>();
+};
+
+impl ScanOptions {
+    pub fn new() -> Self {
+        ScanOptions::default()
+    }
+
+    pub fn direction(mut self, direction: ScanDirection) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn alignment(mut self, alignment: usize) -> Self {
+        self.alignment = Some(alignment.max(1));
+        self
+    }
+
+    pub fn resident_only(mut self, resident_only: bool) -> Self {
+        self.resident_only = resident_only;
+        self
+    }
+
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = Some(endianness);
+        self
+    }
+
+    pub fn exclude_self_artifacts(mut self, exclude: bool) -> Self {
+        self.include_self_artifacts = !exclude;
+        self
+    }
+
+    pub fn validate_regions(mut self, validate: bool) -> Self {
+        self.validate_regions = validate;
+        self
+    }
+
+    pub fn rescan_regions(mut self, rescan: bool) -> Self {
+        self.rescan_regions = rescan;
+        self
+    }
+
+    pub fn refresh_range(mut self, refresh: bool) -> Self {
+        self.refresh_range = refresh;
+        self
+    }
+
+    pub fn merge_allocations(mut self, merge: bool) -> Self {
+        self.merge_allocations = merge;
+        self
+    }
+
+    // At least one page. Every chunk is read at this size; `adaptive_chunks(true)` after it
+    // tunes the size starting from here instead.
+    pub fn chunk_size(mut self, size: usize) -> Self {
+        self.chunk_size = Some(size.max(PAGE_SIZE));
+        self.fixed_chunk_size = true;
+        self
+    }
+
+    pub fn adaptive_chunks(mut self, adaptive: bool) -> Self {
+        self.fixed_chunk_size = !adaptive;
+        self
+    }
+
+    pub fn chunk_size_bounds(mut self, min: usize, max: usize) -> Self {
+        self.chunk_size_bounds = Some((min, max.max(min)));
+        self
+    }
+
+    pub fn skip_uniform_neighborhood(mut self, size: usize) -> Self {
+        self.skip_uniform_neighborhood = Some(size.max(1));
+        self
+    }
+
+    pub fn rank_results(mut self, rank: bool) -> Self {
+        self.rank_results = rank;
+        self
+    }
+
+    pub fn on_match(mut self, callback: impl FnMut(usize) -> ControlFlow<()> + Send + 'static) -> Self {
+        self.on_match = Some(MatchCallback::new(callback));
+        self
+    }
+
+    pub fn sink<S: ScanSink + 'static>(mut self, sink: Arc<Mutex<S>>) -> Self {
+        self.sink = Some(SharedSink::new(sink));
+        self
+    }
+
+    // Whether the `on_match` callback or the sink, if any, asks for the scan to end after
+    // `address`. Both see the match either way.
+    pub(crate) fn report_match(&self, address: usize) -> ControlFlow<()> {
+        report_match(self.on_match.as_ref(), self.sink.as_ref(), address)
+    }
+
+    pub(crate) fn chunk_len(&self) -> usize {
+        self.chunk_size.unwrap_or(CHUNK_SIZE).max(PAGE_SIZE)
+    }
+
+    pub(crate) fn chunk_tuner(&self) -> ChunkTuner {
+        if self.fixed_chunk_size {
+            return ChunkTuner::fixed(self.chunk_len());
+        }
+        let (min, max) = self.chunk_size_bounds.unwrap_or((MIN_CHUNK, MAX_CHUNK));
+        ChunkTuner::new(self.chunk_len(), min, max)
+    }
+}
+
+fn report_match(callback: Option<&MatchCallback>, sink: Option<&SharedSink>, address: usize) -> ControlFlow<()> {
+    let called = callback.map_or(ControlFlow::Continue(()), |callback| callback.call(address));
+    let sunk = sink.map_or(ControlFlow::Continue(()), |sink| sink.on_match(address));
+    if called.is_break() || sunk.is_break() {
+        ControlFlow::Break(())
+    } else {
+        ControlFlow::Continue(())
+    }
+}
+
+// A cached region that no longer matched the target when a validating scan reached it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaleRegion {
+    pub cached: MemoryRegion,
+    // What starts at the cached address now; None if nothing committed does.
+    pub current: Option<MemoryRegion>,
+}
+
+impl StaleRegion {
+    pub fn vanished(&self) -> bool {
+        self.current.is_none()
+    }
+}
+
+// Why a bulk operation left a region out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum SkipReason {
+    // The filter's protection or address range excludes it, or all of it is our own artifacts.
+    Filtered,
+    // Larger than the filter's `max_region_size`.
+    TooLarge,
+    Guard,
+    // Nothing in it could be read.
+    ReadFailed,
+    // It changed since it was cached; `ScanReport::stale` says how.
+    Stale,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SkipReason::Filtered => "filtered",
+            SkipReason::TooLarge => "too large",
+            SkipReason::Guard => "guard",
+            SkipReason::ReadFailed => "read failed",
+            SkipReason::Stale => "stale",
+        })
+    }
+}
+
+// What a bulk operation covered: its matches, how many regions it walked and read, which it
+// left out and why, and how long it took. A scan that found nothing over 12 of 530 regions
+// looks very different from one that found nothing over all of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScanReport {
+    pub matches: Vec<usize>,
+    // Regions in the list the operation walked, whether or not it scanned them.
+    pub regions_total: usize,
+    // Regions at least one byte was read from.
+    pub regions_scanned: usize,
+    // (region address, reason), in the order the operation came to them.
+    pub regions_skipped: Vec<(usize, SkipReason)>,
+    pub bytes_scanned: usize,
+    // Bytes of scanned regions that failed to read, including the holes.
+    pub bytes_unreadable: usize,
+    pub duration: Duration,
+    pub stale: Vec<StaleRegion>,
+    // Unreadable pages inside the scanned regions, which the scan went around. Sorted.
+    pub holes: Vec<Range<usize>>,
+    // With `rank_results`, every match scored, best first.
+    pub ranked: Vec<RankedMatch>,
+    // Matches dropped by `skip_uniform_neighborhood`.
+    pub uniform_skipped: usize,
+    // Chunks read or tried, by their size in bytes, as the tuning chose them. Only pattern
+    // and value scans fill this in.
+    pub chunk_sizes: BTreeMap<usize, usize>,
+}
+
+impl ScanReport {
+    // How many regions were skipped for each reason.
+    pub fn skipped_by_reason(&self) -> BTreeMap<SkipReason, usize> {
+        let mut counts = BTreeMap::new();
+        for (_, reason) in &self.regions_skipped {
+            *counts.entry(*reason).or_default() += 1;
+        }
+        counts
+    }
+}
+
+// "3 matches in 412 of 530 regions, 1.20 GiB read, 118 skipped (110 filtered, 8 guard),
+// 12.00 KiB unreadable (3 holes), 1.52s". The alternate form (`{:#}`) lists the matches, the
+// skipped regions, the stale ones and the holes below that, one per line.
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: usize| if count == 1 { "" } else { "s" };
+        write!(
+            f,
+            "{} match{} in {} of {} region{}, {} read",
+            self.matches.len(),
+            if self.matches.len() == 1 { "" } else { "es" },
+            self.regions_scanned,
+            self.regions_total,
+            plural(self.regions_total),
+            format_size(self.bytes_scanned)
+        )?;
+        if !self.regions_skipped.is_empty() {
+            let reasons: Vec<String> =
+                self.skipped_by_reason().iter().map(|(reason, count)| format!("{} {}", count, reason)).collect();
+            write!(f, ", {} skipped ({})", self.regions_skipped.len(), reasons.join(", "))?;
+        }
+        if self.bytes_unreadable > 0 {
+            write!(f, ", {} unreadable", format_size(self.bytes_unreadable))?;
+            if !self.holes.is_empty() {
+                write!(f, " ({} hole{})", self.holes.len(), plural(self.holes.len()))?;
+            }
+        }
+        if self.uniform_skipped > 0 {
+            write!(f, ", {} in uniform memory dropped", self.uniform_skipped)?;
+        }
+        write!(f, ", {:.2?}", self.duration)?;
+        if !f.alternate() {
+            return Ok(());
+        }
+
+        for address in &self.matches {
+            write!(f, "\n  0x{:X}", address)?;
+        }
+        // Stale regions get their own lines below, with what replaced them.
+        for (address, reason) in self.regions_skipped.iter().filter(|(_, reason)| *reason != SkipReason::Stale) {
+            write!(f, "\n  skipped 0x{:X}: {}", address, reason)?;
+        }
+        for stale in &self.stale {
+            match &stale.current {
+                Some(current) => write!(f, "\n  stale {}, now {}", stale.cached, current)?,
+                None => write!(f, "\n  stale {}, vanished", stale.cached)?,
+            }
+        }
+        for hole in &self.holes {
+            write!(f, "\n  hole 0x{:X}-0x{:X}", hole.start, hole.end)?;
+        }
+        if !self.chunk_sizes.is_empty() {
+            let sizes: Vec<String> =
+                self.chunk_sizes.iter().map(|(size, count)| format!("{} of {}", count, format_size(*size))).collect();
+            write!(f, "\n  chunks: {}", sizes.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// Keeps the numbers for a `ScanReport` while a bulk operation walks its regions. Each region
+// is either skipped up front or entered; an entered region counts as scanned once anything
+// in it was read, and as failed if reads were tried and none succeeded. A sink, if given,
+// hears of each region as it is settled.
+pub(crate) struct ScanTally {
+    started: Instant,
+    report: ScanReport,
+    // Address of the region entered last, until it is settled.
+    current: Option<usize>,
+    attempted: bool,
+    read_any: bool,
+    sink: Option<SharedSink>,
+}
+
+impl ScanTally {
+    pub(crate) fn new(regions_total: usize) -> Self {
+        ScanTally {
+            started: Instant::now(),
+            report: ScanReport { regions_total, ..ScanReport::default() },
+            current: None,
+            attempted: false,
+            read_any: false,
+            sink: None,
+        }
+    }
+
+    pub(crate) fn with_sink(mut self, sink: Option<SharedSink>) -> Self {
+        self.sink = sink;
+        self
+    }
+
+    pub(crate) fn enter(&mut self, region: &MemoryRegion) {
+        self.settle();
+        self.current = Some(region.start_address.get());
+    }
+
+    pub(crate) fn skip(&mut self, region: &MemoryRegion, reason: SkipReason) {
+        self.settle();
+        self.report.regions_skipped.push((region.start_address.get(), reason));
+        if let Some(sink) = &self.sink {
+            sink.on_region_done(region.start_address.get(), Some(reason));
+        }
+    }
+
+    pub(crate) fn stale(&mut self, stale: StaleRegion) {
+        self.skip(&stale.cached, SkipReason::Stale);
+        self.report.stale.push(stale);
+    }
+
+    // `bytes` of the current region were read and scanned.
+    pub(crate) fn read(&mut self, bytes: usize) {
+        self.attempted = true;
+        self.read_any |= bytes > 0;
+        self.report.bytes_scanned += bytes;
+    }
+
+    // `bytes` of the current region failed to read.
+    pub(crate) fn unreadable(&mut self, bytes: usize) {
+        self.attempted = true;
+        self.report.bytes_unreadable += bytes;
+    }
+
+    pub(crate) fn stale_regions(&self) -> &[StaleRegion] {
+        &self.report.stale
+    }
+
+    // The report so far, with the current region settled and the time taken until now.
+    pub(crate) fn report(&self, matches: Vec<usize>) -> ScanReport {
+        let mut report = self.report.clone();
+        if let Some(address) = self.current {
+            settle(&mut report, address, self.attempted, self.read_any);
+        }
+        report.matches = matches;
+        report.duration = self.started.elapsed();
+        report
+    }
+
+    // Settles the current region and returns the final report, which the sink gets too.
+    pub(crate) fn finish(&mut self, matches: Vec<usize>) -> ScanReport {
+        self.close();
+        let report = self.report(matches);
+        if let Some(sink) = &self.sink {
+            sink.on_complete(&report);
+        }
+        report
+    }
+
+    // Settles the current region, for a scan that has no more to walk.
+    pub(crate) fn close(&mut self) {
+        self.settle();
+    }
+
+    fn settle(&mut self) {
+        if let Some(address) = self.current.take() {
+            let skipped = settle(&mut self.report, address, self.attempted, self.read_any);
+            if let Some(sink) = &self.sink {
+                sink.on_region_done(address, skipped);
+            }
+        }
+        self.attempted = false;
+        self.read_any = false;
+    }
+}
+
+// Returns why the region counts as skipped, if it does.
+fn settle(report: &mut ScanReport, address: usize, attempted: bool, read_any: bool) -> Option<SkipReason> {
+    let skipped = match (attempted, read_any) {
+        (_, true) => None,
+        (true, false) => Some(SkipReason::ReadFailed),
+        // Nothing was left to read once our artifacts were taken out.
+        (false, false) => Some(SkipReason::Filtered),
+    };
+    match skipped {
+        Some(reason) => report.regions_skipped.push((address, reason)),
+        None => report.regions_scanned += 1,
+    }
+    skipped
+}
+
+// The regions a scan with these options walks: a fresh enumeration with `rescan_regions`,
+// the cached list with the filter's range enumerated afresh with `refresh_range`, otherwise
+// the cached list, or the one the metadata TTL refreshed in its place.
+pub(crate) fn scan_regions<'a>(
+    memory: &'a Memory,
+    options: &ScanOptions,
+    filter: &RegionFilter,
+) -> Cow<'a, [MemoryRegion]> {
+    match &filter.range {
+        _ if options.rescan_regions => Cow::Owned(memory.enumerate_regions()),
+        Some(range) if options.refresh_range && !range.is_empty() => {
+            let end = range.end;
+            let (fresh, span) = memory.walk_regions(range.start, |next| next < end);
+            Cow::Owned(memory.region_cache().spliced(&span, fresh))
+        }
+        _ => match memory.fresh_regions() {
+            Some(fresh) => Cow::Owned(fresh.to_vec()),
+            None => Cow::Borrowed(memory.cached_regions()),
+        },
+    }
+}
+
+// Checks a cached region against the target, for `validate_regions`, and fails with what is
+// there now if it changed.
+pub(crate) fn validate_region(memory: &Memory, region: &MemoryRegion) -> Result<(), StaleRegion> {
+    let current = memory.region_starting_at(region.start_address.get());
+    let unchanged = current.as_ref().is_some_and(|current| {
+        current.allocation_base == region.allocation_base
+            && current.size == region.size
+            && current.protection == region.protection
+    });
+    if unchanged {
+        return Ok(());
+    }
+    debug!("skipping stale region 0x{:X} (size 0x{:X}): now {:?}", region.start_address, region.size, current);
+    Err(StaleRegion { cached: region.clone(), current })
+}
+
+// Lazily scans the cached regions chunk by chunk: nothing is read until the consumer asks for
+// the next match, so `take(n)` stops reading as soon as it has enough. When a chunk fails to
+// read, or comes back short, its unreadable pages are found by bisection
+// (`Memory::unreadable_ranges`), recorded in `holes()`, and the rest of the chunk is scanned. A
+// chunk that can't be read at all also yields one `Err`; the scan then goes on with the rest of
+// the region. Once the `on_match` callback returns `Break`, the match it was called with is the
+// last one yielded.
+pub struct PatternMatches<'a> {
+    memory: &'a Memory,
+    pattern: &'a Pattern,
+    filter: &'a RegionFilter,
+    regions: Cow<'a, [MemoryRegion]>,
+    validate_regions: bool,
+    merge_allocations: bool,
+    // Allocation base of the region taken last.
+    allocation: Option<Address>,
+    // Regions walked so far, and what became of them.
+    tally: ScanTally,
+    // Unreadable ranges met so far, sorted and disjoint.
+    holes: Vec<Range<usize>>,
+    direction: ScanDirection,
+    // Number of regions taken so far, counted from the end the scan starts at.
+    regions_taken: usize,
+    // Part of the current region still to be read; empty once it has been consumed.
+    remaining: Range<usize>,
+    // Further pieces of the current region left between excluded ranges, the next one to scan
+    // last.
+    pieces: Vec<Range<usize>>,
+    // Sorted, disjoint ranges never to scan.
+    excluded: Vec<Range<usize>>,
+    // The current chunk plus the `pattern.len() - 1` bytes of the previously read neighbour
+    // chunk that a match straddling the seam could extend into (behind it when scanning
+    // forward, in front of it in reverse).
+    buffer: PooledBuffer<'a>,
+    buffer_address: usize,
+    // Forward: next start offset to test. Reverse: exclusive upper bound of offsets to test.
+    // Each chunk only tests offsets the previous one couldn't, so a match in the carried
+    // bytes is attributed to exactly one chunk.
+    position: usize,
+    // Last address yielded. Matches come out strictly ascending (descending in reverse), so
+    // anything not past it would be a repeat.
+    last_match: Option<usize>,
+    alignment: usize,
+    tuner: ChunkTuner,
+    // Chunks read so far, by size.
+    chunk_sizes: BTreeMap<usize, usize>,
+    resident_only: bool,
+    cancel: Option<CancelToken>,
+    on_match: Option<MatchCallback>,
+    sink: Option<SharedSink>,
+    // Set once `on_match` or the sink asked to stop.
+    stopped: bool,
+    // See `ScanOptions::skip_uniform_neighborhood` and `rank_results`, and interest.rs.
+    skip_uniform: bool,
+    rank: bool,
+    neighborhood: usize,
+    ranked: Vec<RankedMatch>,
+    uniform_skipped: usize,
+}
+
+impl<'a> PatternMatches<'a> {
+    pub(crate) fn new(
+        memory: &'a Memory,
+        pattern: &'a Pattern,
+        filter: &'a RegionFilter,
+        options: &ScanOptions,
+    ) -> Self {
+        let regions = scan_regions(memory, options, filter);
+        PatternMatches {
+            memory,
+            pattern,
+            filter,
+            tally: ScanTally::new(regions.len()).with_sink(options.sink.clone()),
+            regions,
+            validate_regions: options.validate_regions,
+            merge_allocations: options.merge_allocations,
+            allocation: None,
+            holes: Vec::new(),
+            direction: options.direction,
+            regions_taken: 0,
+            remaining: 0..0,
+            pieces: Vec::new(),
+            excluded: memory.excluded_ranges(!options.include_self_artifacts),
+            buffer: memory.buffer_pool().rent(options.chunk_len() + pattern.len() - 1),
+            buffer_address: 0,
+            position: 0,
+            last_match: None,
+            alignment: options.alignment.unwrap_or(1),
+            tuner: options.chunk_tuner(),
+            chunk_sizes: BTreeMap::new(),
+            resident_only: options.resident_only,
+            cancel: options.cancel.clone(),
+            on_match: options.on_match.clone(),
+            sink: options.sink.clone(),
+            stopped: false,
+            skip_uniform: options.skip_uniform_neighborhood.is_some(),
+            rank: options.rank_results,
+            neighborhood: options.skip_uniform_neighborhood.unwrap_or(NEIGHBORHOOD),
+            ranked: Vec::new(),
+            uniform_skipped: 0,
+        }
+    }
+
+    // Feeds each match to `visit` until it returns `Break` or the scan ends. Chunks that fail
+    // to read are skipped, as `find_pattern` does.
+    pub(crate) fn for_each_match(&mut self, mut visit: impl FnMut(usize) -> ControlFlow<()>) {
+        for found in self.by_ref() {
+            match found {
+                Ok(address) => {
+                    if visit(address).is_break() {
+                        return;
+                    }
+                }
+                Err(e) => debug!("{}", e),
+            }
+        }
+    }
+
+    // Regions skipped so far because they changed since they were cached.
+    pub fn stale_regions(&self) -> &[StaleRegion] {
+        self.tally.stale_regions()
+    }
+
+    // Unreadable ranges the scan has gone around so far.
+    pub fn holes(&self) -> &[Range<usize>] {
+        &self.holes
+    }
+
+    // What the scan has covered so far. `matches` is left empty: those came out of the
+    // iterator.
+    pub fn report(&self) -> ScanReport {
+        let mut report = self.tally.report(Vec::new());
+        report.holes = self.holes.clone();
+        report.ranked = interest::rank(self.ranked.clone());
+        report.uniform_skipped = self.uniform_skipped;
+        report.chunk_sizes = self.chunk_sizes.clone();
+        report
+    }
+
+    // The report of a scan run as far as it goes, with `matches`; the sink gets it too.
+    pub(crate) fn finish(&mut self, matches: Vec<usize>) -> ScanReport {
+        self.tally.close();
+        let report = ScanReport { matches, ..self.report() };
+        if let Some(sink) = &self.sink {
+            sink.on_complete(&report);
+        }
+        report
+    }
+
+    // The buffered bytes of the neighborhood of a match at `offset`, and where in them the
+    // match is. Near a chunk's edge part of the neighborhood may be missing.
+    fn neighborhood_of(&self, offset: usize) -> (&[u8], Range<usize>) {
+        let address = self.buffer_address + offset;
+        let around = interest::neighborhood(&(address..address + self.pattern.len()), self.neighborhood);
+        let start = around.start.max(self.buffer_address) - self.buffer_address;
+        let end = (around.end - self.buffer_address).min(self.buffer.len());
+        (&self.buffer[start..end], offset - start..offset - start + self.pattern.len())
+    }
+
+    // Nudges `position` past a misaligned candidate at `offset` to the next aligned one.
+    // Alignment is computed on absolute addresses, so region and chunk bases don't matter.
+    fn skip_misaligned(&mut self, offset: usize) {
+        let address = self.buffer_address + offset;
+        let misalignment = address % self.alignment;
+
+        self.position = match self.direction {
+            ScanDirection::Forward => offset + (self.alignment - misalignment),
+            ScanDirection::Reverse => {
+                let aligned = address - misalignment;
+                if aligned < self.buffer_address {
+                    0
+                } else {
+                    aligned - self.buffer_address + 1
+                }
+            }
+        };
+    }
+
+    // Moves on to the next region the filter accepts; false once there are none left.
+    fn next_region(&mut self) -> bool {
+        while self.remaining.is_empty() {
+            if let Some(piece) = self.pieces.pop() {
+                // Pieces of one region are never adjacent, so nothing carries over between
+                // them. Only the first piece of a region can continue the previous region of
+                // its allocation, when merging allocations kept the buffer.
+                if !self.continues_buffer(&piece) {
+                    self.buffer.clear();
+                }
+                self.remaining = piece;
+                continue;
+            }
+            let count = self.regions.len();
+            if self.regions_taken == count {
+                return false;
+            }
+            let region = match self.direction {
+                ScanDirection::Forward => self.regions[self.regions_taken].clone(),
+                ScanDirection::Reverse => self.regions[count - 1 - self.regions_taken].clone(),
+            };
+            self.regions_taken += 1;
+            if !(self.merge_allocations && self.allocation == Some(region.allocation_base)) {
+                self.buffer.clear();
+            }
+            self.allocation = Some(region.allocation_base);
+
+            let clipped = self.filter.clip(&region);
+            if clipped.is_some() && self.validate_regions {
+                if let Err(stale) = validate_region(self.memory, &region) {
+                    self.tally.stale(stale);
+                    continue;
+                }
+            }
+            match clipped {
+                Some(range) => {
+                    self.tally.enter(&region);
+                    self.pieces = artifacts::subtract(range, &self.excluded);
+                    if self.direction == ScanDirection::Forward {
+                        self.pieces.reverse();
+                    }
+                }
+                None => {
+                    self.tally.skip(&region, self.filter.skip_reason(&region));
+                    debug!(
+                        "skipping region 0x{:X} (size 0x{:X}): excluded by filter (protection 0x{:X})",
+                        region.start_address, region.size, region.protection
+                    )
+                }
+            }
+        }
+
+        true
+    }
+
+    // Whether `piece` starts right where the buffered bytes end (or, in reverse, ends right
+    // where they start), so a match may run from one into the other.
+    fn continues_buffer(&self, piece: &Range<usize>) -> bool {
+        !self.buffer.is_empty()
+            && match self.direction {
+                ScanDirection::Forward => self.buffer_address + self.buffer.len() == piece.start,
+                ScanDirection::Reverse => self.buffer_address == piece.end,
+            }
+    }
+
+    // Shrinks the next chunk of `len` bytes to a run of resident pages, moving the scan past
+    // paged-out ones. Returns 0 when the whole window was skipped.
+    fn clip_to_resident(&mut self, len: usize) -> usize {
+        let window = match self.direction {
+            ScanDirection::Forward => self.remaining.start..self.remaining.start + len,
+            ScanDirection::Reverse => self.remaining.end - len..self.remaining.end,
+        };
+        let pages = match self.memory.residency_range(window.start, window.len()) {
+            Ok(pages) => pages,
+            Err(e) => {
+                debug!("residency unknown at 0x{:X}, scanning anyway: {}", window.start, e);
+                return len;
+            }
+        };
+        let page_range = |address: usize| address.max(window.start)..(address + PAGE_SIZE).min(window.end);
+
+        match self.direction {
+            ScanDirection::Forward => {
+                let Some(first) = pages.iter().position(|page| page.valid) else {
+                    self.remaining.start = window.end;
+                    self.buffer.clear();
+                    return 0;
+                };
+                let start = page_range(pages[first].address).start;
+                let end = pages[first..]
+                    .iter()
+                    .find(|page| !page.valid)
+                    .map_or(window.end, |page| page_range(page.address).start);
+                if start != self.remaining.start {
+                    // Bytes carried from before the skipped pages are no longer adjacent.
+                    self.buffer.clear();
+                    self.remaining.start = start;
+                }
+                end - start
+            }
+            ScanDirection::Reverse => {
+                let Some(last) = pages.iter().rposition(|page| page.valid) else {
+                    self.remaining.end = window.start;
+                    self.buffer.clear();
+                    return 0;
+                };
+                let end = page_range(pages[last].address).end;
+                let start = pages[..last]
+                    .iter()
+                    .rfind(|page| !page.valid)
+                    .map_or(window.start, |page| page_range(page.address).end);
+                if end != self.remaining.end {
+                    self.buffer.clear();
+                    self.remaining.end = end;
+                }
+                end - start
+            }
+        }
+    }
+
+    // Advances to the next chunk; Ok(false) once every region has been scanned.
+    fn refill(&mut self) -> io::Result<bool> {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) || self.memory.is_closed() {
+            debug!("scan cancelled before 0x{:X}", self.remaining.start);
+            return Ok(false);
+        }
+
+        let mut len = 0;
+        while len == 0 {
+            if !self.next_region() {
+                return Ok(false);
+            }
+            len = self.tuner.size().min(self.remaining.len());
+            if self.resident_only {
+                len = self.clip_to_resident(len);
+            }
+        }
+
+        let overlap = (self.pattern.len() - 1).min(self.buffer.len());
+        let _span = span!("scan_chunk", address = self.remaining.start, size = len; outcome);
+        let started = Instant::now();
+        let result = match self.direction {
+            ScanDirection::Forward => self.read_forward(overlap, len),
+            ScanDirection::Reverse => self.read_reverse(overlap, len),
+        };
+        record!(_span, outcome = crate::spans::error_outcome(result.as_ref().err().map(|(_, e)| e.kind())));
+        self.tuner.observe(len, result.as_ref().ok().copied(), started.elapsed());
+        *self.chunk_sizes.entry(len).or_default() += 1;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err((address, e)) => self.map_failed_chunk(address..address + len, e),
+        }
+    }
+
+    // Records the holes in a chunk that failed to read and queues its readable parts ahead of
+    // the rest of the region. Fails only when nothing in the chunk could be read.
+    fn map_failed_chunk(&mut self, chunk: Range<usize>, error: io::Error) -> io::Result<bool> {
+        self.buffer.clear();
+        // Reverse reads have already moved past the chunk; forward reads have not.
+        if self.direction == ScanDirection::Forward {
+            self.remaining.start = chunk.end;
+        }
+        if !self.map_holes(chunk.clone()) {
+            return Err(io::Error::new(
+                error.kind(),
+                format!("Failed to read chunk at 0x{:X}: {}", chunk.start, error),
+            ));
+        }
+        Ok(true)
+    }
+
+    // Finds the unreadable pages of `range`, part of a chunk that didn't read, by bisection,
+    // records them as holes and queues the readable parts in between ahead of the rest of the
+    // region. Returns whether there were any.
+    fn map_holes(&mut self, range: Range<usize>) -> bool {
+        let mut holes = self.memory.unreadable_ranges(range.clone());
+        if holes.is_empty() {
+            // It read fine the second time round. Don't queue it again, or a chunk that keeps
+            // failing intermittently would be retried forever.
+            holes.push(range.clone());
+        }
+        let mut readable = artifacts::subtract(range.clone(), &holes);
+        debug!("chunk at 0x{:X} has {} unreadable ranges", range.start, holes.len());
+        self.tally.unreadable(holes.iter().map(|hole| hole.len()).sum());
+        for hole in holes {
+            self.record_hole(hole);
+        }
+
+        if self.remaining.start < self.remaining.end {
+            self.pieces.push(mem::replace(&mut self.remaining, 0..0));
+        }
+        if self.direction == ScanDirection::Forward {
+            readable.reverse();
+        }
+        let any = !readable.is_empty();
+        self.pieces.extend(readable);
+        any
+    }
+
+    fn record_hole(&mut self, hole: Range<usize>) {
+        let index = self.holes.partition_point(|known| known.end < hole.start);
+        match self.holes.get_mut(index) {
+            Some(known) if known.start <= hole.end => {
+                known.start = known.start.min(hole.start);
+                known.end = known.end.max(hole.end);
+            }
+            _ => self.holes.insert(index, hole),
+        }
+    }
+
+    // Both return how many bytes were read.
+    fn read_forward(&mut self, overlap: usize, len: usize) -> Result<usize, (usize, io::Error)> {
+        // None of the carried tail offsets was tested: a full match never fit after them.
+        let tail = self.buffer.len() - overlap;
+        self.buffer.copy_within(tail.., 0);
+        self.buffer.truncate(overlap);
+
+        let address = self.remaining.start;
+        self.buffer_address = address - overlap;
+        self.position = 0;
+        self.buffer.resize(overlap + len, 0);
+
+        let read = self.memory.read_bulk(address, &mut self.buffer[overlap..]).map_err(|e| (address, e))?;
+        self.tally.read(read);
+        self.buffer.truncate(overlap + read);
+        self.remaining.start = address + len;
+        // What was read is searched as it is; the rest of the chunk is mapped like a chunk
+        // that failed, and its readable parts are read after it.
+        if read < len {
+            self.map_holes(address + read..address + len);
+        }
+        Ok(read)
+    }
+
+    fn read_reverse(&mut self, overlap: usize, len: usize) -> Result<usize, (usize, io::Error)> {
+        // The carried head offsets were all tested with the previous chunk; only offsets that
+        // start inside the new chunk are searched.
+        self.buffer.truncate(overlap);
+        self.buffer.resize(len + overlap, 0);
+        self.buffer.copy_within(..overlap, len);
+
+        let address = self.remaining.end - len;
+        self.buffer_address = address;
+        self.remaining.end = address;
+
+        let read = self.memory.read_bulk(address, &mut self.buffer[..len]).map_err(|e| (address, e))?;
+        self.tally.read(read);
+        if read < len {
+            self.tally.unreadable(len - read);
+            // The unread gap separates the chunk from the carried head, so drop the head.
+            self.buffer.truncate(read);
+        }
+        self.position = read;
+        Ok(read)
+    }
+}
+
+impl Iterator for PatternMatches<'_> {
+    type Item = io::Result<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.stopped {
+            return None;
+        }
+        loop {
+            let found = {
+                let _span = span!("match_chunk", address = self.buffer_address, size = self.buffer.len(); found);
+                let matches = pattern::search_iter(&self.buffer, self.pattern);
+                let found = match self.direction {
+                    ScanDirection::Forward => matches.starting_at(self.position).next(),
+                    ScanDirection::Reverse => matches.ending_before(self.position).next_back(),
+                };
+                record!(_span, found = found.is_some());
+                found
+            };
+
+            if let Some(offset) = found {
+                if (self.buffer_address + offset) % self.alignment != 0 {
+                    self.skip_misaligned(offset);
+                    continue;
+                }
+                self.position = match self.direction {
+                    ScanDirection::Forward => offset + 1,
+                    ScanDirection::Reverse => offset,
+                };
+                let address = self.buffer_address + offset;
+                let repeated = self.last_match.is_some_and(|last| match self.direction {
+                    ScanDirection::Forward => address <= last,
+                    ScanDirection::Reverse => address >= last,
+                });
+                if repeated {
+                    trace!("dropping repeated match at 0x{:X}", address);
+                    continue;
+                }
+                if self.skip_uniform || self.rank {
+                    let (window, hit) = self.neighborhood_of(offset);
+                    if self.skip_uniform && interest::is_uniform_around(window, &hit) {
+                        trace!("dropping match at 0x{:X} in uniform memory", address);
+                        self.uniform_skipped += 1;
+                        continue;
+                    }
+                    if self.rank {
+                        let score = interest::score(window, &hit);
+                        self.ranked.push(RankedMatch { address, score });
+                    }
+                }
+                self.last_match = Some(address);
+                self.stopped = report_match(self.on_match.as_ref(), self.sink.as_ref(), address).is_break();
+                return Some(Ok(address));
+            }
+
+            match self.refill() {
+                Ok(true) => continue,
+                Ok(false) => {
+                    self.tally.close();
+                    return None;
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{Call, MockBackend};
+    use crate::strings::Encoding;
+    use crate::sys::{PAGE_EXECUTE_READ, PAGE_GUARD, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE};
+
+    // Three regions of a page each, with the pattern near the start of every one.
+    fn three_regions() -> MockBackend {
+        let mut backend = MockBackend::new();
+        for base in [0x10000, 0x20000, 0x30000] {
+            let mut bytes = vec![0u8; 0x1000];
+            bytes[0x10..0x14].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+            backend = backend.data(base, bytes);
+        }
+        backend
+    }
+
+    #[test]
+    fn take_stops_before_later_regions() {
+        let (mock, mut memory) = three_regions().open();
+        memory.scan_memory().unwrap();
+        mock.clear_calls();
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let filter = RegionFilter::new();
+        let found: Vec<usize> = memory.pattern_matches(&pattern, &filter).take(1).map(Result::unwrap).collect();
+
+        assert_eq!(found, [0x10010]);
+        assert!(!mock.reads().is_empty());
+        assert!(mock.reads().iter().all(|read| read.end <= 0x11000), "{:X?}", mock.reads());
+    }
+
+    #[test]
+    fn find_pattern_all_reads_every_region() {
+        let (mock, mut memory) = three_regions().open();
+        memory.scan_memory().unwrap();
+        mock.clear_calls();
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        assert_eq!(memory.find_pattern_all(&pattern, &RegionFilter::new()).unwrap(), [0x10010, 0x20010, 0x30010]);
+        assert!(mock.reads().iter().any(|read| read.start >= 0x30000));
+    }
+
+    // Four pages with the pattern inside the first, across the seam between the second and
+    // third, and in the last bytes of the fourth.
+    fn seamed() -> MockBackend {
+        let mut bytes = vec![0u8; 4 * PAGE_SIZE];
+        for offset in [0x10, 0x1FFE, 0x3FFC] {
+            bytes[offset..offset + 4].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        }
+        MockBackend::new().data(0x10000, bytes)
+    }
+
+    #[test]
+    fn reverse_scans_find_the_highest_match_first() {
+        let (_, mut memory) = seamed().open();
+        memory.scan_memory().unwrap();
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let filter = RegionFilter::new();
+        let reverse = ScanOptions::default().direction(ScanDirection::Reverse).chunk_size(PAGE_SIZE);
+        let found: Vec<usize> = memory.pattern_matches_with(&pattern, &filter, &reverse).map(Result::unwrap).collect();
+        assert_eq!(found, [0x13FFC, 0x11FFE, 0x10010]);
+        assert_eq!(memory.find_pattern_with(&pattern, &filter, &reverse).unwrap(), Some(0x13FFC));
+        assert_eq!(memory.find_pattern_limit_with(&pattern, &filter, &reverse, 2).unwrap(), [0x11FFE, 0x13FFC]);
+        assert_eq!(memory.find_pattern_all_with(&pattern, &filter, &reverse).unwrap(), [0x10010, 0x11FFE, 0x13FFC]);
+    }
+
+    #[test]
+    fn nearest_matches_are_found_on_either_side() {
+        let (_, mut memory) = seamed().open();
+        memory.scan_memory().unwrap();
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        assert_eq!(memory.find_pattern_before(0x13FFD, &pattern).unwrap(), Some(0x13FFC));
+        assert_eq!(memory.find_pattern_before(0x13FFC, &pattern).unwrap(), Some(0x11FFE));
+        assert_eq!(memory.find_pattern_before(0x11FFE, &pattern).unwrap(), Some(0x10010));
+        assert_eq!(memory.find_pattern_before(0x10010, &pattern).unwrap(), None);
+        assert_eq!(memory.find_pattern_after(0x10010, &pattern).unwrap(), Some(0x11FFE));
+        assert_eq!(memory.find_pattern_after(0x11FFE, &pattern).unwrap(), Some(0x13FFC));
+        assert_eq!(memory.find_pattern_after(0x13FFC, &pattern).unwrap(), None);
+    }
+
+    // What scanning three pages in one-page chunks, each way, makes of `bytes`.
+    fn scan_both_ways(bytes: Vec<u8>, pattern: &Pattern, alignment: usize) -> (Vec<usize>, Vec<usize>) {
+        let (_, mut memory) = MockBackend::new().data(0x10000, bytes).open();
+        memory.scan_memory().unwrap();
+        let filter = RegionFilter::new();
+        let forward = ScanOptions::default().chunk_size(PAGE_SIZE).alignment(alignment);
+        let reverse = forward.clone().direction(ScanDirection::Reverse);
+        let scan = |options| memory.pattern_matches_with(pattern, &filter, options).map(Result::unwrap).collect();
+        (scan(&forward), scan(&reverse))
+    }
+
+    #[test]
+    fn matches_around_chunk_seams_are_reported_once() {
+        // Runs of one byte make overlapping matches, so a seam inside a run is crossed by
+        // several matches at once.
+        for len in 1..=4 {
+            let pattern = Pattern::exact(&vec![0xAA; len]).unwrap();
+            for run in len..len + 4 {
+                for seam in [PAGE_SIZE, 2 * PAGE_SIZE] {
+                    for start in seam - run - 1..=seam + 1 {
+                        let mut bytes = vec![0u8; 3 * PAGE_SIZE];
+                        bytes[start..start + run].fill(0xAA);
+                        let expected: Vec<usize> = (start..=start + run - len).map(|offset| 0x10000 + offset).collect();
+
+                        let (forward, mut reverse) = scan_both_ways(bytes, &pattern, 1);
+                        assert_eq!(forward, expected, "{} of {} at 0x{:X}", len, run, start);
+                        reverse.reverse();
+                        assert_eq!(reverse, expected, "{} of {} at 0x{:X} in reverse", len, run, start);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn alignment_is_by_address_across_chunk_seams() {
+        let pattern = Pattern::parse("AA AA").unwrap();
+        for alignment in [2, 4, 8] {
+            for seam in [PAGE_SIZE, 2 * PAGE_SIZE] {
+                for start in seam - 9..=seam + 1 {
+                    let mut bytes = vec![0u8; 3 * PAGE_SIZE];
+                    bytes[start..start + 9].fill(0xAA);
+                    let expected: Vec<usize> =
+                        (start..=start + 7).map(|offset| 0x10000 + offset).filter(|a| a % alignment == 0).collect();
+
+                    let (forward, mut reverse) = scan_both_ways(bytes, &pattern, alignment);
+                    assert_eq!(forward, expected, "aligned to {} at 0x{:X}", alignment, start);
+                    reverse.reverse();
+                    assert_eq!(reverse, expected, "aligned to {} at 0x{:X} in reverse", alignment, start);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn value_scans_default_to_the_alignment_of_the_type() {
+        let mut bytes = vec![0u8; 3 * PAGE_SIZE];
+        for offset in [0x100, 0x203, 0xFFE, 0x1FFC] {
+            bytes[offset..offset + 4].copy_from_slice(&0x1122_3344u32.to_ne_bytes());
+        }
+        let (_, mut memory) = MockBackend::new().data(0x10000, bytes).open();
+        memory.scan_memory().unwrap();
+
+        let filter = RegionFilter::new();
+        let options = ScanOptions::default().chunk_size(PAGE_SIZE);
+        let scan = |options: &ScanOptions| memory.scan_for_value_with(0x1122_3344u32, &filter, options).unwrap();
+        assert_eq!(scan(&options), [0x10100, 0x11FFC]);
+        assert_eq!(scan(&options.clone().alignment(2)), [0x10100, 0x10FFE, 0x11FFC]);
+        assert_eq!(scan(&options.clone().alignment(1)), [0x10100, 0x10203, 0x10FFE, 0x11FFC]);
+    }
+
+    // `pages` pages with the pattern at the start of each.
+    fn patterned(pages: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; pages * PAGE_SIZE];
+        for page in 0..pages {
+            bytes[page * PAGE_SIZE..page * PAGE_SIZE + 4].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn failing_pages_become_holes_and_the_rest_is_scanned() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, patterned(4)).open();
+        memory.scan_memory().unwrap();
+        mock.fail_reads(0x11000..0x12000);
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let report = memory.find_pattern_report(&pattern, &RegionFilter::new(), &ScanOptions::default()).unwrap();
+        assert_eq!(report.matches, [0x10000, 0x12000, 0x13000]);
+        assert_eq!(report.holes, [0x11000..0x12000]);
+        assert_eq!(report.bytes_unreadable, PAGE_SIZE);
+        assert_eq!(report.regions_scanned, 1);
+    }
+
+    #[test]
+    fn holes_are_found_in_every_chunk() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, patterned(8)).open();
+        memory.scan_memory().unwrap();
+        mock.fail_reads(0x11000..0x12000);
+        mock.fail_reads(0x16000..0x17000);
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let options = ScanOptions::default().chunk_size(4 * PAGE_SIZE);
+        let report = memory.find_pattern_report(&pattern, &RegionFilter::new(), &options).unwrap();
+        assert_eq!(report.matches, [0x10000, 0x12000, 0x13000, 0x14000, 0x15000, 0x17000]);
+        assert_eq!(report.holes, [0x11000..0x12000, 0x16000..0x17000]);
+    }
+
+    #[test]
+    fn the_rest_of_a_short_read_is_mapped_for_holes() {
+        // The cached region still spans four pages when the target has since split it: a read
+        // of the whole stops at the end of the first two, and only the third is unreadable.
+        let (mock, mut memory) = MockBackend::new().data(0x10000, patterned(4)).open();
+        memory.scan_memory().unwrap();
+        let bytes = patterned(4);
+        mock.map(0x10000, bytes[..2 * PAGE_SIZE].to_vec(), PAGE_READWRITE);
+        mock.map(0x12000, bytes[2 * PAGE_SIZE..3 * PAGE_SIZE].to_vec(), PAGE_NOACCESS);
+        mock.map(0x13000, bytes[3 * PAGE_SIZE..].to_vec(), PAGE_READWRITE);
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let report = memory.find_pattern_report(&pattern, &RegionFilter::new(), &ScanOptions::default()).unwrap();
+        assert_eq!(report.matches, [0x10000, 0x11000, 0x13000]);
+        assert_eq!(report.holes, [0x12000..0x13000]);
+        assert_eq!(report.bytes_unreadable, PAGE_SIZE);
+        assert_eq!(report.bytes_scanned, 3 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn an_unreadable_chunk_yields_an_error_and_the_scan_goes_on() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, patterned(8)).open();
+        memory.scan_memory().unwrap();
+        mock.fail_reads(0x10000..0x14000);
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let options = ScanOptions::default().chunk_size(4 * PAGE_SIZE);
+        let results: Vec<io::Result<usize>> =
+            memory.pattern_matches_with(&pattern, &RegionFilter::new(), &options).collect();
+        assert!(results[0].is_err());
+        let found: Vec<usize> = results[1..].iter().map(|found| *found.as_ref().unwrap()).collect();
+        assert_eq!(found, [0x14000, 0x15000, 0x16000, 0x17000]);
+    }
+
+    #[test]
+    fn validation_skips_regions_that_changed_since_caching() {
+        let (mock, mut memory) = three_regions().open();
+        memory.scan_memory().unwrap();
+        // Freed and something else allocated in its place, and freed outright.
+        mock.map(0x20000, patterned(2), PAGE_READWRITE);
+        mock.unmap(0x30000);
+        mock.clear_calls();
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let options = ScanOptions::default().validate_regions(true);
+        let report = memory.find_pattern_report(&pattern, &RegionFilter::new(), &options).unwrap();
+        assert_eq!(report.matches, [0x10010]);
+        let stale: Vec<(usize, bool)> =
+            report.stale.iter().map(|stale| (stale.cached.start_address.get(), stale.vanished())).collect();
+        assert_eq!(stale, [(0x20000, false), (0x30000, true)]);
+        assert!(mock.reads().iter().all(|read| read.end <= 0x11000), "{:X?}", mock.reads());
+    }
+
+    #[test]
+    fn validation_catches_a_swap_made_mid_scan() {
+        let (mock, mut memory) = three_regions().open();
+        memory.scan_memory().unwrap();
+        let swapper = Arc::downgrade(&mock);
+        mock.on_call(move |call| {
+            if matches!(call, Call::Read(read) if read.start == 0x10000) {
+                if let Some(mock) = swapper.upgrade() {
+                    mock.map(0x20000, vec![0; 0x1000], PAGE_READONLY);
+                }
+            }
+        });
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let options = ScanOptions::default().validate_regions(true);
+        let report = memory.find_pattern_report(&pattern, &RegionFilter::new(), &options).unwrap();
+        assert_eq!(report.matches, [0x10010, 0x30010]);
+        assert_eq!(report.stale.len(), 1);
+        assert_eq!(report.stale[0].current.as_ref().map(|region| region.protection), Some(PAGE_READONLY));
+    }
+
+    #[test]
+    fn without_validation_a_stale_region_is_read_as_it_is_now() {
+        let (mock, mut memory) = three_regions().open();
+        memory.scan_memory().unwrap();
+        mock.map(0x20000, vec![0; 0x1000], PAGE_READWRITE);
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let report = memory.find_pattern_report(&pattern, &RegionFilter::new(), &ScanOptions::default()).unwrap();
+        assert_eq!(report.matches, [0x10010, 0x30010]);
+        assert!(report.stale.is_empty());
+    }
+
+    #[test]
+    fn rescanning_sees_regions_allocated_since_caching() {
+        let (mock, mut memory) = three_regions().open();
+        memory.scan_memory().unwrap();
+        mock.map(0x40000, patterned(1), PAGE_READWRITE);
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let filter = RegionFilter::new();
+        assert_eq!(memory.find_pattern_all(&pattern, &filter).unwrap().len(), 3);
+        let options = ScanOptions::default().rescan_regions(true);
+        let report = memory.find_pattern_report(&pattern, &filter, &options).unwrap();
+        assert_eq!(report.matches, [0x10010, 0x20010, 0x30010, 0x40000]);
+        assert_eq!(report.regions_total, 4);
+    }
+
+    // One region of each fate: scanned, filtered out, too large, a guard region and one that
+    // fails to read.
+    fn assorted() -> (Arc<MockBackend>, Memory, RegionFilter) {
+        let (mock, mut memory) = MockBackend::new()
+            .data(0x10000, patterned(1))
+            .region(0x20000, patterned(1), PAGE_EXECUTE_READ)
+            .data(0x30000, patterned(16))
+            .region(0x50000, patterned(1), PAGE_READWRITE | PAGE_GUARD)
+            .data(0x60000, patterned(1))
+            .open();
+        memory.scan_memory().unwrap();
+        mock.fail_reads(0x60000..0x61000);
+        (mock, memory, RegionFilter::new().writable(true).max_region_size(0x8000))
+    }
+
+    fn assert_assorted(report: &ScanReport) {
+        assert_eq!(report.regions_total, 5);
+        assert_eq!(report.regions_scanned, 1);
+        assert_eq!(
+            report.regions_skipped,
+            [
+                (0x20000, SkipReason::Filtered),
+                (0x30000, SkipReason::TooLarge),
+                (0x50000, SkipReason::Guard),
+                (0x60000, SkipReason::ReadFailed),
+            ]
+        );
+        assert_eq!(report.bytes_scanned, PAGE_SIZE);
+        assert_eq!(report.bytes_unreadable, PAGE_SIZE);
+    }
+
+    #[test]
+    fn pattern_scan_reports_what_became_of_each_region() {
+        let (_mock, memory, filter) = assorted();
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+
+        let report = memory.find_pattern_report(&pattern, &filter, &ScanOptions::default()).unwrap();
+        assert_eq!(report.matches, [0x10000]);
+        assert_eq!(report.holes, [0x60000..0x61000]);
+        assert_assorted(&report);
+        let by_reason: Vec<(SkipReason, usize)> = report.skipped_by_reason().into_iter().collect();
+        assert_eq!(by_reason.len(), 4);
+        assert!(by_reason.iter().all(|(_, count)| *count == 1));
+    }
+
+    #[test]
+    fn value_and_predicate_scans_report_alike() {
+        let (_mock, memory, filter) = assorted();
+
+        let report = memory.scan_for_value_report(0xEFBE_ADDEu32, &filter, &ScanOptions::default()).unwrap();
+        assert_eq!(report.matches, [0x10000]);
+        assert_assorted(&report);
+
+        let report =
+            memory.scan_with_report(|window, _| window == [0xDE, 0xAD], 2, &filter, &ScanOptions::default()).unwrap();
+        assert_eq!(report.matches, [0x10000]);
+        assert_assorted(&report);
+    }
+
+    #[test]
+    fn string_scans_report_alike() {
+        let (mock, memory, filter) = assorted();
+        mock.map(0x10000, b"nirvana\0".iter().copied().chain(vec![0; PAGE_SIZE - 8]).collect(), PAGE_READWRITE);
+
+        let mut strings = memory.extract_strings(4, &[Encoding::Ascii], &filter);
+        let found: Vec<String> = strings.by_ref().map(|found| found.text).collect();
+        assert_eq!(found, ["nirvana"]);
+        assert_assorted(&strings.report());
+    }
+
+    #[test]
+    fn reports_read_as_a_summary() {
+        let (_mock, memory, filter) = assorted();
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let report = ScanReport {
+            duration: Duration::ZERO,
+            chunk_sizes: BTreeMap::new(),
+            ..memory.find_pattern_report(&pattern, &filter, &ScanOptions::default()).unwrap()
+        };
+
+        assert_eq!(
+            report.to_string(),
+            "1 match in 1 of 5 regions, 4.0 KiB read, 4 skipped (1 filtered, 1 too large, 1 guard, 1 read failed), \
+             4.0 KiB unreadable (1 hole), 0.00ns"
+        );
+        let detail = format!("{:#}", report);
+        assert!(detail.contains("\n  0x10000"));
+        assert!(detail.contains("\n  skipped 0x50000: guard"));
+        assert!(detail.contains("\n  hole 0x60000-0x61000"));
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(serde_json::from_str::<ScanReport>(&json).unwrap(), report);
+    }
+
+    #[test]
+    fn a_scan_cancelled_mid_region_reads_no_further() {
+        let (mock, mut memory) = MockBackend::new().data(0x10000, patterned(4)).data(0x20000, patterned(1)).open();
+        memory.scan_memory().unwrap();
+        let token = CancelToken::new();
+        let hook_token = token.clone();
+        mock.on_call(move |call| {
+            if matches!(call, Call::Read(range) if range.start == 0x12000) {
+                hook_token.cancel();
+            }
+        });
+
+        let pattern = Pattern::parse("DE AD BE EF").unwrap();
+        let options = ScanOptions::default().chunk_size(PAGE_SIZE).cancel_token(token);
+        let found = memory.find_pattern_all_with(&pattern, &RegionFilter::default(), &options).unwrap();
+        // The chunk being read when the token was cancelled is the last one searched.
+        assert_eq!(found, [0x10000, 0x11000, 0x12000]);
+        assert_eq!(mock.reads().last().map(|read| read.start), Some(0x12000));
+    }
+}
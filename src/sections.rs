@@ -0,0 +1,98 @@
+// Which section of which module an address falls in. The section tables are read from the
+// module headers in the target by `scan_modules` and cached with the module list.
+
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+use log::debug;
+
+use crate::address::Address;
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::pe::{PeHeaders, SCN_MEM_EXECUTE, SCN_MEM_WRITE};
+use crate::wstr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionRef {
+    pub module: String,
+    // As in the section table, e.g. ".text"; at most 8 characters.
+    pub name: String,
+    // Absolute addresses, from the section's RVA over its virtual size (the raw size if that is
+    // zero) rounded up to pages.
+    pub range: Range<usize>,
+    // IMAGE_SCN_* flags.
+    pub characteristics: u32,
+}
+
+impl SectionRef {
+    pub fn contains(&self, address: impl Into<Address>) -> bool {
+        self.range.contains(&address.into().get())
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.characteristics & SCN_MEM_WRITE != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.characteristics & SCN_MEM_EXECUTE != 0
+    }
+}
+
+// "game.exe!.rdata 0x7FF6A0120000-0x7FF6A0140000"
+impl fmt::Display for SectionRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}!{} 0x{:X}-0x{:X}", self.module, self.name, self.range.start, self.range.end)
+    }
+}
+
+// The sections of `module`, read from its headers in the target.
+pub(crate) fn read_sections(memory: &Memory, module: &ModuleInfo) -> io::Result<Vec<SectionRef>> {
+    let headers = memory.read_bytes(module.base_address, 0x1000)?;
+    let headers = PeHeaders::parse(&headers)?;
+    Ok(headers
+        .sections
+        .iter()
+        .map(|section| {
+            let start = module.base_address + section.virtual_address as usize;
+            let size = if section.virtual_size == 0 { section.raw_size } else { section.virtual_size };
+            let size = (size as usize).next_multiple_of(0x1000);
+            SectionRef {
+                module: module.name.clone(),
+                name: section.name.clone(),
+                range: start..start.saturating_add(size).min(module.base_address + module.size),
+                characteristics: section.characteristics,
+            }
+        })
+        .collect())
+}
+
+// Section tables of all `modules`. Modules whose headers can't be read or parsed contribute
+// none and symbolize without a section.
+pub(crate) fn read_all_sections(memory: &Memory, modules: &[ModuleInfo]) -> Vec<SectionRef> {
+    modules
+        .iter()
+        .flat_map(|module| {
+            read_sections(memory, module).unwrap_or_else(|e| {
+                debug!("no section table for {}: {}", module.name, e);
+                Vec::new()
+            })
+        })
+        .collect()
+}
+
+impl Memory {
+    // The cached section containing `address`. Needs `scan_modules`.
+    pub fn section_at(&self, address: impl Into<Address>) -> Option<SectionRef> {
+        let address = address.into();
+        self.cached_sections().iter().find(|section| section.contains(address)).cloned()
+    }
+
+    // The cached section `name` of `module`, both compared case-insensitively.
+    pub fn section(&self, module: &str, name: &str) -> Option<SectionRef> {
+        self.cached_sections()
+            .iter()
+            .find(|section| wstr::eq_ignore_case(&section.module, module) && wstr::eq_ignore_case(&section.name, name))
+            .cloned()
+    }
+}
@@ -0,0 +1,178 @@
+// JSON-lines protocol for driving a `Memory` from another process: every request is one JSON
+// object on its own line and is answered by exactly one response line, in order, so clients
+// may pipeline requests. Each connection gets its own thread; all share the same `Memory`.
+//
+//   -> {"type":"Read","address":4096,"len":4}
+//   <- {"type":"Bytes","bytes":[0,0,0,0]}
+
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Weak};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::filter::RegionFilter;
+use crate::memory::{Memory, MemoryRegion};
+use crate::module::ModuleInfo;
+use crate::pattern::Pattern;
+use crate::process_handle::{spawn_worker, upgrade};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Request {
+    // The regions cached by the server's last `scan_memory`.
+    Regions,
+    Read { address: usize, len: usize },
+    Write { address: usize, bytes: Vec<u8> },
+    // Every match of a `Pattern::parse` pattern in the cached regions.
+    Scan { pattern: String },
+    Modules,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Response {
+    Regions { regions: Vec<MemoryRegion> },
+    Bytes { bytes: Vec<u8> },
+    Written { len: usize },
+    Matches { addresses: Vec<usize> },
+    Modules { modules: Vec<ModuleInfo> },
+    // `kind` is the name of the `io::ErrorKind`, e.g. "PermissionDenied".
+    Error { kind: String, message: String },
+}
+
+// Accepts clients until the listener fails, serving each on its own thread. Once `memory` is
+// closed, clients are disconnected at their next request and the next one to connect ends
+// the loop.
+pub fn serve(memory: Arc<Memory>, listener: TcpListener) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        if memory.is_closed() {
+            break;
+        }
+        spawn_worker(&memory, move |memory| {
+            let peer = stream.peer_addr().map(|addr| addr.to_string()).unwrap_or_default();
+            debug!("client {} connected", peer);
+            if let Err(e) = serve_client(memory, stream) {
+                warn!("client {} failed: {}", peer, e);
+            }
+            debug!("client {} disconnected", peer);
+        });
+    }
+    Ok(())
+}
+
+fn serve_client(memory: &Weak<Memory>, stream: TcpStream) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Some(memory) = upgrade(memory) else {
+            break;
+        };
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => handle(&memory, request),
+            Err(e) => error_response(&io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+fn handle(memory: &Memory, request: Request) -> Response {
+    let result = match request {
+        Request::Regions => Ok(Response::Regions { regions: memory.regions().cloned().collect() }),
+        Request::Read { address, len } => memory.read_bytes(address, len).map(|bytes| Response::Bytes { bytes }),
+        Request::Write { address, bytes } => {
+            memory.write_bytes(address, &bytes).map(|_| Response::Written { len: bytes.len() })
+        }
+        Request::Scan { pattern } => Pattern::parse(&pattern)
+            .and_then(|pattern| memory.find_pattern_all(&pattern, &RegionFilter::new()))
+            .map(|addresses| Response::Matches { addresses }),
+        Request::Modules => memory.modules_or_live().map(|modules| Response::Modules { modules: modules.into_owned() }),
+    };
+    result.unwrap_or_else(|e| error_response(&e))
+}
+
+fn error_response(e: &io::Error) -> Response {
+    Response::Error { kind: format!("{:?}", e.kind()), message: e.to_string() }
+}
+
+// Typed client for `serve`. Requests are sent one at a time and wait for their response.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: BufWriter<TcpStream>,
+}
+
+impl Client {
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        Ok(Client {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: BufWriter::new(stream),
+        })
+    }
+
+    pub fn regions(&mut self) -> io::Result<Vec<MemoryRegion>> {
+        match self.request(&Request::Regions)? {
+            Response::Regions { regions } => Ok(regions),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn read(&mut self, address: usize, len: usize) -> io::Result<Vec<u8>> {
+        match self.request(&Request::Read { address, len })? {
+            Response::Bytes { bytes } => Ok(bytes),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn write(&mut self, address: usize, bytes: &[u8]) -> io::Result<usize> {
+        match self.request(&Request::Write { address, bytes: bytes.to_vec() })? {
+            Response::Written { len } => Ok(len),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn scan(&mut self, pattern: &str) -> io::Result<Vec<usize>> {
+        match self.request(&Request::Scan { pattern: pattern.to_string() })? {
+            Response::Matches { addresses } => Ok(addresses),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn modules(&mut self) -> io::Result<Vec<ModuleInfo>> {
+        match self.request(&Request::Modules)? {
+            Response::Modules { modules } => Ok(modules),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    // Sends a raw request. Server-side failures come back as `Err`, not as `Response::Error`.
+    pub fn request(&mut self, request: &Request) -> io::Result<Response> {
+        serde_json::to_writer(&mut self.writer, request)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Server closed the connection"));
+        }
+        match serde_json::from_str(&line)? {
+            Response::Error { kind, message } => Err(io::Error::other(format!("{}: {}", kind, message))),
+            response => Ok(response),
+        }
+    }
+}
+
+fn unexpected(response: Response) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("Unexpected response {:?}", response))
+}
@@ -0,0 +1,414 @@
+// Iterative "next scan" narrowing: start from the addresses of a first scan, then keep only the
+// candidates whose value changed the way a filter says since the previous round.
+//
+//     let hits = memory.scan_for_float(100.0, 0.01, FloatWidth::F32, &RegionFilter::new())?;
+//     let mut session = ScanSession::<f32>::from_addresses(&memory, hits);
+//     // ... take damage in the target ...
+//     session.next_scan(&memory, ScanFilter::DecreasedByAtLeast(5.0));
+//
+// Without a known starting value, `start_unknown` treats every aligned slot of the filtered
+// regions as a candidate. Those are kept as one byte image plus a survivor bitmap per region
+// instead of an entry per address, until few enough survive to list them individually.
+
+use std::fmt;
+use std::mem;
+use std::ops::Range;
+
+use log::debug;
+
+use crate::artifacts;
+use crate::chunks::Chunks;
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::pod::Pod;
+use crate::remote_struct::extract;
+use crate::scan::{ScanReport, ScanTally, CHUNK_SIZE};
+
+// Survivor count below which an unknown-value session switches to per-address candidates.
+pub const MATERIALIZE_THRESHOLD: usize = 1 << 16;
+
+// Values a session can compare. Integer arithmetic wraps; floats compare as IEEE values, and
+// NaN or infinite readings drop the candidate.
+pub trait ScanValue: Pod + PartialOrd + fmt::Debug {
+    // Type name recorded in saved sessions, e.g. "u32".
+    const NAME: &'static str;
+
+    fn is_valid(self) -> bool {
+        true
+    }
+
+    fn add_delta(self, delta: Self) -> Self;
+    fn sub_delta(self, delta: Self) -> Self;
+}
+
+macro_rules! impl_scan_value_int {
+    ($($ty:ty),*) => {$(
+        impl ScanValue for $ty {
+            const NAME: &'static str = stringify!($ty);
+
+            fn add_delta(self, delta: Self) -> Self {
+                self.wrapping_add(delta)
+            }
+
+            fn sub_delta(self, delta: Self) -> Self {
+                self.wrapping_sub(delta)
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_scan_value_float {
+    ($($ty:ty),*) => {$(
+        impl ScanValue for $ty {
+            const NAME: &'static str = stringify!($ty);
+
+            fn is_valid(self) -> bool {
+                self.is_finite()
+            }
+
+            fn add_delta(self, delta: Self) -> Self {
+                self + delta
+            }
+
+            fn sub_delta(self, delta: Self) -> Self {
+                self - delta
+            }
+        }
+    )*};
+}
+
+impl_scan_value_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+impl_scan_value_float!(f32, f64);
+
+// How a candidate's value must have changed since the previous round to survive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScanFilter<T> {
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    // By exactly this much. For floats prefer the `AtLeast` forms, which survive rounding.
+    IncreasedBy(T),
+    DecreasedBy(T),
+    IncreasedByAtLeast(T),
+    DecreasedByAtLeast(T),
+    Equal(T),
+}
+
+impl<T: ScanValue> ScanFilter<T> {
+    pub fn matches(&self, old: T, new: T) -> bool {
+        if !new.is_valid() {
+            return false;
+        }
+        match *self {
+            ScanFilter::Changed => new != old,
+            ScanFilter::Unchanged => new == old,
+            ScanFilter::Increased => new > old,
+            ScanFilter::Decreased => new < old,
+            ScanFilter::IncreasedBy(delta) => new == old.add_delta(delta),
+            ScanFilter::DecreasedBy(delta) => new == old.sub_delta(delta),
+            ScanFilter::IncreasedByAtLeast(delta) => new > old && new.sub_delta(old) >= delta,
+            ScanFilter::DecreasedByAtLeast(delta) => new < old && old.sub_delta(new) >= delta,
+            ScanFilter::Equal(value) => new == value,
+        }
+    }
+}
+
+pub struct ScanSession<T> {
+    state: State<T>,
+    last_filter: Option<ScanFilter<T>>,
+}
+
+enum State<T> {
+    // Surviving addresses with the value each had in the last round, in ascending order.
+    Listed(Vec<(usize, T)>),
+    Images { images: Vec<RegionImage>, alignment: usize },
+}
+
+// The bytes of one scanned range as of the last round, and which of its slots still survive.
+struct RegionImage {
+    start: usize,
+    bytes: Vec<u8>,
+    // Bit i stands for the slot at `first_slot + i * alignment`.
+    survivors: Vec<u64>,
+    first_slot: usize,
+    survivor_count: usize,
+}
+
+impl RegionImage {
+    fn slot_address(&self, index: usize, alignment: usize) -> usize {
+        self.first_slot + index * alignment
+    }
+
+    fn is_alive(&self, index: usize) -> bool {
+        self.survivors[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    fn kill(&mut self, index: usize) {
+        self.survivors[index / 64] &= !(1 << (index % 64));
+        self.survivor_count -= 1;
+    }
+
+    fn value<T: Pod>(&self, address: usize) -> T {
+        extract(&self.bytes, address - self.start)
+    }
+
+    fn survivors<'a>(&'a self, alignment: usize) -> impl Iterator<Item = usize> + 'a {
+        let slots = self.survivors.len() * 64;
+        (0..slots).filter(move |&index| self.is_alive(index)).map(move |index| self.slot_address(index, alignment))
+    }
+}
+
+impl<T: ScanValue> ScanSession<T> {
+    // Records the current value at each address; unreadable ones are dropped.
+    pub fn from_addresses(memory: &Memory, addresses: impl IntoIterator<Item = usize>) -> Self {
+        let mut candidates: Vec<(usize, T)> = addresses
+            .into_iter()
+            .filter_map(|address| memory.read_unaligned(address).ok().map(|value| (address, value)))
+            .collect();
+        candidates.sort_by_key(|(address, _)| *address);
+        Self::from_candidates(candidates, None)
+    }
+
+    // Candidates must be sorted by address.
+    pub(crate) fn from_candidates(candidates: Vec<(usize, T)>, last_filter: Option<ScanFilter<T>>) -> Self {
+        ScanSession { state: State::Listed(candidates), last_filter }
+    }
+
+    // Snapshots every `align_of::<T>()`-aligned slot of the regions the filter selects, for
+    // when only the way the value changes is known. Our own artifacts are left out as in any
+    // other scan, and ranges that can't be read at all are skipped.
+    pub fn start_unknown(memory: &Memory, filter: &RegionFilter) -> Self {
+        Self::start_unknown_report(memory, filter).0
+    }
+
+    // `start_unknown` with what the snapshot covered. The report's `matches` are left empty;
+    // every slot read is a candidate.
+    pub fn start_unknown_report(memory: &Memory, filter: &RegionFilter) -> (Self, ScanReport) {
+        let alignment = mem::align_of::<T>();
+        let size = mem::size_of::<T>();
+        let excluded = memory.excluded_ranges(true);
+        let regions = memory.regions();
+        let mut tally = ScanTally::new(regions.len());
+
+        let mut images = Vec::new();
+        for region in regions {
+            let Some(range) = filter.clip(region) else {
+                tally.skip(region, filter.skip_reason(region));
+                continue;
+            };
+            tally.enter(region);
+            for piece in artifacts::subtract(range, &excluded) {
+                let Some(bytes) = read_image(memory, &piece) else {
+                    tally.unreadable(piece.len());
+                    continue;
+                };
+                tally.read(bytes.len());
+                tally.unreadable(piece.len() - bytes.len());
+                let first_slot = piece.start.next_multiple_of(alignment);
+                let end = piece.start + bytes.len();
+                let slots = if first_slot + size <= end { (end - size - first_slot) / alignment + 1 } else { 0 };
+                if slots == 0 {
+                    continue;
+                }
+
+                let mut survivors = vec![u64::MAX; slots.div_ceil(64)];
+                if slots % 64 != 0 {
+                    *survivors.last_mut().unwrap() = (1 << (slots % 64)) - 1;
+                }
+                images.push(RegionImage { start: piece.start, bytes, survivors, first_slot, survivor_count: slots });
+            }
+        }
+
+        let mut session = ScanSession { state: State::Images { images, alignment }, last_filter: None };
+        debug!("unknown-value session: {} candidates in {} bytes", session.len(), session.memory_usage());
+        session.materialize_if_small();
+        (session, tally.report(Vec::new()))
+    }
+
+    // Rereads every candidate and keeps those matching `filter`; returns how many are left.
+    pub fn next_scan(&mut self, memory: &Memory, filter: ScanFilter<T>) -> usize {
+        self.last_filter = Some(filter);
+        match &mut self.state {
+            State::Listed(candidates) => candidates.retain_mut(|(address, value)| {
+                match memory.read_unaligned::<T>(*address) {
+                    Ok(new) if filter.matches(*value, new) => {
+                        *value = new;
+                        true
+                    }
+                    _ => false,
+                }
+            }),
+            State::Images { images, alignment } => {
+                for image in images.iter_mut() {
+                    let range = image.start..image.start + image.bytes.len();
+                    let Some(bytes) = read_image(memory, &range).filter(|bytes| bytes.len() == range.len()) else {
+                        // Lose the whole image rather than compare against stale bytes.
+                        image.survivor_count = 0;
+                        continue;
+                    };
+
+                    let old = mem::replace(&mut image.bytes, bytes);
+                    let slots = image.survivors.len() * 64;
+                    for index in 0..slots {
+                        if !image.is_alive(index) {
+                            continue;
+                        }
+                        let address = image.slot_address(index, *alignment);
+                        let old_value: T = extract(&old, address - image.start);
+                        if !filter.matches(old_value, image.value(address)) {
+                            image.kill(index);
+                        }
+                    }
+                }
+                images.retain(|image| image.survivor_count > 0);
+                self.materialize_if_small();
+            }
+        }
+        self.len()
+    }
+
+    // Surviving addresses with their latest values, in ascending order.
+    pub fn candidates(&self) -> Vec<(usize, T)> {
+        match &self.state {
+            State::Listed(candidates) => candidates.clone(),
+            State::Images { .. } => self.iter_candidates().collect(),
+        }
+    }
+
+    // `candidates` without collecting them, so a session still held as images isn't
+    // materialized to be walked once.
+    pub(crate) fn iter_candidates(&self) -> Box<dyn Iterator<Item = (usize, T)> + '_> {
+        match &self.state {
+            State::Listed(candidates) => Box::new(candidates.iter().copied()),
+            State::Images { images, alignment } => Box::new(
+                images
+                    .iter()
+                    .flat_map(|image| image.survivors(*alignment).map(move |address| (address, image.value(address)))),
+            ),
+        }
+    }
+
+    pub fn last_filter(&self) -> Option<ScanFilter<T>> {
+        self.last_filter
+    }
+
+    pub fn addresses(&self) -> Vec<usize> {
+        self.candidates().into_iter().map(|(address, _)| address).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.state {
+            State::Listed(candidates) => candidates.len(),
+            State::Images { images, .. } => images.iter().map(|image| image.survivor_count).sum(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Heap bytes held for the candidates, i.e. the images and bitmaps, or the candidate list.
+    pub fn memory_usage(&self) -> usize {
+        match &self.state {
+            State::Listed(candidates) => candidates.capacity() * mem::size_of::<(usize, T)>(),
+            State::Images { images, .. } => images
+                .iter()
+                .map(|image| image.bytes.capacity() + image.survivors.capacity() * mem::size_of::<u64>())
+                .sum::<usize>()
+                + images.capacity() * mem::size_of::<RegionImage>(),
+        }
+    }
+
+    fn materialize_if_small(&mut self) {
+        if matches!(self.state, State::Images { .. }) && self.len() < MATERIALIZE_THRESHOLD {
+            self.state = State::Listed(self.candidates());
+        }
+    }
+}
+
+// Reads `range` a chunk at a time; None if not even the first chunk could be read. A short
+// read ends the image early.
+fn read_image(memory: &Memory, range: &Range<usize>) -> Option<Vec<u8>> {
+    let mut bytes = vec![0u8; range.len()];
+    let mut chunks = Chunks::new(range.clone(), CHUNK_SIZE, 0, 1);
+    let mut filled = 0;
+    // The chunks follow one another, so each lands right after the one before.
+    while let Some(chunk) = chunks.read_next(memory, &mut bytes[filled..]) {
+        filled += chunk.read;
+    }
+    if filled == 0 {
+        return None;
+    }
+    bytes.truncate(filled);
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{MockBackend, PAGE};
+
+    #[test]
+    fn float_filters_hold_at_their_boundaries() {
+        // 0.25 steps are exact, so the comparisons land on the boundary itself.
+        assert!(ScanFilter::IncreasedByAtLeast(0.25f32).matches(1.0, 1.25));
+        assert!(!ScanFilter::IncreasedByAtLeast(0.25f32).matches(1.0, 1.0 + 0.25 - f32::EPSILON));
+        assert!(ScanFilter::DecreasedByAtLeast(0.25f64).matches(1.0, 0.75));
+        assert!(!ScanFilter::DecreasedByAtLeast(0.25f64).matches(1.0, 0.75 + f64::EPSILON));
+        // At least zero still means it moved in that direction.
+        assert!(!ScanFilter::IncreasedByAtLeast(0.0f32).matches(1.0, 1.0));
+        assert!(ScanFilter::IncreasedByAtLeast(0.0f32).matches(0.0, f32::from_bits(1)));
+
+        // 0.3 - 0.1 falls just short of 0.2, so exact deltas miss; a looser bound survives it.
+        assert!(!ScanFilter::IncreasedBy(0.2f64).matches(0.1, 0.3));
+        assert!(!ScanFilter::IncreasedByAtLeast(0.2f64).matches(0.1, 0.3));
+        assert!(ScanFilter::IncreasedByAtLeast(0.19f64).matches(0.1, 0.3));
+        assert!(ScanFilter::Unchanged.matches(0.0f32, -0.0));
+    }
+
+    #[test]
+    fn nan_and_infinite_readings_drop_the_candidate() {
+        for new in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            assert!(!ScanFilter::Changed.matches(1.0, new));
+            assert!(!ScanFilter::Decreased.matches(1.0, new));
+            assert!(!ScanFilter::IncreasedByAtLeast(0.0).matches(1.0, new));
+        }
+        // But a finite reading after one is compared as usual.
+        assert!(ScanFilter::Changed.matches(f64::NAN, 1.0));
+        assert!(ScanFilter::Equal(f64::MAX).matches(f64::INFINITY, f64::MAX));
+    }
+
+    #[test]
+    fn float_sessions_narrow_on_how_values_moved() {
+        let (_mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        memory.scan_memory().unwrap();
+        for (offset, value) in [(0x10, 10.0f32), (0x14, 10.0), (0x18, 10.0), (0x1C, 10.0)] {
+            memory.write_memory(0x10000 + offset, value).unwrap();
+        }
+        let mut session = ScanSession::<f32>::from_addresses(&memory, [0x1001C, 0x10010, 0x10014, 0x10018]);
+        assert_eq!(session.addresses(), [0x10010, 0x10014, 0x10018, 0x1001C]);
+
+        memory.write_memory(0x10010, 10.5f32).unwrap();
+        memory.write_memory(0x10014, 10.4999f32).unwrap();
+        memory.write_memory(0x10018, f32::NAN).unwrap();
+        memory.write_memory(0x1001C, 9.0f32).unwrap();
+        assert_eq!(session.next_scan(&memory, ScanFilter::IncreasedByAtLeast(0.5)), 1);
+        assert_eq!(session.candidates(), [(0x10010, 10.5)]);
+        assert_eq!(session.last_filter(), Some(ScanFilter::IncreasedByAtLeast(0.5)));
+    }
+
+    #[test]
+    fn unknown_float_sessions_compare_every_slot() {
+        let (_mock, mut memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        memory.scan_memory().unwrap();
+        let mut session = ScanSession::<f32>::start_unknown(&memory, &RegionFilter::new());
+        assert_eq!(session.len(), PAGE / 4);
+
+        memory.write_memory(0x10100, 0.5f32).unwrap();
+        memory.write_memory(0x10200, -0.5f32).unwrap();
+        memory.write_memory(0x10300, f32::INFINITY).unwrap();
+        assert_eq!(session.next_scan(&memory, ScanFilter::Changed), 2);
+        assert_eq!(session.candidates(), [(0x10100, 0.5), (0x10200, -0.5)]);
+    }
+}
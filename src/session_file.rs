@@ -0,0 +1,497 @@
+// Saving and resuming `ScanSession`s, so narrowing a value can span several runs of the tool.
+// The format is a small little-endian binary layout:
+//
+//   the header, with the target's identity         (see persist.rs)
+//   str value type ("u32", "f32", ...)
+//   u8 has last filter, then u8 filter kind + value   (if present)
+//   u16 module count, str module name per module
+//   u64 candidate count, then per candidate:
+//     u16 module index (0xFFFF for an absolute address), u64 RVA or address, value
+//
+// Strings are a u16 byte length followed by UTF-8. Addresses inside modules are stored
+// module-relative (see rebase.rs); everything else only resolves within the same run.
+// Version 1 began with a magic "NNSCAN" and a u16 version, and kept the identity in the body,
+// as a str main module name and u64 size after the value type.
+
+use std::fs;
+use std::io::{self, Read};
+use std::mem;
+use std::path::Path;
+
+use log::debug;
+
+use crate::memory::Memory;
+use crate::pe::PeHeaders;
+use crate::persist::{Format, Header, Legacy, Persist, TargetIdentity};
+use crate::process_tree;
+use crate::rebase::{RebasedAddress, Rebaser};
+use crate::remote_struct::extract;
+use crate::session::{ScanFilter, ScanSession, ScanValue};
+
+const ABSOLUTE: u16 = u16::MAX;
+
+// The headers of the executable, section table included, fit in its first page.
+const IMAGE_HEADER_READ: u64 = 0x1000;
+
+impl<T: ScanValue> ScanSession<T> {
+    pub fn save(&self, path: impl AsRef<Path>, memory: &Memory) -> io::Result<()> {
+        let rebaser = Rebaser::from_memory(memory)?;
+        let target = TargetIdentity::of(memory)?;
+
+        let mut out = Vec::new();
+        match self.last_filter() {
+            Some(filter) => {
+                out.push(1);
+                let (kind, value) = encode_filter(filter);
+                out.push(kind);
+                // Filters without a value still write one, so every filter record has the same size.
+                match value {
+                    Some(value) => out.extend_from_slice(&value_bytes(value)),
+                    None => out.resize(out.len() + mem::size_of::<T>(), 0),
+                }
+            }
+            None => out.push(0),
+        }
+
+        // The candidates are encoded as they are walked, since the module table before them is
+        // only known once they all have been.
+        let mut modules: Vec<String> = Vec::new();
+        let mut entries = Vec::with_capacity(self.len() * (10 + mem::size_of::<T>()));
+        let mut count: u64 = 0;
+        for (address, value) in self.iter_candidates() {
+            let (index, offset) = match rebaser.rebase(address) {
+                RebasedAddress::ModuleRelative { module, rva } => {
+                    let index = match modules.iter().position(|known| *known == module) {
+                        Some(index) => index,
+                        None => {
+                            modules.push(module);
+                            modules.len() - 1
+                        }
+                    };
+                    // 0xFFFF marks an absolute address, so it can't be a module index.
+                    let index = u16::try_from(index).ok().filter(|index| *index != ABSOLUTE).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Session has candidates in more than {} modules", ABSOLUTE),
+                        )
+                    })?;
+                    (index, rva)
+                }
+                _ => (ABSOLUTE, address),
+            };
+            entries.extend_from_slice(&index.to_le_bytes());
+            entries.extend_from_slice(&(offset as u64).to_le_bytes());
+            entries.extend_from_slice(&value_bytes(value));
+            count += 1;
+        }
+
+        out.extend_from_slice(&(modules.len() as u16).to_le_bytes());
+        for module in &modules {
+            put_str(&mut out, module)?;
+        }
+        out.extend_from_slice(&count.to_le_bytes());
+        out.extend_from_slice(&entries);
+
+        SavedScan { value_type: T::NAME.to_string(), target, rest: out }.write_versioned(path)
+    }
+
+    // Refuses a file saved for another value type or format version, and, unless `force` is
+    // set, one saved against a target whose main module differs. Candidates in modules that
+    // aren't loaded are dropped.
+    pub fn load(path: impl AsRef<Path>, memory: &Memory, force: bool) -> io::Result<Self> {
+        let saved = SavedScan::read_versioned(path)?.1;
+        if saved.value_type != T::NAME {
+            return Err(invalid(format!("Session holds {} values, not {}", saved.value_type, T::NAME)));
+        }
+
+        let identity = TargetIdentity::of(memory)?;
+        if saved.target != identity && !force {
+            return Err(invalid(format!("Session was saved for {}, not {}", saved.target, identity)));
+        }
+
+        let mut reader = Reader { bytes: &saved.rest, position: 0 };
+
+        let last_filter = match reader.u8()? {
+            0 => None,
+            _ => {
+                let kind = reader.u8()?;
+                let value = reader.value::<T>()?;
+                Some(decode_filter(kind, value).ok_or_else(|| invalid(format!("Unknown filter kind {}", kind)))?)
+            }
+        };
+
+        let modules = (0..reader.u16()?).map(|_| reader.str()).collect::<io::Result<Vec<_>>>()?;
+        let rebaser = Rebaser::from_memory(memory)?;
+        let count = reader.u64()?;
+        let mut candidates = Vec::new();
+        let mut dropped = 0;
+        for _ in 0..count {
+            let index = reader.u16()?;
+            let offset = reader.u64()? as usize;
+            let value = reader.value::<T>()?;
+            let resolved = if index == ABSOLUTE {
+                Ok(offset)
+            } else {
+                let module = modules.get(index as usize).ok_or_else(|| invalid("Module index out of range"))?;
+                rebaser.resolve(&RebasedAddress::ModuleRelative { module: module.clone(), rva: offset })
+            };
+            match resolved {
+                Ok(address) => candidates.push((address, value)),
+                Err(_) => dropped += 1,
+            }
+        }
+        if dropped > 0 {
+            debug!("dropped {} saved candidates whose module is not loaded", dropped);
+        }
+
+        candidates.sort_by_key(|(address, _)| *address);
+        Ok(ScanSession::from_candidates(candidates, last_filter))
+    }
+}
+
+// The value type a saved session holds, so a caller can pick the `ScanSession<T>` to load it as.
+pub fn saved_value_type(path: impl AsRef<Path>) -> io::Result<String> {
+    Ok(SavedScan::read_versioned(path)?.1.value_type)
+}
+
+// The value type and the target identity (main module name and size) of a saved session,
+// without decoding the candidates.
+#[cfg(feature = "files")]
+pub(crate) fn saved_target(path: impl AsRef<Path>) -> io::Result<(String, (String, usize))> {
+    let saved = SavedScan::read_versioned(path)?.1;
+    Ok((saved.value_type, (saved.target.module, saved.target.size)))
+}
+
+// A session file as read, before its candidates are decoded as some `T`: `rest` is the body
+// after the value type.
+struct SavedScan {
+    value_type: String,
+    target: TargetIdentity,
+    rest: Vec<u8>,
+}
+
+impl Persist for SavedScan {
+    const FORMAT: Format =
+        Format { id: "scan", version: 2, legacy: Some(Legacy { magic: b"NNSCAN", version_len: 2 }) };
+
+    fn target(&self) -> Option<TargetIdentity> {
+        Some(self.target.clone())
+    }
+
+    fn write_body(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        put_str(out, &self.value_type)?;
+        out.extend_from_slice(&self.rest);
+        Ok(())
+    }
+
+    fn read_body(header: &Header, body: &[u8]) -> io::Result<Self> {
+        let target = header.target.clone().ok_or_else(|| invalid("Saved scan session names no target"))?;
+        let mut reader = Reader { bytes: body, position: 0 };
+        let value_type = reader.str()?;
+        Ok(SavedScan { value_type, target, rest: body[reader.position..].to_vec() })
+    }
+
+    // Version 2 moved the identity from after the value type into the header.
+    fn migrate(header: &mut Header, body: Vec<u8>) -> io::Result<Vec<u8>> {
+        let mut reader = Reader { bytes: &body, position: 0 };
+        let value_type = reader.str()?;
+        header.target = Some(TargetIdentity { module: reader.str()?, size: reader.u64()? as usize });
+        let mut migrated = Vec::with_capacity(body.len());
+        put_str(&mut migrated, &value_type)?;
+        migrated.extend_from_slice(&body[reader.position..]);
+        Ok(migrated)
+    }
+}
+
+// The main module's name and size, which survive restarts but change with another build.
+// Without the rights to list modules, the executable's file name and the image size in its
+// PE header on disk stand in: the same values, from the file the module was mapped from.
+pub(crate) fn identity(memory: &Memory) -> io::Result<(String, usize)> {
+    let modules = match memory.modules_or_live() {
+        Ok(modules) => modules,
+        Err(e) => return identity_from_image(memory.process_id()).map_err(|_| e),
+    };
+    let main = modules.first().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Target has no modules"))?;
+    Ok((main.name.clone(), main.size))
+}
+
+fn identity_from_image(process_id: u32) -> io::Result<(String, usize)> {
+    let path = process_tree::image_path(process_id)?;
+    let mut header = Vec::with_capacity(IMAGE_HEADER_READ as usize);
+    fs::File::open(&path)?.take(IMAGE_HEADER_READ).read_to_end(&mut header)?;
+    let headers = PeHeaders::parse(&header)?;
+    let name = Path::new(&path).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or(path);
+    debug!("identifying process {} by its image {}", process_id, name);
+    Ok((name, headers.size_of_image as usize))
+}
+
+fn encode_filter<T: ScanValue>(filter: ScanFilter<T>) -> (u8, Option<T>) {
+    match filter {
+        ScanFilter::Changed => (0, None),
+        ScanFilter::Unchanged => (1, None),
+        ScanFilter::Increased => (2, None),
+        ScanFilter::Decreased => (3, None),
+        ScanFilter::IncreasedBy(value) => (4, Some(value)),
+        ScanFilter::DecreasedBy(value) => (5, Some(value)),
+        ScanFilter::IncreasedByAtLeast(value) => (6, Some(value)),
+        ScanFilter::DecreasedByAtLeast(value) => (7, Some(value)),
+        ScanFilter::Equal(value) => (8, Some(value)),
+    }
+}
+
+fn decode_filter<T: ScanValue>(kind: u8, value: T) -> Option<ScanFilter<T>> {
+    Some(match kind {
+        0 => ScanFilter::Changed,
+        1 => ScanFilter::Unchanged,
+        2 => ScanFilter::Increased,
+        3 => ScanFilter::Decreased,
+        4 => ScanFilter::IncreasedBy(value),
+        5 => ScanFilter::DecreasedBy(value),
+        6 => ScanFilter::IncreasedByAtLeast(value),
+        7 => ScanFilter::DecreasedByAtLeast(value),
+        8 => ScanFilter::Equal(value),
+        _ => return None,
+    })
+}
+
+fn value_bytes<T: ScanValue>(value: T) -> Vec<u8> {
+    let mut bytes = vec![0u8; mem::size_of::<T>()];
+    unsafe { std::ptr::write_unaligned(bytes.as_mut_ptr() as *mut T, value) };
+    bytes
+}
+
+fn put_str(out: &mut Vec<u8>, text: &str) -> io::Result<()> {
+    let len = u16::try_from(text.len()).map_err(|_| invalid(format!("String too long to save: {}", text)))?;
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(text.as_bytes());
+    Ok(())
+}
+
+fn invalid(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated scan session"))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> io::Result<String> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("Invalid UTF-8 in scan session"))
+    }
+
+    fn value<T: ScanValue>(&mut self) -> io::Result<T> {
+        Ok(extract(self.take(mem::size_of::<T>())?, 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::mock::{MockBackend, PAGE};
+    use crate::module::ModuleInfo;
+    use crate::persist;
+
+    const HEAP: usize = 0x10000;
+
+    fn module(name: &str, base_address: usize) -> ModuleInfo {
+        ModuleInfo { name: name.to_string(), path: format!("C:\\game\\{}", name), base_address, size: PAGE }
+    }
+
+    // A target with game.exe at `game` and, optionally, client.dll at `client`, each one page of
+    // counting u32s, and a heap page after them.
+    fn target(game: usize, client: Option<usize>) -> (Arc<MockBackend>, Memory) {
+        let counting: Vec<u8> = (0..PAGE as u32 / 4).flat_map(u32::to_le_bytes).collect();
+        let mut mock = MockBackend::new().data(game, counting.clone()).data(HEAP, counting.clone());
+        let mut modules = vec![module("game.exe", game)];
+        if let Some(client) = client {
+            mock = mock.data(client, counting);
+            modules.push(module("client.dll", client));
+        }
+        let (mock, mut memory) = mock.open();
+        memory.scan_memory().unwrap();
+        memory.set_module_cache(modules, Vec::new());
+        (mock, memory)
+    }
+
+    fn session_path(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("nirvana-session-{}-{}.nnscan", test, std::process::id()))
+    }
+
+    // Saves a narrowed session over `memory` to a fresh file.
+    fn saved(test: &str, memory: &Memory, addresses: &[usize]) -> PathBuf {
+        let mut session = ScanSession::<u32>::from_addresses(memory, addresses.iter().copied());
+        session.next_scan(memory, ScanFilter::Unchanged);
+        let path = session_path(test);
+        session.save(&path, memory).unwrap();
+        path
+    }
+
+    fn load(path: &Path, memory: &Memory, force: bool) -> io::Result<ScanSession<u32>> {
+        ScanSession::load(path, memory, force)
+    }
+
+    #[test]
+    fn sessions_round_trip() {
+        let (_mock, memory) = target(0x4000_0000, Some(0x4100_0000));
+        let path = saved("round-trip", &memory, &[HEAP + 0x10, 0x4000_0020, 0x4100_0030]);
+
+        let loaded = load(&path, &memory, false);
+        assert_eq!(saved_value_type(&path).unwrap(), "u32");
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.candidates(), [(HEAP + 0x10, 4), (0x4000_0020, 8), (0x4100_0030, 12)]);
+        assert_eq!(loaded.last_filter(), Some(ScanFilter::Unchanged));
+    }
+
+    #[test]
+    fn every_filter_round_trips() {
+        let (_mock, memory) = target(0x4000_0000, None);
+        let filters = [
+            ScanFilter::Changed,
+            ScanFilter::Unchanged,
+            ScanFilter::Increased,
+            ScanFilter::Decreased,
+            ScanFilter::IncreasedBy(3),
+            ScanFilter::DecreasedBy(4),
+            ScanFilter::IncreasedByAtLeast(5),
+            ScanFilter::DecreasedByAtLeast(6),
+            ScanFilter::Equal(7),
+        ];
+        let path = session_path("filters");
+        for filter in filters {
+            let session = ScanSession::from_candidates(vec![(HEAP, 0u32)], Some(filter));
+            session.save(&path, &memory).unwrap();
+            assert_eq!(load(&path, &memory, false).unwrap().last_filter(), Some(filter));
+        }
+        ScanSession::<u32>::from_candidates(Vec::new(), None).save(&path, &memory).unwrap();
+        let empty = load(&path, &memory, false).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.last_filter(), None);
+    }
+
+    #[test]
+    fn module_candidates_follow_a_rebase() {
+        let (_mock, memory) = target(0x4000_0000, Some(0x4100_0000));
+        let path = saved("rebase", &memory, &[HEAP + 0x10, 0x4000_0020, 0x4100_0030]);
+
+        // The next run loads the modules elsewhere, and client.dll not at all.
+        let (_mock, moved) = target(0x5000_0000, None);
+        let loaded = load(&path, &moved, false);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap().candidates(), [(HEAP + 0x10, 4), (0x5000_0020, 8)]);
+    }
+
+    #[test]
+    fn other_targets_and_value_types_are_refused() {
+        let (_mock, memory) = target(0x4000_0000, None);
+        let path = saved("refused", &memory, &[HEAP + 0x10]);
+
+        let e = ScanSession::<u64>::load(&path, &memory, true).err().unwrap();
+        assert_eq!(e.to_string(), "Session holds u32 values, not u64");
+
+        let (_mock, mut other) = target(0x4000_0000, None);
+        let mut build = module("game.exe", 0x4000_0000);
+        build.size = 2 * PAGE;
+        other.set_module_cache(vec![build], Vec::new());
+        let e = load(&path, &other, false).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(e.to_string(), "Session was saved for game.exe (0x1000 bytes), not game.exe (0x2000 bytes)");
+
+        let forced = load(&path, &other, true);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(forced.unwrap().candidates(), [(HEAP + 0x10, 4)]);
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let (_mock, memory) = target(0x4000_0000, None);
+        let path = saved("newer", &memory, &[HEAP + 0x10]);
+
+        // The version follows the magic and the format id.
+        let mut bytes = fs::read(&path).unwrap();
+        let at = 8 + 2 + "scan".len();
+        assert_eq!(bytes[at..at + 2], 2u16.to_le_bytes());
+        bytes[at..at + 2].copy_from_slice(&3u16.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        let e = load(&path, &memory, true).err().unwrap();
+        let peeked = saved_value_type(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        let unsupported = persist::unsupported_version(&e).unwrap();
+        assert_eq!((unsupported.found, unsupported.supported), (3, 2));
+        assert!(persist::unsupported_version(&peeked).is_some());
+    }
+
+    #[test]
+    fn version_1_files_still_load() {
+        let (_mock, memory) = target(0x4000_0000, None);
+        let mut file = b"NNSCAN".to_vec();
+        file.extend_from_slice(&1u16.to_le_bytes());
+        for text in ["u32", "game.exe"] {
+            put_str(&mut file, text).unwrap();
+        }
+        file.extend_from_slice(&(PAGE as u64).to_le_bytes());
+        // IncreasedByAtLeast(2), no modules, one absolute candidate.
+        file.extend_from_slice(&[1, 6, 2, 0, 0, 0, 0, 0]);
+        file.extend_from_slice(&1u64.to_le_bytes());
+        file.extend_from_slice(&ABSOLUTE.to_le_bytes());
+        file.extend_from_slice(&(HEAP as u64 + 0x40).to_le_bytes());
+        file.extend_from_slice(&9u32.to_le_bytes());
+        let path = session_path("version-1");
+        fs::write(&path, file).unwrap();
+
+        let loaded = load(&path, &memory, false);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.candidates(), [(HEAP + 0x40, 9)]);
+        assert_eq!(loaded.last_filter(), Some(ScanFilter::IncreasedByAtLeast(2)));
+    }
+
+    #[test]
+    fn truncated_and_corrupt_files_fail_cleanly() {
+        let (_mock, memory) = target(0x4000_0000, None);
+        let path = saved("truncated", &memory, &[HEAP + 0x10, HEAP + 0x20]);
+        let bytes = fs::read(&path).unwrap();
+
+        for len in [0, 8, bytes.len() / 2, bytes.len() - 1] {
+            fs::write(&path, &bytes[..len]).unwrap();
+            assert!(load(&path, &memory, true).is_err(), "{} of {} bytes", len, bytes.len());
+        }
+        // A module index past the module table.
+        let mut corrupt = bytes.clone();
+        let entry = corrupt.len() - 2 * (2 + 8 + 4);
+        corrupt[entry..entry + 2].copy_from_slice(&0u16.to_le_bytes());
+        fs::write(&path, corrupt).unwrap();
+        let e = load(&path, &memory, true).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(e.to_string(), "Module index out of range");
+    }
+}
@@ -0,0 +1,331 @@
+// Getting back to where a session left off after the tool or the machine restarted. The
+// session file only names the target and points at the files the other pieces already save
+// to (bookmarks, a `PatchSet`, signature files, a saved scan); it doesn't copy them.
+//
+//     let mut state = SessionState::for_target(&memory)?;
+//     PatchSet::capture(&memory, &Rebaser::from_memory(&memory)?).save("patches.json")?;
+//     state.patches = Some("patches.json".into());
+//     state.save("game.session.json")?;
+//
+//     // later, maybe after a reboot
+//     let (memory, report) = SessionState::restore("game.session.json")?;
+//     print!("{}", report);
+//
+// Relative paths are taken relative to the session file. Restoring fails only if the session
+// file can't be read or no running process is the saved target; every other piece is
+// restored as far as it goes and the report says what didn't make it.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::bookmarks::Bookmarks;
+use crate::memory::Memory;
+use crate::persist::{self, Format, TargetIdentity};
+use crate::process_tree::ProcessTree;
+#[cfg(feature = "patch")]
+use crate::patch::PatchSet;
+use crate::rebase::{RebasedAddress, Rebaser};
+use crate::session_file;
+use crate::signature::SignatureFile;
+use crate::wstr;
+
+const FORMAT: Format = Format { id: "session", version: 2, legacy: None };
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionState {
+    // Identity of the target: its main module's name and size, as for saved scans.
+    pub module: String,
+    pub module_size: usize,
+    // The process it was at save time, tried first when reattaching.
+    pub pid: u32,
+    #[serde(default)]
+    pub bookmarks: Option<PathBuf>,
+    #[serde(default)]
+    pub patches: Option<PathBuf>,
+    #[serde(default)]
+    pub signatures: Vec<PathBuf>,
+    #[serde(default)]
+    pub scan_session: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatchOutcome {
+    Applied,
+    // The patched bytes were already there, e.g. the target kept running while the tool was
+    // restarted. Nothing was written, so the patch can't be undone through this `Memory`.
+    AlreadyApplied,
+    // Neither the original nor the patched bytes were found: the target changed, or it is
+    // another build. Left alone.
+    Mismatch { found: Vec<u8> },
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchRestore {
+    pub address: RebasedAddress,
+    // Where the patch resolved to, if it did.
+    pub resolved: Option<usize>,
+    pub outcome: PatchOutcome,
+}
+
+// Name -> resolved address, or why it didn't resolve.
+pub type Resolved = BTreeMap<String, Result<usize, String>>;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    pub pid: u32,
+    // False when the saved process was gone and the target was found by its identity.
+    pub same_pid: bool,
+    // Keyed by label.
+    pub bookmarks: Resolved,
+    pub patches: Vec<PatchRestore>,
+    // Per signature file, keyed by signature name.
+    pub signatures: Vec<(PathBuf, Resolved)>,
+    // Value type of the saved scan, to pick the `ScanSession::<T>::load` that reads it.
+    pub scan_session: Option<(PathBuf, String)>,
+    // Pieces that couldn't be restored at all, such as a missing file or one saved for
+    // another target.
+    pub failures: Vec<String>,
+}
+
+impl RestoreReport {
+    // Everything saved was restored: no failed pieces, unresolved addresses or skipped patches.
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+            && self.bookmarks.values().all(Result::is_ok)
+            && self.signatures.iter().all(|(_, resolved)| resolved.values().all(Result::is_ok))
+            && self
+                .patches
+                .iter()
+                .all(|patch| matches!(patch.outcome, PatchOutcome::Applied | PatchOutcome::AlreadyApplied))
+    }
+}
+
+// "attached to pid 4312 (found by identity)", then one line per piece and per problem.
+impl fmt::Display for RestoreReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "attached to pid {}{}", self.pid, if self.same_pid { "" } else { " (found by identity)" })?;
+
+        let resolved = self.bookmarks.values().filter(|address| address.is_ok()).count();
+        if !self.bookmarks.is_empty() {
+            writeln!(f, "bookmarks: {} of {} resolved", resolved, self.bookmarks.len())?;
+        }
+        for (label, address) in &self.bookmarks {
+            if let Err(e) = address {
+                writeln!(f, "  {}: {}", label, e)?;
+            }
+        }
+
+        if !self.patches.is_empty() {
+            let applied = self.patches.iter().filter(|patch| patch.outcome == PatchOutcome::Applied).count();
+            writeln!(f, "patches: {} of {} applied", applied, self.patches.len())?;
+        }
+        for patch in &self.patches {
+            let at = match patch.resolved {
+                Some(address) => format!("0x{:X}", address),
+                None => format!("{:?}", patch.address),
+            };
+            match &patch.outcome {
+                PatchOutcome::Applied => {}
+                PatchOutcome::AlreadyApplied => writeln!(f, "  {}: already applied", at)?,
+                PatchOutcome::Mismatch { found } => {
+                    writeln!(f, "  {}: original bytes don't match (found {}), skipped", at, hex(found))?
+                }
+                PatchOutcome::Failed(e) => writeln!(f, "  {}: {}", at, e)?,
+            }
+        }
+
+        for (path, resolved) in &self.signatures {
+            let found = resolved.values().filter(|address| address.is_ok()).count();
+            writeln!(f, "signatures {}: {} of {} resolved", path.display(), found, resolved.len())?;
+            for (name, address) in resolved {
+                if let Err(e) = address {
+                    writeln!(f, "  {}: {}", name, e)?;
+                }
+            }
+        }
+
+        if let Some((path, value_type)) = &self.scan_session {
+            writeln!(f, "scan session {}: {} values", path.display(), value_type)?;
+        }
+        for failure in &self.failures {
+            writeln!(f, "failed: {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+impl SessionState {
+    // A state naming `memory`'s target, with nothing else in it yet.
+    pub fn for_target(memory: &Memory) -> io::Result<Self> {
+        let (module, module_size) = session_file::identity(memory)?;
+        Ok(SessionState { module, module_size, pid: memory.process_id(), ..Default::default() })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(persist::read_json(path, &FORMAT)?.1)
+    }
+
+    // Written whole or not at all; see atomic_file.rs.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let target = TargetIdentity { module: self.module.clone(), size: self.module_size };
+        persist::write_json(path, &FORMAT, Some(target), self)
+    }
+
+    // Loads the session file and restores everything it refers to.
+    pub fn restore(path: impl AsRef<Path>) -> io::Result<(Memory, RestoreReport)> {
+        let path = path.as_ref();
+        let state = SessionState::load(path)?;
+        let memory = state.reattach()?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let report = state.restore_into(&memory, base);
+        Ok((memory, report))
+    }
+
+    // Opens the saved process if it is still the target, or else the first running process
+    // with the target's identity, with its regions and modules scanned.
+    pub fn reattach(&self) -> io::Result<Memory> {
+        if let Some(memory) = self.open_if_target(self.pid) {
+            return Ok(memory);
+        }
+        debug!("process {} is gone or no longer {}, looking for another", self.pid, self.module);
+        let tree = ProcessTree::snapshot()?;
+        let found = tree
+            .iter()
+            .filter(|process| process.pid != self.pid && wstr::eq_ignore_case(&process.name, &self.module))
+            .find_map(|process| self.open_if_target(process.pid));
+        found.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No running process is {} (0x{:X} bytes)", self.module, self.module_size),
+            )
+        })
+    }
+
+    fn open_if_target(&self, pid: u32) -> Option<Memory> {
+        let mut memory = Memory::open(pid).ok()?;
+        memory.scan_modules().ok()?;
+        if !self.is_target(session_file::identity(&memory).ok()?) {
+            return None;
+        }
+        memory.scan_memory().ok()?;
+        Some(memory)
+    }
+
+    fn is_target(&self, (module, module_size): (String, usize)) -> bool {
+        wstr::eq_ignore_case(&module, &self.module) && module_size == self.module_size
+    }
+
+    // Restores every piece into an attached target. Relative paths are taken relative to `base`.
+    pub fn restore_into(&self, memory: &Memory, base: impl AsRef<Path>) -> RestoreReport {
+        let base = base.as_ref();
+        let pid = memory.process_id();
+        let mut report = RestoreReport { pid, same_pid: pid == self.pid, ..Default::default() };
+        let rebaser = match Rebaser::from_memory(memory) {
+            Ok(rebaser) => rebaser,
+            Err(e) => {
+                report.failures.push(format!("module list: {}", e));
+                Rebaser::default()
+            }
+        };
+
+        if let Some(path) = &self.bookmarks {
+            let path = locate(base, path);
+            match Bookmarks::load(&path) {
+                Ok(bookmarks) if !self.is_target((bookmarks.module.clone(), bookmarks.module_size)) => {
+                    report.failures.push(format!("bookmarks {}: saved for {}", path.display(), bookmarks.module))
+                }
+                Ok(bookmarks) => {
+                    for bookmark in bookmarks.iter() {
+                        let address = bookmark.resolve(&rebaser).map_err(|e| e.to_string());
+                        report.bookmarks.insert(bookmark.label.clone(), address);
+                    }
+                }
+                Err(e) => report.failures.push(format!("bookmarks {}: {}", path.display(), e)),
+            }
+        }
+
+        if let Some(path) = &self.patches {
+            let path = locate(base, path);
+            #[cfg(feature = "patch")]
+            match PatchSet::load(&path) {
+                Ok(patches) => report.patches = restore_patches(memory, &rebaser, &patches),
+                Err(e) => report.failures.push(format!("patches {}: {}", path.display(), e)),
+            }
+            #[cfg(not(feature = "patch"))]
+            report.failures.push(format!("patches {}: built without the `patch` feature", path.display()));
+        }
+
+        for path in &self.signatures {
+            let path = locate(base, path);
+            match SignatureFile::load(&path) {
+                Ok(signatures) => {
+                    let resolved = signatures
+                        .resolve_all(memory)
+                        .into_iter()
+                        .map(|(name, found)| (name, found.map(|ptr| ptr.address()).map_err(|e| e.to_string())))
+                        .collect();
+                    report.signatures.push((path, resolved));
+                }
+                Err(e) => report.failures.push(format!("signatures {}: {}", path.display(), e)),
+            }
+        }
+
+        if let Some(path) = &self.scan_session {
+            let path = locate(base, path);
+            match session_file::saved_target(&path) {
+                Ok((value_type, identity)) if self.is_target(identity.clone()) => {
+                    report.scan_session = Some((path, value_type))
+                }
+                Ok((_, (module, _))) => {
+                    report.failures.push(format!("scan session {}: saved for {}", path.display(), module))
+                }
+                Err(e) => report.failures.push(format!("scan session {}: {}", path.display(), e)),
+            }
+        }
+
+        report
+    }
+}
+
+// Patches whose original bytes are still in place are applied again; the rest are reported.
+#[cfg(feature = "patch")]
+fn restore_patches(memory: &Memory, rebaser: &Rebaser, patches: &PatchSet) -> Vec<PatchRestore> {
+    patches
+        .patches
+        .iter()
+        .map(|saved| {
+            let resolved = rebaser.resolve(&saved.address);
+            let outcome = match &resolved {
+                Err(e) => PatchOutcome::Failed(e.to_string()),
+                Ok(address) => match memory.read_bytes(*address, saved.patched.len()) {
+                    Err(e) => PatchOutcome::Failed(e.to_string()),
+                    Ok(found) if found == saved.patched => PatchOutcome::AlreadyApplied,
+                    Ok(found) if found != saved.original => PatchOutcome::Mismatch { found },
+                    Ok(_) => match memory.apply_patch(*address, &saved.patched) {
+                        Ok(_) => PatchOutcome::Applied,
+                        Err(e) => PatchOutcome::Failed(e.to_string()),
+                    },
+                },
+            };
+            PatchRestore { address: saved.address.clone(), resolved: resolved.ok(), outcome }
+        })
+        .collect()
+}
+
+fn locate(base: &Path, path: &Path) -> PathBuf {
+    if path.is_relative() {
+        base.join(path)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
@@ -0,0 +1,142 @@
+// Memory shared with the target, for exchanging data with code running inside it far faster
+// than ReadProcessMemory polling allows. A pagefile-backed section is mapped both here and
+// into the target; what either side writes the other sees directly.
+//
+//     let mut channel = SharedChannel::create(&memory, 0x10000)?;
+//     // tell the injected component about channel.remote_address(), then:
+//     let (mut to_target, mut from_target) = channel.rings(256)?;
+//     to_target.push(b"hello");
+
+use std::ffi::c_void;
+use std::io;
+use std::ptr;
+use std::slice;
+
+use log::{debug, warn};
+
+use crate::memory::Memory;
+use crate::ring::RingBuffer;
+use crate::sys::{
+    CloseHandle, CreateFileMappingW, MapViewOfFile, NtMapViewOfSection, NtUnmapViewOfSection, UnmapViewOfFile,
+    ViewUnmap, FILE_MAP_ALL_ACCESS, INVALID_HANDLE_VALUE, MEMORY_MAPPED_VIEW_ADDRESS, PAGE_READWRITE,
+};
+use crate::win_error::win_call;
+
+// A section mapped into both processes. Dropping it unmaps both views.
+pub struct SharedChannel<'a> {
+    memory: &'a Memory,
+    section: *mut c_void,
+    local: *mut u8,
+    remote: usize,
+    size: usize,
+}
+
+impl<'a> SharedChannel<'a> {
+    // Creates a zeroed section of `size` bytes and maps it read-write on both sides.
+    pub fn create(memory: &'a Memory, size: usize) -> io::Result<Self> {
+        if memory.is_dry_run() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "Shared channels cannot be mapped in dry-run mode"));
+        }
+        if size == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "A shared channel needs at least one byte"));
+        }
+
+        let section = win_call!(
+            unsafe {
+                CreateFileMappingW(
+                    INVALID_HANDLE_VALUE,
+                    ptr::null(),
+                    PAGE_READWRITE,
+                    (size as u64 >> 32) as u32,
+                    size as u32,
+                    ptr::null(),
+                )
+            },
+            "Failed to create a 0x{:X} byte section",
+            size
+        )?;
+
+        let local = match win_call!(
+            unsafe { MapViewOfFile(section, FILE_MAP_ALL_ACCESS, 0, 0, size).Value },
+            "Failed to map the section locally"
+        ) {
+            Ok(local) => local as *mut u8,
+            Err(e) => {
+                unsafe { CloseHandle(section) };
+                return Err(e);
+            }
+        };
+
+        let mut remote: *mut c_void = ptr::null_mut();
+        let mut view_size = size;
+        let status = unsafe {
+            NtMapViewOfSection(
+                section,
+                memory.handle(),
+                &mut remote,
+                0,
+                0,
+                ptr::null_mut(),
+                &mut view_size,
+                ViewUnmap,
+                0,
+                PAGE_READWRITE,
+            )
+        };
+        if status < 0 {
+            unsafe {
+                UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS { Value: local as *mut c_void });
+                CloseHandle(section);
+            }
+            return Err(io::Error::other(format!("Failed to map the section into the target (status 0x{:08X})", status)));
+        }
+
+        debug!("shared 0x{:X} bytes: local 0x{:X}, remote 0x{:X}", size, local as usize, remote as usize);
+        Ok(SharedChannel { memory, section, local, remote: remote as usize, size })
+    }
+
+    // Where the section is mapped in the target.
+    pub fn remote_address(&self) -> usize {
+        self.remote
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // The local view. The target can change it at any time, so treat contents as untrusted
+    // and use atomics (as `RingBuffer` does) for anything both sides update.
+    pub fn local(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.local, self.size) }
+    }
+
+    pub fn local_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.local, self.size) }
+    }
+
+    // Splits the section in two and lays out a ring in each half: the first carries messages
+    // to the target, the second messages from it. The target's side attaches to the same
+    // halves (see ring.rs for the layout). Fails if a half can't hold one slot.
+    pub fn rings(&mut self, slot_size: usize) -> io::Result<(RingBuffer<'_>, RingBuffer<'_>)> {
+        let half = self.size / 2 / 8 * 8;
+        let (to_target, from_target) = self.local_mut().split_at_mut(half);
+        let too_small = || io::Error::new(io::ErrorKind::InvalidInput, format!("Section too small for {}-byte slots", slot_size));
+        Ok((
+            RingBuffer::create(to_target, slot_size).ok_or_else(too_small)?,
+            RingBuffer::create(&mut from_target[..half], slot_size).ok_or_else(too_small)?,
+        ))
+    }
+}
+
+impl Drop for SharedChannel<'_> {
+    fn drop(&mut self) {
+        let status = unsafe { NtUnmapViewOfSection(self.memory.handle(), self.remote as *const c_void) };
+        if status < 0 {
+            warn!("failed to unmap the shared view at 0x{:X} in the target (status 0x{:08X})", self.remote, status);
+        }
+        unsafe {
+            UnmapViewOfFile(MEMORY_MAPPED_VIEW_ADDRESS { Value: self.local as *mut c_void });
+            CloseHandle(self.section);
+        }
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::pattern::Pattern;
+use crate::remote_ptr::RemotePtr;
+use crate::rescan::{rescan_module, MatchLocation, RescanStrategy, DEFAULT_RESCAN_WINDOW};
+use crate::scan::ScanOptions;
+use crate::signature_heal::SignatureContext;
+use crate::wstr;
+
+// One named signature. The match address is adjusted in this order: `offset` is added, a
+// rip-relative displacement stored there is followed, then the result is dereferenced.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureEntry {
+    pub name: String,
+    // Module whose image is searched, matched case-insensitively (e.g. "game.exe").
+    pub module: String,
+    // Same syntax as `Pattern::parse`.
+    pub pattern: String,
+    #[serde(default)]
+    pub offset: isize,
+    #[serde(default)]
+    pub deref: bool,
+    #[serde(default)]
+    pub rip_relative: bool,
+    // What surrounded the match when it last resolved, for `Memory::heal_signature`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<SignatureContext>,
+}
+
+// A set of signatures, stored as `[[signatures]]` tables in TOML or a `signatures` array in JSON.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureFile {
+    pub signatures: Vec<SignatureEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SigError {
+    InvalidPattern(String),
+    ModuleNotFound(String),
+    NotFound,
+    // Reading the displacement or the dereferenced pointer failed.
+    Read { address: usize, message: String },
+    // The adjusted address fell outside the address space.
+    Overflow,
+}
+
+impl fmt::Display for SigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SigError::InvalidPattern(message) => write!(f, "invalid pattern: {}", message),
+            SigError::ModuleNotFound(name) => write!(f, "module '{}' is not loaded", name),
+            SigError::NotFound => write!(f, "pattern not found"),
+            SigError::Read { address, message } => write!(f, "failed to read 0x{:X}: {}", address, message),
+            SigError::Overflow => write!(f, "address arithmetic overflowed"),
+        }
+    }
+}
+
+impl std::error::Error for SigError {}
+
+// Where each entry of a signature file matched the last time it resolved, by name, for
+// `SignatureFile::resolve_all_cached`. The pattern is kept with its match, so an entry whose
+// pattern has changed since is scanned for afresh. Files follow the same rule as signature
+// files: `.toml` is TOML, anything else JSON.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureCache {
+    #[serde(default)]
+    pub matches: BTreeMap<String, CachedMatch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedMatch {
+    pub pattern: String,
+    // Where the pattern itself matched, before the entry's adjustments.
+    pub location: MatchLocation,
+}
+
+impl SignatureCache {
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        if is_toml(path) {
+            toml::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        } else {
+            Ok(serde_json::from_str(&text)?)
+        }
+    }
+
+    // An empty cache if there is no file at `path` yet.
+    pub fn load_or_default(path: impl AsRef<Path>) -> io::Result<Self> {
+        match Self::load(path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(SignatureCache::default()),
+            result => result,
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let text = if is_toml(path) {
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        fs::write(path, text)
+    }
+
+    fn previous(&self, entry: &SignatureEntry) -> Option<&MatchLocation> {
+        let cached = self.matches.get(&entry.name)?;
+        (cached.pattern == entry.pattern).then_some(&cached.location)
+    }
+}
+
+fn is_toml(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml"))
+}
+
+impl SignatureFile {
+    // Files ending in `.toml` are parsed as TOML, anything else as JSON.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+            Self::from_toml(&text)
+        } else {
+            Self::from_json(&text)
+        }
+    }
+
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        toml::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn from_json(text: &str) -> io::Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    // Written as TOML or JSON by the same rule as `load`.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let text = if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+            toml::to_string(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        } else {
+            serde_json::to_string_pretty(self)?
+        };
+        fs::write(path, text)
+    }
+
+    // Resolves every entry independently, so one missing signature doesn't hide the others.
+    // Only the cached regions are searched; the module list is taken live if none is cached.
+    pub fn resolve_all(&self, memory: &Memory) -> HashMap<String, Result<RemotePtr<u8>, SigError>> {
+        let modules = memory.modules_or_live().unwrap_or_default();
+
+        self.signatures
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.resolve(memory, &modules)))
+            .collect()
+    }
+
+    // Like `resolve_all`, but an entry `cache` holds a match for is first looked for within
+    // `DEFAULT_RESCAN_WINDOW` of it (see `Memory::rescan_near`), and `cache` is updated with
+    // wherever entries matched this time. With an empty cache this resolves as `resolve_all`
+    // does, and fills it.
+    pub fn resolve_all_cached(
+        &self,
+        memory: &Memory,
+        cache: &mut SignatureCache,
+    ) -> HashMap<String, Result<(RemotePtr<u8>, RescanStrategy), SigError>> {
+        let modules = memory.modules_or_live().unwrap_or_default();
+        let options = memory.default_scan_options();
+
+        let mut results = HashMap::new();
+        for entry in &self.signatures {
+            let previous = cache.previous(entry).cloned();
+            let result = entry.find_match_near(memory, &modules, options, previous.as_ref()).and_then(
+                |(found, module, strategy)| {
+                    let location = MatchLocation::new(&module.name, found - module.base_address);
+                    cache.matches.insert(entry.name.clone(), CachedMatch { pattern: entry.pattern.clone(), location });
+                    Ok((entry.adjust(memory, found)?, strategy))
+                },
+            );
+            results.insert(entry.name.clone(), result);
+        }
+        results
+    }
+
+    // Like `resolve_all`, but first waits for each entry's module to load, sharing `timeout`
+    // among all of them (None waits forever). Meant for DLLs that load late, such as after a
+    // launcher phase; their regions are taken live since the cache predates them. Entries
+    // whose module didn't show up in time fail with `ModuleNotFound`.
+    pub fn resolve_all_deferred(
+        &self,
+        memory: &Memory,
+        timeout: Option<Duration>,
+    ) -> HashMap<String, Result<RemotePtr<u8>, SigError>> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut modules: Vec<ModuleInfo> = Vec::new();
+        for entry in &self.signatures {
+            if modules.iter().any(|module| wstr::eq_ignore_case(&module.name, &entry.module)) {
+                continue;
+            }
+            let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Ok(module) = memory.wait_for_module(&entry.module, remaining) {
+                modules.push(module);
+            }
+        }
+
+        let options = memory.default_scan_options().clone().rescan_regions(true);
+        self.signatures
+            .iter()
+            .map(|entry| (entry.name.clone(), entry.resolve_with(memory, &modules, &options)))
+            .collect()
+    }
+}
+
+impl SignatureEntry {
+    pub fn resolve(&self, memory: &Memory, modules: &[ModuleInfo]) -> Result<RemotePtr<u8>, SigError> {
+        self.resolve_with(memory, modules, memory.default_scan_options())
+    }
+
+    pub fn resolve_with(
+        &self,
+        memory: &Memory,
+        modules: &[ModuleInfo],
+        options: &ScanOptions,
+    ) -> Result<RemotePtr<u8>, SigError> {
+        let found = self.find_match(memory, modules, options)?.0;
+        self.adjust(memory, found)
+    }
+
+    // `offset`, `rip_relative` and `deref` applied to where the pattern matched.
+    fn adjust(&self, memory: &Memory, found: usize) -> Result<RemotePtr<u8>, SigError> {
+        let mut address = found.checked_add_signed(self.offset).ok_or(SigError::Overflow)?;
+        if self.rip_relative {
+            // The displacement is the last thing at `address`, so the "instruction" ends 4 bytes on.
+            address = memory
+                .resolve_rip_relative(address, 0, 4)
+                .map_err(|e| SigError::Read { address, message: e.to_string() })?;
+        }
+        let mut ptr = RemotePtr::new(address);
+        if self.deref {
+            ptr = ptr
+                .cast::<RemotePtr<u8>>()
+                .deref(memory)
+                .map_err(|e| SigError::Read { address, message: e.to_string() })?;
+        }
+
+        memory.track_results(&[ptr.address()]);
+        Ok(ptr)
+    }
+
+    // Where the pattern itself matched, before any adjustment, and the module it matched in.
+    pub(crate) fn find_match<'a>(
+        &self,
+        memory: &Memory,
+        modules: &'a [ModuleInfo],
+        options: &ScanOptions,
+    ) -> Result<(usize, &'a ModuleInfo), SigError> {
+        let (found, module, _) = self.find_match_near(memory, modules, options, None)?;
+        Ok((found, module))
+    }
+
+    // Like `find_match`, but looking around `previous` first if it is in this entry's module.
+    pub(crate) fn find_match_near<'a>(
+        &self,
+        memory: &Memory,
+        modules: &'a [ModuleInfo],
+        options: &ScanOptions,
+        previous: Option<&MatchLocation>,
+    ) -> Result<(usize, &'a ModuleInfo, RescanStrategy), SigError> {
+        let pattern = self.parse_pattern()?;
+        let module = modules
+            .iter()
+            .find(|module| wstr::eq_ignore_case(&module.name, &self.module))
+            .ok_or_else(|| SigError::ModuleNotFound(self.module.clone()))?;
+
+        let read_error = |e: io::Error| SigError::Read { address: module.base_address, message: e.to_string() };
+        let found = match previous.filter(|previous| wstr::eq_ignore_case(&previous.module, &module.name)) {
+            Some(previous) => rescan_module(memory, module, previous.rva, DEFAULT_RESCAN_WINDOW, &pattern, options)
+                .map_err(read_error)?,
+            None => {
+                let filter = RegionFilter::new().range(module.base_address..module.base_address + module.size);
+                let found = memory.find_pattern_with(&pattern, &filter, options).map_err(read_error)?;
+                found.map(|found| (found, RescanStrategy::FullScan))
+            }
+        };
+        let (found, strategy) = found.ok_or(SigError::NotFound)?;
+        Ok((found, module, strategy))
+    }
+
+    pub(crate) fn parse_pattern(&self) -> Result<Pattern, SigError> {
+        Pattern::parse(&self.pattern).map_err(|e| SigError::InvalidPattern(e.to_string()))
+    }
+}
@@ -0,0 +1,366 @@
+// Re-finding a signature after the target updated and its pattern stopped matching. When an
+// entry resolves, the bytes around the match and its RVA can be kept with it; once it breaks,
+// healing looks for the place in the new build that best resembles them and proposes a
+// pattern that matches there:
+//
+//     let context = SignatureContext::capture(&memory, &module, match_address, old.len())?;
+//     // ... the target updates ...
+//     match memory.propose_heal(&old, &context)? {
+//         Some(healed) if healed.confidence >= 0.8 => println!("{} ({:.2})", healed.pattern, healed.confidence),
+//         _ => println!("no convincing match"),
+//     }
+//
+// The search stays near the old RVA, scaled by how much the module grew or shrank, within a
+// window as wide as the size change plus a few pages. Every offset there is ranked by how
+// many of the old significant bytes (the context and the pattern's non-wildcard positions)
+// sit where they were; the best few are then scored by the longest common subsequence of
+// those bytes with what's there, which forgives instructions that were inserted or dropped.
+// The confidence is the fraction of significant bytes in that subsequence. The proposal keeps
+// the old pattern's positions, turning every byte that changed into a wildcard.
+//
+// `heal_in_image` does the same against a module image in a buffer, e.g. one read from disk.
+// `SignatureFile::heal` goes through a whole file: entries that resolve get their context
+// captured again, broken ones with a context get a convincing proposal where there is one,
+// and the result is a new file for review; the original is left as it was.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::Memory;
+use crate::module::ModuleInfo;
+use crate::pattern::{self, Pattern};
+use crate::signature::SignatureFile;
+use crate::wstr;
+
+// Bytes captured on each side of the match.
+pub const CONTEXT_BYTES: usize = 32;
+// `heal_signature` only proposes patterns at least this confident.
+pub const MIN_CONFIDENCE: f64 = 0.6;
+
+// How far the window reaches beyond the module's size change, and at most.
+const WINDOW_SLACK: usize = 0x2000;
+const MAX_WINDOW_RADIUS: usize = 0x10_0000;
+// Offsets the positional ranking passes on to the subsequence scoring.
+const CANDIDATES: usize = 32;
+// How far the subsequence may drift from the candidate at either end.
+const DRIFT: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureContext {
+    pub module: String,
+    // Where the match was, relative to the module base, and how large the module was then.
+    pub rva: usize,
+    pub module_size: usize,
+    // Up to `CONTEXT_BYTES` immediately before and after the matched bytes. Hex text in files.
+    #[serde(with = "hex_bytes")]
+    pub before: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub after: Vec<u8>,
+}
+
+impl SignatureContext {
+    // Records what surrounds a `len`-byte match at `address` in `module`, clipped to the image.
+    pub fn capture(memory: &Memory, module: &ModuleInfo, address: usize, len: usize) -> io::Result<Self> {
+        if !module.contains(address) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("0x{:X} is not inside {}", address, module.name),
+            ));
+        }
+        let rva = address - module.base_address;
+        let lead = rva.min(CONTEXT_BYTES);
+        let end = (rva + len).min(module.size);
+        let trail = (module.size - end).min(CONTEXT_BYTES);
+
+        let before = memory.read_bytes(address - lead, lead)?;
+        let after = memory.read_bytes(module.base_address + end, trail)?;
+        Ok(SignatureContext { module: module.name.clone(), rva, module_size: module.size, before, after })
+    }
+}
+
+// A proposed replacement for a pattern that stopped matching.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealedSignature {
+    pub pattern: Pattern,
+    // Where it matches, relative to the module base.
+    pub rva: usize,
+    // Fraction of the old significant bytes found again there, 0 to 1.
+    pub confidence: f64,
+    // The best score clear of the compared bytes; close to `confidence` means ambiguous.
+    pub runner_up: f64,
+    // How often `pattern` matches in the searched window; more than one makes it unusable.
+    pub matches: usize,
+}
+
+impl HealedSignature {
+    // Confident, clearly ahead of anything else, and unique in the window.
+    pub fn is_convincing(&self) -> bool {
+        self.confidence >= MIN_CONFIDENCE && self.runner_up < self.confidence && self.matches == 1
+    }
+}
+
+// What `SignatureFile::heal` did with one entry.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HealOutcome {
+    // It still resolves; its context was captured afresh.
+    Refreshed,
+    // It didn't; the entry now holds the proposal.
+    Healed(HealedSignature),
+    // The best proposal wasn't convincing, so the entry is unchanged.
+    Unconvincing(HealedSignature),
+    // Broken, and no context was stored to heal from.
+    NoContext,
+    Failed(String),
+}
+
+impl fmt::Display for HealOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealOutcome::Refreshed => f.write_str("resolves, context refreshed"),
+            HealOutcome::Healed(healed) => write!(
+                f,
+                "healed to \"{}\" at +0x{:X}, confidence {:.2} (runner-up {:.2})",
+                healed.pattern, healed.rva, healed.confidence, healed.runner_up
+            ),
+            HealOutcome::Unconvincing(healed) => write!(
+                f,
+                "not healed, best match at +0x{:X} has confidence {:.2} (runner-up {:.2}, {} matches)",
+                healed.rva, healed.confidence, healed.runner_up, healed.matches
+            ),
+            HealOutcome::NoContext => f.write_str("not healed, no context stored"),
+            HealOutcome::Failed(message) => write!(f, "not healed: {}", message),
+        }
+    }
+}
+
+impl SignatureFile {
+    // A copy with every entry refreshed or healed as above, and what happened to each, in
+    // file order. Only the cached regions are searched, as by `resolve_all`.
+    pub fn heal(&self, memory: &Memory) -> (SignatureFile, Vec<(String, HealOutcome)>) {
+        let modules = memory.modules_or_live().unwrap_or_default();
+        let options = memory.default_scan_options();
+        let mut healed = self.clone();
+        let mut outcomes = Vec::new();
+        for entry in &mut healed.signatures {
+            let outcome = match (entry.parse_pattern(), entry.find_match(memory, &modules, options)) {
+                (Err(e), _) => HealOutcome::Failed(e.to_string()),
+                (Ok(pattern), Ok((address, module))) => {
+                    match SignatureContext::capture(memory, module, address, pattern.len()) {
+                        Ok(context) => {
+                            entry.context = Some(context);
+                            HealOutcome::Refreshed
+                        }
+                        Err(e) => HealOutcome::Failed(e.to_string()),
+                    }
+                }
+                (Ok(pattern), Err(_)) => match &entry.context {
+                    None => HealOutcome::NoContext,
+                    Some(context) => match memory.propose_heal(&pattern, context) {
+                        Ok(Some(proposal)) if proposal.is_convincing() => {
+                            entry.pattern = proposal.pattern.to_string();
+                            entry.context = modules
+                                .iter()
+                                .find(|module| wstr::eq_ignore_case(&module.name, &context.module))
+                                .and_then(|module| {
+                                    let address = module.base_address + proposal.rva;
+                                    SignatureContext::capture(memory, module, address, pattern.len()).ok()
+                                });
+                            HealOutcome::Healed(proposal)
+                        }
+                        Ok(Some(proposal)) => HealOutcome::Unconvincing(proposal),
+                        Ok(None) => HealOutcome::Failed("nothing to compare against".to_string()),
+                        Err(e) => HealOutcome::Failed(e.to_string()),
+                    },
+                },
+            };
+            outcomes.push((entry.name.clone(), outcome));
+        }
+        (healed, outcomes)
+    }
+}
+
+impl Memory {
+    // The healed pattern if `propose_heal` found a convincing one.
+    pub fn heal_signature(&self, old: &Pattern, context: &SignatureContext) -> io::Result<Option<Pattern>> {
+        let healed = self.propose_heal(old, context)?;
+        Ok(healed.filter(HealedSignature::is_convincing).map(|healed| healed.pattern))
+    }
+
+    // The best candidate near where `old` used to match in the context's module, however
+    // weak; None if the window holds nothing to compare against.
+    pub fn propose_heal(&self, old: &Pattern, context: &SignatureContext) -> io::Result<Option<HealedSignature>> {
+        let modules = self.modules_or_live()?;
+        let module = modules.iter().find(|module| wstr::eq_ignore_case(&module.name, &context.module)).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("Module '{}' is not loaded", context.module))
+        })?;
+
+        let window = search_window(old, context, module.size);
+        let bytes = self.read_bytes(module.base_address + window.start, window.len())?;
+        Ok(heal_at(old, context, &bytes, window.start, module.size))
+    }
+}
+
+// `propose_heal` against `image`, a whole module laid out as in memory.
+pub fn heal_in_image(old: &Pattern, context: &SignatureContext, image: &[u8]) -> Option<HealedSignature> {
+    let window = search_window(old, context, image.len());
+    heal_at(old, context, &image[window.clone()], window.start, image.len())
+}
+
+// RVAs whose bytes healing compares: the window of match starts, widened by the context.
+fn search_window(old: &Pattern, context: &SignatureContext, module_size: usize) -> std::ops::Range<usize> {
+    let expected = scaled_rva(context, module_size);
+    let radius = (module_size.abs_diff(context.module_size) + WINDOW_SLACK).min(MAX_WINDOW_RADIUS);
+    let start = expected.saturating_sub(radius + context.before.len() + DRIFT);
+    let end = expected.saturating_add(radius + old.len() + context.after.len() + DRIFT).min(module_size);
+    start.min(end)..end
+}
+
+fn scaled_rva(context: &SignatureContext, module_size: usize) -> usize {
+    if context.module_size == 0 {
+        return context.rva;
+    }
+    (context.rva as u128 * module_size as u128 / context.module_size as u128) as usize
+}
+
+// One significant byte of the reference: its position relative to the match start, and what
+// it must be under its mask.
+#[derive(Clone, Copy)]
+struct Significant {
+    offset: isize,
+    value: u8,
+    mask: u8,
+}
+
+impl Significant {
+    fn matches(&self, byte: u8) -> bool {
+        byte & self.mask == self.value
+    }
+}
+
+fn reference(old: &Pattern, context: &SignatureContext) -> Vec<Significant> {
+    let lead = context.before.len() as isize;
+    let before = context.before.iter().enumerate().map(|(index, &value)| Significant {
+        offset: index as isize - lead,
+        value,
+        mask: 0xFF,
+    });
+    let matched = old.bytes().iter().zip(old.masks()).enumerate().filter(|(_, (_, &mask))| mask != 0).map(
+        |(index, (&value, &mask))| Significant { offset: index as isize, value, mask },
+    );
+    let after = context.after.iter().enumerate().map(|(index, &value)| Significant {
+        offset: (old.len() + index) as isize,
+        value,
+        mask: 0xFF,
+    });
+    before.chain(matched).chain(after).collect()
+}
+
+// `bytes` holds the module from RVA `base` on.
+fn heal_at(
+    old: &Pattern,
+    context: &SignatureContext,
+    bytes: &[u8],
+    base: usize,
+    module_size: usize,
+) -> Option<HealedSignature> {
+    let reference = reference(old, context);
+    if reference.is_empty() || bytes.len() < old.len() {
+        return None;
+    }
+    let expected = scaled_rva(context, module_size);
+    let byte_at = |start: usize, offset: isize| start.checked_add_signed(offset).and_then(|index| bytes.get(index));
+
+    // Rank every match start by the significant bytes that are exactly where they were.
+    let mut ranked: BinaryHeap<Reverse<(usize, Reverse<usize>, usize)>> = BinaryHeap::new();
+    for start in 0..=bytes.len() - old.len() {
+        let hits = reference
+            .iter()
+            .filter(|item| byte_at(start, item.offset).is_some_and(|&byte| item.matches(byte)))
+            .count();
+        ranked.push(Reverse((hits, Reverse((base + start).abs_diff(expected)), start)));
+        if ranked.len() > CANDIDATES {
+            ranked.pop();
+        }
+    }
+
+    // Then rescore the best of them allowing for insertions and deletions.
+    let first = reference[0].offset;
+    let last = reference[reference.len() - 1].offset;
+    let mut scored: Vec<(usize, usize)> = ranked
+        .into_iter()
+        .map(|Reverse((_, _, start))| {
+            let from = start.checked_add_signed(first).unwrap_or(0).saturating_sub(DRIFT);
+            let to = start.checked_add_signed(last + 1).map_or(bytes.len(), |to| (to + DRIFT).min(bytes.len()));
+            (common_subsequence(&reference, &bytes[from.min(to)..to]), start)
+        })
+        .collect();
+    scored.sort_by_key(|&(score, start)| (Reverse(score), (base + start).abs_diff(expected)));
+    let &(score, start) = scored.first()?;
+    let runner_up = scored
+        .iter()
+        .find(|(_, other)| other.abs_diff(start) as isize > last - first)
+        .map_or(0, |&(score, _)| score);
+
+    let pattern = rewildcard(old, &bytes[start..start + old.len()])?;
+    let matches = pattern::search(bytes, &pattern).len();
+    Some(HealedSignature {
+        pattern,
+        rva: base + start,
+        confidence: score as f64 / reference.len() as f64,
+        runner_up: runner_up as f64 / reference.len() as f64,
+        matches,
+    })
+}
+
+// Length of the longest common subsequence of `reference` and `bytes`, one row at a time.
+fn common_subsequence(reference: &[Significant], bytes: &[u8]) -> usize {
+    let mut row = vec![0usize; bytes.len() + 1];
+    for item in reference {
+        let mut diagonal = 0;
+        for (index, &byte) in bytes.iter().enumerate() {
+            let above = row[index + 1];
+            row[index + 1] = if item.matches(byte) { diagonal + 1 } else { above.max(row[index]) };
+            diagonal = above;
+        }
+    }
+    row[bytes.len()]
+}
+
+// `old`'s masks over the new bytes, with every position whose byte changed a wildcard. None
+// if nothing significant is left.
+fn rewildcard(old: &Pattern, found: &[u8]) -> Option<Pattern> {
+    let masks: Vec<u8> = old
+        .bytes()
+        .iter()
+        .zip(old.masks())
+        .zip(found)
+        .map(|((&value, &mask), &byte)| if byte & mask == value { mask } else { 0 })
+        .collect();
+    if masks.iter().all(|&mask| mask == 0) {
+        return None;
+    }
+    Pattern::with_masks(found, &masks).ok()
+}
+
+// Byte vectors as "48 8B 05" in signature files.
+mod hex_bytes {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        let text: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+        serializer.serialize_str(&text.join(" "))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.split_whitespace()
+            .map(|token| {
+                u8::from_str_radix(token, 16).map_err(|_| de::Error::custom(format!("bad hex byte '{}'", token)))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,225 @@
+// Scan results handed to the caller's code as the scan produces them, rather than collected
+// into a Vec first: for writing them to a database as they come, deduplicating or filtering
+// them in ways this crate has no business knowing about, or streaming them out. A sink sees
+// each match, each region once the scan is done with it, and the finished report:
+//
+//     let sink = Arc::new(Mutex::new(JsonLinesSink::new(io::stdout())));
+//     memory.find_pattern_into(&pattern, &filter, &options, &sink)?;
+//
+//     let hits = Arc::new(Mutex::new(Vec::new()));
+//     memory.scan_with(predicate, 4, &filter, &ScanOptions::new().sink(Arc::clone(&hits)))?;
+//
+// `ScanOptions::sink` hands one to any scan that takes options: pattern, value, float and
+// predicate scans. `find_pattern_into` and `scan_for_value_into` drive it without keeping
+// the matches themselves. The `Vec`, channel and JSON lines sinks below cover the usual
+// cases, and `Tee` feeds two sinks from one scan.
+//
+// A scan calls `on_match` in the order `MatchCallback` describes, right after the callback,
+// and `on_region_done` for a region after every match in it. `on_complete` comes last, from
+// the methods that run the scan to its end; a `pattern_matches` iterator calls the other two
+// only. The sink sits behind a mutex, so scans on several threads can share it: each call
+// is made with the lock held, those of one scan keep their order, and calls of different
+// scans interleave.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::ControlFlow;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use serde_json::json;
+
+use crate::scan::{ScanReport, SkipReason};
+
+pub trait ScanSink: Send {
+    // Returning `Break` ends the scan after this match.
+    fn on_match(&mut self, address: usize) -> ControlFlow<()> {
+        let _ = address;
+        ControlFlow::Continue(())
+    }
+
+    // The region starting at `region` is done: scanned, or skipped for `skipped`.
+    fn on_region_done(&mut self, region: usize, skipped: Option<SkipReason>) {
+        let _ = (region, skipped);
+    }
+
+    // The report of the whole scan. Scans driving a sink with `_into` methods leave its
+    // `matches` empty.
+    fn on_complete(&mut self, report: &ScanReport) {
+        let _ = report;
+    }
+}
+
+// A sink as `ScanOptions` holds it. Clones share the sink.
+#[derive(Clone)]
+pub struct SharedSink(Arc<Mutex<dyn ScanSink>>);
+
+impl SharedSink {
+    pub fn new<S: ScanSink + 'static>(sink: Arc<Mutex<S>>) -> Self {
+        SharedSink(sink)
+    }
+
+    pub(crate) fn on_match(&self, address: usize) -> ControlFlow<()> {
+        self.with(|sink| sink.on_match(address))
+    }
+
+    pub(crate) fn on_region_done(&self, region: usize, skipped: Option<SkipReason>) {
+        self.with(|sink| sink.on_region_done(region, skipped))
+    }
+
+    pub(crate) fn on_complete(&self, report: &ScanReport) {
+        self.with(|sink| sink.on_complete(report))
+    }
+
+    // As with `MatchCallback`, a sink that panicked once is still called.
+    fn with<R>(&self, f: impl FnOnce(&mut dyn ScanSink) -> R) -> R {
+        let mut sink = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        f(&mut *sink)
+    }
+}
+
+impl fmt::Debug for SharedSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedSink")
+    }
+}
+
+// Sinks are equal when they are the same sink.
+impl PartialEq for SharedSink {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for SharedSink {}
+
+// Collects the matches, in the order they came.
+impl ScanSink for Vec<usize> {
+    fn on_match(&mut self, address: usize) -> ControlFlow<()> {
+        self.push(address);
+        ControlFlow::Continue(())
+    }
+}
+
+// What a channel sink sends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkEvent {
+    Match(usize),
+    RegionDone { region: usize, skipped: Option<SkipReason> },
+    Complete(Box<ScanReport>),
+}
+
+// Sends everything to a receiver on another thread. The scan ends once the receiver is gone.
+impl ScanSink for Sender<SinkEvent> {
+    fn on_match(&mut self, address: usize) -> ControlFlow<()> {
+        match self.send(SinkEvent::Match(address)) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(_) => ControlFlow::Break(()),
+        }
+    }
+
+    fn on_region_done(&mut self, region: usize, skipped: Option<SkipReason>) {
+        let _ = self.send(SinkEvent::RegionDone { region, skipped });
+    }
+
+    fn on_complete(&mut self, report: &ScanReport) {
+        let _ = self.send(SinkEvent::Complete(Box::new(report.clone())));
+    }
+}
+
+// One JSON object per line: {"match": "0x7FF6A1B2C3D0"} per match, and at the end
+// {"complete": {...}} with the report's numbers. Addresses are hex strings, as in the
+// fixture's output. Regions are left out unless `regions` is set:
+// {"region": "0x1F0000", "skipped": "guard"}, with "skipped" null for a scanned one.
+//
+// A failed write ends the scan; `finish` returns the error.
+pub struct JsonLinesSink<W: Write + Send> {
+    writer: W,
+    regions: bool,
+    error: Option<io::Error>,
+}
+
+impl<W: Write + Send> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        JsonLinesSink { writer, regions: false, error: None }
+    }
+
+    pub fn regions(mut self, regions: bool) -> Self {
+        self.regions = regions;
+        self
+    }
+
+    // The writer, flushed, or the first error writing to it.
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn write_line(&mut self, value: serde_json::Value) -> ControlFlow<()> {
+        if self.error.is_some() {
+            return ControlFlow::Break(());
+        }
+        match writeln!(self.writer, "{}", value) {
+            Ok(()) => ControlFlow::Continue(()),
+            Err(e) => {
+                self.error = Some(e);
+                ControlFlow::Break(())
+            }
+        }
+    }
+}
+
+impl<W: Write + Send> ScanSink for JsonLinesSink<W> {
+    fn on_match(&mut self, address: usize) -> ControlFlow<()> {
+        self.write_line(json!({ "match": format!("0x{:X}", address) }))
+    }
+
+    fn on_region_done(&mut self, region: usize, skipped: Option<SkipReason>) {
+        if self.regions {
+            let skipped = skipped.map(|reason| reason.to_string());
+            let _ = self.write_line(json!({ "region": format!("0x{:X}", region), "skipped": skipped }));
+        }
+    }
+
+    fn on_complete(&mut self, report: &ScanReport) {
+        let _ = self.write_line(json!({
+            "complete": {
+                "regions_total": report.regions_total,
+                "regions_scanned": report.regions_scanned,
+                "regions_skipped": report.regions_skipped.len(),
+                "bytes_scanned": report.bytes_scanned,
+                "bytes_unreadable": report.bytes_unreadable,
+                "millis": report.duration.as_millis() as u64,
+            }
+        }));
+    }
+}
+
+// Both sinks get every call, the first one first. The scan ends when either asks it to, and
+// the other still gets the regions and the report.
+pub struct Tee<A, B>(pub A, pub B);
+
+impl<A: ScanSink, B: ScanSink> ScanSink for Tee<A, B> {
+    fn on_match(&mut self, address: usize) -> ControlFlow<()> {
+        let first = self.0.on_match(address);
+        let second = self.1.on_match(address);
+        if first.is_break() || second.is_break() {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    }
+
+    fn on_region_done(&mut self, region: usize, skipped: Option<SkipReason>) {
+        self.0.on_region_done(region, skipped);
+        self.1.on_region_done(region, skipped);
+    }
+
+    fn on_complete(&mut self, report: &ScanReport) {
+        self.0.on_complete(report);
+        self.1.on_complete(report);
+    }
+}
@@ -0,0 +1,96 @@
+// Tracing spans for finding out where an operation spends its time. With the `tracing`
+// feature each of these is a `trace`-level span, so any `tracing` subscriber can time them,
+// and the CLI's `--trace-out trace.json` writes a Chrome trace of one command. Without the
+// feature `span!` expands to a unit struct and `record!` to nothing, so neither the spans nor
+// the expressions in their fields cost anything:
+//
+//     let _span = span!("read", address = address, size = len; outcome);
+//     let result = ...;
+//     record!(_span, outcome = crate::spans::outcome(&result));
+//
+// The span names and fields are kept stable, since subscribers and their tests match on them:
+//
+//     enumerate_regions   from, regions (recorded), end (recorded)
+//     scan_chunk          address, size, outcome (recorded)          one per chunk a scan reads
+//     match_chunk         address, size, found (recorded)            one per search of a chunk
+//     read                address, size, outcome (recorded)          every read of target memory
+//     syscall             context, outcome (recorded)                every `win_call!`
+//
+// `outcome` is "ok", or for a failed call the `io::ErrorKind`; for reads that succeeded it
+// is the number of bytes read. Durations are the spans' own, from when they are entered to
+// when they are dropped.
+
+#[cfg(feature = "tracing")]
+use std::fmt;
+#[cfg(feature = "tracing")]
+use std::io;
+
+// `span!(name, field = value, ...; recorded, ...)`: enters a span with the given fields and
+// room for the `recorded` ones, which `record!` fills in later.
+#[cfg(feature = "tracing")]
+macro_rules! span {
+    ($name:literal $(, $field:ident = $value:expr)* $(; $($recorded:ident),+)?) => {
+        ::tracing::trace_span!(
+            $name
+            $(, $field = $value)*
+            $($(, $recorded = ::tracing::field::Empty)+)?
+        )
+        .entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! span {
+    ($($anything:tt)*) => {
+        $crate::spans::NoSpan
+    };
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! record {
+    ($span:expr, $field:ident = $value:expr) => {
+        $span.record(stringify!($field), $value);
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! record {
+    ($($anything:tt)*) => {};
+}
+
+pub(crate) use {record, span};
+
+// What `span!` gives without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+#[cfg(feature = "tracing")]
+pub(crate) struct Outcome(Result<Option<usize>, io::ErrorKind>);
+
+#[cfg(feature = "tracing")]
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Ok(None) => f.write_str("ok"),
+            Ok(Some(bytes)) => write!(f, "{}", bytes),
+            Err(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn outcome<T>(result: &io::Result<T>) -> tracing::field::DisplayValue<Outcome> {
+    tracing::field::display(Outcome(result.as_ref().map(|_| None).map_err(io::Error::kind)))
+}
+
+// For results carrying more than an `io::Error`: the kind of the failure, if there was one.
+#[cfg(feature = "tracing")]
+pub(crate) fn error_outcome(failure: Option<io::ErrorKind>) -> tracing::field::DisplayValue<Outcome> {
+    tracing::field::display(Outcome(failure.map_or(Ok(None), Err)))
+}
+
+// For reads, whose outcome is how many bytes they got.
+#[cfg(feature = "tracing")]
+pub(crate) fn read_outcome(result: &io::Result<usize>) -> tracing::field::DisplayValue<Outcome> {
+    tracing::field::display(Outcome(result.as_ref().map(|&read| Some(read)).map_err(io::Error::kind)))
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::memory::{MemoryRegion, RegionType};
+use crate::module::ModuleInfo;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    pub region_count: usize,
+    pub total_committed: usize,
+    // Committed bytes keyed by `MemoryRegion::protection_string`, e.g. "rw-".
+    pub by_protection: BTreeMap<String, usize>,
+    pub by_type: BTreeMap<RegionType, usize>,
+    // Writable and executable at once: rare in well-behaved processes, common in packers and injected code.
+    pub rwx_regions: usize,
+    pub largest_region: Option<MemoryRegion>,
+    // Committed image bytes per module, in module order.
+    pub module_footprint: Vec<(String, usize)>,
+}
+
+impl MemoryStats {
+    pub fn from_regions(regions: &[MemoryRegion], modules: &[ModuleInfo]) -> Self {
+        let mut stats = MemoryStats {
+            region_count: regions.len(),
+            ..MemoryStats::default()
+        };
+
+        for region in regions {
+            stats.total_committed += region.size.get();
+            *stats.by_protection.entry(region.protection_string()).or_default() += region.size.get();
+            *stats.by_type.entry(region.region_type).or_default() += region.size.get();
+            if region.is_writable && region.is_executable {
+                stats.rwx_regions += 1;
+            }
+            if stats.largest_region.as_ref().is_none_or(|largest| region.size > largest.size) {
+                stats.largest_region = Some(region.clone());
+            }
+        }
+
+        stats.module_footprint = modules
+            .iter()
+            .map(|module| {
+                let footprint = regions
+                    .iter()
+                    .filter(|region| region.region_type == RegionType::Image && module.contains(region.start_address.get()))
+                    .map(|region| region.size.get())
+                    .sum();
+                (module.name.clone(), footprint)
+            })
+            .collect();
+
+        stats
+    }
+}
+
+impl fmt::Display for MemoryStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "committed  {:>10}  in {} regions", format_size(self.total_committed), self.region_count)?;
+        if let Some(largest) = &self.largest_region {
+            writeln!(f, "largest    {:>10}  at 0x{:X}", format_size(largest.size.get()), largest.start_address)?;
+        }
+
+        writeln!(f, "by type")?;
+        for (region_type, size) in &self.by_type {
+            writeln!(f, "  {:<8} {:>10}", format!("{:?}", region_type).to_lowercase(), format_size(*size))?;
+        }
+
+        writeln!(f, "by protection")?;
+        for (protection, size) in &self.by_protection {
+            let flag = if protection == "rwx" { "  <- writable code" } else { "" };
+            writeln!(f, "  {:<8} {:>10}{}", protection, format_size(*size), flag)?;
+        }
+
+        if !self.module_footprint.is_empty() {
+            writeln!(f, "modules")?;
+            for (name, size) in &self.module_footprint {
+                writeln!(f, "  {:<24} {:>10}", name, format_size(*size))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::{Address, Size};
+    use crate::backend::mock::MockBackend;
+    use crate::sys::{PAGE_EXECUTE_READWRITE, PAGE_READONLY};
+
+    // A committed region with the access `protection` spells out, e.g. "rw-".
+    fn region(start: usize, size: usize, protection: &str, region_type: RegionType) -> MemoryRegion {
+        MemoryRegion {
+            start_address: Address::new(start),
+            size: Size::new(size),
+            allocation_base: Address::new(start),
+            is_readable: protection.contains('r'),
+            is_writable: protection.contains('w'),
+            is_executable: protection.contains('x'),
+            is_guard: false,
+            is_copy_on_write: false,
+            protection: 0,
+            region_type,
+            is_inferred: false,
+        }
+    }
+
+    fn module(name: &str, base_address: usize, size: usize) -> ModuleInfo {
+        ModuleInfo { name: name.to_string(), path: String::new(), base_address, size }
+    }
+
+    #[test]
+    fn no_regions_add_up_to_nothing() {
+        let stats = MemoryStats::from_regions(&[], &[]);
+        assert_eq!(stats, MemoryStats::default());
+        assert_eq!(stats.to_string(), "committed         0 B  in 0 regions\nby type\nby protection\n");
+
+        // Modules still get a row, with nothing committed.
+        let stats = MemoryStats::from_regions(&[], &[module("game.exe", 0x40_0000, 0x1000)]);
+        assert_eq!(stats.module_footprint, [("game.exe".to_string(), 0)]);
+    }
+
+    #[test]
+    fn one_huge_region_is_all_of_it() {
+        let huge = region(0x10000, usize::MAX - 0xFFFF, "rw-", RegionType::Private);
+        let stats = MemoryStats::from_regions(std::slice::from_ref(&huge), &[]);
+        assert_eq!(stats.region_count, 1);
+        assert_eq!(stats.total_committed, usize::MAX - 0xFFFF);
+        assert_eq!(stats.by_protection, BTreeMap::from([("rw-".to_string(), usize::MAX - 0xFFFF)]));
+        assert_eq!(stats.by_type, BTreeMap::from([(RegionType::Private, usize::MAX - 0xFFFF)]));
+        assert_eq!(stats.largest_region, Some(huge));
+        assert!(stats.to_string().contains("largest"), "{}", stats);
+    }
+
+    #[test]
+    fn regions_are_broken_down_by_protection_type_and_module() {
+        let regions = [
+            region(0x40_0000, 0x1000, "r--", RegionType::Image),
+            region(0x40_1000, 0x3000, "r-x", RegionType::Image),
+            region(0x40_4000, 0x2000, "rw-", RegionType::Image),
+            // A view mapped inside the module's range isn't part of its image.
+            region(0x40_6000, 0x1000, "r--", RegionType::Mapped),
+            region(0x100_0000, 0x8000, "rwx", RegionType::Private),
+            region(0x200_0000, 0x8000, "rwx", RegionType::Private),
+            region(0x300_0000, 0x1000, "---", RegionType::Private),
+        ];
+        let modules = [module("game.exe", 0x40_0000, 0x7000), module("unloaded.dll", 0x5000_0000, 0x1000)];
+        let stats = MemoryStats::from_regions(&regions, &modules);
+
+        assert_eq!(stats.region_count, 7);
+        assert_eq!(stats.total_committed, 0x18000);
+        let by_protection = [("---", 0x1000), ("r--", 0x2000), ("r-x", 0x3000), ("rw-", 0x2000), ("rwx", 0x10000)];
+        assert_eq!(stats.by_protection, by_protection.map(|(key, size)| (key.to_string(), size)).into());
+        let by_type = [(RegionType::Image, 0x6000), (RegionType::Mapped, 0x1000), (RegionType::Private, 0x11000)];
+        assert_eq!(stats.by_type, by_type.into());
+        assert_eq!(stats.rwx_regions, 2);
+        // Of two equally large regions, the first stays the largest.
+        assert_eq!(stats.largest_region.unwrap().start_address, 0x100_0000usize);
+        assert_eq!(stats.module_footprint, [("game.exe".to_string(), 0x6000), ("unloaded.dll".to_string(), 0)]);
+    }
+
+    #[test]
+    fn the_table_flags_writable_code() {
+        let regions = [
+            region(0x40_0000, 0x3000, "r-x", RegionType::Image),
+            region(0x100_0000, 0x800, "rwx", RegionType::Private),
+        ];
+        let stats = MemoryStats::from_regions(&regions, &[module("game.exe", 0x40_0000, 0x3000)]);
+        assert_eq!(
+            stats.to_string(),
+            "committed    14.0 KiB  in 2 regions\n\
+             largest      12.0 KiB  at 0x400000\n\
+             by type\n\
+             \x20 image      12.0 KiB\n\
+             \x20 private     2.0 KiB\n\
+             by protection\n\
+             \x20 r-x        12.0 KiB\n\
+             \x20 rwx         2.0 KiB  <- writable code\n\
+             modules\n\
+             \x20 game.exe                   12.0 KiB\n"
+        );
+    }
+
+    #[test]
+    fn sizes_print_in_binary_units() {
+        for (bytes, text) in [
+            (0, "0 B"),
+            (1023, "1023 B"),
+            (1024, "1.0 KiB"),
+            (1536, "1.5 KiB"),
+            (1 << 20, "1.0 MiB"),
+            ((3 << 30) + (1 << 29), "3.5 GiB"),
+        ] {
+            assert_eq!(format_size(bytes), text);
+        }
+    }
+
+    #[test]
+    fn memory_stats_come_from_the_cached_regions() {
+        let (_, mut memory) = MockBackend::new()
+            .region(0x10000, vec![0; 0x2000], PAGE_READONLY)
+            .region(0x20000, vec![0; 0x1000], PAGE_EXECUTE_READWRITE)
+            .open();
+        assert_eq!(memory.memory_stats().region_count, 0);
+
+        memory.scan_memory().unwrap();
+        let stats = memory.memory_stats();
+        assert_eq!((stats.region_count, stats.total_committed, stats.rwx_regions), (2, 0x3000, 1));
+    }
+}
@@ -0,0 +1,314 @@
+// Reads and writes that cross from one region into the next. A struct can straddle two
+// adjacent committed regions with different protections, and a single ReadProcessMemory or
+// WriteProcessMemory over both can fail or stop at the boundary even though both halves are
+// accessible. When a read or write spanning regions fails that way, it is retried one region
+// at a time and the pieces stitched together; callers see one read or write either way:
+//
+//     let header: [u8; 0x40] = memory.read_memory(straddling)?;
+//     memory.write_bytes(straddling, &header)?;
+//
+// Region boundaries come from the cached regions when they cover the range, else from the
+// target (`query_range`). A piece that still fails is a real hole. A read stops there and
+// comes back short, with what the pieces before it read, so `read_memory` and the other
+// fixed-size reads fail with `UnexpectedEof`; one whose first piece fails, or that runs into
+// a guard page, fails with that piece's error (a `GuardPageError`). A write fails with a
+// `PartialWrite` counting the pieces before it. `read_bytes_with_holes` reads past holes
+// instead, zero-filling them and listing them page by page, as `unreadable_ranges` does.
+
+use std::io;
+use std::ops::Range;
+
+use log::{debug, trace};
+
+use crate::capabilities::missing_capability;
+use crate::memory::{is_guard_page_error, partial_write, Memory, PartialWrite};
+
+impl Memory {
+    // `len` bytes at `address`, with the pages that can't be read left as zeros and returned
+    // alongside, sorted and merged. Fails only for a range past the end of the address space.
+    pub fn read_bytes_with_holes(&self, address: usize, len: usize) -> io::Result<(Vec<u8>, Vec<Range<usize>>)> {
+        let end = address.checked_add(len).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Read of 0x{:X} bytes at 0x{:X} overflows the address space", len, address),
+            )
+        })?;
+        if let Some(bytes) = self.read_bytes(address, len).ok().filter(|bytes| bytes.len() == len) {
+            return Ok((bytes, Vec::new()));
+        }
+
+        let mut bytes = vec![0u8; len];
+        let mut holes = self.unreadable_ranges(address..end);
+        let gaps = gaps(address..end, &holes);
+        for gap in gaps {
+            // The target can change between finding the holes and reading what lies between
+            // them; what stopped reading since is a hole too.
+            let window = &mut bytes[gap.start - address..gap.end - address];
+            let read = self.read_into(gap.start, window).unwrap_or(0);
+            if read < gap.len() {
+                window[read..].fill(0);
+                holes.push(gap.start + read..gap.end);
+            }
+        }
+        holes.sort_by_key(|hole| hole.start);
+        Ok((bytes, merge(holes)))
+    }
+
+    // Where `range` crosses from one region into the next, as the consecutive pieces between
+    // those boundaries. A range whose regions can't be told comes back as one piece.
+    pub(crate) fn region_pieces(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let cached = self.region_cache().overlapping(&range);
+        let covered = cached.first().is_some_and(|first| first.start_address.get() <= range.start)
+            && cached.last().is_some_and(|last| last.end().get() >= range.end)
+            && cached.windows(2).all(|pair| pair[0].end() == pair[1].start_address);
+        let ends: Vec<usize> = if covered {
+            cached.iter().map(|region| region.end().get()).collect()
+        } else {
+            match self.query_range(range.clone()) {
+                Ok(regions) => regions.iter().map(|region| region.end().get()).collect(),
+                Err(_) => Vec::new(),
+            }
+        };
+
+        let mut pieces = Vec::new();
+        let mut start = range.start;
+        for end in ends.into_iter().filter(|end| *end > range.start && *end < range.end) {
+            pieces.push(start..end);
+            start = end;
+        }
+        pieces.push(start..range.end);
+        pieces
+    }
+
+    // Retries a read of `buffer.len()` bytes at `address` that failed or came back short, one
+    // region at a time. `first` is how the single read went, returned as it was if the range
+    // lies in one region or failed in a way retrying can't help (see `is_final`).
+    pub(crate) fn read_stitched(
+        &self,
+        address: usize,
+        buffer: &mut [u8],
+        first: io::Result<usize>,
+    ) -> io::Result<usize> {
+        let end = address.checked_add(buffer.len());
+        let Some(end) = end.filter(|_| !first.as_ref().is_err_and(is_final)) else {
+            return first;
+        };
+        let pieces = self.region_pieces(address..end);
+        if pieces.len() < 2 {
+            return first;
+        }
+
+        let mut read = 0;
+        for piece in pieces {
+            let window = &mut buffer[piece.start - address..piece.end - address];
+            // Bytes already read are kept: the read comes back short where the piece failed,
+            // as a plain one does at an unreadable page.
+            let got = match self.read_once(piece.start, window) {
+                Ok(got) => got,
+                Err(e) if read > 0 && !is_guard_page_error(&e) => {
+                    debug!("stitched read stopped at 0x{:X}: {}", piece.start, e);
+                    break;
+                }
+                Err(e) => return Err(e),
+            };
+            read += got;
+            if got < piece.len() {
+                break;
+            }
+        }
+        trace!("stitched a read of 0x{:X} bytes at 0x{:X}: 0x{:X} read", buffer.len(), address, read);
+        Ok(read)
+    }
+
+    // As `read_stitched`, for a write that failed with `first`.
+    pub(crate) fn write_stitched(&self, address: usize, bytes: &[u8], first: io::Result<()>) -> io::Result<()> {
+        let end = address.checked_add(bytes.len());
+        let Some(end) = end.filter(|_| !first.as_ref().is_err_and(is_final)) else {
+            return first;
+        };
+        let pieces = self.region_pieces(address..end);
+        if pieces.len() < 2 {
+            return first;
+        }
+
+        let mut written = 0;
+        for piece in pieces {
+            if let Err(e) = self.write_once(piece.start, &bytes[piece.start - address..piece.end - address]) {
+                let written = written + partial_write(&e).map_or(0, |partial| partial.written);
+                if written == 0 {
+                    return Err(e);
+                }
+                return Err(io::Error::other(PartialWrite { address, requested: bytes.len(), written }));
+            }
+            written += piece.len();
+        }
+        trace!("stitched a write of 0x{:X} bytes at 0x{:X}", bytes.len(), address);
+        Ok(())
+    }
+}
+
+// Whether a failed read or write is one that retrying a region at a time only repeats or
+// makes worse: a guard page, which a second access could disarm, a timeout, which more calls
+// only prolong, and a handle that is closed or lacks the rights for the call.
+fn is_final(e: &io::Error) -> bool {
+    is_guard_page_error(e)
+        || missing_capability(e).is_some()
+        || matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::NotConnected)
+}
+
+// The parts of `range` outside `holes` (sorted, disjoint, inside `range`).
+fn gaps(range: Range<usize>, holes: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut gaps = Vec::new();
+    let mut start = range.start;
+    for hole in holes {
+        if hole.start > start {
+            gaps.push(start..hole.start);
+        }
+        start = start.max(hole.end);
+    }
+    if start < range.end {
+        gaps.push(start..range.end);
+    }
+    gaps
+}
+
+// Sorted ranges with the touching and overlapping ones joined.
+fn merge(ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{Call, MockBackend, PAGE};
+    use crate::sys::{PAGE_EXECUTE_READ, PAGE_GUARD, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE};
+
+    // A read-write region and a read-only one right after it, with bytes counting up
+    // across the boundary at 0x11000.
+    fn straddled() -> MockBackend {
+        let bytes: Vec<u8> = (0..2 * PAGE).map(|i| i as u8).collect();
+        MockBackend::new().data(0x10000, bytes[..PAGE].to_vec()).region(0x11000, bytes[PAGE..].to_vec(), PAGE_READONLY)
+    }
+
+    #[test]
+    fn reads_across_a_boundary_are_stitched() {
+        let (mock, memory) = straddled().open();
+
+        let value: [u8; 8] = memory.read_memory(0x10FFC).unwrap();
+        assert_eq!(value, [0xFC, 0xFD, 0xFE, 0xFF, 0x00, 0x01, 0x02, 0x03]);
+        assert_eq!(mock.reads(), [0x10FFC..0x11004, 0x10FFC..0x11000, 0x11000..0x11004]);
+    }
+
+    #[test]
+    fn stitching_uses_cached_regions_when_they_cover_the_range() {
+        let (mock, mut memory) = straddled().open();
+        memory.scan_memory().unwrap();
+        mock.clear_calls();
+
+        assert_eq!(memory.read_bytes(0x10F00, 0x200).unwrap().len(), 0x200);
+        assert!(mock.calls().iter().all(|call| matches!(call, Call::Read(_))));
+    }
+
+    #[test]
+    fn writes_across_a_boundary_are_stitched() {
+        let (mock, memory) =
+            MockBackend::new().data(0x10000, vec![0; PAGE]).region(0x11000, vec![0; PAGE], PAGE_READWRITE).open();
+
+        memory.write_bytes(0x10FFE, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(mock.bytes(0x10FFE, 2), [1, 2]);
+        assert_eq!(mock.bytes(0x11000, 2), [3, 4]);
+        assert_eq!(mock.writes(), [0x10FFE..0x11002, 0x10FFE..0x11000, 0x11000..0x11002]);
+    }
+
+    #[test]
+    fn writes_into_an_unwritable_piece_are_partial() {
+        let (_mock, memory) =
+            MockBackend::new().data(0x10000, vec![0; PAGE]).region(0x11000, vec![0; PAGE], PAGE_EXECUTE_READ).open();
+
+        let e = memory.write_bytes(0x10FFE, &[1, 2, 3, 4]).unwrap_err();
+        assert_eq!(partial_write(&e), Some(PartialWrite { address: 0x10FFE, requested: 4, written: 2 }));
+    }
+
+    #[test]
+    fn a_hole_after_the_first_piece_shortens_the_read() {
+        let (_mock, memory) = MockBackend::new()
+            .data(0x10000, vec![7; PAGE])
+            .region(0x11000, vec![0; PAGE], PAGE_NOACCESS)
+            .data(0x12000, vec![9; PAGE])
+            .open();
+
+        assert_eq!(memory.read_bytes(0x10FF0, 0x1020).unwrap(), [7; 0x10]);
+        let e = memory.read_memory::<[u8; 0x20]>(0x10FF0).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn a_hole_in_the_first_piece_fails_the_read() {
+        let (_mock, memory) =
+            MockBackend::new().region(0x10000, vec![0; PAGE], PAGE_NOACCESS).data(0x11000, vec![9; PAGE]).open();
+
+        assert!(memory.read_bytes(0x10FF0, 0x20).is_err());
+    }
+
+    #[test]
+    fn a_guard_page_after_the_first_piece_fails_the_read() {
+        let (_mock, memory) = MockBackend::new()
+            .data(0x10000, vec![0; PAGE])
+            .region(0x11000, vec![0; PAGE], PAGE_READWRITE | PAGE_GUARD)
+            .open();
+
+        let e = memory.read_bytes(0x10FF0, 0x20).unwrap_err();
+        assert!(is_guard_page_error(&e), "{}", e);
+    }
+
+    #[test]
+    fn hole_tolerant_reads_zero_fill_the_hole() {
+        let (_mock, memory) = MockBackend::new()
+            .data(0x10000, vec![7; PAGE])
+            .region(0x11000, vec![0xFF; PAGE], PAGE_NOACCESS)
+            .data(0x12000, vec![9; PAGE])
+            .open();
+
+        let (bytes, holes) = memory.read_bytes_with_holes(0x10FF0, 0x1020).unwrap();
+        assert_eq!(holes, [0x11000..0x12000]);
+        assert_eq!(bytes[..0x10], [7; 0x10]);
+        assert!(bytes[0x10..0x1010].iter().all(|&byte| byte == 0));
+        assert_eq!(bytes[0x1010..], [9; 0x10]);
+    }
+
+    #[test]
+    fn hole_tolerant_reads_of_failing_pages_inside_a_region() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![5; 4 * PAGE]).open();
+        mock.fail_reads(0x11000..0x12000);
+        mock.fail_reads(0x13000..0x14000);
+
+        let (bytes, holes) = memory.read_bytes_with_holes(0x10000, 4 * PAGE).unwrap();
+        assert_eq!(holes, [0x11000..0x12000, 0x13000..0x14000]);
+        assert_eq!(bytes[..PAGE], [5; PAGE]);
+        assert_eq!(bytes[2 * PAGE..3 * PAGE], [5; PAGE]);
+    }
+
+    #[test]
+    fn gaps_are_what_the_holes_leave() {
+        assert_eq!(gaps(0..100, &[]), [0..100]);
+        assert_eq!(gaps(0..100, &[0..100]), Vec::<Range<usize>>::new());
+        assert_eq!(gaps(0..100, &[0..10, 50..60]), [10..50, 60..100]);
+        assert_eq!(gaps(0..100, &[10..20, 90..100]), [0..10, 20..90]);
+    }
+
+    #[test]
+    fn merge_joins_touching_and_overlapping_ranges() {
+        assert_eq!(merge(Vec::new()), Vec::<Range<usize>>::new());
+        assert_eq!(merge(vec![0..10, 10..20, 30..40]), [0..20, 30..40]);
+        assert_eq!(merge(vec![0..30, 10..20, 25..40]), [0..40]);
+        assert_eq!(merge(vec![0..10, 11..20]), [0..10, 11..20]);
+    }
+}
@@ -0,0 +1,268 @@
+// Extracting printable strings from the target, like the `strings` utility but on live memory
+// and with the address of every string. Results stream out of an iterator: large processes
+// hold millions of strings.
+//
+//     for found in memory.extract_strings(8, &[Encoding::Ascii, Encoding::Utf16Le], &RegionFilter::new()) {
+//         println!("0x{:X} {}", found.address, found.text);
+//     }
+
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::ops::Range;
+
+use log::debug;
+
+use crate::artifacts;
+use crate::chunks::Chunks;
+use crate::filter::RegionFilter;
+use crate::memory::{Memory, MemoryRegion};
+use crate::scan::{self, CancelToken, ScanOptions, ScanReport, ScanTally};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Ascii,
+    // Only code units that are printable ASCII with a zero high byte count, as with
+    // `strings -e l`; other characters end the run.
+    Utf16Le,
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Ascii => "ascii",
+            Encoding::Utf16Le => "utf-16le",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundString {
+    pub address: usize,
+    pub encoding: Encoding,
+    pub text: String,
+}
+
+// A run of printable characters being collected.
+#[derive(Default)]
+struct Run {
+    start: usize,
+    text: String,
+}
+
+// Yields each string once its run ends, so strings come out in ascending order per encoding
+// but interleaved between encodings. Runs continue across chunks of a region; unreadable
+// chunks end the region.
+pub struct StringScan<'a> {
+    memory: &'a Memory,
+    filter: &'a RegionFilter,
+    regions: Cow<'a, [MemoryRegion]>,
+    regions_taken: usize,
+    tally: ScanTally,
+    validate_regions: bool,
+    chunk_size: usize,
+    cancel: Option<CancelToken>,
+    excluded: Vec<Range<usize>>,
+    // Parts of the current region still to be read, the next one last.
+    pieces: Vec<Range<usize>>,
+    // The piece being read.
+    chunks: Chunks,
+    min_len: usize,
+    ascii: Option<Run>,
+    // UTF-16 runs starting at even and at odd addresses, tracked apart.
+    wide: [Option<Run>; 2],
+    wide_enabled: bool,
+    ascii_enabled: bool,
+    // The byte before the next one, for pairing UTF-16 code units across chunks.
+    previous: Option<u8>,
+    buffer: Vec<u8>,
+    found: VecDeque<FoundString>,
+    // With `dedup`, every string already yielded.
+    seen: Option<HashSet<(Encoding, String)>>,
+}
+
+impl Memory {
+    // Strings of at least `min_len` characters in the cached regions the filter accepts.
+    pub fn extract_strings<'a>(
+        &'a self,
+        min_len: usize,
+        encodings: &[Encoding],
+        filter: &'a RegionFilter,
+    ) -> StringScan<'a> {
+        self.extract_strings_with(min_len, encodings, filter, self.default_scan_options())
+    }
+
+    // Honors the region selection options, `chunk_size` and `cancel`.
+    pub fn extract_strings_with<'a>(
+        &'a self,
+        min_len: usize,
+        encodings: &[Encoding],
+        filter: &'a RegionFilter,
+        options: &ScanOptions,
+    ) -> StringScan<'a> {
+        let regions = scan::scan_regions(self, options, filter);
+        StringScan {
+            memory: self,
+            filter,
+            tally: ScanTally::new(regions.len()),
+            regions,
+            regions_taken: 0,
+            validate_regions: options.validate_regions,
+            chunk_size: options.chunk_len(),
+            cancel: options.cancel.clone(),
+            excluded: self.excluded_ranges(!options.include_self_artifacts),
+            pieces: Vec::new(),
+            chunks: Chunks::empty(),
+            min_len: min_len.max(1),
+            ascii: None,
+            wide: [None, None],
+            ascii_enabled: encodings.contains(&Encoding::Ascii),
+            wide_enabled: encodings.contains(&Encoding::Utf16Le),
+            previous: None,
+            buffer: Vec::new(),
+            found: VecDeque::new(),
+            seen: None,
+        }
+    }
+}
+
+impl StringScan<'_> {
+    // Yield each distinct text (per encoding) only at its first address. Keeps every distinct
+    // string in memory for the rest of the scan.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.seen = dedup.then(HashSet::new);
+        self
+    }
+
+    // What the scan has covered so far. `matches` is left empty: the strings came out of the
+    // iterator.
+    pub fn report(&self) -> ScanReport {
+        self.tally.report(Vec::new())
+    }
+
+    // Moves on to the next piece of memory to read; false once there is none.
+    fn next_piece(&mut self) -> bool {
+        while self.chunks.is_done() {
+            if let Some(piece) = self.pieces.pop() {
+                self.end_runs();
+                self.chunks = Chunks::new(piece, self.chunk_size, 0, 1);
+                continue;
+            }
+            let Some(region) = self.regions.get(self.regions_taken) else {
+                self.end_runs();
+                return false;
+            };
+            self.regions_taken += 1;
+            let Some(range) = self.filter.clip(region) else {
+                self.tally.skip(region, self.filter.skip_reason(region));
+                continue;
+            };
+            if self.validate_regions {
+                if let Err(stale) = scan::validate_region(self.memory, region) {
+                    self.tally.stale(stale);
+                    continue;
+                }
+            }
+            self.tally.enter(region);
+            self.pieces = artifacts::subtract(range, &self.excluded);
+            self.pieces.reverse();
+        }
+        true
+    }
+
+    fn read_chunk(&mut self) -> bool {
+        if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+            debug!("string scan cancelled");
+            self.end_runs();
+            return false;
+        }
+        if !self.next_piece() {
+            return false;
+        }
+        self.buffer.resize(self.chunk_size, 0);
+        let Some(chunk) = self.chunks.read_next(self.memory, &mut self.buffer) else {
+            return true;
+        };
+        self.tally.read(chunk.read);
+        // The rest of the piece is gone after a short read.
+        self.tally.unreadable(chunk.unreadable);
+
+        let buffer = std::mem::take(&mut self.buffer);
+        for (offset, &byte) in buffer[..chunk.read].iter().enumerate() {
+            self.feed(chunk.address + offset, byte);
+        }
+        self.buffer = buffer;
+        if chunk.short {
+            self.end_runs();
+        }
+        true
+    }
+
+    fn feed(&mut self, address: usize, byte: u8) {
+        if self.ascii_enabled {
+            if is_printable(byte) {
+                self.ascii.get_or_insert_with(|| Run { start: address, text: String::new() }).text.push(byte as char);
+            } else if let Some(run) = self.ascii.take() {
+                self.finish(run, Encoding::Ascii);
+            }
+        }
+
+        if self.wide_enabled {
+            // The code unit that starts at the previous byte.
+            if let Some(low) = self.previous {
+                let start = address - 1;
+                let parity = start % 2;
+                if is_printable(low) && byte == 0 {
+                    self.wide[parity].get_or_insert_with(|| Run { start, text: String::new() }).text.push(low as char);
+                } else if let Some(run) = self.wide[parity].take() {
+                    self.finish(run, Encoding::Utf16Le);
+                }
+            }
+            self.previous = Some(byte);
+        }
+    }
+
+    // Ends every open run, as at a gap in memory.
+    fn end_runs(&mut self) {
+        self.previous = None;
+        if let Some(run) = self.ascii.take() {
+            self.finish(run, Encoding::Ascii);
+        }
+        for parity in 0..2 {
+            if let Some(run) = self.wide[parity].take() {
+                self.finish(run, Encoding::Utf16Le);
+            }
+        }
+    }
+
+    fn finish(&mut self, run: Run, encoding: Encoding) {
+        if run.text.len() < self.min_len {
+            return;
+        }
+        if let Some(seen) = &mut self.seen {
+            if !seen.insert((encoding, run.text.clone())) {
+                return;
+            }
+        }
+        self.found.push_back(FoundString { address: run.start, encoding, text: run.text });
+    }
+}
+
+impl Iterator for StringScan<'_> {
+    type Item = FoundString;
+
+    fn next(&mut self) -> Option<FoundString> {
+        loop {
+            if let Some(found) = self.found.pop_front() {
+                return Some(found);
+            }
+            if !self.read_chunk() {
+                return self.found.pop_front();
+            }
+        }
+    }
+}
+
+fn is_printable(byte: u8) -> bool {
+    byte == b'\t' || (0x20..0x7F).contains(&byte)
+}
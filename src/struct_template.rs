@@ -0,0 +1,517 @@
+// Structure layouts described at run time, for decoding the bytes around a known address
+// without declaring a `remote_struct!`. One field per entry, separated by `;` or newlines:
+//
+//     0x00 u64 vtable; 0x10 f32 health; 0x30 ptr->utf16 name; 0x40 utf8[16] tag
+//
+// or the same as TOML:
+//
+//     [[fields]]
+//     offset = 0x10
+//     type = "f32"
+//     name = "health"
+//
+// Types: u8..u64, i8..i64, f32, f64, ptr (the target's pointer width), bytes[N], and inline
+// strings utf8[N] / utf16[N] of N characters, cut at the first NUL. `ptr->T` follows the
+// pointer once and decodes a T there; `ptr->utf8` and `ptr->utf16` read a NUL-terminated
+// string of at most MAX_STRING_LEN characters. Text after `#` is a comment.
+//
+// `size 0x80` (or `size = 0x80` in TOML) declares how big the structure is; no field may
+// reach past it. Without one, `apply` reads at most MAX_SPAN bytes.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::memory::Memory;
+use crate::remote_struct::read_span;
+use crate::wstr;
+
+// Characters read for a string behind a `ptr->utf8` or `ptr->utf16` field.
+pub const MAX_STRING_LEN: usize = 256;
+
+// Bytes `apply` reads for a template that doesn't declare its size.
+pub const MAX_SPAN: usize = 0x10000;
+
+// Raw bytes shown per field in the table; the serialized form has them all.
+const RAW_BYTES_SHOWN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldType {
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Ptr,
+    Bytes(usize),
+    // Inline character buffers of this many characters. None only behind a pointer, where it
+    // means a NUL-terminated string.
+    Utf8(Option<usize>),
+    Utf16(Option<usize>),
+    // `ptr->T`: a pointer-sized field decoded as a T where it points.
+    Deref(Box<FieldType>),
+}
+
+impl FieldType {
+    // Bytes the field takes up in the structure.
+    pub fn size(&self, pointer_size: usize) -> usize {
+        match self {
+            FieldType::U8 | FieldType::I8 => 1,
+            FieldType::U16 | FieldType::I16 => 2,
+            FieldType::U32 | FieldType::I32 | FieldType::F32 => 4,
+            FieldType::U64 | FieldType::I64 | FieldType::F64 => 8,
+            FieldType::Ptr | FieldType::Deref(_) => pointer_size,
+            FieldType::Bytes(len) => *len,
+            FieldType::Utf8(len) => len.unwrap_or(MAX_STRING_LEN),
+            FieldType::Utf16(len) => len.unwrap_or(MAX_STRING_LEN).saturating_mul(2),
+        }
+    }
+}
+
+impl FromStr for FieldType {
+    type Err = io::Error;
+
+    fn from_str(text: &str) -> io::Result<Self> {
+        if let Some(target) = text.strip_prefix("ptr->") {
+            let target = match target {
+                "utf8" => FieldType::Utf8(None),
+                "utf16" => FieldType::Utf16(None),
+                _ => target.parse()?,
+            };
+            if matches!(target, FieldType::Deref(_)) {
+                return Err(invalid(format!("'{}': only one level of indirection is supported", text)));
+            }
+            return Ok(FieldType::Deref(Box::new(target)));
+        }
+
+        if let Some((kind, len)) = text.strip_suffix(']').and_then(|text| text.split_once('[')) {
+            let len = parse_number(len)
+                .filter(|&len| len > 0)
+                .ok_or_else(|| invalid(format!("bad length in '{}'", text)))?;
+            return match kind {
+                "bytes" => Ok(FieldType::Bytes(len)),
+                "utf8" => Ok(FieldType::Utf8(Some(len))),
+                "utf16" => Ok(FieldType::Utf16(Some(len))),
+                _ => Err(invalid(format!("unknown array type '{}'", text))),
+            };
+        }
+
+        Ok(match text {
+            "u8" => FieldType::U8,
+            "u16" => FieldType::U16,
+            "u32" => FieldType::U32,
+            "u64" => FieldType::U64,
+            "i8" => FieldType::I8,
+            "i16" => FieldType::I16,
+            "i32" => FieldType::I32,
+            "i64" => FieldType::I64,
+            "f32" => FieldType::F32,
+            "f64" => FieldType::F64,
+            "ptr" => FieldType::Ptr,
+            _ => return Err(invalid(format!("unknown field type '{}'", text))),
+        })
+    }
+}
+
+// The form `from_str` reads.
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldType::U8 => f.write_str("u8"),
+            FieldType::U16 => f.write_str("u16"),
+            FieldType::U32 => f.write_str("u32"),
+            FieldType::U64 => f.write_str("u64"),
+            FieldType::I8 => f.write_str("i8"),
+            FieldType::I16 => f.write_str("i16"),
+            FieldType::I32 => f.write_str("i32"),
+            FieldType::I64 => f.write_str("i64"),
+            FieldType::F32 => f.write_str("f32"),
+            FieldType::F64 => f.write_str("f64"),
+            FieldType::Ptr => f.write_str("ptr"),
+            FieldType::Bytes(len) => write!(f, "bytes[{}]", len),
+            FieldType::Utf8(Some(len)) => write!(f, "utf8[{}]", len),
+            FieldType::Utf8(None) => f.write_str("utf8"),
+            FieldType::Utf16(Some(len)) => write!(f, "utf16[{}]", len),
+            FieldType::Utf16(None) => f.write_str("utf16"),
+            FieldType::Deref(target) => write!(f, "ptr->{}", target),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateField {
+    pub offset: usize,
+    pub ty: FieldType,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StructTemplate {
+    // In ascending offset order. Fields may overlap, e.g. to view a union both ways.
+    pub fields: Vec<TemplateField>,
+    // Bytes the structure takes up, if the template declares it.
+    pub size: Option<usize>,
+}
+
+// The TOML form, before the type strings are parsed.
+#[derive(Deserialize)]
+struct TomlTemplate {
+    size: Option<usize>,
+    fields: Vec<TomlField>,
+}
+
+#[derive(Deserialize)]
+struct TomlField {
+    offset: usize,
+    r#type: String,
+    name: String,
+}
+
+impl StructTemplate {
+    // Reads the TOML form from `.toml` files and the text form from anything else.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("toml")) {
+            Self::from_toml(&text)
+        } else {
+            Self::parse(&text)
+        }
+    }
+
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut fields = Vec::new();
+        let mut size = None;
+        for entry in text.lines().flat_map(|line| line.split('#').next().unwrap_or_default().split(';')) {
+            let words: Vec<&str> = entry.split_whitespace().collect();
+            match words.as_slice() {
+                [] => continue,
+                ["size", bytes] => {
+                    size = Some(parse_number(bytes).ok_or_else(|| invalid(format!("bad size '{}'", bytes)))?);
+                }
+                [offset, ty, name] => fields.push(TemplateField {
+                    offset: parse_number(offset).ok_or_else(|| invalid(format!("bad offset '{}'", offset)))?,
+                    ty: ty.parse()?,
+                    name: name.to_string(),
+                }),
+                _ => return Err(invalid(format!("expected '<offset> <type> <name>', got '{}'", entry.trim()))),
+            }
+        }
+        Ok(Self { size, ..Self::new(fields)? })
+    }
+
+    pub fn from_toml(text: &str) -> io::Result<Self> {
+        let template: TomlTemplate = toml::from_str(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let fields = template
+            .fields
+            .into_iter()
+            .map(|field| Ok(TemplateField { offset: field.offset, ty: field.r#type.parse()?, name: field.name }))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self { size: template.size, ..Self::new(fields)? })
+    }
+
+    // Sorts the fields by offset; fails on an empty template.
+    pub fn new(mut fields: Vec<TemplateField>) -> io::Result<Self> {
+        if fields.is_empty() {
+            return Err(invalid("The template has no fields".to_string()));
+        }
+        fields.sort_by_key(|field| field.offset);
+        Ok(StructTemplate { fields, size: None })
+    }
+
+    pub fn with_size(mut self, size: usize) -> Self {
+        self.size = Some(size);
+        self
+    }
+
+    // Reads the bytes from the first field to the end of the last one in one go, then
+    // decodes each field; pointers are followed with a read each. Fails if a field reaches
+    // past the declared size (or MAX_SPAN) or the span itself can't be read, otherwise
+    // unreadable pointees are reported per field.
+    pub fn apply(&self, memory: &Memory, base: usize) -> io::Result<DecodedStruct> {
+        let pointer_size = memory.pointer_size()?;
+        let start = self.fields[0].offset;
+        let limit = self.size.unwrap_or(MAX_SPAN);
+        let mut end = start;
+        for field in &self.fields {
+            let field_end = field.offset.checked_add(field.ty.size(pointer_size));
+            let Some(field_end) = field_end.filter(|&field_end| field_end <= limit) else {
+                return Err(match self.size {
+                    Some(size) => invalid(format!("'{}' reaches past the template's size, 0x{:X}", field.name, size)),
+                    None => invalid(format!(
+                        "'{}' reaches past 0x{:X}; declare the template's size to read further",
+                        field.name, MAX_SPAN
+                    )),
+                });
+            };
+            end = end.max(field_end);
+        }
+        let bytes = read_span(memory, base, start, end)?;
+
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| {
+                let at = field.offset - start;
+                let raw = bytes[at..at + field.ty.size(pointer_size)].to_vec();
+                let value = match &field.ty {
+                    FieldType::Deref(target) => {
+                        let address = read_uint(&raw) as usize;
+                        let target = Box::new(deref(memory, address, target, pointer_size));
+                        DecodedValue::Pointer { address, target }
+                    }
+                    ty => decode(ty, &raw),
+                };
+                DecodedField { name: field.name.clone(), offset: field.offset, ty: field.ty.to_string(), raw, value }
+            })
+            .collect();
+
+        Ok(DecodedStruct { base, fields })
+    }
+}
+
+// Decodes a single value of type `ty` at `address`. Fails where `apply` would report the
+// value unreadable.
+pub(crate) fn read_field(memory: &Memory, address: usize, ty: &FieldType) -> io::Result<DecodedValue> {
+    let field = TemplateField { offset: 0, ty: ty.clone(), name: String::new() };
+    let decoded = StructTemplate::new(vec![field])?.apply(memory, address)?;
+    match decoded.fields.into_iter().next().map(|field| field.value) {
+        Some(DecodedValue::Unreadable(message)) => Err(io::Error::other(message)),
+        Some(value) => Ok(value),
+        None => unreachable!("the template has one field"),
+    }
+}
+
+// The text form `parse` reads, one field per line.
+impl fmt::Display for StructTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(size) = self.size {
+            writeln!(f, "size 0x{:X}", size)?;
+        }
+        for field in &self.fields {
+            writeln!(f, "0x{:02X} {} {}", field.offset, field.ty, field.name)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum DecodedValue {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+    Text(String),
+    Bytes(Vec<u8>),
+    // A `ptr->` field: where it points and what was decoded there.
+    Pointer { address: usize, target: Box<DecodedValue> },
+    // The pointee of a `ptr->` field couldn't be read.
+    Unreadable(String),
+}
+
+impl fmt::Display for DecodedValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodedValue::Unsigned(value) => write!(f, "{} (0x{:X})", value, value),
+            DecodedValue::Signed(value) => write!(f, "{}", value),
+            DecodedValue::Float(value) => write!(f, "{}", value),
+            DecodedValue::Text(text) => write!(f, "{:?}", text),
+            DecodedValue::Bytes(bytes) => f.write_str(&hex(bytes)),
+            DecodedValue::Pointer { address, target } => write!(f, "0x{:X} -> {}", address, target),
+            DecodedValue::Unreadable(reason) => write!(f, "<unreadable: {}>", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedField {
+    pub name: String,
+    pub offset: usize,
+    // As written in the template, e.g. "ptr->utf16".
+    pub ty: String,
+    pub raw: Vec<u8>,
+    pub value: DecodedValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedStruct {
+    pub base: usize,
+    pub fields: Vec<DecodedField>,
+}
+
+impl DecodedStruct {
+    pub fn field(&self, name: &str) -> Option<&DecodedField> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+// One line per field, in aligned columns: offset, type, name, raw bytes and value.
+impl fmt::Display for DecodedStruct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows: Vec<[String; 5]> = self
+            .fields
+            .iter()
+            .map(|field| {
+                let mut raw = hex(&field.raw[..field.raw.len().min(RAW_BYTES_SHOWN)]);
+                if field.raw.len() > RAW_BYTES_SHOWN {
+                    raw.push_str(" ..");
+                }
+                [format!("+0x{:02X}", field.offset), field.ty.clone(), field.name.clone(), raw, field.value.to_string()]
+            })
+            .collect();
+
+        let mut widths = [0; 4];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        writeln!(f, "0x{:X}:", self.base)?;
+        for [offset, ty, name, raw, value] in &rows {
+            writeln!(
+                f,
+                "  {:<w0$}  {:<w1$}  {:<w2$}  {:<w3$}  {}",
+                offset,
+                ty,
+                name,
+                raw,
+                value,
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2],
+                w3 = widths[3]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn decode(ty: &FieldType, raw: &[u8]) -> DecodedValue {
+    match ty {
+        FieldType::U8 | FieldType::U16 | FieldType::U32 | FieldType::U64 | FieldType::Ptr => {
+            DecodedValue::Unsigned(read_uint(raw))
+        }
+        FieldType::I8 => DecodedValue::Signed(raw[0] as i8 as i64),
+        FieldType::I16 => DecodedValue::Signed(i16::from_le_bytes([raw[0], raw[1]]) as i64),
+        FieldType::I32 => DecodedValue::Signed(i32::from_le_bytes(raw.try_into().unwrap()) as i64),
+        FieldType::I64 => DecodedValue::Signed(i64::from_le_bytes(raw.try_into().unwrap())),
+        FieldType::F32 => DecodedValue::Float(f32::from_le_bytes(raw.try_into().unwrap()) as f64),
+        FieldType::F64 => DecodedValue::Float(f64::from_le_bytes(raw.try_into().unwrap())),
+        FieldType::Bytes(_) => DecodedValue::Bytes(raw.to_vec()),
+        FieldType::Utf8(_) => {
+            let end = raw.iter().position(|&byte| byte == 0).unwrap_or(raw.len());
+            DecodedValue::Text(String::from_utf8_lossy(&raw[..end]).into_owned())
+        }
+        FieldType::Utf16(_) => {
+            DecodedValue::Text(wstr::from_wide_nul(&wstr::units_from_bytes(raw)))
+        }
+        FieldType::Deref(_) => unreachable!("pointers are followed by `apply`"),
+    }
+}
+
+// `text` as the bytes of a `ty` field, the reverse of `decode`: integers in decimal or 0x hex,
+// floats in decimal, bytes as hex pairs and inline strings padded with NULs to the field's
+// length. Fields behind pointers, and strings without a length, have no bytes of their own.
+pub fn encode(ty: &FieldType, text: &str, pointer_size: usize) -> io::Result<Vec<u8>> {
+    let text = text.trim();
+    let size = ty.size(pointer_size);
+    let out_of_range = || invalid(format!("'{}' doesn't fit into a {} field", text, ty));
+    let mut bytes = match ty {
+        FieldType::U8 | FieldType::U16 | FieldType::U32 | FieldType::U64 | FieldType::Ptr => {
+            let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                Some(hex) => u64::from_str_radix(hex, 16),
+                None => text.parse(),
+            }
+            .map_err(|_| invalid(format!("'{}' is not a {} value", text, ty)))?;
+            if size < 8 && value >> (size * 8) != 0 {
+                return Err(out_of_range());
+            }
+            value.to_le_bytes()[..size].to_vec()
+        }
+        FieldType::I8 | FieldType::I16 | FieldType::I32 | FieldType::I64 => {
+            let value: i64 = text.parse().map_err(|_| invalid(format!("'{}' is not a {} value", text, ty)))?;
+            let bits = size as u32 * 8;
+            if bits < 64 && (value < -(1 << (bits - 1)) || value >= 1 << (bits - 1)) {
+                return Err(out_of_range());
+            }
+            value.to_le_bytes()[..size].to_vec()
+        }
+        FieldType::F32 => {
+            let value: f32 = text.parse().map_err(|_| invalid(format!("'{}' is not an f32 value", text)))?;
+            value.to_le_bytes().to_vec()
+        }
+        FieldType::F64 => {
+            let value: f64 = text.parse().map_err(|_| invalid(format!("'{}' is not an f64 value", text)))?;
+            value.to_le_bytes().to_vec()
+        }
+        FieldType::Bytes(_) => {
+            let bytes = text
+                .split_whitespace()
+                .map(|byte| u8::from_str_radix(byte, 16).ok().filter(|_| byte.len() == 2))
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| invalid(format!("'{}' is not hex bytes", text)))?;
+            if bytes.len() != size {
+                return Err(invalid(format!("'{}' is not {} bytes", text, size)));
+            }
+            bytes
+        }
+        FieldType::Utf8(Some(_)) => text.as_bytes().to_vec(),
+        FieldType::Utf16(Some(_)) => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+        FieldType::Utf8(None) | FieldType::Utf16(None) | FieldType::Deref(_) => {
+            return Err(invalid(format!("a {} field can't be written as a value", ty)));
+        }
+    };
+    if bytes.len() > size {
+        return Err(out_of_range());
+    }
+    bytes.resize(size, 0);
+    Ok(bytes)
+}
+
+// Decodes a `target` at `address`. NUL-terminated strings may end before MAX_STRING_LEN
+// characters if the rest can't be read.
+fn deref(memory: &Memory, address: usize, target: &FieldType, pointer_size: usize) -> DecodedValue {
+    if address == 0 {
+        return DecodedValue::Unreadable("null pointer".to_string());
+    }
+    let size = target.size(pointer_size);
+    match memory.read_bytes(address, size) {
+        Ok(bytes) if bytes.len() == size => decode(target, &bytes),
+        Ok(bytes) if matches!(target, FieldType::Utf8(None) | FieldType::Utf16(None)) && !bytes.is_empty() => {
+            decode(target, &bytes[..bytes.len() / 2 * 2])
+        }
+        Ok(bytes) => DecodedValue::Unreadable(format!("only 0x{:X} of 0x{:X} bytes readable", bytes.len(), size)),
+        Err(e) => DecodedValue::Unreadable(e.to_string()),
+    }
+}
+
+// Little-endian unsigned integer of up to 8 bytes.
+fn read_uint(raw: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes[..raw.len()].copy_from_slice(raw);
+    u64::from_le_bytes(bytes)
+}
+
+fn parse_number(text: &str) -> Option<usize> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<_>>().join(" ")
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,98 @@
+// The Win32 and NT declarations the crate calls. They come from windows-sys, whose struct
+// layouts and constants are generated from the Windows metadata for each target, so
+// MEMORY_BASIC_INFORMATION has the `PartitionId` field on 64-bit targets and not on i686
+// without anything here knowing. Everything here is crate-private; callers use it like any
+// other import:
+//
+//     use crate::sys::{VirtualQueryEx, MEMORY_BASIC_INFORMATION};
+//
+// Declarations only an optional feature calls are re-exported with that feature. The few
+// constants whose windows-sys type doesn't fit how they're compared stay where they're used,
+// and so do the NT structs and information classes windows-sys doesn't declare.
+
+pub(crate) use windows_sys::Wdk::Foundation::{NtQueryObject, ObjectTypeInformation};
+pub(crate) use windows_sys::Wdk::System::SystemInformation::NtQuerySystemInformation;
+pub(crate) use windows_sys::Wdk::System::Threading::{
+    NtQueryInformationProcess, NtQueryInformationThread, ProcessBasicInformation, ProcessCommandLineInformation,
+    ProcessProtectionInformation, ThreadBasicInformation,
+};
+pub(crate) use windows_sys::Win32::{
+    Foundation::{
+        CloseHandle, DuplicateHandle, GetLastError, DUPLICATE_SAME_ACCESS, ERROR_NOT_ALL_ASSIGNED, LUID,
+        STATUS_INFO_LENGTH_MISMATCH, UNICODE_STRING, WAIT_ABANDONED, WAIT_OBJECT_0, WAIT_TIMEOUT,
+    },
+    Security::{
+        AdjustTokenPrivileges, GetTokenInformation, LookupAccountSidW, LookupPrivilegeValueW, TokenElevation,
+        TokenPrivileges, TokenUser, LUID_AND_ATTRIBUTES, SE_PRIVILEGE_ENABLED, TOKEN_ADJUST_PRIVILEGES,
+        TOKEN_PRIVILEGES, TOKEN_QUERY,
+    },
+    Storage::FileSystem::{QueryDosDeviceW, SYNCHRONIZE},
+    System::Console::{SetConsoleCtrlHandler, CTRL_BREAK_EVENT, CTRL_C_EVENT},
+    System::Diagnostics::Debug::{
+        FlushInstructionCache, GetThreadContext, ReadProcessMemory, WriteProcessMemory, CONTEXT,
+    },
+    System::Diagnostics::ToolHelp::{
+        CreateToolhelp32Snapshot, Module32FirstW, Module32NextW, Process32FirstW, Process32NextW, Thread32First,
+        Thread32Next, MODULEENTRY32W, PROCESSENTRY32W, TH32CS_SNAPMODULE, TH32CS_SNAPMODULE32, TH32CS_SNAPPROCESS,
+        TH32CS_SNAPTHREAD, THREADENTRY32,
+    },
+    System::Memory::{
+        VirtualAllocEx, VirtualFreeEx, VirtualProtectEx, VirtualQueryEx, MEMORY_BASIC_INFORMATION, MEM_COMMIT,
+        MEM_IMAGE, MEM_MAPPED, MEM_PRIVATE, MEM_RELEASE, MEM_RESERVE, PAGE_EXECUTE, PAGE_EXECUTE_READ,
+        PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_NOACCESS, PAGE_READONLY, PAGE_READWRITE,
+        PAGE_WRITECOPY,
+    },
+    System::ProcessStatus::{
+        K32GetMappedFileNameW, K32QueryWorkingSetEx, PSAPI_WORKING_SET_EX_BLOCK, PSAPI_WORKING_SET_EX_INFORMATION,
+    },
+    System::RemoteDesktop::ProcessIdToSessionId,
+    System::Threading::{
+        CreateMutexW, CreateProcessW, GetCurrentProcess, GetCurrentProcessId, GetCurrentThread, GetCurrentThreadId,
+        GetExitCodeProcess, GetThreadPriority, IsWow64Process, OpenProcess, OpenProcessToken, OpenThread,
+        QueryFullProcessImageNameW, ReleaseMutex, ResumeThread, SetThreadPriority, SuspendThread, TerminateProcess,
+        WaitForSingleObject, CREATE_SUSPENDED, PROCESS_ALL_ACCESS, PROCESS_CREATE_THREAD, PROCESS_DUP_HANDLE,
+        PROCESS_INFORMATION, PROCESS_QUERY_INFORMATION, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_TERMINATE,
+        PROCESS_VM_OPERATION, PROCESS_VM_READ, PROCESS_VM_WRITE, STARTUPINFOW, THREAD_GET_CONTEXT,
+        THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_NORMAL, THREAD_QUERY_INFORMATION, THREAD_SUSPEND_RESUME,
+    },
+};
+
+// The register layouts `Memory::thread_context` reads.
+#[cfg(target_arch = "x86_64")]
+pub(crate) use windows_sys::Win32::System::Diagnostics::Debug::{
+    Wow64GetThreadContext, CONTEXT_CONTROL_AMD64, CONTEXT_INTEGER_AMD64, WOW64_CONTEXT, WOW64_CONTEXT_CONTROL,
+    WOW64_CONTEXT_INTEGER,
+};
+#[cfg(target_arch = "x86")]
+pub(crate) use windows_sys::Win32::System::Diagnostics::Debug::{CONTEXT_CONTROL_X86, CONTEXT_INTEGER_X86};
+
+#[cfg(feature = "inject")]
+pub(crate) use windows_sys::Wdk::System::Memory::{NtMapViewOfSection, NtUnmapViewOfSection, ViewUnmap};
+#[cfg(feature = "last-error-checks")]
+pub(crate) use windows_sys::Win32::Foundation::SetLastError;
+#[cfg(any(feature = "hooks", feature = "sandbox"))]
+pub(crate) use windows_sys::Win32::System::Memory::VirtualProtect;
+#[cfg(feature = "sandbox")]
+pub(crate) use windows_sys::Win32::{
+    Foundation::FreeLibrary,
+    System::LibraryLoader::LoadLibraryW,
+    System::Memory::{VirtualAlloc, VirtualFree},
+};
+#[cfg(feature = "debugger")]
+pub(crate) use windows_sys::Win32::{
+    Foundation::{DBG_CONTINUE, DBG_EXCEPTION_NOT_HANDLED, EXCEPTION_BREAKPOINT, STATUS_WX86_BREAKPOINT},
+    Storage::FileSystem::GetFinalPathNameByHandleW,
+    System::Diagnostics::Debug::{
+        ContinueDebugEvent, DebugActiveProcess, DebugActiveProcessStop, DebugSetProcessKillOnExit, WaitForDebugEvent,
+        CREATE_PROCESS_DEBUG_EVENT, DEBUG_EVENT, EXCEPTION_DEBUG_EVENT, EXIT_PROCESS_DEBUG_EVENT, LOAD_DLL_DEBUG_EVENT,
+        UNLOAD_DLL_DEBUG_EVENT,
+    },
+};
+#[cfg(feature = "inject")]
+pub(crate) use windows_sys::Win32::{
+    Foundation::{INVALID_HANDLE_VALUE, WAIT_FAILED},
+    System::Memory::{
+        CreateFileMappingW, MapViewOfFile, UnmapViewOfFile, FILE_MAP_ALL_ACCESS, MEMORY_MAPPED_VIEW_ADDRESS,
+    },
+    System::Threading::{CreateRemoteThread, GetExitCodeThread, LPTHREAD_START_ROUTINE},
+};
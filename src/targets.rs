@@ -0,0 +1,170 @@
+// Several attached processes under names, for comparing the same data across instances of a
+// program (modded against vanilla, two versions, ...):
+//
+//     let mut session = Session::new();
+//     session.attach("modded", pid_a)?;
+//     session.attach("vanilla", pid_b)?;
+//     let diff = session.compare_range("modded", "vanilla", "game.exe", 0x1000..0x2000)?;
+//
+// Each target is a full `Memory` with its own cached regions, modules and default scan
+// options; broadcast operations run on every target in name order.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::ops::Range;
+
+use log::debug;
+
+use crate::filter::RegionFilter;
+use crate::memory::Memory;
+use crate::pattern::Pattern;
+use crate::scan::ScanOptions;
+use crate::wstr;
+
+#[derive(Default)]
+pub struct Session {
+    targets: BTreeMap<String, Memory>,
+}
+
+// Where two targets differ inside a module, as offsets from each target's module base.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeDiff {
+    pub module: String,
+    pub range: Range<usize>,
+    // Runs of differing bytes, in ascending order.
+    pub differences: Vec<Range<usize>>,
+}
+
+impl RangeDiff {
+    pub fn is_identical(&self) -> bool {
+        self.differences.is_empty()
+    }
+
+    pub fn differing_bytes(&self) -> usize {
+        self.differences.iter().map(|run| run.len()).sum()
+    }
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Session::default()
+    }
+
+    // Opens `pid` and caches its regions and modules. Replaces any target of the same name.
+    pub fn attach(&mut self, name: &str, pid: u32) -> io::Result<&mut Memory> {
+        let mut memory = Memory::open(pid)?;
+        memory.scan_memory()?;
+        if let Err(e) = memory.scan_modules() {
+            debug!("module list of {} (pid {}) unavailable: {}", name, pid, e);
+        }
+        Ok(self.insert(name, memory))
+    }
+
+    // Adds an already opened target.
+    pub fn insert(&mut self, name: &str, memory: Memory) -> &mut Memory {
+        self.targets.insert(name.to_string(), memory);
+        self.targets.get_mut(name).unwrap()
+    }
+
+    pub fn detach(&mut self, name: &str) -> Option<Memory> {
+        self.targets.remove(name)
+    }
+
+    pub fn target(&self, name: &str) -> io::Result<&Memory> {
+        self.targets.get(name).ok_or_else(|| not_attached(name))
+    }
+
+    pub fn target_mut(&mut self, name: &str) -> io::Result<&mut Memory> {
+        self.targets.get_mut(name).ok_or_else(|| not_attached(name))
+    }
+
+    // Target names in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.targets.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    // The options the target's own scans use; see `Memory::set_default_scan_options`.
+    pub fn set_scan_options(&mut self, name: &str, options: ScanOptions) -> io::Result<()> {
+        self.target_mut(name)?.set_default_scan_options(options);
+        Ok(())
+    }
+
+    // `find_pattern_all` on every target, with each target's default scan options. One
+    // target failing doesn't stop the others.
+    pub fn find_pattern_all_targets(
+        &self,
+        pattern: &Pattern,
+        filter: &RegionFilter,
+    ) -> BTreeMap<String, io::Result<Vec<usize>>> {
+        self.targets.iter().map(|(name, memory)| (name.clone(), memory.find_pattern_all(pattern, filter))).collect()
+    }
+
+    // Compares `range` (offsets from the module base) of `module` in targets `a` and `b`,
+    // each at its own base.
+    pub fn compare_range(&self, a: &str, b: &str, module: &str, range: Range<usize>) -> io::Result<RangeDiff> {
+        let bytes_a = read_module_range(self.target(a)?, a, module, &range)?;
+        let bytes_b = read_module_range(self.target(b)?, b, module, &range)?;
+
+        let mut differences: Vec<Range<usize>> = Vec::new();
+        for (index, (x, y)) in bytes_a.iter().zip(&bytes_b).enumerate() {
+            if x == y {
+                continue;
+            }
+            let offset = range.start + index;
+            match differences.last_mut() {
+                Some(run) if run.end == offset => run.end += 1,
+                _ => differences.push(offset..offset + 1),
+            }
+        }
+
+        Ok(RangeDiff { module: module.to_string(), range, differences })
+    }
+}
+
+// Module-relative `range` of `module` in `memory`, read in full or not at all.
+fn read_module_range(memory: &Memory, name: &str, module: &str, range: &Range<usize>) -> io::Result<Vec<u8>> {
+    let loaded = memory
+        .modules_or_live()?
+        .iter()
+        .find(|loaded| wstr::eq_ignore_case(&loaded.name, module))
+        .cloned()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} is not loaded in {}", module, name)))?;
+    if range.start > range.end || range.end > loaded.size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "0x{:X}..0x{:X} is outside {} (0x{:X} bytes) in {}",
+                range.start, range.end, module, loaded.size, name
+            ),
+        ));
+    }
+
+    let address = loaded.base_address + range.start;
+    let bytes = memory.read_bytes(address, range.len())?;
+    if bytes.len() < range.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "Only 0x{:X} of 0x{:X} bytes at {}+0x{:X} are readable in {}",
+                bytes.len(),
+                range.len(),
+                module,
+                range.start,
+                name
+            ),
+        ));
+    }
+    Ok(bytes)
+}
+
+fn not_attached(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("No target named '{}' is attached", name))
+}
@@ -0,0 +1,240 @@
+// The target's threads and where they are executing. Each thread is suspended for as long as
+// it takes to read its context, so sampling often slows the target down a little. Where a
+// thread's stack lies needs no suspending: `thread_stacks` reads it from the thread's TEB.
+
+use std::ffi::c_void;
+use std::io;
+use std::mem;
+
+use log::debug;
+
+use crate::capabilities::Capabilities;
+use crate::memory::Memory;
+use crate::sys::{
+    CloseHandle, CreateToolhelp32Snapshot, GetCurrentThreadId, GetThreadContext, NtQueryInformationThread, OpenThread,
+    ResumeThread, SuspendThread, Thread32First, Thread32Next, ThreadBasicInformation, CONTEXT, TH32CS_SNAPTHREAD,
+    THREADENTRY32, THREAD_GET_CONTEXT, THREAD_QUERY_INFORMATION, THREAD_SUSPEND_RESUME,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::sys::{
+    Wow64GetThreadContext, CONTEXT_CONTROL_AMD64, CONTEXT_INTEGER_AMD64, WOW64_CONTEXT, WOW64_CONTEXT_CONTROL,
+    WOW64_CONTEXT_INTEGER,
+};
+#[cfg(target_arch = "x86")]
+use crate::sys::{CONTEXT_CONTROL_X86, CONTEXT_INTEGER_X86};
+use crate::win_error::win_call;
+
+const SUSPEND_FAILED: u32 = u32::MAX;
+
+// StackBase and StackLimit in the NT_TIB that starts each TEB, for 64-bit and 32-bit TEBs. A
+// WOW64 thread's 32-bit TEB follows its 64-bit one.
+const TIB64_STACK_BASE: usize = 0x08;
+const TIB64_STACK_LIMIT: usize = 0x10;
+const TIB32_STACK_BASE: usize = 0x04;
+const TIB32_STACK_LIMIT: usize = 0x08;
+const WOW64_TEB32_OFFSET: usize = 0x2000;
+
+// THREAD_BASIC_INFORMATION, which windows-sys doesn't declare.
+#[repr(C)]
+struct ThreadBasicInfo {
+    exit_status: i32,
+    teb_base_address: usize,
+    client_id: [usize; 2],
+    affinity_mask: usize,
+    priority: i32,
+    base_priority: i32,
+}
+
+// GetThreadContext wants CONTEXT 16-byte aligned, which the windows-sys struct doesn't ask for.
+#[repr(C, align(16))]
+struct Aligned<T>(T);
+
+// The registers of one thread at the moment it was sampled. For a 32-bit target these are
+// EIP, ESP and EBP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ThreadContext {
+    pub thread_id: u32,
+    pub instruction_pointer: usize,
+    pub stack_pointer: usize,
+    pub frame_pointer: usize,
+}
+
+// Where one thread's stack is committed, as its TEB has it: from `limit` up to `base`, which
+// the stack grows down from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ThreadStack {
+    pub thread_id: u32,
+    pub limit: usize,
+    pub base: usize,
+}
+
+impl Memory {
+    pub fn thread_ids(&self) -> io::Result<Vec<u32>> {
+        let snapshot =
+            win_call!(unsafe { CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0) }, "Failed to create thread snapshot")?;
+
+        let mut ids = Vec::new();
+        let mut entry: THREADENTRY32 = unsafe { mem::zeroed() };
+        entry.dwSize = mem::size_of::<THREADENTRY32>() as u32;
+        // The snapshot lists the threads of every process.
+        let mut more = unsafe { Thread32First(snapshot, &mut entry) } != 0;
+        while more {
+            if entry.th32OwnerProcessID == self.process_id() {
+                ids.push(entry.th32ThreadID);
+            }
+            more = unsafe { Thread32Next(snapshot, &mut entry) } != 0;
+        }
+        unsafe { CloseHandle(snapshot) };
+
+        Ok(ids)
+    }
+
+    // Whether one of the target's threads opens with the rights `thread_context` needs, for
+    // `Capabilities::probe`. Threads of one process share their security descriptor.
+    pub(crate) fn can_suspend_threads(&self) -> bool {
+        let Some(thread_id) = self.thread_ids().ok().and_then(|ids| ids.first().copied()) else {
+            return false;
+        };
+        let access = THREAD_SUSPEND_RESUME | THREAD_GET_CONTEXT | THREAD_QUERY_INFORMATION;
+        let thread = unsafe { OpenThread(access, 0, thread_id) };
+        if thread.is_null() {
+            return false;
+        }
+        unsafe { CloseHandle(thread) };
+        true
+    }
+
+    // Suspends the thread, reads its registers and resumes it. Don't call this for the calling
+    // thread: it would never resume.
+    pub fn thread_context(&self, thread_id: u32) -> io::Result<ThreadContext> {
+        self.require(Capabilities::SUSPEND)?;
+        let wow64 = self.pointer_size()? == 4 && mem::size_of::<usize>() == 8;
+        let access = THREAD_SUSPEND_RESUME | THREAD_GET_CONTEXT | THREAD_QUERY_INFORMATION;
+        let thread = win_call!(unsafe { OpenThread(access, 0, thread_id) }, "Failed to open thread {}", thread_id)?;
+
+        let suspended = win_call!(
+            unsafe { SuspendThread(thread) },
+            failed: |count| *count == SUSPEND_FAILED,
+            "SuspendThread failed"
+        );
+        // Resumed when `_resume` drops, before the handle is closed.
+        let result = suspended.and_then(|_| {
+            let _resume = Resume(thread);
+            read_context(thread, wow64)
+        });
+        unsafe { CloseHandle(thread) };
+
+        let (instruction_pointer, stack_pointer, frame_pointer) = result.map_err(|e| {
+            io::Error::new(e.kind(), format!("Failed to read the context of thread {}: {}", thread_id, e))
+        })?;
+        Ok(ThreadContext { thread_id, instruction_pointer, stack_pointer, frame_pointer })
+    }
+
+    // One context per thread that could be sampled. Threads that exited since they were
+    // listed, or that can't be opened, are left out, and so is the calling thread when the
+    // target is this process.
+    pub fn thread_contexts(&self) -> io::Result<Vec<ThreadContext>> {
+        self.require(Capabilities::SUSPEND)?;
+        let current = unsafe { GetCurrentThreadId() };
+        let mut contexts = Vec::new();
+        for thread_id in self.thread_ids()?.into_iter().filter(|&id| id != current) {
+            match self.thread_context(thread_id) {
+                Ok(context) => contexts.push(context),
+                Err(e) => debug!("{}", e),
+            }
+        }
+        Ok(contexts)
+    }
+
+    // The stack bounds of one thread, read from its TEB while it runs on.
+    pub fn thread_stack(&self, thread_id: u32) -> io::Result<ThreadStack> {
+        let wow64 = self.pointer_size()? == 4 && mem::size_of::<usize>() == 8;
+        let thread = win_call!(
+            unsafe { OpenThread(THREAD_QUERY_INFORMATION, 0, thread_id) },
+            "Failed to open thread {}",
+            thread_id
+        )?;
+        let mut info: ThreadBasicInfo = unsafe { mem::zeroed() };
+        let status = unsafe {
+            NtQueryInformationThread(
+                thread,
+                ThreadBasicInformation,
+                &mut info as *mut ThreadBasicInfo as *mut c_void,
+                mem::size_of::<ThreadBasicInfo>() as u32,
+                std::ptr::null_mut(),
+            )
+        };
+        unsafe { CloseHandle(thread) };
+        if status < 0 {
+            return Err(io::Error::other(format!(
+                "Failed to query thread {}: NTSTATUS 0x{:08X}",
+                thread_id, status as u32
+            )));
+        }
+
+        let teb = info.teb_base_address;
+        let (base, limit) = if wow64 {
+            let teb = teb + WOW64_TEB32_OFFSET;
+            let base: u32 = self.read_memory(teb + TIB32_STACK_BASE)?;
+            let limit: u32 = self.read_memory(teb + TIB32_STACK_LIMIT)?;
+            (base as usize, limit as usize)
+        } else if mem::size_of::<usize>() == 8 {
+            (self.read_memory(teb + TIB64_STACK_BASE)?, self.read_memory(teb + TIB64_STACK_LIMIT)?)
+        } else {
+            (self.read_memory(teb + TIB32_STACK_BASE)?, self.read_memory(teb + TIB32_STACK_LIMIT)?)
+        };
+        if limit >= base {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Thread {} has no stack in its TEB (0x{:X}-0x{:X})", thread_id, limit, base),
+            ));
+        }
+        Ok(ThreadStack { thread_id, limit, base })
+    }
+
+    // One stack per thread whose TEB could be read; the others are left out.
+    pub fn thread_stacks(&self) -> io::Result<Vec<ThreadStack>> {
+        let mut stacks = Vec::new();
+        for thread_id in self.thread_ids()? {
+            match self.thread_stack(thread_id) {
+                Ok(stack) => stacks.push(stack),
+                Err(e) => debug!("{}", e),
+            }
+        }
+        Ok(stacks)
+    }
+}
+
+// Resumes a thread this module suspended when dropped, so that the thread runs again even if
+// reading its context panics.
+struct Resume(*mut c_void);
+
+impl Drop for Resume {
+    fn drop(&mut self) {
+        unsafe { ResumeThread(self.0) };
+    }
+}
+
+// (instruction pointer, stack pointer, frame pointer) of a suspended thread.
+#[cfg(target_arch = "x86_64")]
+fn read_context(thread: *mut c_void, wow64: bool) -> io::Result<(usize, usize, usize)> {
+    if wow64 {
+        let mut context: WOW64_CONTEXT = unsafe { mem::zeroed() };
+        context.ContextFlags = WOW64_CONTEXT_CONTROL | WOW64_CONTEXT_INTEGER;
+        win_call!(unsafe { Wow64GetThreadContext(thread, &mut context) }, "GetThreadContext failed")?;
+        return Ok((context.Eip as usize, context.Esp as usize, context.Ebp as usize));
+    }
+    let mut context: Aligned<CONTEXT> = unsafe { mem::zeroed() };
+    context.0.ContextFlags = CONTEXT_CONTROL_AMD64 | CONTEXT_INTEGER_AMD64;
+    win_call!(unsafe { GetThreadContext(thread, &mut context.0) }, "GetThreadContext failed")?;
+    Ok((context.0.Rip as usize, context.0.Rsp as usize, context.0.Rbp as usize))
+}
+
+// `wow64` is never set here: only a 64-bit process reads contexts through WOW64.
+#[cfg(target_arch = "x86")]
+fn read_context(thread: *mut c_void, _wow64: bool) -> io::Result<(usize, usize, usize)> {
+    let mut context: Aligned<CONTEXT> = unsafe { mem::zeroed() };
+    context.0.ContextFlags = CONTEXT_CONTROL_X86 | CONTEXT_INTEGER_X86;
+    win_call!(unsafe { GetThreadContext(thread, &mut context.0) }, "GetThreadContext failed")?;
+    Ok((context.0.Eip as usize, context.0.Esp as usize, context.0.Ebp as usize))
+}
@@ -0,0 +1,280 @@
+// Rate limits for bulk reads. Background monitoring and big scans can issue enough
+// ReadProcessMemory calls to cost the target frames, so a `Memory` can carry a `Throttle`,
+// a token bucket on bytes and calls per second that every bulk read waits on: the chunks of
+// scans, readers and monitors. Single reads such as `read_memory`, `read_bytes` and the
+// reads of disassembly or bit fields are not throttled.
+//
+//     memory.set_throttle(Some(Throttle::new().bytes_per_sec(64 << 20).calls_per_sec(2000)));
+
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+
+use crate::memory::Memory;
+use crate::sys::{
+    GetCurrentThread, GetThreadPriority, SetThreadPriority, THREAD_PRIORITY_BELOW_NORMAL, THREAD_PRIORITY_NORMAL,
+};
+use crate::win_error::win_call;
+
+// What GetThreadPriority returns on failure; windows-sys has it as a u32.
+const THREAD_PRIORITY_ERROR_RETURN: i32 = i32::MAX;
+
+// What a throttle has let through since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ThrottleStats {
+    pub bytes: u64,
+    pub calls: u64,
+    // Total time readers spent waiting for budget.
+    pub waited: Duration,
+}
+
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_sec: Option<u64>,
+    calls_per_sec: Option<u64>,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    // Tokens may go negative: a read larger than the bucket still goes through, and the debt
+    // is paid off by waiting before the next one.
+    bytes: f64,
+    calls: f64,
+    refilled: Option<Instant>,
+    stats: ThrottleStats,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Throttle::new()
+    }
+}
+
+impl Throttle {
+    // Without limits, so it only counts.
+    pub fn new() -> Self {
+        Throttle {
+            bytes_per_sec: None,
+            calls_per_sec: None,
+            state: Mutex::new(State { bytes: 0.0, calls: 0.0, refilled: None, stats: ThrottleStats::default() }),
+        }
+    }
+
+    // The bucket holds one second's worth of each budget and starts full. Limits below 1 are
+    // raised to 1.
+    pub fn bytes_per_sec(mut self, limit: u64) -> Self {
+        let limit = limit.max(1);
+        self.bytes_per_sec = Some(limit);
+        self.state.get_mut().unwrap().bytes = limit as f64;
+        self
+    }
+
+    pub fn calls_per_sec(mut self, limit: u64) -> Self {
+        let limit = limit.max(1);
+        self.calls_per_sec = Some(limit);
+        self.state.get_mut().unwrap().calls = limit as f64;
+        self
+    }
+
+    pub fn stats(&self) -> ThrottleStats {
+        self.state.lock().unwrap().stats
+    }
+
+    // Blocks until a read of `bytes` bytes fits the budget.
+    pub fn acquire(&self, bytes: usize) {
+        let wait = self.acquire_at(Instant::now(), bytes);
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+
+    // Takes the budget for one call of `bytes` bytes as of `now` and returns how long the
+    // caller has to wait before making it. Time only advances through `now`, so a caller
+    // with its own clock gets deterministic results.
+    pub fn acquire_at(&self, now: Instant, bytes: usize) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = state.refilled.map_or(0.0, |refilled| now.saturating_duration_since(refilled).as_secs_f64());
+        state.refilled = Some(now);
+
+        let mut wait: f64 = 0.0;
+        if let Some(limit) = self.bytes_per_sec {
+            state.bytes = refill(state.bytes, elapsed, limit) - bytes as f64;
+            wait = wait.max(-state.bytes / limit as f64);
+        }
+        if let Some(limit) = self.calls_per_sec {
+            state.calls = refill(state.calls, elapsed, limit) - 1.0;
+            wait = wait.max(-state.calls / limit as f64);
+        }
+
+        let wait = Duration::from_secs_f64(wait.max(0.0));
+        state.stats.bytes += bytes as u64;
+        state.stats.calls += 1;
+        state.stats.waited += wait;
+        wait
+    }
+}
+
+fn refill(tokens: f64, elapsed: f64, limit: u64) -> f64 {
+    (tokens + elapsed * limit as f64).min(limit as f64)
+}
+
+impl Memory {
+    // None without a throttle.
+    pub fn throttle_stats(&self) -> Option<ThrottleStats> {
+        self.throttle().map(Throttle::stats)
+    }
+
+    // Called by worker threads on themselves. The priority the thread had comes back when the
+    // guard drops, since some of them, such as a blocking pool's, go on to other work.
+    pub(crate) fn apply_thread_priority(&self) -> PriorityGuard {
+        let priority = if self.background_priority() { THREAD_PRIORITY_BELOW_NORMAL } else { THREAD_PRIORITY_NORMAL };
+        let previous = unsafe { GetThreadPriority(GetCurrentThread()) };
+        let mut guard = PriorityGuard { previous: None, _thread: PhantomData };
+        if previous == priority || previous == THREAD_PRIORITY_ERROR_RETURN {
+            return guard;
+        }
+        let set = win_call!(
+            unsafe { SetThreadPriority(GetCurrentThread(), priority) },
+            "failed to set worker thread priority to {}",
+            priority
+        );
+        match set {
+            Ok(_) => guard.previous = Some(previous),
+            Err(e) => warn!("{}", e),
+        }
+        guard
+    }
+
+    pub(crate) fn throttle_read(&self, bytes: usize) {
+        if let Some(throttle) = self.throttle() {
+            throttle.acquire(bytes);
+        }
+    }
+}
+
+// Restores the priority `apply_thread_priority` changed, on the thread it changed it on.
+#[must_use]
+pub(crate) struct PriorityGuard {
+    previous: Option<i32>,
+    // Not Send: the priority belongs to the thread that made the guard.
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for PriorityGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous {
+            unsafe { SetThreadPriority(GetCurrentThread(), previous) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::mock::{MockBackend, PAGE};
+    use crate::filter::RegionFilter;
+    use crate::scan::ScanOptions;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn the_bucket_starts_full_and_refills_over_time() {
+        let throttle = Throttle::new().bytes_per_sec(1000);
+        let start = Instant::now();
+
+        assert_eq!(throttle.acquire_at(start, 1000), Duration::ZERO);
+        assert_eq!(throttle.acquire_at(start, 500), ms(500));
+        // Half a second pays off the debt, not beyond it.
+        assert_eq!(throttle.acquire_at(start + ms(500), 0), Duration::ZERO);
+        assert_eq!(throttle.acquire_at(start + ms(750), 500), ms(250));
+    }
+
+    #[test]
+    fn the_bucket_holds_one_second() {
+        let throttle = Throttle::new().bytes_per_sec(1000);
+        let start = Instant::now();
+
+        throttle.acquire_at(start, 0);
+        assert_eq!(throttle.acquire_at(start + Duration::from_secs(60), 3000), ms(2000));
+        // A read larger than the bucket still goes through, and the next one pays for it.
+        assert_eq!(throttle.acquire_at(start + Duration::from_secs(61), 0), ms(1000));
+    }
+
+    #[test]
+    fn calls_and_bytes_take_the_longer_wait() {
+        let throttle = Throttle::new().bytes_per_sec(1000).calls_per_sec(4);
+        let start = Instant::now();
+
+        for _ in 0..4 {
+            assert_eq!(throttle.acquire_at(start, 10), Duration::ZERO);
+        }
+        assert_eq!(throttle.acquire_at(start, 10), ms(250));
+        assert_eq!(throttle.acquire_at(start, 900), ms(500));
+        assert_eq!(throttle.acquire_at(start, 1050), ms(1000));
+    }
+
+    #[test]
+    fn a_clock_going_backwards_refills_nothing() {
+        let throttle = Throttle::new().calls_per_sec(1);
+        let start = Instant::now() + Duration::from_secs(1);
+
+        assert_eq!(throttle.acquire_at(start, 0), Duration::ZERO);
+        assert_eq!(throttle.acquire_at(start - ms(500), 0), ms(1000));
+    }
+
+    #[test]
+    fn limits_below_one_are_raised_to_one() {
+        let throttle = Throttle::new().bytes_per_sec(0).calls_per_sec(0);
+        let start = Instant::now();
+
+        assert_eq!(throttle.acquire_at(start, 1), Duration::ZERO);
+        assert_eq!(throttle.acquire_at(start, 1), ms(1000));
+    }
+
+    #[test]
+    fn stats_count_everything_let_through() {
+        let unlimited = Throttle::new();
+        let start = Instant::now();
+        for bytes in [PAGE, 2 * PAGE, 0] {
+            assert_eq!(unlimited.acquire_at(start, bytes), Duration::ZERO);
+        }
+        assert_eq!(unlimited.stats(), ThrottleStats { bytes: 3 * PAGE as u64, calls: 3, waited: Duration::ZERO });
+
+        let limited = Throttle::new().calls_per_sec(2);
+        for _ in 0..4 {
+            limited.acquire_at(start, 1);
+        }
+        assert_eq!(limited.stats(), ThrottleStats { bytes: 4, calls: 4, waited: ms(500) + ms(1000) });
+    }
+
+    #[test]
+    fn bulk_scans_wait_on_the_throttle_and_single_reads_do_not() {
+        let pages = 41;
+        let (mock, mut memory) = MockBackend::new().data(0x10000, vec![0; pages * PAGE]).open();
+        memory.scan_memory().unwrap();
+        // A second's worth of budget is 40 pages, so the 41st waits a fortieth of a second.
+        memory.set_throttle(Some(Throttle::new().bytes_per_sec(40 * PAGE as u64)));
+        mock.clear_calls();
+
+        let options = ScanOptions::default().chunk_size(PAGE);
+        let started = Instant::now();
+        memory.scan_for_value_with(1u32, &RegionFilter::new(), &options).unwrap();
+        let elapsed = started.elapsed();
+
+        let stats = memory.throttle_stats().unwrap();
+        let read: usize = mock.reads().iter().map(|range| range.len()).sum();
+        assert_eq!((stats.calls, stats.bytes), (mock.reads().len() as u64, read as u64));
+        assert!(stats.bytes >= (pages * PAGE) as u64);
+        assert!(stats.waited >= ms(20) && elapsed >= stats.waited, "{:?} waited, {:?} elapsed", stats.waited, elapsed);
+
+        memory.read_bytes(0x10000, PAGE).unwrap();
+        memory.read_memory::<u32>(0x10000).unwrap();
+        assert_eq!(memory.throttle_stats().unwrap(), stats);
+    }
+}
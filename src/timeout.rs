@@ -0,0 +1,136 @@
+// Calls that can block forever (NtQueryObject on a busy synchronous file, ReadProcessMemory
+// against a target stuck in the kernel) run on a pooled worker thread, and the caller stops
+// waiting at a deadline. A worker whose call never returns is left behind rather than the
+// caller; at most MAX_HUNG can be left behind at once, after which timed calls fail straight
+// away until one of them comes back. Idle workers exit after IDLE_TIMEOUT, so no threads
+// linger once nothing hangs.
+
+use std::io;
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, warn};
+
+const MAX_IDLE: usize = 4;
+const MAX_HUNG: usize = 16;
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+const RUNNING: u8 = 0;
+const DONE: u8 = 1;
+const ABANDONED: u8 = 2;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+struct Pool {
+    // Idle workers by id, each waiting for its next job.
+    idle: Mutex<Vec<(usize, Sender<Job>)>>,
+    next_id: AtomicUsize,
+    // Workers still running a call whose caller gave up on it.
+    hung: AtomicUsize,
+}
+
+static POOL: Pool = Pool { idle: Mutex::new(Vec::new()), next_id: AtomicUsize::new(0), hung: AtomicUsize::new(0) };
+
+// Runs `op` on a worker and waits up to `timeout` for its result. Fails with TimedOut if it
+// takes longer (the call keeps running in the background and its result is dropped), or if
+// too many earlier calls are still hung.
+pub(crate) fn with_timeout<T, F>(timeout: Duration, op: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let hung = POOL.hung.load(Ordering::SeqCst);
+    if hung >= MAX_HUNG {
+        return Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{} earlier operations are still hung; not starting another", hung),
+        ));
+    }
+
+    let state = Arc::new(AtomicU8::new(RUNNING));
+    let (result_sender, result) = mpsc::sync_channel(1);
+    let worker_state = Arc::clone(&state);
+    dispatch(Box::new(move || {
+        let _ = result_sender.send(op());
+        if worker_state.swap(DONE, Ordering::SeqCst) == ABANDONED {
+            POOL.hung.fetch_sub(1, Ordering::SeqCst);
+            debug!("a timed-out operation finished after all");
+        }
+    }))?;
+
+    match result.recv_timeout(timeout) {
+        Ok(value) => Ok(value),
+        Err(RecvTimeoutError::Timeout) => {
+            // Counted first, so the worker can't uncount it before it is counted.
+            POOL.hung.fetch_add(1, Ordering::SeqCst);
+            if state.compare_exchange(RUNNING, ABANDONED, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+                // It finished just now; the result has been sent.
+                POOL.hung.fetch_sub(1, Ordering::SeqCst);
+                return result.recv().map_err(|_| worker_panicked());
+            }
+            warn!("operation did not finish within {:?}; leaving it running", timeout);
+            Err(io::Error::new(io::ErrorKind::TimedOut, format!("Operation did not finish within {:?}", timeout)))
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(worker_panicked()),
+    }
+}
+
+fn worker_panicked() -> io::Error {
+    io::Error::other("The worker running the operation panicked")
+}
+
+fn dispatch(job: Job) -> io::Result<()> {
+    let idle = POOL.idle.lock().unwrap().pop();
+    let job = match idle {
+        Some((_, worker)) => match worker.send(job) {
+            Ok(()) => return Ok(()),
+            // It exited anyway; run the job on a new one.
+            Err(mpsc::SendError(job)) => job,
+        },
+        None => job,
+    };
+    spawn_worker(job)
+}
+
+fn spawn_worker(first: Job) -> io::Result<()> {
+    let id = POOL.next_id.fetch_add(1, Ordering::Relaxed);
+    let (sender, jobs) = mpsc::channel::<Job>();
+    thread::Builder::new().name(format!("nirvana-timeout-{}", id)).spawn(move || {
+        let mut job = first;
+        loop {
+            job();
+
+            {
+                let mut idle = POOL.idle.lock().unwrap();
+                if idle.len() >= MAX_IDLE {
+                    return;
+                }
+                idle.push((id, sender.clone()));
+            }
+            job = match jobs.recv_timeout(IDLE_TIMEOUT) {
+                Ok(next) => next,
+                Err(_) => {
+                    let mut idle = POOL.idle.lock().unwrap();
+                    match idle.iter().position(|(idle_id, _)| *idle_id == id) {
+                        Some(index) => {
+                            idle.remove(index);
+                            return;
+                        }
+                        // Taken off the idle list just as we gave up waiting: a job is on its way.
+                        None => {
+                            drop(idle);
+                            match jobs.recv() {
+                                Ok(next) => next,
+                                Err(_) => return,
+                            }
+                        }
+                    }
+                }
+            };
+        }
+    })?;
+    Ok(())
+}
@@ -0,0 +1,177 @@
+// Sampling a few candidate addresses over time, to tell which one behaves like the value
+// being looked for:
+//
+//     let interval = Duration::from_millis(100);
+//     let history = ValueRecorder::record::<i32>(&memory, &candidates, interval, Duration::from_secs(30))?;
+//     for (address, samples) in &history {
+//         println!("0x{:X}: {:?}", address, value_recorder::summarize(samples));
+//     }
+//
+// Addresses close together are read with one ReadProcessMemory call per sample. An address
+// that can't be read stops being recorded but keeps the samples taken until then.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, Write};
+use std::mem;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+use crate::memory::Memory;
+use crate::pod::Pod;
+use crate::remote_struct::extract;
+
+// Addresses are batched into one read while they fit into a span this long.
+const MAX_BATCH_SPAN: usize = 0x1000;
+
+pub struct ValueRecorder;
+
+impl ValueRecorder {
+    // Samples every address once per `interval` until `duration` has passed, or until none can
+    // be read any more. Samples that fall behind schedule are skipped rather than bunched up.
+    pub fn record<T: Pod>(
+        memory: &Memory,
+        addresses: &[usize],
+        interval: Duration,
+        duration: Duration,
+    ) -> io::Result<HashMap<usize, Vec<(Instant, T)>>> {
+        if interval.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The sampling interval must not be zero"));
+        }
+
+        let mut live: Vec<usize> = addresses.to_vec();
+        live.sort_unstable();
+        live.dedup();
+        let mut history: HashMap<usize, Vec<(Instant, T)>> =
+            live.iter().map(|&address| (address, Vec::new())).collect();
+
+        let start = Instant::now();
+        let mut next = start;
+        while !live.is_empty() && next.duration_since(start) < duration {
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            }
+            let taken = Instant::now();
+
+            let values = sample::<T>(memory, &live);
+            let mut values = values.into_iter();
+            live.retain(|&address| match values.next().flatten() {
+                Some(value) => {
+                    history.get_mut(&address).unwrap().push((taken, value));
+                    true
+                }
+                None => {
+                    debug!("0x{:X} became unreadable; stopped recording it", address);
+                    false
+                }
+            });
+
+            next += interval;
+            while next < Instant::now() {
+                next += interval;
+            }
+        }
+
+        Ok(history)
+    }
+}
+
+// The current value at each of the sorted `addresses`, None where it can't be read.
+fn sample<T: Pod>(memory: &Memory, addresses: &[usize]) -> Vec<Option<T>> {
+    let size = mem::size_of::<T>();
+    let mut values = Vec::with_capacity(addresses.len());
+    let mut buffer = Vec::new();
+
+    let mut first = 0;
+    while first < addresses.len() {
+        let start = addresses[first];
+        let mut last = first;
+        while addresses.get(last + 1).is_some_and(|&next| next + size - start <= MAX_BATCH_SPAN) {
+            last += 1;
+        }
+
+        buffer.resize(addresses[last] + size - start, 0);
+        let read = memory.read_bulk(start, &mut buffer).unwrap_or(0);
+        for &address in &addresses[first..=last] {
+            let offset = address - start;
+            values.push(if offset + size <= read {
+                Some(extract(&buffer, offset))
+            } else {
+                // Past the end of what the span read: maybe only this slot is bad.
+                memory.read_unaligned::<T>(address).ok()
+            });
+        }
+        first = last + 1;
+    }
+
+    values
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueSummary<T> {
+    pub samples: usize,
+    pub min: T,
+    pub max: T,
+    // Samples that differ from the one before.
+    pub changes: usize,
+    // Never went down (or up) between two samples. Both hold for a constant value.
+    pub non_decreasing: bool,
+    pub non_increasing: bool,
+}
+
+// None for an empty history. Values that don't compare (NaN) count as changes but leave
+// min, max and the monotonic flags alone.
+pub fn summarize<T: Pod + PartialOrd>(history: &[(Instant, T)]) -> Option<ValueSummary<T>> {
+    let (_, first) = *history.first()?;
+    let mut summary = ValueSummary {
+        samples: history.len(),
+        min: first,
+        max: first,
+        changes: 0,
+        non_decreasing: true,
+        non_increasing: true,
+    };
+
+    for pair in history.windows(2) {
+        let (previous, value) = (pair[0].1, pair[1].1);
+        if value != previous {
+            summary.changes += 1;
+        }
+        if value < previous {
+            summary.non_decreasing = false;
+        }
+        if value > previous {
+            summary.non_increasing = false;
+        }
+        if value < summary.min {
+            summary.min = value;
+        }
+        if value > summary.max {
+            summary.max = value;
+        }
+    }
+
+    Some(summary)
+}
+
+// One "address,elapsed_ms,value" row per sample, by address and then time. Times are
+// milliseconds since `start`, normally the instant recording began.
+pub fn write_csv<T: Pod + Display>(
+    history: &HashMap<usize, Vec<(Instant, T)>>,
+    start: Instant,
+    mut writer: impl Write,
+) -> io::Result<()> {
+    let mut addresses: Vec<&usize> = history.keys().collect();
+    addresses.sort_unstable();
+
+    writeln!(writer, "address,elapsed_ms,value")?;
+    for address in addresses {
+        for (taken, value) in &history[address] {
+            writeln!(writer, "0x{:X},{},{}", address, taken.saturating_duration_since(start).as_millis(), value)?;
+        }
+    }
+    writer.flush()
+}
@@ -0,0 +1,311 @@
+// A live feed of labeled values for overlays and HUDs. A background thread reads the watch
+// list at a fixed rate and queues one frame per tick, with a sequence number and timestamp:
+//
+//     let watchlist = vec![WatchedValue::new("health", health_address, FieldType::F32)];
+//     let stream = ValueStream::new(&memory, watchlist, 60)?;
+//     stream.write_to(TcpStream::connect("127.0.0.1:9000")?)?;
+//
+// HUDs want the newest values, not every value: the queue holds FRAME_QUEUE_LEN frames, and
+// when the consumer falls behind the oldest frame is dropped to make room. Gaps in the
+// sequence numbers show where. A value that fails to read is null in its frame and counted
+// in `errors`; it doesn't hold up the others.
+
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use log::debug;
+use serde::Serialize;
+
+use crate::bookmarks::Bookmarks;
+use crate::landmarks::Landmarks;
+use crate::memory::Memory;
+use crate::process_handle::{spawn_worker, upgrade};
+use crate::rebase::Rebaser;
+use crate::struct_template::{self, DecodedValue, FieldType};
+
+// Frames kept for a slow consumer before the oldest is dropped.
+pub const FRAME_QUEUE_LEN: usize = 8;
+
+// Values are batched into one read while they fit into a span this long.
+const MAX_BATCH_SPAN: usize = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchedValue {
+    pub label: String,
+    pub address: usize,
+    pub ty: FieldType,
+}
+
+impl WatchedValue {
+    pub fn new(label: &str, address: usize, ty: FieldType) -> Self {
+        WatchedValue { label: label.to_string(), address, ty }
+    }
+
+    // The bookmarks that have a type hint and resolve in the target now, in label order.
+    pub fn from_bookmarks(bookmarks: &Bookmarks, rebaser: &Rebaser) -> Vec<WatchedValue> {
+        bookmarks
+            .iter()
+            .filter_map(|bookmark| {
+                let ty = bookmark.type_hint.as_deref()?.parse().ok()?;
+                let address = bookmark.resolve(rebaser).map_err(|e| debug!("not streaming: {}", e)).ok()?;
+                Some(WatchedValue { label: bookmark.label.clone(), address, ty })
+            })
+            .collect()
+    }
+
+    // The typed landmarks that resolve, in name order, so values relative to an anchor are
+    // watched wherever the anchor was last put.
+    pub fn from_landmarks(landmarks: &Landmarks) -> Vec<WatchedValue> {
+        landmarks
+            .iter()
+            .filter_map(|(name, landmark, address)| {
+                let ty = landmark.type_hint.as_deref()?.parse().ok()?;
+                Some(WatchedValue { label: name.to_string(), address: address?, ty })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameValue {
+    pub label: String,
+    // None if the value couldn't be read this tick.
+    pub value: Option<DecodedValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValueFrame {
+    // Counts every frame taken, including dropped ones.
+    pub sequence: u64,
+    // Microseconds since the stream started.
+    pub timestamp_us: u64,
+    // In watch list order.
+    pub values: Vec<FrameValue>,
+    // Failed reads since the stream started, this frame's included.
+    pub errors: u64,
+}
+
+impl ValueFrame {
+    // One line of JSON, without the newline.
+    pub fn to_json(&self) -> io::Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ValueStreamReport {
+    pub frames: u64,
+    // Frames dropped because the consumer was behind.
+    pub dropped: u64,
+    pub errors: u64,
+}
+
+#[derive(Default)]
+struct FrameQueue {
+    frames: VecDeque<ValueFrame>,
+    dropped: u64,
+    // Set when the sampling thread has stopped; nothing more will be queued.
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    queue: Mutex<FrameQueue>,
+    ready: Condvar,
+}
+
+// Samples the watch list from a background thread until stopped.
+pub struct ValueStream {
+    stop: Arc<AtomicBool>,
+    shared: Arc<Shared>,
+    thread: JoinHandle<ValueStreamReport>,
+}
+
+impl ValueStream {
+    // `rate` is in frames per second. The stream stops by itself once `memory` is dropped or
+    // closed.
+    pub fn new(memory: &Arc<Memory>, watchlist: Vec<WatchedValue>, rate: u32) -> io::Result<Self> {
+        if rate == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The frame rate must not be zero"));
+        }
+        let pointer_size = memory.pointer_size()?;
+        let interval = Duration::from_secs(1) / rate;
+        let stop = Arc::new(AtomicBool::new(false));
+        let shared = Arc::new(Shared::default());
+
+        let thread = {
+            let stop = Arc::clone(&stop);
+            let shared = Arc::clone(&shared);
+            spawn_worker(memory, move |memory| {
+                let report = run(memory, &watchlist, pointer_size, interval, &stop, &shared);
+                shared.queue.lock().unwrap().closed = true;
+                shared.ready.notify_all();
+                report
+            })
+        };
+        Ok(ValueStream { stop, shared, thread })
+    }
+
+    // The oldest queued frame, waiting for one if none is; None once the stream has stopped.
+    pub fn recv(&self) -> Option<ValueFrame> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        loop {
+            if let Some(frame) = queue.frames.pop_front() {
+                return Some(frame);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = self.shared.ready.wait(queue).unwrap();
+        }
+    }
+
+    // Like `recv`, giving up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ValueFrame> {
+        let queue = self.shared.queue.lock().unwrap();
+        let (mut queue, _) = self
+            .shared
+            .ready
+            .wait_timeout_while(queue, timeout, |queue| queue.frames.is_empty() && !queue.closed)
+            .unwrap();
+        queue.frames.pop_front()
+    }
+
+    // Writes each frame to `writer` as a line of JSON until the stream stops or a write
+    // fails, e.g. because the client on the other end of a socket went away. Returns the
+    // frames written.
+    pub fn write_to(&self, mut writer: impl Write) -> io::Result<u64> {
+        let mut written = 0;
+        while let Some(frame) = self.recv() {
+            writeln!(writer, "{}", frame.to_json()?)?;
+            writer.flush()?;
+            written += 1;
+        }
+        Ok(written)
+    }
+
+    pub fn stop(self) -> ValueStreamReport {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.thread().unpark();
+        let mut report = self.thread.join().unwrap_or_default();
+        report.dropped = self.shared.queue.lock().unwrap().dropped;
+        report
+    }
+}
+
+fn run(
+    memory: &Weak<Memory>,
+    watchlist: &[WatchedValue],
+    pointer_size: usize,
+    interval: Duration,
+    stop: &AtomicBool,
+    shared: &Shared,
+) -> ValueStreamReport {
+    let batches = batches(watchlist, pointer_size);
+    let mut report = ValueStreamReport::default();
+    let start = Instant::now();
+    let mut next = start;
+
+    while !stop.load(Ordering::SeqCst) {
+        let now = Instant::now();
+        if next > now {
+            thread::park_timeout(next - now);
+            continue;
+        }
+
+        let Some(memory) = upgrade(memory) else {
+            break;
+        };
+        let _priority = memory.apply_thread_priority();
+        let timestamp_us = start.elapsed().as_micros() as u64;
+        let values = sample(&memory, watchlist, &batches, pointer_size);
+        drop(memory);
+        report.errors += values.iter().filter(|value| value.is_none()).count() as u64;
+        let frame = ValueFrame {
+            sequence: report.frames,
+            timestamp_us,
+            values: watchlist
+                .iter()
+                .zip(values)
+                .map(|(watched, value)| FrameValue { label: watched.label.clone(), value })
+                .collect(),
+            errors: report.errors,
+        };
+        report.frames += 1;
+
+        let mut queue = shared.queue.lock().unwrap();
+        if queue.frames.len() == FRAME_QUEUE_LEN {
+            queue.frames.pop_front();
+            queue.dropped += 1;
+        }
+        queue.frames.push_back(frame);
+        drop(queue);
+        shared.ready.notify_one();
+
+        // Ticks missed while the target was slow to read are skipped, not made up.
+        next += interval;
+        while next < Instant::now() {
+            next += interval;
+        }
+    }
+
+    debug!("value stream stopped after {} frames, {} failed reads", report.frames, report.errors);
+    report
+}
+
+// Indices into the watch list grouped into spans read with one call each, by address.
+fn batches(watchlist: &[WatchedValue], pointer_size: usize) -> Vec<Vec<usize>> {
+    let mut order: Vec<usize> = (0..watchlist.len()).collect();
+    order.sort_by_key(|&index| watchlist[index].address);
+
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+    let mut span_start = 0;
+    for index in order {
+        let watched = &watchlist[index];
+        let end = watched.address.saturating_add(watched.ty.size(pointer_size));
+        match batches.last_mut() {
+            Some(batch) if end - span_start <= MAX_BATCH_SPAN => batch.push(index),
+            _ => {
+                span_start = watched.address;
+                batches.push(vec![index]);
+            }
+        }
+    }
+    batches
+}
+
+// The current value of each watched value, in watch list order.
+fn sample(
+    memory: &Memory,
+    watchlist: &[WatchedValue],
+    batches: &[Vec<usize>],
+    pointer_size: usize,
+) -> Vec<Option<DecodedValue>> {
+    let mut values = vec![None; watchlist.len()];
+    let mut buffer = Vec::new();
+    for batch in batches {
+        let start = watchlist[batch[0]].address;
+        let end = batch.iter().map(|&index| watchlist[index].address + watchlist[index].ty.size(pointer_size)).max();
+        buffer.resize(end.unwrap_or(start) - start, 0);
+        let read = memory.read_bulk(start, &mut buffer).unwrap_or(0);
+
+        for &index in batch {
+            let watched = &watchlist[index];
+            let offset = watched.address - start;
+            let size = watched.ty.size(pointer_size);
+            values[index] = match &watched.ty {
+                ty if !matches!(ty, FieldType::Deref(_)) && offset + size <= read => {
+                    Some(struct_template::decode(ty, &buffer[offset..offset + size]))
+                }
+                // Pointers are followed, and a value past what the span read may still be
+                // readable on its own.
+                ty => struct_template::read_field(memory, watched.address, ty).ok(),
+            };
+        }
+    }
+    values
+}
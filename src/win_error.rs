@@ -0,0 +1,109 @@
+// Win32 failures carry their reason in the thread's last-error value, which the next call
+// into the system may overwrite: a CloseHandle, a VirtualQueryEx made to explain the
+// failure, even logging. Calls go through `win_call!`, which reads the value straight after
+// the call returns, before anything else runs:
+//
+//     let process = win_call!(unsafe { OpenProcess(access, 0, pid) }, "Failed to open process {}", pid)?;
+//
+// The error keeps the OS error's kind, and its message is the context followed by the OS
+// message. Results whose failure isn't a zero BOOL or a null handle give the test:
+//
+//     win_call!(unsafe { ResumeThread(thread) }, failed: |count| *count == u32::MAX, "...")?;
+//
+// With the `last-error-checks` feature the last error is set to a marker value before each
+// call and after each capture, and a debug assertion fails if a capture reads the marker:
+// the error then wasn't set by the call before, which means either a stale read or a call
+// that doesn't report through GetLastError at all (NTSTATUS functions, for one).
+
+use std::fmt;
+use std::io;
+
+#[cfg(feature = "last-error-checks")]
+use crate::sys::SetLastError;
+
+// Not a system error code: bit 29 marks application-defined codes.
+#[cfg(feature = "last-error-checks")]
+const POISON: u32 = 0x2BAD_C0DE;
+
+// Whether a raw Win32 result means the call failed.
+pub(crate) trait WinResult {
+    fn failed(&self) -> bool;
+}
+
+// BOOL and other int results where zero is failure.
+impl WinResult for i32 {
+    fn failed(&self) -> bool {
+        *self == 0
+    }
+}
+
+// VirtualQueryEx and friends, which return the bytes written and zero on failure.
+impl WinResult for usize {
+    fn failed(&self) -> bool {
+        *self == 0
+    }
+}
+
+// Handles and mapped views. Null is what most calls return on failure; toolhelp snapshots
+// and CreateFile return INVALID_HANDLE_VALUE, which is never a handle they hand out.
+impl WinResult for *mut std::ffi::c_void {
+    fn failed(&self) -> bool {
+        self.is_null() || *self as isize == -1
+    }
+}
+
+// Called right before a wrapped call; only does something with `last-error-checks`.
+#[inline]
+pub(crate) fn poison() {
+    #[cfg(feature = "last-error-checks")]
+    unsafe {
+        SetLastError(POISON)
+    };
+}
+
+// The calling thread's last error, with `context` in front of the OS message. Call it before
+// anything else after the failed call; `win_call!` does.
+pub(crate) fn last_error(context: impl fmt::Display) -> io::Error {
+    let e = io::Error::last_os_error();
+    #[cfg(feature = "last-error-checks")]
+    {
+        debug_assert_ne!(
+            e.raw_os_error(),
+            Some(POISON as i32),
+            "last error read after a call that didn't set it ({})",
+            context
+        );
+        poison();
+    }
+    io::Error::new(e.kind(), format!("{}: {}", context, e))
+}
+
+macro_rules! win_call {
+    ($call:expr, failed: $failed:expr, $context:tt $($args:tt)*) => {{
+        let _span = $crate::spans::span!("syscall", context = $context; outcome);
+        $crate::win_error::poison();
+        let result = $call;
+        let failed: fn(&_) -> bool = $failed;
+        let result = if failed(&result) {
+            Err($crate::win_error::last_error(format_args!($context $($args)*)))
+        } else {
+            Ok(result)
+        };
+        $crate::spans::record!(_span, outcome = $crate::spans::outcome(&result));
+        result
+    }};
+    ($call:expr, $context:tt $($args:tt)*) => {{
+        let _span = $crate::spans::span!("syscall", context = $context; outcome);
+        $crate::win_error::poison();
+        let result = $call;
+        let result = if $crate::win_error::WinResult::failed(&result) {
+            Err($crate::win_error::last_error(format_args!($context $($args)*)))
+        } else {
+            Ok(result)
+        };
+        $crate::spans::record!(_span, outcome = $crate::spans::outcome(&result));
+        result
+    }};
+}
+
+pub(crate) use win_call;
@@ -0,0 +1,237 @@
+// Optional safety rail against writing to a mistyped address. A guard lists where writes may
+// go; anything else fails with a `WriteBlocked` error before the target is touched:
+//
+//     memory.set_write_guard(WriteGuard::restrict().allow_regions(RegionFilter::new().writable(true)));
+//     memory.write_memory(health, 100.0f32)?;
+//
+//     let _bypass = memory.bypass_write_guard();
+//     memory.write_protected(code, &[0x90, 0x90])?; // deliberately outside the guard
+//
+// The default guard allows every write, as before guards existed. Allocations made through
+// this `Memory` are always writable. The write policy (see `WritePolicy`) applies on top of
+// the guard either way.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::filter::RegionFilter;
+use crate::memory::{Memory, MemoryRegion};
+
+// Where writes may go. A write is allowed if it lies entirely in one of the allowed ranges,
+// or starts at a tracked scan result; with nothing allowed, every write is blocked.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WriteGuard {
+    restricted: bool,
+    regions: Option<RegionFilter>,
+    ranges: Vec<Range<usize>>,
+    scan_results: bool,
+}
+
+impl WriteGuard {
+    // Allows every write.
+    pub fn permissive() -> Self {
+        WriteGuard::default()
+    }
+
+    // Blocks every write until something is allowed.
+    pub fn restrict() -> Self {
+        WriteGuard { restricted: true, ..WriteGuard::default() }
+    }
+
+    // The parts of the cached regions the filter selects, as they are when the guard is set
+    // and after each `scan_memory`, or of the regions a metadata TTL refreshed them to.
+    pub fn allow_regions(mut self, filter: RegionFilter) -> Self {
+        self.regions = Some(filter);
+        self
+    }
+
+    pub fn allow_range(mut self, range: Range<usize>) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    // Addresses returned by this `Memory`'s pattern, value and float scans and by signature
+    // resolution since the guard was set. A write has to start exactly at one of them.
+    pub fn allow_scan_results(mut self) -> Self {
+        self.scan_results = true;
+        self
+    }
+
+    pub fn is_permissive(&self) -> bool {
+        !self.restricted
+    }
+}
+
+// Payload of the `io::Error` a guard returns for a write it blocks. See `is_write_blocked`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteBlocked {
+    pub address: usize,
+    pub len: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for WriteBlocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Write of 0x{:X} bytes at 0x{:X} blocked by the write guard: {}", self.len, self.address, self.reason)
+    }
+}
+
+impl std::error::Error for WriteBlocked {}
+
+pub fn is_write_blocked(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<WriteBlocked>())
+}
+
+// Sorted, merged ranges; lookups are a binary search.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct RangeSet {
+    ranges: Vec<Range<usize>>,
+}
+
+impl RangeSet {
+    pub(crate) fn new(mut ranges: Vec<Range<usize>>) -> Self {
+        ranges.retain(|range| range.start < range.end);
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        RangeSet { ranges: merged }
+    }
+
+    // Whether `range` lies inside one of the (merged) ranges.
+    pub(crate) fn covers(&self, range: &Range<usize>) -> bool {
+        let index = self.ranges.partition_point(|candidate| candidate.end <= range.start);
+        self.ranges.get(index).is_some_and(|candidate| candidate.start <= range.start && range.end <= candidate.end)
+    }
+}
+
+// The guard with its allowed ranges worked out, and what checking it needs at run time.
+#[derive(Debug, Default)]
+pub(crate) struct WriteGuardState {
+    guard: WriteGuard,
+    allowed: RangeSet,
+    // The allowed ranges for the regions a metadata refresh put in place of the cached ones,
+    // with the list they were worked out from.
+    refreshed: Mutex<Option<(Arc<Vec<MemoryRegion>>, RangeSet)>>,
+    tracked: Mutex<HashSet<usize>>,
+    bypass_depth: AtomicUsize,
+}
+
+impl WriteGuardState {
+    fn new(guard: WriteGuard, regions: &[MemoryRegion]) -> Self {
+        let allowed = allowed_ranges(&guard, regions);
+        WriteGuardState { guard, allowed, ..WriteGuardState::default() }
+    }
+
+    pub(crate) fn refresh_regions(&mut self, regions: &[MemoryRegion]) {
+        self.allowed = allowed_ranges(&self.guard, regions);
+        *self.refreshed.get_mut().unwrap() = None;
+    }
+
+    // Whether `range` is allowed, by the regions scans use now: a refreshed list if there is
+    // one, else the cached regions.
+    fn allows(&self, range: &Range<usize>, fresh: Option<Arc<Vec<MemoryRegion>>>) -> bool {
+        let Some(fresh) = fresh else {
+            return self.allowed.covers(range);
+        };
+        let mut refreshed = self.refreshed.lock().unwrap();
+        match &*refreshed {
+            Some((regions, allowed)) if Arc::ptr_eq(regions, &fresh) => allowed.covers(range),
+            _ => {
+                let allowed = allowed_ranges(&self.guard, &fresh);
+                let covers = allowed.covers(range);
+                *refreshed = Some((fresh, allowed));
+                covers
+            }
+        }
+    }
+}
+
+fn allowed_ranges(guard: &WriteGuard, regions: &[MemoryRegion]) -> RangeSet {
+    let mut ranges = guard.ranges.clone();
+    if let Some(filter) = &guard.regions {
+        ranges.extend(regions.iter().filter_map(|region| filter.clip(region)));
+    }
+    RangeSet::new(ranges)
+}
+
+// Re-enables the guard when dropped. See `Memory::bypass_write_guard`.
+pub struct WriteGuardBypass<'a> {
+    memory: &'a Memory,
+}
+
+impl Drop for WriteGuardBypass<'_> {
+    fn drop(&mut self) {
+        self.memory.write_guard_state().bypass_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Memory {
+    // Replaces the guard, forgetting the scan results tracked for the previous one.
+    pub fn set_write_guard(&mut self, guard: WriteGuard) {
+        let state = WriteGuardState::new(guard, self.cached_regions());
+        *self.write_guard_state_mut() = state;
+    }
+
+    pub fn write_guard(&self) -> &WriteGuard {
+        &self.write_guard_state().guard
+    }
+
+    // Lets every write through, on any thread, until the returned value is dropped. Bypasses
+    // nest.
+    pub fn bypass_write_guard(&self) -> WriteGuardBypass<'_> {
+        self.write_guard_state().bypass_depth.fetch_add(1, Ordering::Relaxed);
+        WriteGuardBypass { memory: self }
+    }
+
+    // Remembers scan results for `WriteGuard::allow_scan_results`; a no-op for other guards.
+    pub(crate) fn track_results(&self, addresses: &[usize]) {
+        let state = self.write_guard_state();
+        if state.guard.restricted && state.guard.scan_results {
+            state.tracked.lock().unwrap().extend(addresses);
+        }
+    }
+
+    // Fails with PermissionDenied and a `WriteBlocked` payload if the guard doesn't allow
+    // writing `len` bytes at `address`.
+    pub(crate) fn check_write_guard(&self, address: usize, len: usize) -> io::Result<()> {
+        let state = self.write_guard_state();
+        if !state.guard.restricted || state.bypass_depth.load(Ordering::Relaxed) > 0 {
+            return Ok(());
+        }
+
+        let range = address..address.saturating_add(len);
+        // Our own allocations (stubs, trampolines, mapped images) are always writable.
+        let own = self.allocation_list().lock().unwrap().iter().any(|allocation| {
+            allocation.start <= range.start && range.end <= allocation.end
+        });
+        let fresh = if state.guard.regions.is_some() { self.fresh_regions() } else { None };
+        if own
+            || state.allows(&range, fresh)
+            || (state.guard.scan_results && state.tracked.lock().unwrap().contains(&address))
+        {
+            return Ok(());
+        }
+
+        let mut reasons = Vec::new();
+        if state.guard.regions.is_some() {
+            reasons.push("outside the guarded regions");
+        }
+        if !state.guard.ranges.is_empty() {
+            reasons.push("outside the allowed ranges");
+        }
+        if state.guard.scan_results {
+            reasons.push("not at a scan result");
+        }
+        let reason = if reasons.is_empty() { "the guard allows no writes".to_string() } else { reasons.join(", ") };
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, WriteBlocked { address, len, reason }))
+    }
+}
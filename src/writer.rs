@@ -0,0 +1,164 @@
+// Counterpart of reader.rs: a `Write + Seek` stream into the target that batches writes into
+// chunk-sized WriteProcessMemory calls, e.g. to copy a file into a remote allocation:
+//
+//     let base = memory.allocate(len, 0x04)?; // PAGE_READWRITE
+//     let mut writer = MemoryWriter::new(&memory, base);
+//     io::copy(&mut File::open("payload.bin")?, &mut writer)?;
+//     let written = writer.finish()?;
+//
+// Stream offset 0 is the start address. Like `BufWriter`, dropping the writer flushes it but
+// ignores errors; call `finish` or `flush` to see them.
+
+use std::io::{self, Seek, SeekFrom, Write};
+
+use log::debug;
+
+use crate::memory::{partial_write, Memory};
+use crate::scan::CHUNK_SIZE;
+
+pub struct MemoryWriter<'a> {
+    memory: &'a Memory,
+    start: usize,
+    // Absolute address the next byte goes to.
+    position: usize,
+    // Bytes for `position - buffer.len()..position`, not written yet.
+    buffer: Vec<u8>,
+    // Highest address written or buffered so far, which `SeekFrom::End` is relative to.
+    end: usize,
+    written: usize,
+    write_protected: bool,
+}
+
+impl<'a> MemoryWriter<'a> {
+    pub fn new(memory: &'a Memory, start: usize) -> Self {
+        MemoryWriter {
+            memory,
+            start,
+            position: start,
+            buffer: Vec::new(),
+            end: start,
+            written: 0,
+            write_protected: false,
+        }
+    }
+
+    // Write through read-only pages with `write_protected`, restoring their protection after
+    // each chunk. Off by default, so writes to read-only memory fail.
+    pub fn write_protected(mut self, enabled: bool) -> Self {
+        self.write_protected = enabled;
+        self
+    }
+
+    // Address in the target the next byte will be written to.
+    pub fn address(&self) -> usize {
+        self.position
+    }
+
+    // Flushes and returns the total number of bytes written to the target.
+    pub fn finish(mut self) -> io::Result<usize> {
+        self.flush()?;
+        Ok(self.written)
+    }
+}
+
+impl Write for MemoryWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() == CHUNK_SIZE {
+            self.flush()?;
+        }
+        let len = bytes.len().min(CHUNK_SIZE - self.buffer.len());
+        let end = self
+            .position
+            .checked_add(len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Write range overflows the address space"))?;
+        self.buffer.extend_from_slice(&bytes[..len]);
+        self.position = end;
+        self.end = self.end.max(end);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let address = self.position - self.buffer.len();
+        let result = if self.write_protected {
+            self.memory.write_protected(address, &self.buffer)
+        } else {
+            self.memory.write_bytes(address, &self.buffer)
+        };
+        // After a partial write only the unwritten tail stays buffered, so `written` is exact.
+        let written = match &result {
+            Ok(()) => self.buffer.len(),
+            Err(e) => partial_write(e).map_or(0, |partial| partial.written),
+        };
+        self.written += written;
+        self.buffer.drain(..written);
+        result
+    }
+}
+
+impl Seek for MemoryWriter<'_> {
+    // Flushes first, so a failed write surfaces here rather than after the jump.
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.flush()?;
+        let (base, offset) = match position {
+            SeekFrom::Start(offset) => (self.start as i128, offset as i128),
+            SeekFrom::End(offset) => (self.end as i128, offset as i128),
+            SeekFrom::Current(offset) => (self.position as i128, offset as i128),
+        };
+        let target = base + offset;
+        if target < self.start as i128 || target > usize::MAX as i128 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek outside the address space of the writer"));
+        }
+        self.position = target as usize;
+        Ok((self.position - self.start) as u64)
+    }
+}
+
+impl Drop for MemoryWriter<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            debug!("dropping 0x{:X} unflushed bytes at 0x{:X}: {}", self.buffer.len(), self.position, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Seek, SeekFrom, Write};
+
+    use super::MemoryWriter;
+    use crate::backend::mock::{MockBackend, PAGE};
+    use crate::memory::partial_write;
+
+    #[test]
+    fn partial_flushes_keep_the_unwritten_tail() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+        mock.fail_writes(0x10006..0x11000);
+
+        let mut writer = MemoryWriter::new(&memory, 0x10000);
+        writer.write_all(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        let e = writer.flush().unwrap_err();
+        assert_eq!(partial_write(&e).map(|partial| partial.written), Some(6));
+        assert_eq!(mock.bytes(0x10000, 8), [1, 2, 3, 4, 5, 6, 0, 0]);
+        assert_eq!(writer.buffer, [7, 8]);
+        assert_eq!(writer.written, 6);
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn seeks_flush_first() {
+        let (mock, memory) = MockBackend::new().data(0x10000, vec![0; PAGE]).open();
+
+        let mut writer = MemoryWriter::new(&memory, 0x10000);
+        writer.write_all(&[1, 2]).unwrap();
+        assert_eq!(writer.seek(SeekFrom::Start(0x10)).unwrap(), 0x10);
+        writer.write_all(&[3]).unwrap();
+        assert_eq!(writer.seek(SeekFrom::End(0)).unwrap(), 0x11);
+        assert_eq!(writer.finish().unwrap(), 3);
+        assert_eq!(mock.bytes(0x10000, 2), [1, 2]);
+        assert_eq!(mock.bytes(0x10010, 1), [3]);
+        assert_eq!(mock.writes(), [0x10000..0x10002, 0x10010..0x10011]);
+    }
+}
@@ -0,0 +1,151 @@
+// UTF-16 as Windows hands it out: module and process names, file paths, wide strings in the
+// target. Everything that decodes wide text or matches names goes through here, so names
+// outside ASCII ("игра.exe", "ゲーム.dll", paths with emoji) behave like any other.
+//
+//     let name = wstr::read_utf16_until_nul(&memory, address, 260)?;
+//     if wstr::eq_ignore_case(&wstr::decode_lossy(&name), "ИГРА.EXE") { ... }
+//
+// Name comparisons fold case one character at a time, the way the loader and the file system
+// do: no character turns into several, so "ß" doesn't match "SS".
+
+use std::io;
+
+use crate::memory::Memory;
+use crate::sys::QueryDosDeviceW;
+
+// Characters read per call while looking for the terminator.
+const READ_CHUNK: usize = 128;
+
+// The units before the first NUL, or all of them.
+pub fn until_nul(units: &[u16]) -> &[u16] {
+    let len = units.iter().position(|&unit| unit == 0).unwrap_or(units.len());
+    &units[..len]
+}
+
+// Little-endian byte pairs as UTF-16 units; a trailing odd byte is dropped.
+pub fn units_from_bytes(bytes: &[u8]) -> Vec<u16> {
+    bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect()
+}
+
+// Unpaired surrogates become U+FFFD.
+pub fn decode_lossy(units: &[u16]) -> String {
+    String::from_utf16_lossy(units)
+}
+
+// Fails with InvalidData on an unpaired surrogate, for text that has to round-trip, e.g. a
+// path that will be opened again.
+pub fn decode_strict(units: &[u16]) -> io::Result<String> {
+    String::from_utf16(units).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// `decode_lossy` of the units before the first NUL.
+pub fn from_wide_nul(units: &[u16]) -> String {
+    decode_lossy(until_nul(units))
+}
+
+// NUL-terminated, for passing to the W functions.
+pub fn to_wide_nul(text: &str) -> Vec<u16> {
+    text.encode_utf16().chain(Some(0)).collect()
+}
+
+// Up to `max_chars` units at `address`, stopping before the first NUL. Reading stops early
+// at memory that can't be read; it fails only if the first unit can't be.
+pub fn read_utf16_until_nul(memory: &Memory, address: usize, max_chars: usize) -> io::Result<Vec<u16>> {
+    let mut units = Vec::new();
+    while units.len() < max_chars {
+        let chunk = READ_CHUNK.min(max_chars - units.len());
+        let at = address + units.len() * 2;
+        let bytes = match memory.read_bytes(at, chunk * 2) {
+            Ok(bytes) => bytes,
+            Err(e) if units.is_empty() => return Err(e),
+            Err(_) => break,
+        };
+        let read = units_from_bytes(&bytes);
+        if let Some(end) = read.iter().position(|&unit| unit == 0) {
+            units.extend_from_slice(&read[..end]);
+            return Ok(units);
+        }
+        let short = read.len() < chunk;
+        units.extend(read);
+        if short {
+            break;
+        }
+    }
+    if units.is_empty() && max_chars > 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("No UTF-16 text readable at 0x{:X}", address),
+        ));
+    }
+    Ok(units)
+}
+
+// Simple case folding: each character maps to at most one other.
+pub fn fold_char(c: char) -> char {
+    if c.is_ascii() {
+        return c.to_ascii_lowercase();
+    }
+    let mut lower = c.to_lowercase();
+    match (lower.next(), lower.next()) {
+        (Some(folded), None) => folded,
+        _ => c,
+    }
+}
+
+// `text` case-folded, e.g. as a map key that matches like `eq_ignore_case`.
+pub fn fold_case(text: &str) -> String {
+    text.chars().map(fold_char).collect()
+}
+
+// Case-insensitive name comparison for module, process and file names.
+pub fn eq_ignore_case(a: &str, b: &str) -> bool {
+    a.chars().map(fold_char).eq(b.chars().map(fold_char))
+}
+
+// "\Device\HarddiskVolume3\Games\игра.exe" -> "C:\Games\игра.exe", using the drive letters
+// currently defined. "\??\C:\x" and "\\?\C:\x" lose their prefix, and
+// "\Device\Mup\server\share\x" becomes "\\server\share\x". None if no drive maps the device.
+pub fn nt_to_dos_path(path: &str) -> Option<String> {
+    for prefix in [r"\??\", r"\\?\"] {
+        if let Some(rest) = path.strip_prefix(prefix) {
+            return Some(match rest.strip_prefix(r"UNC\") {
+                Some(share) => format!(r"\\{}", share),
+                None => rest.to_string(),
+            });
+        }
+    }
+    if let Some(share) = strip_prefix_ignore_case(path, r"\Device\Mup\") {
+        return Some(format!(r"\\{}", share));
+    }
+
+    for letter in 'A'..='Z' {
+        let drive = format!("{}:", letter);
+        let Some(device) = query_dos_device(&drive) else { continue };
+        if let Some(rest) = strip_prefix_ignore_case(path, &device) {
+            if rest.is_empty() || rest.starts_with('\\') {
+                return Some(format!("{}{}", drive, rest));
+            }
+        }
+    }
+    None
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let mut chars = text.char_indices();
+    for expected in prefix.chars() {
+        let (_, c) = chars.next()?;
+        if fold_char(c) != fold_char(expected) {
+            return None;
+        }
+    }
+    Some(chars.next().map_or("", |(index, _)| &text[index..]))
+}
+
+// The first NT device a drive letter ("C:") maps to.
+fn query_dos_device(drive: &str) -> Option<String> {
+    let name = to_wide_nul(drive);
+    let mut buffer = vec![0u16; 1024];
+    let len = unsafe { QueryDosDeviceW(name.as_ptr(), buffer.as_mut_ptr(), buffer.len() as u32) };
+    // The result is a list of NUL-terminated strings; the first is the current mapping.
+    (len != 0).then(|| from_wide_nul(&buffer)).filter(|device| !device.is_empty())
+}
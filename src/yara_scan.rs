@@ -0,0 +1,177 @@
+// Running YARA rules over the target's memory. Regions are fed to the scanner as memory
+// blocks at their real addresses, read chunk by chunk like the pattern scans, so match
+// offsets map straight back to addresses in the target.
+//
+//     let rules = yara::Compiler::new()?.add_rules_str(r#"rule marker { strings: $a = "MARKER" condition: $a }"#)?
+//         .compile_rules()?;
+//     for hit in memory.scan_yara(&rules, &RegionFilter::new())? {
+//         println!("{} at {:X?}", hit.rule, hit.strings.iter().map(|s| s.address).collect::<Vec<_>>());
+//     }
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io;
+use std::ops::Range;
+
+use log::debug;
+use yara::{MemoryBlock, MemoryBlockIterator, Rules};
+
+use crate::artifacts;
+use crate::chunks::Chunks;
+use crate::filter::RegionFilter;
+use crate::memory::{Memory, MemoryRegion};
+use crate::scan::{self, CancelToken, ScanOptions};
+
+// Every block also holds this many bytes of the next one, since YARA never matches across
+// blocks. Matches found twice because of it are reported once.
+const BLOCK_OVERLAP: usize = 0x1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YaraMatch {
+    pub rule: String,
+    pub namespace: String,
+    pub tags: Vec<String>,
+    // Sorted by address. Empty for rules whose condition matched without any strings.
+    pub strings: Vec<YaraStringMatch>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct YaraStringMatch {
+    // The string's identifier in the rule, e.g. "$marker".
+    pub identifier: String,
+    pub address: usize,
+    pub data: Vec<u8>,
+}
+
+impl Memory {
+    pub fn scan_yara(&self, rules: &Rules, filter: &RegionFilter) -> io::Result<Vec<YaraMatch>> {
+        self.scan_yara_with(rules, filter, self.default_scan_options())
+    }
+
+    // Honors the region selection options (`validate_regions`, `rescan_regions`,
+    // `include_self_artifacts`) and `cancel`: a cancelled scan ends at the next chunk and
+    // reports what the rules matched so far. Chunks that fail to read are skipped.
+    pub fn scan_yara_with(
+        &self,
+        rules: &Rules,
+        filter: &RegionFilter,
+        options: &ScanOptions,
+    ) -> io::Result<Vec<YaraMatch>> {
+        let blocks = Blocks {
+            memory: self,
+            filter,
+            regions: scan::scan_regions(self, options, filter),
+            regions_taken: 0,
+            validate_regions: options.validate_regions,
+            excluded: self.excluded_ranges(!options.include_self_artifacts),
+            pieces: Vec::new(),
+            chunks: Chunks::empty(),
+            buffer: Vec::new(),
+            cancel: options.cancel.clone(),
+            chunk_size: options.chunk_len(),
+        };
+
+        let found = rules
+            .scan_mem_blocks(blocks, 0)
+            .map_err(|e| io::Error::other(format!("YARA scan failed: {}", e)))?;
+
+        Ok(found
+            .into_iter()
+            .map(|rule| {
+                let mut seen = HashSet::new();
+                let mut strings: Vec<YaraStringMatch> = rule
+                    .strings
+                    .iter()
+                    .flat_map(|string| {
+                        string.matches.iter().map(move |found| YaraStringMatch {
+                            identifier: string.identifier.to_string(),
+                            address: found.base + found.offset,
+                            data: found.data.clone(),
+                        })
+                    })
+                    .filter(|string| seen.insert((string.identifier.clone(), string.address)))
+                    .collect();
+                strings.sort_by_key(|string| string.address);
+                YaraMatch {
+                    rule: rule.identifier.to_string(),
+                    namespace: rule.namespace.to_string(),
+                    tags: rule.tags.iter().map(|tag| tag.to_string()).collect(),
+                    strings,
+                }
+            })
+            .collect())
+    }
+}
+
+// Hands the scanner one chunk at a time, reading the next only when it asks.
+struct Blocks<'a> {
+    memory: &'a Memory,
+    filter: &'a RegionFilter,
+    regions: Cow<'a, [MemoryRegion]>,
+    regions_taken: usize,
+    validate_regions: bool,
+    excluded: Vec<Range<usize>>,
+    // Parts of the current region still to be read, the next one last.
+    pieces: Vec<Range<usize>>,
+    // The piece being read.
+    chunks: Chunks,
+    buffer: Vec<u8>,
+    cancel: Option<CancelToken>,
+    chunk_size: usize,
+}
+
+impl Blocks<'_> {
+    // Moves on to the next piece of a region the filter accepts; false once there are none
+    // left.
+    fn next_piece(&mut self) -> bool {
+        while self.chunks.is_done() {
+            if let Some(piece) = self.pieces.pop() {
+                self.chunks = Chunks::new(piece, self.chunk_size, BLOCK_OVERLAP, 1);
+                continue;
+            }
+            let Some(region) = self.regions.get(self.regions_taken).cloned() else {
+                return false;
+            };
+            self.regions_taken += 1;
+
+            let Some(range) = self.filter.clip(&region) else { continue };
+            if self.validate_regions && scan::validate_region(self.memory, &region).is_err() {
+                continue;
+            }
+            self.pieces = artifacts::subtract(range, &self.excluded);
+            self.pieces.reverse();
+        }
+        true
+    }
+
+    fn next_block(&mut self) -> Option<MemoryBlock<'_>> {
+        loop {
+            if self.cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+                debug!("YARA scan cancelled");
+                return None;
+            }
+            if !self.next_piece() {
+                return None;
+            }
+
+            self.buffer.resize(self.chunk_size + BLOCK_OVERLAP, 0);
+            let Some(chunk) = self.chunks.read_next(self.memory, &mut self.buffer) else {
+                continue;
+            };
+            if chunk.read > 0 {
+                self.buffer.truncate(chunk.read);
+                return Some(MemoryBlock::new(chunk.address as u64, &self.buffer));
+            }
+        }
+    }
+}
+
+impl MemoryBlockIterator for Blocks<'_> {
+    fn first(&mut self) -> Option<MemoryBlock<'_>> {
+        self.next_block()
+    }
+
+    fn next(&mut self) -> Option<MemoryBlock<'_>> {
+        self.next_block()
+    }
+}
@@ -0,0 +1,105 @@
+// Every input under tests/corpus/ goes through each parser that takes untrusted input, which
+// must return for it, Ok or Err, without panicking. The regression-* files are the inputs
+// that made the PE parser panic before it was hardened (see fuzz/corpus/); the others are
+// edge cases for the text parsers. `EXPECTED` pins what each parser makes of each input, so
+// a parser that starts accepting what it used to refuse shows up too.
+
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use nirvana_notject::parse;
+use nirvana_notject::pe::{self, ImportName, PeHeaders, DIRECTORY_BASERELOC, DIRECTORY_EXPORT, DIRECTORY_IMPORT};
+use nirvana_notject::Pattern;
+
+// Per input: pattern, address expression, PE, CSV import, x64dbg import. Any first line
+// without a '+' is an address expression, taken as a module name.
+const EXPECTED: &[(&str, [bool; 5])] = &[
+    ("address-overflow", [false, false, false, false, false]),
+    ("csv-unterminated-quote", [false, false, false, false, false]),
+    ("pattern-long-wildcards", [true, true, false, false, false]),
+    ("regression-export-range-overflow", [false, true, true, false, false]),
+    ("regression-relocation-page-overflow", [false, true, false, false, false]),
+    ("x64dbg-bad-address", [false, true, false, true, false]),
+];
+
+fn corpus() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut inputs: Vec<PathBuf> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    inputs.sort();
+    inputs
+}
+
+// Whether `parse` accepted the input, or the panic it raised.
+fn outcome<T, E>(parse: impl FnOnce() -> Result<T, E>) -> Result<bool, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| parse().is_ok())).map_err(|payload| {
+        let message = payload.downcast_ref::<&str>().map(|message| message.to_string());
+        message.or_else(|| payload.downcast_ref::<String>().cloned()).unwrap_or_default()
+    })
+}
+
+// The PE parser as manual mapping and export resolution use it: the headers, then every
+// directory they name. Accepted only if all of it parsed.
+fn parse_pe(image: &[u8]) -> Result<(), std::io::Error> {
+    let headers = PeHeaders::parse(image)?;
+    if let Some(directory) = headers.directory(DIRECTORY_BASERELOC) {
+        pe::relocations(image, directory)?;
+    }
+    if let Some(directory) = headers.directory(DIRECTORY_IMPORT) {
+        pe::imports(image, directory, headers.is_64)?;
+    }
+    if let Some(directory) = headers.directory(DIRECTORY_EXPORT) {
+        let exports = pe::Exports::parse(image, 0, directory)?;
+        let _ = exports.get(&ImportName::Ordinal(1));
+        let _ = exports.get(&ImportName::Name("DllMain".to_string()));
+    }
+    Ok(())
+}
+
+#[test]
+fn every_parser_returns_for_every_corpus_input() {
+    let mut panics = Vec::new();
+    let mut outcomes = Vec::new();
+    for path in corpus() {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let bytes = fs::read(&path).unwrap();
+        let text = String::from_utf8_lossy(&bytes);
+        let line = text.lines().next().unwrap_or_default();
+
+        let results = [
+            outcome(|| Pattern::parse(line)),
+            outcome(|| parse::parse_address_expr(line)),
+            outcome(|| parse_pe(&bytes)),
+            #[cfg(feature = "files")]
+            outcome(|| nirvana_notject::interop::import_csv(&path)),
+            #[cfg(feature = "files")]
+            outcome(|| nirvana_notject::interop::import_x64dbg(&path)),
+        ];
+        // Every line on its own as well, for the parsers of one-line input.
+        for line in text.lines() {
+            for result in [outcome(|| Pattern::parse(line)), outcome(|| parse::parse_address_expr(line))] {
+                if let Err(message) = result {
+                    panics.push(format!("{}: {:?}: {}", name, line, message));
+                }
+            }
+        }
+        let mut accepted = Vec::new();
+        for result in results {
+            match result {
+                Ok(ok) => accepted.push(ok),
+                Err(message) => panics.push(format!("{}: {}", name, message)),
+            }
+        }
+        outcomes.push((name, accepted));
+    }
+    assert!(panics.is_empty(), "parsers panicked:\n{}", panics.join("\n"));
+
+    let expected: Vec<(String, Vec<bool>)> = EXPECTED
+        .iter()
+        .map(|(name, accepted)| {
+            let parsers = if cfg!(feature = "files") { 5 } else { 3 };
+            (name.to_string(), accepted[..parsers].to_vec())
+        })
+        .collect();
+    assert_eq!(outcomes, expected);
+}
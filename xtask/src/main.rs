@@ -0,0 +1,118 @@
+// Repository chores that need more than a cargo command line:
+//
+//     cargo xtask features                  # check every feature on its own
+//     cargo xtask features --skip yara      # ... except those that need system libraries
+//     cargo xtask features --target i686-pc-windows-msvc
+//
+// `features` runs `cargo clippy --all-targets -- -D warnings` with no default features, once
+// more with each feature of the main package alone, and with the defaults, so code that
+// uses a module behind a feature it doesn't enable is caught. The feature list is read from
+// Cargo.toml, so new features are checked without touching this file.
+//
+// Run it for both x86_64-pc-windows-msvc and i686-pc-windows-msvc: struct layouts differ
+// between the two (`rustup target add i686-pc-windows-msvc` first).
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+const USAGE: &str = "usage: cargo xtask features [--skip <feature>]... [--target <triple>]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("features") => parse_options(&args[1..]).and_then(|(skips, target)| features(&skips, target.as_deref())),
+        _ => Err(USAGE.to_string()),
+    };
+    if let Err(message) = result {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+// The features to skip and the target to build for, if not the host.
+fn parse_options(args: &[String]) -> Result<(Vec<String>, Option<String>), String> {
+    let mut skips = Vec::new();
+    let mut target = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--skip", Some(feature)) => skips.push(feature.clone()),
+            ("--target", Some(triple)) => target = Some(triple.clone()),
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+    Ok((skips, target))
+}
+
+fn features(skips: &[String], target: Option<&str>) -> Result<(), String> {
+    let root = project_root();
+    let manifest =
+        fs::read_to_string(root.join("Cargo.toml")).map_err(|e| format!("Failed to read Cargo.toml: {}", e))?;
+
+    let mut runs: Vec<(String, Vec<String>)> = vec![("no default features".to_string(), no_default(None))];
+    for feature in feature_names(&manifest).into_iter().filter(|feature| !skips.contains(feature)) {
+        runs.push((feature.clone(), no_default(Some(&feature))));
+    }
+    runs.push(("default features".to_string(), Vec::new()));
+
+    let mut failed = Vec::new();
+    for (name, flags) in &runs {
+        println!("== {}", name);
+        let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()))
+            .current_dir(&root)
+            .args(["clippy", "--all-targets"])
+            .args(target.map(|triple| ["--target", triple]).into_iter().flatten())
+            .args(flags)
+            .args(["--", "-D", "warnings"])
+            .status()
+            .map_err(|e| format!("Failed to run cargo: {}", e))?;
+        if !status.success() {
+            failed.push(name.as_str());
+        }
+    }
+
+    match failed.as_slice() {
+        [] => {
+            println!("all {} feature sets build", runs.len());
+            Ok(())
+        }
+        failed => Err(format!("failed: {}", failed.join(", "))),
+    }
+}
+
+fn no_default(feature: Option<&str>) -> Vec<String> {
+    let mut flags = vec!["--no-default-features".to_string()];
+    if let Some(feature) = feature {
+        flags.extend(["--features".to_string(), feature.to_string()]);
+    }
+    flags
+}
+
+// The keys of the `[features]` table, except `default`. Feature lines are simple enough that
+// this doesn't need a TOML parser.
+fn feature_names(manifest: &str) -> Vec<String> {
+    let mut in_features = false;
+    let mut names = Vec::new();
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_features = line == "[features]";
+            continue;
+        }
+        if !in_features || line.starts_with('#') {
+            continue;
+        }
+        if let Some((name, _)) = line.split_once('=') {
+            let name = name.trim();
+            if name != "default" {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+fn project_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("xtask lives in a subdirectory").to_path_buf()
+}